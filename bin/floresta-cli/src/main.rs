@@ -12,6 +12,7 @@ use floresta_rpc::rpc::FlorestaRPC;
 use floresta_rpc::rpc_types::AddNodeCommand;
 use floresta_rpc::rpc_types::GetBlockRes;
 use floresta_rpc::rpc_types::RescanConfidence;
+use serde_json::Value;
 
 // Main function that runs the CLI application
 fn main() -> anyhow::Result<()> {
@@ -85,6 +86,9 @@ fn do_request(cmd: &Cli, client: Client) -> anyhow::Result<String> {
         Methods::GetBlockHeader { hash } => {
             serde_json::to_string_pretty(&client.get_block_header(hash)?)?
         }
+        Methods::GetDeploymentInfo { blockhash } => {
+            serde_json::to_string_pretty(&client.get_deployment_info(blockhash)?)?
+        }
         Methods::LoadDescriptor { desc } => {
             serde_json::to_string_pretty(&client.load_descriptor(desc)?)?
         }
@@ -127,6 +131,26 @@ fn do_request(cmd: &Cli, client: Client) -> anyhow::Result<String> {
         Methods::Uptime => serde_json::to_string_pretty(&client.uptime()?)?,
         Methods::ListDescriptors => serde_json::to_string_pretty(&client.list_descriptors()?)?,
         Methods::Ping => serde_json::to_string_pretty(&client.ping()?)?,
+        Methods::GetBackfillProgress => {
+            serde_json::to_string_pretty(&client.get_backfill_progress()?)?
+        }
+        Methods::GetIndexInfo { index_name } => {
+            serde_json::to_string_pretty(&client.get_index_info(index_name)?)?
+        }
+        Methods::DumpAssumeUtreexo { height, format } => {
+            match client.dump_assume_utreexo(height, format)? {
+                // The toml and rust formats are printed as is, so they can be pasted
+                Value::String(value) => value,
+                value => serde_json::to_string_pretty(&value)?,
+            }
+        }
+        Methods::VerifyAssumeUtreexo { value } => {
+            let value = serde_json::from_str(&value)?;
+            serde_json::to_string_pretty(&client.verify_assume_utreexo(value)?)?
+        }
+        Methods::GetTxOutSetInfo { hash_type, height } => {
+            serde_json::to_string_pretty(&client.get_txout_set_info(hash_type, height)?)?
+        }
     })
 }
 
@@ -227,6 +251,10 @@ pub enum Methods {
     #[command(name = "getblockheader")]
     GetBlockHeader { hash: BlockHash },
 
+    /// Returns the status of each soft fork at a block, our best block by default
+    #[command(name = "getdeploymentinfo")]
+    GetDeploymentInfo { blockhash: Option<BlockHash> },
+
     /// Loads a new descriptor to the watch only wallet
     #[command(name = "loaddescriptor")]
     LoadDescriptor { desc: String },
@@ -312,4 +340,49 @@ pub enum Methods {
     /// Result: json null
     #[command(name = "ping")]
     Ping,
+
+    /// Returns how far we are in the backfill of assumed blocks
+    ///
+    /// Result: json object with the progress of each backfill range, how many blocks we've
+    /// validated out of the total, and an estimate of how many seconds until we finish
+    #[command(name = "getbackfillprogress")]
+    GetBackfillProgress,
+
+    /// Returns the status of our indexes, or only of `index_name`
+    ///
+    /// Result: json object with an entry for each enabled index, telling whether it's synced
+    /// and the last block it has indexed
+    #[command(name = "getindexinfo")]
+    GetIndexInfo { index_name: Option<String> },
+
+    /// Returns our accumulator after a block, as an assumeutreexo value
+    ///
+    /// Args: height (default: our last validated block), format (json, toml or rust, default
+    /// json)
+    /// Result: the block hash, height, roots and leaves. With toml or rust, they're printed as
+    /// a config file section or as the code in `ChainParams::get_assume_utreexo`
+    #[command(name = "dumpassumeutreexo")]
+    DumpAssumeUtreexo {
+        height: Option<u32>,
+        format: Option<String>,
+    },
+
+    /// Checks an assumeutreexo value against our own accumulator at the same height
+    ///
+    /// Args: value, a json object with block_hash, height, roots and leaves, as returned by
+    /// dumpassumeutreexo
+    /// Result: json object telling whether it's valid, and our own value at that height
+    #[command(name = "verifyassumeutreexo")]
+    VerifyAssumeUtreexo { value: String },
+
+    /// Returns statistics about the UTXO set, from the coinstatsindex
+    ///
+    /// Args: hash_type (muhash or none, default none), height (default: the index's best block)
+    /// Result: json object with the UTXO count, total amount, unspendable amount and, with
+    /// hash_type=muhash, the MuHash of the UTXO set
+    #[command(name = "gettxoutsetinfo")]
+    GetTxOutSetInfo {
+        hash_type: Option<String>,
+        height: Option<u32>,
+    },
 }
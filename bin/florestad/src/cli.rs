@@ -1,5 +1,8 @@
+use std::net::IpAddr;
+
 use bitcoin::Network;
 use clap::Parser;
+use floresta_node::ChainStoreBackend;
 
 #[derive(Parser)]
 #[command(
@@ -21,6 +24,14 @@ pub struct Cli {
     /// Which network should we use
     pub network: Network,
 
+    #[arg(long = "signetchallenge", value_name = "HEX")]
+    /// Use a custom signet with this block challenge, instead of the default one
+    ///
+    /// Same as Bitcoin Core's `-signetchallenge`, only valid with `--network signet`. Other
+    /// parameters of a custom signet or regtest (genesis, activation heights, seeds...) can be
+    /// set in the `[chain]` section of the config file.
+    pub signet_challenge: Option<String>,
+
     #[arg(short, long, default_value_t = false)]
     /// Turn debugging information on
     pub debug: bool,
@@ -156,6 +167,24 @@ pub struct Cli {
     /// (TODO: Update when they implement this)
     pub allow_v1_fallback: bool,
 
+    #[arg(long, value_name = "address[:<port>]")]
+    /// Accept inbound P2P connections on this address, in the format `<address>[:<port>]`
+    ///
+    /// This lets other nodes, like your own light clients, connect to us directly. Both V1 and
+    /// V2 (BIP-324) connections are accepted. If not set, we only make outbound connections.
+    pub listen: Option<String>,
+
+    #[arg(long, value_name = "PEERS", requires = "listen")]
+    /// How many inbound P2P connections we accept. Defaults to 32
+    pub max_inbound: Option<u32>,
+
+    #[arg(long, value_name = "IP", requires = "listen")]
+    /// Always accept inbound P2P connections from this address
+    ///
+    /// Whitelisted peers are never banned or evicted, and can connect even if all inbound slots
+    /// are taken. This option can be passed many times.
+    pub whitelist: Vec<IpAddr>,
+
     #[cfg(unix)]
     #[arg(long, default_value = "false")]
     /// Whether we should run as a daemon
@@ -182,4 +211,97 @@ pub struct Cli {
     /// This will run in the background and wont't affect node's operation. However,
     /// to disable backfilling, run floresta using this flag.
     pub no_backfill: bool,
+
+    #[arg(long, value_name = "RANGES")]
+    /// Into how many height ranges should we split the backfill
+    ///
+    /// Each range is downloaded and validated in parallel, starting from an accumulator that
+    /// our peers agreed on. Defaults to 4.
+    pub backfill_ranges: Option<usize>,
+
+    #[arg(long, default_value_t = ChainStoreBackend::default())]
+    /// Which database should we use to store the chain data
+    ///
+    /// Only the backends enabled at compile time are available: `flat` (flat-chainstore),
+    /// `kv` (kv-chainstore) and `sqlite` (sqlite-chainstore).
+    pub chainstore_backend: ChainStoreBackend,
+
+    #[arg(long, value_name = "BACKEND")]
+    /// Copy the chain data from this backend into the one selected with --chainstore-backend
+    ///
+    /// This lets you switch backends without syncing again. The migration is skipped if the new
+    /// backend already has chain data, and the old database is left untouched, so you can remove
+    /// it after checking that everything works.
+    pub migrate_chainstore_from: Option<ChainStoreBackend>,
+
+    #[arg(long, value_name = "THREADS")]
+    /// How many threads should we use to verify the scripts of each block
+    ///
+    /// Defaults to the number of available CPU cores.
+    pub script_workers: Option<usize>,
+
+    #[arg(long, default_value_t = false)]
+    /// Run as a Utreexo bridge, generating and serving proofs for every block
+    ///
+    /// A bridge keeps the whole accumulator forest on disk, so it doesn't need other bridges to
+    /// sync, and our peers can get block proofs from us. This disables assumeutreexo, as every
+    /// block since genesis must be validated to build the forest.
+    pub bridge: bool,
+
+    #[arg(long, value_name = "BLOCKS")]
+    /// How many of our most recent blocks should we keep on disk
+    ///
+    /// We serve these blocks, and their Utreexo proofs, to our peers, and use them to answer
+    /// `getblock` without asking the network. By default, we don't keep any block.
+    ///
+    /// Peers are only told we serve recent blocks (NODE_NETWORK_LIMITED) if we keep at least
+    /// 288 blocks and --block-cache-size isn't set.
+    pub block_cache_depth: Option<u32>,
+
+    #[arg(long, value_name = "MiB")]
+    /// How much space, in MiB, our cached blocks may take
+    ///
+    /// If the cache gets bigger than this, the oldest blocks are removed.
+    pub block_cache_size: Option<u64>,
+
+    #[arg(long, default_value_t = false)]
+    /// Keep an index with the block of every transaction
+    ///
+    /// This lets `getrawtransaction` return any confirmed transaction, not only the ones in our
+    /// wallet. We don't keep blocks, so each lookup fetches the block from our peers.
+    pub txindex: bool,
+
+    #[arg(long, value_name = "BLOCKS", requires = "txindex")]
+    /// Only index the transactions in our most recent BLOCKS blocks
+    pub txindex_prune: Option<u32>,
+
+    #[arg(long, default_value_t = false, requires = "txindex")]
+    /// Throw away our transaction index and build it again
+    pub reindex_txindex: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Keep statistics about the UTXO set at every height, like Core's coinstatsindex
+    ///
+    /// This is needed by `gettxoutsetinfo`. The index is built as we validate blocks, so it's
+    /// only complete if enabled before the node validates the chain.
+    pub coinstatsindex: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Periodically remove old fork headers and accumulator roots from our chainstore
+    ///
+    /// This keeps our chainstore from growing forever. Reorgs deeper than the roots we keep are
+    /// still possible, using our undo data.
+    pub compact_chainstore: bool,
+
+    #[arg(long, value_name = "BLOCKS", requires = "compact_chainstore")]
+    /// Remove fork headers buried more than BLOCKS blocks below our tip. Defaults to 2016
+    pub compaction_fork_depth: Option<u32>,
+
+    #[arg(long, value_name = "BLOCKS", requires = "compact_chainstore")]
+    /// Keep the accumulator roots for every BLOCKS-th height. Defaults to 2016
+    pub compaction_roots_interval: Option<u32>,
+
+    #[arg(long, value_name = "BLOCKS", requires = "compact_chainstore")]
+    /// Keep the accumulator roots for our last BLOCKS validated blocks. Defaults to 2016
+    pub compaction_roots_window: Option<u32>,
 }
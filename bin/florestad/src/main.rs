@@ -26,6 +26,7 @@ use clap::Parser;
 use cli::Cli;
 #[cfg(unix)]
 use daemonize::Daemonize;
+use floresta_node::CompactionPolicy;
 use floresta_node::Config;
 use floresta_node::Florestad;
 use tokio::sync::RwLock;
@@ -36,9 +37,24 @@ use tracing::info;
 fn main() {
     let params = Cli::parse();
 
+    let chainstore_compaction = params.compact_chainstore.then(|| {
+        let default = CompactionPolicy::default();
+        CompactionPolicy {
+            fork_depth: params.compaction_fork_depth.unwrap_or(default.fork_depth),
+            roots_interval: params
+                .compaction_roots_interval
+                .unwrap_or(default.roots_interval),
+            roots_window: params
+                .compaction_roots_window
+                .unwrap_or(default.roots_window),
+        }
+    });
+
     let config = Config {
         disable_dns_seeds: params.connect.is_some() || params.disable_dns_seeds,
         network: params.network,
+        signet_challenge: params.signet_challenge,
+        chain_params: None,
         debug: params.debug,
         data_dir: params.data_dir.clone(),
         cfilters: !params.no_cfilters,
@@ -69,6 +85,21 @@ fn main() {
         tls_key_path: params.tls_key_path,
         allow_v1_fallback: params.allow_v1_fallback,
         backfill: !params.no_backfill,
+        backfill_ranges: params.backfill_ranges,
+        chainstore_backend: params.chainstore_backend,
+        migrate_chainstore_from: params.migrate_chainstore_from,
+        script_workers: params.script_workers,
+        bridge: params.bridge,
+        block_cache_depth: params.block_cache_depth,
+        block_cache_size: params.block_cache_size,
+        txindex: params.txindex,
+        txindex_prune: params.txindex_prune,
+        reindex_txindex: params.reindex_txindex,
+        coinstatsindex: params.coinstatsindex,
+        chainstore_compaction,
+        p2p_listen_address: params.listen,
+        max_inbound: params.max_inbound,
+        whitelist: params.whitelist,
     };
 
     #[cfg(unix)]
@@ -8,15 +8,19 @@ use bitcoin::consensus::Decodable;
 use bitcoin::Block;
 use bitcoin::Network;
 use bitcoin::OutPoint;
+use bitcoin::Work;
 use criterion::criterion_group;
 use criterion::criterion_main;
 use criterion::BatchSize;
+use criterion::BenchmarkId;
 use criterion::Criterion;
 use criterion::SamplingMode;
 use floresta_chain::pruned_utreexo::utxo_data::UtxoData;
 use floresta_chain::pruned_utreexo::UpdatableChainstate;
 use floresta_chain::AssumeValidArg;
+use floresta_chain::ChainParams;
 use floresta_chain::ChainState;
+use floresta_chain::DeploymentRules;
 #[cfg(feature = "flat-chainstore")]
 use floresta_chain::FlatChainStore;
 #[cfg(feature = "flat-chainstore")]
@@ -57,6 +61,13 @@ fn read_mainnet_headers() -> Vec<BlockHeader> {
     headers
 }
 
+/// The parameters for `network`, without a minimum chain work, so our test headers get saved
+fn test_params(network: Network) -> ChainParams {
+    let mut params = ChainParams::from(network);
+    params.minimum_chain_work = Work::from_be_bytes([0; 32]);
+    params
+}
+
 #[cfg(feature = "kv-chainstore")]
 fn setup_test_chain<'a>(
     network: Network,
@@ -64,7 +75,7 @@ fn setup_test_chain<'a>(
 ) -> ChainState<KvChainStore<'a>> {
     let test_id = rand::random::<u64>();
     let chainstore = KvChainStore::new(format!("./tmp-db/{test_id}/")).unwrap();
-    ChainState::new(chainstore, network, assume_valid_arg)
+    ChainState::new(chainstore, test_params(network), assume_valid_arg)
 }
 
 #[cfg(feature = "flat-chainstore")]
@@ -83,7 +94,7 @@ fn setup_test_chain(
     };
 
     let chainstore = FlatChainStore::new(config).unwrap();
-    ChainState::new(chainstore, network, assume_valid_arg)
+    ChainState::new(chainstore, test_params(network), assume_valid_arg)
 }
 
 fn decode_block_and_inputs(
@@ -209,6 +220,19 @@ fn connect_blocks_benchmark(c: &mut Criterion) {
     });
 }
 
+/// The script worker counts we benchmark block validation with: a single thread, and one thread
+/// per available core. Scripts are only verified with the `bitcoinconsensus` or
+/// `script-interpreter` features, and only in parallel with `parallel-validation`.
+fn script_worker_counts() -> Vec<usize> {
+    let cores = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    let mut counts = vec![1, cores];
+    counts.dedup();
+    counts
+}
+
 fn validate_full_block_benchmark(c: &mut Criterion) {
     let block_file = File::open("./testdata/block_866342/raw.zst").unwrap();
     let stxos_file = File::open("./testdata/block_866342/spent_utxos.zst").unwrap();
@@ -216,13 +240,33 @@ fn validate_full_block_benchmark(c: &mut Criterion) {
 
     let chain = setup_test_chain(Network::Bitcoin, AssumeValidArg::Disabled);
 
-    c.bench_function("validate_block_866342", |b| {
-        b.iter_batched(
-            || inputs.clone(),
-            |inputs| chain.validate_block_no_acc(&block, 866342, inputs).unwrap(),
-            BatchSize::LargeInput,
-        )
-    });
+    let mut group = c.benchmark_group("validate_block_866342");
+    for workers in script_worker_counts() {
+        chain.set_script_workers(workers);
+
+        group.bench_with_input(
+            BenchmarkId::new("script_workers", workers),
+            &workers,
+            |b, _| {
+                b.iter_batched(
+                    || inputs.clone(),
+                    |inputs| {
+                        chain
+                            .validate_block_no_acc(
+                                &block,
+                                866342,
+                                block.header.time,
+                                &DeploymentRules::default(),
+                                inputs,
+                            )
+                            .unwrap()
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
 }
 
 fn validate_many_inputs_block_benchmark(c: &mut Criterion) {
@@ -246,13 +290,31 @@ fn validate_many_inputs_block_benchmark(c: &mut Criterion) {
     group.sampling_mode(SamplingMode::Flat);
     group.sample_size(10);
 
-    group.bench_function("validate_block_367891", |b| {
-        b.iter_batched(
-            || inputs.clone(),
-            |inputs| chain.validate_block_no_acc(&block, 367891, inputs).unwrap(),
-            BatchSize::LargeInput,
-        )
-    });
+    for workers in script_worker_counts() {
+        chain.set_script_workers(workers);
+
+        group.bench_with_input(
+            BenchmarkId::new("script_workers", workers),
+            &workers,
+            |b, _| {
+                b.iter_batched(
+                    || inputs.clone(),
+                    |inputs| {
+                        chain
+                            .validate_block_no_acc(
+                                &block,
+                                367891,
+                                block.header.time,
+                                &DeploymentRules::default(),
+                                inputs,
+                            )
+                            .unwrap()
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
     group.finish();
 }
 
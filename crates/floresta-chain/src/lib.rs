@@ -18,16 +18,23 @@
 
 pub mod pruned_utreexo;
 pub(crate) use floresta_common::prelude;
+pub use pruned_utreexo::chain_snapshot::*;
 pub use pruned_utreexo::chain_state::*;
 pub use pruned_utreexo::chainparams::*;
 pub use pruned_utreexo::chainstore::*;
 pub use pruned_utreexo::error::*;
+pub use pruned_utreexo::fee_estimator::*;
 #[cfg(feature = "flat-chainstore")]
 pub use pruned_utreexo::flat_chain_store::*;
+pub use pruned_utreexo::headers_sync::*;
 #[cfg(feature = "kv-chainstore")]
 pub use pruned_utreexo::kv_chainstore::*;
+#[cfg(feature = "sqlite-chainstore")]
+pub use pruned_utreexo::sqlite_chainstore::*;
 pub use pruned_utreexo::udata::*;
+pub use pruned_utreexo::undo::*;
 pub use pruned_utreexo::utxo_data::*;
+pub use pruned_utreexo::versionbits::*;
 pub use pruned_utreexo::BlockchainInterface;
 pub use pruned_utreexo::ChainBackend;
 pub use pruned_utreexo::Notification;
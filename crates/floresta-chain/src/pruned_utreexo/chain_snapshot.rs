@@ -0,0 +1,727 @@
+//! Offline export and import of a [ChainStore]
+//!
+//! A snapshot holds our header chain, its block index, the [BestChain] and the accumulator roots
+//! for a few selected heights, so a synced chain can be copied between machines without the rest
+//! of the data directory. Undo data and fee estimates are not included, the former is only needed
+//! for reorgs deeper than the roots we have, and the latter is rebuilt as we see new blocks.
+//!
+//! The file format is:
+//!
+//! | Field               | Size     | Description                                          |
+//! |---------------------|----------|------------------------------------------------------|
+//! | magic               | 4 bytes  | [SNAPSHOT_MAGIC], little-endian                      |
+//! | version             | 4 bytes  | [SNAPSHOT_VERSION], little-endian                    |
+//! | checksum            | 24 bytes | a [DbCheckSum] over the three sections below         |
+//! | headers             | variable | one [DiskBlockHeader] per height in our best chain   |
+//! | index               | variable | the [BestChain], then the roots for selected heights |
+//! | fork headers        | variable | the headers in our alternative tips                  |
+//!
+//! Each section is prefixed by its length as a little-endian u64. The block index is implicit in
+//! the headers section, since the header for height `n` is the `n`-th one.
+//!
+//! Before writing anything into a store, we check the checksum, that the headers build a chain
+//! from this network's genesis, that each header has enough proof-of-work for its own target, and
+//! that those targets follow the network's difficulty adjustment rules.
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+
+use bitcoin::consensus::encode;
+use bitcoin::consensus::serialize;
+use bitcoin::consensus::Decodable;
+use bitcoin::io::Read;
+use bitcoin::io::Write;
+use bitcoin::BlockHash;
+
+use super::consensus::Consensus;
+use crate::prelude::*;
+use crate::BestChain;
+use crate::BlockchainError;
+use crate::ChainParams;
+use crate::ChainStore;
+use crate::DbCheckSum;
+use crate::DiskBlockHeader;
+use crate::FileChecksum;
+
+/// The magic number at the start of every snapshot
+///
+/// Like the flat chainstore magic, this is backwards so it shows in the correct order in a
+/// hex dump.
+pub const SNAPSHOT_MAGIC: u32 = 0x6E_73_6C_66; // "flsn" backwards
+
+/// The current version of our snapshot format
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// How much data we read at once when loading a section, so a bogus length can't make us
+/// allocate more memory than the snapshot actually has
+const SECTION_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Reasons why we may refuse to import a snapshot
+pub enum ChainSnapshotError {
+    /// The file doesn't start with [SNAPSHOT_MAGIC]
+    InvalidMagic(u32),
+
+    /// The snapshot was written by a newer version of the format
+    UnsupportedVersion(u32),
+
+    /// The sections don't match the snapshot's checksum
+    ChecksumMismatch,
+
+    /// The first header isn't this network's genesis
+    WrongGenesis(BlockHash),
+
+    /// A header doesn't build on top of the previous one
+    BrokenChain(BlockHash),
+
+    /// A header doesn't have enough proof-of-work, or its target is above the network's limit or
+    /// what the difficulty adjustment allows
+    InvalidPow(BlockHash),
+
+    /// The [BestChain] doesn't match the headers in this snapshot
+    InconsistentBestChain,
+
+    /// We don't have the accumulator roots for this height, but we need them
+    MissingRoots(u32),
+}
+
+impl Display for ChainSnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainSnapshotError::InvalidMagic(magic) => {
+                write!(f, "Invalid snapshot magic {magic:#010x}")
+            }
+            ChainSnapshotError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported snapshot version {version}")
+            }
+            ChainSnapshotError::ChecksumMismatch => write!(f, "Snapshot checksum mismatch"),
+            ChainSnapshotError::WrongGenesis(hash) => {
+                write!(f, "Snapshot starts at {hash}, which is not our genesis")
+            }
+            ChainSnapshotError::BrokenChain(hash) => {
+                write!(f, "Header {hash} doesn't build on the previous one")
+            }
+            ChainSnapshotError::InvalidPow(hash) => write!(f, "Header {hash} has invalid PoW"),
+            ChainSnapshotError::InconsistentBestChain => {
+                write!(f, "The best chain doesn't match the snapshot headers")
+            }
+            ChainSnapshotError::MissingRoots(height) => {
+                write!(f, "Missing accumulator roots for height {height}")
+            }
+        }
+    }
+}
+
+impl From<ChainSnapshotError> for BlockchainError {
+    fn from(value: ChainSnapshotError) -> Self {
+        BlockchainError::InvalidSnapshot(value)
+    }
+}
+
+/// The decoded contents of a snapshot
+struct ChainSnapshot {
+    headers: Vec<DiskBlockHeader>,
+    best_chain: BestChain,
+    roots: Vec<(u32, Vec<u8>)>,
+    fork_headers: Vec<DiskBlockHeader>,
+}
+
+/// Writes a snapshot of `store` into `writer`, returning how many bytes were written.
+///
+/// Besides the heights in `roots_heights`, we always include the roots for our validation index,
+/// since those are needed to resume validation after importing. Requested heights that we don't
+/// have roots for are skipped.
+pub fn export_chain_snapshot<S: ChainStore, W: Write + ?Sized>(
+    store: &mut S,
+    writer: &mut W,
+    roots_heights: &[u32],
+) -> Result<usize, BlockchainError> {
+    let best_chain = store
+        .load_height()?
+        .ok_or(BlockchainError::ChainNotInitialized)?;
+
+    let mut headers = Vec::new();
+    for height in 0..=best_chain.depth {
+        let hash = store
+            .get_block_hash(height)?
+            .ok_or(BlockchainError::BlockNotPresent)?;
+        let header = store
+            .get_header(&hash)?
+            .ok_or(BlockchainError::BlockNotPresent)?;
+
+        headers.extend(serialize(&header));
+    }
+
+    // Walk back from each alternative tip until we reach our best chain, or a fork we already
+    // have. We write them tip-first, the importer doesn't care about the order.
+    let mut fork_headers = Vec::new();
+    let mut seen = HashSet::new();
+    for tip in best_chain.alternative_tips.iter() {
+        let mut hash = *tip;
+        while let Some(header) = store.get_header(&hash)? {
+            let in_best_chain = match header.height() {
+                Some(height) => store.get_block_hash(height)? == Some(hash),
+                None => false,
+            };
+
+            if in_best_chain || !seen.insert(hash) {
+                break;
+            }
+
+            fork_headers.extend(serialize(&header));
+            hash = header.prev_blockhash;
+        }
+    }
+
+    let validation_height = store
+        .get_header(&best_chain.validation_index)?
+        .ok_or(BlockchainError::BlockNotPresent)?
+        .try_height()?;
+
+    let mut heights = roots_heights.to_vec();
+    heights.push(validation_height);
+    heights.sort_unstable();
+    heights.dedup();
+
+    let mut roots = Vec::new();
+    for height in heights {
+        match store.load_roots_for_block(height)? {
+            Some(acc) => roots.push((height, acc)),
+            None if height == validation_height && height != 0 => {
+                return Err(ChainSnapshotError::MissingRoots(height).into());
+            }
+            None => {}
+        }
+    }
+
+    let mut index = serialize(&best_chain);
+    index.extend(serialize(&(roots.len() as u32)));
+    for (height, acc) in roots {
+        index.extend(serialize(&height));
+        index.extend(serialize(&acc));
+    }
+
+    let checksum = DbCheckSum::new(&headers, &index, &fork_headers);
+
+    let mut prefix = serialize(&SNAPSHOT_MAGIC);
+    prefix.extend(serialize(&SNAPSHOT_VERSION));
+    prefix.extend(serialize(&checksum.headers_checksum.0));
+    prefix.extend(serialize(&checksum.index_checksum.0));
+    prefix.extend(serialize(&checksum.fork_headers_checksum.0));
+    writer.write_all(&prefix).map_err(io_error)?;
+
+    let mut len = prefix.len();
+    for section in [&headers, &index, &fork_headers] {
+        let section_len = serialize(&(section.len() as u64));
+        writer.write_all(&section_len).map_err(io_error)?;
+        writer.write_all(section).map_err(io_error)?;
+        len += section_len.len() + section.len();
+    }
+
+    Ok(len)
+}
+
+/// Reads a snapshot from `reader` and writes it into `store`, returning how many headers were
+/// imported.
+///
+/// The whole snapshot is checked before we touch `store`, see the module documentation for what
+/// we check. Like [migrate_chain_store](crate::migrate_chain_store), this refuses to write into a
+/// store that already has a [BestChain], and resumes an interrupted import.
+pub fn import_chain_snapshot<S: ChainStore, R: Read + ?Sized>(
+    store: &mut S,
+    reader: &mut R,
+    params: &ChainParams,
+) -> Result<usize, BlockchainError> {
+    let snapshot = read_snapshot(reader)?;
+    verify_snapshot(&snapshot, params)?;
+
+    if store.load_height()?.is_some() {
+        return Err(BlockchainError::ChainStoreNotEmpty);
+    }
+
+    for (height, header) in snapshot.headers.iter().enumerate() {
+        store.save_header(header)?;
+        store.update_block_index(height as u32, header.block_hash())?;
+    }
+
+    // Fork headers aren't overwritten by their hash, so we skip those from an interrupted import
+    for header in snapshot.fork_headers.iter() {
+        if store.get_header(&header.block_hash())?.is_none() {
+            store.save_header(header)?;
+        }
+    }
+
+    for (height, acc) in snapshot.roots {
+        store.save_roots_for_block(acc, height)?;
+    }
+
+    // The best chain goes last, so an interrupted import doesn't look initialized
+    store.save_height(&snapshot.best_chain)?;
+    store.flush()?;
+
+    Ok(snapshot.headers.len() + snapshot.fork_headers.len())
+}
+
+/// Decodes a snapshot, checking its magic, version and checksum
+fn read_snapshot<R: Read + ?Sized>(reader: &mut R) -> Result<ChainSnapshot, BlockchainError> {
+    let magic = u32::consensus_decode(reader)?;
+    if magic != SNAPSHOT_MAGIC {
+        return Err(ChainSnapshotError::InvalidMagic(magic).into());
+    }
+
+    let version = u32::consensus_decode(reader)?;
+    if version > SNAPSHOT_VERSION {
+        return Err(ChainSnapshotError::UnsupportedVersion(version).into());
+    }
+
+    let checksum = DbCheckSum {
+        headers_checksum: FileChecksum(u64::consensus_decode(reader)?),
+        index_checksum: FileChecksum(u64::consensus_decode(reader)?),
+        fork_headers_checksum: FileChecksum(u64::consensus_decode(reader)?),
+    };
+
+    let headers = read_section(reader)?;
+    let index = read_section(reader)?;
+    let fork_headers = read_section(reader)?;
+
+    if DbCheckSum::new(&headers, &index, &fork_headers) != checksum {
+        return Err(ChainSnapshotError::ChecksumMismatch.into());
+    }
+
+    let mut index = index.as_slice();
+    let best_chain = BestChain::consensus_decode(&mut index)?;
+    let roots_count = u32::consensus_decode(&mut index)?;
+
+    let mut roots = Vec::new();
+    for _ in 0..roots_count {
+        let height = u32::consensus_decode(&mut index)?;
+        let acc = Vec::<u8>::consensus_decode(&mut index)?;
+        roots.push((height, acc));
+    }
+
+    Ok(ChainSnapshot {
+        headers: decode_headers(&headers)?,
+        best_chain,
+        roots,
+        fork_headers: decode_headers(&fork_headers)?,
+    })
+}
+
+/// Reads a section, prefixed by its length as a little-endian u64
+fn read_section<R: Read + ?Sized>(reader: &mut R) -> Result<Vec<u8>, BlockchainError> {
+    let len = u64::consensus_decode(reader)?;
+    let mut section = Vec::new();
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let chunk_len = remaining.min(SECTION_CHUNK_SIZE as u64) as usize;
+        let start = section.len();
+
+        section.resize(start + chunk_len, 0);
+        reader.read_exact(&mut section[start..]).map_err(io_error)?;
+        remaining -= chunk_len as u64;
+    }
+
+    Ok(section)
+}
+
+/// Wraps an I/O error from the snapshot reader or writer
+fn io_error(error: bitcoin::io::Error) -> BlockchainError {
+    BlockchainError::Io(error.into())
+}
+
+/// Decodes a sequence of [DiskBlockHeader]s, until the data runs out
+fn decode_headers(mut data: &[u8]) -> Result<Vec<DiskBlockHeader>, encode::Error> {
+    let mut headers = Vec::new();
+    while !data.is_empty() {
+        headers.push(DiskBlockHeader::consensus_decode(&mut data)?);
+    }
+
+    Ok(headers)
+}
+
+/// Checks that a header has enough work for its own target, and that this target is allowed
+fn check_pow(header: &DiskBlockHeader, params: &ChainParams) -> Result<(), ChainSnapshotError> {
+    let target = header.target();
+    if target > params.params.max_attainable_target || header.validate_pow(target).is_err() {
+        return Err(ChainSnapshotError::InvalidPow(header.block_hash()));
+    }
+
+    Ok(())
+}
+
+/// Checks that a header's target follows the difficulty adjustment, given its parent and height.
+/// Epochs start in our best chain, the same way [ChainState](crate::ChainState) does it.
+fn check_difficulty(
+    header: &DiskBlockHeader,
+    parent: &DiskBlockHeader,
+    height: u32,
+    headers: &[DiskBlockHeader],
+    consensus: &Consensus,
+) -> Result<(), ChainSnapshotError> {
+    let invalid = || ChainSnapshotError::InvalidPow(header.block_hash());
+    let expected = consensus.get_next_required_work(parent, height, header, |height| {
+        headers
+            .get(height as usize)
+            .map(|header| **header)
+            .ok_or_else(invalid)
+    })?;
+
+    if header.target() > expected {
+        return Err(invalid());
+    }
+
+    Ok(())
+}
+
+/// Checks that a decoded snapshot is a valid chain for this network
+fn verify_snapshot(snapshot: &ChainSnapshot, params: &ChainParams) -> Result<(), BlockchainError> {
+    let genesis = params.genesis.block_hash();
+    let consensus = Consensus {
+        parameters: params.clone(),
+    };
+
+    let first = snapshot
+        .headers
+        .first()
+        .ok_or(ChainSnapshotError::InconsistentBestChain)?;
+
+    if first.block_hash() != genesis {
+        return Err(ChainSnapshotError::WrongGenesis(first.block_hash()).into());
+    }
+
+    let mut prev_hash = None;
+    for (height, header) in snapshot.headers.iter().enumerate() {
+        let hash = header.block_hash();
+        if prev_hash.is_some_and(|prev| prev != header.prev_blockhash)
+            || header.height() != Some(height as u32)
+        {
+            return Err(ChainSnapshotError::BrokenChain(hash).into());
+        }
+
+        // The genesis block doesn't need to meet the PoW limit on every network
+        if height != 0 {
+            check_pow(header, params)?;
+
+            let parent = &snapshot.headers[height - 1];
+            check_difficulty(header, parent, height as u32, &snapshot.headers, &consensus)?;
+        }
+
+        prev_hash = Some(hash);
+    }
+
+    let best_chain = &snapshot.best_chain;
+    let tip = snapshot.headers.len() as u32 - 1;
+    if prev_hash != Some(best_chain.best_block) || best_chain.depth != tip {
+        return Err(ChainSnapshotError::InconsistentBestChain.into());
+    }
+
+    let validation_height = snapshot
+        .headers
+        .iter()
+        .position(|header| header.block_hash() == best_chain.validation_index)
+        .ok_or(ChainSnapshotError::InconsistentBestChain)? as u32;
+
+    let has_roots = snapshot
+        .roots
+        .iter()
+        .any(|(height, _)| *height == validation_height);
+
+    if validation_height != 0 && !has_roots {
+        return Err(ChainSnapshotError::MissingRoots(validation_height).into());
+    }
+
+    // Fork headers must build on our best chain, or on another fork header
+    let known = snapshot
+        .headers
+        .iter()
+        .chain(snapshot.fork_headers.iter())
+        .map(|header| (header.block_hash(), header))
+        .collect::<HashMap<_, _>>();
+
+    for header in snapshot.fork_headers.iter() {
+        let Some(parent) = known.get(&header.prev_blockhash) else {
+            return Err(ChainSnapshotError::BrokenChain(header.block_hash()).into());
+        };
+
+        check_pow(header, params)?;
+
+        // Orphan and invalid headers don't have a height, so we can only check their own PoW
+        if let Some(height) = header.height() {
+            check_difficulty(header, parent, height, &snapshot.headers, &consensus)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::block::Header as BlockHeader;
+    use bitcoin::constants::genesis_block;
+    use bitcoin::CompactTarget;
+    use bitcoin::Network;
+
+    use super::*;
+    use crate::pruned_utreexo::test_utils::MemChainStore;
+    use crate::AssumeValidArg;
+    use crate::BlockchainInterface;
+    use crate::ChainState;
+
+    /// Grinds the nonce until `header` has (or, if `valid` is false, lacks) enough PoW
+    fn mine(mut header: BlockHeader, valid: bool) -> BlockHeader {
+        while header.validate_pow(header.target()).is_ok() != valid {
+            header.nonce += 1;
+        }
+
+        header
+    }
+
+    /// Builds a regtest store with five blocks in the best chain and a two-block fork from
+    /// height two. The last block in the best chain has `valid_pow` PoW.
+    fn build_store(valid_pow: bool) -> MemChainStore {
+        let mut store = MemChainStore::default();
+        let genesis = genesis_block(Network::Regtest).header;
+        store
+            .save_header(&DiskBlockHeader::FullyValid(genesis, 0))
+            .unwrap();
+        store.update_block_index(0, genesis.block_hash()).unwrap();
+
+        let mut prev = genesis;
+        for height in 1..5 {
+            let mut header = genesis;
+            header.prev_blockhash = prev.block_hash();
+            header.time += height;
+            let header = mine(header, valid_pow || height != 4);
+
+            store
+                .save_header(&DiskBlockHeader::FullyValid(header, height))
+                .unwrap();
+            store
+                .update_block_index(height, header.block_hash())
+                .unwrap();
+            store
+                .save_roots_for_block(vec![height as u8], height)
+                .unwrap();
+            prev = header;
+        }
+
+        let mut fork_prev = store.get_header_by_height(2).unwrap().unwrap().block_hash();
+        for height in 3..5 {
+            let mut header = genesis;
+            header.prev_blockhash = fork_prev;
+            header.time += 100 + height;
+            let header = mine(header, true);
+
+            store
+                .save_header(&DiskBlockHeader::InFork(header, height))
+                .unwrap();
+            fork_prev = header.block_hash();
+        }
+
+        store
+            .save_height(&BestChain {
+                best_block: prev.block_hash(),
+                depth: 4,
+                validation_index: prev.block_hash(),
+                alternative_tips: vec![fork_prev],
+            })
+            .unwrap();
+
+        store
+    }
+
+    fn export(store: &mut MemChainStore, roots_heights: &[u32]) -> Vec<u8> {
+        let mut snapshot = Vec::new();
+        let len = export_chain_snapshot(store, &mut snapshot, roots_heights).unwrap();
+        assert_eq!(len, snapshot.len());
+
+        snapshot
+    }
+
+    fn import(snapshot: &[u8], network: Network) -> Result<MemChainStore, BlockchainError> {
+        let mut store = MemChainStore::default();
+        import_chain_snapshot(&mut store, &mut &snapshot[..], &network.into())?;
+
+        Ok(store)
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let mut source = build_store(true);
+        let snapshot = export(&mut source, &[1, 2, 42]);
+        let mut imported = import(&snapshot, Network::Regtest).unwrap();
+
+        assert_eq!(imported.best_chain, source.best_chain);
+        assert_eq!(imported.headers, source.headers);
+        assert_eq!(imported.index, source.index);
+
+        // We only keep the roots that were asked for, plus the validation index
+        for height in 0..5 {
+            let expected = match height {
+                1 | 2 | 4 => Some(vec![height as u8]),
+                _ => None,
+            };
+
+            assert_eq!(imported.load_roots_for_block(height).unwrap(), expected);
+        }
+
+        // The imported store gives us the same snapshot back
+        assert_eq!(export(&mut imported, &[1, 2]), snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_rejects_invalid_data() {
+        let snapshot = export(&mut build_store(true), &[]);
+
+        let mut bad_magic = snapshot.clone();
+        bad_magic[0] ^= 1;
+        assert!(matches!(
+            import(&bad_magic, Network::Regtest),
+            Err(BlockchainError::InvalidSnapshot(
+                ChainSnapshotError::InvalidMagic(_)
+            ))
+        ));
+
+        let mut too_new = snapshot.clone();
+        too_new[4] = 2;
+        assert!(matches!(
+            import(&too_new, Network::Regtest),
+            Err(BlockchainError::InvalidSnapshot(
+                ChainSnapshotError::UnsupportedVersion(2)
+            ))
+        ));
+
+        // Flip one bit inside the headers section
+        let mut corrupted = snapshot.clone();
+        corrupted[50] ^= 1;
+        assert!(matches!(
+            import(&corrupted, Network::Regtest),
+            Err(BlockchainError::InvalidSnapshot(
+                ChainSnapshotError::ChecksumMismatch
+            ))
+        ));
+
+        assert!(matches!(
+            import(&snapshot[..snapshot.len() - 1], Network::Regtest),
+            Err(BlockchainError::Io(_))
+        ));
+
+        assert!(matches!(
+            import(&snapshot, Network::Signet),
+            Err(BlockchainError::InvalidSnapshot(
+                ChainSnapshotError::WrongGenesis(_)
+            ))
+        ));
+
+        // The checksum matches, but the tip doesn't have enough work
+        let bad_pow = export(&mut build_store(false), &[]);
+        assert!(matches!(
+            import(&bad_pow, Network::Regtest),
+            Err(BlockchainError::InvalidSnapshot(
+                ChainSnapshotError::InvalidPow(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_rejects_easier_target() {
+        // Regtest doesn't retarget, so every header must have the previous header's target
+        let mut store = MemChainStore::default();
+        let genesis = genesis_block(Network::Regtest).header;
+        store
+            .save_header(&DiskBlockHeader::FullyValid(genesis, 0))
+            .unwrap();
+        store.update_block_index(0, genesis.block_hash()).unwrap();
+
+        let mut prev = genesis;
+        for height in 1..4 {
+            let mut header = genesis;
+            header.prev_blockhash = prev.block_hash();
+            header.time += height;
+
+            // The first blocks are harder than the genesis, the last one goes back to its target
+            if height != 3 {
+                header.bits = CompactTarget::from_consensus(0x2000ffff);
+            }
+            let header = mine(header, true);
+
+            store
+                .save_header(&DiskBlockHeader::FullyValid(header, height))
+                .unwrap();
+            store
+                .update_block_index(height, header.block_hash())
+                .unwrap();
+            prev = header;
+        }
+
+        store
+            .save_height(&BestChain {
+                best_block: prev.block_hash(),
+                depth: 3,
+                validation_index: genesis.block_hash(),
+                alternative_tips: Vec::new(),
+            })
+            .unwrap();
+
+        let snapshot = export(&mut store, &[]);
+        assert!(matches!(
+            import(&snapshot, Network::Regtest),
+            Err(BlockchainError::InvalidSnapshot(
+                ChainSnapshotError::InvalidPow(hash)
+            )) if hash == prev.block_hash()
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_needs_empty_store() {
+        let mut store = build_store(true);
+        let snapshot = export(&mut store, &[]);
+
+        assert!(matches!(
+            import_chain_snapshot(
+                &mut store,
+                &mut snapshot.as_slice(),
+                &Network::Regtest.into()
+            ),
+            Err(BlockchainError::ChainStoreNotEmpty)
+        ));
+
+        // An interrupted import wrote some headers, but not the best chain, so we resume it
+        let mut partial = build_store(true);
+        partial.best_chain = None;
+        import_chain_snapshot(
+            &mut partial,
+            &mut snapshot.as_slice(),
+            &Network::Regtest.into(),
+        )
+        .unwrap();
+        assert_eq!(partial.best_chain, store.best_chain);
+    }
+
+    #[test]
+    fn test_chain_state_snapshot() {
+        let chain = ChainState::new(
+            MemChainStore::default(),
+            Network::Regtest,
+            AssumeValidArg::Disabled,
+        );
+
+        let mut snapshot = Vec::new();
+        chain.export_snapshot(&mut snapshot, &[]).unwrap();
+
+        let imported = ChainState::import_snapshot(
+            MemChainStore::default(),
+            &mut snapshot.as_slice(),
+            Network::Regtest,
+            AssumeValidArg::Disabled,
+        )
+        .unwrap();
+
+        assert_eq!(
+            imported.get_best_block().unwrap(),
+            chain.get_best_block().unwrap()
+        );
+    }
+}
@@ -14,6 +14,7 @@
 //! Key definitions:
 //! - [ChainState]: The high-level chain backend
 //! - [BlockConsumer]: Trait for receiving new block notifications
+//! - [TxSource]: Trait for finding confirmed transactions, as we don't keep blocks
 extern crate alloc;
 
 use alloc::borrow::ToOwned;
@@ -24,15 +25,18 @@ use alloc::vec::Vec;
 use core::cell::UnsafeCell;
 
 use bitcoin::block::Header as BlockHeader;
-use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::consensus::deserialize;
+use bitcoin::consensus::serialize;
 use bitcoin::hashes::sha256;
+use bitcoin::io::Read;
+use bitcoin::io::Write;
 use bitcoin::script;
 use bitcoin::Block;
 use bitcoin::BlockHash;
-use bitcoin::Network;
 use bitcoin::OutPoint;
 use bitcoin::Target;
 use bitcoin::Transaction;
+use bitcoin::Txid;
 use bitcoin::Work;
 use floresta_common::Channel;
 #[cfg(feature = "metrics")]
@@ -45,15 +49,24 @@ use tracing::debug;
 use tracing::info;
 use tracing::warn;
 
+use super::chain_snapshot::export_chain_snapshot;
+use super::chain_snapshot::import_chain_snapshot;
 use super::chain_state_builder::BlockchainBuilderError;
 use super::chain_state_builder::ChainStateBuilder;
 use super::chainparams::ChainParams;
 use super::chainstore::DiskBlockHeader;
 use super::consensus::Consensus;
+use super::consensus::MEDIAN_TIME_SPAN;
 use super::error::BlockValidationErrors;
 use super::error::BlockchainError;
+use super::fee_estimator::FeeEstimator;
+use super::headers_sync::HeadersSyncState;
 use super::partial_chain::PartialChainState;
 use super::partial_chain::PartialChainStateInner;
+use super::undo::AccumulatorUndo;
+use super::versionbits::DeploymentInfo;
+use super::versionbits::DeploymentRules;
+use super::versionbits::VersionBitsCache;
 use super::BlockchainInterface;
 use super::UpdatableChainstate;
 use crate::prelude::*;
@@ -62,6 +75,8 @@ use crate::read_lock;
 use crate::write_lock;
 use crate::BestChain;
 use crate::ChainStore;
+use crate::CompactionPolicy;
+use crate::CompactionStats;
 
 /// Trait for components that need to receive notifications about new blocks.
 pub trait BlockConsumer: Sync + Send + 'static {
@@ -77,6 +92,15 @@ pub trait BlockConsumer: Sync + Send + 'static {
     );
 }
 
+/// Trait for components that can find transactions in our chain, like a transaction index.
+///
+/// We don't keep blocks, so [BlockchainInterface::get_tx] can only return confirmed
+/// transactions if some [TxSource] was given with [ChainState::set_tx_source].
+pub trait TxSource: Sync + Send + 'static {
+    /// Returns the transaction with this txid, if it's in our chain and we could fetch it.
+    fn get_tx(&self, txid: &Txid) -> Option<Transaction>;
+}
+
 impl BlockConsumer for Channel<(Block, u32)> {
     fn wants_spent_utxos(&self) -> bool {
         false
@@ -127,8 +151,11 @@ pub struct ChainStateInner<PersistedState: ChainStore> {
     /// If a module just wants pass in a channel, `Sender` implements [BlockConsumer], and can
     /// be used during subscription (just keep the `Receiver` side.
     subscribers: Vec<Arc<dyn BlockConsumer>>,
-    /// Fee estimation for 1, 10 and 20 blocks
-    fee_estimation: (f64, f64, f64),
+    /// Where [BlockchainInterface::get_tx] looks for transactions, if anywhere.
+    tx_source: Option<Arc<dyn TxSource>>,
+    /// Learns feerates from the blocks we connect (and transactions in the mempool), it's used
+    /// to answer [BlockchainInterface::estimate_fee]. Its state is persisted in our chainstore.
+    fee_estimator: Arc<FeeEstimator>,
     /// Are we in Initial Block Download?
     ibd: bool,
     /// Parameters for the chain and functions that verify the chain.
@@ -137,6 +164,14 @@ pub struct ChainStateInner<PersistedState: ChainStore> {
     /// in blocks before this one. Note that we only skip signature validation, everything else
     /// is still validated.
     assume_valid: Option<BlockHash>,
+    /// The state of each versionbits deployment, computed once per period.
+    versionbits: VersionBitsCache,
+    /// How many threads we use to verify the scripts of a block, see
+    /// [Consensus::verify_block_transactions].
+    script_workers: usize,
+    /// Whether our best chain has the minimum chain work, so headers extending it don't need a
+    /// presync. See [UpdatableChainstate::start_headers_presync].
+    minimum_work_reached: bool,
 }
 
 /// The high-level chain backend managing the blockchain state.
@@ -236,6 +271,65 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         Ok(block_hash)
     }
 
+    /// Validates a header that builds on a block we have, and saves it
+    fn connect_header(&self, header: BlockHeader) -> Result<(), BlockchainError> {
+        // The best block we know of
+        let best_block = self.get_best_block()?;
+
+        // Do validation in this header
+        let block_hash = self.validate_header(&header)?;
+
+        // Update our current tip
+        if header.prev_blockhash == best_block.1 {
+            let height = best_block.0 + 1;
+            debug!("Header builds on top of our best chain");
+
+            write_lock!(self).best_block.new_block(block_hash, height);
+            let disk_header = DiskBlockHeader::HeadersOnly(header, height);
+
+            self.update_header_and_index(&disk_header, block_hash, height)?;
+        } else {
+            debug!("Header not in the best chain");
+
+            self.maybe_reorg(header)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks a header at `height` against our checkpoints. It must match the checkpoint at its
+    /// height, if any, and it can't fork from our best chain before the last checkpoint in it.
+    fn check_checkpoints(&self, block_hash: BlockHash, height: u32) -> Result<(), BlockchainError> {
+        let inner = read_lock!(self);
+        let params = &inner.consensus.parameters;
+
+        if let Some(checkpoint) = params.get_checkpoint(height) {
+            if checkpoint != block_hash {
+                return Err(BlockValidationErrors::CheckpointMismatch(height))?;
+            }
+        }
+
+        // Headers above every checkpoint can't fork before one
+        let best_height = inner.best_block.depth;
+        for (checkpoint_height, checkpoint) in params.checkpoints.iter().rev() {
+            if *checkpoint_height <= height {
+                break;
+            }
+
+            if *checkpoint_height > best_height {
+                continue;
+            }
+
+            if inner.chainstore.get_block_hash(*checkpoint_height)? == Some(*checkpoint) {
+                return Err(BlockValidationErrors::ForkBeforeCheckpoint(
+                    *checkpoint_height,
+                ))?;
+            }
+        }
+
+        Ok(())
+    }
+
     #[inline]
     /// Whether a node is the genesis block for this net
     fn is_genesis(&self, header: &BlockHeader) -> bool {
@@ -248,6 +342,94 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         self.get_disk_block_header(&header.prev_blockhash)
     }
 
+    /// Returns the median time past (MTP) of a block, computed over the timestamps of the block
+    /// and its ancestors, as defined by BIP 113.
+    fn get_block_mtp(&self, block: BlockHash) -> Result<u32, BlockchainError> {
+        let mut header = *self.get_disk_block_header(&block)?;
+        let mut timestamps = vec![header.time];
+
+        while timestamps.len() < MEDIAN_TIME_SPAN && !self.is_genesis(&header) {
+            header = *self.get_ancestor(&header)?;
+            timestamps.push(header.time);
+        }
+
+        Ok(Consensus::get_median_time_past(timestamps))
+    }
+
+    /// Accepts a new header, see [UpdatableChainstate::accept_header]. If `min_work_checked` is
+    /// false, we refuse this header unless its chain has the minimum chain work.
+    fn accept_header_inner(
+        &self,
+        header: BlockHeader,
+        min_work_checked: bool,
+    ) -> Result<(), BlockchainError> {
+        let block_hash = header.block_hash();
+        let disk_header = self.get_disk_block_header(&block_hash);
+
+        match disk_header {
+            Err(e @ BlockchainError::Database(_)) => {
+                // If there's a database error we don't know if we already
+                // have the header or not
+                return Err(e);
+            }
+            Ok(found) => {
+                // Possibly reindex to recompute the best_block field
+                self.maybe_reindex(&found)?;
+                // We already have this header
+                return Ok(());
+            }
+            _ => (),
+        }
+
+        let parent = self.get_disk_block_header(&header.prev_blockhash)?;
+        let height = parent
+            .height()
+            .ok_or(BlockValidationErrors::BlockExtendsAnOrphanChain)?
+            + 1;
+        self.check_checkpoints(block_hash, height)?;
+
+        if !min_work_checked {
+            self.check_minimum_work(&header, &parent)?;
+        }
+
+        self.connect_header(header)
+    }
+
+    /// Refuses a header whose chain doesn't have the minimum chain work, since it could be part of
+    /// a low-work spam chain. Those headers must be presynced first, see
+    /// [UpdatableChainstate::start_headers_presync].
+    fn check_minimum_work(
+        &self,
+        header: &BlockHeader,
+        parent: &DiskBlockHeader,
+    ) -> Result<(), BlockchainError> {
+        let (minimum_work, reached, best_block) = {
+            let inner = read_lock!(self);
+            (
+                inner.consensus.parameters.minimum_chain_work,
+                inner.minimum_work_reached,
+                inner.best_block.best_block,
+            )
+        };
+
+        let extends_tip = header.prev_blockhash == best_block;
+        if minimum_work == Work::from_be_bytes([0; 32]) || (reached && extends_tip) {
+            return Ok(());
+        }
+
+        let parent_work = self.get_branch_work(parent)?;
+        if extends_tip && parent_work >= minimum_work {
+            write_lock!(self).minimum_work_reached = true;
+            return Ok(());
+        }
+
+        if parent_work + header.work() < minimum_work {
+            return Err(BlockValidationErrors::LowChainWork)?;
+        }
+
+        Ok(())
+    }
+
     /// Returns the cumulative work in this branch
     fn get_branch_work(&self, header: &BlockHeader) -> Result<Work, BlockchainError> {
         let mut header = *header;
@@ -378,21 +560,83 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
     }
 
     /// Changes the acc we are using to validate blocks.
-    fn reorg_acc(&self, fork_point: &BlockHeader) -> Result<(), BlockchainError> {
-        let height = self
+    /// Sets our accumulator to the one we had right after the fork point.
+    ///
+    /// `old_validation_index` is the last block we've validated in the chain we are leaving. If
+    /// it is past the fork point, we roll the accumulator back using our undo data. If we lack it,
+    /// we try the roots we stored for the fork point.
+    fn reorg_acc(
+        &self,
+        fork_point: &BlockHeader,
+        old_validation_index: BlockHash,
+    ) -> Result<(), BlockchainError> {
+        let fork_height = self
             .get_block_height(&fork_point.block_hash())?
             .ok_or(BlockchainError::BlockNotPresent)?;
 
-        let acc = self.get_roots_for_block(height)?.unwrap_or_default();
+        let old_height = self
+            .get_block_height(&old_validation_index)?
+            .ok_or(BlockchainError::BlockNotPresent)?;
+
+        // We haven't validated anything after the fork point, our accumulator is still good
+        if old_height <= fork_height {
+            return Ok(());
+        }
+
+        let acc = match self.rollback_acc(old_validation_index, old_height, fork_height) {
+            Ok(acc) => acc,
+            Err(BlockchainError::MissingUndoData(height)) => {
+                warn!("Missing undo data for block {height}, using the roots for the fork point");
+
+                match self.get_roots_for_block(fork_height)? {
+                    Some(acc) => acc,
+                    None if fork_height == 0 => Stump::new(),
+                    None => return Err(BlockchainError::MissingUndoData(height)),
+                }
+            }
+            Err(e) => return Err(e),
+        };
+
         let mut inner = write_lock!(self);
         inner.acc = acc;
 
         Ok(())
     }
 
+    /// Undoes the accumulator changes of every block from `tip` (at `tip_height`) down to, but
+    /// not including, the block at `target_height`.
+    ///
+    /// The current accumulator must be the one after `tip`.
+    fn rollback_acc(
+        &self,
+        tip: BlockHash,
+        tip_height: u32,
+        target_height: u32,
+    ) -> Result<Stump, BlockchainError> {
+        let mut acc = self.acc();
+        let mut block_hash = tip;
+
+        for height in ((target_height + 1)..=tip_height).rev() {
+            let undo = write_lock!(self).chainstore.load_undo_for_block(height)?;
+            let undo: AccumulatorUndo =
+                deserialize(&undo.ok_or(BlockchainError::MissingUndoData(height))?)?;
+
+            // Undo data is indexed by height, so this may be for another block at the same height
+            if undo.block_hash != block_hash {
+                return Err(BlockchainError::MissingUndoData(height));
+            }
+
+            acc = undo.undo(&acc)?;
+            block_hash = self.get_block_header(&block_hash)?.prev_blockhash;
+        }
+
+        Ok(acc)
+    }
+
     // This method should only be called after we validate the new branch
     fn reorg(&self, new_tip: BlockHeader) -> Result<(), BlockchainError> {
         let current_best_block = self.get_block_header(&self.get_best_block()?.1)?;
+        let old_validation_index = read_lock!(self).best_block.validation_index;
         let fork_point = self.find_fork_point(&new_tip)?;
 
         self.mark_chain_as_inactive(&current_best_block, fork_point.block_hash())?;
@@ -402,7 +646,7 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         let depth = self.get_chain_depth(&new_tip)?;
 
         self.change_active_chain(&new_tip, validation_index, depth);
-        self.reorg_acc(&fork_point)?;
+        self.reorg_acc(&fork_point, old_validation_index)?;
 
         Ok(())
     }
@@ -516,6 +760,7 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
 
     fn notify(&self, block: &Block, height: u32, inputs: Option<&HashMap<OutPoint, UtxoData>>) {
         let inner = self.inner.read();
+
         for client in &inner.subscribers {
             if client.wants_spent_utxos() {
                 client.on_block(block, height, inputs);
@@ -525,13 +770,15 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         }
     }
 
+    /// Creates a new chain, with only the genesis block. `chain_params` may be a [Network] or
+    /// custom [ChainParams].
     pub fn new(
         mut chainstore: PersistedState,
-        network: Network,
+        chain_params: impl Into<ChainParams>,
         assume_valid: AssumeValidArg,
     ) -> ChainState<PersistedState> {
-        let parameters = network.into();
-        let genesis = genesis_block(&parameters);
+        let parameters: ChainParams = chain_params.into();
+        let genesis = parameters.genesis.clone();
 
         chainstore
             .save_header(&DiskBlockHeader::FullyValid(genesis.header, 0))
@@ -541,7 +788,9 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
             .update_block_index(0, genesis.block_hash())
             .expect("Error updating index");
 
-        let assume_valid = ChainParams::get_assume_valid(network, assume_valid);
+        let assume_valid = parameters.resolve_assume_valid(assume_valid);
+        let minimum_work_reached = parameters.minimum_chain_work == Work::from_be_bytes([0; 32]);
+        let fee_estimator = Arc::new(FeeEstimator::new());
 
         ChainState {
             inner: RwLock::new(ChainStateInner {
@@ -554,11 +803,15 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
                     alternative_tips: Vec::new(),
                 },
                 broadcast_queue: Vec::new(),
-                subscribers: Vec::new(),
-                fee_estimation: (1_f64, 1_f64, 1_f64),
+                subscribers: vec![fee_estimator.clone()],
+                tx_source: None,
+                fee_estimator,
                 ibd: true,
                 consensus: Consensus { parameters },
                 assume_valid,
+                versionbits: VersionBitsCache::new(),
+                script_workers: 1,
+                minimum_work_reached,
             }),
         }
     }
@@ -572,19 +825,6 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
             .ok_or(BlockchainError::BlockNotPresent)
     }
 
-    /// Returns the parsed accumulator for a given block height, if they are present.
-    fn get_roots_for_block(&self, height: u32) -> Result<Option<Stump>, BlockchainError> {
-        let acc = { write_lock!(self).chainstore.load_roots_for_block(height)? };
-
-        let Some(acc) = acc else {
-            return Ok(None);
-        };
-
-        let mut acc = acc.as_slice();
-        let acc = Stump::deserialize(&mut acc).map_err(BlockchainError::UtreexoError)?;
-        Ok(Some(acc))
-    }
-
     /// Re-indexes the chain if we find ourselves in an undefined state
     ///
     /// Here, we have to find what's the best chain we have, then figure out
@@ -693,11 +933,14 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         }
     }
 
+    /// Loads a chain we've persisted in `chainstore`. `chain_params` may be a [Network] or custom
+    /// [ChainParams].
     pub fn load_chain_state(
         mut chainstore: PersistedState,
-        network: Network,
+        chain_params: impl Into<ChainParams>,
         assume_valid: AssumeValidArg,
     ) -> Result<ChainState<PersistedState>, BlockchainError> {
+        let parameters: ChainParams = chain_params.into();
         let best_block = chainstore
             .load_height()?
             .ok_or(BlockchainError::ChainNotInitialized)?;
@@ -711,19 +954,24 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
 
         let loaded_acc = chainstore.load_roots_for_block(validation_index_height)?;
         let acc = Self::deserialize_accumulator(loaded_acc)?;
+        let fee_estimator = Arc::new(Self::load_fee_estimator(chainstore.load_fee_estimates()?));
 
+        // We only know if our chain has enough work once we see a header extending it
+        let minimum_work_reached = parameters.minimum_chain_work == Work::from_be_bytes([0; 32]);
         let inner = ChainStateInner {
             acc,
             best_block,
             broadcast_queue: Vec::new(),
             chainstore,
-            fee_estimation: (1_f64, 1_f64, 1_f64),
-            subscribers: Vec::new(),
+            subscribers: vec![fee_estimator.clone()],
+            tx_source: None,
+            fee_estimator,
             ibd: true,
-            consensus: Consensus {
-                parameters: network.into(),
-            },
-            assume_valid: ChainParams::get_assume_valid(network, assume_valid),
+            assume_valid: parameters.resolve_assume_valid(assume_valid),
+            consensus: Consensus { parameters },
+            versionbits: VersionBitsCache::new(),
+            script_workers: 1,
+            minimum_work_reached,
         };
 
         info!(
@@ -740,6 +988,69 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         Ok(chainstate)
     }
 
+    /// Writes a snapshot of our chain into `writer`, returning how many bytes were written.
+    ///
+    /// We flush our current state before exporting, so the snapshot includes every header we
+    /// know about. See [export_chain_snapshot] for the data we keep.
+    pub fn export_snapshot<W: Write + ?Sized>(
+        &self,
+        writer: &mut W,
+        roots_heights: &[u32],
+    ) -> Result<usize, BlockchainError> {
+        self.flush()?;
+
+        let mut inner = write_lock!(self);
+        export_chain_snapshot(&mut inner.chainstore, writer, roots_heights)
+    }
+
+    /// Imports a snapshot made with [ChainState::export_snapshot] into `chainstore`, and loads
+    /// a [ChainState] from it.
+    ///
+    /// The snapshot must be for the same chain, and `chainstore` must be empty. Nothing is
+    /// written unless the snapshot passes the checks in [import_chain_snapshot].
+    pub fn import_snapshot<R: Read + ?Sized>(
+        mut chainstore: PersistedState,
+        reader: &mut R,
+        chain_params: impl Into<ChainParams>,
+        assume_valid: AssumeValidArg,
+    ) -> Result<ChainState<PersistedState>, BlockchainError> {
+        let parameters: ChainParams = chain_params.into();
+        let imported = import_chain_snapshot(&mut chainstore, reader, &parameters)?;
+        info!("Imported {imported} headers from snapshot");
+
+        Self::load_chain_state(chainstore, parameters, assume_valid)
+    }
+
+    /// Removes old fork headers, roots and undo data from our chainstore, following `policy`. See
+    /// [ChainStore::compact].
+    ///
+    /// Alternative tips whose headers were removed are forgotten.
+    pub fn compact_chainstore(
+        &self,
+        policy: &CompactionPolicy,
+    ) -> Result<CompactionStats, BlockchainError> {
+        let mut inner = write_lock!(self);
+        let best_height = inner.best_block.depth;
+        let validation_height = inner
+            .chainstore
+            .get_header(&inner.best_block.validation_index)?
+            .and_then(|header| header.height())
+            .unwrap_or(0);
+
+        let stats = inner
+            .chainstore
+            .compact(policy, best_height, validation_height)?;
+
+        let mut alternative_tips = core::mem::take(&mut inner.best_block.alternative_tips);
+        alternative_tips.retain(|tip| matches!(inner.chainstore.get_header(tip), Ok(Some(_))));
+        inner.best_block.alternative_tips = alternative_tips;
+
+        let best_block = inner.best_block.clone();
+        inner.chainstore.save_height(&best_block)?;
+
+        Ok(stats)
+    }
+
     /// Checks whether our database got a file-level corruption, and if so, reindex.
     ///
     /// This protects us from fs corruption, like random bit-flips or power loss.
@@ -822,6 +1133,45 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         Stump::deserialize(&mut acc).map_err(BlockchainError::UtreexoError)
     }
 
+    /// Builds the fee estimator from the estimates saved in our chainstore.
+    ///
+    /// Fee estimates aren't critical, if we can't decode them (e.g. they were written by an
+    /// incompatible version), we just start from scratch.
+    fn load_fee_estimator(estimates: Option<Vec<u8>>) -> FeeEstimator {
+        let Some(estimates) = estimates else {
+            return FeeEstimator::new();
+        };
+
+        match deserialize(&estimates) {
+            Ok(fee_estimator) => fee_estimator,
+            Err(e) => {
+                warn!("Could not load fee estimates, starting from scratch: {e}");
+                FeeEstimator::new()
+            }
+        }
+    }
+
+    /// Returns the fee estimator used by this chainstate.
+    ///
+    /// The mempool should feed new transactions into it, so it can learn how long they take to
+    /// get confirmed.
+    pub fn fee_estimator(&self) -> Arc<FeeEstimator> {
+        read_lock!(self).fee_estimator.clone()
+    }
+
+    /// Sets how many threads we use to verify the scripts of each block. This only has an
+    /// effect with the `parallel-validation` feature, otherwise scripts are verified in the
+    /// calling thread.
+    pub fn set_script_workers(&self, workers: usize) {
+        write_lock!(self).script_workers = workers.max(1);
+    }
+
+    /// Sets where [BlockchainInterface::get_tx] looks for transactions, as we don't keep the
+    /// blocks ourselves.
+    pub fn set_tx_source(&self, source: Arc<dyn TxSource>) {
+        write_lock!(self).tx_source = Some(source);
+    }
+
     fn update_view(
         &self,
         height: u32,
@@ -882,32 +1232,10 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         next_height: u32,
         next_header: &BlockHeader,
     ) -> Result<Target, BlockchainError> {
-        let params: ChainParams = self.chain_params();
-        // Special testnet rule, if a block takes more than 20 minutes to mine, we can
-        // mine a block with diff 1
-        if params.params.allow_min_difficulty_blocks
-            && last_block.time + params.params.pow_target_spacing as u32 * 2 < next_header.time
-        {
-            return Ok(params.params.max_attainable_target);
-        }
-
-        // Regtest don't have retarget
-        if !params.params.no_pow_retargeting && (next_height) % 2016 == 0 {
-            // First block in this epoch
-            let first_block = self.get_header_by_height(next_height - 2016)?;
-            let last_block = self.get_header_by_height(next_height - 1)?;
-
-            let target =
-                Consensus::calc_next_work_required(&last_block, &first_block, self.chain_params());
-
-            if target < params.params.max_attainable_target {
-                return Ok(target);
-            }
-
-            return Ok(params.params.max_attainable_target);
-        }
-
-        Ok(last_block.target())
+        let consensus = read_lock!(self).consensus.clone();
+        consensus.get_next_required_work(last_block, next_height, next_header, |height| {
+            self.get_header_by_height(height).map(|header| *header)
+        })
     }
 
     /// Check timestamp against prev for difficulty-adjustment blocks to prevent timewarp attacks.
@@ -949,15 +1277,37 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
         }
     }
 
+    /// Returns the versionbits rules for the block after `prev`, using the deployments in our
+    /// [ChainParams]
+    pub fn get_deployment_rules(
+        &self,
+        prev: BlockHash,
+    ) -> Result<DeploymentRules, BlockchainError> {
+        let mut guard = write_lock!(self);
+        let inner = &mut *guard;
+
+        inner.versionbits.get_rules(
+            &inner.chainstore,
+            &inner.consensus.parameters.deployments,
+            Some(prev),
+        )
+    }
+
     /// Validates the block without checking whether the inputs are present in the UTXO set. This
     /// function contains the core validation logic.
     ///
     /// The methods `BlockchainInterface::validate_block` and `UpdatableChainstate::connect_block`
     /// call this and additionally verify the inclusion proof (i.e., they perform full validation).
+    ///
+    /// `median_time_past` is the MTP of the previous block, which is needed to check the lock
+    /// times of the transactions, and `rules` are the versionbits rules for this block, see
+    /// [ChainState::get_deployment_rules].
     pub fn validate_block_no_acc(
         &self,
         block: &Block,
         height: u32,
+        median_time_past: u32,
+        rules: &DeploymentRules,
         inputs: HashMap<OutPoint, UtxoData>,
     ) -> Result<(), BlockchainError> {
         if !block.check_merkle_root() {
@@ -978,25 +1328,30 @@ impl<PersistedState: ChainStore> ChainState<PersistedState> {
             return Err(BlockValidationErrors::BlockTooBig)?;
         }
 
+        rules.check_signaling(block.header.version)?;
+
         // Validate block transactions
         let subsidy = read_lock!(self).consensus.get_subsidy(height);
         let verify_script = self.verify_script(height)?;
 
-        #[cfg(feature = "bitcoinconsensus")]
-        let flags = read_lock!(self)
-            .consensus
-            .parameters
-            .get_validation_flags(height, block.block_hash());
-        #[cfg(not(feature = "bitcoinconsensus"))]
-        let flags = 0;
+        let flags = read_lock!(self).consensus.parameters.get_validation_flags(
+            height,
+            block.block_hash(),
+            rules.script_flags,
+        );
+        let lock_time_cutoff =
+            Consensus::get_lock_time_cutoff(block.header.time, median_time_past, flags);
+        let script_workers = read_lock!(self).script_workers;
 
         Consensus::verify_block_transactions(
             height,
+            lock_time_cutoff,
             inputs,
             &block.txdata,
             subsidy,
             verify_script,
             flags,
+            script_workers,
         )?;
         Ok(())
     }
@@ -1013,11 +1368,39 @@ impl<PersistedState: ChainStore> BlockchainInterface for ChainState<PersistedSta
         read_lock!(self).acc.to_owned()
     }
 
+    fn get_roots_for_block(&self, height: u32) -> Result<Option<Stump>, Self::Error> {
+        let acc = { write_lock!(self).chainstore.load_roots_for_block(height)? };
+
+        let Some(acc) = acc else {
+            return Ok(None);
+        };
+
+        let mut acc = acc.as_slice();
+        let acc = Stump::deserialize(&mut acc).map_err(BlockchainError::UtreexoError)?;
+        Ok(Some(acc))
+    }
+
     fn get_fork_point(&self, block: BlockHash) -> Result<BlockHash, Self::Error> {
         let fork_point = self.find_fork_point(&self.get_block_header(&block)?)?;
         Ok(fork_point.block_hash())
     }
 
+    fn get_deployment_info(&self, block: BlockHash) -> Result<DeploymentInfo, Self::Error> {
+        let mut guard = write_lock!(self);
+        let inner = &mut *guard;
+
+        let deployments = inner.versionbits.get_status(
+            &inner.chainstore,
+            &inner.consensus.parameters.deployments,
+            block,
+        )?;
+
+        Ok(DeploymentInfo {
+            buried: inner.consensus.parameters.buried_deployments(),
+            deployments,
+        })
+    }
+
     fn update_acc(
         &self,
         acc: Stump,
@@ -1057,8 +1440,10 @@ impl<PersistedState: ChainStore> BlockchainInterface for ChainState<PersistedSta
         let height = self
             .get_disk_block_header(&block.block_hash())?
             .try_height()?;
+        let median_time_past = self.get_block_mtp(block.header.prev_blockhash)?;
+        let rules = self.get_deployment_rules(block.header.prev_blockhash)?;
 
-        self.validate_block_no_acc(block, height, inputs)
+        self.validate_block_no_acc(block, height, median_time_past, &rules, inputs)
     }
 
     fn get_block_locator_for_tip(&self, tip: BlockHash) -> Result<Vec<BlockHash>, BlockchainError> {
@@ -1110,8 +1495,17 @@ impl<PersistedState: ChainStore> BlockchainInterface for ChainState<PersistedSta
             .ok_or(BlockchainError::BlockNotPresent)
     }
 
-    fn get_tx(&self, _txid: &bitcoin::Txid) -> Result<Option<bitcoin::Transaction>, Self::Error> {
-        unimplemented!("This chainstate doesn't hold any tx")
+    fn get_median_time_past(&self, height: u32) -> Result<u32, Self::Error> {
+        let hash = self.get_block_hash(height)?;
+        self.get_block_mtp(hash)
+    }
+
+    fn get_tx(&self, txid: &bitcoin::Txid) -> Result<Option<bitcoin::Transaction>, Self::Error> {
+        // We don't keep any block, so we ask our tx source, e.g. florestad's txindex. The source
+        // may need our chain too, so we can't hold the lock while it looks.
+        let source = read_lock!(self).tx_source.clone();
+
+        Ok(source.and_then(|source| source.get_tx(txid)))
     }
 
     fn get_height(&self) -> Result<u32, Self::Error> {
@@ -1127,13 +1521,7 @@ impl<PersistedState: ChainStore> BlockchainInterface for ChainState<PersistedSta
 
     fn estimate_fee(&self, target: usize) -> Result<f64, Self::Error> {
         let inner = read_lock!(self);
-        if target == 1 {
-            Ok(inner.fee_estimation.0)
-        } else if target == 10 {
-            Ok(inner.fee_estimation.1)
-        } else {
-            Ok(inner.fee_estimation.2)
-        }
+        Ok(inner.fee_estimator.estimate_fee(target))
     }
 
     fn get_block(&self, _hash: &BlockHash) -> Result<bitcoin::Block, Self::Error> {
@@ -1275,6 +1663,7 @@ impl<PersistedState: ChainStore> UpdatableChainstate for ChainState<PersistedSta
     fn toggle_ibd(&self, is_ibd: bool) {
         let mut inner = write_lock!(self);
         inner.ibd = is_ibd;
+        inner.fee_estimator.set_ibd(is_ibd);
     }
 
     fn connect_block(
@@ -1329,19 +1718,30 @@ impl<PersistedState: ChainStore> UpdatableChainstate for ChainState<PersistedSta
             }
         };
 
-        // Clone inputs only if a subscriber wants spent utxos
-        let inputs_for_notifications = self
-            .inner
-            .read()
-            .subscribers
-            .iter()
-            .any(|subscriber| subscriber.wants_spent_utxos())
-            .then(|| inputs.clone());
+        // Clone inputs only if a subscriber (like our fee estimator) wants spent utxos
+        let inputs_for_notifications = {
+            let inner = self.inner.read();
+            let wants_spent_utxos = inner
+                .subscribers
+                .iter()
+                .any(|subscriber| subscriber.wants_spent_utxos());
+
+            wants_spent_utxos.then(|| inputs.clone())
+        };
+
+        let median_time_past = self.get_block_mtp(block.header.prev_blockhash)?;
+        let rules = self.get_deployment_rules(block.header.prev_blockhash)?;
+        self.validate_block_no_acc(block, height, median_time_past, &rules, inputs)?;
+        let prev_acc = self.acc();
+        let acc = Consensus::update_acc(&prev_acc, block, height, proof, del_hashes)?;
 
-        self.validate_block_no_acc(block, height, inputs)?;
-        let acc = Consensus::update_acc(&self.acc(), block, height, proof, del_hashes)?;
+        // Keep what we need to roll this block back in case of a reorg
+        let undo = AccumulatorUndo::new(block.block_hash(), &prev_acc, &acc);
 
         self.update_view(height, &block.header, acc)?;
+        write_lock!(self)
+            .chainstore
+            .save_undo_for_block(serialize(&undo), height)?;
 
         info!(
             "New tip! hash={} height={height} tx_count={}",
@@ -1370,50 +1770,90 @@ impl<PersistedState: ChainStore> UpdatableChainstate for ChainState<PersistedSta
         let best_block = inner.best_block.clone();
 
         inner.chainstore.save_height(&best_block)?;
+
+        let fee_estimates = serialize(&*inner.fee_estimator);
+        inner.chainstore.save_fee_estimates(fee_estimates)?;
+
         inner.chainstore.flush()?;
 
         Ok(())
     }
 
     fn accept_header(&self, header: BlockHeader) -> Result<(), BlockchainError> {
-        let disk_header = self.get_disk_block_header(&header.block_hash());
+        self.accept_header_inner(header, false)
+    }
 
-        match disk_header {
-            Err(e @ BlockchainError::Database(_)) => {
-                // If there's a database error we don't know if we already
-                // have the header or not
-                return Err(e);
-            }
-            Ok(found) => {
-                // Possibly reindex to recompute the best_block field
-                self.maybe_reindex(&found)?;
-                // We already have this header
-                return Ok(());
-            }
-            _ => (),
-        }
-        // The best block we know of
-        let best_block = self.get_best_block()?;
+    fn accept_presynced_header(&self, header: BlockHeader) -> Result<(), BlockchainError> {
+        self.accept_header_inner(header, true)
+    }
 
-        // Do validation in this header
-        let block_hash = self.validate_header(&header)?;
+    fn start_headers_presync(
+        &self,
+        headers: &[BlockHeader],
+        now: u32,
+        salt: u64,
+    ) -> Result<Option<HeadersSyncState>, BlockchainError> {
+        let Some(first) = headers.first() else {
+            return Ok(None);
+        };
 
-        // Update our current tip
-        if header.prev_blockhash == best_block.1 {
-            let height = best_block.0 + 1;
-            debug!("Header builds on top of our best chain");
+        let (minimum_work, reached, best_block) = {
+            let inner = read_lock!(self);
+            (
+                inner.consensus.parameters.minimum_chain_work,
+                inner.minimum_work_reached,
+                inner.best_block.best_block,
+            )
+        };
 
-            write_lock!(self).best_block.new_block(block_hash, height);
-            let disk_header = DiskBlockHeader::HeadersOnly(header, height);
+        let extends_tip = first.prev_blockhash == best_block;
+        if minimum_work == Work::from_be_bytes([0; 32]) || (reached && extends_tip) {
+            return Ok(None);
+        }
 
-            self.update_header_and_index(&disk_header, block_hash, height)?;
-        } else {
-            debug!("Header not in the best chain");
+        // If we don't know the parent, these headers will be refused by `accept_header`
+        let parent = match self.get_disk_block_header(&first.prev_blockhash) {
+            Ok(parent) => parent,
+            Err(BlockchainError::BlockNotPresent) => return Ok(None),
+            Err(e) => return Err(e),
+        };
 
-            self.maybe_reorg(header)?;
+        let Some(parent_height) = parent.height() else {
+            return Ok(None);
+        };
+
+        let parent_work = self.get_branch_work(&parent)?;
+        if extends_tip && parent_work >= minimum_work {
+            write_lock!(self).minimum_work_reached = true;
+            return Ok(None);
         }
 
-        Ok(())
+        let work = headers
+            .iter()
+            .fold(parent_work, |work, header| work + header.work());
+        if work >= minimum_work {
+            return Ok(None);
+        }
+
+        debug!(
+            "Headers building on {} don't have the minimum chain work yet, starting a presync",
+            first.prev_blockhash
+        );
+
+        let mtp = self.get_block_mtp(first.prev_blockhash)?;
+        let locator = self.get_block_locator_for_tip(first.prev_blockhash)?;
+
+        Ok(Some(HeadersSyncState::new(
+            *parent,
+            parent_height,
+            parent_work,
+            mtp,
+            locator,
+            minimum_work,
+            self.chain_params().params,
+            now,
+            salt,
+        )))
     }
 
     fn get_root_hashes(&self) -> Vec<BitcoinNodeHash> {
@@ -1438,9 +1878,27 @@ impl<PersistedState: ChainStore> UpdatableChainstate for ChainState<PersistedSta
             })
             .collect();
 
+        // The rules only change at the start of a period, so we only compute them for the first
+        // block and for each period boundary in this interval
+        let deployments = self.chain_params().deployments;
+        let mut deployment_rules: Vec<(u32, DeploymentRules)> = Vec::new();
+        for height in (initial_height + 1).max(1)..=final_height {
+            let is_boundary = deployments.iter().any(|d| height % d.period == 0);
+            if !deployment_rules.is_empty() && !is_boundary {
+                continue;
+            }
+
+            let prev = self.get_block_hash(height - 1)?;
+            let rules = self.get_deployment_rules(prev)?;
+            if deployment_rules.last().map(|(_, last)| last) != Some(&rules) {
+                deployment_rules.push((height, rules));
+            }
+        }
+
         let inner = PartialChainStateInner {
             error: None,
             blocks,
+            deployment_rules,
             consensus: Consensus {
                 parameters: self.chain_params(),
             },
@@ -1448,6 +1906,7 @@ impl<PersistedState: ChainStore> UpdatableChainstate for ChainState<PersistedSta
             final_height,
             assume_valid: false,
             current_height: initial_height,
+            script_workers: read_lock!(self).script_workers,
         };
 
         Ok(PartialChainState(UnsafeCell::new(inner)))
@@ -1458,18 +1917,28 @@ impl<T: ChainStore> TryFrom<ChainStateBuilder<T>> for ChainState<T> {
     type Error = BlockchainBuilderError;
 
     fn try_from(mut builder: ChainStateBuilder<T>) -> Result<Self, Self::Error> {
+        let parameters = builder.chain_params()?;
+        let minimum_work_reached = parameters.minimum_chain_work == Work::from_be_bytes([0; 32]);
+        let ibd = builder.ibd();
+        let mut chainstore = builder.chainstore()?;
+
+        let fee_estimator = Arc::new(Self::load_fee_estimator(chainstore.load_fee_estimates()?));
+        fee_estimator.set_ibd(ibd);
+
         let inner = ChainStateInner {
             acc: builder.acc().unwrap_or_default(),
-            chainstore: builder.chainstore()?,
+            chainstore,
             best_block: builder.best_block()?,
-            assume_valid: builder.assume_valid(),
-            ibd: builder.ibd(),
+            assume_valid: builder.assume_valid()?,
+            ibd,
             broadcast_queue: Vec::new(),
-            subscribers: Vec::new(),
-            fee_estimation: (1_f64, 1_f64, 1_f64),
-            consensus: Consensus {
-                parameters: builder.chain_params()?,
-            },
+            subscribers: vec![fee_estimator.clone()],
+            tx_source: None,
+            fee_estimator,
+            consensus: Consensus { parameters },
+            versionbits: VersionBitsCache::new(),
+            script_workers: 1,
+            minimum_work_reached,
         };
 
         let inner = RwLock::new(inner);
@@ -1498,17 +1967,23 @@ mod test {
     use std::format;
     use std::fs::File;
     use std::io::Cursor;
+    use std::sync::Arc;
     use std::vec::Vec;
 
     use bitcoin::block::Header as BlockHeader;
     use bitcoin::consensus::deserialize;
     use bitcoin::consensus::encode::deserialize_hex;
+    use bitcoin::consensus::serialize;
     use bitcoin::consensus::Decodable;
     use bitcoin::constants::genesis_block;
+    use bitcoin::hashes::Hash;
     use bitcoin::Block;
     use bitcoin::BlockHash;
     use bitcoin::Network;
     use bitcoin::OutPoint;
+    use bitcoin::Transaction;
+    use bitcoin::Txid;
+    use bitcoin::Work;
     use floresta_common::assert_ok;
     use floresta_common::bhash;
     use rand::Rng;
@@ -1518,34 +1993,32 @@ mod test {
     use super::BlockchainInterface;
     use super::ChainParams;
     use super::ChainState;
+    use super::ChainStateBuilder;
+    use super::DeploymentRules;
     use super::DiskBlockHeader;
+    use super::FeeEstimator;
+    use super::TxSource;
     use super::UpdatableChainstate;
     use crate::prelude::HashMap;
     use crate::pruned_utreexo::consensus::Consensus;
     use crate::pruned_utreexo::utxo_data::UtxoData;
     use crate::AssumeValidArg;
+    use crate::BlockValidationErrors;
     use crate::BlockchainError;
+    use crate::ChainStore;
     #[cfg(feature = "flat-chainstore")]
     use crate::FlatChainStore;
+    use crate::HeadersSyncPhase;
     #[cfg(feature = "kv-chainstore")]
     use crate::KvChainStore;
 
     #[cfg(feature = "kv-chainstore")]
-    fn setup_test_chain<'a>(
-        network: Network,
-        assume_valid_arg: AssumeValidArg,
-    ) -> ChainState<KvChainStore<'a>> {
-        let test_id = rand::random::<u64>();
-        let chainstore = KvChainStore::new(format!("./tmp-db/{test_id}/")).unwrap();
-        ChainState::new(chainstore, network, assume_valid_arg)
+    fn open_test_chainstore<'a>(test_id: u64) -> KvChainStore<'a> {
+        KvChainStore::new(format!("./tmp-db/{test_id}/")).unwrap()
     }
 
     #[cfg(feature = "flat-chainstore")]
-    fn setup_test_chain(
-        network: Network,
-        assume_valid_arg: AssumeValidArg,
-    ) -> ChainState<FlatChainStore> {
-        let test_id = rand::random::<u64>();
+    fn open_test_chainstore(test_id: u64) -> FlatChainStore {
         let config = crate::FlatChainStoreConfig {
             block_index_size: Some(32_768),
             headers_file_size: Some(32_768),
@@ -1555,8 +2028,32 @@ mod test {
             path: format!("./tmp-db/{test_id}/"),
         };
 
-        let chainstore = FlatChainStore::new(config).unwrap();
-        ChainState::new(chainstore, network, assume_valid_arg)
+        FlatChainStore::new(config).unwrap()
+    }
+
+    #[cfg(feature = "kv-chainstore")]
+    fn setup_test_chain<'a>(
+        network: Network,
+        assume_valid_arg: AssumeValidArg,
+    ) -> ChainState<KvChainStore<'a>> {
+        let chainstore = open_test_chainstore(rand::random::<u64>());
+        ChainState::new(chainstore, test_params(network), assume_valid_arg)
+    }
+
+    #[cfg(feature = "flat-chainstore")]
+    fn setup_test_chain(
+        network: Network,
+        assume_valid_arg: AssumeValidArg,
+    ) -> ChainState<FlatChainStore> {
+        let chainstore = open_test_chainstore(rand::random::<u64>());
+        ChainState::new(chainstore, test_params(network), assume_valid_arg)
+    }
+
+    /// The parameters for `network`, without a minimum chain work, so our test headers get saved
+    fn test_params(network: Network) -> ChainParams {
+        let mut params = ChainParams::from(network);
+        params.minimum_chain_work = Work::from_be_bytes([0; 32]);
+        params
     }
 
     fn decode_block_and_inputs(
@@ -1599,7 +2096,13 @@ mod test {
         // Check whether the block validation passes or not
         let chain = setup_test_chain(Network::Bitcoin, AssumeValidArg::Disabled);
         chain
-            .validate_block_no_acc(&block, 367891, inputs)
+            .validate_block_no_acc(
+                &block,
+                367891,
+                block.header.time,
+                &DeploymentRules::default(),
+                inputs,
+            )
             .expect("Block must be valid");
     }
 
@@ -1617,10 +2120,41 @@ mod test {
         // Check whether the block validation passes or not
         let chain = setup_test_chain(Network::Bitcoin, AssumeValidArg::Disabled);
         chain
-            .validate_block_no_acc(&block, 866342, inputs)
+            .validate_block_no_acc(
+                &block,
+                866342,
+                block.header.time,
+                &DeploymentRules::default(),
+                inputs,
+            )
             .expect("Block must be valid");
     }
 
+    #[test]
+    fn test_get_median_time_past() {
+        let file = include_bytes!("../../testdata/headers.zst");
+        let uncompressed: Vec<u8> = zstd::decode_all(Cursor::new(file)).unwrap();
+        let mut buffer = uncompressed.as_slice();
+
+        let chain = setup_test_chain(Network::Bitcoin, AssumeValidArg::Hardcoded);
+        let headers: Vec<BlockHeader> = (0..100)
+            .map(|_| BlockHeader::consensus_decode(&mut buffer).unwrap())
+            .collect();
+        headers
+            .iter()
+            .for_each(|header| chain.accept_header(*header).unwrap());
+
+        for height in 0..100usize {
+            // The MTP uses the timestamps of the block and (up to) its 10 ancestors
+            let first = height.saturating_sub(10);
+            let mut timestamps: Vec<_> = headers[first..=height].iter().map(|h| h.time).collect();
+            timestamps.sort();
+            let expected = timestamps[timestamps.len() / 2];
+
+            assert_eq!(chain.get_median_time_past(height as u32).unwrap(), expected);
+        }
+    }
+
     #[test]
     fn accept_mainnet_headers() {
         // Accepts the first 10,237 mainnet headers
@@ -1711,6 +2245,130 @@ mod test {
         assert_eq!(0x1e012fa7, next_target.to_compact_lossy().to_consensus());
     }
 
+    #[test]
+    fn test_fee_estimates_persistence() {
+        let test_id = rand::random::<u64>();
+        let chain = ChainState::new(
+            open_test_chainstore(test_id),
+            Network::Regtest,
+            AssumeValidArg::Hardcoded,
+        );
+        chain.toggle_ibd(false);
+
+        let json_blocks = include_str!("../../testdata/test_reorg.json");
+        let blocks: Vec<Vec<&str>> = serde_json::from_str(json_blocks).unwrap();
+        let blocks: Vec<Block> = blocks[0]
+            .iter()
+            .map(|s| deserialize_hex(s).unwrap())
+            .collect();
+
+        // Pretend each coinbase was in our mempool before the block, so every block confirms
+        // one 20 sat/vB transaction in the next block
+        let fee_estimator = chain.fee_estimator();
+        for block in blocks.iter() {
+            fee_estimator.process_transaction(block.txdata[0].compute_txid(), 20.0);
+
+            chain.accept_header(block.header).unwrap();
+            chain
+                .connect_block(block, Proof::default(), HashMap::new(), Vec::new())
+                .unwrap();
+        }
+
+        let estimate = chain.estimate_fee(1).unwrap();
+        assert!((estimate - 20.0).abs() < 1e-9, "Estimate is {estimate}");
+
+        chain.flush().unwrap();
+        drop(fee_estimator);
+        drop(chain);
+
+        let chain = ChainState::load_chain_state(
+            open_test_chainstore(test_id),
+            Network::Regtest,
+            AssumeValidArg::Hardcoded,
+        )
+        .unwrap();
+
+        assert_eq!(chain.fee_estimator().height(), 10);
+        assert_eq!(chain.estimate_fee(1).unwrap(), estimate);
+    }
+
+    #[test]
+    fn test_builder_loads_fee_estimates() {
+        let test_id = rand::random::<u64>();
+        let mut chainstore = open_test_chainstore(test_id);
+
+        let fee_estimator = FeeEstimator::new();
+        fee_estimator.skip_to_height(42);
+        chainstore
+            .save_fee_estimates(serialize(&fee_estimator))
+            .unwrap();
+
+        let chain = ChainStateBuilder::new()
+            .with_chain_params(ChainParams::from(Network::Regtest))
+            .with_chainstore(chainstore)
+            .build()
+            .unwrap();
+
+        assert_eq!(chain.fee_estimator().height(), 42);
+    }
+
+    #[test]
+    fn test_corrupted_fee_estimates() {
+        let test_id = rand::random::<u64>();
+        let chain = ChainState::new(
+            open_test_chainstore(test_id),
+            Network::Regtest,
+            AssumeValidArg::Hardcoded,
+        );
+
+        // We need at least one header, otherwise the chain won't be considered initialized
+        let json_blocks = include_str!("../../testdata/test_reorg.json");
+        let blocks: Vec<Vec<&str>> = serde_json::from_str(json_blocks).unwrap();
+        let block: Block = deserialize_hex(blocks[0][0]).unwrap();
+        chain.accept_header(block.header).unwrap();
+
+        chain.flush().unwrap();
+        drop(chain);
+
+        let mut chainstore = open_test_chainstore(test_id);
+        chainstore.save_fee_estimates(vec![0xff; 42]).unwrap();
+        chainstore.flush().unwrap();
+        drop(chainstore);
+
+        // We should ignore the garbage and start with a fresh estimator
+        let chain = ChainState::load_chain_state(
+            open_test_chainstore(test_id),
+            Network::Regtest,
+            AssumeValidArg::Hardcoded,
+        )
+        .unwrap();
+
+        assert_eq!(chain.fee_estimator().height(), 0);
+        assert_eq!(chain.estimate_fee(1).unwrap(), crate::MIN_RELAY_FEERATE);
+    }
+
+    #[test]
+    fn test_tx_source() {
+        struct GenesisSource(Transaction);
+
+        impl TxSource for GenesisSource {
+            fn get_tx(&self, txid: &Txid) -> Option<Transaction> {
+                (self.0.compute_txid() == *txid).then(|| self.0.clone())
+            }
+        }
+
+        let chain = setup_test_chain(Network::Regtest, AssumeValidArg::Hardcoded);
+        let coinbase = genesis_block(Network::Regtest).txdata[0].clone();
+        let txid = coinbase.compute_txid();
+
+        // Without a source we can't find any transaction
+        assert_eq!(chain.get_tx(&txid).unwrap(), None);
+
+        chain.set_tx_source(Arc::new(GenesisSource(coinbase.clone())));
+        assert_eq!(chain.get_tx(&txid).unwrap(), Some(coinbase));
+        assert_eq!(chain.get_tx(&Txid::all_zeros()).unwrap(), None);
+    }
+
     #[test]
     fn test_reorg() {
         let chain = setup_test_chain(Network::Regtest, AssumeValidArg::Hardcoded);
@@ -1802,6 +2460,209 @@ mod test {
         }
     }
 
+    /// Returns the regtest chain and the fork from `test_reorg.json`, as headers
+    fn get_reorg_headers() -> (Vec<BlockHeader>, Vec<BlockHeader>) {
+        let json_blocks = include_str!("../../testdata/test_reorg.json");
+        let blocks: Vec<Vec<&str>> = serde_json::from_str(json_blocks).unwrap();
+
+        let parse_headers = |blocks: &[&str]| {
+            blocks
+                .iter()
+                .map(|s| deserialize_hex::<Block>(s).unwrap().header)
+                .collect::<Vec<BlockHeader>>()
+        };
+
+        (parse_headers(&blocks[0]), parse_headers(&blocks[1]))
+    }
+
+    #[test]
+    fn test_minimum_chain_work() {
+        let (headers, _) = get_reorg_headers();
+
+        // We need five blocks of work, genesis doesn't count
+        let mut params = ChainParams::from(Network::Regtest);
+        params.minimum_chain_work = headers[..5]
+            .iter()
+            .fold(Work::from_be_bytes([0; 32]), |work, header| {
+                work + header.work()
+            });
+
+        let chainstore = open_test_chainstore(rand::random::<u64>());
+        let chain = ChainState::new(chainstore, params, AssumeValidArg::Disabled);
+        let genesis = genesis_block(Network::Regtest).header;
+
+        // Four headers aren't enough, so they need a presync starting at genesis
+        let sync = chain
+            .start_headers_presync(&headers[..4], genesis.time, rand::random())
+            .unwrap()
+            .expect("these headers don't have enough work");
+        assert_eq!(sync.phase(), HeadersSyncPhase::Presync);
+        assert_eq!(sync.current_height(), 0);
+
+        // But five are
+        assert!(chain
+            .start_headers_presync(&headers[..5], genesis.time, rand::random())
+            .unwrap()
+            .is_none());
+
+        // Without a presync, a low-work header is refused
+        assert!(matches!(
+            chain.accept_header(headers[0]),
+            Err(BlockchainError::BlockValidation(
+                BlockValidationErrors::LowChainWork
+            ))
+        ));
+        assert_eq!(chain.get_best_block().unwrap().0, 0);
+
+        for header in &headers[..5] {
+            chain.accept_presynced_header(*header).unwrap();
+        }
+
+        // From now on, headers extending our chain don't need a presync
+        assert!(chain
+            .start_headers_presync(&headers[5..6], genesis.time, rand::random())
+            .unwrap()
+            .is_none());
+        assert!(read_lock!(chain).minimum_work_reached);
+
+        // A fork building on genesis still does
+        assert!(chain
+            .start_headers_presync(&headers[..1], genesis.time, rand::random())
+            .unwrap()
+            .is_some());
+
+        // While headers extending our tip are accepted right away
+        chain.accept_header(headers[5]).unwrap();
+        assert_eq!(
+            chain.get_best_block().unwrap(),
+            (6, headers[5].block_hash())
+        );
+    }
+
+    #[test]
+    fn test_low_work_mainnet_headers() {
+        let file = include_bytes!("../../testdata/headers.zst");
+        let uncompressed: Vec<u8> = zstd::decode_all(Cursor::new(file)).unwrap();
+        let mut buffer = uncompressed.as_slice();
+
+        let mut headers = Vec::new();
+        while let Ok(header) = BlockHeader::consensus_decode(&mut buffer) {
+            headers.push(header);
+        }
+        headers.remove(0);
+
+        // The minimum is only reached by the last header, after a few retargets
+        let mut params = ChainParams::from(Network::Bitcoin);
+        params.minimum_chain_work = headers
+            .iter()
+            .fold(Work::from_be_bytes([0; 32]), |work, header| {
+                work + header.work()
+            });
+
+        let chainstore = open_test_chainstore(rand::random::<u64>());
+        let chain = ChainState::new(chainstore, params, AssumeValidArg::Hardcoded);
+        let now = headers.last().unwrap().time;
+        let mut sync = chain
+            .start_headers_presync(&headers[..2_000], now, rand::random())
+            .unwrap()
+            .unwrap();
+
+        // We download the chain twice, and only accept it after the second time
+        for _ in 0..2 {
+            for chunk in headers.chunks(2_000) {
+                let result = sync.process_next_headers(chunk, chunk.len() == 2_000);
+                assert!(result.success);
+
+                for header in result.headers {
+                    chain.accept_presynced_header(header).unwrap();
+                }
+            }
+
+            if sync.phase() == HeadersSyncPhase::Presync {
+                assert_eq!(chain.get_best_block().unwrap().0, 0);
+            }
+        }
+
+        assert_eq!(sync.phase(), HeadersSyncPhase::Final);
+        assert_eq!(
+            chain.get_best_block().unwrap(),
+            (10_236, headers.last().unwrap().block_hash())
+        );
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let (headers, fork) = get_reorg_headers();
+
+        // The fork builds on height five, so it forks before the checkpoint at height seven
+        let mut params = ChainParams::from(Network::Regtest);
+        params.checkpoints = vec![(7, headers[6].block_hash())];
+        let chainstore = open_test_chainstore(rand::random::<u64>());
+        let chain = ChainState::new(chainstore, params, AssumeValidArg::Disabled);
+
+        for header in &headers {
+            chain.accept_header(*header).unwrap();
+        }
+
+        assert!(matches!(
+            chain.accept_header(fork[0]),
+            Err(BlockchainError::BlockValidation(
+                BlockValidationErrors::ForkBeforeCheckpoint(7)
+            ))
+        ));
+
+        // A header that doesn't match our checkpoint is refused
+        let mut params = ChainParams::from(Network::Regtest);
+        params.checkpoints = vec![(3, BlockHash::all_zeros())];
+        let chainstore = open_test_chainstore(rand::random::<u64>());
+        let chain = ChainState::new(chainstore, params, AssumeValidArg::Disabled);
+
+        chain.accept_header(headers[0]).unwrap();
+        chain.accept_header(headers[1]).unwrap();
+        assert!(matches!(
+            chain.accept_header(headers[2]),
+            Err(BlockchainError::BlockValidation(
+                BlockValidationErrors::CheckpointMismatch(3)
+            ))
+        ));
+        assert_eq!(chain.get_best_block().unwrap().0, 2);
+    }
+
+    #[test]
+    fn test_rollback_acc() {
+        let chain = setup_test_chain(Network::Regtest, AssumeValidArg::Hardcoded);
+        let json_blocks = include_str!("../../testdata/test_reorg.json");
+        let blocks: Vec<Vec<&str>> = serde_json::from_str(json_blocks).unwrap();
+        let blocks: Vec<Block> = blocks[0]
+            .iter()
+            .map(|s| deserialize_hex(s).unwrap())
+            .collect();
+
+        let mut accs = vec![chain.acc()];
+        for block in blocks.iter() {
+            chain.accept_header(block.header).unwrap();
+            chain
+                .connect_block(block, Proof::default(), HashMap::new(), Vec::new())
+                .unwrap();
+
+            accs.push(chain.acc());
+        }
+
+        // We should be able to get back to the accumulator at any height using the undo data
+        let tip = blocks.last().unwrap().block_hash();
+        for (height, acc) in accs.iter().enumerate() {
+            assert_eq!(&chain.rollback_acc(tip, 10, height as u32).unwrap(), acc);
+        }
+
+        // Undo data for a different block can't be used
+        let other_block =
+            bhash!("6e9c49a19038f7db8d13f6c2e70566385536ea11975528b557799e08a014e785");
+        match chain.rollback_acc(other_block, 10, 5) {
+            Err(BlockchainError::MissingUndoData(10)) => {}
+            other => panic!("Expected missing undo data, got {other:?}"),
+        }
+    }
+
     #[test]
     fn test_chainstate_functions() {
         let file = include_bytes!("../../testdata/signet_headers.zst");
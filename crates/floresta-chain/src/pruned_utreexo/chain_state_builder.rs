@@ -10,7 +10,6 @@
 //! - Current chain tip and header
 use bitcoin::block::Header as BlockHeader;
 use bitcoin::BlockHash;
-use bitcoin::Network;
 use rustreexo::accumulator::stump::Stump;
 
 use super::chain_state::ChainState;
@@ -57,8 +56,8 @@ pub struct ChainStateBuilder<PersistedState: ChainStore> {
     /// The chain parameters.
     chain_params: Option<ChainParams>,
 
-    /// The block hash that is assumed to be valid.
-    assume_valid: Option<BlockHash>,
+    /// The assume-valid argument, resolved against the chain parameters on build.
+    assume_valid: Option<AssumeValidArg>,
 
     /// The current chain tip.
     tip: Option<(BlockHash, u32)>,
@@ -125,9 +124,10 @@ impl<T: ChainStore> ChainStateBuilder<T> {
 
     /// Sets the assume-valid argument, which can be `Disabled`, `Hardcoded` or `UserInput`. This
     /// option is used to skip script validation up to the specified block, speeding up IBD.
-    pub fn with_assume_valid(mut self, arg: AssumeValidArg, network: Network) -> Self {
-        // TODO: handle possible Err
-        self.assume_valid = ChainParams::get_assume_valid(network, arg);
+    ///
+    /// `Hardcoded` uses the assume-valid block from the chain parameters.
+    pub fn with_assume_valid(mut self, arg: AssumeValidArg) -> Self {
+        self.assume_valid = Some(arg);
         self
     }
 
@@ -190,7 +190,10 @@ impl<T: ChainStore> ChainStateBuilder<T> {
     }
 
     /// Returns the block hash of the assume-valid option, if enabled.
-    pub(super) fn assume_valid(&self) -> Option<BlockHash> {
-        self.assume_valid
+    ///
+    /// Returns an error if the chain parameters are missing.
+    pub(super) fn assume_valid(&self) -> Result<Option<BlockHash>, BlockchainBuilderError> {
+        let arg = self.assume_valid.unwrap_or(AssumeValidArg::Disabled);
+        Ok(self.chain_params()?.resolve_assume_valid(arg))
     }
 }
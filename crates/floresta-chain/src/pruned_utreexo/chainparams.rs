@@ -6,26 +6,73 @@
 //! - DNS seeds for peer discovery
 //! - Assumable validation states for Utreexo
 //! - Block verification flag exceptions
+//! - The minimum chain work and checkpoints we require from headers
+//! - Soft forks deployed with versionbits, see [`Deployment`]
+//! - Custom signet and regtest parameters, see [`ChainParamsConfig`]
 //!
 //! The main struct [`ChainParams`] encapsulates all chain-specific parameters while
 //! [`DnsSeed`] handles peer discovery through DNS.
 
 extern crate alloc;
+use alloc::string::String;
+use alloc::string::ToString;
 use alloc::vec::Vec;
 use core::ffi::c_uint;
+use core::fmt;
 
+use bitcoin::block::Header as BlockHeader;
 use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::sha256d;
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::Magic;
 use bitcoin::p2p::ServiceFlags;
 use bitcoin::params::Params;
 use bitcoin::Block;
 use bitcoin::BlockHash;
 use bitcoin::Network;
+use bitcoin::ScriptBuf;
+use bitcoin::Work;
 use floresta_common::acchashes;
 use floresta_common::bhash;
 use rustreexo::accumulator::node_hash::BitcoinNodeHash;
 
 use crate::prelude::*;
 use crate::AssumeValidArg;
+use crate::BuriedDeployment;
+use crate::Deployment;
+use crate::DeploymentTrigger;
+use crate::NO_TIMEOUT;
+
+/// Script verification flags, with the same values used by `libbitcoinconsensus`. We define them
+/// here so they are available even without the `bitcoinconsensus` feature.
+pub mod verify_flags {
+    use core::ffi::c_uint;
+
+    /// Don't enforce any soft-fork rule
+    pub const VERIFY_NONE: c_uint = 0;
+
+    /// Evaluate P2SH subscripts (BIP16)
+    pub const VERIFY_P2SH: c_uint = 1 << 0;
+
+    /// Enforce strict DER signatures (BIP66)
+    pub const VERIFY_DERSIG: c_uint = 1 << 2;
+
+    /// Enforce NULLDUMMY (BIP147)
+    pub const VERIFY_NULLDUMMY: c_uint = 1 << 4;
+
+    /// Enable CHECKLOCKTIMEVERIFY (BIP65)
+    pub const VERIFY_CHECKLOCKTIMEVERIFY: c_uint = 1 << 9;
+
+    /// Enable CHECKSEQUENCEVERIFY (BIP112)
+    pub const VERIFY_CHECKSEQUENCEVERIFY: c_uint = 1 << 10;
+
+    /// Enable witness programs (BIP141)
+    pub const VERIFY_WITNESS: c_uint = 1 << 11;
+
+    /// Enable taproot and tapscript (BIPs 341 and 342)
+    pub const VERIFY_TAPROOT: c_uint = 1 << 17;
+}
 
 #[derive(Clone, Debug)]
 /// This struct encapsulates all chain-specific parameters.
@@ -61,6 +108,103 @@ pub struct ChainParams {
 
     /// Whether we should enforce BIP-094 "Testnet 4" rules
     pub enforce_bip94: bool,
+
+    /// The magic bytes that start every p2p message on this chain
+    pub magic: Magic,
+
+    /// The block challenge of a custom signet, or `None` if this is not a custom signet
+    pub signet_challenge: Option<ScriptBuf>,
+
+    /// The block we assume valid if [`AssumeValidArg::Hardcoded`] is used
+    pub assume_valid: Option<BlockHash>,
+
+    /// The Utreexo state we use if assume-utreexo is enabled
+    pub assume_utreexo: AssumeUtreexoValue,
+
+    /// The DNS seeds we use to find peers on this chain
+    pub dns_seeds: Vec<DnsSeed>,
+
+    /// Soft forks activated by miner signaling, tracked with a
+    /// [`VersionBitsCache`](crate::VersionBitsCache)
+    pub deployments: Vec<Deployment>,
+
+    /// The least amount of work a chain must have before we save its headers. Like Core's
+    /// `nMinimumChainWork`, this stops peers from filling our disk with cheap, low-work headers
+    pub minimum_chain_work: Work,
+
+    /// Blocks that must be in the best chain, sorted by height. We refuse headers that don't
+    /// match a checkpoint, or that fork before the last checkpoint in our best chain
+    pub checkpoints: Vec<(u32, BlockHash)>,
+}
+
+/// Overrides for the default parameters of a network, used to run custom signets (with their own
+/// block challenge) or regtest chains with different activation heights.
+///
+/// Every field left as `None` keeps the default for the network. Note that Floresta doesn't
+/// check the signet block solutions, the challenge is only used to find the network magic.
+#[derive(Clone, Debug, Default)]
+pub struct ChainParamsConfig {
+    /// The signet block challenge, only valid for signet
+    pub signet_challenge: Option<ScriptBuf>,
+
+    /// A custom genesis header
+    pub genesis: Option<BlockHeader>,
+
+    /// The height at which BIP34 (height in coinbase) is activated
+    pub bip34_height: Option<u32>,
+
+    /// The height at which BIP65 (CHECKLOCKTIMEVERIFY) is activated
+    pub bip65_height: Option<u32>,
+
+    /// The height at which BIP66 (strict DER signatures) is activated
+    pub bip66_height: Option<u32>,
+
+    /// The height at which csv(CHECK_SEQUENCE_VERIFY) is activated
+    pub csv_activation_height: Option<u32>,
+
+    /// The height at which segwit is activated
+    pub segwit_activation_height: Option<u32>,
+
+    /// The block we assume valid if [`AssumeValidArg::Hardcoded`] is used
+    pub assume_valid: Option<BlockHash>,
+
+    /// The Utreexo state we use if assume-utreexo is enabled
+    pub assume_utreexo: Option<AssumeUtreexoValue>,
+
+    /// Domain names of DNS seeds for this chain
+    pub dns_seeds: Option<Vec<String>>,
+
+    /// The least amount of work a chain must have before we save its headers
+    pub minimum_chain_work: Option<Work>,
+
+    /// Blocks that must be in the best chain, as `(height, hash)`
+    pub checkpoints: Option<Vec<(u32, BlockHash)>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Errors returned while building [`ChainParams`] from a [`ChainParamsConfig`]
+pub enum ChainParamsError {
+    /// Only signet and regtest parameters may be customized
+    NotCustomizable(Network),
+
+    /// A signet challenge was given for a network that isn't signet
+    ChallengeWithoutSignet(Network),
+}
+
+impl fmt::Display for ChainParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainParamsError::NotCustomizable(network) => {
+                write!(f, "the parameters for {network} can't be customized")
+            }
+            ChainParamsError::ChallengeWithoutSignet(network) => {
+                write!(
+                    f,
+                    "a signet challenge was given, but the network is {network}"
+                )
+            }
+        }
+    }
 }
 
 /// A dns seed is a authoritative DNS server that returns the IP addresses of nodes that are
@@ -71,12 +215,13 @@ pub struct ChainParams {
 ///
 /// Some seeds allow filtering by service flags, so we may use this to find peers that are
 /// likely to be running Utreexo, for example.
+#[derive(Clone, Debug)]
 pub struct DnsSeed {
     /// The network this peer supports (e.g, mainnet, testnet, etc)
     pub network: Network,
 
     /// The domain name of the seed
-    pub seed: &'static str,
+    pub seed: String,
 
     /// Useful filters we can use to find relevant peers
     pub filters: ServiceFlags,
@@ -85,10 +230,10 @@ pub struct DnsSeed {
 /// This functionality is used to create a new DNS seed with possible filters.
 impl DnsSeed {
     /// Create a new DNS seed
-    pub fn new(network: Network, seed: &'static str, filters: ServiceFlags) -> Self {
+    pub fn new(network: Network, seed: &str, filters: ServiceFlags) -> Self {
         DnsSeed {
             network,
-            seed,
+            seed: seed.to_string(),
             filters,
         }
     }
@@ -113,6 +258,29 @@ pub struct AssumeUtreexoValue {
     pub leaves: u64,
 }
 
+impl AssumeUtreexoValue {
+    /// Formats this value as Rust code, in the same shape as the hardcoded values in
+    /// [ChainParams::get_assume_utreexo]
+    pub fn to_rust(&self) -> String {
+        let roots = match self.roots.is_empty() {
+            true => "Vec::new()".to_string(),
+            false => {
+                let mut roots = String::new();
+                for root in self.roots.iter() {
+                    roots.push_str(&format!("        \"{root}\",\n"));
+                }
+
+                format!("acchashes![\n{roots}    ]\n    .to_vec()")
+            }
+        };
+
+        format!(
+            "AssumeUtreexoValue {{\n    block_hash: bhash!(\n        \"{}\"\n    ),\n    height: {},\n    roots: {roots},\n    leaves: {},\n}}",
+            self.block_hash, self.height, self.leaves
+        )
+    }
+}
+
 impl ChainParams {
     /// This method is called when Assume Utreexo is set to true. It means that the user will accept the hardcoded utreexo state for the specified block, if it is found in the best chain. We can then sync rapidly from this state.
     pub fn get_assume_utreexo(network: Network) -> AssumeUtreexoValue {
@@ -200,9 +368,134 @@ impl ChainParams {
         }
     }
 
-    #[cfg(feature = "bitcoinconsensus")]
-    /// Returns the validation flags for a given block hash and height
-    pub fn get_validation_flags(&self, height: u32, hash: BlockHash) -> c_uint {
+    /// Resolves an [`AssumeValidArg`] for this chain, using our own assume-valid block if it's
+    /// [`AssumeValidArg::Hardcoded`]
+    pub fn resolve_assume_valid(&self, arg: AssumeValidArg) -> Option<BlockHash> {
+        match arg {
+            AssumeValidArg::Disabled => None,
+            AssumeValidArg::UserInput(hash) => Some(hash),
+            AssumeValidArg::Hardcoded => self.assume_valid,
+        }
+    }
+
+    /// Returns the checkpoint at `height`, if there's one
+    pub fn get_checkpoint(&self, height: u32) -> Option<BlockHash> {
+        self.checkpoints
+            .iter()
+            .find(|(checkpoint_height, _)| *checkpoint_height == height)
+            .map(|(_, hash)| *hash)
+    }
+
+    /// Builds the parameters for `network`, with the overrides in `config` applied.
+    ///
+    /// Only signet and regtest can be customized. If a signet challenge or a genesis header is
+    /// given, this is a different chain, so the hardcoded assume-valid, assume-utreexo, DNS
+    /// seeds, minimum chain work and checkpoints of `network` are dropped, unless they are also
+    /// given in `config`.
+    pub fn from_config(
+        network: Network,
+        config: ChainParamsConfig,
+    ) -> Result<ChainParams, ChainParamsError> {
+        if !matches!(network, Network::Signet | Network::Regtest) {
+            return Err(ChainParamsError::NotCustomizable(network));
+        }
+
+        let mut params = ChainParams::from(network);
+
+        if let Some(challenge) = config.signet_challenge {
+            if network != Network::Signet {
+                return Err(ChainParamsError::ChallengeWithoutSignet(network));
+            }
+
+            params.magic = signet_magic(&challenge);
+            params.signet_challenge = Some(challenge);
+        }
+
+        if let Some(header) = config.genesis {
+            // We only know the header, but the genesis coinbase is unspendable anyway
+            params.genesis = Block {
+                header,
+                txdata: Vec::new(),
+            };
+        }
+
+        if params.signet_challenge.is_some() || config.genesis.is_some() {
+            let genesis_hash = params.genesis.block_hash();
+
+            params.assume_valid = None;
+            params.assume_utreexo = AssumeUtreexoValue {
+                block_hash: genesis_hash,
+                height: 0,
+                roots: Vec::new(),
+                leaves: 0,
+            };
+            params.dns_seeds = Vec::new();
+            params.minimum_chain_work = Work::from_be_bytes([0; 32]);
+            params.checkpoints = Vec::new();
+        }
+
+        if let Some(height) = config.bip34_height {
+            params.params.bip34_height = height;
+        }
+        if let Some(height) = config.bip65_height {
+            params.params.bip65_height = height;
+        }
+        if let Some(height) = config.bip66_height {
+            params.params.bip66_height = height;
+        }
+        if let Some(height) = config.csv_activation_height {
+            params.csv_activation_height = height;
+        }
+        if let Some(height) = config.segwit_activation_height {
+            params.segwit_activation_height = height;
+        }
+        if let Some(hash) = config.assume_valid {
+            params.assume_valid = Some(hash);
+        }
+        if let Some(assume_utreexo) = config.assume_utreexo {
+            params.assume_utreexo = assume_utreexo;
+        }
+        if let Some(seeds) = config.dns_seeds {
+            // We don't know which service filters a custom seed supports
+            params.dns_seeds = seeds
+                .iter()
+                .map(|seed| DnsSeed::new(network, seed, ServiceFlags::NONE))
+                .collect();
+        }
+        if let Some(work) = config.minimum_chain_work {
+            params.minimum_chain_work = work;
+        }
+        if let Some(mut checkpoints) = config.checkpoints {
+            checkpoints.sort_by_key(|(height, _)| *height);
+            params.checkpoints = checkpoints;
+        }
+
+        Ok(params)
+    }
+
+    /// Returns the soft forks that activate at a fixed height on this chain
+    pub fn buried_deployments(&self) -> Vec<BuriedDeployment> {
+        let buried = |name, height| BuriedDeployment { name, height };
+
+        vec![
+            buried("bip34", self.params.bip34_height),
+            buried("bip66", self.params.bip66_height),
+            buried("bip65", self.params.bip65_height),
+            buried("csv", self.csv_activation_height),
+            buried("segwit", self.segwit_activation_height),
+        ]
+    }
+
+    /// Returns the validation flags for a given block hash and height.
+    ///
+    /// `deployment_flags` are the flags of the versionbits deployments active for this block,
+    /// see [`DeploymentRules`](crate::DeploymentRules).
+    pub fn get_validation_flags(
+        &self,
+        height: u32,
+        hash: BlockHash,
+        deployment_flags: c_uint,
+    ) -> c_uint {
         if let Some(flag) = self.exceptions.get(&hash) {
             return *flag;
         }
@@ -216,34 +509,192 @@ impl ChainParams {
         // mainnet.
         // For simplicity, always leave P2SH+WITNESS+TAPROOT on except for the two
         // violating blocks.
-        let mut flags = bitcoinconsensus::VERIFY_P2SH | bitcoinconsensus::VERIFY_WITNESS;
+        let mut flags = verify_flags::VERIFY_P2SH
+            | verify_flags::VERIFY_WITNESS
+            | verify_flags::VERIFY_TAPROOT
+            | deployment_flags;
 
         if height >= self.params.bip65_height {
-            flags |= bitcoinconsensus::VERIFY_CHECKLOCKTIMEVERIFY;
+            flags |= verify_flags::VERIFY_CHECKLOCKTIMEVERIFY;
         }
         if height >= self.params.bip66_height {
-            flags |= bitcoinconsensus::VERIFY_DERSIG;
+            flags |= verify_flags::VERIFY_DERSIG;
         }
         if height >= self.csv_activation_height {
-            flags |= bitcoinconsensus::VERIFY_CHECKSEQUENCEVERIFY;
+            flags |= verify_flags::VERIFY_CHECKSEQUENCEVERIFY;
         }
         if height >= self.segwit_activation_height {
-            flags |= bitcoinconsensus::VERIFY_NULLDUMMY;
+            flags |= verify_flags::VERIFY_NULLDUMMY;
         }
         flags
     }
 }
 
-#[cfg(feature = "bitcoinconsensus")]
+/// Computes the network magic of a signet with the given block challenge.
+///
+/// Like Bitcoin Core, the magic is the first four bytes of the double-SHA256 of the serialized
+/// challenge (with its length prefix).
+pub fn signet_magic(challenge: &ScriptBuf) -> Magic {
+    let hash = sha256d::Hash::hash(&serialize(challenge));
+    let mut magic = [0; 4];
+    magic.copy_from_slice(&hash.to_byte_array()[..4]);
+
+    Magic::from_bytes(magic)
+}
+
+/// Returns the versionbits deployments of a network, with the same parameters as Bitcoin Core.
+///
+/// Taproot is enforced since genesis (see [`ChainParams::get_validation_flags`]), so we only
+/// track its deployment to report it.
+fn get_deployments(network: Network) -> Vec<Deployment> {
+    let taproot = |trigger, min_activation_height, threshold| Deployment {
+        name: "taproot",
+        bit: 2,
+        trigger,
+        min_activation_height,
+        threshold,
+        period: 2016,
+        script_flags: verify_flags::VERIFY_TAPROOT,
+    };
+
+    let testdummy = |trigger, threshold, period| Deployment {
+        name: "testdummy",
+        bit: 28,
+        trigger,
+        min_activation_height: 0,
+        threshold,
+        period,
+        script_flags: verify_flags::VERIFY_NONE,
+    };
+
+    let taproot_signaling = DeploymentTrigger::MedianTime {
+        start_time: 1619222400, // April 24th, 2021
+        timeout: 1628640000,    // August 11th, 2021
+    };
+    let testdummy_signaling = DeploymentTrigger::MedianTime {
+        start_time: 1199145601, // January 1, 2008
+        timeout: 1230767999,    // December 31, 2008
+    };
+
+    match network {
+        Network::Bitcoin => vec![
+            testdummy(testdummy_signaling, 1815, 2016),
+            taproot(taproot_signaling, 709_632, 1815),
+        ],
+        Network::Testnet => vec![
+            testdummy(testdummy_signaling, 1512, 2016),
+            taproot(taproot_signaling, 0, 1512),
+        ],
+        Network::Testnet4 => vec![
+            testdummy(DeploymentTrigger::NeverActive, 1512, 2016),
+            taproot(DeploymentTrigger::AlwaysActive, 0, 1512),
+        ],
+        Network::Signet => vec![
+            testdummy(DeploymentTrigger::NeverActive, 1815, 2016),
+            taproot(DeploymentTrigger::AlwaysActive, 0, 1815),
+        ],
+        Network::Regtest => vec![
+            testdummy(
+                DeploymentTrigger::MedianTime {
+                    start_time: 0,
+                    timeout: NO_TIMEOUT,
+                },
+                108,
+                144,
+            ),
+            taproot(DeploymentTrigger::AlwaysActive, 0, 108),
+        ],
+    }
+}
+
+/// Returns the minimum chain work of a network, the same values used by Bitcoin Core 24.
+///
+/// Testnet4 and regtest don't have one.
+fn get_minimum_chain_work(network: Network) -> Work {
+    let work = |hex| Work::from_unprefixed_hex(hex).expect("hardcoded values are valid");
+
+    match network {
+        Network::Bitcoin => {
+            work("00000000000000000000000000000000000000003404ba0801921119f903495e")
+        }
+        Network::Testnet => {
+            work("00000000000000000000000000000000000000000000076f6e7cbd0beade5d20")
+        }
+        Network::Signet => work("000000000000000000000000000000000000000000000000000001ad46be4862"),
+        Network::Testnet4 | Network::Regtest => Work::from_be_bytes([0; 32]),
+    }
+}
+
+/// Returns the checkpoints of a network, taken from Bitcoin Core. Only mainnet has them.
+fn get_checkpoints(network: Network) -> Vec<(u32, BlockHash)> {
+    match network {
+        Network::Bitcoin => vec![
+            (
+                11111,
+                bhash!("0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d"),
+            ),
+            (
+                33333,
+                bhash!("000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6"),
+            ),
+            (
+                74000,
+                bhash!("0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20"),
+            ),
+            (
+                105000,
+                bhash!("00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97"),
+            ),
+            (
+                134444,
+                bhash!("00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe"),
+            ),
+            (
+                168000,
+                bhash!("000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763"),
+            ),
+            (
+                193000,
+                bhash!("000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317"),
+            ),
+            (
+                210000,
+                bhash!("000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e"),
+            ),
+            (
+                216116,
+                bhash!("00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e"),
+            ),
+            (
+                225430,
+                bhash!("00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932"),
+            ),
+            (
+                250000,
+                bhash!("000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214"),
+            ),
+            (
+                279000,
+                bhash!("0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40"),
+            ),
+            (
+                295000,
+                bhash!("00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983"),
+            ),
+        ],
+        _ => Vec::new(),
+    }
+}
+
 /// We use an inverse logic to pick validation flags.
 /// When we call verify_script we need to tell what to validate (taproot, segwit, CSV, P2SH...).
 /// Although those features were added later in the protocol, their exact template would rarely appear in a transaction.
 /// There's almost no transactions in the chain that "looks like segwit but are not segwit".
 /// We pretend segwit was enabled since genesis, and only skip this for blocks that have such transactions using hardcoded values.
 fn get_exceptions() -> HashMap<BlockHash, c_uint> {
-    use bitcoinconsensus::VERIFY_NONE;
-    use bitcoinconsensus::VERIFY_P2SH;
-    use bitcoinconsensus::VERIFY_WITNESS;
+    use verify_flags::VERIFY_NONE;
+    use verify_flags::VERIFY_P2SH;
+    use verify_flags::VERIFY_WITNESS;
     let mut exceptions = HashMap::new();
     exceptions.insert(
         bhash!("00000000000002dc756eebf4f49723ed8d30cc28a5f108eb94b1ba88ac4f9c22"),
@@ -260,11 +711,6 @@ fn get_exceptions() -> HashMap<BlockHash, c_uint> {
     exceptions
 }
 
-#[cfg(not(feature = "bitcoinconsensus"))]
-fn get_exceptions() -> HashMap<BlockHash, c_uint> {
-    HashMap::new()
-}
-
 impl AsRef<Params> for ChainParams {
     fn as_ref(&self) -> &Params {
         &self.params
@@ -275,6 +721,13 @@ impl From<Network> for ChainParams {
     fn from(network: Network) -> Self {
         let genesis = genesis_block(Params::new(network));
         let exceptions = get_exceptions();
+        let magic = network.magic();
+        let assume_valid = ChainParams::get_assume_valid(network, AssumeValidArg::Hardcoded);
+        let assume_utreexo = ChainParams::get_assume_utreexo(network);
+        let dns_seeds = get_chain_dns_seeds(network);
+        let deployments = get_deployments(network);
+        let minimum_chain_work = get_minimum_chain_work(network);
+        let checkpoints = get_checkpoints(network);
 
         match network {
             Network::Bitcoin => ChainParams {
@@ -288,6 +741,14 @@ impl From<Network> for ChainParams {
                 csv_activation_height: 419_328,
                 exceptions,
                 enforce_bip94: false,
+                magic,
+                signet_challenge: None,
+                assume_valid,
+                assume_utreexo,
+                dns_seeds,
+                deployments,
+                minimum_chain_work,
+                checkpoints: checkpoints.clone(),
             },
             Network::Testnet => ChainParams {
                 params: Params::new(network),
@@ -300,6 +761,14 @@ impl From<Network> for ChainParams {
                 csv_activation_height: 770_112,
                 exceptions,
                 enforce_bip94: false,
+                magic,
+                signet_challenge: None,
+                assume_valid,
+                assume_utreexo,
+                dns_seeds,
+                deployments,
+                minimum_chain_work,
+                checkpoints: checkpoints.clone(),
             },
             Network::Testnet4 => ChainParams {
                 params: Params::new(network),
@@ -312,6 +781,14 @@ impl From<Network> for ChainParams {
                 csv_activation_height: 1,
                 exceptions,
                 enforce_bip94: true,
+                magic,
+                signet_challenge: None,
+                assume_valid,
+                assume_utreexo,
+                dns_seeds,
+                deployments,
+                minimum_chain_work,
+                checkpoints: checkpoints.clone(),
             },
             Network::Signet => ChainParams {
                 params: Params::new(network),
@@ -324,6 +801,14 @@ impl From<Network> for ChainParams {
                 segwit_activation_height: 1,
                 exceptions,
                 enforce_bip94: false,
+                magic,
+                signet_challenge: None,
+                assume_valid,
+                assume_utreexo,
+                dns_seeds,
+                deployments,
+                minimum_chain_work,
+                checkpoints: checkpoints.clone(),
             },
             Network::Regtest => ChainParams {
                 params: Params::new(network),
@@ -336,6 +821,14 @@ impl From<Network> for ChainParams {
                 segwit_activation_height: 0,
                 exceptions,
                 enforce_bip94: false,
+                magic,
+                signet_challenge: None,
+                assume_valid,
+                assume_utreexo,
+                dns_seeds,
+                deployments,
+                minimum_chain_work,
+                checkpoints,
             },
         }
     }
@@ -443,3 +936,127 @@ pub fn get_chain_dns_seeds(network: Network) -> Vec<DnsSeed> {
 
     seeds
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use bitcoin::p2p::Magic;
+    use bitcoin::BlockHash;
+    use bitcoin::Network;
+    use bitcoin::ScriptBuf;
+    use bitcoin::Work;
+
+    use super::signet_magic;
+    use super::ChainParams;
+    use super::ChainParamsConfig;
+    use super::ChainParamsError;
+    use crate::AssumeValidArg;
+
+    /// The challenge of the default signet
+    const SIGNET_CHALLENGE: &str = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+
+    #[test]
+    fn test_assume_utreexo_to_rust() {
+        let genesis = ChainParams::get_assume_utreexo(Network::Regtest);
+        let expected = "AssumeUtreexoValue {
+    block_hash: bhash!(
+        \"0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206\"
+    ),
+    height: 0,
+    roots: Vec::new(),
+    leaves: 0,
+}";
+        assert_eq!(genesis.to_rust(), expected);
+
+        let mainnet = ChainParams::get_assume_utreexo(Network::Bitcoin);
+        let code = mainnet.to_rust();
+        assert!(code.starts_with("AssumeUtreexoValue {\n    block_hash: bhash!(\n        \"0000000000000000000239f2b7f982df299193bdd693f499e6b893d8276ab7ce\"\n    ),\n    height: 902967,\n    roots: acchashes![\n        \"bd53eef66849c9d3ca13b62ce694030ac4d4b484c6f490f473b9868a7c5df2e8\",\n"));
+        assert!(code.ends_with("        \"976184c55f74cbb780938a20e2a5df2791cf51e712f68a400a6b024c77ad78e4\",\n    ]\n    .to_vec(),\n    leaves: 2860457445,\n}"));
+        // One line per root, plus the block hash, height, roots and leaves
+        assert_eq!(code.matches(",\n").count(), mainnet.roots.len() + 4);
+    }
+
+    #[test]
+    fn test_signet_magic() {
+        let challenge = ScriptBuf::from_hex(SIGNET_CHALLENGE).unwrap();
+        assert_eq!(signet_magic(&challenge), Network::Signet.magic());
+
+        // An OP_TRUE signet, as often used for testing
+        let anyone_can_sign = ScriptBuf::from_hex("51").unwrap();
+        assert_ne!(signet_magic(&anyone_can_sign), Network::Signet.magic());
+    }
+
+    #[test]
+    fn test_custom_signet() {
+        let challenge = ScriptBuf::from_hex("51").unwrap();
+        let config = ChainParamsConfig {
+            signet_challenge: Some(challenge.clone()),
+            ..Default::default()
+        };
+
+        let params = ChainParams::from_config(Network::Signet, config).unwrap();
+        assert_eq!(params.magic, signet_magic(&challenge));
+        assert_eq!(params.signet_challenge, Some(challenge));
+
+        // The default signet values don't apply to our chain
+        assert!(params.dns_seeds.is_empty());
+        assert_eq!(params.resolve_assume_valid(AssumeValidArg::Hardcoded), None);
+        assert_eq!(
+            params.assume_utreexo.block_hash,
+            params.genesis.block_hash()
+        );
+        assert_eq!(params.minimum_chain_work, Work::from_be_bytes([0; 32]));
+        assert!(params.checkpoints.is_empty());
+    }
+
+    #[test]
+    fn test_custom_regtest() {
+        let assume_valid = BlockHash::all_zeros();
+        let config = ChainParamsConfig {
+            bip34_height: Some(500),
+            bip65_height: Some(1351),
+            bip66_height: Some(1251),
+            csv_activation_height: Some(432),
+            segwit_activation_height: Some(100),
+            assume_valid: Some(assume_valid),
+            dns_seeds: Some(vec!["seed.example.com".to_string()]),
+            minimum_chain_work: Some(Work::from_be_bytes([1; 32])),
+            checkpoints: Some(vec![(20, BlockHash::all_zeros()), (10, assume_valid)]),
+            ..Default::default()
+        };
+
+        let params = ChainParams::from_config(Network::Regtest, config).unwrap();
+        assert_eq!(params.magic, Magic::REGTEST);
+        assert_eq!(params.params.bip34_height, 500);
+        assert_eq!(params.params.bip65_height, 1351);
+        assert_eq!(params.params.bip66_height, 1251);
+        assert_eq!(params.csv_activation_height, 432);
+        assert_eq!(params.segwit_activation_height, 100);
+        assert_eq!(params.dns_seeds[0].seed, "seed.example.com");
+        assert_eq!(
+            params.resolve_assume_valid(AssumeValidArg::Hardcoded),
+            Some(assume_valid)
+        );
+        assert_eq!(params.minimum_chain_work, Work::from_be_bytes([1; 32]));
+        assert_eq!(params.checkpoints[0].0, 10);
+        assert_eq!(params.get_checkpoint(20), Some(BlockHash::all_zeros()));
+        assert_eq!(params.get_checkpoint(15), None);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let with_challenge = ChainParamsConfig {
+            signet_challenge: Some(ScriptBuf::from_hex("51").unwrap()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ChainParams::from_config(Network::Regtest, with_challenge).unwrap_err(),
+            ChainParamsError::ChallengeWithoutSignet(Network::Regtest)
+        );
+        assert_eq!(
+            ChainParams::from_config(Network::Bitcoin, ChainParamsConfig::default()).unwrap_err(),
+            ChainParamsError::NotCustomizable(Network::Bitcoin)
+        );
+    }
+}
@@ -5,12 +5,15 @@
 //! It also defines two important types for our storage format:
 //! - [DiskBlockHeader]: A block header linked to its validation-state metadata
 //! - [BestChain]: Tracks the current best chain, last valid block, and fork tips
+//!
+//! And [DbCheckSum], used to detect corruption in our databases and snapshots.
 
 use bitcoin::block::Header as BlockHeader;
 use bitcoin::consensus::encode;
 use bitcoin::consensus::Decodable;
 use bitcoin::consensus::Encodable;
 use bitcoin::BlockHash;
+use xxhash_rust::xxh3;
 
 use crate::prelude::*;
 use crate::BlockchainError;
@@ -20,9 +23,12 @@ use crate::DatabaseError;
 /// the [ChainState](super::chain_state::ChainState) to save and retrieve data about the blockchain,
 /// likely on disk.
 ///
-/// Right now, you can use the [FlatChainStore](super::flat_chain_store::FlatChainStore) or
-/// [KvChainStore](super::kv_chainstore::KvChainStore) implementations. The former is the store that
-/// we use at production, while the latter is a simpler key-value store.
+/// Right now, you can use the [FlatChainStore](super::flat_chain_store::FlatChainStore),
+/// [KvChainStore](super::kv_chainstore::KvChainStore) or
+/// [SqliteChainStore](super::sqlite_chainstore::SqliteChainStore) implementations. The first is the
+/// store that we use at production, the second is a simpler key-value store, and the last one keeps
+/// everything in a SQLite database that can be inspected with standard tools. Data can be moved
+/// between them with [migrate_chain_store].
 ///
 /// This trait requires an associated error type that implements [DatabaseError]; a marker trait
 /// satisfied by any `T: std::error::Error + std::fmt::Display`. This is useful to abstract the
@@ -46,6 +52,34 @@ pub trait ChainStore {
     /// Saves the blockchain height.
     fn save_height(&mut self, height: &BestChain) -> Result<(), Self::Error>;
 
+    /// Saves the serialized state of our [FeeEstimator](crate::FeeEstimator), overwriting the
+    /// previous one.
+    ///
+    /// The default implementation doesn't persist anything, so the estimator starts from scratch
+    /// after each restart.
+    fn save_fee_estimates(&mut self, _estimates: Vec<u8>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Loads the serialized state of our [FeeEstimator](crate::FeeEstimator), if we have one.
+    fn load_fee_estimates(&mut self) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(None)
+    }
+
+    /// Saves the [AccumulatorUndo](crate::AccumulatorUndo) for the block at a given height.
+    ///
+    /// If we already have undo data for this height (e.g. after a reorg), it gets replaced. The
+    /// default implementation doesn't persist anything, so reorgs rely only on the stored roots.
+    fn save_undo_for_block(&mut self, _undo: Vec<u8>, _height: u32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Loads the serialized [AccumulatorUndo](crate::AccumulatorUndo) for the block at a given
+    /// height, if we have one.
+    fn load_undo_for_block(&mut self, _height: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(None)
+    }
+
     /// Get a block header from our database. See [DiskBlockHeader] for more info about
     /// the data we save.
     fn get_header(&self, block_hash: &BlockHash) -> Result<Option<DiskBlockHeader>, Self::Error>;
@@ -74,6 +108,78 @@ pub trait ChainStore {
     /// If you're using a database that already checks for integrity by itself,
     /// this can safely be a no-op.
     fn check_integrity(&self) -> Result<(), Self::Error>;
+
+    /// Removes the fork headers, roots and undo data we don't need anymore, following `policy`.
+    /// `best_height` is the height of our best chain, and `validation_height` the height of the
+    /// last block we validated.
+    ///
+    /// The default implementation doesn't remove anything.
+    fn compact(
+        &mut self,
+        _policy: &CompactionPolicy,
+        _best_height: u32,
+        _validation_height: u32,
+    ) -> Result<CompactionStats, Self::Error> {
+        Ok(CompactionStats::default())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What we keep when compacting our chainstore, see [ChainStore::compact]
+pub struct CompactionPolicy {
+    /// Fork headers and undo data buried more than this many blocks below our tip are removed
+    pub fork_depth: u32,
+
+    /// We keep the roots for every height that is a multiple of this, so we can still load an
+    /// accumulator close to any old block
+    pub roots_interval: u32,
+
+    /// We keep the roots for all of our last `roots_window` validated blocks
+    pub roots_window: u32,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        CompactionPolicy {
+            fork_depth: 2016,
+            roots_interval: 2016,
+            roots_window: 2016,
+        }
+    }
+}
+
+impl CompactionPolicy {
+    /// Whether we should keep the roots for `height`, given the height of the last block we
+    /// validated
+    pub fn keep_roots(&self, height: u32, validation_height: u32) -> bool {
+        height == 0
+            || height % self.roots_interval.max(1) == 0
+            || height.saturating_add(self.roots_window) >= validation_height
+    }
+
+    /// Whether we should keep a fork header at `height`, given the height of our best chain
+    pub fn keep_fork_header(&self, height: u32, best_height: u32) -> bool {
+        height.saturating_add(self.fork_depth) >= best_height
+    }
+
+    /// Whether we should keep the undo data for `height`, given the height of our best chain.
+    /// We won't reorg deeper than the fork headers we keep, so this follows `fork_depth` too.
+    pub fn keep_undo(&self, height: u32, best_height: u32) -> bool {
+        self.keep_fork_header(height, best_height)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// What was removed by [ChainStore::compact]
+pub struct CompactionStats {
+    /// How many fork headers were removed
+    pub fork_headers: u32,
+
+    /// For how many heights we've removed the roots
+    pub roots: u32,
+
+    /// For how many heights we've removed the undo data
+    pub undo: u32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -280,6 +386,137 @@ impl Decodable for BestChain {
     }
 }
 
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileChecksum(pub(crate) u64);
+
+impl FileChecksum {
+    /// Computes the XXH3-64 checksum of `data`
+    pub(crate) fn new(data: &[u8]) -> Self {
+        FileChecksum(xxh3::xxh3_64(data))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The current checksum of our database
+pub struct DbCheckSum {
+    /// The checksum of the headers file
+    pub(crate) headers_checksum: FileChecksum,
+
+    /// The checksum of the index map
+    pub(crate) index_checksum: FileChecksum,
+
+    /// The checksum of the fork headers file
+    pub(crate) fork_headers_checksum: FileChecksum,
+}
+
+impl DbCheckSum {
+    /// Computes the checksum for the headers, index and fork headers data
+    ///
+    /// As checksum, the [xxHash] of each region is used. This is a fast hash function that is
+    /// very good at detecting errors in memory. It is not cryptographically secure, but it is
+    /// enough for random errors in a file.
+    ///
+    /// [xxHash]: https://github.com/Cyan4973/xxHash
+    pub(crate) fn new(headers: &[u8], index: &[u8], fork_headers: &[u8]) -> Self {
+        DbCheckSum {
+            headers_checksum: FileChecksum::new(headers),
+            index_checksum: FileChecksum::new(index),
+            fork_headers_checksum: FileChecksum::new(fork_headers),
+        }
+    }
+}
+
+/// How many headers we copy between flushes in [migrate_chain_store]
+const MIGRATION_FLUSH_INTERVAL: u32 = 10_000;
+
+/// Copies all the chain data from `source` into `dest`, returning how many headers were copied.
+///
+/// This moves the headers in our best chain and their block index, the accumulator roots and
+/// undo data for each height, the headers for every alternative tip we know about (back to where
+/// they fork from our best chain), the fee estimator state and, lastly, the [BestChain]. Since
+/// the [BestChain] is only written after everything else, an interrupted migration leaves a
+/// destination without one, and running the migration again resumes it, overwriting what was
+/// already copied.
+///
+/// The destination must not have a [BestChain], and the source must be initialized, otherwise we
+/// return [BlockchainError::ChainStoreNotEmpty] and [BlockchainError::ChainNotInitialized]
+/// respectively.
+pub fn migrate_chain_store<S: ChainStore, D: ChainStore>(
+    source: &mut S,
+    dest: &mut D,
+) -> Result<usize, BlockchainError> {
+    let best_chain = source
+        .load_height()?
+        .ok_or(BlockchainError::ChainNotInitialized)?;
+
+    if dest.load_height()?.is_some() {
+        return Err(BlockchainError::ChainStoreNotEmpty);
+    }
+
+    let mut copied = 0;
+    for height in 0..=best_chain.depth {
+        let hash = source
+            .get_block_hash(height)?
+            .ok_or(BlockchainError::BlockNotPresent)?;
+        let header = source
+            .get_header(&hash)?
+            .ok_or(BlockchainError::BlockNotPresent)?;
+
+        dest.save_header(&header)?;
+        dest.update_block_index(height, hash)?;
+
+        if let Some(roots) = source.load_roots_for_block(height)? {
+            dest.save_roots_for_block(roots, height)?;
+        }
+
+        if let Some(undo) = source.load_undo_for_block(height)? {
+            dest.save_undo_for_block(undo, height)?;
+        }
+
+        copied += 1;
+        if height % MIGRATION_FLUSH_INTERVAL == 0 {
+            dest.flush()?;
+        }
+    }
+
+    // Forks aren't in the block index, so we walk back from each tip until we reach our best
+    // chain, or a fork we've already walked. Fork headers copied by an interrupted migration are
+    // kept, so we don't save them twice.
+    let mut walked = HashSet::new();
+    for tip in best_chain.alternative_tips.iter() {
+        let mut hash = *tip;
+        while let Some(header) = source.get_header(&hash)? {
+            let in_best_chain = matches!(
+                header,
+                DiskBlockHeader::FullyValid(..)
+                    | DiskBlockHeader::HeadersOnly(..)
+                    | DiskBlockHeader::AssumedValid(..)
+            );
+
+            if in_best_chain || !walked.insert(hash) {
+                break;
+            }
+
+            if dest.get_header(&hash)?.is_none() {
+                dest.save_header(&header)?;
+                copied += 1;
+            }
+
+            hash = header.prev_blockhash;
+        }
+    }
+
+    if let Some(estimates) = source.load_fee_estimates()? {
+        dest.save_fee_estimates(estimates)?;
+    }
+
+    dest.save_height(&best_chain)?;
+    dest.flush()?;
+
+    Ok(copied)
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
@@ -362,6 +599,19 @@ mod tests {
         assert_eq!(disk_header.block_hash(), header.block_hash());
     }
 
+    #[test]
+    fn compaction_policy_large_values() {
+        let policy = CompactionPolicy {
+            fork_depth: u32::MAX,
+            roots_interval: 2016,
+            roots_window: u32::MAX,
+        };
+
+        // We keep everything, instead of overflowing
+        assert!(policy.keep_roots(1, 900_000));
+        assert!(policy.keep_fork_header(1, 900_000));
+    }
+
     #[test]
     fn encode_decode_disk_block_header() {
         let header = gen_header();
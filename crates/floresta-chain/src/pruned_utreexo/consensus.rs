@@ -3,26 +3,39 @@
 //! assume anything about the chainstate, so it can be used in any context.
 //! We use this to avoid code reuse among the different implementations of the chainstate.
 extern crate alloc;
+#[cfg(feature = "parallel-validation")]
+extern crate std;
 
 use core::ffi::c_uint;
 
 use bitcoin::block::Header as BlockHeader;
 use bitcoin::hashes::sha256;
+use bitcoin::opcodes::Class;
+use bitcoin::opcodes::ClassifyContext;
+use bitcoin::script::Instruction;
+use bitcoin::taproot::TAPROOT_LEAF_MASK;
+use bitcoin::taproot::TAPROOT_LEAF_TAPSCRIPT;
 use bitcoin::Block;
 use bitcoin::CompactTarget;
 use bitcoin::OutPoint;
+use bitcoin::Script;
 use bitcoin::ScriptBuf;
+use bitcoin::Sequence;
 use bitcoin::Target;
 use bitcoin::Transaction;
 use bitcoin::TxIn;
+use bitcoin::TxOut;
 use bitcoin::Txid;
 use floresta_common::prelude::*;
 use rustreexo::accumulator::proof::Proof;
 use rustreexo::accumulator::stump::Stump;
 
+use super::chainparams::verify_flags;
 use super::chainparams::ChainParams;
 use super::error::BlockValidationErrors;
 use super::error::BlockchainError;
+#[cfg(feature = "script-interpreter")]
+use super::script_interpreter;
 use super::udata;
 use crate::pruned_utreexo::utxo_data::UtxoData;
 use crate::TransactionError;
@@ -30,6 +43,53 @@ use crate::TransactionError;
 /// The value of a single coin in satoshis.
 pub const COIN_VALUE: u64 = 100_000_000;
 
+/// The maximum sigop cost allowed in a block. Legacy and P2SH sigops count as 4, while witness
+/// sigops count as 1, the same way the block weight is computed.
+///
+/// See <https://github.com/bitcoin/bitcoin/blob/v28.0/src/consensus/consensus.h#L17>
+pub const MAX_BLOCK_SIGOPS_COST: usize = 80_000;
+
+/// The maximum size of a script, in bytes. This applies to scriptPubKeys, scriptSigs and
+/// witness scripts, but not to tapscripts.
+///
+/// See <https://github.com/bitcoin/bitcoin/blob/v28.0/src/script/script.h#L39>
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// The maximum size of a single stack element, in bytes. For segwit v0 and tapscript spends,
+/// every item in the initial witness stack must respect this limit.
+///
+/// See <https://github.com/bitcoin/bitcoin/blob/v28.0/src/script/script.h#L27>
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+/// The maximum number of elements in the stack. A witness script (or tapscript) that starts with
+/// more items than this will always fail.
+///
+/// See <https://github.com/bitcoin/bitcoin/blob/v28.0/src/script/script.h#L36>
+pub const MAX_STACK_SIZE: usize = 1_000;
+
+/// The first byte of a taproot annex, if one is present in the witness.
+const TAPROOT_ANNEX_PREFIX: u8 = 0x50;
+
+/// The number of blocks used to compute the median time past (MTP), defined in BIP 113 as the
+/// median timestamp of a block and its 10 ancestors.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Lock times below this value are interpreted as block heights, and as UNIX timestamps otherwise.
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// If this bit is set in an input's nSequence, it doesn't have a relative lock time (BIP 68).
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+
+/// If this bit is set in an input's nSequence, its relative lock time is expressed in units of
+/// 512 seconds. Otherwise, it is expressed in blocks (BIP 68).
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+
+/// The bits of an input's nSequence that encode its relative lock time value (BIP 68).
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_ffff;
+
+/// Time-based relative lock times have a granularity of 2^9 = 512 seconds (BIP 68).
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
 /// The version tag to be prepended to the leafhash. It's just the sha512 hash of the string
 /// `UtreexoV1` represented as a vector of [u8] ([85 116 114 101 101 120 111 86 49]).
 /// The same tag is "5574726565786f5631" as a hex string.
@@ -70,6 +130,85 @@ pub struct Consensus {
     pub parameters: ChainParams,
 }
 
+/// The scripts of a transaction, whose spent outputs were already taken from the UTXO set.
+///
+/// This lets [`Consensus::verify_block_transactions`] verify scripts after (and in parallel to)
+/// the UTXO bookkeeping, which must be serial.
+pub struct ScriptCheck<'a> {
+    transaction: &'a Transaction,
+    spent_outputs: Vec<TxOut>,
+}
+
+impl<'a> ScriptCheck<'a> {
+    /// Removes the UTXOs spent by `transaction` from `utxos`, failing if any of them is missing.
+    pub fn new(
+        transaction: &'a Transaction,
+        utxos: &mut HashMap<OutPoint, UtxoData>,
+    ) -> Result<Self, TransactionError> {
+        let txid = || transaction.compute_txid();
+        let spent_outputs = transaction
+            .input
+            .iter()
+            .map(|input| match utxos.remove(&input.previous_output) {
+                Some(utxo) => Ok(utxo.txout),
+                None => Err(tx_err!(txid, UtxoNotFound, input.previous_output)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(ScriptCheck {
+            transaction,
+            spent_outputs,
+        })
+    }
+
+    /// Verifies the scripts of every input with our own [script
+    /// interpreter](crate::pruned_utreexo::script_interpreter), even if we were also built with
+    /// `bitcoinconsensus`. Unlike `libbitcoinconsensus`, it supports taproot.
+    #[cfg(feature = "script-interpreter")]
+    pub fn verify_with_interpreter(&self, flags: c_uint) -> Result<(), TransactionError> {
+        let txid = || self.transaction.compute_txid();
+
+        script_interpreter::verify_scripts(self.transaction, &self.spent_outputs, flags)
+            .map_err(|e| tx_err!(txid, ScriptValidationError, format!("{e}")))
+    }
+
+    /// Verifies the scripts of every input, with `libbitcoinconsensus` if we were built with the
+    /// `bitcoinconsensus` feature, or with our own interpreter otherwise
+    pub fn verify(&self, _flags: c_uint) -> Result<(), TransactionError> {
+        // rust-bitcoin doesn't pass the spent outputs to libbitcoinconsensus, which are
+        // required to verify taproot spends, so we can't ask for them to be checked
+        #[cfg(feature = "bitcoinconsensus")]
+        {
+            let txid = || self.transaction.compute_txid();
+            let spent: HashMap<&OutPoint, &TxOut> = self
+                .transaction
+                .input
+                .iter()
+                .map(|input| &input.previous_output)
+                .zip(self.spent_outputs.iter())
+                .collect();
+
+            self.transaction
+                .verify_with_flags(
+                    |outpoint| spent.get(outpoint).map(|txout| (*txout).clone()),
+                    _flags & !verify_flags::VERIFY_TAPROOT,
+                )
+                .map_err(|e| tx_err!(txid, ScriptValidationError, format!("{e:?}")))
+        }
+
+        #[cfg(all(feature = "script-interpreter", not(feature = "bitcoinconsensus")))]
+        {
+            self.verify_with_interpreter(_flags)
+        }
+
+        #[cfg(not(any(feature = "bitcoinconsensus", feature = "script-interpreter")))]
+        {
+            let _ = (self.transaction, &self.spent_outputs);
+            Ok(())
+        }
+    }
+}
+
 impl Consensus {
     /// Returns the amount of block subsidy to be paid in a block, given it's height.
     ///
@@ -91,15 +230,58 @@ impl Consensus {
     /// - The first transaction in the block must be coinbase
     /// - The coinbase transaction must have the correct value (subsidy + fees)
     /// - The block must not create more coins than allowed
+    /// - The total sigop cost must not exceed [MAX_BLOCK_SIGOPS_COST]
+    /// - All transactions must be final and satisfy their relative lock times, as verified by
+    ///   [`Consensus::validate_locktime`]. `lock_time_cutoff` is the value returned by
+    ///   [`Consensus::get_lock_time_cutoff`] for this block. The coinbase must be final as well
     /// - All transactions must be valid, as verified by [`Consensus::verify_transaction`]
+    ///
+    /// The UTXO bookkeeping is done serially, but the scripts are only verified afterwards,
+    /// split across `script_workers` threads (this requires the `parallel-validation` feature,
+    /// otherwise they are verified in the calling thread). The returned error is the same we get
+    /// by verifying each transaction in order.
     #[allow(unused)]
+    #[allow(clippy::too_many_arguments)]
     pub fn verify_block_transactions(
         height: u32,
-        mut utxos: HashMap<OutPoint, UtxoData>,
+        lock_time_cutoff: u32,
+        utxos: HashMap<OutPoint, UtxoData>,
         transactions: &[Transaction],
         subsidy: u64,
         verify_script: bool,
         flags: c_uint,
+        script_workers: usize,
+    ) -> Result<(), BlockchainError> {
+        let mut script_checks = Vec::new();
+        let result = Self::check_block_transactions(
+            height,
+            lock_time_cutoff,
+            utxos,
+            transactions,
+            subsidy,
+            verify_script,
+            flags,
+            &mut script_checks,
+        );
+
+        // If a transaction is invalid, the scripts of the transactions before it would have been
+        // verified first, so their errors take precedence
+        Self::verify_script_checks(&script_checks, flags, script_workers)?;
+        result
+    }
+
+    /// Runs every check of [`Consensus::verify_block_transactions`], except for the scripts,
+    /// which are pushed to `script_checks` in block order.
+    #[allow(clippy::too_many_arguments)]
+    fn check_block_transactions<'a>(
+        height: u32,
+        lock_time_cutoff: u32,
+        mut utxos: HashMap<OutPoint, UtxoData>,
+        transactions: &'a [Transaction],
+        subsidy: u64,
+        verify_script: bool,
+        flags: c_uint,
+        script_checks: &mut Vec<ScriptCheck<'a>>,
     ) -> Result<(), BlockchainError> {
         // Blocks must contain at least one transaction (i.e., the coinbase)
         if transactions.is_empty() {
@@ -108,20 +290,42 @@ impl Consensus {
 
         // Total block fees that the miner can claim in the coinbase
         let mut fee = 0;
+        // Total sigop cost of the block, see `MAX_BLOCK_SIGOPS_COST`
+        let mut sigop_cost = 0;
 
         for (n, transaction) in transactions.iter().enumerate() {
+            // We must count sigops before verifying the transaction, as it may consume the UTXOs
+            sigop_cost += Self::get_sigop_cost(transaction, &utxos, flags);
+            if sigop_cost > MAX_BLOCK_SIGOPS_COST {
+                return Err(BlockValidationErrors::TooManySigops)?;
+            }
+
             if n == 0 {
                 if !transaction.is_coinbase() {
                     return Err(BlockValidationErrors::FirstTxIsNotCoinbase)?;
                 }
                 Self::verify_coinbase(transaction)?;
+
+                // The coinbase must be final too, but it spends no UTXO that could have a
+                // relative lock time
+                if !Self::is_final_tx(transaction, height, lock_time_cutoff) {
+                    let txid = || transaction.compute_txid();
+                    return Err(tx_err!(txid, NonFinalTransaction))?;
+                }
+
                 // Skip next checks: coinbase input is exempt, coinbase reward checked later
                 continue;
             }
 
-            // Actually verify the transaction
+            // Lock times must be checked before verifying the transaction, as it may consume the UTXOs
+            Self::validate_locktime(transaction, &utxos, height, lock_time_cutoff, flags)?;
+
+            // Verify everything but the scripts, which are checked after the whole block
             let (in_value, out_value) =
-                Self::verify_transaction(transaction, &mut utxos, height, verify_script, flags)?;
+                Self::check_transaction(transaction, &utxos, height, flags)?;
+            if let Some(check) = Self::take_script_check(transaction, &mut utxos, verify_script)? {
+                script_checks.push(check);
+            }
 
             // Fee is the difference between inputs and outputs
             fee += in_value - out_value;
@@ -142,22 +346,113 @@ impl Consensus {
         Ok(())
     }
 
+    /// Verifies the scripts of each transaction, returning the error of the first invalid one.
+    fn verify_script_checks(
+        script_checks: &[ScriptCheck],
+        flags: c_uint,
+        _script_workers: usize,
+    ) -> Result<(), TransactionError> {
+        #[cfg(feature = "parallel-validation")]
+        if _script_workers > 1 && script_checks.len() > 1 {
+            return Self::verify_script_checks_parallel(script_checks, flags, _script_workers);
+        }
+
+        script_checks
+            .iter()
+            .try_for_each(|check| check.verify(flags))
+    }
+
+    /// Verifies the scripts of each transaction using `script_workers` threads.
+    ///
+    /// Workers take the next unverified transaction until they are all verified, or until we
+    /// find an invalid one. In that case, we still verify the transactions before it, so we
+    /// return the same error as [`Consensus::verify_script_checks`] would.
+    #[cfg(feature = "parallel-validation")]
+    fn verify_script_checks_parallel(
+        script_checks: &[ScriptCheck],
+        flags: c_uint,
+        script_workers: usize,
+    ) -> Result<(), TransactionError> {
+        use std::panic;
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+        use std::thread;
+
+        let next = AtomicUsize::new(0);
+        // The index of the first invalid transaction we've found so far
+        let first_invalid = AtomicUsize::new(usize::MAX);
+
+        let worker = || {
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= script_checks.len() || index > first_invalid.load(Ordering::Relaxed) {
+                    return None;
+                }
+
+                // Each worker takes increasing indexes, so this is its first error
+                if let Err(e) = script_checks[index].verify(flags) {
+                    first_invalid.fetch_min(index, Ordering::Relaxed);
+                    return Some((index, e));
+                }
+            }
+        };
+
+        let error = thread::scope(|scope| {
+            let workers: Vec<_> = (0..script_workers.min(script_checks.len()))
+                .map(|_| scope.spawn(worker))
+                .collect();
+
+            workers
+                .into_iter()
+                .filter_map(|worker| {
+                    // A panic must not be mistaken for a valid block
+                    worker.join().unwrap_or_else(|e| panic::resume_unwind(e))
+                })
+                .min_by_key(|(index, _)| *index)
+        });
+
+        match error {
+            Some((_, e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+
     /// Verifies a single, non-coinbase transaction. To verify (the structure of) a coinbase
     /// transaction, use [`Consensus::verify_coinbase`].
     ///
     /// This function checks that the transaction:
     ///   - Has at least one input and one output
+    ///   - Doesn't spend the same PrevOut twice
     ///   - Doesn't have null PrevOuts (reserved only for coinbase transactions)
     ///   - Doesn't spend more coins than it claims in the inputs
     ///   - Doesn't "move" more coins than allowed (at most 21 million)
     ///   - Spends mature coins, in case any input refers to a coinbase transaction
     ///   - Has valid scripts (if we don't assume them), and within the allowed size
+    ///   - Has witnesses within the allowed sizes, see [`Consensus::validate_witness`]. This
+    ///     is only checked if `flags` contains [VERIFY_WITNESS](verify_flags::VERIFY_WITNESS)
     pub fn verify_transaction(
         transaction: &Transaction,
         utxos: &mut HashMap<OutPoint, UtxoData>,
         height: u32,
-        _verify_script: bool,
-        _flags: c_uint,
+        verify_script: bool,
+        flags: c_uint,
+    ) -> Result<(u64, u64), BlockchainError> {
+        let values = Self::check_transaction(transaction, utxos, height, flags)?;
+
+        if let Some(check) = Self::take_script_check(transaction, utxos, verify_script)? {
+            check.verify(flags)?;
+        }
+
+        Ok(values)
+    }
+
+    /// Runs every check of [`Consensus::verify_transaction`], except for the scripts. Returns
+    /// the input and output values.
+    fn check_transaction(
+        transaction: &Transaction,
+        utxos: &HashMap<OutPoint, UtxoData>,
+        height: u32,
+        flags: c_uint,
     ) -> Result<(u64, u64), BlockchainError> {
         let txid = || transaction.compute_txid();
 
@@ -168,6 +463,16 @@ impl Consensus {
             return Err(tx_err!(txid, EmptyOutputs))?;
         }
 
+        // The same PrevOut can't be spent twice, not even in the same transaction
+        let mut prevouts = HashSet::with_capacity(transaction.input.len());
+        if !transaction
+            .input
+            .iter()
+            .all(|input| prevouts.insert(input.previous_output))
+        {
+            return Err(tx_err!(txid, DuplicateInput))?;
+        }
+
         let out_value: u64 = transaction
             .output
             .iter()
@@ -188,10 +493,12 @@ impl Consensus {
                 return Err(tx_err!(txid, CoinbaseNotMatured))?;
             }
 
-            // Check script sizes (spent txo pubkey, and current tx scriptsig and TODO witness)
+            // Check script sizes (spent txo pubkey, current tx scriptsig and witness)
             Self::validate_script_size(&txout.script_pubkey, txid)?;
             Self::validate_script_size(&input.script_sig, txid)?;
-            // TODO check also witness script size
+            if flags & verify_flags::VERIFY_WITNESS != 0 {
+                Self::validate_witness(input, &txout.script_pubkey, flags, txid)?;
+            }
 
             in_value += txout.value.to_sat();
         }
@@ -205,20 +512,29 @@ impl Consensus {
             return Err(BlockValidationErrors::TooManyCoins)?;
         }
 
-        // Verify the tx script
-        #[cfg(feature = "bitcoinconsensus")]
-        if _verify_script {
-            transaction
-                .verify_with_flags(
-                    |outpoint| utxos.remove(outpoint).map(|utxo| utxo.txout),
-                    _flags,
-                )
-                .map_err(|e| tx_err!(txid, ScriptValidationError, format!("{e:?}")))?;
-        };
-
         Ok((in_value, out_value))
     }
 
+    /// Removes the UTXOs spent by `transaction`, returning what we need to verify its scripts.
+    ///
+    /// Returns `None` (keeping the UTXOs) if `verify_script` is false, or if we were built
+    /// without a script verifier.
+    fn take_script_check<'a>(
+        transaction: &'a Transaction,
+        utxos: &mut HashMap<OutPoint, UtxoData>,
+        verify_script: bool,
+    ) -> Result<Option<ScriptCheck<'a>>, TransactionError> {
+        let has_verifier = cfg!(any(
+            feature = "bitcoinconsensus",
+            feature = "script-interpreter"
+        ));
+        if !verify_script || !has_verifier {
+            return Ok(None);
+        }
+
+        ScriptCheck::new(transaction, utxos).map(Some)
+    }
+
     /// Returns the TxOut being spent by the given input.
     ///
     /// Fails if the UTXO is not present in the given hashmap.
@@ -236,13 +552,105 @@ impl Consensus {
         }
     }
 
-    #[allow(unused)]
-    fn validate_locktime(
-        input: &TxIn,
+    /// Returns the median time past (MTP) given the timestamps of a block and its ancestors, up
+    /// to [MEDIAN_TIME_SPAN] of them. Near the genesis block, fewer timestamps can be used.
+    ///
+    /// See <https://github.com/bitcoin/bitcoin/blob/v28.0/src/chain.h#L278-L290>
+    pub fn get_median_time_past(mut timestamps: Vec<u32>) -> u32 {
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied().unwrap_or(0)
+    }
+
+    /// Returns the time that absolute lock times are compared against. Once BIP 113 is active,
+    /// together with the rest of the CSV deployment, this is the MTP of the previous block;
+    /// before that, the block's own timestamp is used.
+    pub fn get_lock_time_cutoff(block_time: u32, median_time_past: u32, flags: c_uint) -> u32 {
+        if flags & verify_flags::VERIFY_CHECKSEQUENCEVERIFY != 0 {
+            return median_time_past;
+        }
+
+        block_time
+    }
+
+    /// Checks that a non-coinbase transaction can be included in a block at `height`:
+    ///
+    /// - Its absolute lock time must be satisfied, unless all inputs have final sequences. Time
+    ///   locks are compared against `lock_time_cutoff` (BIP 113)
+    /// - If CSV is active in `flags` and the transaction version is at least 2, the relative lock
+    ///   time of each input must be satisfied (BIP 68). Relative lock times are measured from the
+    ///   spent UTXO's `creation_height` or `creation_time`, and the latter is compared against
+    ///   `lock_time_cutoff`, which is then the MTP of the previous block
+    ///
+    /// This doesn't consume the UTXOs, and fails if any of them is not present in `utxos`.
+    pub fn validate_locktime(
         transaction: &Transaction,
+        utxos: &HashMap<OutPoint, UtxoData>,
         height: u32,
-    ) -> Result<(), BlockValidationErrors> {
-        unimplemented!("validate_locktime")
+        lock_time_cutoff: u32,
+        flags: c_uint,
+    ) -> Result<(), TransactionError> {
+        let txid = || transaction.compute_txid();
+
+        if !Self::is_final_tx(transaction, height, lock_time_cutoff) {
+            return Err(tx_err!(txid, NonFinalTransaction));
+        }
+
+        // BIP 68 only applies to version 2 transactions, once CSV is active
+        let enforce_bip68 = flags & verify_flags::VERIFY_CHECKSEQUENCEVERIFY != 0;
+        if !enforce_bip68 || (transaction.version.0 as u32) < 2 {
+            return Ok(());
+        }
+
+        // The last height and time at which the transaction is still locked
+        let mut min_height: i64 = -1;
+        let mut min_time: i64 = -1;
+
+        for input in transaction.input.iter() {
+            let sequence = input.sequence.0;
+            if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+                continue;
+            }
+
+            let utxo = Self::get_utxo(input, utxos, txid)?;
+            let lock_value = (sequence & SEQUENCE_LOCKTIME_MASK) as i64;
+
+            if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+                let lock_seconds = lock_value << SEQUENCE_LOCKTIME_GRANULARITY;
+                min_time = min_time.max(utxo.creation_time as i64 + lock_seconds - 1);
+            } else {
+                min_height = min_height.max(utxo.creation_height as i64 + lock_value - 1);
+            }
+        }
+
+        if min_height >= height as i64 || min_time >= lock_time_cutoff as i64 {
+            return Err(tx_err!(txid, SequenceLockNotSatisfied));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the absolute lock time of a transaction is satisfied at this height and time.
+    ///
+    /// See <https://github.com/bitcoin/bitcoin/blob/v28.0/src/consensus/tx_verify.cpp#L17-L36>
+    fn is_final_tx(transaction: &Transaction, height: u32, lock_time_cutoff: u32) -> bool {
+        let lock_time = transaction.lock_time.to_consensus_u32();
+        if lock_time == 0 {
+            return true;
+        }
+
+        let threshold = match lock_time < LOCKTIME_THRESHOLD {
+            true => height,
+            false => lock_time_cutoff,
+        };
+        if lock_time < threshold {
+            return true;
+        }
+
+        // The lock time is ignored if all inputs have the maximum sequence number
+        transaction
+            .input
+            .iter()
+            .all(|input| input.sequence == Sequence::MAX)
     }
 
     /// Validates the script size and the number of sigops in a scriptpubkey or scriptsig.
@@ -251,8 +659,7 @@ impl Consensus {
         txid: F,
     ) -> Result<(), TransactionError> {
         // The maximum script size for non-taproot spends is 10,000 bytes
-        // https://github.com/bitcoin/bitcoin/blob/v28.0/src/script/script.h#L39
-        if script.len() > 10_000 {
+        if script.len() > MAX_SCRIPT_SIZE {
             return Err(tx_err!(txid, ScriptError));
         }
         if script.count_sigops() > 80_000 {
@@ -261,6 +668,206 @@ impl Consensus {
         Ok(())
     }
 
+    /// Returns the sigop cost of a transaction, given the UTXOs it spends.
+    ///
+    /// This accounts for legacy sigops in the scriptSigs and scriptPubKeys, sigops in P2SH
+    /// redeem scripts if `flags` contains [VERIFY_P2SH](verify_flags::VERIFY_P2SH) and segwit
+    /// v0 sigops (including P2SH-wrapped ones) if it contains
+    /// [VERIFY_WITNESS](verify_flags::VERIFY_WITNESS). Taproot spends don't count towards the
+    /// block limit, since they have a per-input budget.
+    ///
+    /// See <https://github.com/bitcoin/bitcoin/blob/v28.0/src/consensus/tx_verify.cpp#L143-L161>
+    pub fn get_sigop_cost(
+        transaction: &Transaction,
+        utxos: &HashMap<OutPoint, UtxoData>,
+        flags: c_uint,
+    ) -> usize {
+        // Without the spent outputs, only legacy sigops are counted (already scaled by 4)
+        let mut cost = transaction.total_sigop_cost(|_| None);
+        if transaction.is_coinbase() {
+            return cost;
+        }
+
+        for input in transaction.input.iter() {
+            let Some(utxo) = utxos.get(&input.previous_output) else {
+                continue;
+            };
+            let script_pubkey = &utxo.txout.script_pubkey;
+
+            if flags & verify_flags::VERIFY_P2SH != 0 && script_pubkey.is_p2sh() {
+                cost += Self::count_p2sh_sigops(&input.script_sig) * 4;
+            }
+
+            if flags & verify_flags::VERIFY_WITNESS != 0 {
+                cost += Self::count_witness_sigops(input, script_pubkey);
+            }
+        }
+
+        cost
+    }
+
+    /// Counts the sigops in the redeem script of a P2SH spend, which is the last push of the
+    /// scriptSig. Non push-only scriptSigs don't count, as they will fail anyway.
+    fn count_p2sh_sigops(script_sig: &Script) -> usize {
+        if !script_sig.is_push_only() {
+            return 0;
+        }
+
+        script_sig
+            .redeem_script()
+            .map(|redeem_script| redeem_script.count_sigops())
+            .unwrap_or(0)
+    }
+
+    /// Counts the sigops of a segwit v0 spend, native or P2SH-wrapped. Other witness versions
+    /// don't have sigops that count towards the block limit.
+    fn count_witness_sigops(input: &TxIn, script_pubkey: &Script) -> usize {
+        let program = if script_pubkey.is_witness_program() {
+            script_pubkey
+        } else if script_pubkey.is_p2sh() && input.script_sig.is_push_only() {
+            match input.script_sig.redeem_script() {
+                Some(redeem_script) if redeem_script.is_witness_program() => redeem_script,
+                _ => return 0,
+            }
+        } else {
+            return 0;
+        };
+
+        if program.is_p2wpkh() {
+            return 1;
+        }
+
+        if program.is_p2wsh() {
+            return input
+                .witness
+                .last()
+                .map(|witness_script| Script::from_bytes(witness_script).count_sigops())
+                .unwrap_or(0);
+        }
+
+        0
+    }
+
+    /// Validates the witness of an input, given the scriptPubKey it spends. Those are the checks
+    /// done before executing a witness program, so they apply even if scripts are assumed valid.
+    ///
+    /// For segwit v0 spends (native or P2SH-wrapped):
+    ///   - Each stack item must be at most [MAX_SCRIPT_ELEMENT_SIZE] bytes
+    ///   - The witness script (P2WSH only) must be at most [MAX_SCRIPT_SIZE] bytes
+    ///   - The witness script (P2WSH only) can't start with more than [MAX_STACK_SIZE] items
+    ///
+    /// For taproot script path spends with leaf version 0xc0 and no OP_SUCCESSx in the tapscript,
+    /// the tapscript can't start with more than [MAX_STACK_SIZE] items, each at most
+    /// [MAX_SCRIPT_ELEMENT_SIZE] bytes. This only applies to native (not P2SH-wrapped) outputs,
+    /// and only if `flags` contains [VERIFY_TAPROOT](verify_flags::VERIFY_TAPROOT), just like
+    /// Bitcoin Core. Unknown leaf versions, tapscripts with an OP_SUCCESSx and other witness
+    /// versions are left unchecked, as they are reserved for upgrades.
+    pub fn validate_witness<F: Fn() -> Txid>(
+        input: &TxIn,
+        script_pubkey: &ScriptBuf,
+        flags: c_uint,
+        txid: F,
+    ) -> Result<(), TransactionError> {
+        let witness = &input.witness;
+        if witness.is_empty() {
+            return Ok(());
+        }
+
+        let program = if script_pubkey.is_witness_program() {
+            script_pubkey.as_script()
+        } else if script_pubkey.is_p2sh() {
+            // P2SH-wrapped segwit, the witness program is the redeem script
+            match input.script_sig.redeem_script() {
+                Some(redeem_script) if redeem_script.is_witness_program() => redeem_script,
+                _ => return Ok(()),
+            }
+        } else {
+            return Ok(());
+        };
+
+        if program.is_p2wpkh() {
+            return Self::validate_witness_items(witness.iter(), txid);
+        }
+
+        if program.is_p2wsh() {
+            let witness_script = witness.last().expect("witness is not empty");
+            if witness_script.len() > MAX_SCRIPT_SIZE {
+                return Err(tx_err!(txid, WitnessScriptTooBig));
+            }
+
+            if witness.len() - 1 > MAX_STACK_SIZE {
+                return Err(tx_err!(txid, TooManyWitnessItems));
+            }
+
+            return Self::validate_witness_items(witness.iter().take(witness.len() - 1), txid);
+        }
+
+        // P2SH-wrapped witness v1 outputs aren't taproot, so spending them is unencumbered
+        if script_pubkey.is_p2tr() && flags & verify_flags::VERIFY_TAPROOT != 0 {
+            // Items that are not part of the initial stack: annex, tapscript and control block
+            let has_annex = witness.len() > 1
+                && witness
+                    .last()
+                    .is_some_and(|last| last.first() == Some(&TAPROOT_ANNEX_PREFIX));
+            let stack_len = witness.len() - has_annex as usize;
+
+            // Key path spends only have a signature, and don't execute any script
+            if stack_len < 2 {
+                return Ok(());
+            }
+
+            let tapscript = Script::from_bytes(&witness[stack_len - 2]);
+            let control_block = &witness[stack_len - 1];
+            let is_tapscript = control_block
+                .first()
+                .is_some_and(|first| first & TAPROOT_LEAF_MASK == TAPROOT_LEAF_TAPSCRIPT);
+
+            if !is_tapscript || Self::may_succeed(tapscript) {
+                return Ok(());
+            }
+
+            if stack_len - 2 > MAX_STACK_SIZE {
+                return Err(tx_err!(txid, TooManyWitnessItems));
+            }
+
+            return Self::validate_witness_items(witness.iter().take(stack_len - 2), txid);
+        }
+
+        Ok(())
+    }
+
+    /// Whether a tapscript may succeed without being executed, because it has an OP_SUCCESSx
+    /// (BIP 342). If it can't be parsed, we also return true, leaving that error for the script
+    /// verifier.
+    fn may_succeed(tapscript: &Script) -> bool {
+        for instruction in tapscript.instructions() {
+            match instruction {
+                Ok(Instruction::Op(opcode))
+                    if opcode.classify(ClassifyContext::TapScript) == Class::SuccessOp =>
+                {
+                    return true
+                }
+                Ok(_) => {}
+                Err(_) => return true,
+            }
+        }
+
+        false
+    }
+
+    /// Checks that every item in a segwit v0 or tapscript witness stack is within
+    /// [MAX_SCRIPT_ELEMENT_SIZE].
+    fn validate_witness_items<'a, F: Fn() -> Txid>(
+        mut items: impl Iterator<Item = &'a [u8]>,
+        txid: F,
+    ) -> Result<(), TransactionError> {
+        if items.any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+            return Err(tx_err!(txid, WitnessItemTooBig));
+        }
+
+        Ok(())
+    }
+
     /// Validates the coinbase transaction's input. The checks on the outputs require context about
     /// the block and are performed by [`Consensus::verify_block_transactions`].
     pub fn verify_coinbase(tx: &Transaction) -> Result<(), TransactionError> {
@@ -300,6 +907,43 @@ impl Consensus {
         Ok(())
     }
 
+    /// Returns the highest target allowed for `next_header`, at `next_height`, that builds on
+    /// `last_block`. `get_header` gives the header in our chain at some height, and is only
+    /// called to find the first block in the epoch when the difficulty retargets.
+    pub fn get_next_required_work<E>(
+        &self,
+        last_block: &BlockHeader,
+        next_height: u32,
+        next_header: &BlockHeader,
+        get_header: impl FnOnce(u32) -> Result<BlockHeader, E>,
+    ) -> Result<Target, E> {
+        let params = &self.parameters.params;
+
+        // Special testnet rule, if a block takes more than 20 minutes to mine, we can
+        // mine a block with diff 1
+        if params.allow_min_difficulty_blocks
+            && last_block.time + params.pow_target_spacing as u32 * 2 < next_header.time
+        {
+            return Ok(params.max_attainable_target);
+        }
+
+        // Regtest don't have retarget
+        if !params.no_pow_retargeting && next_height % 2016 == 0 {
+            // First block in this epoch
+            let first_block = get_header(next_height - 2016)?;
+            let target =
+                Self::calc_next_work_required(last_block, &first_block, self.parameters.clone());
+
+            if target < params.max_attainable_target {
+                return Ok(target);
+            }
+
+            return Ok(params.max_attainable_target);
+        }
+
+        Ok(last_block.target())
+    }
+
     /// Calculates the next target for the proof of work algorithm, given the
     /// first and last block headers inside a difficulty adjustment period.
     pub fn calc_next_work_required(
@@ -365,6 +1009,7 @@ mod tests {
     use bitcoin::hashes::Hash;
     use bitcoin::opcodes::all::OP_NOP;
     use bitcoin::opcodes::OP_TRUE;
+    use bitcoin::script::PushBytesBuf;
     use bitcoin::transaction::Version;
     use bitcoin::Amount;
     use bitcoin::OutPoint;
@@ -650,6 +1295,67 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "bitcoinconsensus")]
+    #[test]
+    fn test_parallel_script_validation() {
+        // A block with the coinbase and the given legacy cases, in order
+        let block = |cases: &[usize]| {
+            let mut txs = vec![coinbase(true)];
+            let mut utxos = HashMap::new();
+            for &i in cases {
+                let (tx, tx_utxos) = create_case(TX_VALIDATION_CASES_LEGACY[i]);
+                txs.push(tx);
+                utxos.extend(tx_utxos);
+            }
+            (txs, utxos)
+        };
+        let verify = |txs: &[Transaction], utxos: HashMap<_, _>, workers| {
+            // These cases don't pay fees, so the coinbase can only claim the subsidy
+            let subsidy = 5_000_350_000;
+            let flags = bitcoinconsensus::VERIFY_ALL_PRE_TAPROOT;
+            Consensus::verify_block_transactions(0, 0, utxos, txs, subsidy, true, flags, workers)
+        };
+
+        // Returns the position of the invalid transaction, and whether it has invalid scripts
+        let first_invalid = |txs: &[Transaction], result| match result {
+            Err(BlockchainError::TransactionError(e)) => {
+                let position = txs.iter().position(|tx| tx.compute_txid() == e.txid);
+                let is_script_error =
+                    matches!(e.error, BlockValidationErrors::ScriptValidationError(_));
+                (position, is_script_error)
+            }
+            other => panic!("Expected a transaction error, got {other:?}"),
+        };
+
+        // Only the valid cases
+        let (txs, utxos) = block(&[1, 4]);
+        for workers in [1, 2, 8] {
+            assert_ok!(verify(&txs, utxos.clone(), workers));
+        }
+
+        // Every worker count must report the first invalid transaction, like a serial validation
+        let (txs, utxos) = block(&[1, 4, 6, 0, 2, 3, 5]);
+        let expected = first_invalid(&txs, verify(&txs, utxos.clone(), 1));
+        assert_eq!(expected.0, Some(3));
+        for workers in [2, 3, 8] {
+            let result = verify(&txs, utxos.clone(), workers);
+            assert_eq!(first_invalid(&txs, result), expected, "{workers} workers");
+        }
+
+        // A script error comes before any later non-script error, even if scripts are only
+        // verified after the rest of the block
+        let (mut txs, utxos) = block(&[1, 0, 4]);
+        txs[3].input[0].previous_output.vout = 1;
+        for workers in [1, 2, 8] {
+            let result = verify(&txs, utxos.clone(), workers);
+            assert_eq!(
+                first_invalid(&txs, result),
+                (Some(2), true),
+                "{workers} workers"
+            );
+        }
+    }
+
     pub fn true_script() -> ScriptBuf {
         let mut script = ScriptBuf::default();
         script.push_opcode(OP_TRUE);
@@ -735,4 +1441,445 @@ mod tests {
             e => panic!("Expected a TransactionError, but got: {e:?}"),
         }
     }
+
+    /// Builds a transaction spending `prevout` with the given witness, and the UTXO set with a
+    /// single output locked by `script_pubkey`.
+    fn witness_spend(
+        script_pubkey: ScriptBuf,
+        script_sig: ScriptBuf,
+        witness: Vec<Vec<u8>>,
+    ) -> (Transaction, HashMap<OutPoint, UtxoData>) {
+        let prevout = OutPoint::new(Txid::all_zeros(), 0);
+        let mut input = txin!(prevout, script_sig);
+        input.witness = Witness::from_slice(&witness);
+
+        let tx = Transaction {
+            version: Version(2),
+            lock_time: LockTime::ZERO,
+            input: vec![input],
+            output: vec![txout!(0, true_script())],
+        };
+
+        let mut utxos = HashMap::new();
+        utxos.insert(
+            prevout,
+            UtxoData {
+                txout: txout!(1_000, script_pubkey),
+                is_coinbase: false,
+                creation_height: 0,
+                creation_time: 0,
+            },
+        );
+
+        (tx, utxos)
+    }
+
+    /// Asserts that verifying `tx` fails with the given error, or succeeds if `None`.
+    fn assert_tx_result(
+        tx: &Transaction,
+        mut utxos: HashMap<OutPoint, UtxoData>,
+        expected: Option<BlockValidationErrors>,
+    ) {
+        let flags =
+            verify_flags::VERIFY_P2SH | verify_flags::VERIFY_WITNESS | verify_flags::VERIFY_TAPROOT;
+        let result = Consensus::verify_transaction(tx, &mut utxos, 0, false, flags);
+
+        match (result, expected) {
+            (Ok(_), None) => {}
+            (Err(BlockchainError::TransactionError(inner)), Some(expected)) => {
+                assert_eq!(inner.error, expected);
+            }
+            (result, expected) => panic!("Expected {expected:?}, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_inputs() {
+        let prevout = OutPoint::new(Txid::all_zeros(), 0);
+        let mut utxos = HashMap::new();
+        utxos.insert(
+            prevout,
+            UtxoData {
+                txout: txout!(1_000, true_script()),
+                is_coinbase: false,
+                creation_height: 0,
+                creation_time: 0,
+            },
+        );
+
+        let mut tx = Transaction {
+            version: Version(1),
+            lock_time: LockTime::ZERO,
+            input: vec![txin!(prevout, ScriptBuf::new())],
+            output: vec![txout!(1_000, true_script())],
+        };
+        assert_tx_result(&tx, utxos.clone(), None);
+
+        // Spending the same output twice would double its value
+        tx.input.push(txin!(prevout, ScriptBuf::new()));
+        tx.output[0].value = Amount::from_sat(2_000);
+        assert_tx_result(&tx, utxos, Some(BlockValidationErrors::DuplicateInput));
+    }
+
+    #[test]
+    fn test_witness_limits() {
+        let witness_script = true_script();
+        let p2wsh = ScriptBuf::new_p2wsh(&witness_script.wscript_hash());
+        let p2wpkh = ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+
+        // P2SH-wrapped P2WSH, the scriptSig only pushes the witness program
+        let p2sh_p2wsh = ScriptBuf::new_p2sh(&p2wsh.script_hash());
+        let mut wrapped_sig = ScriptBuf::new();
+        wrapped_sig.push_slice(<&bitcoin::script::PushBytes>::try_from(p2wsh.as_bytes()).unwrap());
+
+        let max_item = vec![1; MAX_SCRIPT_ELEMENT_SIZE];
+        let big_item = vec![1; MAX_SCRIPT_ELEMENT_SIZE + 1];
+        let big_script = vec![0x61; MAX_SCRIPT_SIZE + 1]; // OP_NOP
+
+        let p2tr = ScriptBuf::from_hex(&format!("5120{}", "11".repeat(32))).unwrap();
+        let op_success = ScriptBuf::from_bytes(vec![0x51, 0x50]); // OP_TRUE OP_SUCCESS80
+
+        let cases = [
+            // Items at the size limit are fine
+            (
+                p2wsh.clone(),
+                ScriptBuf::new(),
+                vec![max_item.clone(), witness_script.to_bytes()],
+                None,
+            ),
+            (
+                p2wsh.clone(),
+                ScriptBuf::new(),
+                vec![big_item.clone(), witness_script.to_bytes()],
+                Some(BlockValidationErrors::WitnessItemTooBig),
+            ),
+            (
+                p2wpkh.clone(),
+                ScriptBuf::new(),
+                vec![big_item.clone(), vec![2; 33]],
+                Some(BlockValidationErrors::WitnessItemTooBig),
+            ),
+            (
+                p2sh_p2wsh.clone(),
+                wrapped_sig.clone(),
+                vec![big_item.clone(), witness_script.to_bytes()],
+                Some(BlockValidationErrors::WitnessItemTooBig),
+            ),
+            (
+                p2wsh.clone(),
+                ScriptBuf::new(),
+                vec![big_script],
+                Some(BlockValidationErrors::WitnessScriptTooBig),
+            ),
+            (
+                p2wsh.clone(),
+                ScriptBuf::new(),
+                [
+                    vec![vec![]; MAX_STACK_SIZE],
+                    vec![witness_script.to_bytes()],
+                ]
+                .concat(),
+                None,
+            ),
+            (
+                p2wsh.clone(),
+                ScriptBuf::new(),
+                [
+                    vec![vec![]; MAX_STACK_SIZE + 1],
+                    vec![witness_script.to_bytes()],
+                ]
+                .concat(),
+                Some(BlockValidationErrors::TooManyWitnessItems),
+            ),
+            // The 520 bytes limit doesn't apply to legacy outputs with a witness
+            (
+                true_script(),
+                ScriptBuf::new(),
+                vec![big_item.clone()],
+                None,
+            ),
+            // For taproot, it only applies to tapscripts without an OP_SUCCESSx
+            (
+                p2tr.clone(),
+                ScriptBuf::new(),
+                vec![max_item.clone(), witness_script.to_bytes(), vec![0xc0; 33]],
+                None,
+            ),
+            (
+                p2tr.clone(),
+                ScriptBuf::new(),
+                vec![big_item.clone(), witness_script.to_bytes(), vec![0xc0; 33]],
+                Some(BlockValidationErrors::WitnessItemTooBig),
+            ),
+            (
+                p2tr.clone(),
+                ScriptBuf::new(),
+                vec![big_item.clone(), witness_script.to_bytes(), vec![0xc2; 33]],
+                None,
+            ),
+            (
+                p2tr.clone(),
+                ScriptBuf::new(),
+                vec![big_item.clone(), op_success.to_bytes(), vec![0xc0; 33]],
+                None,
+            ),
+            // Key path spends don't execute any script
+            (p2tr, ScriptBuf::new(), vec![big_item.clone()], None),
+        ];
+
+        for (script_pubkey, script_sig, witness, expected) in cases {
+            let (tx, utxos) = witness_spend(script_pubkey, script_sig, witness);
+            assert_tx_result(&tx, utxos, expected);
+        }
+    }
+
+    #[test]
+    fn test_taproot_stack_limit() {
+        let p2tr = ScriptBuf::from_hex(&format!("5120{}", "11".repeat(32))).unwrap();
+        let tapscript = true_script().to_bytes();
+        let control_block = vec![0xc0; 33];
+        let annex = vec![TAPROOT_ANNEX_PREFIX, 0];
+
+        let script_path = |items: usize, annex: Option<Vec<u8>>| {
+            let mut witness = vec![vec![]; items];
+            witness.push(tapscript.clone());
+            witness.push(control_block.clone());
+            witness.extend(annex);
+            witness
+        };
+
+        // Unknown leaf versions and OP_SUCCESSx make the spend succeed before the limit applies
+        let mut unknown_leaf = script_path(MAX_STACK_SIZE + 1, None);
+        unknown_leaf[MAX_STACK_SIZE + 2] = vec![0xc2; 33];
+
+        let mut op_success = script_path(MAX_STACK_SIZE + 1, None);
+        op_success[MAX_STACK_SIZE + 1] = vec![0x51, 0x50]; // OP_TRUE OP_SUCCESS80
+
+        let cases = [
+            (unknown_leaf, None),
+            (op_success, None),
+            (script_path(MAX_STACK_SIZE, None), None),
+            (script_path(MAX_STACK_SIZE, Some(annex.clone())), None),
+            (
+                script_path(MAX_STACK_SIZE + 1, None),
+                Some(BlockValidationErrors::TooManyWitnessItems),
+            ),
+            (
+                script_path(MAX_STACK_SIZE + 1, Some(annex)),
+                Some(BlockValidationErrors::TooManyWitnessItems),
+            ),
+            // Key path spend
+            (vec![vec![1; 64]], None),
+        ];
+
+        for (witness, expected) in cases {
+            let (tx, utxos) = witness_spend(p2tr.clone(), ScriptBuf::new(), witness);
+            assert_tx_result(&tx, utxos, expected);
+        }
+
+        // A P2SH-wrapped witness v1 program isn't taproot, so its spends aren't limited
+        let p2sh_p2tr = ScriptBuf::new_p2sh(&p2tr.script_hash());
+        let mut wrapped_sig = ScriptBuf::new();
+        wrapped_sig.push_slice(<&bitcoin::script::PushBytes>::try_from(p2tr.as_bytes()).unwrap());
+
+        let witness = script_path(MAX_STACK_SIZE + 1, None);
+        let (tx, utxos) = witness_spend(p2sh_p2tr, wrapped_sig, witness.clone());
+        assert_tx_result(&tx, utxos, None);
+
+        // Neither are native spends before taproot activates
+        let (tx, _) = witness_spend(p2tr.clone(), ScriptBuf::new(), witness);
+        let segwit_flags = verify_flags::VERIFY_P2SH | verify_flags::VERIFY_WITNESS;
+        assert_ok!(Consensus::validate_witness(
+            &tx.input[0],
+            &p2tr,
+            segwit_flags,
+            || tx.compute_txid()
+        ));
+    }
+
+    #[test]
+    fn test_block_sigops_limit() {
+        // Each OP_CHECKMULTISIG counts as 20 legacy sigops, that is a cost of 80
+        fn coinbase_with_sigops(checkmultisigs: usize) -> Transaction {
+            let mut script = ScriptBuf::new();
+            for _ in 0..checkmultisigs {
+                script.push_opcode(bitcoin::opcodes::all::OP_CHECKMULTISIG);
+            }
+
+            let mut coinbase = coinbase(true);
+            coinbase.output = vec![txout!(0, script)];
+            coinbase
+        }
+
+        let subsidy = 50 * COIN_VALUE;
+        let verify = |txs: &[Transaction]| {
+            Consensus::verify_block_transactions(0, 0, HashMap::new(), txs, subsidy, false, 0, 1)
+        };
+
+        // 1,000 * 80 = 80,000 is exactly the limit
+        let at_limit = coinbase_with_sigops(1_000);
+        assert_eq!(
+            Consensus::get_sigop_cost(&at_limit, &HashMap::new(), 0),
+            MAX_BLOCK_SIGOPS_COST
+        );
+        assert_ok!(verify(&[at_limit]));
+
+        match verify(&[coinbase_with_sigops(1_001)]) {
+            Err(BlockchainError::BlockValidation(BlockValidationErrors::TooManySigops)) => {}
+            other => panic!("Expected TooManySigops, got {other:?}"),
+        }
+
+        // Witness sigops count as 1, so a P2WSH spend with 20 CHECKSIGs costs 20
+        let mut witness_script = ScriptBuf::new();
+        for _ in 0..20 {
+            witness_script.push_opcode(bitcoin::opcodes::all::OP_CHECKSIG);
+        }
+        let p2wsh = ScriptBuf::new_p2wsh(&witness_script.wscript_hash());
+        let (tx, utxos) = witness_spend(p2wsh, ScriptBuf::new(), vec![witness_script.to_bytes()]);
+        let segwit_flags = verify_flags::VERIFY_P2SH | verify_flags::VERIFY_WITNESS;
+        assert_eq!(Consensus::get_sigop_cost(&tx, &utxos, segwit_flags), 20);
+
+        // Before segwit, witness sigops don't count
+        assert_eq!(Consensus::get_sigop_cost(&tx, &utxos, 0), 0);
+
+        // A P2SH spend with 20 CHECKSIGs in its redeem script costs 80, but only with BIP16
+        let redeem_script = witness_script;
+        let p2sh = ScriptBuf::new_p2sh(&redeem_script.script_hash());
+        let script_sig = ScriptBuf::builder()
+            .push_slice(PushBytesBuf::try_from(redeem_script.to_bytes()).unwrap())
+            .into_script();
+        let (tx, utxos) = witness_spend(p2sh, script_sig, Vec::new());
+        assert_eq!(Consensus::get_sigop_cost(&tx, &utxos, segwit_flags), 80);
+        assert_eq!(Consensus::get_sigop_cost(&tx, &utxos, 0), 0);
+    }
+
+    /// Builds a transaction with one input, spending a UTXO created at `creation_height` with
+    /// `creation_time` as its creation MTP.
+    fn locktime_spend(
+        version: i32,
+        lock_time: u32,
+        sequence: u32,
+        creation_height: u32,
+        creation_time: u32,
+    ) -> (Transaction, HashMap<OutPoint, UtxoData>) {
+        let prevout = OutPoint::new(Txid::all_zeros(), 0);
+        let tx = Transaction {
+            version: Version(version),
+            lock_time: LockTime::from_consensus(lock_time),
+            input: vec![txin!(prevout, ScriptBuf::new(), Sequence(sequence))],
+            output: vec![txout!(0, true_script())],
+        };
+
+        let mut utxos = HashMap::new();
+        utxos.insert(
+            prevout,
+            UtxoData {
+                txout: txout!(1_000, true_script()),
+                is_coinbase: false,
+                creation_height,
+                creation_time,
+            },
+        );
+
+        (tx, utxos)
+    }
+
+    #[test]
+    fn test_absolute_locktime() {
+        let validate = |(tx, utxos): (Transaction, _), height, cutoff| {
+            Consensus::validate_locktime(&tx, &utxos, height, cutoff, 0).map_err(|e| e.error)
+        };
+        let non_final = Err(BlockValidationErrors::NonFinalTransaction);
+
+        // Height locks are satisfied in the blocks after the lock height
+        assert_eq!(validate(locktime_spend(1, 100, 0, 0, 0), 100, 0), non_final);
+        assert_ok!(validate(locktime_spend(1, 100, 0, 0, 0), 101, 0));
+
+        // Time locks are satisfied once the cutoff is past the lock time
+        let lock_time = LOCKTIME_THRESHOLD + 100;
+        assert_eq!(
+            validate(locktime_spend(1, lock_time, 0, 0, 0), 0, lock_time),
+            non_final
+        );
+        assert_ok!(validate(
+            locktime_spend(1, lock_time, 0, 0, 0),
+            0,
+            lock_time + 1
+        ));
+
+        // The lock time is ignored if all sequences are final
+        assert_ok!(validate(locktime_spend(1, 100, u32::MAX, 0, 0), 100, 0));
+
+        // The coinbase lock time is checked as well
+        let mut locked_coinbase = coinbase(true);
+        locked_coinbase.input[0].sequence = Sequence::ZERO;
+        locked_coinbase.output[0].value = Amount::ZERO;
+        let verify = |coinbase: &Transaction, height| {
+            let txs = [coinbase.clone()];
+            Consensus::verify_block_transactions(height, 0, HashMap::new(), &txs, 0, false, 0, 1)
+        };
+
+        match verify(&locked_coinbase, 150_007) {
+            Err(BlockchainError::TransactionError(e)) => {
+                assert_eq!(e.error, BlockValidationErrors::NonFinalTransaction)
+            }
+            other => panic!("Expected NonFinalTransaction, got {other:?}"),
+        }
+        assert_ok!(verify(&locked_coinbase, 150_008));
+    }
+
+    #[test]
+    fn test_relative_locktime() {
+        let csv = verify_flags::VERIFY_CHECKSEQUENCEVERIFY;
+        let validate = |(tx, utxos): (Transaction, _), height, cutoff, flags| {
+            Consensus::validate_locktime(&tx, &utxos, height, cutoff, flags).map_err(|e| e.error)
+        };
+        let locked = Err(BlockValidationErrors::SequenceLockNotSatisfied);
+
+        // A UTXO created at height 100 with a 10 blocks lock can be spent at height 110
+        assert_eq!(
+            validate(locktime_spend(2, 0, 10, 100, 0), 109, 0, csv),
+            locked
+        );
+        assert_ok!(validate(locktime_spend(2, 0, 10, 100, 0), 110, 0, csv));
+
+        // Sequence locks are not enforced before CSV, for version 1 txs or if disabled
+        assert_ok!(validate(locktime_spend(2, 0, 10, 100, 0), 109, 0, 0));
+        assert_ok!(validate(locktime_spend(1, 0, 10, 100, 0), 109, 0, csv));
+        let disabled = SEQUENCE_LOCKTIME_DISABLE_FLAG | 10;
+        assert_ok!(validate(
+            locktime_spend(2, 0, disabled, 100, 0),
+            109,
+            0,
+            csv
+        ));
+
+        // A 2 * 512 seconds lock on a UTXO created with a MTP of 1,000 requires the previous
+        // block MTP to be at least 2,024
+        let sequence = SEQUENCE_LOCKTIME_TYPE_FLAG | 2;
+        assert_eq!(
+            validate(locktime_spend(2, 0, sequence, 100, 1_000), 200, 2_023, csv),
+            locked
+        );
+        assert_ok!(validate(
+            locktime_spend(2, 0, sequence, 100, 1_000),
+            200,
+            2_024,
+            csv
+        ));
+    }
+
+    #[test]
+    fn test_median_time_past() {
+        assert_eq!(Consensus::get_median_time_past(vec![5]), 5);
+        assert_eq!(Consensus::get_median_time_past(vec![3, 1, 2]), 2);
+
+        let timestamps = vec![10, 2, 9, 1, 7, 3, 11, 4, 8, 5, 6];
+        assert_eq!(timestamps.len(), MEDIAN_TIME_SPAN);
+        assert_eq!(Consensus::get_median_time_past(timestamps), 6);
+
+        let csv = verify_flags::VERIFY_CHECKSEQUENCEVERIFY;
+        assert_eq!(Consensus::get_lock_time_cutoff(200, 100, csv), 100);
+        assert_eq!(Consensus::get_lock_time_cutoff(200, 100, 0), 200);
+    }
 }
@@ -18,6 +18,7 @@ use floresta_common::impl_error_from;
 use floresta_common::prelude::*;
 
 use crate::proof_util::UtreexoLeafError;
+use crate::pruned_utreexo::chain_snapshot::ChainSnapshotError;
 use crate::pruned_utreexo::chain_state_builder::BlockchainBuilderError;
 
 pub trait DatabaseError: Debug + Send + Sync + 'static {}
@@ -41,6 +42,9 @@ pub enum BlockchainError {
     Io(ioError),
     UnsupportedNetwork(Network),
     BadValidationIndex,
+    MissingUndoData(u32),
+    ChainStoreNotEmpty,
+    InvalidSnapshot(ChainSnapshotError),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -79,6 +83,17 @@ pub enum BlockValidationErrors {
     CoinbaseNotMatured,
     UnspendableUTXO,
     BIP94TimeWarp,
+    TooManySigops,
+    DuplicateInput,
+    WitnessItemTooBig,
+    WitnessScriptTooBig,
+    TooManyWitnessItems,
+    NonFinalTransaction,
+    SequenceLockNotSatisfied,
+    MissingDeploymentSignal(u8),
+    CheckpointMismatch(u32),
+    ForkBeforeCheckpoint(u32),
+    LowChainWork,
 }
 
 // Helpful macro for generating a TransactionError
@@ -169,6 +184,51 @@ impl Display for BlockValidationErrors {
             BlockValidationErrors::BIP94TimeWarp => {
                 write!(f, "BIP94 time warp detected")
             }
+            BlockValidationErrors::TooManySigops => {
+                write!(f, "This block exceeds the sigop cost limit")
+            }
+            BlockValidationErrors::DuplicateInput => {
+                write!(f, "This transaction spends the same input twice")
+            }
+            BlockValidationErrors::WitnessItemTooBig => {
+                write!(f, "Witness stack item is bigger than 520 bytes")
+            }
+            BlockValidationErrors::WitnessScriptTooBig => {
+                write!(f, "Witness script is bigger than 10,000 bytes")
+            }
+            BlockValidationErrors::TooManyWitnessItems => {
+                write!(f, "Witness stack has more than 1,000 items")
+            }
+            BlockValidationErrors::NonFinalTransaction => {
+                write!(f, "This transaction's lock time is not satisfied yet")
+            }
+            BlockValidationErrors::SequenceLockNotSatisfied => {
+                write!(
+                    f,
+                    "This transaction's relative lock time is not satisfied yet"
+                )
+            }
+            BlockValidationErrors::MissingDeploymentSignal(bit) => {
+                write!(
+                    f,
+                    "This block doesn't signal for the deployment on bit {bit}"
+                )
+            }
+            BlockValidationErrors::CheckpointMismatch(height) => {
+                write!(
+                    f,
+                    "This block doesn't match our checkpoint at height {height}"
+                )
+            }
+            BlockValidationErrors::ForkBeforeCheckpoint(height) => {
+                write!(
+                    f,
+                    "This block forks before our checkpoint at height {height}"
+                )
+            }
+            BlockValidationErrors::LowChainWork => {
+                write!(f, "This header's chain doesn't have the minimum chain work")
+            }
         }
     }
 }
@@ -0,0 +1,814 @@
+//! A fee estimator that learns from the blocks we connect and the transactions we see in our
+//! mempool.
+//!
+//! We keep two independent sources of data:
+//!
+//! - **Mempool confirmation statistics**: every time a transaction enters our mempool, we record
+//!   its feerate and the height we've seen it at. Once that transaction gets confirmed, we know how
+//!   many blocks it took to confirm, and we account for it inside a feerate bucket. This is roughly
+//!   the same idea used by Bitcoin Core's `CBlockPolicyEstimator`, albeit much simpler. Statistics
+//!   are exponentially decayed, so older blocks have less weight than newer ones.
+//! - **Block feerate floors**: for each block we connect, we compute the lowest feerate needed to
+//!   get into that block, using the values of the spent UTXOs the chainstate gives us. If we
+//!   don't have enough mempool data for a given target (e.g. we've just started, or the target
+//!   is too far away), we use the history of those floors to find a feerate that would likely
+//!   have been mined within the target.
+//!
+//! All feerates here are in sat/vB.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+use bitcoin::consensus::encode;
+use bitcoin::consensus::Decodable;
+use bitcoin::consensus::Encodable;
+use bitcoin::Block;
+use bitcoin::OutPoint;
+use bitcoin::Transaction;
+use bitcoin::Txid;
+use spin::RwLock;
+
+use crate::prelude::*;
+use crate::BlockConsumer;
+use crate::UtxoData;
+
+/// The minimum feerate we will ever return, in sat/vB. This is also the default relay fee.
+pub const MIN_RELAY_FEERATE: f64 = 1.0;
+
+/// The maximum confirmation target we will estimate for. Bigger targets are clamped to this value.
+pub const MAX_CONFIRMATION_TARGET: usize = 1008;
+
+/// For how many blocks we keep confirmation statistics from our mempool. For targets bigger than
+/// this, we only use the block feerate floors.
+const MEMPOOL_TRACKED_TARGETS: usize = 48;
+
+/// The highest feerate bucket we track, anything above this goes into the last bucket.
+const MAX_BUCKET_FEERATE: f64 = 10_000.0;
+
+/// How much bigger each bucket is, compared to the previous one.
+const BUCKET_SPACING: f64 = 1.1;
+
+/// How much each data point decays per block. With 0.998, a data point has half of its original
+/// weight after ~346 blocks.
+const DECAY: f64 = 0.998;
+
+/// The fraction of transactions in a bucket that must have confirmed within the target for us to
+/// consider this bucket's feerate enough.
+const SUCCESS_THRESHOLD: f64 = 0.85;
+
+/// How many (decayed) data points we need, before trusting a group of buckets.
+const SUFFICIENT_TXS: f64 = 5.0;
+
+/// The probability of missing the target we are willing to accept when using block floors.
+const FAILURE_PROBABILITY: f64 = 0.05;
+
+/// A block with less than this weight isn't considered full, so anything paying the minimum relay
+/// fee could get in.
+const FULL_BLOCK_WEIGHT: u64 = 3_600_000;
+
+/// The weight percentile (from the cheapest transaction) we use as the block's feerate floor.
+const BLOCK_FLOOR_PERCENTILE: f64 = 0.1;
+
+/// The version of our serialization format.
+const FEE_ESTIMATOR_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Confirmation statistics for transactions within a feerate range.
+struct FeeBucket {
+    /// How many transactions we've seen confirming in this bucket.
+    tx_count: f64,
+
+    /// The sum of the feerates for those transactions, used to compute the average feerate.
+    feerate_sum: f64,
+
+    /// How many transactions confirmed within `n + 1` blocks, for each `n`.
+    confirmed: [f64; MEMPOOL_TRACKED_TARGETS],
+}
+
+impl Default for FeeBucket {
+    fn default() -> Self {
+        FeeBucket {
+            tx_count: 0.0,
+            feerate_sum: 0.0,
+            confirmed: [0.0; MEMPOOL_TRACKED_TARGETS],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A transaction we've seen in our mempool, but isn't confirmed yet.
+struct MempoolEntry {
+    /// Our best height when we saw this transaction.
+    height: u32,
+
+    /// The feerate this transaction pays.
+    feerate: f64,
+
+    /// The bucket this transaction falls into.
+    bucket: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The actual state of our estimator, see [FeeEstimator].
+struct FeeEstimatorInner {
+    /// The last block we've processed.
+    height: u32,
+
+    /// The upper bound for each bucket.
+    boundaries: Vec<f64>,
+
+    /// Our confirmation statistics, one for each entry in `boundaries`.
+    buckets: Vec<FeeBucket>,
+
+    /// Transactions we are waiting to see confirmed.
+    mempool: HashMap<Txid, MempoolEntry>,
+
+    /// The feerate floor for the last [MAX_CONFIRMATION_TARGET] blocks, oldest first.
+    block_floors: VecDeque<f64>,
+}
+
+/// A fee estimator that can give a feerate for any confirmation target.
+///
+/// The chainstate owns an estimator, and subscribes it as a [BlockConsumer], so it learns from
+/// every block connected after IBD. The mempool should call [FeeEstimator::process_transaction]
+/// for each new transaction, so we can learn how long transactions take to confirm. You can
+/// persist its state using the [Encodable] and [Decodable] implementations.
+#[derive(Debug)]
+pub struct FeeEstimator {
+    inner: RwLock<FeeEstimatorInner>,
+
+    /// Whether our chain is in IBD, where we only keep track of the height
+    ibd: AtomicBool,
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FeeEstimator {
+    /// Creates a new estimator, without any data.
+    pub fn new() -> Self {
+        let boundaries = Self::bucket_boundaries();
+        let buckets = vec![FeeBucket::default(); boundaries.len()];
+
+        FeeEstimator {
+            inner: RwLock::new(FeeEstimatorInner {
+                height: 0,
+                boundaries,
+                buckets,
+                mempool: HashMap::new(),
+                block_floors: VecDeque::new(),
+            }),
+            ibd: AtomicBool::new(true),
+        }
+    }
+
+    /// Returns the upper bound of each bucket, starting from [MIN_RELAY_FEERATE] and growing by
+    /// [BUCKET_SPACING] until [MAX_BUCKET_FEERATE].
+    fn bucket_boundaries() -> Vec<f64> {
+        let mut boundaries = Vec::new();
+        let mut boundary = MIN_RELAY_FEERATE;
+
+        while boundary < MAX_BUCKET_FEERATE {
+            boundaries.push(boundary);
+            boundary *= BUCKET_SPACING;
+        }
+
+        boundaries.push(MAX_BUCKET_FEERATE);
+        boundaries
+    }
+
+    /// Returns the index of the bucket a given feerate falls into.
+    fn bucket_for(boundaries: &[f64], feerate: f64) -> usize {
+        boundaries
+            .iter()
+            .position(|boundary| feerate <= *boundary)
+            .unwrap_or(boundaries.len() - 1)
+    }
+
+    /// Tells whether our chain is in IBD. Blocks connected while in IBD don't update our
+    /// statistics.
+    pub fn set_ibd(&self, is_ibd: bool) {
+        self.ibd.store(is_ibd, Ordering::Relaxed);
+    }
+
+    /// Returns the height of the last block we've processed.
+    pub fn height(&self) -> u32 {
+        self.inner.read().height
+    }
+
+    /// Starts tracking a transaction that just entered our mempool, with a given feerate (in
+    /// sat/vB).
+    pub fn process_transaction(&self, txid: Txid, feerate: f64) {
+        let mut inner = self.inner.write();
+
+        let bucket = Self::bucket_for(&inner.boundaries, feerate);
+        let height = inner.height;
+
+        inner.mempool.insert(
+            txid,
+            MempoolEntry {
+                height,
+                feerate,
+                bucket,
+            },
+        );
+    }
+
+    /// Moves our height forward without learning anything from the blocks in between.
+    ///
+    /// This is used while in IBD, where feerates are meaningless for current estimates, but we
+    /// still need to know the current height to track new mempool transactions correctly.
+    pub fn skip_to_height(&self, height: u32) {
+        let mut inner = self.inner.write();
+        if height <= inner.height {
+            return;
+        }
+
+        let max_age = MAX_CONFIRMATION_TARGET as u32;
+        inner
+            .mempool
+            .retain(|_, entry| height.saturating_sub(entry.height) < max_age);
+
+        inner.height = height;
+    }
+
+    /// Computes the feerate (in sat/vB) for a transaction, given the UTXOs spent by it.
+    ///
+    /// Returns `None` if this is a coinbase or if we don't have all the spent UTXOs.
+    pub fn get_feerate(tx: &Transaction, spent_utxos: &HashMap<OutPoint, UtxoData>) -> Option<f64> {
+        Self::compute_feerate(tx, |prevout| {
+            spent_utxos
+                .get(prevout)
+                .map(|utxo| utxo.txout.value.to_sat())
+        })
+    }
+
+    /// Computes the feerate (in sat/vB) for a transaction, using `prevout_value` to find the
+    /// value of each spent output.
+    ///
+    /// Returns `None` if this is a coinbase, if `prevout_value` returns `None` for some input or
+    /// if the transaction spends more than it has.
+    pub fn compute_feerate(
+        tx: &Transaction,
+        prevout_value: impl Fn(&OutPoint) -> Option<u64>,
+    ) -> Option<f64> {
+        if tx.is_coinbase() {
+            return None;
+        }
+
+        let mut in_value = 0_u64;
+        for input in tx.input.iter() {
+            in_value = in_value.checked_add(prevout_value(&input.previous_output)?)?;
+        }
+
+        let out_value = tx
+            .output
+            .iter()
+            .try_fold(0_u64, |acc, out| acc.checked_add(out.value.to_sat()))?;
+
+        let fee = in_value.checked_sub(out_value)?;
+        Some(fee as f64 / tx.vsize() as f64)
+    }
+
+    /// Learns from a newly connected block.
+    ///
+    /// This will update the confirmation statistics for every transaction we were tracking, and
+    /// record the feerate floor for this block.
+    pub fn process_block(
+        &self,
+        block: &Block,
+        height: u32,
+        spent_utxos: &HashMap<OutPoint, UtxoData>,
+    ) {
+        let mut inner = self.inner.write();
+
+        // We've already seen this height, probably a reorg. Just forget about the transactions
+        // in this block, so we don't count them twice.
+        if height <= inner.height {
+            for tx in block.txdata.iter() {
+                inner.mempool.remove(&tx.compute_txid());
+            }
+
+            return;
+        }
+
+        for bucket in inner.buckets.iter_mut() {
+            bucket.tx_count *= DECAY;
+            bucket.feerate_sum *= DECAY;
+            bucket
+                .confirmed
+                .iter_mut()
+                .for_each(|count| *count *= DECAY);
+        }
+
+        let mut feerates = Vec::with_capacity(block.txdata.len());
+        for tx in block.txdata.iter() {
+            if let Some(entry) = inner.mempool.remove(&tx.compute_txid()) {
+                let blocks = height.saturating_sub(entry.height).max(1) as usize;
+                let bucket = &mut inner.buckets[entry.bucket];
+
+                bucket.tx_count += 1.0;
+                bucket.feerate_sum += entry.feerate;
+
+                // A transaction that confirmed within `blocks` also confirmed within any target
+                // bigger than that
+                for count in bucket.confirmed.iter_mut().skip(blocks - 1) {
+                    *count += 1.0;
+                }
+            }
+
+            if let Some(feerate) = Self::get_feerate(tx, spent_utxos) {
+                feerates.push((feerate, tx.weight().to_wu()));
+            }
+        }
+
+        let floor = Self::block_floor(feerates, block.weight().to_wu());
+        inner.block_floors.push_back(floor);
+        while inner.block_floors.len() > MAX_CONFIRMATION_TARGET {
+            inner.block_floors.pop_front();
+        }
+
+        // Transactions that didn't confirm for this long are probably never getting confirmed,
+        // there's no reason to keep them around
+        let max_age = MAX_CONFIRMATION_TARGET as u32;
+        inner
+            .mempool
+            .retain(|_, entry| height.saturating_sub(entry.height) < max_age);
+
+        inner.height = height;
+    }
+
+    /// Returns the lowest feerate needed to get into a block, given the feerate and weight of
+    /// each non-coinbase transaction in it.
+    fn block_floor(mut feerates: Vec<(f64, u64)>, block_weight: u64) -> f64 {
+        if block_weight < FULL_BLOCK_WEIGHT || feerates.is_empty() {
+            return MIN_RELAY_FEERATE;
+        }
+
+        feerates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let total_weight: u64 = feerates.iter().map(|(_, weight)| weight).sum();
+        let target_weight = total_weight as f64 * BLOCK_FLOOR_PERCENTILE;
+
+        let mut accumulated = 0_u64;
+        for (feerate, weight) in feerates.iter() {
+            accumulated += weight;
+            if accumulated as f64 >= target_weight {
+                return feerate.max(MIN_RELAY_FEERATE);
+            }
+        }
+
+        MIN_RELAY_FEERATE
+    }
+
+    /// Returns the estimated feerate (in sat/vB) for a transaction to get confirmed within
+    /// `target` blocks.
+    ///
+    /// Targets are clamped between 1 and [MAX_CONFIRMATION_TARGET]. If we don't have any data,
+    /// this returns [MIN_RELAY_FEERATE].
+    pub fn estimate_fee(&self, target: usize) -> f64 {
+        let target = target.clamp(1, MAX_CONFIRMATION_TARGET);
+        let inner = self.inner.read();
+
+        if target <= MEMPOOL_TRACKED_TARGETS {
+            if let Some(feerate) = Self::estimate_from_mempool(&inner, target) {
+                return feerate.max(MIN_RELAY_FEERATE);
+            }
+        }
+
+        Self::estimate_from_floors(&inner, target)
+            .unwrap_or(MIN_RELAY_FEERATE)
+            .max(MIN_RELAY_FEERATE)
+    }
+
+    /// Uses our confirmation statistics to find the lowest feerate where at least
+    /// [SUCCESS_THRESHOLD] of the transactions confirmed within `target` blocks.
+    ///
+    /// We walk the buckets from the highest feerate to the lowest, grouping them until we have
+    /// enough data points. Then, if the group succeeds, we keep going. The average feerate of the
+    /// last group that succeeded is our estimate.
+    fn estimate_from_mempool(inner: &FeeEstimatorInner, target: usize) -> Option<f64> {
+        // Transactions still in our mempool after `target` blocks count as failures
+        let mut failed = vec![0.0; inner.buckets.len()];
+        for entry in inner.mempool.values() {
+            if inner.height.saturating_sub(entry.height) as usize >= target {
+                failed[entry.bucket] += 1.0;
+            }
+        }
+
+        let mut estimate = None;
+        let (mut count, mut confirmed, mut feerate_sum, mut failures) = (0.0, 0.0, 0.0, 0.0);
+
+        for (idx, bucket) in inner.buckets.iter().enumerate().rev() {
+            count += bucket.tx_count;
+            confirmed += bucket.confirmed[target - 1];
+            feerate_sum += bucket.feerate_sum;
+            failures += failed[idx];
+
+            if count + failures < SUFFICIENT_TXS {
+                continue;
+            }
+
+            if count == 0.0 || confirmed / (count + failures) < SUCCESS_THRESHOLD {
+                break;
+            }
+
+            estimate = Some(feerate_sum / count);
+            (count, confirmed, feerate_sum, failures) = (0.0, 0.0, 0.0, 0.0);
+        }
+
+        estimate
+    }
+
+    /// Uses the block floors to find a feerate that would've been mined within `target` blocks
+    /// with probability of at least `1 - FAILURE_PROBABILITY`.
+    ///
+    /// If a fraction `q` of the recent blocks have a floor lower than some feerate, the probability
+    /// of not getting mined in `target` blocks is `(1 - q)^target`. So we look for the smallest
+    /// floor where this is below [FAILURE_PROBABILITY].
+    fn estimate_from_floors(inner: &FeeEstimatorInner, target: usize) -> Option<f64> {
+        if inner.block_floors.is_empty() {
+            return None;
+        }
+
+        let mut floors: Vec<f64> = inner.block_floors.iter().copied().collect();
+        floors.sort_by(|a, b| a.total_cmp(b));
+
+        let n = floors.len();
+        let failure_probability = |k: usize| {
+            let miss = 1.0 - k as f64 / n as f64;
+            let mut probability = 1.0;
+
+            for _ in 0..target {
+                probability *= miss;
+                if probability <= FAILURE_PROBABILITY {
+                    break;
+                }
+            }
+
+            probability
+        };
+
+        // `failure_probability` is decreasing on k, and always succeeds for k = n
+        let (mut low, mut high) = (1, n);
+        while low < high {
+            let mid = (low + high) / 2;
+            if failure_probability(mid) <= FAILURE_PROBABILITY {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+
+        Some(floors[low - 1])
+    }
+}
+
+impl BlockConsumer for FeeEstimator {
+    fn wants_spent_utxos(&self) -> bool {
+        !self.ibd.load(Ordering::Relaxed)
+    }
+
+    fn on_block(
+        &self,
+        block: &Block,
+        height: u32,
+        spent_utxos: Option<&HashMap<OutPoint, UtxoData>>,
+    ) {
+        // Feerates from IBD blocks are meaningless for current estimates
+        match (self.ibd.load(Ordering::Relaxed), spent_utxos) {
+            (false, Some(spent_utxos)) => self.process_block(block, height, spent_utxos),
+            _ => self.skip_to_height(height),
+        }
+    }
+}
+
+impl Encodable for FeeEstimator {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> bitcoin::io::Result<usize> {
+        let inner = self.inner.read();
+
+        let mut len = 0;
+        len += FEE_ESTIMATOR_VERSION.consensus_encode(writer)?;
+        len += inner.height.consensus_encode(writer)?;
+
+        len += (inner.buckets.len() as u32).consensus_encode(writer)?;
+        for bucket in inner.buckets.iter() {
+            len += bucket.tx_count.to_bits().consensus_encode(writer)?;
+            len += bucket.feerate_sum.to_bits().consensus_encode(writer)?;
+
+            for count in bucket.confirmed.iter() {
+                len += count.to_bits().consensus_encode(writer)?;
+            }
+        }
+
+        len += (inner.block_floors.len() as u32).consensus_encode(writer)?;
+        for floor in inner.block_floors.iter() {
+            len += floor.to_bits().consensus_encode(writer)?;
+        }
+
+        Ok(len)
+    }
+}
+
+impl Decodable for FeeEstimator {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, encode::Error> {
+        let version = u8::consensus_decode(reader)?;
+        if version != FEE_ESTIMATOR_VERSION {
+            return Err(encode::Error::ParseFailed("unknown fee estimator version"));
+        }
+
+        let height = u32::consensus_decode(reader)?;
+        let boundaries = Self::bucket_boundaries();
+
+        let bucket_count = u32::consensus_decode(reader)? as usize;
+        if bucket_count != boundaries.len() {
+            return Err(encode::Error::ParseFailed(
+                "fee estimator bucket count mismatch",
+            ));
+        }
+
+        let read_f64 = |reader: &mut R| u64::consensus_decode(reader).map(f64::from_bits);
+
+        let mut buckets = Vec::with_capacity(bucket_count);
+        for _ in 0..bucket_count {
+            let tx_count = read_f64(reader)?;
+            let feerate_sum = read_f64(reader)?;
+
+            let mut confirmed = [0.0; MEMPOOL_TRACKED_TARGETS];
+            for count in confirmed.iter_mut() {
+                *count = read_f64(reader)?;
+            }
+
+            buckets.push(FeeBucket {
+                tx_count,
+                feerate_sum,
+                confirmed,
+            });
+        }
+
+        let floor_count = u32::consensus_decode(reader)? as usize;
+        if floor_count > MAX_CONFIRMATION_TARGET {
+            return Err(encode::Error::ParseFailed("too many block floors"));
+        }
+
+        let mut block_floors = VecDeque::with_capacity(floor_count);
+        for _ in 0..floor_count {
+            block_floors.push_back(read_f64(reader)?);
+        }
+
+        Ok(FeeEstimator {
+            inner: RwLock::new(FeeEstimatorInner {
+                height,
+                boundaries,
+                buckets,
+                mempool: HashMap::new(),
+                block_floors,
+            }),
+            ibd: AtomicBool::new(true),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::absolute::LockTime;
+    use bitcoin::block::Header;
+    use bitcoin::block::Version as BlockVersion;
+    use bitcoin::consensus::deserialize;
+    use bitcoin::consensus::serialize;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::Amount;
+    use bitcoin::BlockHash;
+    use bitcoin::CompactTarget;
+    use bitcoin::ScriptBuf;
+    use bitcoin::Sequence;
+    use bitcoin::TxIn;
+    use bitcoin::TxMerkleNode;
+    use bitcoin::TxOut;
+    use bitcoin::Witness;
+
+    use super::*;
+
+    /// Builds a transaction spending a fake outpoint, and the UTXO it spends. The transaction
+    /// pays `fee` satoshis, and may carry a witness of `witness_size` bytes to make it heavier.
+    fn build_tx(seed: u32, fee: u64, witness_size: usize) -> (Transaction, OutPoint, UtxoData) {
+        let prevout = OutPoint {
+            txid: Txid::from_byte_array([seed as u8; 32]),
+            vout: seed,
+        };
+
+        let mut witness = Witness::new();
+        if witness_size > 0 {
+            witness.push(vec![0; witness_size]);
+        }
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: prevout,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness,
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+
+        let utxo = UtxoData {
+            txout: TxOut {
+                value: Amount::from_sat(100_000 + fee),
+                script_pubkey: ScriptBuf::new(),
+            },
+            is_coinbase: false,
+            creation_height: 0,
+            creation_time: 0,
+        };
+
+        (tx, prevout, utxo)
+    }
+
+    fn build_block(txdata: Vec<Transaction>) -> Block {
+        Block {
+            header: Header {
+                version: BlockVersion::ONE,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0),
+                nonce: 0,
+            },
+            txdata,
+        }
+    }
+
+    #[test]
+    fn test_no_data() {
+        let estimator = FeeEstimator::new();
+
+        assert_eq!(estimator.estimate_fee(0), MIN_RELAY_FEERATE);
+        assert_eq!(estimator.estimate_fee(1), MIN_RELAY_FEERATE);
+        assert_eq!(estimator.estimate_fee(100_000), MIN_RELAY_FEERATE);
+    }
+
+    #[test]
+    fn test_get_feerate() {
+        let (tx, prevout, utxo) = build_tx(1, 1_000, 0);
+        let mut spent_utxos = HashMap::new();
+
+        assert_eq!(FeeEstimator::get_feerate(&tx, &spent_utxos), None);
+
+        spent_utxos.insert(prevout, utxo);
+        let feerate = FeeEstimator::get_feerate(&tx, &spent_utxos).unwrap();
+        assert_eq!(feerate, 1_000.0 / tx.vsize() as f64);
+    }
+
+    #[test]
+    fn test_mempool_estimates() {
+        let estimator = FeeEstimator::new();
+
+        // Every block, one transaction paying ~50 sat/vB confirms right away, and one paying
+        // ~5 sat/vB takes 10 blocks to confirm
+        let mut slow_txs = VecDeque::new();
+        for height in 1..=200 {
+            let (fast, fast_prevout, fast_utxo) = build_tx(height * 2, 3_000, 0);
+            let (slow, slow_prevout, slow_utxo) = build_tx(height * 2 + 1, 300, 0);
+
+            let fast_feerate = 3_000.0 / fast.vsize() as f64;
+            let slow_feerate = 300.0 / slow.vsize() as f64;
+
+            estimator.process_transaction(fast.compute_txid(), fast_feerate);
+            estimator.process_transaction(slow.compute_txid(), slow_feerate);
+            slow_txs.push_back((slow, slow_prevout, slow_utxo));
+
+            let mut spent_utxos = HashMap::new();
+            spent_utxos.insert(fast_prevout, fast_utxo);
+
+            let mut txdata = vec![fast];
+            if slow_txs.len() >= 10 {
+                let (slow, prevout, utxo) = slow_txs.pop_front().unwrap();
+                spent_utxos.insert(prevout, utxo);
+                txdata.push(slow);
+            }
+
+            estimator.process_block(&build_block(txdata), height, &spent_utxos);
+        }
+
+        let next_block = estimator.estimate_fee(1);
+        let ten_blocks = estimator.estimate_fee(10);
+
+        assert!(next_block > 40.0 && next_block < 60.0, "{next_block}");
+        assert!(ten_blocks > 4.0 && ten_blocks < 6.0, "{ten_blocks}");
+    }
+
+    #[test]
+    fn test_skip_to_height() {
+        let estimator = FeeEstimator::new();
+
+        // We were in IBD for a while, so we didn't learn anything from those blocks
+        estimator.skip_to_height(800_000);
+        assert_eq!(estimator.height(), 800_000);
+        assert_eq!(estimator.estimate_fee(1), MIN_RELAY_FEERATE);
+
+        // Transactions seen after IBD must count their age from the current height, so they
+        // are accounted as confirmed within one block
+        let mut spent_utxos = HashMap::new();
+        let mut txdata = Vec::new();
+        for i in 0..10 {
+            let (tx, prevout, utxo) = build_tx(i, 3_000, 0);
+            estimator.process_transaction(tx.compute_txid(), 3_000.0 / tx.vsize() as f64);
+
+            spent_utxos.insert(prevout, utxo);
+            txdata.push(tx);
+        }
+
+        estimator.process_block(&build_block(txdata), 800_001, &spent_utxos);
+        assert!(estimator.estimate_fee(1) > MIN_RELAY_FEERATE);
+    }
+
+    #[test]
+    fn test_block_floor_estimates() {
+        let estimator = FeeEstimator::new();
+
+        // Build full blocks, alternating their floors between 2 and 20 sat/vB.
+        for height in 1..=10 {
+            let fee_per_vbyte = if height % 2 == 0 { 2 } else { 20 };
+            let mut spent_utxos = HashMap::new();
+            let mut txdata = Vec::new();
+
+            for i in 0..4 {
+                let (tx, prevout, mut utxo) = build_tx(height * 4 + i, 0, 900_000);
+                let fee = fee_per_vbyte * tx.vsize() as u64;
+                utxo.txout.value = Amount::from_sat(100_000 + fee);
+
+                spent_utxos.insert(prevout, utxo);
+                txdata.push(tx);
+            }
+
+            let block = build_block(txdata);
+            assert!(block.weight().to_wu() >= FULL_BLOCK_WEIGHT);
+
+            estimator.process_block(&block, height, &spent_utxos);
+        }
+
+        // Only the 20 sat/vB floor is safe for the next block, but the cheaper floor will likely
+        // be enough within a few blocks
+        assert_eq!(estimator.estimate_fee(1), 20.0);
+        assert_eq!(estimator.estimate_fee(6), 2.0);
+    }
+
+    #[test]
+    fn test_empty_blocks_floor() {
+        let estimator = FeeEstimator::new();
+        let (tx, prevout, utxo) = build_tx(1, 50_000, 0);
+
+        let mut spent_utxos = HashMap::new();
+        spent_utxos.insert(prevout, utxo);
+
+        // This block isn't full, so anything could get in
+        estimator.process_block(&build_block(vec![tx]), 1, &spent_utxos);
+        assert_eq!(estimator.estimate_fee(1), MIN_RELAY_FEERATE);
+        assert_eq!(estimator.height(), 1);
+    }
+
+    #[test]
+    fn test_serialization_roundtrip() {
+        let estimator = FeeEstimator::new();
+
+        for height in 1..=20 {
+            let (tx, prevout, utxo) = build_tx(height, 2_000, 0);
+            estimator.process_transaction(tx.compute_txid(), 2_000.0 / tx.vsize() as f64);
+
+            let mut spent_utxos = HashMap::new();
+            spent_utxos.insert(prevout, utxo);
+
+            estimator.process_block(&build_block(vec![tx]), height, &spent_utxos);
+        }
+
+        let decoded: FeeEstimator = deserialize(&serialize(&estimator)).unwrap();
+
+        assert_eq!(decoded.height(), 20);
+        assert_eq!(*decoded.inner.read(), *estimator.inner.read());
+        for target in [1, 2, 6, 48, 144, 1008] {
+            assert_eq!(decoded.estimate_fee(target), estimator.estimate_fee(target));
+        }
+
+        let mut data = serialize(&estimator);
+        data[0] = FEE_ESTIMATOR_VERSION + 1;
+        assert!(deserialize::<FeeEstimator>(&data).is_err());
+    }
+}
@@ -43,8 +43,20 @@
 //! 900k blocks on mainnet. So we would have 32 * 16 + 8 = 520 bytes per accumulator.
 //! 520 * 900k = 468 MiB. This is the absolute worst case for the almost two decades that Bitcoin
 //! existed. However, although this is a pretty manageable number, we can safely get rid of some
-//! older roots, only storing the latest ones, and a few old ones for very deep reorgs. This is,
-//! however, a TODO.
+//! older roots, only storing the latest ones, and a few old ones for very deep reorgs. This is
+//! done by [ChainStore::compact], that also removes fork headers buried deep below our tip. See
+//! [CompactionPolicy]. A compaction writes the roots we keep into a new file, and saves where
+//! each one went in `accumulators.journal` before touching our headers. If we crash before the
+//! headers and the new file are in place, we finish it from the journal when opening the store.
+//! Fork headers are moved inside their own file, so we first copy the ones we keep, and the
+//! hashes of the ones we remove, into `fork_headers.journal`, and finish it the same way.
+//!
+//! For reorgs deeper than the roots we keep, we also store undo data for every block, similar to
+//! Core's `rev` files. The undo records are appended to `undo.bin`, and `undo_index.bin` keeps the
+//! position and length of the record for each height, as a little-endian u64 and u32. We only
+//! need undo data for blocks we may still reorg, so a compaction rewrites `undo.bin` without the
+//! records older than `policy.fork_depth`. It starts with the position of its first record, so
+//! the positions in the index stay the same.
 //!
 //! # Good to know
 //!
@@ -93,12 +105,15 @@ use lru::LruCache;
 use memmap2::MmapMut;
 use memmap2::MmapOptions;
 use tracing::info;
-use xxhash_rust::xxh3;
 
 use crate::BestChain;
 use crate::ChainStore;
+use crate::CompactionPolicy;
+use crate::CompactionStats;
 use crate::DatabaseError;
+use crate::DbCheckSum;
 use crate::DiskBlockHeader;
+use crate::FileChecksum;
 
 /// The magic number we use to make sure we're reading the right file
 ///
@@ -119,6 +134,23 @@ type CacheType = LruCache<BlockHash, DiskBlockHeader>;
 /// leaves. So 32 * 64 + 8 = 2048 + 8 = 2056 bytes
 const UTREEXO_ACC_SIZE: usize = 32 * 64 + 8;
 
+/// The size of each entry in the undo index: a u64 for the position of the undo data, and a u32
+/// for its length
+const UNDO_INDEX_ENTRY_SIZE: u64 = 12;
+
+/// The size of the start of the undo file: the position of its first record, as a little-endian
+/// u64. Positions count every byte we've ever written, so pruning doesn't change them.
+const UNDO_HEADER_SIZE: u64 = 8;
+
+/// The size of each entry in the journal of a roots compaction: the height, and the new position
+/// and length of its roots, as little-endian u32s
+const ROOTS_JOURNAL_ENTRY_SIZE: usize = 12;
+
+/// The size of the start of a fork headers compaction journal: the fork count before it, how
+/// many headers we keep and remove as little-endian u32s, and the index occupancy after it as a
+/// little-endian u64
+const FORK_JOURNAL_HEADER_SIZE: usize = 20;
+
 #[derive(Clone)]
 /// Configuration for our flat chain store. See each field for more information
 pub struct FlatChainStoreConfig {
@@ -184,23 +216,6 @@ impl FlatChainStoreConfig {
     }
 }
 
-#[repr(transparent)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct FileChecksum(u64);
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-/// The current checksum of our database
-pub struct DbCheckSum {
-    /// The checksum of the headers file
-    headers_checksum: FileChecksum,
-
-    /// The checksum of the index map
-    index_checksum: FileChecksum,
-
-    /// The checksum of the fork headers file
-    fork_headers_checksum: FileChecksum,
-}
-
 /// A bucket in our index map, holding a pointer to the index.
 ///
 /// This enum indicates whether a given bucket is occupied, and if it is, it holds the respective
@@ -467,6 +482,60 @@ impl BlockIndex {
         }
     }
 
+    /// Removes the entry for a block hash from our index, returning whether it was there
+    ///
+    /// Since we use linear probing, we can't just empty its bucket, or we would break the probe
+    /// sequence of the entries after it. Instead, we move back every entry after it (in the same
+    /// cluster) that may be in the emptied bucket. This is known as backward shift deletion.
+    unsafe fn remove_index_for_hash(
+        &self,
+        hash: BlockHash,
+        get_header_by_index: impl Fn(Index) -> Result<HashedDiskHeader, FlatChainstoreError>,
+    ) -> Result<bool, FlatChainstoreError> {
+        let IndexBucket::Occupied { ptr, .. } =
+            self.hash_map_find_pos(hash, &get_header_by_index)?
+        else {
+            return Ok(false);
+        };
+
+        let base_ptr = self.index_map.as_ptr() as *mut Index;
+        let mask = self.index_size - 1;
+
+        let mut empty = ptr.offset_from(base_ptr) as usize;
+        let mut current = empty;
+        loop {
+            current = (current + 1) & mask;
+
+            // SAFETY: the masked position is lower than the `index_size`
+            let entry_ptr = base_ptr.add(current);
+            let index = *entry_ptr;
+            if index.is_empty() {
+                break;
+            }
+
+            // We can't find stale entries anyway, so they can stay where they are
+            let Ok(header) = get_header_by_index(index) else {
+                continue;
+            };
+
+            // An entry can be moved back if its home bucket isn't between the empty bucket and
+            // where it is now, otherwise we wouldn't find it anymore
+            let home = Self::index_hash_fn(header.hash) as usize & mask;
+            let stays = match empty <= current {
+                true => empty < home && home <= current,
+                false => empty < home || home <= current,
+            };
+
+            if !stays {
+                base_ptr.add(empty).write(index);
+                empty = current;
+            }
+        }
+
+        base_ptr.add(empty).write(Index::new(0)?);
+        Ok(true)
+    }
+
     /// Returns the position inside the hash map where a given hash should be
     ///
     /// This function computes the short hash for the block hash and looks up the position inside
@@ -569,8 +638,20 @@ pub struct FlatChainStore {
     /// The file containing the accumulators for each blocks
     accumulator_file: File,
 
+    /// The file containing the serialized state of our fee estimator
+    fee_estimates_file: File,
+
+    /// The file where we append the undo data for each block
+    undo_file: File,
+
+    /// The position and length of each block's undo data inside `undo_file`, indexed by height
+    undo_index_file: File,
+
     /// A LRU cache for the last n blocks we've touched
     cache: Mutex<LruCache<BlockHash, DiskBlockHeader>>,
+
+    /// The directory where we keep our files
+    path: String,
 }
 
 impl FlatChainStore {
@@ -603,6 +684,9 @@ impl FlatChainStore {
         let metadata_path = format!("{dir}/metadata.bin");
         let fork_headers_path = format!("{dir}/fork_headers.bin");
         let accumulator_file_path = format!("{dir}/accumulators.bin");
+        let fee_estimates_path = format!("{dir}/fee_estimates.bin");
+        let undo_path = format!("{dir}/undo.bin");
+        let undo_index_path = format!("{dir}/undo_index.bin");
 
         let index_map_file_size = index_size * size_of::<u32>();
         let index_map = unsafe { Self::init_file(&index_path, index_map_file_size, file_mode)? };
@@ -652,16 +736,85 @@ impl FlatChainStore {
             .truncate(false)
             .open(accumulator_file_path)?;
 
+        let fee_estimates_file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(fee_estimates_path)?;
+
+        let undo_file = Self::open_undo_file(&undo_path)?;
+        let undo_index_file = Self::open_data_file(&undo_index_path)?;
+
         Ok(Self {
             headers,
             accumulator_file,
+            fee_estimates_file,
+            undo_file,
+            undo_index_file,
             metadata,
             block_index: BlockIndex::new(index_map, index_size),
             fork_headers,
             cache: LruCache::new(cache_size).into(),
+            path: config.path,
         })
     }
 
+    /// Opens a regular file for reading and writing, creating it if it doesn't exist
+    fn open_data_file(path: &str) -> Result<File, FlatChainstoreError> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        Ok(file)
+    }
+
+    /// Opens the undo file, writing its start if it's a new one
+    fn open_undo_file(path: &str) -> Result<File, FlatChainstoreError> {
+        let mut file = Self::open_data_file(path)?;
+        if file.metadata()?.len() < UNDO_HEADER_SIZE {
+            file.set_len(0)?;
+            file.write_all(&0_u64.to_le_bytes())?;
+            file.sync_all()?;
+        }
+
+        Ok(file)
+    }
+
+    /// Returns the position of the first record in the undo file, and the position where the
+    /// next one goes
+    fn get_undo_bounds(&mut self) -> Result<(u64, u64), FlatChainstoreError> {
+        let mut start = [0; UNDO_HEADER_SIZE as usize];
+        self.undo_file.seek(SeekFrom::Start(0))?;
+        self.undo_file.read_exact(&mut start)?;
+
+        let start = u64::from_le_bytes(start);
+        let end = start + self.undo_file.metadata()?.len() - UNDO_HEADER_SIZE;
+
+        Ok((start, end))
+    }
+
+    /// Reads the position and length of the undo data for a given height, if we have an entry
+    /// for it
+    fn get_undo_entry(&mut self, height: u32) -> Result<Option<(u64, u32)>, FlatChainstoreError> {
+        let offset = height as u64 * UNDO_INDEX_ENTRY_SIZE;
+        if offset + UNDO_INDEX_ENTRY_SIZE > self.undo_index_file.metadata()?.len() {
+            return Ok(None);
+        }
+
+        let mut entry = [0; UNDO_INDEX_ENTRY_SIZE as usize];
+        self.undo_index_file.seek(SeekFrom::Start(offset))?;
+        self.undo_index_file.read_exact(&mut entry)?;
+
+        let pos = u64::from_le_bytes(entry[0..8].try_into().expect("slice has 8 bytes"));
+        let len = u32::from_le_bytes(entry[8..12].try_into().expect("slice has 4 bytes"));
+
+        Ok(Some((pos, len)))
+    }
+
     /// Opens a new storage. If it already exists, just load. If not, create a new one
     pub fn new(config: FlatChainStoreConfig) -> Result<Self, FlatChainstoreError> {
         let dir = &config.path;
@@ -702,6 +855,9 @@ impl FlatChainStore {
         let headers_file_path = format!("{}/headers.bin", config.path);
         let fork_file_path = format!("{}/fork_headers.bin", config.path);
         let accumulator_file_path = format!("{}/accumulators.bin", config.path);
+        let fee_estimates_path = format!("{}/fee_estimates.bin", config.path);
+        let undo_path = format!("{}/undo.bin", config.path);
+        let undo_index_path = format!("{}/undo_index.bin", config.path);
 
         let index_file_size = metadata.index_capacity * size_of::<u32>();
         let headers_file_size = metadata.headers_file_size * size_of::<HashedDiskHeader>();
@@ -721,14 +877,37 @@ impl FlatChainStore {
             .truncate(false)
             .open(accumulator_file_path)?;
 
-        Ok(Self {
+        let fee_estimates_file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(fee_estimates_path)?;
+
+        let undo_file = Self::open_undo_file(&undo_path)?;
+        let undo_index_file = Self::open_data_file(&undo_index_path)?;
+
+        let mut store = Self {
             headers,
             accumulator_file,
+            fee_estimates_file,
+            undo_file,
+            undo_index_file,
             metadata: metadata_file,
             block_index: BlockIndex::new(index_map, metadata.index_capacity),
             fork_headers,
             cache: LruCache::new(cache_size).into(),
-        })
+            path: config.path,
+        };
+
+        // We may have crashed in the middle of a compaction
+        let _ = std::fs::remove_file(format!("{}/undo.bin.tmp", store.path));
+        unsafe {
+            store.finish_fork_compaction()?;
+            store.finish_roots_compaction()?;
+        }
+
+        Ok(store)
     }
 
     /// Adds a new entry into the block index, given a block hash and its `Index`
@@ -783,23 +962,11 @@ impl FlatChainStore {
 
     /// Computes the XXH3-64 checksum for our database
     pub fn compute_checksum(&self) -> DbCheckSum {
-        // a function that computes the xxHash of a memory map
-        let checksum_fn = |mmap: &MmapMut| {
-            let mmap_as_slice = mmap.iter().as_slice();
-            let hash = xxh3::xxh3_64(mmap_as_slice);
-
-            FileChecksum(hash)
-        };
-
-        let headers_checksum = checksum_fn(&self.headers);
-        let index_checksum = checksum_fn(&self.block_index.index_map);
-        let fork_headers_checksum = checksum_fn(&self.fork_headers);
-
-        DbCheckSum {
-            headers_checksum,
-            index_checksum,
-            fork_headers_checksum,
-        }
+        DbCheckSum::new(
+            &self.headers,
+            &self.block_index.index_map,
+            &self.fork_headers,
+        )
     }
 
     /// Truncates a number to the nearest power of 2
@@ -1052,6 +1219,391 @@ impl FlatChainStore {
         Ok(())
     }
 
+    /// Returns the height where a fork header's branch leaves our main chain, or `None` if we
+    /// don't know all of its ancestors
+    ///
+    /// We remember the answer for every header we go through in `fork_points`, so each branch is
+    /// only walked once.
+    unsafe fn get_fork_point_height(
+        &self,
+        header: &DiskBlockHeader,
+        fork_points: &mut HashMap<BlockHash, Option<u32>>,
+    ) -> Result<Option<u32>, FlatChainstoreError> {
+        let mut branch = Vec::new();
+        let mut prev_hash = header.prev_blockhash;
+
+        let fork_point = loop {
+            if let Some(fork_point) = fork_points.get(&prev_hash) {
+                break *fork_point;
+            }
+
+            // Fork headers are never more than `fork_count` deep, anything else is a loop
+            if branch.len() > self.get_metadata()?.fork_count as usize {
+                break None;
+            }
+
+            let entry = self
+                .block_index
+                .get_index_for_hash(prev_hash, |index| self.get_disk_header(index).copied())?;
+
+            match entry {
+                Some((index, parent)) if index.is_main_chain() => break parent.height(),
+                Some((_, parent)) => {
+                    branch.push(prev_hash);
+                    prev_hash = parent.prev_blockhash;
+                }
+                None => break None,
+            }
+        };
+
+        for hash in branch {
+            fork_points.insert(hash, fork_point);
+        }
+
+        Ok(fork_point)
+    }
+
+    /// Removes the branches that left our main chain more than `policy.fork_depth` blocks below
+    /// `best_height`, and moves the remaining fork headers to the start of the fork file
+    ///
+    /// We remove whole branches, so we never keep a fork header without its parent. Returns how
+    /// many headers were removed.
+    unsafe fn compact_fork_headers(
+        &mut self,
+        policy: &CompactionPolicy,
+        best_height: u32,
+    ) -> Result<u32, FlatChainstoreError> {
+        let removed = self.write_fork_journal(policy, best_height)?;
+        self.finish_fork_compaction()?;
+
+        Ok(removed)
+    }
+
+    /// Writes the fork headers we keep under `policy`, and the hashes of the ones we remove, into
+    /// `fork_headers.journal`. This doesn't change our fork file or our index, that's done by
+    /// [Self::finish_fork_compaction]. Returns how many headers we'll remove.
+    unsafe fn write_fork_journal(
+        &mut self,
+        policy: &CompactionPolicy,
+        best_height: u32,
+    ) -> Result<u32, FlatChainstoreError> {
+        let metadata = self.get_metadata()?;
+        let fork_count = metadata.fork_count;
+        let occupancy = metadata.block_index_occupancy;
+
+        let mut fork_points = HashMap::new();
+        let mut kept = Vec::new();
+        let mut stale = Vec::new();
+
+        for position in 0..fork_count {
+            let index = Index::new_fork(position)?;
+            let header = match self.get_disk_header(index) {
+                Ok(header) => *header,
+                Err(FlatChainstoreError::BlockNotFound) => continue,
+                Err(e) => return Err(e),
+            };
+
+            // If this header went back to our main chain in a reorg, this is a stale copy that
+            // our index doesn't point to anymore
+            let current = self
+                .block_index
+                .get_index_for_hash(header.hash, |index| self.get_disk_header(index).copied())?
+                .map(|(index, _)| index);
+
+            if current != Some(index) {
+                continue;
+            }
+
+            let fork_point = self.get_fork_point_height(&header.header, &mut fork_points)?;
+            match fork_point {
+                Some(height) if !policy.keep_fork_header(height, best_height) => {
+                    stale.push(header.hash)
+                }
+                _ => kept.push((position, header.hash)),
+            }
+        }
+
+        if kept.len() as u32 == fork_count {
+            return Ok(0);
+        }
+
+        let header_size = size_of::<HashedDiskHeader>();
+        let mut journal = Vec::with_capacity(
+            FORK_JOURNAL_HEADER_SIZE + stale.len() * 32 + kept.len() * (32 + header_size),
+        );
+
+        journal.extend(fork_count.to_le_bytes());
+        journal.extend((kept.len() as u32).to_le_bytes());
+        journal.extend((stale.len() as u32).to_le_bytes());
+        journal.extend((occupancy as u64 - stale.len() as u64).to_le_bytes());
+
+        for hash in stale.iter() {
+            journal.extend(hash.to_byte_array());
+        }
+
+        for (position, hash) in kept {
+            let start = position as usize * header_size;
+            journal.extend(hash.to_byte_array());
+            journal.extend(&self.fork_headers[start..start + header_size]);
+        }
+
+        // The journal only counts once it's complete, so we write it somewhere else first
+        let journal_path = format!("{}/fork_headers.journal", self.path);
+        let journal_tmp_path = format!("{}/fork_headers.journal.tmp", self.path);
+        let mut journal_file = File::create(&journal_tmp_path)?;
+        journal_file.write_all(&journal)?;
+        journal_file.sync_all()?;
+        std::fs::rename(&journal_tmp_path, journal_path)?;
+
+        Ok(stale.len() as u32)
+    }
+
+    /// Finishes a fork headers compaction from its journal: removes every journaled header from
+    /// our index, writes the ones we keep to the start of the fork file, and only then points our
+    /// index to their new positions.
+    ///
+    /// If we crash in the middle, we just do it again: the journal is only removed at the end.
+    /// Since our index never points to a fork header while we move them, we can always find (or
+    /// not find) the journaled hashes, no matter how far we got.
+    unsafe fn finish_fork_compaction(&mut self) -> Result<(), FlatChainstoreError> {
+        let journal_path = format!("{}/fork_headers.journal", self.path);
+
+        let journal = match std::fs::read(&journal_path) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let _ = std::fs::remove_file(format!("{journal_path}.tmp"));
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let field = |i: usize| {
+            u32::from_le_bytes(journal[i..i + 4].try_into().expect("slice has 4 bytes")) as usize
+        };
+        let fork_count = field(0);
+        let kept_count = field(4);
+        let stale_count = field(8);
+        let occupancy = u64::from_le_bytes(journal[12..20].try_into().expect("slice has 8 bytes"));
+
+        let header_size = size_of::<HashedDiskHeader>();
+        let (stale, kept) = journal[FORK_JOURNAL_HEADER_SIZE..].split_at(stale_count * 32);
+        let kept = kept.chunks_exact(32 + header_size);
+        let hash = |entry: &[u8]| {
+            BlockHash::from_byte_array(entry[..32].try_into().expect("slice has 32 bytes"))
+        };
+
+        for entry in stale.chunks_exact(32).chain(kept.clone()) {
+            self.block_index
+                .remove_index_for_hash(hash(entry), |index| self.get_disk_header(index).copied())?;
+        }
+
+        for (position, entry) in kept.clone().enumerate() {
+            let start = position * header_size;
+            self.fork_headers[start..start + header_size].copy_from_slice(&entry[32..]);
+        }
+        self.fork_headers[kept_count * header_size..fork_count * header_size].fill(0);
+
+        for (position, entry) in kept.enumerate() {
+            let index = Index::new_fork(position as u32)?;
+            self.block_index
+                .set_index_for_hash(hash(entry), index, |index| {
+                    self.get_disk_header(index).copied()
+                })?;
+        }
+
+        let metadata = self.get_metadata_mut()?;
+        metadata.fork_count = kept_count as u32;
+        metadata.block_index_occupancy = occupancy as usize;
+
+        self.do_flush()?;
+        std::fs::remove_file(&journal_path)?;
+
+        Ok(())
+    }
+
+    /// Removes the roots we don't need to keep under `policy`, by writing the ones we keep into
+    /// a new accumulator file. Returns for how many heights we've removed the roots.
+    unsafe fn compact_roots(
+        &mut self,
+        policy: &CompactionPolicy,
+        best_height: u32,
+        validation_height: u32,
+    ) -> Result<u32, FlatChainstoreError> {
+        let removed = self.write_compacted_roots(policy, best_height, validation_height)?;
+        self.finish_roots_compaction()?;
+
+        Ok(removed)
+    }
+
+    /// Writes the roots we keep under `policy` into `accumulators.bin.tmp`, and where each one
+    /// went into `accumulators.journal`. This doesn't change our headers or our accumulator
+    /// file, that's done by [Self::finish_roots_compaction]. Returns for how many heights we'll
+    /// remove the roots.
+    unsafe fn write_compacted_roots(
+        &mut self,
+        policy: &CompactionPolicy,
+        best_height: u32,
+        validation_height: u32,
+    ) -> Result<u32, FlatChainstoreError> {
+        let mut kept = Vec::new();
+        let mut removed = Vec::new();
+
+        for height in 0..=best_height {
+            let header = match self.get_disk_header(Index::new(height)?) {
+                Ok(header) => header,
+                Err(FlatChainstoreError::BlockNotFound) => break,
+                Err(e) => return Err(e),
+            };
+
+            if header.acc_len == 0 {
+                continue;
+            }
+
+            match policy.keep_roots(height, validation_height) {
+                true => kept.push((height, header.acc_pos, header.acc_len)),
+                false => removed.push(height),
+            }
+        }
+
+        if removed.is_empty() {
+            return Ok(0);
+        }
+
+        let tmp_path = format!("{}/accumulators.bin.tmp", self.path);
+        let mut new_file = File::create(tmp_path)?;
+
+        let mut journal =
+            Vec::with_capacity((kept.len() + removed.len()) * ROOTS_JOURNAL_ENTRY_SIZE);
+        for (height, pos, len) in kept {
+            let mut roots = vec![0; len as usize];
+            self.accumulator_file.seek(SeekFrom::Start(pos as u64))?;
+            self.accumulator_file.read_exact(&mut roots)?;
+
+            let new_pos = new_file.stream_position()? as u32;
+            new_file.write_all(&roots)?;
+
+            journal.extend(height.to_le_bytes());
+            journal.extend(new_pos.to_le_bytes());
+            journal.extend(len.to_le_bytes());
+        }
+
+        for height in removed.iter() {
+            journal.extend(height.to_le_bytes());
+            journal.extend([0; 8]);
+        }
+
+        new_file.sync_all()?;
+
+        // The journal only counts once it's complete, so we write it somewhere else first
+        let journal_path = format!("{}/accumulators.journal", self.path);
+        let journal_tmp_path = format!("{}/accumulators.journal.tmp", self.path);
+        let mut journal_file = File::create(&journal_tmp_path)?;
+        journal_file.write_all(&journal)?;
+        journal_file.sync_all()?;
+        std::fs::rename(&journal_tmp_path, journal_path)?;
+
+        Ok(removed.len() as u32)
+    }
+
+    /// Finishes a roots compaction from its journal: points our headers to the roots in the new
+    /// accumulator file, flushes them, and only then replaces the old file.
+    ///
+    /// If we crash in the middle, we just do it again: the journal is only removed at the end.
+    /// Without a journal, a compaction didn't get to change anything, so we keep the old file.
+    unsafe fn finish_roots_compaction(&mut self) -> Result<(), FlatChainstoreError> {
+        let path = format!("{}/accumulators.bin", self.path);
+        let tmp_path = format!("{}/accumulators.bin.tmp", self.path);
+        let journal_path = format!("{}/accumulators.journal", self.path);
+
+        let journal = match std::fs::read(&journal_path) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let _ = std::fs::remove_file(&tmp_path);
+                let _ = std::fs::remove_file(format!("{journal_path}.tmp"));
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in journal.chunks_exact(ROOTS_JOURNAL_ENTRY_SIZE) {
+            let field = |i: usize| {
+                u32::from_le_bytes(entry[i..i + 4].try_into().expect("slice has 4 bytes"))
+            };
+
+            let header = self.get_disk_header_mut(Index::new(field(0))?)?;
+            header.acc_pos = field(4);
+            header.acc_len = field(8);
+        }
+
+        self.do_flush()?;
+
+        // If we crashed after this, the new file is already in place
+        if std::path::Path::new(&tmp_path).exists() {
+            std::fs::rename(&tmp_path, &path)?;
+        }
+
+        self.accumulator_file = Self::open_data_file(&path)?;
+        std::fs::remove_file(&journal_path)?;
+
+        Ok(())
+    }
+
+    /// Removes the undo data for blocks buried more than `policy.fork_depth` blocks below
+    /// `best_height`, since we won't reorg them anymore. Returns for how many heights we've
+    /// removed it.
+    ///
+    /// We copy the records we keep into `undo.bin.tmp`, and rename it over the old file once
+    /// it's complete. The positions in our index don't change, so a crash at any point leaves
+    /// either the old file or the new one.
+    fn prune_undo(
+        &mut self,
+        policy: &CompactionPolicy,
+        best_height: u32,
+    ) -> Result<u32, FlatChainstoreError> {
+        let (start, end) = self.get_undo_bounds()?;
+        let index = std::fs::read(format!("{}/undo_index.bin", self.path))?;
+
+        // Records are appended by height, so the first one we keep is where the new file starts
+        let mut new_start = end;
+        let mut removed = 0;
+        for (height, entry) in index
+            .chunks_exact(UNDO_INDEX_ENTRY_SIZE as usize)
+            .enumerate()
+        {
+            let pos = u64::from_le_bytes(entry[0..8].try_into().expect("slice has 8 bytes"));
+            let len = u32::from_le_bytes(entry[8..12].try_into().expect("slice has 4 bytes"));
+            if len == 0 || pos < start {
+                continue;
+            }
+
+            if policy.keep_undo(height as u32, best_height) {
+                new_start = pos;
+                break;
+            }
+
+            removed += 1;
+        }
+
+        if removed == 0 {
+            return Ok(0);
+        }
+
+        let path = format!("{}/undo.bin", self.path);
+        let tmp_path = format!("{}/undo.bin.tmp", self.path);
+        let mut new_file = File::create(&tmp_path)?;
+        new_file.write_all(&new_start.to_le_bytes())?;
+
+        self.undo_file
+            .seek(SeekFrom::Start(new_start - start + UNDO_HEADER_SIZE))?;
+        std::io::copy(&mut self.undo_file, &mut new_file)?;
+        new_file.sync_all()?;
+
+        std::fs::rename(&tmp_path, &path)?;
+        self.undo_file = Self::open_undo_file(&path)?;
+
+        Ok(removed)
+    }
+
     unsafe fn do_flush(&mut self) -> Result<(), FlatChainstoreError> {
         self.headers.flush()?;
         self.block_index.flush()?;
@@ -1102,12 +1654,16 @@ impl ChainStore for FlatChainStore {
             // this is probably a reorg, truncate the file up to the previous block height
             let header = unsafe { self.get_disk_header(index)? };
 
-            // this is where the new acc starts, truncating the file to this position
-            let pos = header.acc_pos as u64;
+            // If we don't have roots for this height (e.g. they were removed by a compaction), we
+            // don't know where to truncate, so we just append
+            if header.acc_len != 0 {
+                // this is where the new acc starts, truncating the file to this position
+                let pos = header.acc_pos as u64;
 
-            self.accumulator_file
-                .set_len(pos)
-                .map_err(FlatChainstoreError::Io)?;
+                self.accumulator_file
+                    .set_len(pos)
+                    .map_err(FlatChainstoreError::Io)?;
+            }
         }
 
         let pos = self.accumulator_file.seek(SeekFrom::End(0))?;
@@ -1154,6 +1710,81 @@ impl ChainStore for FlatChainStore {
         Ok(Some(roots))
     }
 
+    fn save_fee_estimates(&mut self, estimates: Vec<u8>) -> Result<(), Self::Error> {
+        self.fee_estimates_file.set_len(0)?;
+        self.fee_estimates_file.seek(SeekFrom::Start(0))?;
+
+        self.fee_estimates_file.write_all(&estimates)?;
+        self.fee_estimates_file.flush()?;
+
+        Ok(())
+    }
+
+    fn load_fee_estimates(&mut self) -> Result<Option<Vec<u8>>, Self::Error> {
+        let mut estimates = Vec::new();
+
+        self.fee_estimates_file.seek(SeekFrom::Start(0))?;
+        self.fee_estimates_file.read_to_end(&mut estimates)?;
+
+        if estimates.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(estimates))
+    }
+
+    fn save_undo_for_block(&mut self, undo: Vec<u8>, height: u32) -> Result<(), Self::Error> {
+        // If we already have undo data for this height, this is a reorg. Everything from this
+        // height up belongs to the old chain, so we can drop it.
+        let (start, _) = self.get_undo_bounds()?;
+        if let Some((pos, len)) = self.get_undo_entry(height)? {
+            // If this record was pruned, every record we still have came after it
+            if len != 0 {
+                self.undo_file
+                    .set_len(pos.max(start) - start + UNDO_HEADER_SIZE)?;
+            }
+
+            self.undo_index_file
+                .set_len(height as u64 * UNDO_INDEX_ENTRY_SIZE)?;
+        }
+
+        let (_, pos) = self.get_undo_bounds()?;
+        self.undo_file.seek(SeekFrom::End(0))?;
+        self.undo_file.write_all(&undo)?;
+        self.undo_file.flush()?;
+
+        let mut entry = Vec::with_capacity(UNDO_INDEX_ENTRY_SIZE as usize);
+        entry.extend_from_slice(&pos.to_le_bytes());
+        entry.extend_from_slice(&(undo.len() as u32).to_le_bytes());
+
+        // Heights we skipped (e.g. assumed blocks) are left as zeroes, meaning no undo data
+        self.undo_index_file
+            .seek(SeekFrom::Start(height as u64 * UNDO_INDEX_ENTRY_SIZE))?;
+        self.undo_index_file.write_all(&entry)?;
+        self.undo_index_file.flush()?;
+
+        Ok(())
+    }
+
+    fn load_undo_for_block(&mut self, height: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        let Some((pos, len)) = self.get_undo_entry(height)? else {
+            return Ok(None);
+        };
+
+        // Pruned records are before the start of our file
+        let (start, end) = self.get_undo_bounds()?;
+        if len == 0 || pos < start || pos + len as u64 > end {
+            return Ok(None);
+        }
+
+        let mut undo = vec![0; len as usize];
+        self.undo_file
+            .seek(SeekFrom::Start(pos - start + UNDO_HEADER_SIZE))?;
+        self.undo_file.read_exact(&mut undo)?;
+
+        Ok(Some(undo))
+    }
+
     fn get_header(&self, block_hash: &BlockHash) -> Result<Option<DiskBlockHeader>, Self::Error> {
         let mut cache = self.get_cache_mut()?;
 
@@ -1225,6 +1856,30 @@ impl ChainStore for FlatChainStore {
 
         unsafe { self.add_index_entry(hash, index) }
     }
+
+    fn compact(
+        &mut self,
+        policy: &CompactionPolicy,
+        best_height: u32,
+        validation_height: u32,
+    ) -> Result<CompactionStats, Self::Error> {
+        let fork_headers = unsafe { self.compact_fork_headers(policy, best_height)? };
+        let roots = unsafe { self.compact_roots(policy, best_height, validation_height)? };
+        let undo = self.prune_undo(policy, best_height)?;
+
+        // Our cache may have some of the headers we've removed
+        if fork_headers != 0 {
+            self.get_cache_mut()?.clear();
+        }
+
+        unsafe { self.do_flush()? };
+
+        Ok(CompactionStats {
+            fork_headers,
+            roots,
+            undo,
+        })
+    }
 }
 
 pub mod migrate_v0_to_v1 {
@@ -1331,6 +1986,7 @@ mod tests {
     use bitcoin::Block;
     use bitcoin::BlockHash;
     use bitcoin::Network;
+    use bitcoin::Work;
     use floresta_common::bhash;
     use tempfile::TempDir;
     use xxhash_rust::xxh3;
@@ -1341,17 +1997,20 @@ mod tests {
     use super::Index;
     use super::FLAT_CHAINSTORE_MAGIC;
     use super::FLAT_CHAINSTORE_VERSION;
+    use super::UNDO_HEADER_SIZE;
     use crate::migrate_v0_to_v1::init_mmap;
     use crate::migrate_v0_to_v1::maybe_migrate;
-    use crate::pruned_utreexo::flat_chain_store::FileChecksum;
     use crate::pruned_utreexo::flat_chain_store::Metadata;
     use crate::pruned_utreexo::UpdatableChainstate;
     use crate::AssumeValidArg;
     use crate::BestChain;
+    use crate::ChainParams;
     use crate::ChainState;
     use crate::ChainStore;
+    use crate::CompactionPolicy;
     use crate::DbCheckSum;
     use crate::DiskBlockHeader;
+    use crate::FileChecksum;
 
     #[test]
     fn test_truncate_pow2() {
@@ -1633,6 +2292,110 @@ mod tests {
         assert_eq!(recovered, height);
     }
 
+    #[test]
+    fn test_save_fee_estimates() {
+        let test_id = rand::random::<u64>();
+        let mut store = get_test_chainstore(Some(test_id)).unwrap();
+
+        assert_eq!(store.load_fee_estimates().unwrap(), None);
+
+        store.save_fee_estimates(vec![1; 100]).unwrap();
+        // A shorter state must fully replace the previous one
+        store.save_fee_estimates(vec![2; 10]).unwrap();
+        assert_eq!(store.load_fee_estimates().unwrap(), Some(vec![2; 10]));
+
+        // Reopen the store and check that we still have the estimates
+        drop(store);
+        let mut store = get_test_chainstore(Some(test_id)).unwrap();
+        assert_eq!(store.load_fee_estimates().unwrap(), Some(vec![2; 10]));
+    }
+
+    #[test]
+    fn test_save_undo_data() {
+        let test_id = rand::random::<u64>();
+        let mut store = get_test_chainstore(Some(test_id)).unwrap();
+
+        assert_eq!(store.load_undo_for_block(1).unwrap(), None);
+
+        for height in 1..=5 {
+            store
+                .save_undo_for_block(vec![height as u8; 10], height)
+                .unwrap();
+        }
+
+        // A gap in the heights is left without undo data
+        store.save_undo_for_block(vec![7; 10], 7).unwrap();
+        assert_eq!(store.load_undo_for_block(6).unwrap(), None);
+        assert_eq!(store.load_undo_for_block(7).unwrap(), Some(vec![7; 10]));
+
+        // Replacing height 3 (e.g. a reorg) drops the undo data for all blocks after it
+        store.save_undo_for_block(vec![33; 4], 3).unwrap();
+        assert_eq!(store.load_undo_for_block(2).unwrap(), Some(vec![2; 10]));
+        assert_eq!(store.load_undo_for_block(3).unwrap(), Some(vec![33; 4]));
+        assert_eq!(store.load_undo_for_block(4).unwrap(), None);
+        assert_eq!(store.load_undo_for_block(7).unwrap(), None);
+
+        // Reopen the store and check that we still have the undo data
+        drop(store);
+        let mut store = get_test_chainstore(Some(test_id)).unwrap();
+        assert_eq!(store.load_undo_for_block(1).unwrap(), Some(vec![1; 10]));
+        assert_eq!(store.load_undo_for_block(3).unwrap(), Some(vec![33; 4]));
+    }
+
+    #[test]
+    fn test_prune_undo_data() {
+        let test_id = rand::random::<u64>();
+        let mut store = get_test_chainstore(Some(test_id)).unwrap();
+
+        for height in 0..20 {
+            store
+                .save_undo_for_block(vec![height as u8; 10], height)
+                .unwrap();
+        }
+
+        let policy = CompactionPolicy {
+            fork_depth: 5,
+            roots_interval: 5,
+            roots_window: 3,
+        };
+
+        let stats = store.compact(&policy, 19, 19).unwrap();
+        assert_eq!(stats.undo, 14);
+        assert_eq!(
+            store.undo_file.metadata().unwrap().len(),
+            UNDO_HEADER_SIZE + 60
+        );
+
+        for height in 0..20 {
+            let undo = store.load_undo_for_block(height).unwrap();
+            match policy.keep_undo(height, 19) {
+                true => assert_eq!(undo, Some(vec![height as u8; 10])),
+                false => assert_eq!(undo, None),
+            }
+        }
+
+        // Pruning again doesn't remove anything else
+        let stats = store.compact(&policy, 19, 19).unwrap();
+        assert_eq!(stats.undo, 0);
+
+        // New records go after the ones we kept, even after reopening the store
+        drop(store);
+        let mut store = get_test_chainstore(Some(test_id)).unwrap();
+        store.save_undo_for_block(vec![20; 10], 20).unwrap();
+        assert_eq!(store.load_undo_for_block(20).unwrap(), Some(vec![20; 10]));
+        assert_eq!(store.load_undo_for_block(19).unwrap(), Some(vec![19; 10]));
+
+        // Replacing a pruned height drops everything after it
+        store.save_undo_for_block(vec![10; 4], 10).unwrap();
+        assert_eq!(store.load_undo_for_block(10).unwrap(), Some(vec![10; 4]));
+        assert_eq!(store.load_undo_for_block(14).unwrap(), None);
+        assert_eq!(store.load_undo_for_block(20).unwrap(), None);
+        assert_eq!(
+            store.undo_file.metadata().unwrap().len(),
+            UNDO_HEADER_SIZE + 4
+        );
+    }
+
     #[test]
     fn test_index() {
         let mut store = get_test_chainstore(None).unwrap();
@@ -1669,7 +2432,10 @@ mod tests {
         let file = include_bytes!("../../testdata/headers.zst");
         let uncompressed: Vec<u8> = zstd::decode_all(std::io::Cursor::new(file)).unwrap();
         let store = get_test_chainstore(None).unwrap();
-        let chain = ChainState::new(store, Network::Bitcoin, AssumeValidArg::Hardcoded);
+        // These headers don't have the minimum chain work
+        let mut params = ChainParams::from(Network::Bitcoin);
+        params.minimum_chain_work = Work::from_be_bytes([0; 32]);
+        let chain = ChainState::new(store, params, AssumeValidArg::Hardcoded);
         let mut buffer = uncompressed.as_slice();
 
         while let Ok(header) = Header::consensus_decode(&mut buffer) {
@@ -1683,7 +2449,9 @@ mod tests {
         let file = include_bytes!("../../testdata/signet_headers.zst");
         let uncompressed: Vec<u8> = zstd::decode_all(std::io::Cursor::new(file)).unwrap();
         let store = get_test_chainstore(None).unwrap();
-        let chain = ChainState::new(store, Network::Signet, AssumeValidArg::Hardcoded);
+        let mut params = ChainParams::from(Network::Signet);
+        params.minimum_chain_work = Work::from_be_bytes([0; 32]);
+        let chain = ChainState::new(store, params, AssumeValidArg::Hardcoded);
         let mut buffer = uncompressed.as_slice();
 
         while let Ok(header) = Header::consensus_decode(&mut buffer) {
@@ -1731,6 +2499,227 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_compact() {
+        let mut store = get_test_chainstore(None).unwrap();
+        let file = include_str!("../../testdata/blocks.txt");
+        let blocks = file
+            .lines()
+            .take(20)
+            .map(|x| deserialize::<Block>(&hex::decode(x).unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        for (height, block) in blocks.iter().enumerate() {
+            let height = height as u32;
+            store
+                .save_header(&DiskBlockHeader::FullyValid(block.header, height))
+                .unwrap();
+            store
+                .update_block_index(height, block.block_hash())
+                .unwrap();
+            store
+                .save_roots_for_block(vec![height as u8; 4], height)
+                .unwrap();
+        }
+
+        // Creates a branch of two headers, leaving our main chain after `fork_height`
+        let mut make_fork = |fork_height: u32| {
+            let mut prev_hash = blocks[fork_height as usize].block_hash();
+            let mut hashes = Vec::new();
+            for height in (fork_height + 1)..=(fork_height + 2) {
+                let mut header = blocks[height as usize].header;
+                header.prev_blockhash = prev_hash;
+                header.nonce = header.nonce.wrapping_add(1);
+
+                store
+                    .save_header(&DiskBlockHeader::InFork(header, height))
+                    .unwrap();
+
+                prev_hash = header.block_hash();
+                hashes.push(prev_hash);
+            }
+
+            hashes
+        };
+
+        let old_fork = make_fork(2);
+        let recent_fork = make_fork(16);
+
+        let policy = CompactionPolicy {
+            fork_depth: 5,
+            roots_interval: 5,
+            roots_window: 3,
+        };
+
+        let stats = store.compact(&policy, 19, 19).unwrap();
+        assert_eq!(stats.fork_headers, 2);
+        assert_eq!(stats.roots, 12);
+
+        for hash in old_fork {
+            assert!(store.get_header(&hash).unwrap().is_none());
+        }
+
+        for hash in recent_fork {
+            assert_eq!(store.get_header(&hash).unwrap().unwrap().block_hash(), hash);
+        }
+
+        for (height, block) in blocks.iter().enumerate() {
+            let height = height as u32;
+            let header = store.get_header(&block.block_hash()).unwrap().unwrap();
+            assert_eq!(header.block_hash(), block.block_hash());
+
+            let roots = store.load_roots_for_block(height).unwrap();
+            match policy.keep_roots(height, 19) {
+                true => assert_eq!(roots, Some(vec![height as u8; 4])),
+                false => assert_eq!(roots, None),
+            }
+        }
+
+        // Compacting again doesn't remove anything else
+        let stats = store.compact(&policy, 19, 19).unwrap();
+        assert_eq!(stats.fork_headers, 0);
+        assert_eq!(stats.roots, 0);
+    }
+
+    #[test]
+    fn test_interrupted_compaction() {
+        let test_id = rand::random::<u64>();
+        let mut store = get_test_chainstore(Some(test_id)).unwrap();
+        let file = include_str!("../../testdata/blocks.txt");
+        let blocks = file
+            .lines()
+            .take(20)
+            .map(|x| deserialize::<Block>(&hex::decode(x).unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        for (height, block) in blocks.iter().enumerate() {
+            let height = height as u32;
+            store
+                .save_header(&DiskBlockHeader::FullyValid(block.header, height))
+                .unwrap();
+            store
+                .update_block_index(height, block.block_hash())
+                .unwrap();
+            store
+                .save_roots_for_block(vec![height as u8; 4], height)
+                .unwrap();
+        }
+        store.flush().unwrap();
+
+        let policy = CompactionPolicy {
+            fork_depth: 5,
+            roots_interval: 5,
+            roots_window: 3,
+        };
+
+        // We crash after writing the new file and the journal, but before using them
+        let removed = unsafe { store.write_compacted_roots(&policy, 19, 19).unwrap() };
+        assert_eq!(removed, 12);
+        drop(store);
+
+        let mut store = get_test_chainstore(Some(test_id)).unwrap();
+        store.check_integrity().unwrap();
+        for height in 0..20 {
+            let roots = store.load_roots_for_block(height).unwrap();
+            match policy.keep_roots(height, 19) {
+                true => assert_eq!(roots, Some(vec![height as u8; 4])),
+                false => assert_eq!(roots, None),
+            }
+        }
+
+        // A new block appends its roots to the compacted file
+        store.save_roots_for_block(vec![42; 4], 19).unwrap();
+        assert_eq!(store.load_roots_for_block(19).unwrap(), Some(vec![42; 4]));
+        assert_eq!(store.load_roots_for_block(15).unwrap(), Some(vec![15; 4]));
+    }
+
+    #[test]
+    fn test_interrupted_fork_compaction() {
+        let test_id = rand::random::<u64>();
+        let mut store = get_test_chainstore(Some(test_id)).unwrap();
+        let file = include_str!("../../testdata/blocks.txt");
+        let blocks = file
+            .lines()
+            .take(20)
+            .map(|x| deserialize::<Block>(&hex::decode(x).unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        for (height, block) in blocks.iter().enumerate() {
+            let height = height as u32;
+            store
+                .save_header(&DiskBlockHeader::FullyValid(block.header, height))
+                .unwrap();
+            store
+                .update_block_index(height, block.block_hash())
+                .unwrap();
+        }
+
+        // Creates a branch of two headers, leaving our main chain after `fork_height`
+        let mut make_fork = |fork_height: u32| {
+            let mut prev_hash = blocks[fork_height as usize].block_hash();
+            let mut hashes = Vec::new();
+            for height in (fork_height + 1)..=(fork_height + 2) {
+                let mut header = blocks[height as usize].header;
+                header.prev_blockhash = prev_hash;
+                header.nonce = header.nonce.wrapping_add(1);
+
+                store
+                    .save_header(&DiskBlockHeader::InFork(header, height))
+                    .unwrap();
+
+                prev_hash = header.block_hash();
+                hashes.push(prev_hash);
+            }
+
+            hashes
+        };
+
+        let old_fork = make_fork(2);
+        let recent_fork = make_fork(16);
+        store.flush().unwrap();
+
+        let policy = CompactionPolicy {
+            fork_depth: 5,
+            roots_interval: 5,
+            roots_window: 3,
+        };
+
+        let check_store = |store: &FlatChainStore| {
+            store.check_integrity().unwrap();
+            assert_eq!(unsafe { store.get_metadata().unwrap().fork_count }, 2);
+
+            for hash in old_fork.iter() {
+                assert!(store.get_header(hash).unwrap().is_none());
+            }
+
+            for hash in recent_fork.iter() {
+                assert_eq!(store.get_header(hash).unwrap().unwrap().block_hash(), *hash);
+            }
+
+            for block in blocks.iter() {
+                let header = store.get_header(&block.block_hash()).unwrap().unwrap();
+                assert_eq!(header.block_hash(), block.block_hash());
+            }
+        };
+
+        // We crash after writing the journal, but before touching the fork file
+        let removed = unsafe { store.write_fork_journal(&policy, 19).unwrap() };
+        assert_eq!(removed, 2);
+        let journal_path = format!("{}/fork_headers.journal", store.path);
+        let journal = std::fs::read(&journal_path).unwrap();
+        drop(store);
+
+        let store = get_test_chainstore(Some(test_id)).unwrap();
+        check_store(&store);
+
+        // We crash after moving the headers, but before removing the journal
+        std::fs::write(&journal_path, journal).unwrap();
+        drop(store);
+
+        let store = get_test_chainstore(Some(test_id)).unwrap();
+        check_store(&store);
+    }
+
     #[test]
     fn test_recover_acc() {
         let test_id = rand::random::<u64>();
@@ -0,0 +1,565 @@
+//! Headers presync, a port of Bitcoin Core's `HeadersSyncState`.
+//!
+//! A chain of headers is cheap to make if it doesn't need much work, so we don't want to save a
+//! peer's headers before knowing that its chain has the minimum chain work (see
+//! [ChainParams::minimum_chain_work](crate::ChainParams::minimum_chain_work)). We can't keep
+//! every header in memory either, that's just as easy to abuse. Instead, we sync a peer's chain
+//! twice:
+//!
+//! 1. Presync: we download the headers and only check them in isolation, keeping the running
+//!    chain work and a one-bit commitment to one header in every [HEADER_COMMITMENT_PERIOD]. The
+//!    commitments use a secret salt, so the peer can't tell which headers we commit to.
+//! 2. Redownload: once the chain has enough work, we ask for the same headers again. Each one
+//!    must match our commitments, and we only release them for validation once there are
+//!    [REDOWNLOAD_BUFFER_SIZE] headers after them (or the minimum chain work was reached again).
+//!
+//! To feed us a different chain in the second pass, a peer would have to guess our commitments.
+//! Each wrong guess ends the sync, so the chance of getting [REDOWNLOAD_BUFFER_SIZE] headers past
+//! them is negligible.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+
+use bitcoin::block::Header as BlockHeader;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::params::Params;
+use bitcoin::BlockHash;
+use bitcoin::CompactTarget;
+use bitcoin::Target;
+use bitcoin::Work;
+
+use crate::prelude::*;
+
+/// We commit to one header in every this many, the value used by Bitcoin Core 24
+pub const HEADER_COMMITMENT_PERIOD: u32 = 584;
+
+/// How many redownloaded headers we keep before releasing them, the value used by Bitcoin Core 24
+pub const REDOWNLOAD_BUFFER_SIZE: usize = 13_959;
+
+/// How far in the future a block's timestamp may be
+const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+
+/// The maximum number of headers in a `headers` message. Anything shorter tells us the peer has
+/// no more headers to send.
+pub const MAX_HEADERS_RESULTS: usize = 2_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where we are in a [HeadersSyncState]
+pub enum HeadersSyncPhase {
+    /// Downloading the chain for the first time, only storing commitments
+    Presync,
+    /// Downloading the chain again, checking it against our commitments
+    Redownload,
+    /// We are done, either because the sync ended or because it failed
+    Final,
+}
+
+#[derive(Debug, Default)]
+/// What came out of [HeadersSyncState::process_next_headers]
+pub struct HeadersSyncResult {
+    /// Headers that passed the presync, and can now be validated and saved
+    pub headers: Vec<BlockHeader>,
+    /// Whether the headers we got were fine
+    pub success: bool,
+    /// Whether we should ask this peer for more headers, see
+    /// [HeadersSyncState::next_headers_request_locator]
+    pub request_more: bool,
+}
+
+#[derive(Debug, Clone)]
+/// The presync of a peer's chain, see the module documentation
+pub struct HeadersSyncState {
+    /// The block this peer's chain builds on, that we already have
+    chain_start: BlockHeader,
+    /// The height of `chain_start`
+    chain_start_height: u32,
+    /// The chain work up to `chain_start`
+    chain_start_work: Work,
+    /// A locator for `chain_start`, appended to our requests
+    chain_start_locator: Vec<BlockHash>,
+    /// The work this chain needs before we accept it
+    minimum_work: Work,
+    /// The consensus parameters of our network
+    params: Params,
+    /// The salt of our commitments
+    salt: u64,
+    /// We commit to headers at heights where `height % HEADER_COMMITMENT_PERIOD` is this
+    commit_offset: u32,
+    /// How many commitments a chain can have, based on how much time passed since
+    /// `chain_start`. A peer can't keep us presyncing forever.
+    max_commitments: usize,
+    /// Our commitments, the oldest first
+    commitments: VecDeque<bool>,
+    /// What we are doing now
+    phase: HeadersSyncPhase,
+
+    /// The last header we've got during presync
+    last_header: BlockHeader,
+    /// The height of `last_header`
+    current_height: u32,
+    /// The chain work up to `last_header`
+    current_work: Work,
+
+    /// Headers we've redownloaded, but can't release yet
+    redownloaded_headers: VecDeque<BlockHeader>,
+    /// The parent of the first header in `redownloaded_headers`
+    redownload_first_prev_hash: BlockHash,
+    /// The last header we've redownloaded
+    redownload_last_header: BlockHeader,
+    /// The height of `redownload_last_header`
+    redownload_last_height: u32,
+    /// The chain work up to `redownload_last_header`
+    redownload_work: Work,
+    /// Whether the redownloaded chain reached the minimum work, so every header can be released
+    process_all_remaining_headers: bool,
+}
+
+impl HeadersSyncState {
+    /// Starts a presync for a chain building on `chain_start`, a block we have at
+    /// `chain_start_height` with `chain_start_work`. `chain_start_mtp` is its median time past,
+    /// `now` our current time, and `salt` must be random and secret.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        chain_start: BlockHeader,
+        chain_start_height: u32,
+        chain_start_work: Work,
+        chain_start_mtp: u32,
+        chain_start_locator: Vec<BlockHash>,
+        minimum_work: Work,
+        params: Params,
+        now: u32,
+        salt: u64,
+    ) -> Self {
+        // Like Core, we assume there can't be more than 6 blocks per second since `chain_start`.
+        // That's a lot more than the real chain, but we only keep one bit per period.
+        let max_seconds = now.saturating_sub(chain_start_mtp) as u64 + MAX_FUTURE_BLOCK_TIME as u64;
+        let max_commitments = (6 * max_seconds / HEADER_COMMITMENT_PERIOD as u64) as usize;
+
+        HeadersSyncState {
+            chain_start,
+            chain_start_height,
+            chain_start_work,
+            chain_start_locator,
+            minimum_work,
+            params,
+            salt,
+            commit_offset: (salt % HEADER_COMMITMENT_PERIOD as u64) as u32,
+            max_commitments,
+            commitments: VecDeque::new(),
+            phase: HeadersSyncPhase::Presync,
+            last_header: chain_start,
+            current_height: chain_start_height,
+            current_work: chain_start_work,
+            redownloaded_headers: VecDeque::new(),
+            redownload_first_prev_hash: chain_start.block_hash(),
+            redownload_last_header: chain_start,
+            redownload_last_height: chain_start_height,
+            redownload_work: chain_start_work,
+            process_all_remaining_headers: false,
+        }
+    }
+
+    /// Returns what we are doing now
+    pub fn phase(&self) -> HeadersSyncPhase {
+        self.phase
+    }
+
+    /// Returns the height of the last header we've got from this peer, in either phase
+    pub fn current_height(&self) -> u32 {
+        match self.phase {
+            HeadersSyncPhase::Redownload => self.redownload_last_height,
+            _ => self.current_height,
+        }
+    }
+
+    /// How many redownloaded headers we are holding in memory
+    pub fn buffered_headers(&self) -> usize {
+        self.redownloaded_headers.len()
+    }
+
+    /// Processes the headers in a `headers` message. `full_message` tells whether the message
+    /// had [MAX_HEADERS_RESULTS] headers, otherwise the peer has nothing more to send.
+    ///
+    /// If the result isn't successful, or we shouldn't request more headers, this sync is over.
+    pub fn process_next_headers(
+        &mut self,
+        headers: &[BlockHeader],
+        full_message: bool,
+    ) -> HeadersSyncResult {
+        let mut result = HeadersSyncResult::default();
+        if headers.is_empty() || self.phase == HeadersSyncPhase::Final {
+            return result;
+        }
+
+        match self.phase {
+            HeadersSyncPhase::Presync => {
+                result.success = self.validate_and_store_commitments(headers);
+                if result.success {
+                    // If we got a short message, the peer's chain ended without enough work
+                    result.request_more =
+                        full_message || self.phase == HeadersSyncPhase::Redownload;
+                }
+            }
+            HeadersSyncPhase::Redownload => {
+                result.success = headers
+                    .iter()
+                    .all(|header| self.validate_and_store_redownloaded(header));
+
+                if result.success {
+                    result.headers = self.pop_headers_ready_for_acceptance();
+
+                    if self.redownloaded_headers.is_empty() && self.process_all_remaining_headers {
+                        // We are done, the caller can sync the rest of this chain normally
+                    } else if full_message {
+                        result.request_more = true;
+                    } else {
+                        // The peer stopped sending headers before the chain had enough work,
+                        // which is different from what it sent us the first time
+                        result.success = false;
+                    }
+                }
+            }
+            HeadersSyncPhase::Final => {}
+        }
+
+        if !(result.success && result.request_more) {
+            self.finalize();
+        }
+
+        result
+    }
+
+    /// Returns the locator we should send in our next `getheaders` to this peer
+    pub fn next_headers_request_locator(&self) -> Vec<BlockHash> {
+        let tip = match self.phase {
+            HeadersSyncPhase::Presync => self.last_header.block_hash(),
+            HeadersSyncPhase::Redownload => self.redownload_last_header.block_hash(),
+            HeadersSyncPhase::Final => return Vec::new(),
+        };
+
+        let mut locator = vec![tip];
+        locator.extend(self.chain_start_locator.iter().copied());
+        locator
+    }
+
+    /// Checks headers during presync, committing to the ones at our commitment offset
+    fn validate_and_store_commitments(&mut self, headers: &[BlockHeader]) -> bool {
+        // Headers must continue the chain we've seen so far
+        if headers[0].prev_blockhash != self.last_header.block_hash() {
+            return false;
+        }
+
+        for header in headers {
+            if !self.validate_and_process_single_header(header) {
+                return false;
+            }
+        }
+
+        if self.current_work >= self.minimum_work {
+            self.redownloaded_headers.clear();
+            self.redownload_first_prev_hash = self.chain_start.block_hash();
+            self.redownload_last_header = self.chain_start;
+            self.redownload_last_height = self.chain_start_height;
+            self.redownload_work = self.chain_start_work;
+            self.phase = HeadersSyncPhase::Redownload;
+        }
+
+        true
+    }
+
+    /// Checks one presync header
+    fn validate_and_process_single_header(&mut self, header: &BlockHeader) -> bool {
+        let next_height = self.current_height + 1;
+
+        if header.prev_blockhash != self.last_header.block_hash()
+            || !has_valid_pow(&self.params, header)
+            || !permitted_difficulty_transition(
+                &self.params,
+                next_height,
+                self.last_header.bits,
+                header.bits,
+            )
+        {
+            return false;
+        }
+
+        if next_height % HEADER_COMMITMENT_PERIOD == self.commit_offset {
+            self.commitments
+                .push_back(self.commitment(&header.block_hash()));
+            if self.commitments.len() > self.max_commitments {
+                // This chain is longer than what could've been mined since chain_start
+                return false;
+            }
+        }
+
+        self.current_work = self.current_work + header.work();
+        self.last_header = *header;
+        self.current_height = next_height;
+
+        true
+    }
+
+    /// Checks a redownloaded header against our commitments, and buffers it
+    fn validate_and_store_redownloaded(&mut self, header: &BlockHeader) -> bool {
+        if header.prev_blockhash != self.redownload_last_header.block_hash() {
+            return false;
+        }
+
+        let next_height = self.redownload_last_height + 1;
+        if !has_valid_pow(&self.params, header)
+            || !permitted_difficulty_transition(
+                &self.params,
+                next_height,
+                self.redownload_last_header.bits,
+                header.bits,
+            )
+        {
+            return false;
+        }
+
+        self.redownload_work = self.redownload_work + header.work();
+        if self.redownload_work >= self.minimum_work {
+            self.process_all_remaining_headers = true;
+        }
+
+        // After the minimum work, we don't need commitments anymore
+        if !self.process_all_remaining_headers
+            && next_height % HEADER_COMMITMENT_PERIOD == self.commit_offset
+        {
+            let expected = match self.commitments.pop_front() {
+                Some(expected) => expected,
+                None => return false,
+            };
+
+            if self.commitment(&header.block_hash()) != expected {
+                return false;
+            }
+        }
+
+        self.redownloaded_headers.push_back(*header);
+        self.redownload_last_header = *header;
+        self.redownload_last_height = next_height;
+
+        true
+    }
+
+    /// Releases the headers that are buried deep enough in our buffer, or every header if we've
+    /// reached the minimum work
+    fn pop_headers_ready_for_acceptance(&mut self) -> Vec<BlockHeader> {
+        let mut headers = Vec::new();
+        while self.redownloaded_headers.len() > REDOWNLOAD_BUFFER_SIZE
+            || (!self.redownloaded_headers.is_empty() && self.process_all_remaining_headers)
+        {
+            let header = self
+                .redownloaded_headers
+                .pop_front()
+                .expect("we just checked it's not empty");
+
+            self.redownload_first_prev_hash = header.block_hash();
+            headers.push(header);
+        }
+
+        headers
+    }
+
+    /// Our one-bit commitment to a header
+    fn commitment(&self, hash: &BlockHash) -> bool {
+        let mut data = [0; 40];
+        data[..8].copy_from_slice(&self.salt.to_le_bytes());
+        data[8..].copy_from_slice(hash.as_byte_array());
+
+        sha256::Hash::hash(&data).to_byte_array()[0] & 1 == 1
+    }
+
+    /// Frees our memory, this sync is over
+    fn finalize(&mut self) {
+        self.commitments = VecDeque::new();
+        self.redownloaded_headers = VecDeque::new();
+        self.phase = HeadersSyncPhase::Final;
+    }
+}
+
+/// Whether a header's hash meets its own target, and that target isn't above our network's limit
+fn has_valid_pow(params: &Params, header: &BlockHeader) -> bool {
+    let target = header.target();
+    target <= params.max_attainable_target && header.validate_pow(target).is_ok()
+}
+
+/// Whether the difficulty may go from `old_bits` to `new_bits` at `height`, without knowing the
+/// timestamps of previous blocks. Like Core's `PermittedDifficultyTransition`, the difficulty may
+/// only change at a retarget, by a factor of four at most. Networks that allow minimum difficulty
+/// blocks accept anything.
+pub fn permitted_difficulty_transition(
+    params: &Params,
+    height: u32,
+    old_bits: CompactTarget,
+    new_bits: CompactTarget,
+) -> bool {
+    if params.allow_min_difficulty_blocks {
+        return true;
+    }
+
+    if height as u64 % params.difficulty_adjustment_interval() != 0 {
+        return old_bits == new_bits;
+    }
+
+    // Compact targets are rounded down, so we round the bounds the same way
+    let old_target = Target::from_compact(old_bits);
+    let max = Target::from_compact(
+        old_target
+            .max_transition_threshold(params)
+            .to_compact_lossy(),
+    );
+    let min = Target::from_compact(old_target.min_transition_threshold().to_compact_lossy());
+    let new_target = Target::from_compact(new_bits);
+
+    new_target <= max && new_target >= min
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitcoin::block::Header as BlockHeader;
+    use bitcoin::consensus::Decodable;
+    use bitcoin::constants::genesis_block;
+    use bitcoin::params::Params;
+    use bitcoin::Network;
+    use bitcoin::Work;
+
+    use super::HeadersSyncPhase;
+    use super::HeadersSyncState;
+    use super::MAX_HEADERS_RESULTS;
+    use crate::prelude::*;
+
+    /// The first 10,237 mainnet headers, without genesis
+    fn get_headers() -> Vec<BlockHeader> {
+        let file = include_bytes!("../../testdata/headers.zst");
+        let uncompressed: Vec<u8> = zstd::decode_all(Cursor::new(file)).unwrap();
+        let mut buffer = uncompressed.as_slice();
+
+        let mut headers = Vec::new();
+        while let Ok(header) = BlockHeader::consensus_decode(&mut buffer) {
+            headers.push(header);
+        }
+
+        headers.remove(0);
+        headers
+    }
+
+    /// Starts a presync from genesis, that needs the work of `headers`
+    fn start_sync(headers: &[BlockHeader], salt: u64) -> HeadersSyncState {
+        let genesis = genesis_block(Network::Bitcoin).header;
+        let minimum_work = headers
+            .iter()
+            .fold(Work::from_be_bytes([0; 32]), |work, header| {
+                work + header.work()
+            });
+
+        HeadersSyncState::new(
+            genesis,
+            0,
+            Work::from_be_bytes([0; 32]),
+            genesis.time,
+            vec![genesis.block_hash()],
+            minimum_work,
+            Params::new(Network::Bitcoin),
+            // Two months after genesis
+            genesis.time + 60 * 24 * 60 * 60,
+            salt,
+        )
+    }
+
+    /// Feeds `headers` in full messages, returning the headers released on each call
+    fn feed(sync: &mut HeadersSyncState, headers: &[BlockHeader]) -> Vec<BlockHeader> {
+        let mut released = Vec::new();
+        for chunk in headers.chunks(MAX_HEADERS_RESULTS) {
+            let result = sync.process_next_headers(chunk, chunk.len() == MAX_HEADERS_RESULTS);
+            assert!(result.success);
+            released.extend(result.headers);
+        }
+
+        released
+    }
+
+    #[test]
+    fn test_presync_and_redownload() {
+        let headers = get_headers();
+        let mut sync = start_sync(&headers, rand::random());
+
+        // Presync doesn't release anything
+        assert!(feed(&mut sync, &headers).is_empty());
+        assert_eq!(sync.phase(), HeadersSyncPhase::Redownload);
+
+        // We ask for the same chain again, from genesis
+        let genesis = genesis_block(Network::Bitcoin).block_hash();
+        assert_eq!(sync.next_headers_request_locator(), vec![genesis, genesis]);
+
+        // The minimum work is only reached again by the last header, so everything gets
+        // released at once
+        let released = feed(&mut sync, &headers);
+        assert_eq!(released, headers);
+        assert_eq!(sync.phase(), HeadersSyncPhase::Final);
+    }
+
+    #[test]
+    fn test_redownload_buffer() {
+        let headers = get_headers();
+
+        // The first 2,000 headers have enough work, but we got more in the first message. Since
+        // the minimum is reached early in the redownload, we release as we go.
+        let mut sync = start_sync(&headers[..100], rand::random());
+        assert!(feed(&mut sync, &headers[..2_000]).is_empty());
+        assert_eq!(feed(&mut sync, &headers[..2_000]), headers[..2_000]);
+    }
+
+    #[test]
+    fn test_bad_redownload() {
+        let headers = get_headers();
+
+        // A salt with offset zero commits to the headers at multiples of 584
+        let mut sync = start_sync(&headers[..6_000], 0);
+        assert!(feed(&mut sync, &headers[..6_000]).is_empty());
+
+        // A chain that doesn't connect with what we've redownloaded so far
+        feed(&mut sync, &headers[..MAX_HEADERS_RESULTS]);
+        let result = sync.process_next_headers(
+            &headers[MAX_HEADERS_RESULTS + 1..2 * MAX_HEADERS_RESULTS],
+            true,
+        );
+        assert!(!result.success);
+        assert!(result.headers.is_empty());
+        assert_eq!(sync.phase(), HeadersSyncPhase::Final);
+    }
+
+    #[test]
+    fn test_low_work_chain() {
+        let headers = get_headers();
+
+        // The peer's chain ends before the minimum work
+        let mut sync = start_sync(&headers, rand::random());
+        let result = sync.process_next_headers(&headers[..100], false);
+        assert!(result.success);
+        assert!(!result.request_more);
+        assert_eq!(sync.phase(), HeadersSyncPhase::Final);
+
+        // Headers that don't connect to what we've seen
+        let mut sync = start_sync(&headers, rand::random());
+        let result = sync.process_next_headers(&headers[1..100], true);
+        assert!(!result.success);
+    }
+
+    #[test]
+    fn test_too_many_commitments() {
+        let headers = get_headers();
+
+        // Pretend only a few hundred blocks could have been mined since genesis
+        let mut sync = start_sync(&headers, 0);
+        sync.max_commitments = 2;
+        let result = sync.process_next_headers(&headers[..MAX_HEADERS_RESULTS], true);
+        assert!(!result.success);
+        assert_eq!(sync.phase(), HeadersSyncPhase::Final);
+    }
+}
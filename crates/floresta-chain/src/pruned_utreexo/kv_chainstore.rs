@@ -27,6 +27,7 @@ pub struct KvChainStore<'a> {
     index: Bucket<'a, Integer, Vec<u8>>,
     meta: Bucket<'a, &'a str, Vec<u8>>,
     roots: Bucket<'a, &'a str, Vec<u8>>,
+    undo: Bucket<'a, &'a str, Vec<u8>>,
     headers_cache: RwLock<HashMap<BlockHash, DiskBlockHeader>>,
     index_cache: RwLock<HashMap<u32, BlockHash>>,
 }
@@ -44,6 +45,7 @@ impl<'a> KvChainStore<'a> {
             headers: store.bucket(Some("headers"))?,
             index: store.bucket(Some("index"))?,
             roots: store.bucket(Some("roots"))?,
+            undo: store.bucket(Some("undo"))?,
             meta: store.bucket(None)?,
             _store: store,
             headers_cache: RwLock::new(HashMap::new()),
@@ -80,6 +82,20 @@ impl ChainStore for KvChainStore<'_> {
         Ok(())
     }
 
+    /// Saves the accumulator undo data for a given block.
+    fn save_undo_for_block(&mut self, undo: Vec<u8>, height: u32) -> Result<(), Self::Error> {
+        let key = format!("undo_{height}");
+        self.undo.set(&key.as_str(), &undo)?;
+
+        Ok(())
+    }
+
+    /// Loads the accumulator undo data for a given block.
+    fn load_undo_for_block(&mut self, height: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        let key = format!("undo_{height}");
+        self.undo.get(&key.as_str())
+    }
+
     /// Loads the best chain data from the metadata bucket.
     fn load_height(&self) -> Result<Option<BestChain>, Self::Error> {
         if let Some(b) = self.meta.get(&"height")? {
@@ -97,6 +113,17 @@ impl ChainStore for KvChainStore<'_> {
         Ok(())
     }
 
+    /// Saves the fee estimator state to the metadata bucket.
+    fn save_fee_estimates(&mut self, estimates: Vec<u8>) -> Result<(), Self::Error> {
+        self.meta.set(&"fee_estimates", &estimates)?;
+        Ok(())
+    }
+
+    /// Loads the fee estimator state from the metadata bucket.
+    fn load_fee_estimates(&mut self) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.meta.get(&"fee_estimates")
+    }
+
     /// Gets the block header using the provided block hash. If it is on cache, it returns it
     /// directly, otherwise it fetches it from the database.
     fn get_header(&self, block_hash: &BlockHash) -> Result<Option<DiskBlockHeader>, Self::Error> {
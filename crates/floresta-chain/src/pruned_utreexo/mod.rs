@@ -7,6 +7,7 @@
 //! - [UpdatableChainstate]: Trait defining methods for updating the chain state
 extern crate alloc;
 
+pub mod chain_snapshot;
 pub mod chain_state;
 pub mod chain_state_builder;
 pub mod chainparams;
@@ -16,10 +17,20 @@ pub mod kv_chainstore;
 #[macro_use]
 pub mod error;
 pub mod consensus;
+pub mod fee_estimator;
 #[cfg(feature = "flat-chainstore")]
 pub mod flat_chain_store;
+pub mod headers_sync;
 pub mod partial_chain;
+#[cfg(feature = "script-interpreter")]
+pub mod script_interpreter;
+#[cfg(feature = "sqlite-chainstore")]
+pub mod sqlite_chainstore;
+#[cfg(test)]
+pub(crate) mod test_utils;
 pub mod udata;
+pub mod undo;
+pub mod versionbits;
 
 use alloc::sync::Arc;
 
@@ -33,7 +44,9 @@ use rustreexo::accumulator::node_hash::BitcoinNodeHash;
 use rustreexo::accumulator::proof::Proof;
 use rustreexo::accumulator::stump::Stump;
 
+use self::headers_sync::HeadersSyncState;
 use self::partial_chain::PartialChainState;
+use self::versionbits::DeploymentInfo;
 use crate::prelude::*;
 use crate::pruned_utreexo::utxo_data::UtxoData;
 use crate::BlockConsumer;
@@ -45,6 +58,9 @@ pub trait BlockchainInterface {
     type Error: Error + Send + Sync + 'static;
     /// Returns the block with a given height in our current tip.
     fn get_block_hash(&self, height: u32) -> Result<bitcoin::BlockHash, Self::Error>;
+    /// Returns the median time past (MTP) of the block with a given height in our current tip,
+    /// as defined by BIP 113.
+    fn get_median_time_past(&self, height: u32) -> Result<u32, Self::Error>;
     /// Returns a bitcoin [Transaction] given it's txid.
     fn get_tx(&self, txid: &bitcoin::Txid) -> Result<Option<bitcoin::Transaction>, Self::Error>;
     /// Get the height of our best know chain.
@@ -104,6 +120,10 @@ pub trait BlockchainInterface {
     fn get_fork_point(&self, block: BlockHash) -> Result<BlockHash, Self::Error>;
     fn get_params(&self) -> bitcoin::params::Params;
     fn acc(&self) -> Stump;
+    /// Returns our accumulator after the block at `height`, if we still have it saved
+    fn get_roots_for_block(&self, height: u32) -> Result<Option<Stump>, Self::Error>;
+    /// Returns the status of each versionbits deployment at a given block
+    fn get_deployment_info(&self, block: BlockHash) -> Result<DeploymentInfo, Self::Error>;
 }
 
 /// [UpdatableChainstate] is a contract that a is expected from a chainstate
@@ -128,7 +148,34 @@ pub trait UpdatableChainstate {
     /// valid after calling connect_block.
     ///
     /// This function returns whether this block is on our best-known chain, or in a fork
+    ///
+    /// Headers whose chain doesn't have the minimum chain work are refused, they must be
+    /// presynced first (see [start_headers_presync]) and then given to [accept_presynced_header].
+    ///
+    /// [start_headers_presync]: UpdatableChainstate::start_headers_presync
+    /// [accept_presynced_header]: UpdatableChainstate::accept_presynced_header
     fn accept_header(&self, header: BlockHeader) -> Result<(), BlockchainError>;
+    /// Same as [accept_header], but for headers we already know build a chain with the minimum
+    /// chain work, like the ones released by a presync, or a batch for which
+    /// [start_headers_presync] returned `None`. This skips the minimum chain work check.
+    ///
+    /// [accept_header]: UpdatableChainstate::accept_header
+    /// [start_headers_presync]: UpdatableChainstate::start_headers_presync
+    fn accept_presynced_header(&self, header: BlockHeader) -> Result<(), BlockchainError>;
+    /// Checks whether a peer's headers need a presync before we accept them, because the chain
+    /// they build doesn't have the minimum chain work yet. If so, returns the state for this
+    /// presync, and the headers should be given to it instead of [accept_header].
+    ///
+    /// `now` is the current unix time, and `salt` must be random and kept secret from the peer.
+    /// See [HeadersSyncState] for more details.
+    ///
+    /// [accept_header]: UpdatableChainstate::accept_header
+    fn start_headers_presync(
+        &self,
+        headers: &[BlockHeader],
+        now: u32,
+        salt: u64,
+    ) -> Result<Option<HeadersSyncState>, BlockchainError>;
     /// Not used for now, but in a future blockchain with mempool, we can process transactions
     /// that are not in a block yet.
     fn handle_transaction(&self) -> Result<(), BlockchainError>;
@@ -203,6 +250,19 @@ impl<T: UpdatableChainstate> UpdatableChainstate for Arc<T> {
         T::accept_header(self, header)
     }
 
+    fn accept_presynced_header(&self, header: BlockHeader) -> Result<(), BlockchainError> {
+        T::accept_presynced_header(self, header)
+    }
+
+    fn start_headers_presync(
+        &self,
+        headers: &[BlockHeader],
+        now: u32,
+        salt: u64,
+    ) -> Result<Option<HeadersSyncState>, BlockchainError> {
+        T::start_headers_presync(self, headers, now, salt)
+    }
+
     fn get_root_hashes(&self) -> Vec<BitcoinNodeHash> {
         T::get_root_hashes(self)
     }
@@ -252,6 +312,10 @@ impl<T: BlockchainInterface> BlockchainInterface for Arc<T> {
         T::acc(self)
     }
 
+    fn get_roots_for_block(&self, height: u32) -> Result<Option<Stump>, Self::Error> {
+        T::get_roots_for_block(self, height)
+    }
+
     fn broadcast(&self, tx: &bitcoin::Transaction) -> Result<(), Self::Error> {
         T::broadcast(self, tx)
     }
@@ -280,6 +344,10 @@ impl<T: BlockchainInterface> BlockchainInterface for Arc<T> {
         T::get_block_hash(self, height)
     }
 
+    fn get_median_time_past(&self, height: u32) -> Result<u32, Self::Error> {
+        T::get_median_time_past(self, height)
+    }
+
     fn get_best_block(&self) -> Result<(u32, BlockHash), Self::Error> {
         T::get_best_block(self)
     }
@@ -341,6 +409,10 @@ impl<T: BlockchainInterface> BlockchainInterface for Arc<T> {
     fn get_fork_point(&self, block: BlockHash) -> Result<BlockHash, Self::Error> {
         T::get_fork_point(self, block)
     }
+
+    fn get_deployment_info(&self, block: BlockHash) -> Result<DeploymentInfo, Self::Error> {
+        T::get_deployment_info(self, block)
+    }
 }
 
 /// This module defines an [UtxoData] struct, helpful for transaction validation
@@ -34,8 +34,12 @@ use tracing::info;
 
 use super::chainparams::ChainParams;
 use super::consensus::Consensus;
+use super::consensus::MEDIAN_TIME_SPAN;
 use super::error::BlockValidationErrors;
 use super::error::BlockchainError;
+use super::headers_sync::HeadersSyncState;
+use super::versionbits::DeploymentInfo;
+use super::versionbits::DeploymentRules;
 use super::BlockchainInterface;
 use super::UpdatableChainstate;
 use crate::pruned_utreexo::utxo_data::UtxoData;
@@ -64,6 +68,11 @@ pub(crate) struct PartialChainStateInner {
     /// Whether we assume the signatures in this interval as valid, this is used to
     /// speed up syncing, by assuming signatures in old blocks are valid.
     pub(crate) assume_valid: bool,
+    /// The versionbits rules for the blocks in this interval, as `(height, rules)` pairs sorted
+    /// by height. Each entry applies from its height until the next one.
+    pub(crate) deployment_rules: Vec<(u32, DeploymentRules)>,
+    /// How many threads we use to verify the scripts of each block.
+    pub(crate) script_workers: usize,
 }
 
 /// A partial chain is a chain that only contains a subset of the blocks in the
@@ -117,6 +126,18 @@ impl PartialChainStateInner {
         self.consensus.parameters.clone()
     }
 
+    /// Returns the versionbits rules for the block at `height`
+    fn get_deployment_rules(&self, height: u32) -> DeploymentRules {
+        let next = self
+            .deployment_rules
+            .partition_point(|(start, _)| *start <= height);
+
+        match next {
+            0 => DeploymentRules::default(),
+            next => self.deployment_rules[next - 1].1.clone(),
+        }
+    }
+
     #[inline]
     /// Returns the ancestor for a given block header
     fn get_ancestor(&self, height: u32) -> Result<BlockHeader, BlockchainError> {
@@ -127,6 +148,17 @@ impl PartialChainStateInner {
         Ok(*prev)
     }
 
+    /// Returns the median time past (MTP) of the block at a given height, as defined by BIP 113
+    fn get_median_time_past(&self, height: u32) -> Result<u32, BlockchainError> {
+        let first = (height + 1).saturating_sub(MEDIAN_TIME_SPAN as u32);
+        let timestamps = (first..=height)
+            .map(|h| self.get_block(h).map(|header| header.time))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(BlockchainError::BlockNotPresent)?;
+
+        Ok(Consensus::get_median_time_past(timestamps))
+    }
+
     /// Process a block, given the proof, inputs, and deleted hashes. If we find an error,
     /// we save it.
     pub fn process_block(
@@ -196,21 +228,27 @@ impl PartialChainStateInner {
         let subsidy = self.consensus.get_subsidy(height);
         let verify_script = self.assume_valid;
 
-        #[cfg(feature = "bitcoinconsensus")]
-        let flags = self
-            .consensus
-            .parameters
-            .get_validation_flags(height, block.block_hash());
-        #[cfg(not(feature = "bitcoinconsensus"))]
-        let flags = 0;
+        let rules = self.get_deployment_rules(height);
+        rules.check_signaling(block.header.version)?;
+
+        let flags = self.consensus.parameters.get_validation_flags(
+            height,
+            block.block_hash(),
+            rules.script_flags,
+        );
+        let median_time_past = self.get_median_time_past(height - 1)?;
+        let lock_time_cutoff =
+            Consensus::get_lock_time_cutoff(block.header.time, median_time_past, flags);
 
         Consensus::verify_block_transactions(
             height,
+            lock_time_cutoff,
             inputs,
             &block.txdata,
             subsidy,
             verify_script,
             flags,
+            self.script_workers,
         )?;
         Ok(())
     }
@@ -305,6 +343,19 @@ impl UpdatableChainstate for PartialChainState {
         unimplemented!("partialChainState shouldn't be used to accept new headers")
     }
 
+    fn accept_presynced_header(&self, _header: BlockHeader) -> Result<(), BlockchainError> {
+        unimplemented!("partialChainState shouldn't be used to accept new headers")
+    }
+
+    fn start_headers_presync(
+        &self,
+        _headers: &[BlockHeader],
+        _now: u32,
+        _salt: u64,
+    ) -> Result<Option<HeadersSyncState>, BlockchainError> {
+        unimplemented!("partialChainState shouldn't be used to accept new headers")
+    }
+
     fn switch_chain(&self, _new_tip: BlockHash) -> Result<(), BlockchainError> {
         unimplemented!("partialChainState shouldn't be used to switch chains")
     }
@@ -346,6 +397,12 @@ impl BlockchainInterface for PartialChainState {
         self.inner().current_acc.clone()
     }
 
+    fn get_roots_for_block(&self, height: u32) -> Result<Option<Stump>, Self::Error> {
+        // We only have the accumulator for our current height
+        let inner = self.inner();
+        Ok((height == inner.current_height).then(|| inner.current_acc.clone()))
+    }
+
     fn get_height(&self) -> Result<u32, Self::Error> {
         Ok(self.inner().current_height)
     }
@@ -358,6 +415,10 @@ impl BlockchainInterface for PartialChainState {
             .ok_or(BlockchainError::BlockNotPresent)
     }
 
+    fn get_median_time_past(&self, height: u32) -> Result<u32, Self::Error> {
+        self.inner().get_median_time_past(height)
+    }
+
     fn get_best_block(&self) -> Result<(u32, bitcoin::BlockHash), Self::Error> {
         Ok((
             self.inner().final_height,
@@ -425,6 +486,10 @@ impl BlockchainInterface for PartialChainState {
         unimplemented!("PartialChainState::get_fork_point")
     }
 
+    fn get_deployment_info(&self, _block: BlockHash) -> Result<DeploymentInfo, Self::Error> {
+        unimplemented!("PartialChainState::get_deployment_info")
+    }
+
     fn update_acc(
         &self,
         _acc: Stump,
@@ -524,6 +589,8 @@ mod tests {
     fn get_empty_pchain(blocks: Vec<Header>) -> PartialChainState {
         PartialChainStateInner {
             assume_valid: true,
+            deployment_rules: Vec::new(),
+            script_workers: 1,
             consensus: Consensus {
                 parameters: ChainParams::from(Network::Regtest),
             },
@@ -548,6 +615,8 @@ mod tests {
         }
         let chainstate: PartialChainState = PartialChainStateInner {
             assume_valid: true,
+            deployment_rules: Vec::new(),
+            script_workers: 1,
             consensus: Consensus {
                 parameters: ChainParams::from(Network::Regtest),
             },
@@ -584,6 +653,8 @@ mod tests {
         let (blocks1, blocks2) = split.split_at(101);
         let mut chainstate1 = PartialChainStateInner {
             assume_valid: true,
+            deployment_rules: Vec::new(),
+            script_workers: 1,
             consensus: Consensus {
                 parameters: ChainParams::from(Network::Regtest),
             },
@@ -628,6 +699,8 @@ mod tests {
 
         let chainstate2: PartialChainState = PartialChainStateInner {
             assume_valid: true,
+            deployment_rules: Vec::new(),
+            script_workers: 1,
             consensus: Consensus {
                 parameters: ChainParams::from(Network::Regtest),
             },
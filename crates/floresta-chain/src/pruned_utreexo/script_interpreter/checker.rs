@@ -0,0 +1,338 @@
+//! Signature and lock time checks used by the script interpreter. This is the equivalent of
+//! Bitcoin Core's `GenericTransactionSignatureChecker`, and it's the only part of the interpreter
+//! that knows about the transaction being validated.
+
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::sha256d;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::HashEngine;
+use bitcoin::secp256k1::ecdsa;
+use bitcoin::secp256k1::schnorr;
+use bitcoin::secp256k1::Message;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::secp256k1::VerifyOnly;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::sighash::Prevouts;
+use bitcoin::sighash::SighashCache;
+use bitcoin::sighash::TapSighashType;
+use bitcoin::Script;
+use bitcoin::Transaction;
+use bitcoin::TxOut;
+use bitcoin::VarInt;
+
+use super::eval::remove_code_separators;
+use super::eval::ExecData;
+use super::eval::SigVersion;
+use super::ScriptError;
+use crate::prelude::*;
+use crate::pruned_utreexo::consensus::LOCKTIME_THRESHOLD;
+use crate::pruned_utreexo::consensus::SEQUENCE_LOCKTIME_DISABLE_FLAG;
+use crate::pruned_utreexo::consensus::SEQUENCE_LOCKTIME_MASK;
+use crate::pruned_utreexo::consensus::SEQUENCE_LOCKTIME_TYPE_FLAG;
+
+const SIGHASH_NONE: u32 = 0x02;
+const SIGHASH_SINGLE: u32 = 0x03;
+const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+/// The data shared by the script checks of all inputs in a transaction, so hashes that commit to
+/// the whole transaction are only computed once.
+pub(super) struct TxContext<'a> {
+    /// The transaction being validated
+    pub(super) tx: &'a Transaction,
+    /// The outputs spent by each input of the transaction, in the same order
+    pub(super) spent_outputs: &'a [TxOut],
+    secp: Secp256k1<VerifyOnly>,
+    sighash_cache: SighashCache<&'a Transaction>,
+    segwit_v0_hashes: Option<SegwitV0Hashes>,
+}
+
+impl<'a> TxContext<'a> {
+    pub(super) fn new(tx: &'a Transaction, spent_outputs: &'a [TxOut]) -> Self {
+        TxContext {
+            tx,
+            spent_outputs,
+            secp: Secp256k1::verification_only(),
+            sighash_cache: SighashCache::new(tx),
+            segwit_v0_hashes: None,
+        }
+    }
+}
+
+/// The transaction-wide hashes used by the BIP 143 signature hash
+struct SegwitV0Hashes {
+    prevouts: sha256d::Hash,
+    sequences: sha256d::Hash,
+    outputs: sha256d::Hash,
+}
+
+impl SegwitV0Hashes {
+    fn new(tx: &Transaction) -> Self {
+        let mut prevouts = sha256d::Hash::engine();
+        let mut sequences = sha256d::Hash::engine();
+        let mut outputs = sha256d::Hash::engine();
+
+        // Writing to a hash engine never fails
+        for input in tx.input.iter() {
+            let _ = input.previous_output.consensus_encode(&mut prevouts);
+            let _ = input.sequence.consensus_encode(&mut sequences);
+        }
+        for output in tx.output.iter() {
+            let _ = output.consensus_encode(&mut outputs);
+        }
+
+        SegwitV0Hashes {
+            prevouts: sha256d::Hash::from_engine(prevouts),
+            sequences: sha256d::Hash::from_engine(sequences),
+            outputs: sha256d::Hash::from_engine(outputs),
+        }
+    }
+}
+
+/// Checks signatures and lock times for a single transaction input.
+pub(super) struct SignatureChecker<'a, 'b> {
+    ctx: &'b mut TxContext<'a>,
+    input_index: usize,
+}
+
+impl<'a, 'b> SignatureChecker<'a, 'b> {
+    pub(super) fn new(ctx: &'b mut TxContext<'a>, input_index: usize) -> Self {
+        SignatureChecker { ctx, input_index }
+    }
+
+    pub(super) fn secp(&self) -> &Secp256k1<VerifyOnly> {
+        &self.ctx.secp
+    }
+
+    /// Verifies an ECDSA signature, with the sighash type as its last byte, for legacy and
+    /// segwit v0 scripts. Like Bitcoin Core, this parses the signature with lax DER rules and
+    /// normalizes S before verifying.
+    pub(super) fn check_ecdsa_signature(
+        &mut self,
+        sig: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sigversion: SigVersion,
+    ) -> bool {
+        let Ok(pubkey) = PublicKey::from_slice(pubkey) else {
+            return false;
+        };
+        let Some((&hash_type, sig)) = sig.split_last() else {
+            return false;
+        };
+        let Ok(mut sig) = ecdsa::Signature::from_der_lax(sig) else {
+            return false;
+        };
+        sig.normalize_s();
+
+        let sighash = match sigversion {
+            SigVersion::Base => self.legacy_sighash(script_code, hash_type as u32),
+            SigVersion::WitnessV0 => self.segwit_v0_sighash(script_code, hash_type as u32),
+            SigVersion::Taproot | SigVersion::Tapscript => return false,
+        };
+        let Some(sighash) = sighash else {
+            return false;
+        };
+
+        let msg = Message::from_digest(sighash);
+        self.ctx.secp.verify_ecdsa(&msg, &sig, &pubkey).is_ok()
+    }
+
+    /// Verifies a BIP 340 signature for a taproot key path spend or tapscript, where `sig` may
+    /// have an explicit sighash type as its 65th byte.
+    pub(super) fn check_schnorr_signature(
+        &mut self,
+        sig: &[u8],
+        pubkey: &[u8],
+        sigversion: SigVersion,
+        execdata: &ExecData,
+    ) -> Result<(), ScriptError> {
+        let (sig, hash_type) = match sig.len() {
+            64 => (sig, TapSighashType::Default),
+            65 => {
+                // An explicit SIGHASH_DEFAULT must be omitted instead
+                if sig[64] == 0 {
+                    return Err(ScriptError::SchnorrSigHashtype);
+                }
+                let hash_type = TapSighashType::from_consensus_u8(sig[64])
+                    .map_err(|_| ScriptError::SchnorrSigHashtype)?;
+                (&sig[..64], hash_type)
+            }
+            _ => return Err(ScriptError::SchnorrSigSize),
+        };
+
+        let leaf_hash_code_separator = match sigversion {
+            SigVersion::Tapscript => execdata
+                .tapleaf_hash
+                .map(|leaf_hash| (leaf_hash, execdata.codesep_pos)),
+            _ => None,
+        };
+        let sighash = self
+            .ctx
+            .sighash_cache
+            .taproot_signature_hash(
+                self.input_index,
+                &Prevouts::All(self.ctx.spent_outputs),
+                execdata.annex.clone(),
+                leaf_hash_code_separator,
+                hash_type,
+            )
+            .map_err(|_| ScriptError::SchnorrSigHashtype)?;
+
+        let sig = schnorr::Signature::from_slice(sig).map_err(|_| ScriptError::SchnorrSig)?;
+        let pubkey = XOnlyPublicKey::from_slice(pubkey).map_err(|_| ScriptError::SchnorrSig)?;
+        let msg = Message::from_digest(sighash.to_byte_array());
+
+        self.ctx
+            .secp
+            .verify_schnorr(&sig, &msg, &pubkey)
+            .map_err(|_| ScriptError::SchnorrSig)
+    }
+
+    /// Checks an `OP_CHECKLOCKTIMEVERIFY` argument against the transaction's lock time (BIP 65)
+    pub(super) fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx_lock_time = self.ctx.tx.lock_time.to_consensus_u32() as i64;
+        let threshold = LOCKTIME_THRESHOLD as i64;
+
+        // Both lock times must be of the same kind (height or time)
+        if (tx_lock_time < threshold) != (lock_time < threshold) {
+            return false;
+        }
+        if lock_time > tx_lock_time {
+            return false;
+        }
+
+        // The lock time is ignored if the input sequence is final, so this could be bypassed
+        !self.ctx.tx.input[self.input_index].sequence.is_final()
+    }
+
+    /// Checks an `OP_CHECKSEQUENCEVERIFY` argument against the input's sequence (BIP 112)
+    pub(super) fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = self.ctx.tx.input[self.input_index].sequence.0 as i64;
+
+        // Relative lock times are only enforced for version 2 transactions
+        if (self.ctx.tx.version.0 as u32) < 2 {
+            return false;
+        }
+        if tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 != 0 {
+            return false;
+        }
+
+        let mask = (SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK) as i64;
+        let type_flag = SEQUENCE_LOCKTIME_TYPE_FLAG as i64;
+        let tx_sequence = tx_sequence & mask;
+        let sequence = sequence & mask;
+
+        // Both relative lock times must be of the same kind (blocks or time)
+        if (tx_sequence < type_flag) != (sequence < type_flag) {
+            return false;
+        }
+
+        sequence <= tx_sequence
+    }
+
+    /// The original signature hash algorithm. Any `OP_CODESEPARATOR` left in the script code is
+    /// removed, and the SIGHASH_SINGLE bug is handled by the `bitcoin` crate.
+    fn legacy_sighash(&mut self, script_code: &[u8], hash_type: u32) -> Option<[u8; 32]> {
+        let script_code = remove_code_separators(script_code);
+        let sighash = self
+            .ctx
+            .sighash_cache
+            .legacy_signature_hash(
+                self.input_index,
+                Script::from_bytes(&script_code),
+                hash_type,
+            )
+            .ok()?;
+
+        Some(sighash.to_byte_array())
+    }
+
+    /// The BIP 143 signature hash. We don't use the `bitcoin` crate for this one because it only
+    /// takes standard sighash types, while any 32-bit value is valid and committed to.
+    fn segwit_v0_sighash(&mut self, script_code: &[u8], hash_type: u32) -> Option<[u8; 32]> {
+        let tx = self.ctx.tx;
+        let input = tx.input.get(self.input_index)?;
+        let amount = self.ctx.spent_outputs.get(self.input_index)?.value;
+        let hashes = self
+            .ctx
+            .segwit_v0_hashes
+            .get_or_insert_with(|| SegwitV0Hashes::new(tx));
+
+        let base_type = hash_type & 0x1f;
+        let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+        let single_or_none = base_type == SIGHASH_SINGLE || base_type == SIGHASH_NONE;
+        let zero = sha256d::Hash::all_zeros();
+
+        let hash_prevouts = match anyone_can_pay {
+            true => zero,
+            false => hashes.prevouts,
+        };
+        let hash_sequence = match anyone_can_pay || single_or_none {
+            true => zero,
+            false => hashes.sequences,
+        };
+        let hash_outputs = match tx.output.get(self.input_index) {
+            _ if !single_or_none => hashes.outputs,
+            Some(output) if base_type == SIGHASH_SINGLE => {
+                let mut engine = sha256d::Hash::engine();
+                let _ = output.consensus_encode(&mut engine);
+                sha256d::Hash::from_engine(engine)
+            }
+            _ => zero,
+        };
+
+        // Writing to a hash engine never fails
+        let mut engine = sha256d::Hash::engine();
+        let _ = tx.version.consensus_encode(&mut engine);
+        engine.input(hash_prevouts.as_byte_array());
+        engine.input(hash_sequence.as_byte_array());
+        let _ = input.previous_output.consensus_encode(&mut engine);
+        let _ = VarInt(script_code.len() as u64).consensus_encode(&mut engine);
+        engine.input(script_code);
+        let _ = amount.consensus_encode(&mut engine);
+        let _ = input.sequence.consensus_encode(&mut engine);
+        engine.input(hash_outputs.as_byte_array());
+        let _ = tx.lock_time.consensus_encode(&mut engine);
+        engine.input(&hash_type.to_le_bytes());
+
+        Some(sha256d::Hash::from_engine(engine).to_byte_array())
+    }
+}
+
+/// Whether a signature is strictly DER encoded, as required by BIP 66. The signature includes
+/// the trailing sighash type byte.
+///
+/// See <https://github.com/bitcoin/bitcoin/blob/v28.0/src/script/interpreter.cpp#L97-L170>
+pub(super) fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    // Format: 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [sighash]
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+
+    // A signature is of type 0x30 (compound), and the length covers the entire signature
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+
+    // The lengths of R and S must be consistent with the total length
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+
+    // R and S are non-empty, non-negative integers without unnecessary leading zeros
+    let is_valid_integer = |marker: u8, int: &[u8]| {
+        marker == 0x02
+            && !int.is_empty()
+            && int[0] & 0x80 == 0
+            && !(int.len() > 1 && int[0] == 0 && int[1] & 0x80 == 0)
+    };
+
+    is_valid_integer(sig[2], &sig[4..4 + len_r])
+        && is_valid_integer(sig[4 + len_r], &sig[6 + len_r..6 + len_r + len_s])
+}
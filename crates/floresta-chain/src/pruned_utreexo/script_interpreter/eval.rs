@@ -0,0 +1,1026 @@
+//! The script evaluation loop, a port of Bitcoin Core's `EvalScript` and `ExecuteWitnessScript`.
+//! Only consensus rules are implemented here, policy-only checks like MINIMALDATA or LOW_S are
+//! left out.
+//!
+//! See <https://github.com/bitcoin/bitcoin/blob/v28.0/src/script/interpreter.cpp>
+
+use bitcoin::hashes::hash160;
+use bitcoin::hashes::ripemd160;
+use bitcoin::hashes::sha1;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::sha256d;
+use bitcoin::hashes::Hash;
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::Opcode;
+use bitcoin::sighash::Annex;
+use bitcoin::taproot::TapLeafHash;
+
+use super::checker::is_valid_signature_encoding;
+use super::checker::SignatureChecker;
+use super::ScriptError;
+use crate::prelude::*;
+use crate::pruned_utreexo::chainparams::verify_flags;
+use crate::pruned_utreexo::consensus::MAX_SCRIPT_ELEMENT_SIZE;
+use crate::pruned_utreexo::consensus::MAX_SCRIPT_SIZE;
+use crate::pruned_utreexo::consensus::MAX_STACK_SIZE;
+use crate::pruned_utreexo::consensus::SEQUENCE_LOCKTIME_DISABLE_FLAG;
+
+/// Maximum number of non-push operations per legacy or segwit v0 script
+const MAX_OPS_PER_SCRIPT: usize = 201;
+
+/// Maximum number of public keys per `OP_CHECKMULTISIG`
+const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+
+/// The validation weight each executed signature check consumes in tapscript (BIP 342)
+pub(super) const VALIDATION_WEIGHT_PER_SIGOP_PASSED: i64 = 50;
+
+/// Numbers are at most 4 bytes long, except for lock time arguments
+const DEFAULT_MAX_NUM_SIZE: usize = 4;
+const LOCKTIME_MAX_NUM_SIZE: usize = 5;
+
+/// The rule set a script is evaluated with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum SigVersion {
+    /// Bare scripts, P2SH redeem scripts and the scriptSig
+    Base,
+    /// Witness v0 scripts (BIP 143)
+    WitnessV0,
+    /// Taproot key path spends (BIP 341)
+    Taproot,
+    /// Taproot script path spends with leaf version 0xc0 (BIP 342)
+    Tapscript,
+}
+
+/// Data about the current taproot spend that signature hashes commit to
+#[derive(Debug, Default)]
+pub(super) struct ExecData<'a> {
+    /// The hash of the leaf being executed, for script path spends
+    pub(super) tapleaf_hash: Option<TapLeafHash>,
+    /// The position of the last executed `OP_CODESEPARATOR`, or `u32::MAX` if there's none
+    pub(super) codesep_pos: u32,
+    /// The annex, if the witness has one
+    pub(super) annex: Option<Annex<'a>>,
+    /// How much signature checking the tapscript can still do
+    pub(super) validation_weight_left: i64,
+}
+
+/// Keeps track of the nested `OP_IF` branches. Since we only need to know whether all of them
+/// are true, we only store the depth and the position of the first false one, like Core does.
+#[derive(Debug, Default)]
+struct ConditionStack {
+    size: usize,
+    first_false_pos: Option<usize>,
+}
+
+impl ConditionStack {
+    fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    fn all_true(&self) -> bool {
+        self.first_false_pos.is_none()
+    }
+
+    fn push(&mut self, value: bool) {
+        if self.first_false_pos.is_none() && !value {
+            self.first_false_pos = Some(self.size);
+        }
+        self.size += 1;
+    }
+
+    fn pop(&mut self) {
+        self.size -= 1;
+        if self.first_false_pos == Some(self.size) {
+            self.first_false_pos = None;
+        }
+    }
+
+    fn toggle_top(&mut self) {
+        match self.first_false_pos {
+            // The top is the first false value, so it becomes true
+            Some(pos) if pos == self.size - 1 => self.first_false_pos = None,
+            // There's an earlier false value, toggling the top doesn't change anything
+            Some(_) => {}
+            None => self.first_false_pos = Some(self.size - 1),
+        }
+    }
+}
+
+/// Reads the operation at `pc` and advances it. Returns the opcode and the pushed data, which
+/// is empty for non-push opcodes, or `None` if the script ends in the middle of a push.
+pub(super) fn get_op<'s>(script: &'s [u8], pc: &mut usize) -> Option<(Opcode, &'s [u8])> {
+    let opcode = *script.get(*pc)?;
+    *pc += 1;
+
+    let mut read_len = |bytes: usize| {
+        let len = script.get(*pc..*pc + bytes)?;
+        *pc += bytes;
+        Some(
+            len.iter()
+                .rev()
+                .fold(0usize, |acc, b| (acc << 8) | *b as usize),
+        )
+    };
+    let len = match Opcode::from(opcode) {
+        OP_PUSHDATA1 => read_len(1)?,
+        OP_PUSHDATA2 => read_len(2)?,
+        OP_PUSHDATA4 => read_len(4)?,
+        _ if opcode < OP_PUSHDATA1.to_u8() => opcode as usize,
+        _ => 0,
+    };
+
+    let data = script.get(*pc..pc.checked_add(len)?)?;
+    *pc += len;
+
+    Some((Opcode::from(opcode), data))
+}
+
+/// Returns the script that pushes `data`, using the smallest push opcode
+pub(super) fn push_data_script(data: &[u8]) -> Vec<u8> {
+    let mut script = Vec::with_capacity(data.len() + 5);
+    match data.len() {
+        len if len < OP_PUSHDATA1.to_u8() as usize => script.push(len as u8),
+        len if len <= 0xff => {
+            script.push(OP_PUSHDATA1.to_u8());
+            script.push(len as u8);
+        }
+        len if len <= 0xffff => {
+            script.push(OP_PUSHDATA2.to_u8());
+            script.extend((len as u16).to_le_bytes());
+        }
+        len => {
+            script.push(OP_PUSHDATA4.to_u8());
+            script.extend((len as u32).to_le_bytes());
+        }
+    }
+    script.extend(data);
+    script
+}
+
+/// Whether the script only has push operations (`OP_16` or below)
+pub(super) fn is_push_only(script: &[u8]) -> bool {
+    let mut pc = 0;
+    while pc < script.len() {
+        match get_op(script, &mut pc) {
+            Some((opcode, _)) if opcode.to_u8() <= OP_PUSHNUM_16.to_u8() => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Removes all `OP_CODESEPARATOR`s from a script, as done when computing legacy signature
+/// hashes. Anything after a truncated push is kept as is.
+pub(super) fn remove_code_separators(script: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(script.len());
+    let mut pc = 0;
+    let mut begin = 0;
+
+    while let Some((opcode, _)) = get_op(script, &mut pc) {
+        if opcode == OP_CODESEPARATOR {
+            result.extend(&script[begin..pc - 1]);
+            begin = pc;
+        }
+    }
+    result.extend(&script[begin..]);
+    result
+}
+
+/// Removes every occurrence of `pattern` that starts at an opcode boundary, returning how many
+/// were found. This is Core's `FindAndDelete`, used to remove signatures from the script code
+/// of legacy signature hashes.
+fn find_and_delete(script: &mut Vec<u8>, pattern: &[u8]) -> usize {
+    if pattern.is_empty() {
+        return 0;
+    }
+
+    let mut found = 0;
+    let mut result = Vec::with_capacity(script.len());
+    let mut pc = 0;
+    let mut kept_from = 0;
+
+    loop {
+        result.extend(&script[kept_from..pc]);
+        while script[pc..].starts_with(pattern) {
+            pc += pattern.len();
+            found += 1;
+        }
+        kept_from = pc;
+
+        if get_op(script, &mut pc).is_none() {
+            break;
+        }
+    }
+
+    if found > 0 {
+        result.extend(&script[kept_from..]);
+        *script = result;
+    }
+    found
+}
+
+/// Whether this opcode makes a tapscript succeed unconditionally (BIP 342)
+fn is_op_success(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        80 | 98 | 126..=129 | 131..=134 | 137..=138 | 141..=142 | 149..=153 | 187..=254
+    )
+}
+
+/// Whether these opcodes were disabled in the early days of Bitcoin. Scripts containing them
+/// fail even if they are in an unexecuted branch.
+fn is_disabled(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        OP_CAT
+            | OP_SUBSTR
+            | OP_LEFT
+            | OP_RIGHT
+            | OP_INVERT
+            | OP_AND
+            | OP_OR
+            | OP_XOR
+            | OP_2MUL
+            | OP_2DIV
+            | OP_MUL
+            | OP_DIV
+            | OP_MOD
+            | OP_LSHIFT
+            | OP_RSHIFT
+    )
+}
+
+/// Interprets a stack element as a boolean. Any non-zero value is true, except for negative zero.
+pub(super) fn cast_to_bool(bytes: &[u8]) -> bool {
+    match bytes.split_last() {
+        Some((&last, rest)) => rest.iter().any(|b| *b != 0) || (last != 0 && last != 0x80),
+        None => false,
+    }
+}
+
+/// Decodes a little-endian, sign-magnitude script number of at most `max_len` bytes
+pub(super) fn decode_num(bytes: &[u8], max_len: usize) -> Result<i64, ScriptError> {
+    if bytes.len() > max_len {
+        return Err(ScriptError::NumOverflow);
+    }
+    let Some(&last) = bytes.last() else {
+        return Ok(0);
+    };
+
+    let value = bytes
+        .iter()
+        .rev()
+        .fold(0i64, |acc, b| (acc << 8) | *b as i64);
+
+    // The most significant bit of the last byte is the sign
+    let sign_bit = 0x80i64 << (8 * (bytes.len() - 1));
+    match last & 0x80 != 0 {
+        true => Ok(-(value & !sign_bit)),
+        false => Ok(value),
+    }
+}
+
+/// Encodes a number in the minimal script number format
+pub(super) fn encode_num(value: i64) -> Vec<u8> {
+    let mut result = Vec::new();
+    let negative = value < 0;
+    let mut abs = value.unsigned_abs();
+
+    while abs > 0 {
+        result.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+
+    // Add an extra byte for the sign if the most significant bit is already taken
+    if let Some(last) = result.last_mut() {
+        if *last & 0x80 != 0 {
+            result.push(if negative { 0x80 } else { 0 });
+        } else if negative {
+            *last |= 0x80;
+        }
+    }
+    result
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    match value {
+        true => vec![1],
+        false => Vec::new(),
+    }
+}
+
+/// Returns the `n`-th element from the top of the stack, starting at 1
+fn top(stack: &[Vec<u8>], n: usize) -> Result<&Vec<u8>, ScriptError> {
+    stack
+        .len()
+        .checked_sub(n)
+        .map(|i| &stack[i])
+        .ok_or(ScriptError::InvalidStackOperation)
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::InvalidStackOperation)
+}
+
+fn require(stack: &[Vec<u8>], len: usize) -> Result<(), ScriptError> {
+    match stack.len() < len {
+        true => Err(ScriptError::InvalidStackOperation),
+        false => Ok(()),
+    }
+}
+
+/// Checks an ECDSA signature for `OP_CHECKSIG` and friends in legacy and segwit v0 scripts.
+/// An invalid signature makes the opcode return false rather than failing the script, unless
+/// it's not strictly DER encoded and BIP 66 is active.
+fn eval_checksig_pre_tapscript(
+    sig: &[u8],
+    pubkey: &[u8],
+    script_code: &[u8],
+    flags: u32,
+    checker: &mut SignatureChecker,
+    sigversion: SigVersion,
+) -> Result<bool, ScriptError> {
+    let mut script_code = script_code.to_vec();
+
+    // Legacy signatures can't sign themselves, so they are removed from the script code
+    if sigversion == SigVersion::Base {
+        find_and_delete(&mut script_code, &push_data_script(sig));
+    }
+    check_signature_encoding(sig, flags)?;
+
+    Ok(checker.check_ecdsa_signature(sig, pubkey, &script_code, sigversion))
+}
+
+/// Checks a BIP 340 signature for `OP_CHECKSIG` and friends in tapscript. Here a non-empty
+/// signature must be valid, and an empty one makes the opcode return false.
+fn eval_checksig_tapscript(
+    sig: &[u8],
+    pubkey: &[u8],
+    checker: &mut SignatureChecker,
+    execdata: &mut ExecData,
+) -> Result<bool, ScriptError> {
+    let success = !sig.is_empty();
+    if success {
+        execdata.validation_weight_left -= VALIDATION_WEIGHT_PER_SIGOP_PASSED;
+        if execdata.validation_weight_left < 0 {
+            return Err(ScriptError::TapscriptValidationWeight);
+        }
+    }
+
+    match pubkey.len() {
+        0 => return Err(ScriptError::PubkeyType),
+        32 if success => {
+            checker.check_schnorr_signature(sig, pubkey, SigVersion::Tapscript, execdata)?;
+        }
+        // Unknown public key types are reserved for soft forks, and always succeed
+        _ => {}
+    }
+
+    Ok(success)
+}
+
+fn eval_checksig(
+    sig: &[u8],
+    pubkey: &[u8],
+    script_code: &[u8],
+    flags: u32,
+    checker: &mut SignatureChecker,
+    sigversion: SigVersion,
+    execdata: &mut ExecData,
+) -> Result<bool, ScriptError> {
+    match sigversion {
+        SigVersion::Base | SigVersion::WitnessV0 => {
+            eval_checksig_pre_tapscript(sig, pubkey, script_code, flags, checker, sigversion)
+        }
+        SigVersion::Tapscript => eval_checksig_tapscript(sig, pubkey, checker, execdata),
+        // Key path spends never execute a script
+        SigVersion::Taproot => Err(ScriptError::BadOpcode),
+    }
+}
+
+/// Empty signatures are allowed, since they are a compact way to provide an invalid signature
+fn check_signature_encoding(sig: &[u8], flags: u32) -> Result<(), ScriptError> {
+    if sig.is_empty() {
+        return Ok(());
+    }
+    if flags & verify_flags::VERIFY_DERSIG != 0 && !is_valid_signature_encoding(sig) {
+        return Err(ScriptError::SigDer);
+    }
+    Ok(())
+}
+
+/// Evaluates `script` on top of `stack`.
+pub(super) fn eval_script(
+    stack: &mut Vec<Vec<u8>>,
+    script: &[u8],
+    flags: u32,
+    checker: &mut SignatureChecker,
+    sigversion: SigVersion,
+    execdata: &mut ExecData,
+) -> Result<(), ScriptError> {
+    let is_tapscript = sigversion == SigVersion::Tapscript;
+    if !is_tapscript && script.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ScriptSize);
+    }
+
+    let mut pc = 0;
+    let mut begin_code_hash = 0;
+    let mut exec_stack = ConditionStack::default();
+    let mut altstack: Vec<Vec<u8>> = Vec::new();
+    let mut op_count = 0;
+    let mut opcode_pos = 0;
+    execdata.codesep_pos = u32::MAX;
+
+    while pc < script.len() {
+        let exec = exec_stack.all_true();
+        let Some((opcode, push_value)) = get_op(script, &mut pc) else {
+            return Err(ScriptError::BadOpcode);
+        };
+        let op = opcode.to_u8();
+
+        if push_value.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(ScriptError::PushSize);
+        }
+
+        // Tapscript has no op count limit, signature checks are limited by weight instead
+        if !is_tapscript && op > OP_PUSHNUM_16.to_u8() {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+        }
+
+        if is_disabled(opcode) {
+            return Err(ScriptError::DisabledOpcode);
+        }
+
+        let is_conditional = (OP_IF.to_u8()..=OP_ENDIF.to_u8()).contains(&op);
+        if exec && op <= OP_PUSHDATA4.to_u8() {
+            stack.push(push_value.to_vec());
+        } else if exec || is_conditional {
+            match opcode {
+                OP_PUSHNUM_NEG1 | OP_PUSHNUM_1 | OP_PUSHNUM_2 | OP_PUSHNUM_3 | OP_PUSHNUM_4
+                | OP_PUSHNUM_5 | OP_PUSHNUM_6 | OP_PUSHNUM_7 | OP_PUSHNUM_8 | OP_PUSHNUM_9
+                | OP_PUSHNUM_10 | OP_PUSHNUM_11 | OP_PUSHNUM_12 | OP_PUSHNUM_13 | OP_PUSHNUM_14
+                | OP_PUSHNUM_15 | OP_PUSHNUM_16 => {
+                    let value = op as i64 - (OP_PUSHNUM_1.to_u8() as i64 - 1);
+                    stack.push(encode_num(value));
+                }
+
+                // Before BIP 65 this was OP_NOP2
+                OP_CLTV if flags & verify_flags::VERIFY_CHECKLOCKTIMEVERIFY != 0 => {
+                    let lock_time = decode_num(top(stack, 1)?, LOCKTIME_MAX_NUM_SIZE)?;
+                    if lock_time < 0 {
+                        return Err(ScriptError::NegativeLocktime);
+                    }
+                    if !checker.check_lock_time(lock_time) {
+                        return Err(ScriptError::UnsatisfiedLocktime);
+                    }
+                }
+
+                // Before BIP 112 this was OP_NOP3
+                OP_CSV if flags & verify_flags::VERIFY_CHECKSEQUENCEVERIFY != 0 => {
+                    let sequence = decode_num(top(stack, 1)?, LOCKTIME_MAX_NUM_SIZE)?;
+                    if sequence < 0 {
+                        return Err(ScriptError::NegativeLocktime);
+                    }
+
+                    // With the disable flag set, the argument behaves as a NOP
+                    let disabled = sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 != 0;
+                    if !disabled && !checker.check_sequence(sequence) {
+                        return Err(ScriptError::UnsatisfiedLocktime);
+                    }
+                }
+
+                OP_NOP | OP_NOP1 | OP_CLTV | OP_CSV | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7
+                | OP_NOP8 | OP_NOP9 | OP_NOP10 => {}
+
+                OP_IF | OP_NOTIF => {
+                    let mut value = false;
+                    if exec {
+                        let condition =
+                            top(stack, 1).map_err(|_| ScriptError::UnbalancedConditional)?;
+
+                        // Tapscript requires the argument to be exactly empty or 0x01
+                        let minimal = condition.is_empty() || condition.as_slice() == [1];
+                        if is_tapscript && !minimal {
+                            return Err(ScriptError::TapscriptMinimalIf);
+                        }
+
+                        value = cast_to_bool(condition) == (opcode == OP_IF);
+                        stack.pop();
+                    }
+                    exec_stack.push(value);
+                }
+
+                OP_ELSE => {
+                    if exec_stack.is_empty() {
+                        return Err(ScriptError::UnbalancedConditional);
+                    }
+                    exec_stack.toggle_top();
+                }
+
+                OP_ENDIF => {
+                    if exec_stack.is_empty() {
+                        return Err(ScriptError::UnbalancedConditional);
+                    }
+                    exec_stack.pop();
+                }
+
+                OP_VERIFY => {
+                    if !cast_to_bool(top(stack, 1)?) {
+                        return Err(ScriptError::Verify);
+                    }
+                    stack.pop();
+                }
+
+                OP_RETURN => return Err(ScriptError::OpReturn),
+
+                OP_TOALTSTACK => {
+                    let value = pop(stack)?;
+                    altstack.push(value);
+                }
+
+                OP_FROMALTSTACK => {
+                    let value = altstack
+                        .pop()
+                        .ok_or(ScriptError::InvalidAltstackOperation)?;
+                    stack.push(value);
+                }
+
+                OP_2DROP => {
+                    require(stack, 2)?;
+                    stack.truncate(stack.len() - 2);
+                }
+
+                OP_2DUP => {
+                    require(stack, 2)?;
+                    stack.extend_from_within(stack.len() - 2..);
+                }
+
+                OP_3DUP => {
+                    require(stack, 3)?;
+                    stack.extend_from_within(stack.len() - 3..);
+                }
+
+                OP_2OVER => {
+                    require(stack, 4)?;
+                    let len = stack.len();
+                    stack.extend_from_within(len - 4..len - 2);
+                }
+
+                OP_2ROT => {
+                    require(stack, 6)?;
+                    let len = stack.len();
+                    stack[len - 6..].rotate_left(2);
+                }
+
+                OP_2SWAP => {
+                    require(stack, 4)?;
+                    let len = stack.len();
+                    stack[len - 4..].rotate_left(2);
+                }
+
+                OP_IFDUP => {
+                    let value = top(stack, 1)?.clone();
+                    if cast_to_bool(&value) {
+                        stack.push(value);
+                    }
+                }
+
+                OP_DEPTH => stack.push(encode_num(stack.len() as i64)),
+
+                OP_DROP => {
+                    pop(stack)?;
+                }
+
+                OP_DUP => stack.push(top(stack, 1)?.clone()),
+
+                OP_NIP => {
+                    require(stack, 2)?;
+                    stack.remove(stack.len() - 2);
+                }
+
+                OP_OVER => stack.push(top(stack, 2)?.clone()),
+
+                OP_PICK | OP_ROLL => {
+                    require(stack, 2)?;
+                    let n = decode_num(&pop(stack)?, DEFAULT_MAX_NUM_SIZE)?;
+                    if n < 0 || n as usize >= stack.len() {
+                        return Err(ScriptError::InvalidStackOperation);
+                    }
+
+                    let index = stack.len() - n as usize - 1;
+                    let value = match opcode == OP_ROLL {
+                        true => stack.remove(index),
+                        false => stack[index].clone(),
+                    };
+                    stack.push(value);
+                }
+
+                OP_ROT => {
+                    require(stack, 3)?;
+                    let len = stack.len();
+                    stack[len - 3..].rotate_left(1);
+                }
+
+                OP_SWAP => {
+                    require(stack, 2)?;
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                }
+
+                OP_TUCK => {
+                    require(stack, 2)?;
+                    let value = top(stack, 1)?.clone();
+                    stack.insert(stack.len() - 2, value);
+                }
+
+                OP_SIZE => {
+                    let size = top(stack, 1)?.len();
+                    stack.push(encode_num(size as i64));
+                }
+
+                OP_EQUAL | OP_EQUALVERIFY => {
+                    require(stack, 2)?;
+                    let equal = pop(stack)? == pop(stack)?;
+
+                    if opcode == OP_EQUALVERIFY {
+                        if !equal {
+                            return Err(ScriptError::EqualVerify);
+                        }
+                    } else {
+                        stack.push(encode_bool(equal));
+                    }
+                }
+
+                OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                    let value = decode_num(top(stack, 1)?, DEFAULT_MAX_NUM_SIZE)?;
+                    let result = match opcode {
+                        OP_1ADD => value + 1,
+                        OP_1SUB => value - 1,
+                        OP_NEGATE => -value,
+                        OP_ABS => value.abs(),
+                        OP_NOT => (value == 0) as i64,
+                        _ => (value != 0) as i64,
+                    };
+
+                    stack.pop();
+                    stack.push(encode_num(result));
+                }
+
+                OP_ADD
+                | OP_SUB
+                | OP_BOOLAND
+                | OP_BOOLOR
+                | OP_NUMEQUAL
+                | OP_NUMEQUALVERIFY
+                | OP_NUMNOTEQUAL
+                | OP_LESSTHAN
+                | OP_GREATERTHAN
+                | OP_LESSTHANOREQUAL
+                | OP_GREATERTHANOREQUAL
+                | OP_MIN
+                | OP_MAX => {
+                    let a = decode_num(top(stack, 2)?, DEFAULT_MAX_NUM_SIZE)?;
+                    let b = decode_num(top(stack, 1)?, DEFAULT_MAX_NUM_SIZE)?;
+                    let result = match opcode {
+                        OP_ADD => a + b,
+                        OP_SUB => a - b,
+                        OP_BOOLAND => (a != 0 && b != 0) as i64,
+                        OP_BOOLOR => (a != 0 || b != 0) as i64,
+                        OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                        OP_NUMNOTEQUAL => (a != b) as i64,
+                        OP_LESSTHAN => (a < b) as i64,
+                        OP_GREATERTHAN => (a > b) as i64,
+                        OP_LESSTHANOREQUAL => (a <= b) as i64,
+                        OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                        OP_MIN => a.min(b),
+                        _ => a.max(b),
+                    };
+
+                    stack.truncate(stack.len() - 2);
+                    if opcode == OP_NUMEQUALVERIFY {
+                        if result == 0 {
+                            return Err(ScriptError::NumEqualVerify);
+                        }
+                    } else {
+                        stack.push(encode_num(result));
+                    }
+                }
+
+                OP_WITHIN => {
+                    let value = decode_num(top(stack, 3)?, DEFAULT_MAX_NUM_SIZE)?;
+                    let min = decode_num(top(stack, 2)?, DEFAULT_MAX_NUM_SIZE)?;
+                    let max = decode_num(top(stack, 1)?, DEFAULT_MAX_NUM_SIZE)?;
+
+                    stack.truncate(stack.len() - 3);
+                    stack.push(encode_bool(min <= value && value < max));
+                }
+
+                OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                    let value = pop(stack)?;
+                    let hash = match opcode {
+                        OP_RIPEMD160 => ripemd160::Hash::hash(&value).to_byte_array().to_vec(),
+                        OP_SHA1 => sha1::Hash::hash(&value).to_byte_array().to_vec(),
+                        OP_SHA256 => sha256::Hash::hash(&value).to_byte_array().to_vec(),
+                        OP_HASH160 => hash160::Hash::hash(&value).to_byte_array().to_vec(),
+                        _ => sha256d::Hash::hash(&value).to_byte_array().to_vec(),
+                    };
+                    stack.push(hash);
+                }
+
+                OP_CODESEPARATOR => {
+                    // Signatures only commit to the script after the last executed separator
+                    begin_code_hash = pc;
+                    execdata.codesep_pos = opcode_pos;
+                }
+
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                    let sig = top(stack, 2)?;
+                    let pubkey = top(stack, 1)?;
+                    let success = eval_checksig(
+                        sig,
+                        pubkey,
+                        &script[begin_code_hash..],
+                        flags,
+                        checker,
+                        sigversion,
+                        execdata,
+                    )?;
+
+                    stack.truncate(stack.len() - 2);
+                    if opcode == OP_CHECKSIGVERIFY {
+                        if !success {
+                            return Err(ScriptError::CheckSigVerify);
+                        }
+                    } else {
+                        stack.push(encode_bool(success));
+                    }
+                }
+
+                OP_CHECKSIGADD => {
+                    // Only available in tapscript, where it replaces OP_CHECKMULTISIG
+                    if !is_tapscript {
+                        return Err(ScriptError::BadOpcode);
+                    }
+
+                    let sig = top(stack, 3)?;
+                    let n = decode_num(top(stack, 2)?, DEFAULT_MAX_NUM_SIZE)?;
+                    let pubkey = top(stack, 1)?;
+                    let success = eval_checksig(
+                        sig,
+                        pubkey,
+                        &script[begin_code_hash..],
+                        flags,
+                        checker,
+                        sigversion,
+                        execdata,
+                    )?;
+
+                    stack.truncate(stack.len() - 3);
+                    stack.push(encode_num(n + success as i64));
+                }
+
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    if is_tapscript {
+                        return Err(ScriptError::TapscriptCheckMultisig);
+                    }
+
+                    let success = eval_checkmultisig(
+                        stack,
+                        &script[begin_code_hash..],
+                        flags,
+                        checker,
+                        sigversion,
+                        &mut op_count,
+                    )?;
+
+                    if opcode == OP_CHECKMULTISIGVERIFY {
+                        if !success {
+                            return Err(ScriptError::CheckMultisigVerify);
+                        }
+                    } else {
+                        stack.push(encode_bool(success));
+                    }
+                }
+
+                _ => return Err(ScriptError::BadOpcode),
+            }
+        }
+
+        check_stack_size(stack, &altstack)?;
+        opcode_pos += 1;
+    }
+
+    if !exec_stack.is_empty() {
+        return Err(ScriptError::UnbalancedConditional);
+    }
+
+    Ok(())
+}
+
+/// The stack and altstack together can't have more than [MAX_STACK_SIZE] elements
+fn check_stack_size(stack: &[Vec<u8>], altstack: &[Vec<u8>]) -> Result<(), ScriptError> {
+    match stack.len() + altstack.len() > MAX_STACK_SIZE {
+        true => Err(ScriptError::StackSize),
+        false => Ok(()),
+    }
+}
+
+/// Executes `OP_CHECKMULTISIG`, removing its arguments from the stack and returning whether
+/// the signatures are valid. Signatures must be in the same order as their public keys, and
+/// because of an off-by-one bug in the original implementation, an extra dummy element is
+/// consumed, which must be empty since BIP 147.
+fn eval_checkmultisig(
+    stack: &mut Vec<Vec<u8>>,
+    script_code: &[u8],
+    flags: u32,
+    checker: &mut SignatureChecker,
+    sigversion: SigVersion,
+    op_count: &mut usize,
+) -> Result<bool, ScriptError> {
+    let mut i = 1;
+    let keys_count = decode_num(top(stack, i)?, DEFAULT_MAX_NUM_SIZE)?;
+    if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&keys_count) {
+        return Err(ScriptError::PubkeyCount);
+    }
+    let mut keys_count = keys_count as usize;
+
+    *op_count += keys_count;
+    if *op_count > MAX_OPS_PER_SCRIPT {
+        return Err(ScriptError::OpCount);
+    }
+
+    i += 1;
+    let mut key_index = i;
+    i += keys_count;
+
+    let sigs_count = decode_num(top(stack, i)?, DEFAULT_MAX_NUM_SIZE)?;
+    if sigs_count < 0 || sigs_count as usize > keys_count {
+        return Err(ScriptError::SigCount);
+    }
+    let mut sigs_count = sigs_count as usize;
+
+    i += 1;
+    let mut sig_index = i;
+    i += sigs_count;
+    require(stack, i)?;
+
+    // Legacy signatures can't sign themselves, so they are removed from the script code
+    let mut script_code = script_code.to_vec();
+    if sigversion == SigVersion::Base {
+        for k in 0..sigs_count {
+            let sig = top(stack, sig_index + k)?;
+            find_and_delete(&mut script_code, &push_data_script(sig));
+        }
+    }
+
+    let mut success = true;
+    while success && sigs_count > 0 {
+        let sig = top(stack, sig_index)?;
+        let pubkey = top(stack, key_index)?;
+        check_signature_encoding(sig, flags)?;
+
+        if checker.check_ecdsa_signature(sig, pubkey, &script_code, sigversion) {
+            sig_index += 1;
+            sigs_count -= 1;
+        }
+        key_index += 1;
+        keys_count -= 1;
+
+        // There aren't enough keys left for the remaining signatures
+        if sigs_count > keys_count {
+            success = false;
+        }
+    }
+
+    // Remove the keys, signatures and counts
+    stack.truncate(stack.len() - (i - 1));
+
+    let dummy = pop(stack)?;
+    if flags & verify_flags::VERIFY_NULLDUMMY != 0 && !dummy.is_empty() {
+        return Err(ScriptError::SigNullDummy);
+    }
+
+    Ok(success)
+}
+
+/// Runs a witness script (or the implied P2WPKH script) with the initial stack from the
+/// witness, and checks that it leaves exactly one true element.
+pub(super) fn execute_witness_script(
+    mut stack: Vec<Vec<u8>>,
+    script: &[u8],
+    flags: u32,
+    sigversion: SigVersion,
+    checker: &mut SignatureChecker,
+    execdata: &mut ExecData,
+) -> Result<(), ScriptError> {
+    if sigversion == SigVersion::Tapscript {
+        // OP_SUCCESSx overrides everything, including the stack element size limits below
+        let mut pc = 0;
+        while pc < script.len() {
+            let (opcode, _) = get_op(script, &mut pc).ok_or(ScriptError::BadOpcode)?;
+            if is_op_success(opcode.to_u8()) {
+                return Ok(());
+            }
+        }
+
+        if stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+
+    if stack
+        .iter()
+        .any(|elem| elem.len() > MAX_SCRIPT_ELEMENT_SIZE)
+    {
+        return Err(ScriptError::PushSize);
+    }
+
+    eval_script(&mut stack, script, flags, checker, sigversion, execdata)?;
+
+    // Witness scripts must leave a clean stack
+    if stack.len() != 1 {
+        return Err(ScriptError::CleanStack);
+    }
+    if !cast_to_bool(&stack[0]) {
+        return Err(ScriptError::EvalFalse);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_num() {
+        let cases: [(i64, &[u8]); 7] = [
+            (0, &[]),
+            (1, &[0x01]),
+            (-1, &[0x81]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x00]),
+            (-128, &[0x80, 0x80]),
+            (0x7fff_ffff, &[0xff, 0xff, 0xff, 0x7f]),
+        ];
+        for (value, bytes) in cases {
+            assert_eq!(encode_num(value), bytes);
+            assert_eq!(decode_num(bytes, DEFAULT_MAX_NUM_SIZE), Ok(value));
+        }
+
+        // Non-minimal encodings are accepted, but not longer than allowed
+        assert_eq!(decode_num(&[0x01, 0x00], DEFAULT_MAX_NUM_SIZE), Ok(1));
+        assert_eq!(decode_num(&[0x00, 0x80], DEFAULT_MAX_NUM_SIZE), Ok(0));
+        assert_eq!(
+            decode_num(&[0; 5], DEFAULT_MAX_NUM_SIZE),
+            Err(ScriptError::NumOverflow)
+        );
+        assert_eq!(
+            decode_num(&[0xff; 5], LOCKTIME_MAX_NUM_SIZE),
+            Ok(-0x7f_ffff_ffff)
+        );
+    }
+
+    #[test]
+    fn test_cast_to_bool() {
+        assert!(!cast_to_bool(&[]));
+        assert!(!cast_to_bool(&[0x00, 0x00]));
+        assert!(!cast_to_bool(&[0x00, 0x80]));
+        assert!(cast_to_bool(&[0x80, 0x00]));
+        assert!(cast_to_bool(&[0x01]));
+    }
+
+    #[test]
+    fn test_condition_stack() {
+        let mut stack = ConditionStack::default();
+        stack.push(true);
+        stack.push(false);
+        stack.push(true);
+        assert!(!stack.all_true());
+
+        stack.toggle_top();
+        assert!(!stack.all_true());
+        stack.pop();
+        stack.toggle_top();
+        assert!(stack.all_true());
+        stack.pop();
+        stack.pop();
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn test_find_and_delete() {
+        // Only matches at opcode boundaries are removed
+        let sig = push_data_script(&[0xab]);
+        let mut script = [&sig[..], &[0x02, 0x01, 0xab], &sig[..]].concat();
+        assert_eq!(find_and_delete(&mut script, &sig), 2);
+        assert_eq!(script, [0x02, 0x01, 0xab]);
+
+        let mut script = vec![OP_CODESEPARATOR.to_u8(), 0x01];
+        assert_eq!(remove_code_separators(&script), [0x01]);
+        assert_eq!(find_and_delete(&mut script, &[0x02]), 0);
+    }
+}
@@ -0,0 +1,903 @@
+//! A pure-Rust script interpreter, used to verify transaction scripts when `floresta-chain` is
+//! built without the `bitcoinconsensus` feature (e.g. for wasm, or without a C toolchain).
+//!
+//! It supports legacy scripts, P2SH (BIP 16), segwit v0 (BIPs 141 and 143) and taproot (BIPs 341
+//! and 342), selected by the same [verify_flags] bitflags returned by
+//! [ChainParams::get_validation_flags](crate::ChainParams::get_validation_flags). This is a port
+//! of Bitcoin Core's `VerifyScript`, but only consensus rules are enforced: policy flags such as
+//! CLEANSTACK or MINIMALDATA are rejected with [ScriptError::InvalidFlags], the same way
+//! `libbitcoinconsensus` does.
+
+mod checker;
+mod eval;
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::HashEngine;
+use bitcoin::key::Parity;
+use bitcoin::opcodes::all::OP_CHECKSIG;
+use bitcoin::opcodes::all::OP_DUP;
+use bitcoin::opcodes::all::OP_EQUAL;
+use bitcoin::opcodes::all::OP_EQUALVERIFY;
+use bitcoin::opcodes::all::OP_HASH160;
+use bitcoin::opcodes::all::OP_PUSHBYTES_20;
+use bitcoin::opcodes::all::OP_PUSHNUM_1;
+use bitcoin::opcodes::all::OP_PUSHNUM_16;
+use bitcoin::secp256k1::Scalar;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::sighash::Annex;
+use bitcoin::taproot::TapLeafHash;
+use bitcoin::taproot::TapNodeHash;
+use bitcoin::taproot::TapTweakHash;
+use bitcoin::taproot::TAPROOT_ANNEX_PREFIX;
+use bitcoin::taproot::TAPROOT_CONTROL_BASE_SIZE;
+use bitcoin::taproot::TAPROOT_CONTROL_MAX_SIZE;
+use bitcoin::taproot::TAPROOT_CONTROL_NODE_SIZE;
+use bitcoin::taproot::TAPROOT_LEAF_MASK;
+use bitcoin::taproot::TAPROOT_LEAF_TAPSCRIPT;
+use bitcoin::Transaction;
+use bitcoin::TxOut;
+use bitcoin::VarInt;
+use bitcoin::Witness;
+use checker::SignatureChecker;
+use checker::TxContext;
+use eval::cast_to_bool;
+use eval::eval_script;
+use eval::execute_witness_script;
+use eval::is_push_only;
+use eval::push_data_script;
+use eval::ExecData;
+use eval::SigVersion;
+use eval::VALIDATION_WEIGHT_PER_SIGOP_PASSED;
+
+use crate::prelude::*;
+use crate::pruned_utreexo::chainparams::verify_flags;
+
+/// All the flags this interpreter knows about. Anything else is a policy rule, and is rejected.
+pub const SUPPORTED_FLAGS: u32 = verify_flags::VERIFY_P2SH
+    | verify_flags::VERIFY_DERSIG
+    | verify_flags::VERIFY_NULLDUMMY
+    | verify_flags::VERIFY_CHECKLOCKTIMEVERIFY
+    | verify_flags::VERIFY_CHECKSEQUENCEVERIFY
+    | verify_flags::VERIFY_WITNESS
+    | verify_flags::VERIFY_TAPROOT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The reasons a script may fail, mirroring Bitcoin Core's `ScriptError`.
+pub enum ScriptError {
+    /// The script finished with an empty or false top stack element
+    EvalFalse,
+    /// `OP_RETURN` was executed
+    OpReturn,
+    /// The script is larger than 10,000 bytes
+    ScriptSize,
+    /// A pushed element is larger than 520 bytes
+    PushSize,
+    /// More than 201 non-push operations were executed
+    OpCount,
+    /// The stack and altstack have more than 1,000 elements
+    StackSize,
+    /// Invalid `OP_CHECKMULTISIG` signature count
+    SigCount,
+    /// Invalid `OP_CHECKMULTISIG` public key count
+    PubkeyCount,
+    /// `OP_VERIFY` failed
+    Verify,
+    /// `OP_EQUALVERIFY` failed
+    EqualVerify,
+    /// `OP_CHECKMULTISIGVERIFY` failed
+    CheckMultisigVerify,
+    /// `OP_CHECKSIGVERIFY` failed
+    CheckSigVerify,
+    /// `OP_NUMEQUALVERIFY` failed
+    NumEqualVerify,
+    /// The script has an invalid or truncated opcode
+    BadOpcode,
+    /// The script has a disabled opcode
+    DisabledOpcode,
+    /// An operation needed more stack elements than available
+    InvalidStackOperation,
+    /// `OP_FROMALTSTACK` was executed with an empty altstack
+    InvalidAltstackOperation,
+    /// `OP_IF` and `OP_ENDIF` don't match
+    UnbalancedConditional,
+    /// A numeric argument is longer than allowed
+    NumOverflow,
+    /// `OP_CHECKLOCKTIMEVERIFY` or `OP_CHECKSEQUENCEVERIFY` got a negative argument
+    NegativeLocktime,
+    /// The lock time required by the script isn't satisfied
+    UnsatisfiedLocktime,
+    /// A signature isn't strictly DER encoded (BIP 66)
+    SigDer,
+    /// The `OP_CHECKMULTISIG` dummy element isn't empty (BIP 147)
+    SigNullDummy,
+    /// A P2SH scriptSig has non-push operations
+    SigPushOnly,
+    /// An empty public key was used in tapscript
+    PubkeyType,
+    /// A witness script didn't leave exactly one element on the stack
+    CleanStack,
+    /// The witness program has an invalid length
+    WitnessProgramWrongLength,
+    /// A witness program was spent with an empty witness
+    WitnessProgramWitnessEmpty,
+    /// The witness doesn't match the witness program
+    WitnessProgramMismatch,
+    /// A native witness program was spent with a non-empty scriptSig
+    WitnessMalleated,
+    /// A P2SH witness program was spent with something besides the redeem script push
+    WitnessMalleatedP2sh,
+    /// A non-witness output was spent with a witness
+    WitnessUnexpected,
+    /// A schnorr signature is invalid
+    SchnorrSig,
+    /// A schnorr signature has an invalid size
+    SchnorrSigSize,
+    /// A schnorr signature has an invalid sighash type
+    SchnorrSigHashtype,
+    /// The taproot control block has an invalid size
+    TaprootWrongControlSize,
+    /// The tapscript did too many signature checks for its witness size
+    TapscriptValidationWeight,
+    /// `OP_CHECKMULTISIG` was used in tapscript
+    TapscriptCheckMultisig,
+    /// The `OP_IF` argument in tapscript isn't exactly empty or 0x01
+    TapscriptMinimalIf,
+    /// The verification flags have policy or unknown rules
+    InvalidFlags,
+    /// The number of spent outputs doesn't match the transaction inputs
+    SpentOutputsMismatch,
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ScriptError::EvalFalse => {
+                "Script evaluated without error but finished with a false/empty top stack element"
+            }
+            ScriptError::OpReturn => "OP_RETURN was encountered",
+            ScriptError::ScriptSize => "Script is too big",
+            ScriptError::PushSize => "Push value size limit exceeded",
+            ScriptError::OpCount => "Operation limit exceeded",
+            ScriptError::StackSize => "Stack size limit exceeded",
+            ScriptError::SigCount => "Signature count negative or greater than pubkey count",
+            ScriptError::PubkeyCount => "Pubkey count negative or limit exceeded",
+            ScriptError::Verify => "Script failed an OP_VERIFY operation",
+            ScriptError::EqualVerify => "Script failed an OP_EQUALVERIFY operation",
+            ScriptError::CheckMultisigVerify => "Script failed an OP_CHECKMULTISIGVERIFY operation",
+            ScriptError::CheckSigVerify => "Script failed an OP_CHECKSIGVERIFY operation",
+            ScriptError::NumEqualVerify => "Script failed an OP_NUMEQUALVERIFY operation",
+            ScriptError::BadOpcode => "Opcode missing or not understood",
+            ScriptError::DisabledOpcode => "Attempted to use a disabled opcode",
+            ScriptError::InvalidStackOperation => "Operation not valid with the current stack size",
+            ScriptError::InvalidAltstackOperation => {
+                "Operation not valid with the current altstack size"
+            }
+            ScriptError::UnbalancedConditional => "Invalid OP_IF construction",
+            ScriptError::NumOverflow => "Script number overflow",
+            ScriptError::NegativeLocktime => "Negative locktime",
+            ScriptError::UnsatisfiedLocktime => "Locktime requirement not satisfied",
+            ScriptError::SigDer => "Non-canonical DER signature",
+            ScriptError::SigNullDummy => "Dummy CHECKMULTISIG argument must be zero",
+            ScriptError::SigPushOnly => "Only push operators allowed in signatures",
+            ScriptError::PubkeyType => "Public key is neither compressed or uncompressed",
+            ScriptError::CleanStack => "Stack size must be exactly one after execution",
+            ScriptError::WitnessProgramWrongLength => "Witness program has incorrect length",
+            ScriptError::WitnessProgramWitnessEmpty => {
+                "Witness program was passed an empty witness"
+            }
+            ScriptError::WitnessProgramMismatch => "Witness program hash mismatch",
+            ScriptError::WitnessMalleated => "Witness requires empty scriptSig",
+            ScriptError::WitnessMalleatedP2sh => "Witness requires only-redeemscript scriptSig",
+            ScriptError::WitnessUnexpected => "Witness provided for non-witness script",
+            ScriptError::SchnorrSig => "Invalid Schnorr signature",
+            ScriptError::SchnorrSigSize => "Invalid Schnorr signature size",
+            ScriptError::SchnorrSigHashtype => "Invalid Schnorr signature hash type",
+            ScriptError::TaprootWrongControlSize => "Invalid Taproot control block size",
+            ScriptError::TapscriptValidationWeight => {
+                "Too much signature validation relative to witness weight"
+            }
+            ScriptError::TapscriptCheckMultisig => {
+                "OP_CHECKMULTISIG(VERIFY) is not available in tapscript"
+            }
+            ScriptError::TapscriptMinimalIf => "OP_IF/NOTIF argument must be minimal in tapscript",
+            ScriptError::InvalidFlags => "Invalid script verification flags",
+            ScriptError::SpentOutputsMismatch => "Spent outputs don't match the transaction inputs",
+        };
+        write!(f, "{msg}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A script failure, together with the input that caused it.
+pub struct ScriptVerifyError {
+    /// The index of the input whose scripts failed
+    pub input_index: usize,
+
+    /// Why the scripts failed
+    pub error: ScriptError,
+}
+
+impl Display for ScriptVerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Input {}: {}", self.input_index, self.error)
+    }
+}
+
+/// Verifies the scripts of all inputs in `tx`, where `spent_outputs` are the outputs being spent,
+/// in the same order as the inputs. Amounts and all spent outputs are required for the segwit
+/// and taproot signature hashes.
+pub fn verify_scripts(
+    tx: &Transaction,
+    spent_outputs: &[TxOut],
+    flags: u32,
+) -> Result<(), ScriptVerifyError> {
+    let fail = |input_index, error| ScriptVerifyError { input_index, error };
+
+    if flags & !SUPPORTED_FLAGS != 0 {
+        return Err(fail(0, ScriptError::InvalidFlags));
+    }
+    if spent_outputs.len() != tx.input.len() {
+        return Err(fail(0, ScriptError::SpentOutputsMismatch));
+    }
+
+    let mut ctx = TxContext::new(tx, spent_outputs);
+    for (index, (input, spent)) in tx.input.iter().zip(spent_outputs).enumerate() {
+        let mut checker = SignatureChecker::new(&mut ctx, index);
+        verify_script(
+            input.script_sig.as_bytes(),
+            spent.script_pubkey.as_bytes(),
+            &input.witness,
+            flags,
+            &mut checker,
+        )
+        .map_err(|error| fail(index, error))?;
+    }
+
+    Ok(())
+}
+
+/// Returns the version and program of a witness output script, if it is one
+fn witness_program(script: &[u8]) -> Option<(u8, &[u8])> {
+    if !(4..=42).contains(&script.len()) || script[1] as usize + 2 != script.len() {
+        return None;
+    }
+
+    match script[0] {
+        0 => Some((0, &script[2..])),
+        op if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op) => {
+            Some((op - OP_PUSHNUM_1.to_u8() + 1, &script[2..]))
+        }
+        _ => None,
+    }
+}
+
+fn is_p2sh(script: &[u8]) -> bool {
+    script.len() == 23
+        && script[0] == OP_HASH160.to_u8()
+        && script[1] == OP_PUSHBYTES_20.to_u8()
+        && script[22] == OP_EQUAL.to_u8()
+}
+
+/// Verifies a single input, given its scriptSig, witness and the spent scriptPubKey.
+fn verify_script(
+    script_sig: &[u8],
+    script_pubkey: &[u8],
+    witness: &Witness,
+    flags: u32,
+    checker: &mut SignatureChecker,
+) -> Result<(), ScriptError> {
+    let mut had_witness = false;
+    let mut execdata = ExecData::default();
+
+    // The scriptSig and scriptPubKey are evaluated sequentially on the same stack, rather than
+    // being concatenated, so the scriptSig can't affect the scriptPubKey's control flow
+    let mut stack = Vec::new();
+    eval_script(
+        &mut stack,
+        script_sig,
+        flags,
+        checker,
+        SigVersion::Base,
+        &mut execdata,
+    )?;
+
+    let stack_copy = match flags & verify_flags::VERIFY_P2SH != 0 {
+        true => stack.clone(),
+        false => Vec::new(),
+    };
+
+    eval_script(
+        &mut stack,
+        script_pubkey,
+        flags,
+        checker,
+        SigVersion::Base,
+        &mut execdata,
+    )?;
+    if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+        return Err(ScriptError::EvalFalse);
+    }
+
+    // Bare witness programs
+    if flags & verify_flags::VERIFY_WITNESS != 0 {
+        if let Some((version, program)) = witness_program(script_pubkey) {
+            had_witness = true;
+            if !script_sig.is_empty() {
+                return Err(ScriptError::WitnessMalleated);
+            }
+            verify_witness_program(witness, version, program, flags, checker, false)?;
+        }
+    }
+
+    // Additional validation for P2SH, where the scriptSig's last push is the redeem script
+    if flags & verify_flags::VERIFY_P2SH != 0 && is_p2sh(script_pubkey) {
+        if !is_push_only(script_sig) {
+            return Err(ScriptError::SigPushOnly);
+        }
+
+        // The stack can't be empty, or `OP_HASH160` would have failed above
+        let mut stack = stack_copy;
+        let redeem_script = stack.pop().ok_or(ScriptError::InvalidStackOperation)?;
+
+        eval_script(
+            &mut stack,
+            &redeem_script,
+            flags,
+            checker,
+            SigVersion::Base,
+            &mut execdata,
+        )?;
+        if !stack.last().is_some_and(|top| cast_to_bool(top)) {
+            return Err(ScriptError::EvalFalse);
+        }
+
+        // P2SH-wrapped witness programs
+        if flags & verify_flags::VERIFY_WITNESS != 0 {
+            if let Some((version, program)) = witness_program(&redeem_script) {
+                had_witness = true;
+                if script_sig != push_data_script(&redeem_script) {
+                    return Err(ScriptError::WitnessMalleatedP2sh);
+                }
+                verify_witness_program(witness, version, program, flags, checker, true)?;
+            }
+        }
+    }
+
+    // A witness can't be provided for non-witness outputs, or it could be malleated
+    if flags & verify_flags::VERIFY_WITNESS != 0 && !had_witness && !witness.is_empty() {
+        return Err(ScriptError::WitnessUnexpected);
+    }
+
+    Ok(())
+}
+
+/// Verifies a witness program spend. Unknown witness versions are reserved for soft forks, and
+/// always succeed.
+fn verify_witness_program(
+    witness: &Witness,
+    version: u8,
+    program: &[u8],
+    flags: u32,
+    checker: &mut SignatureChecker,
+    is_p2sh: bool,
+) -> Result<(), ScriptError> {
+    let mut stack = witness.to_vec();
+
+    match (version, program.len()) {
+        // P2WSH: the last witness element is the script, committed to by its sha256
+        (0, 32) => {
+            let script = stack.pop().ok_or(ScriptError::WitnessProgramWitnessEmpty)?;
+            if sha256::Hash::hash(&script).as_byte_array() != program {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+
+            let mut execdata = ExecData::default();
+            execute_witness_script(
+                stack,
+                &script,
+                flags,
+                SigVersion::WitnessV0,
+                checker,
+                &mut execdata,
+            )
+        }
+
+        // P2WPKH: the witness is a signature and a public key, for the implied P2PKH script
+        (0, 20) => {
+            if stack.len() != 2 {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+
+            let mut script = vec![OP_DUP.to_u8(), OP_HASH160.to_u8()];
+            script.extend(push_data_script(program));
+            script.extend([OP_EQUALVERIFY.to_u8(), OP_CHECKSIG.to_u8()]);
+
+            let mut execdata = ExecData::default();
+            execute_witness_script(
+                stack,
+                &script,
+                flags,
+                SigVersion::WitnessV0,
+                checker,
+                &mut execdata,
+            )
+        }
+
+        (0, _) => Err(ScriptError::WitnessProgramWrongLength),
+
+        // Taproot can't be wrapped in P2SH
+        (1, 32) if !is_p2sh => {
+            if flags & verify_flags::VERIFY_TAPROOT == 0 {
+                return Ok(());
+            }
+            verify_taproot(witness, stack, program, flags, checker)
+        }
+
+        _ => Ok(()),
+    }
+}
+
+/// Verifies a taproot spend, either with a signature for the output key (key path) or with a
+/// script committed to by the output key (script path).
+fn verify_taproot(
+    witness: &Witness,
+    mut stack: Vec<Vec<u8>>,
+    program: &[u8],
+    flags: u32,
+    checker: &mut SignatureChecker,
+) -> Result<(), ScriptError> {
+    if stack.is_empty() {
+        return Err(ScriptError::WitnessProgramWitnessEmpty);
+    }
+
+    // If there are at least two elements and the last one starts with 0x50, it's the annex
+    let has_annex = stack.len() >= 2
+        && stack
+            .last()
+            .is_some_and(|last| last.first() == Some(&TAPROOT_ANNEX_PREFIX));
+    let annex = match has_annex {
+        true => stack.pop(),
+        false => None,
+    };
+
+    let mut execdata = ExecData {
+        annex: annex.as_deref().and_then(|annex| Annex::new(annex).ok()),
+        ..Default::default()
+    };
+
+    // Key path spend, the only element is a signature for the output key
+    if stack.len() == 1 {
+        return checker.check_schnorr_signature(&stack[0], program, SigVersion::Taproot, &execdata);
+    }
+
+    // Script path spend, the last two elements are the control block and the script
+    let control = stack.pop().ok_or(ScriptError::InvalidStackOperation)?;
+    let script = stack.pop().ok_or(ScriptError::InvalidStackOperation)?;
+
+    if control.len() < TAPROOT_CONTROL_BASE_SIZE
+        || control.len() > TAPROOT_CONTROL_MAX_SIZE
+        || (control.len() - TAPROOT_CONTROL_BASE_SIZE) % TAPROOT_CONTROL_NODE_SIZE != 0
+    {
+        return Err(ScriptError::TaprootWrongControlSize);
+    }
+
+    let leaf_version = control[0] & TAPROOT_LEAF_MASK;
+    let tapleaf_hash = compute_tapleaf_hash(leaf_version, &script);
+    if !verify_taproot_commitment(&control, program, tapleaf_hash, checker) {
+        return Err(ScriptError::WitnessProgramMismatch);
+    }
+    execdata.tapleaf_hash = Some(tapleaf_hash);
+
+    // Unknown leaf versions are reserved for soft forks, and always succeed
+    if leaf_version != TAPROOT_LEAF_TAPSCRIPT {
+        return Ok(());
+    }
+
+    // Each signature check costs validation weight, and the budget grows with the witness size
+    execdata.validation_weight_left = witness.size() as i64 + VALIDATION_WEIGHT_PER_SIGOP_PASSED;
+    execute_witness_script(
+        stack,
+        &script,
+        flags,
+        SigVersion::Tapscript,
+        checker,
+        &mut execdata,
+    )
+}
+
+/// Computes the leaf hash manually, since [bitcoin::taproot::LeafVersion] doesn't accept every
+/// leaf version that can be committed to.
+fn compute_tapleaf_hash(leaf_version: u8, script: &[u8]) -> TapLeafHash {
+    let mut engine = TapLeafHash::engine();
+    engine.input(&[leaf_version]);
+    engine.input(&bitcoin::consensus::serialize(&VarInt(script.len() as u64)));
+    engine.input(script);
+
+    TapLeafHash::from_engine(engine)
+}
+
+/// Checks that the output key commits to the leaf hash, using the internal key and merkle path
+/// from the control block.
+fn verify_taproot_commitment(
+    control: &[u8],
+    program: &[u8],
+    tapleaf_hash: TapLeafHash,
+    checker: &SignatureChecker,
+) -> bool {
+    let Ok(internal_key) = XOnlyPublicKey::from_slice(&control[1..TAPROOT_CONTROL_BASE_SIZE])
+    else {
+        return false;
+    };
+    let Ok(output_key) = XOnlyPublicKey::from_slice(program) else {
+        return false;
+    };
+
+    let mut merkle_root = TapNodeHash::from(tapleaf_hash);
+    for sibling in control[TAPROOT_CONTROL_BASE_SIZE..].chunks_exact(TAPROOT_CONTROL_NODE_SIZE) {
+        let Ok(sibling) = TapNodeHash::from_slice(sibling) else {
+            return false;
+        };
+        merkle_root = TapNodeHash::from_node_hashes(merkle_root, sibling);
+    }
+
+    let tweak = TapTweakHash::from_key_and_tweak(internal_key, Some(merkle_root));
+    let Ok(tweak) = Scalar::from_be_bytes(tweak.to_byte_array()) else {
+        return false;
+    };
+    let parity = match control[0] & 1 {
+        0 => Parity::Even,
+        _ => Parity::Odd,
+    };
+
+    internal_key.tweak_add_check(checker.secp(), &output_key, parity, tweak)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::Keypair;
+    use bitcoin::key::TapTweak;
+    use bitcoin::opcodes::all::*;
+    use bitcoin::script::Builder;
+    use bitcoin::script::PushBytesBuf;
+    use bitcoin::secp256k1::Message;
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::sighash::EcdsaSighashType;
+    use bitcoin::sighash::Prevouts;
+    use bitcoin::sighash::SighashCache;
+    use bitcoin::sighash::TapSighashType;
+    use bitcoin::taproot::LeafVersion;
+    use bitcoin::taproot::TaprootBuilder;
+    use bitcoin::transaction::Version;
+    use bitcoin::Amount;
+    use bitcoin::CompressedPublicKey;
+    use bitcoin::OutPoint;
+    use bitcoin::PublicKey;
+    use bitcoin::ScriptBuf;
+    use bitcoin::Sequence;
+    use bitcoin::TxIn;
+
+    use super::*;
+
+    const ALL_FLAGS: u32 = SUPPORTED_FLAGS;
+
+    fn new_keypair(byte: u8) -> Keypair {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+        Keypair::from_secret_key(&secp, &secret)
+    }
+
+    fn new_prevout(script_pubkey: ScriptBuf) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(50_000),
+            script_pubkey,
+        }
+    }
+
+    /// Returns a transaction spending a single output
+    fn spending_tx() -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(bitcoin::Txid::all_zeros(), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(40_000),
+                script_pubkey: ScriptBuf::new_op_return([]),
+            }],
+        }
+    }
+
+    fn verify(tx: &Transaction, prevout: &TxOut, flags: u32) -> Result<(), ScriptError> {
+        verify_scripts(tx, &[prevout.clone()], flags).map_err(|e| e.error)
+    }
+
+    fn push(data: &[u8]) -> PushBytesBuf {
+        PushBytesBuf::try_from(data.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_invalid_flags() {
+        let prevout = new_prevout(ScriptBuf::new());
+        let tx = spending_tx();
+
+        // CLEANSTACK is a policy flag
+        let flags = verify_flags::VERIFY_P2SH | verify_flags::VERIFY_WITNESS | (1 << 8);
+        assert_eq!(verify(&tx, &prevout, flags), Err(ScriptError::InvalidFlags));
+        assert_eq!(
+            verify_scripts(&tx, &[], ALL_FLAGS).map_err(|e| e.error),
+            Err(ScriptError::SpentOutputsMismatch)
+        );
+    }
+
+    #[test]
+    fn test_p2pkh() {
+        let secp = Secp256k1::new();
+        let keypair = new_keypair(1);
+        let pubkey = PublicKey::new(keypair.public_key());
+        let prevout = new_prevout(ScriptBuf::new_p2pkh(&pubkey.pubkey_hash()));
+
+        let mut tx = spending_tx();
+        let sighash = SighashCache::new(&tx)
+            .legacy_signature_hash(0, &prevout.script_pubkey, EcdsaSighashType::All.to_u32())
+            .unwrap();
+        let msg = Message::from_digest(sighash.to_byte_array());
+        let mut sig = secp
+            .sign_ecdsa(&msg, &keypair.secret_key())
+            .serialize_der()
+            .to_vec();
+        sig.push(EcdsaSighashType::All as u8);
+
+        tx.input[0].script_sig = Builder::new()
+            .push_slice(push(&sig))
+            .push_key(&pubkey)
+            .into_script();
+        assert_eq!(verify(&tx, &prevout, ALL_FLAGS), Ok(()));
+
+        // A signature for another key makes OP_CHECKSIG push false
+        let other = PublicKey::new(new_keypair(2).public_key());
+        let prevout = new_prevout(ScriptBuf::new_p2pk(&other));
+        tx.input[0].script_sig = Builder::new().push_slice(push(&sig)).into_script();
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::EvalFalse)
+        );
+    }
+
+    #[test]
+    fn test_p2wpkh() {
+        let secp = Secp256k1::new();
+        let keypair = new_keypair(3);
+        let pubkey = CompressedPublicKey(keypair.public_key());
+        let prevout = new_prevout(ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()));
+
+        let mut tx = spending_tx();
+        let sighash = SighashCache::new(&tx)
+            .p2wpkh_signature_hash(
+                0,
+                &prevout.script_pubkey,
+                prevout.value,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let msg = Message::from_digest(sighash.to_byte_array());
+        let mut sig = secp
+            .sign_ecdsa(&msg, &keypair.secret_key())
+            .serialize_der()
+            .to_vec();
+        sig.push(EcdsaSighashType::All as u8);
+
+        tx.input[0].witness = Witness::from_slice(&[sig.clone(), pubkey.to_bytes().to_vec()]);
+        assert_eq!(verify(&tx, &prevout, ALL_FLAGS), Ok(()));
+
+        // The signature commits to the amount
+        let mut other_amount = prevout.clone();
+        other_amount.value = Amount::from_sat(50_001);
+        assert_eq!(
+            verify(&tx, &other_amount, ALL_FLAGS),
+            Err(ScriptError::EvalFalse)
+        );
+
+        // Without segwit, this is an anyone-can-spend output, but the witness is not allowed
+        let flags = verify_flags::VERIFY_P2SH;
+        assert_eq!(verify(&tx, &prevout, flags), Ok(()));
+
+        tx.input[0].witness = Witness::from_slice(&[sig]);
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::WitnessProgramMismatch)
+        );
+    }
+
+    #[test]
+    fn test_taproot_key_path() {
+        let secp = Secp256k1::new();
+        let keypair = new_keypair(4);
+        let (internal_key, _) = keypair.x_only_public_key();
+        let prevout = new_prevout(ScriptBuf::new_p2tr(&secp, internal_key, None));
+
+        let mut tx = spending_tx();
+        let sighash = SighashCache::new(&tx)
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&[prevout.clone()]),
+                TapSighashType::Default,
+            )
+            .unwrap();
+        let msg = Message::from_digest(sighash.to_byte_array());
+        let tweaked = keypair.tap_tweak(&secp, None).to_keypair();
+        let sig = secp.sign_schnorr_no_aux_rand(&msg, &tweaked);
+
+        tx.input[0].witness = Witness::from_slice(&[sig.as_ref().to_vec()]);
+        assert_eq!(verify(&tx, &prevout, ALL_FLAGS), Ok(()));
+
+        // SIGHASH_DEFAULT can't be explicit
+        let mut explicit_default = sig.as_ref().to_vec();
+        explicit_default.push(0);
+        tx.input[0].witness = Witness::from_slice(&[explicit_default]);
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::SchnorrSigHashtype)
+        );
+
+        // A signature from the untweaked key is invalid, unless taproot is not active
+        let untweaked = secp.sign_schnorr_no_aux_rand(&msg, &keypair);
+        tx.input[0].witness = Witness::from_slice(&[untweaked.as_ref().to_vec()]);
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::SchnorrSig)
+        );
+        let flags = ALL_FLAGS & !verify_flags::VERIFY_TAPROOT;
+        assert_eq!(verify(&tx, &prevout, flags), Ok(()));
+
+        tx.input[0].witness = Witness::new();
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::WitnessProgramWitnessEmpty)
+        );
+    }
+
+    #[test]
+    fn test_taproot_script_path() {
+        let secp = Secp256k1::new();
+        let (internal_key, _) = new_keypair(5).x_only_public_key();
+        let leaf_keypair = new_keypair(6);
+        let (leaf_key, _) = leaf_keypair.x_only_public_key();
+
+        let checksig = Builder::new()
+            .push_x_only_key(&leaf_key)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        let op_success = Builder::new()
+            .push_opcode(OP_RESERVED)
+            .push_opcode(OP_RETURN)
+            .into_script();
+        let minimal_if = Builder::new()
+            .push_opcode(OP_IF)
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(1, checksig.clone())
+            .unwrap()
+            .add_leaf(2, op_success.clone())
+            .unwrap()
+            .add_leaf(2, minimal_if.clone())
+            .unwrap()
+            .finalize(&secp, internal_key)
+            .unwrap();
+        let prevout = new_prevout(ScriptBuf::new_p2tr_tweaked(spend_info.output_key()));
+        let control = |script: &ScriptBuf| {
+            spend_info
+                .control_block(&(script.clone(), LeafVersion::TapScript))
+                .unwrap()
+                .serialize()
+        };
+
+        let mut tx = spending_tx();
+        let leaf_hash = TapLeafHash::from_script(&checksig, LeafVersion::TapScript);
+        let sighash = SighashCache::new(&tx)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::All(&[prevout.clone()]),
+                leaf_hash,
+                TapSighashType::All,
+            )
+            .unwrap();
+        let msg = Message::from_digest(sighash.to_byte_array());
+        let mut sig = secp
+            .sign_schnorr_no_aux_rand(&msg, &leaf_keypair)
+            .as_ref()
+            .to_vec();
+        sig.push(TapSighashType::All as u8);
+
+        let witness = [sig.clone(), checksig.to_bytes(), control(&checksig)];
+        tx.input[0].witness = Witness::from_slice(&witness);
+        assert_eq!(verify(&tx, &prevout, ALL_FLAGS), Ok(()));
+
+        // An empty signature makes OP_CHECKSIG push false
+        let witness = [vec![], checksig.to_bytes(), control(&checksig)];
+        tx.input[0].witness = Witness::from_slice(&witness);
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::EvalFalse)
+        );
+
+        // While an invalid one fails the script
+        let mut bad_sig = sig.clone();
+        bad_sig[0] ^= 1;
+        let witness = [bad_sig, checksig.to_bytes(), control(&checksig)];
+        tx.input[0].witness = Witness::from_slice(&witness);
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::SchnorrSig)
+        );
+
+        // The script must be committed to by the output key
+        let witness = [sig, op_success.to_bytes(), control(&checksig)];
+        tx.input[0].witness = Witness::from_slice(&witness);
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::WitnessProgramMismatch)
+        );
+
+        // OP_SUCCESS makes the script succeed before OP_RETURN is executed
+        let witness = [op_success.to_bytes(), control(&op_success)];
+        tx.input[0].witness = Witness::from_slice(&witness);
+        assert_eq!(verify(&tx, &prevout, ALL_FLAGS), Ok(()));
+
+        // The OP_IF argument must be exactly 0x01 to be true
+        let witness = [vec![1], minimal_if.to_bytes(), control(&minimal_if)];
+        tx.input[0].witness = Witness::from_slice(&witness);
+        assert_eq!(verify(&tx, &prevout, ALL_FLAGS), Ok(()));
+
+        let witness = [vec![2], minimal_if.to_bytes(), control(&minimal_if)];
+        tx.input[0].witness = Witness::from_slice(&witness);
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::TapscriptMinimalIf)
+        );
+
+        // The annex is removed before the control block and script
+        let witness = [
+            op_success.to_bytes(),
+            control(&op_success),
+            vec![TAPROOT_ANNEX_PREFIX],
+        ];
+        tx.input[0].witness = Witness::from_slice(&witness);
+        assert_eq!(verify(&tx, &prevout, ALL_FLAGS), Ok(()));
+
+        let witness = [op_success.to_bytes(), control(&op_success)[..40].to_vec()];
+        tx.input[0].witness = Witness::from_slice(&witness);
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::TaprootWrongControlSize)
+        );
+    }
+
+    #[test]
+    fn test_p2wsh_op_success_is_bad_opcode() {
+        // OP_SUCCESS opcodes only exist in tapscript
+        let script = Builder::new().push_opcode(OP_RESERVED).into_script();
+        let prevout = new_prevout(ScriptBuf::new_p2wsh(&script.wscript_hash()));
+
+        let mut tx = spending_tx();
+        tx.input[0].witness = Witness::from_slice(&[script.to_bytes()]);
+        assert_eq!(
+            verify(&tx, &prevout, ALL_FLAGS),
+            Err(ScriptError::BadOpcode)
+        );
+    }
+}
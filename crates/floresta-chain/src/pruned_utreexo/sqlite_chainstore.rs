@@ -0,0 +1,591 @@
+//! A [ChainStore] backed by SQLite
+//!
+//! Unlike the [FlatChainStore](crate::FlatChainStore) and [KvChainStore](crate::KvChainStore),
+//! this database can be inspected and queried with standard tools, like the `sqlite3` shell. Each
+//! kind of data we keep lives in its own table:
+//!
+//! - `headers`: the block hash (hex, display order), the validation status, the height (`NULL`
+//!   for orphan and invalid blocks) and the raw 80-byte header
+//! - `block_index`: maps each height in our best chain to its block hash
+//! - `best_chain`: a single row with our [BestChain]
+//! - `roots` and `undo`: the serialized accumulator and its undo data for each height
+//! - `meta`: miscellaneous blobs, like the fee estimator state
+//!
+//! Writes happen inside a transaction that is only committed on [ChainStore::flush], so a crash
+//! loses at most the data since the last flush, like with the other stores.
+
+extern crate std;
+
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+use core::str::FromStr;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::PoisonError;
+
+use bitcoin::block::Header as BlockHeader;
+use bitcoin::consensus::deserialize;
+use bitcoin::consensus::serialize;
+use bitcoin::BlockHash;
+use floresta_common::impl_error_from;
+use floresta_common::prelude::*;
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+
+use crate::BestChain;
+use crate::ChainStore;
+use crate::DatabaseError;
+use crate::DiskBlockHeader;
+
+/// The current version of our schema, stored as SQLite's `user_version`
+const SQLITE_CHAINSTORE_VERSION: u32 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS headers (
+    hash TEXT PRIMARY KEY NOT NULL,
+    status TEXT NOT NULL,
+    height INTEGER,
+    header BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS block_index (
+    height INTEGER PRIMARY KEY NOT NULL,
+    hash TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS best_chain (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    best_block TEXT NOT NULL,
+    depth INTEGER NOT NULL,
+    validation_index TEXT NOT NULL,
+    alternative_tips TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS roots (
+    height INTEGER PRIMARY KEY NOT NULL,
+    roots BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS undo (
+    height INTEGER PRIMARY KEY NOT NULL,
+    undo BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY NOT NULL,
+    value BLOB NOT NULL
+);
+";
+
+#[derive(Debug)]
+/// An error that can happen when we're dealing with our SQLite chain store
+pub enum SqliteChainStoreError {
+    /// SQLite returned an error
+    ///
+    /// Check the inner error for more information
+    Sqlite(rusqlite::Error),
+
+    /// Tried to open a database with an unsupported schema version
+    DbTooNew(u32),
+
+    /// Our connection lock is poisoned
+    Poisoned,
+
+    /// The database has data we can't decode, or failed SQLite's integrity check
+    DbCorrupted(String),
+}
+
+/// Need this to use [SqliteChainStoreError] as a [DatabaseError] in [ChainStore]
+impl DatabaseError for SqliteChainStoreError {}
+
+impl_error_from!(SqliteChainStoreError, rusqlite::Error, Sqlite);
+
+impl From<PoisonError<MutexGuard<'_, Connection>>> for SqliteChainStoreError {
+    fn from(_: PoisonError<MutexGuard<'_, Connection>>) -> Self {
+        SqliteChainStoreError::Poisoned
+    }
+}
+
+impl Display for SqliteChainStoreError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SqliteChainStoreError::Sqlite(e) => write!(f, "SQLite error: {e}"),
+            SqliteChainStoreError::DbTooNew(version) => {
+                write!(f, "Unsupported database version {version}")
+            }
+            SqliteChainStoreError::Poisoned => write!(f, "Database lock is poisoned"),
+            SqliteChainStoreError::DbCorrupted(reason) => {
+                write!(f, "Database is corrupted: {reason}")
+            }
+        }
+    }
+}
+
+/// A [ChainStore] that keeps everything in a single SQLite database file.
+pub struct SqliteChainStore {
+    /// The connection to our database. SQLite connections can't be shared between threads, so
+    /// we guard it with a mutex.
+    conn: Mutex<Connection>,
+}
+
+impl SqliteChainStore {
+    /// Opens the database at `path`, creating it and our tables if needed.
+    pub fn new(path: &str) -> Result<Self, SqliteChainStoreError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Creates a store that only lives in memory, useful for tests.
+    pub fn new_in_memory() -> Result<Self, SqliteChainStoreError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self, SqliteChainStoreError> {
+        let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SQLITE_CHAINSTORE_VERSION {
+            return Err(SqliteChainStoreError::DbTooNew(version));
+        }
+
+        // WAL mode makes commits much cheaper, and the database stays consistent on crashes
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA)?;
+        conn.pragma_update(None, "user_version", SQLITE_CHAINSTORE_VERSION)?;
+
+        Ok(SqliteChainStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Returns the connection, starting a new transaction if there's none pending. All writes go
+    /// through here, so they are only committed on [ChainStore::flush].
+    fn writer(&self) -> Result<MutexGuard<'_, Connection>, SqliteChainStoreError> {
+        let conn = self.conn.lock()?;
+        if conn.is_autocommit() {
+            conn.execute_batch("BEGIN")?;
+        }
+
+        Ok(conn)
+    }
+
+    fn parse_hash(hash: &str) -> Result<BlockHash, SqliteChainStoreError> {
+        BlockHash::from_str(hash)
+            .map_err(|_| SqliteChainStoreError::DbCorrupted(format!("invalid block hash {hash}")))
+    }
+
+    /// Returns the name of the status column for a [DiskBlockHeader], its height and header.
+    fn header_columns(header: &DiskBlockHeader) -> (&'static str, Option<u32>, Vec<u8>) {
+        let status = match header {
+            DiskBlockHeader::FullyValid(..) => "fully_valid",
+            DiskBlockHeader::AssumedValid(..) => "assumed_valid",
+            DiskBlockHeader::Orphan(..) => "orphan",
+            DiskBlockHeader::HeadersOnly(..) => "headers_only",
+            DiskBlockHeader::InFork(..) => "in_fork",
+            DiskBlockHeader::InvalidChain(..) => "invalid_chain",
+        };
+
+        (status, header.height(), serialize(&**header))
+    }
+
+    /// The inverse of [SqliteChainStore::header_columns]
+    fn header_from_columns(
+        status: &str,
+        height: Option<u32>,
+        header: &[u8],
+    ) -> Result<DiskBlockHeader, SqliteChainStoreError> {
+        let corrupted = |reason: &str| SqliteChainStoreError::DbCorrupted(reason.to_string());
+        let header: BlockHeader = deserialize(header).map_err(|_| corrupted("invalid header"))?;
+        let height = || height.ok_or_else(|| corrupted("missing header height"));
+
+        match status {
+            "fully_valid" => Ok(DiskBlockHeader::FullyValid(header, height()?)),
+            "assumed_valid" => Ok(DiskBlockHeader::AssumedValid(header, height()?)),
+            "orphan" => Ok(DiskBlockHeader::Orphan(header)),
+            "headers_only" => Ok(DiskBlockHeader::HeadersOnly(header, height()?)),
+            "in_fork" => Ok(DiskBlockHeader::InFork(header, height()?)),
+            "invalid_chain" => Ok(DiskBlockHeader::InvalidChain(header)),
+            _ => Err(corrupted("invalid header status")),
+        }
+    }
+
+    fn load_blob(
+        &self,
+        query: &str,
+        key: impl rusqlite::ToSql,
+    ) -> Result<Option<Vec<u8>>, SqliteChainStoreError> {
+        let conn = self.conn.lock()?;
+        let blob = conn.query_row(query, [key], |row| row.get(0)).optional()?;
+
+        Ok(blob)
+    }
+}
+
+impl ChainStore for SqliteChainStore {
+    type Error = SqliteChainStoreError;
+
+    /// Runs SQLite's `quick_check`, which detects most kinds of corruption without being as
+    /// slow as a full `integrity_check`.
+    fn check_integrity(&self) -> Result<(), Self::Error> {
+        let conn = self.conn.lock()?;
+        let result: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+
+        match result.as_str() {
+            "ok" => Ok(()),
+            _ => Err(SqliteChainStoreError::DbCorrupted(result)),
+        }
+    }
+
+    fn save_roots_for_block(&mut self, roots: Vec<u8>, height: u32) -> Result<(), Self::Error> {
+        self.writer()?.execute(
+            "INSERT OR REPLACE INTO roots (height, roots) VALUES (?1, ?2)",
+            params![height, roots],
+        )?;
+
+        Ok(())
+    }
+
+    fn load_roots_for_block(&mut self, height: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.load_blob("SELECT roots FROM roots WHERE height = ?1", height)
+    }
+
+    fn save_undo_for_block(&mut self, undo: Vec<u8>, height: u32) -> Result<(), Self::Error> {
+        self.writer()?.execute(
+            "INSERT OR REPLACE INTO undo (height, undo) VALUES (?1, ?2)",
+            params![height, undo],
+        )?;
+
+        Ok(())
+    }
+
+    fn load_undo_for_block(&mut self, height: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.load_blob("SELECT undo FROM undo WHERE height = ?1", height)
+    }
+
+    fn save_fee_estimates(&mut self, estimates: Vec<u8>) -> Result<(), Self::Error> {
+        self.writer()?.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('fee_estimates', ?1)",
+            params![estimates],
+        )?;
+
+        Ok(())
+    }
+
+    fn load_fee_estimates(&mut self) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.load_blob("SELECT value FROM meta WHERE key = ?1", "fee_estimates")
+    }
+
+    fn load_height(&self) -> Result<Option<BestChain>, Self::Error> {
+        let conn = self.conn.lock()?;
+        let row = conn
+            .query_row(
+                "SELECT best_block, depth, validation_index, alternative_tips
+                 FROM best_chain WHERE id = 0",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, u32>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        let Some((best_block, depth, validation_index, alternative_tips)) = row else {
+            return Ok(None);
+        };
+
+        let alternative_tips = alternative_tips
+            .split(',')
+            .filter(|tip| !tip.is_empty())
+            .map(Self::parse_hash)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some(BestChain {
+            best_block: Self::parse_hash(&best_block)?,
+            depth,
+            validation_index: Self::parse_hash(&validation_index)?,
+            alternative_tips,
+        }))
+    }
+
+    fn save_height(&mut self, height: &BestChain) -> Result<(), Self::Error> {
+        let alternative_tips = height
+            .alternative_tips
+            .iter()
+            .map(|tip| tip.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        self.writer()?.execute(
+            "INSERT OR REPLACE INTO best_chain
+             (id, best_block, depth, validation_index, alternative_tips)
+             VALUES (0, ?1, ?2, ?3, ?4)",
+            params![
+                height.best_block.to_string(),
+                height.depth,
+                height.validation_index.to_string(),
+                alternative_tips,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn get_header(&self, block_hash: &BlockHash) -> Result<Option<DiskBlockHeader>, Self::Error> {
+        let conn = self.conn.lock()?;
+        let row = conn
+            .query_row(
+                "SELECT status, height, header FROM headers WHERE hash = ?1",
+                [block_hash.to_string()],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<u32>>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        row.map(|(status, height, header)| Self::header_from_columns(&status, height, &header))
+            .transpose()
+    }
+
+    fn get_header_by_height(&self, height: u32) -> Result<Option<DiskBlockHeader>, Self::Error> {
+        match self.get_block_hash(height)? {
+            Some(hash) => self.get_header(&hash),
+            None => Ok(None),
+        }
+    }
+
+    fn save_header(&mut self, header: &DiskBlockHeader) -> Result<(), Self::Error> {
+        let (status, height, raw_header) = Self::header_columns(header);
+        self.writer()?.execute(
+            "INSERT OR REPLACE INTO headers (hash, status, height, header)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![header.block_hash().to_string(), status, height, raw_header],
+        )?;
+
+        Ok(())
+    }
+
+    fn get_block_hash(&self, height: u32) -> Result<Option<BlockHash>, Self::Error> {
+        let conn = self.conn.lock()?;
+        let hash: Option<String> = conn
+            .query_row(
+                "SELECT hash FROM block_index WHERE height = ?1",
+                [height],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        hash.as_deref().map(Self::parse_hash).transpose()
+    }
+
+    /// Commits the pending transaction, if any.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        let conn = self.conn.lock()?;
+        if !conn.is_autocommit() {
+            conn.execute_batch("COMMIT")?;
+        }
+
+        Ok(())
+    }
+
+    fn update_block_index(&mut self, height: u32, hash: BlockHash) -> Result<(), Self::Error> {
+        self.writer()?.execute(
+            "INSERT OR REPLACE INTO block_index (height, hash) VALUES (?1, ?2)",
+            params![height, hash.to_string()],
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::constants::genesis_block;
+    use bitcoin::hashes::Hash;
+    use bitcoin::BlockHash;
+    use bitcoin::Network;
+    use tempfile::TempDir;
+
+    use super::SqliteChainStore;
+    use super::SqliteChainStoreError;
+    use crate::migrate_chain_store;
+    use crate::BestChain;
+    use crate::BlockchainError;
+    use crate::ChainStore;
+    use crate::DiskBlockHeader;
+
+    #[test]
+    fn test_headers_and_index() {
+        let mut store = SqliteChainStore::new_in_memory().unwrap();
+        let header = genesis_block(Network::Regtest).header;
+        let hash = header.block_hash();
+
+        let headers = [
+            DiskBlockHeader::FullyValid(header, 0),
+            DiskBlockHeader::AssumedValid(header, 1),
+            DiskBlockHeader::Orphan(header),
+            DiskBlockHeader::HeadersOnly(header, 2),
+            DiskBlockHeader::InFork(header, 3),
+            DiskBlockHeader::InvalidChain(header),
+        ];
+
+        // Saving the same hash again replaces the header, e.g. after it gets validated
+        for disk_header in headers {
+            store.save_header(&disk_header).unwrap();
+            assert_eq!(store.get_header(&hash).unwrap(), Some(disk_header));
+        }
+        assert_eq!(store.get_header(&BlockHash::all_zeros()).unwrap(), None);
+
+        store.update_block_index(0, hash).unwrap();
+        assert_eq!(store.get_block_hash(0).unwrap(), Some(hash));
+        assert_eq!(store.get_block_hash(1).unwrap(), None);
+        assert_eq!(
+            store.get_header_by_height(0).unwrap(),
+            Some(DiskBlockHeader::InvalidChain(header))
+        );
+        store.check_integrity().unwrap();
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("chainstore.sqlite");
+        let path = path.to_str().unwrap();
+
+        let genesis = genesis_block(Network::Regtest).header;
+        let best_chain = BestChain {
+            best_block: genesis.block_hash(),
+            depth: 0,
+            validation_index: genesis.block_hash(),
+            alternative_tips: vec![BlockHash::all_zeros(), genesis.block_hash()],
+        };
+
+        {
+            let mut store = SqliteChainStore::new(path).unwrap();
+            store.save_height(&best_chain).unwrap();
+            store.save_roots_for_block(vec![1, 2, 3], 0).unwrap();
+            store.save_undo_for_block(vec![4, 5], 0).unwrap();
+            store.save_fee_estimates(vec![6]).unwrap();
+            store
+                .save_header(&DiskBlockHeader::FullyValid(genesis, 0))
+                .unwrap();
+            store.flush().unwrap();
+
+            // This one is never flushed, so it's lost
+            store.save_roots_for_block(vec![7], 1).unwrap();
+        }
+
+        let mut store = SqliteChainStore::new(path).unwrap();
+        assert_eq!(store.load_height().unwrap(), Some(best_chain));
+        assert_eq!(store.load_roots_for_block(0).unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(store.load_roots_for_block(1).unwrap(), None);
+        assert_eq!(store.load_undo_for_block(0).unwrap(), Some(vec![4, 5]));
+        assert_eq!(store.load_fee_estimates().unwrap(), Some(vec![6]));
+        assert_eq!(
+            store.get_header(&genesis.block_hash()).unwrap(),
+            Some(DiskBlockHeader::FullyValid(genesis, 0))
+        );
+    }
+
+    #[test]
+    fn test_db_too_new() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("chainstore.sqlite");
+        let path = path.to_str().unwrap();
+
+        let conn = rusqlite::Connection::open(path).unwrap();
+        conn.pragma_update(None, "user_version", 2).unwrap();
+        drop(conn);
+
+        assert!(matches!(
+            SqliteChainStore::new(path),
+            Err(SqliteChainStoreError::DbTooNew(2))
+        ));
+    }
+
+    #[test]
+    fn test_migrate_chain_store() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let mut source = SqliteChainStore::new_in_memory().unwrap();
+
+        // A best chain with three blocks, and a one-block fork off the first one
+        let mut headers = vec![genesis];
+        for nonce in 1..3 {
+            let mut header = genesis;
+            header.prev_blockhash = headers.last().unwrap().block_hash();
+            header.nonce = nonce;
+            headers.push(header);
+        }
+
+        for (height, header) in headers.iter().enumerate() {
+            let height = height as u32;
+            source
+                .save_header(&DiskBlockHeader::FullyValid(*header, height))
+                .unwrap();
+            source
+                .update_block_index(height, header.block_hash())
+                .unwrap();
+            source
+                .save_roots_for_block(vec![height as u8], height)
+                .unwrap();
+            source.save_undo_for_block(vec![0xff], height).unwrap();
+        }
+
+        let mut fork = headers[1];
+        fork.nonce = 42;
+        source
+            .save_header(&DiskBlockHeader::InFork(fork, 1))
+            .unwrap();
+        source.save_fee_estimates(vec![1, 2]).unwrap();
+
+        let best_chain = BestChain {
+            best_block: headers[2].block_hash(),
+            depth: 2,
+            validation_index: headers[2].block_hash(),
+            alternative_tips: vec![fork.block_hash()],
+        };
+        source.save_height(&best_chain).unwrap();
+
+        // An interrupted migration, that copied a header and the fork, but not the best chain
+        let mut dest = SqliteChainStore::new_in_memory().unwrap();
+        dest.save_header(&DiskBlockHeader::FullyValid(genesis, 0))
+            .unwrap();
+        dest.update_block_index(0, genesis.block_hash()).unwrap();
+        dest.save_header(&DiskBlockHeader::InFork(fork, 1)).unwrap();
+
+        // Running it again resumes it, without copying the fork twice
+        assert_eq!(migrate_chain_store(&mut source, &mut dest).unwrap(), 3);
+
+        assert_eq!(dest.load_height().unwrap(), Some(best_chain));
+        for height in 0..3 {
+            assert_eq!(
+                dest.get_header_by_height(height).unwrap(),
+                source.get_header_by_height(height).unwrap()
+            );
+            assert_eq!(
+                dest.load_roots_for_block(height).unwrap(),
+                Some(vec![height as u8])
+            );
+            assert_eq!(dest.load_undo_for_block(height).unwrap(), Some(vec![0xff]));
+        }
+        assert_eq!(
+            dest.get_header(&fork.block_hash()).unwrap(),
+            Some(DiskBlockHeader::InFork(fork, 1))
+        );
+        assert_eq!(dest.load_fee_estimates().unwrap(), Some(vec![1, 2]));
+
+        // We refuse to overwrite a store that already has data, and need an initialized source
+        assert!(matches!(
+            migrate_chain_store(&mut source, &mut dest),
+            Err(BlockchainError::ChainStoreNotEmpty)
+        ));
+
+        let mut empty = SqliteChainStore::new_in_memory().unwrap();
+        assert!(matches!(
+            migrate_chain_store(&mut empty, &mut dest),
+            Err(BlockchainError::ChainNotInitialized)
+        ));
+    }
+}
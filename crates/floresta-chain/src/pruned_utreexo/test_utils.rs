@@ -0,0 +1,86 @@
+//! Helpers shared by the unit tests of the chain backend.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use bitcoin::BlockHash;
+
+use crate::BestChain;
+use crate::ChainStore;
+use crate::DatabaseError;
+use crate::DiskBlockHeader;
+
+#[derive(Debug)]
+pub(crate) struct NoError;
+
+impl DatabaseError for NoError {}
+
+impl From<Infallible> for NoError {
+    fn from(_: Infallible) -> Self {
+        NoError
+    }
+}
+
+/// A [ChainStore] that only lives in memory
+#[derive(Default)]
+pub(crate) struct MemChainStore {
+    pub(crate) headers: HashMap<BlockHash, DiskBlockHeader>,
+    pub(crate) index: HashMap<u32, BlockHash>,
+    pub(crate) roots: HashMap<u32, Vec<u8>>,
+    pub(crate) best_chain: Option<BestChain>,
+}
+
+impl ChainStore for MemChainStore {
+    type Error = NoError;
+
+    fn save_roots_for_block(&mut self, roots: Vec<u8>, height: u32) -> Result<(), NoError> {
+        self.roots.insert(height, roots);
+        Ok(())
+    }
+
+    fn load_roots_for_block(&mut self, height: u32) -> Result<Option<Vec<u8>>, NoError> {
+        Ok(self.roots.get(&height).cloned())
+    }
+
+    fn load_height(&self) -> Result<Option<BestChain>, NoError> {
+        Ok(self.best_chain.clone())
+    }
+
+    fn save_height(&mut self, height: &BestChain) -> Result<(), NoError> {
+        self.best_chain = Some(height.clone());
+        Ok(())
+    }
+
+    fn get_header(&self, hash: &BlockHash) -> Result<Option<DiskBlockHeader>, NoError> {
+        Ok(self.headers.get(hash).copied())
+    }
+
+    fn get_header_by_height(&self, height: u32) -> Result<Option<DiskBlockHeader>, NoError> {
+        Ok(self
+            .index
+            .get(&height)
+            .and_then(|hash| self.headers.get(hash).copied()))
+    }
+
+    fn save_header(&mut self, header: &DiskBlockHeader) -> Result<(), NoError> {
+        self.headers.insert(header.block_hash(), *header);
+        Ok(())
+    }
+
+    fn get_block_hash(&self, height: u32) -> Result<Option<BlockHash>, NoError> {
+        Ok(self.index.get(&height).copied())
+    }
+
+    fn flush(&mut self) -> Result<(), NoError> {
+        Ok(())
+    }
+
+    fn update_block_index(&mut self, height: u32, hash: BlockHash) -> Result<(), NoError> {
+        self.index.insert(height, hash);
+        Ok(())
+    }
+
+    fn check_integrity(&self) -> Result<(), NoError> {
+        Ok(())
+    }
+}
@@ -18,7 +18,7 @@ use crate::pruned_utreexo::consensus::UTREEXO_TAG_V1;
 
 /// Leaf data is the data that is hashed when adding to utreexo state. It contains validation
 /// data and some commitments to make it harder to attack an utreexo-only node.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LeafData {
     /// A commitment to the block creating this utxo
     pub block_hash: BlockHash,
@@ -99,7 +99,7 @@ impl Decodable for LeafData {
 /// ScriptPubKeyKind is the output's scriptPubKey, but serialized in a more efficient way
 /// to save bandwidth. If the type is recoverable from the scriptSig, don't download the
 /// scriptPubKey.
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct CompactLeafData {
     /// Header code tells the height of creating for this UTXO and whether it's a coinbase
     pub header_code: u32,
@@ -114,7 +114,7 @@ pub struct CompactLeafData {
 /// An example is a p2pkh, the public key is serialized in the scriptSig, so we can just
 /// grab it and hash to obtain the actual scriptPubKey. Since this data is committed in
 /// the Utreexo leaf hash, it is still authenticated
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum ScriptPubKeyKind {
     /// An non-specified type, in this case the script is just copied over
     Other(Box<[u8]>),
@@ -298,7 +298,7 @@ pub mod proof_util {
 
     /// Checks if a script is unspendable either by its length or if it contains the `OP_RETURN` opcode.
     /// It follows the implementation on Bitcoin Core.
-    fn is_unspendable(script: &ScriptBuf) -> bool {
+    pub fn is_unspendable(script: &ScriptBuf) -> bool {
         if script.len() > 10_000 {
             return true;
         }
@@ -393,18 +393,20 @@ pub mod proof_util {
     type ProcessedProof = (Vec<sha256::Hash>, UtxoMap);
 
     /// This function processes a proof of inclusion for a given block.
-    /// It takes in the [`CompactLeafData`] for this block, this block's transactions, the height
-    /// and a function to get the block hash for a given height. Then returns a [`Result`] containing
-    /// a vector with hashes for deleted leaves, and a `UtxoMap`, which is defined
-    /// as [`HashMap<OutPoint, UtxoData>`].
-    pub fn process_proof<F, E>(
+    /// It takes in the [`CompactLeafData`] for this block, this block's transactions, the height,
+    /// a function to get the block hash for a given height and a function to get the median time
+    /// past (MTP) for a given height. Then returns a [`Result`] containing a vector with hashes
+    /// for deleted leaves, and a `UtxoMap`, which is defined as [`HashMap<OutPoint, UtxoData>`].
+    pub fn process_proof<F, G, E>(
         leaves: &[CompactLeafData],
         txdata: &[Transaction],
         height: u32,
         get_block_hash: F,
+        get_median_time_past: G,
     ) -> Result<ProcessedProof, E>
     where
         F: Fn(u32) -> Result<BlockHash, E>,
+        G: Fn(u32) -> Result<u32, E>,
         E: From<UtreexoLeafError>,
     {
         // Initialize return values
@@ -413,6 +415,19 @@ pub mod proof_util {
 
         let mut leaves_iter = leaves.iter().cloned();
 
+        // The creation time of a UTXO (BIP 68) is the MTP of the block preceding the one that
+        // confirmed it. Many UTXOs share the creation height, so we only compute it once.
+        let mut creation_times = HashMap::new();
+        let mut get_creation_time = |creation_height: u32| -> Result<u32, E> {
+            if let Some(time) = creation_times.get(&creation_height) {
+                return Ok(*time);
+            }
+            let time = get_median_time_past(creation_height.saturating_sub(1))?;
+            creation_times.insert(creation_height, time);
+            Ok(time)
+        };
+        let block_creation_time = get_creation_time(height)?;
+
         // Skip coinbase transaction
         for tx in txdata.iter().skip(1) {
             let txid = tx.compute_txid();
@@ -425,7 +440,7 @@ pub mod proof_util {
                         txout: out.clone(),
                         is_coinbase: tx.is_coinbase(),
                         creation_height: height,
-                        creation_time: 0, // TODO add MTP(`height` - 1)
+                        creation_time: block_creation_time,
                     },
                 );
             }
@@ -446,6 +461,7 @@ pub mod proof_util {
                 let is_coinbase = (leaf.header_code & 1) != 0;
 
                 let hash = get_block_hash(creation_height)?;
+                let creation_time = get_creation_time(creation_height)?;
                 let leaf =
                     reconstruct_leaf_data(&leaf, input, hash).map_err(|e| UtreexoLeafError {
                         leaf,
@@ -462,7 +478,7 @@ pub mod proof_util {
                         txout: leaf.utxo,
                         is_coinbase,
                         creation_height,
-                        creation_time: 0, // TODO add MTP(`creation_height` - 1)
+                        creation_time,
                     },
                 );
             }
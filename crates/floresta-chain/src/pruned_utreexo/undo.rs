@@ -0,0 +1,255 @@
+//! Undo data for our accumulator, analogous to Bitcoin Core's `rev` files.
+//!
+//! Every time we connect a block, the [Stump] we use for validation changes. To handle a reorg,
+//! we need the accumulator as it was at the fork point. We used to rely only on the roots saved
+//! for every height, but those may not be available (e.g. they were pruned). With an
+//! [AccumulatorUndo] for each block, we can roll the accumulator back one block at a time,
+//! starting from our current tip, all the way down to the fork point.
+//!
+//! Since additions and deletions can't be reversed from the roots alone (we would need to invert
+//! a hash), we record the roots that were replaced by the block. Roots that didn't change are not
+//! stored, we just take them from the accumulator we are rolling back.
+
+use bitcoin::consensus::encode;
+use bitcoin::consensus::Decodable;
+use bitcoin::consensus::Encodable;
+use bitcoin::BlockHash;
+use rustreexo::accumulator::node_hash::BitcoinNodeHash;
+use rustreexo::accumulator::stump::Stump;
+
+use crate::prelude::*;
+use crate::BlockchainError;
+
+/// The tag used for a root that is the same in the accumulator after the block.
+const ROOT_UNCHANGED: u8 = 0x00;
+
+/// The tag used for an empty root (i.e. all leaves in that tree were deleted).
+const ROOT_EMPTY: u8 = 0x01;
+
+/// The tag used for a root that was replaced by the block.
+const ROOT_REPLACED: u8 = 0x02;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The information needed to undo the changes a block made to our accumulator.
+pub struct AccumulatorUndo {
+    /// The block that caused these changes.
+    ///
+    /// We use this to make sure we are undoing the right block, since undo data is indexed by
+    /// height and there may be multiple blocks at the same height.
+    pub block_hash: BlockHash,
+
+    /// How many leaves the accumulator had before this block.
+    pub prev_leaves: u64,
+
+    /// The roots before this block.
+    ///
+    /// A `None` here means this root is the same as the one in the same position after the
+    /// block was connected.
+    pub prev_roots: Vec<Option<BitcoinNodeHash>>,
+}
+
+impl AccumulatorUndo {
+    /// Builds the undo data for a block, given the accumulator before and after connecting it.
+    pub fn new(block_hash: BlockHash, prev_acc: &Stump, new_acc: &Stump) -> Self {
+        let prev_roots = prev_acc
+            .roots
+            .iter()
+            .enumerate()
+            .map(|(idx, root)| match new_acc.roots.get(idx) {
+                Some(new_root) if new_root == root => None,
+                _ => Some(*root),
+            })
+            .collect();
+
+        AccumulatorUndo {
+            block_hash,
+            prev_leaves: prev_acc.leaves,
+            prev_roots,
+        }
+    }
+
+    /// Takes the accumulator after this block was connected, and returns the accumulator before
+    /// it.
+    pub fn undo(&self, acc: &Stump) -> Result<Stump, BlockchainError> {
+        if acc.leaves < self.prev_leaves {
+            return Err(BlockchainError::UtreexoError(format!(
+                "Can't undo block {}: accumulator has {} leaves, expected at least {}",
+                self.block_hash, acc.leaves, self.prev_leaves
+            )));
+        }
+
+        let roots = self
+            .prev_roots
+            .iter()
+            .enumerate()
+            .map(|(idx, root)| match root {
+                Some(root) => Ok(*root),
+                None => acc.roots.get(idx).copied().ok_or_else(|| {
+                    BlockchainError::UtreexoError(format!(
+                        "Can't undo block {}: missing root at position {idx}",
+                        self.block_hash
+                    ))
+                }),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Stump {
+            leaves: self.prev_leaves,
+            roots,
+        })
+    }
+}
+
+impl Encodable for AccumulatorUndo {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> bitcoin::io::Result<usize> {
+        let mut len = 0;
+        len += self.block_hash.consensus_encode(writer)?;
+        len += self.prev_leaves.consensus_encode(writer)?;
+        len += (self.prev_roots.len() as u8).consensus_encode(writer)?;
+
+        for root in self.prev_roots.iter() {
+            match root {
+                None => len += ROOT_UNCHANGED.consensus_encode(writer)?,
+                Some(BitcoinNodeHash::Some(hash)) => {
+                    len += ROOT_REPLACED.consensus_encode(writer)?;
+                    len += hash.consensus_encode(writer)?;
+                }
+                Some(_) => len += ROOT_EMPTY.consensus_encode(writer)?,
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+impl Decodable for AccumulatorUndo {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, encode::Error> {
+        let block_hash = BlockHash::consensus_decode(reader)?;
+        let prev_leaves = u64::consensus_decode(reader)?;
+        let roots_len = u8::consensus_decode(reader)?;
+
+        // One root for each bit set in the leaf count
+        if roots_len as u32 != prev_leaves.count_ones() {
+            return Err(encode::Error::ParseFailed("invalid number of roots"));
+        }
+
+        let mut prev_roots = Vec::with_capacity(roots_len as usize);
+        for _ in 0..roots_len {
+            let root = match u8::consensus_decode(reader)? {
+                ROOT_UNCHANGED => None,
+                ROOT_EMPTY => Some(BitcoinNodeHash::Empty),
+                ROOT_REPLACED => Some(BitcoinNodeHash::Some(<[u8; 32]>::consensus_decode(reader)?)),
+                _ => return Err(encode::Error::ParseFailed("invalid root tag")),
+            };
+
+            prev_roots.push(root);
+        }
+
+        Ok(AccumulatorUndo {
+            block_hash,
+            prev_leaves,
+            prev_roots,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::consensus::deserialize;
+    use bitcoin::consensus::serialize;
+    use bitcoin::hashes::Hash;
+    use rustreexo::accumulator::mem_forest::MemForest;
+    use rustreexo::accumulator::proof::Proof;
+
+    use super::*;
+
+    fn hashes(range: core::ops::Range<u8>) -> Vec<BitcoinNodeHash> {
+        range.map(|i| BitcoinNodeHash::Some([i; 32])).collect()
+    }
+
+    #[test]
+    fn test_undo_additions() {
+        let mut acc = Stump::new();
+        let mut history = vec![acc.clone()];
+        let mut undos = Vec::new();
+
+        // Add some leaves in batches of different sizes, so roots get merged
+        for (i, batch) in [3_u8, 1, 4, 7, 2].iter().enumerate() {
+            let start = i as u8 * 10;
+            let new_acc = acc
+                .modify(&hashes(start..start + batch), &[], &Proof::default())
+                .unwrap()
+                .0;
+
+            let block_hash = BlockHash::from_byte_array([i as u8; 32]);
+            undos.push(AccumulatorUndo::new(block_hash, &acc, &new_acc));
+            history.push(new_acc.clone());
+            acc = new_acc;
+        }
+
+        // Roll everything back, one block at a time
+        for undo in undos.iter().rev() {
+            history.pop();
+            acc = undo.undo(&acc).unwrap();
+            assert_eq!(&acc, history.last().unwrap());
+        }
+
+        assert_eq!(acc, Stump::new());
+    }
+
+    #[test]
+    fn test_undo_deletions() {
+        let leaves = hashes(0..8);
+        let acc = Stump::new()
+            .modify(&leaves, &[], &Proof::default())
+            .unwrap()
+            .0;
+
+        // Build a proof for the first two leaves using a full accumulator
+        let mut forest = MemForest::new();
+        forest.modify(&leaves, &[]).unwrap();
+        let proof = forest.prove(&leaves[0..2]).unwrap();
+
+        let new_acc = acc
+            .modify(&hashes(100..101), &leaves[0..2], &proof)
+            .unwrap()
+            .0;
+
+        let block_hash = BlockHash::from_byte_array([1; 32]);
+        let undo = AccumulatorUndo::new(block_hash, &acc, &new_acc);
+
+        assert_eq!(undo.undo(&new_acc).unwrap(), acc);
+
+        // Undo data for a smaller accumulator can't be applied
+        assert!(undo.undo(&Stump::new()).is_err());
+    }
+
+    #[test]
+    fn test_serialization_roundtrip() {
+        let prev_acc = Stump::new()
+            .modify(&hashes(0..7), &[], &Proof::default())
+            .unwrap()
+            .0;
+        let new_acc = prev_acc
+            .modify(&hashes(7..8), &[], &Proof::default())
+            .unwrap()
+            .0;
+
+        let block_hash = BlockHash::from_byte_array([7; 32]);
+        let mut undo = AccumulatorUndo::new(block_hash, &prev_acc, &new_acc);
+        undo.prev_roots[0] = Some(BitcoinNodeHash::Empty);
+
+        let decoded: AccumulatorUndo = deserialize(&serialize(&undo)).unwrap();
+        assert_eq!(decoded, undo);
+
+        // The number of roots must match the number of leaves
+        let mut data = serialize(&undo);
+        data[32] = 8;
+        assert!(deserialize::<AccumulatorUndo>(&data).is_err());
+    }
+}
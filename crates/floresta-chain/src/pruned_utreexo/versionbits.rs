@@ -0,0 +1,881 @@
+//! Versionbits (BIP9 and BIP8) soft fork deployments
+//!
+//! Miners signal readiness for a soft fork by setting one bit of the block version. For each
+//! retarget period, a [Deployment] goes through the states in [ThresholdState], depending on how
+//! many blocks signaled in the previous period. BIP9 deployments start and time out based on the
+//! median time past, while BIP8 ones use block heights and may require signaling before timing
+//! out (`lock_in_on_timeout`).
+//!
+//! The state is the same for every block in a period, so [VersionBitsCache] only computes it
+//! once per period, keyed by the last block of the previous period. Header versions and times
+//! are read from the [ChainStore].
+
+use core::ffi::c_uint;
+use core::fmt;
+use core::fmt::Display;
+use core::fmt::Formatter;
+
+use bitcoin::block::Header as BlockHeader;
+use bitcoin::block::Version;
+use bitcoin::BlockHash;
+
+use super::consensus::Consensus;
+use super::consensus::MEDIAN_TIME_SPAN;
+use crate::prelude::*;
+use crate::BlockValidationErrors;
+use crate::BlockchainError;
+use crate::ChainStore;
+
+/// The top bits a version must have for its other bits to be interpreted as signals
+pub const VERSIONBITS_TOP_BITS: i32 = 0x2000_0000;
+
+/// The mask applied to a version to check [VERSIONBITS_TOP_BITS]
+pub const VERSIONBITS_TOP_MASK: i32 = 0xE000_0000_u32 as i32;
+
+/// A timeout that is never reached
+pub const NO_TIMEOUT: i64 = i64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The state of a deployment for a given block, see BIP9 and BIP8
+pub enum ThresholdState {
+    /// The first state of every deployment, before it starts
+    Defined,
+
+    /// Miners are signaling for this deployment
+    Started,
+
+    /// The deployment will time out at the end of this period, so every block must signal
+    /// (BIP8 with `lock_in_on_timeout` only)
+    MustSignal,
+
+    /// Enough blocks signaled in the last period, the deployment is waiting to activate
+    LockedIn,
+
+    /// The new rules are enforced
+    Active,
+
+    /// The deployment timed out before locking in
+    Failed,
+}
+
+impl Display for ThresholdState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            ThresholdState::Defined => "defined",
+            ThresholdState::Started => "started",
+            ThresholdState::MustSignal => "must_signal",
+            ThresholdState::LockedIn => "locked_in",
+            ThresholdState::Active => "active",
+            ThresholdState::Failed => "failed",
+        };
+
+        write!(f, "{state}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// When a deployment starts and times out
+pub enum DeploymentTrigger {
+    /// BIP9, both values are compared against the median time past of the last block of a
+    /// period
+    MedianTime {
+        /// Signaling starts in the first period after this time
+        start_time: i64,
+
+        /// The deployment fails if it isn't locked in after this time, may be [NO_TIMEOUT]
+        timeout: i64,
+    },
+
+    /// BIP8, both heights should be multiples of the deployment period
+    Height {
+        /// Signaling starts at this height
+        start_height: u32,
+
+        /// The deployment fails if it isn't locked in by this height
+        timeout_height: u32,
+
+        /// If set, instead of failing, every block in the last period before the timeout must
+        /// signal
+        lock_in_on_timeout: bool,
+    },
+
+    /// The deployment is active since genesis
+    AlwaysActive,
+
+    /// The deployment never activates
+    NeverActive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A soft fork deployed with versionbits
+pub struct Deployment {
+    /// The name used to report this deployment, like `taproot`
+    pub name: &'static str,
+
+    /// Which version bit miners set to signal for this deployment
+    pub bit: u8,
+
+    /// When signaling starts and times out
+    pub trigger: DeploymentTrigger,
+
+    /// A locked in deployment only activates at or after this height
+    pub min_activation_height: u32,
+
+    /// How many blocks in a period must signal for the deployment to lock in
+    pub threshold: u32,
+
+    /// The length of a signaling period, in blocks
+    pub period: u32,
+
+    /// Script verification flags enforced once this deployment is active, see
+    /// [ChainParams::get_validation_flags](crate::ChainParams::get_validation_flags)
+    pub script_flags: c_uint,
+}
+
+impl Deployment {
+    /// Whether a block with this version signals for this deployment
+    pub fn signals(&self, version: Version) -> bool {
+        let version = version.to_consensus();
+        (version & VERSIONBITS_TOP_MASK) == VERSIONBITS_TOP_BITS && (version >> self.bit) & 1 == 1
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The rules our deployments add to a block
+pub struct DeploymentRules {
+    /// The script flags of every active deployment
+    pub script_flags: c_uint,
+
+    /// The bits of the deployments in [ThresholdState::MustSignal], this block must set them
+    pub must_signal: Vec<u8>,
+}
+
+impl DeploymentRules {
+    /// Checks whether a block with `version` signals for every deployment that requires it
+    pub fn check_signaling(&self, version: Version) -> Result<(), BlockValidationErrors> {
+        let version = version.to_consensus();
+        let has_top_bits = (version & VERSIONBITS_TOP_MASK) == VERSIONBITS_TOP_BITS;
+
+        for bit in self.must_signal.iter() {
+            if !has_top_bits || (version >> bit) & 1 == 0 {
+                return Err(BlockValidationErrors::MissingDeploymentSignal(*bit));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Signaling statistics for the current period of a started deployment
+pub struct DeploymentStatistics {
+    /// The length of the period
+    pub period: u32,
+
+    /// How many blocks must signal in this period to lock in
+    pub threshold: u32,
+
+    /// How many blocks of this period we've seen
+    pub elapsed: u32,
+
+    /// How many of those blocks signal
+    pub count: u32,
+
+    /// Whether the threshold can still be reached in this period
+    pub possible: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The status of a deployment at some block, as reported by `getdeploymentinfo`
+pub struct DeploymentStatus {
+    /// The deployment this status is for
+    pub deployment: Deployment,
+
+    /// The state for this block
+    pub state: ThresholdState,
+
+    /// The state for the next block
+    pub state_next: ThresholdState,
+
+    /// The height of the first block with the current state
+    pub since: u32,
+
+    /// Signaling statistics, only for [ThresholdState::Started] and
+    /// [ThresholdState::MustSignal]
+    pub statistics: Option<DeploymentStatistics>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A soft fork that activates at a fixed height, like BIP34 and segwit
+pub struct BuriedDeployment {
+    /// The name used to report this deployment, like `segwit`
+    pub name: &'static str,
+
+    /// The first block enforcing this deployment
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Every soft fork we know about, as reported by `getdeploymentinfo`
+pub struct DeploymentInfo {
+    /// Soft forks with a hardcoded activation height
+    pub buried: Vec<BuriedDeployment>,
+
+    /// Soft forks deployed with versionbits
+    pub deployments: Vec<DeploymentStatus>,
+}
+
+/// A block in the chain we are computing states for
+#[derive(Clone, Copy)]
+struct IndexedHeader {
+    hash: BlockHash,
+    header: BlockHeader,
+    height: u32,
+}
+
+/// Reads the chain ending at some block from a [ChainStore]
+///
+/// If a block is in our best chain, we can find its ancestors by height, otherwise we need to
+/// follow the `prev_blockhash` links until we get into the best chain.
+struct HeaderReader<'a, S: ChainStore> {
+    store: &'a S,
+}
+
+impl<S: ChainStore> HeaderReader<'_, S> {
+    fn get(&self, hash: BlockHash) -> Result<IndexedHeader, BlockchainError> {
+        let header = self
+            .store
+            .get_header(&hash)?
+            .ok_or(BlockchainError::BlockNotPresent)?;
+
+        Ok(IndexedHeader {
+            hash,
+            height: header.try_height()?,
+            header: *header,
+        })
+    }
+
+    fn is_in_best_chain(&self, block: &IndexedHeader) -> Result<bool, BlockchainError> {
+        Ok(self.store.get_block_hash(block.height)? == Some(block.hash))
+    }
+
+    /// Returns the ancestor of `block` at `height`, which must not be above `block`
+    fn ancestor(
+        &self,
+        mut block: IndexedHeader,
+        height: u32,
+    ) -> Result<IndexedHeader, BlockchainError> {
+        while block.height > height {
+            if self.is_in_best_chain(&block)? {
+                let hash = self
+                    .store
+                    .get_block_hash(height)?
+                    .ok_or(BlockchainError::BlockNotPresent)?;
+
+                return self.get(hash);
+            }
+
+            block = self.get(block.header.prev_blockhash)?;
+        }
+
+        Ok(block)
+    }
+
+    /// Returns the ancestor of `block` `depth` blocks below it, or `None` if we would go below
+    /// genesis
+    fn ancestor_below(
+        &self,
+        block: IndexedHeader,
+        depth: u32,
+    ) -> Result<Option<IndexedHeader>, BlockchainError> {
+        match block.height.checked_sub(depth) {
+            Some(height) => self.ancestor(block, height).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The median time past of `block`, see BIP113
+    fn median_time_past(&self, mut block: IndexedHeader) -> Result<u32, BlockchainError> {
+        let mut timestamps = vec![block.header.time];
+
+        while timestamps.len() < MEDIAN_TIME_SPAN && block.height > 0 {
+            block = self.get(block.header.prev_blockhash)?;
+            timestamps.push(block.header.time);
+        }
+
+        Ok(Consensus::get_median_time_past(timestamps))
+    }
+
+    /// How many blocks signal for `deployment`, among `block` and the `count - 1` blocks below it
+    fn count_signals(
+        &self,
+        deployment: &Deployment,
+        mut block: IndexedHeader,
+        count: u32,
+    ) -> Result<u32, BlockchainError> {
+        let mut signals = 0;
+        for i in 0..count {
+            if deployment.signals(block.header.version) {
+                signals += 1;
+            }
+
+            if i + 1 < count {
+                block = self.get(block.header.prev_blockhash)?;
+            }
+        }
+
+        Ok(signals)
+    }
+}
+
+#[derive(Debug, Default)]
+/// Caches the state of each deployment per period
+pub struct VersionBitsCache {
+    /// The state of a deployment for the blocks in a period, keyed by the deployment name and the
+    /// last block of the previous period (`None` for the first period)
+    states: HashMap<(&'static str, Option<BlockHash>), ThresholdState>,
+}
+
+impl VersionBitsCache {
+    /// Creates an empty cache
+    pub fn new() -> Self {
+        VersionBitsCache::default()
+    }
+
+    /// Forgets every computed state, must be called if headers we've used may be gone
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+
+    /// Returns the state of `deployment` for the block after `prev`, or for genesis if `prev` is
+    /// `None`
+    pub fn get_state<S: ChainStore>(
+        &mut self,
+        store: &S,
+        deployment: &Deployment,
+        prev: Option<BlockHash>,
+    ) -> Result<ThresholdState, BlockchainError> {
+        let reader = HeaderReader { store };
+        let prev = prev.map(|hash| reader.get(hash)).transpose()?;
+
+        self.state_for(&reader, deployment, prev)
+    }
+
+    /// Returns the rules that `deployments` add to the block after `prev`
+    pub fn get_rules<S: ChainStore>(
+        &mut self,
+        store: &S,
+        deployments: &[Deployment],
+        prev: Option<BlockHash>,
+    ) -> Result<DeploymentRules, BlockchainError> {
+        let reader = HeaderReader { store };
+        let prev = prev.map(|hash| reader.get(hash)).transpose()?;
+        let mut rules = DeploymentRules::default();
+
+        for deployment in deployments {
+            match self.state_for(&reader, deployment, prev)? {
+                ThresholdState::Active => rules.script_flags |= deployment.script_flags,
+                ThresholdState::MustSignal => rules.must_signal.push(deployment.bit),
+                _ => {}
+            }
+        }
+
+        Ok(rules)
+    }
+
+    /// Returns the status of each deployment at `block`
+    pub fn get_status<S: ChainStore>(
+        &mut self,
+        store: &S,
+        deployments: &[Deployment],
+        block: BlockHash,
+    ) -> Result<Vec<DeploymentStatus>, BlockchainError> {
+        let reader = HeaderReader { store };
+        let block = reader.get(block)?;
+        let prev = match block.height {
+            0 => None,
+            _ => Some(reader.get(block.header.prev_blockhash)?),
+        };
+
+        deployments
+            .iter()
+            .map(|deployment| {
+                let state = self.state_for(&reader, deployment, prev)?;
+                let state_next = self.state_for(&reader, deployment, Some(block))?;
+                let since = self.state_since(&reader, deployment, prev)?;
+
+                let statistics = match state_next {
+                    ThresholdState::Started | ThresholdState::MustSignal => {
+                        Some(Self::statistics(&reader, deployment, block)?)
+                    }
+                    _ => None,
+                };
+
+                Ok(DeploymentStatus {
+                    deployment: deployment.clone(),
+                    state,
+                    state_next,
+                    since,
+                    statistics,
+                })
+            })
+            .collect()
+    }
+
+    /// Computes the state for the block after `prev`, like `GetStateFor` in Bitcoin Core
+    fn state_for<S: ChainStore>(
+        &mut self,
+        reader: &HeaderReader<S>,
+        deployment: &Deployment,
+        prev: Option<IndexedHeader>,
+    ) -> Result<ThresholdState, BlockchainError> {
+        match deployment.trigger {
+            DeploymentTrigger::AlwaysActive => return Ok(ThresholdState::Active),
+            DeploymentTrigger::NeverActive => return Ok(ThresholdState::Failed),
+            _ => {}
+        }
+
+        let period = deployment.period;
+
+        // The state only changes at period boundaries, so we look for the last block of the
+        // previous period
+        let mut prev = match prev {
+            Some(block) => reader.ancestor_below(block, (block.height + 1) % period)?,
+            None => None,
+        };
+
+        // Walk back until we find a period with a known state
+        let mut to_compute = Vec::new();
+        let mut state = loop {
+            let key = (deployment.name, prev.map(|block| block.hash));
+            if let Some(state) = self.states.get(&key) {
+                break *state;
+            }
+
+            let Some(block) = prev else {
+                self.states.insert(key, ThresholdState::Defined);
+                break ThresholdState::Defined;
+            };
+
+            let before_start = match deployment.trigger {
+                DeploymentTrigger::MedianTime { start_time, .. } => {
+                    (reader.median_time_past(block)? as i64) < start_time
+                }
+                DeploymentTrigger::Height { start_height, .. } => block.height + 1 < start_height,
+                _ => unreachable!("handled above"),
+            };
+
+            if before_start {
+                self.states.insert(key, ThresholdState::Defined);
+                break ThresholdState::Defined;
+            }
+
+            to_compute.push(block);
+            prev = reader.ancestor_below(block, period)?;
+        };
+
+        // And then move forward, computing the state for each period
+        while let Some(block) = to_compute.pop() {
+            let next_height = block.height + 1;
+            state = match state {
+                ThresholdState::Defined => ThresholdState::Started,
+                ThresholdState::Started => {
+                    let signals = reader.count_signals(deployment, block, period)?;
+
+                    match deployment.trigger {
+                        _ if signals >= deployment.threshold => ThresholdState::LockedIn,
+                        DeploymentTrigger::MedianTime { timeout, .. } => {
+                            match reader.median_time_past(block)? as i64 >= timeout {
+                                true => ThresholdState::Failed,
+                                false => ThresholdState::Started,
+                            }
+                        }
+                        DeploymentTrigger::Height {
+                            timeout_height,
+                            lock_in_on_timeout,
+                            ..
+                        } => {
+                            if lock_in_on_timeout && next_height + period >= timeout_height {
+                                ThresholdState::MustSignal
+                            } else if next_height >= timeout_height {
+                                ThresholdState::Failed
+                            } else {
+                                ThresholdState::Started
+                            }
+                        }
+                        _ => unreachable!("handled above"),
+                    }
+                }
+                ThresholdState::MustSignal => ThresholdState::LockedIn,
+                ThresholdState::LockedIn if next_height >= deployment.min_activation_height => {
+                    ThresholdState::Active
+                }
+                state => state,
+            };
+
+            self.states
+                .insert((deployment.name, Some(block.hash)), state);
+        }
+
+        Ok(state)
+    }
+
+    /// Returns the height of the first block with the same state as the block after `prev`
+    fn state_since<S: ChainStore>(
+        &mut self,
+        reader: &HeaderReader<S>,
+        deployment: &Deployment,
+        prev: Option<IndexedHeader>,
+    ) -> Result<u32, BlockchainError> {
+        let state = self.state_for(reader, deployment, prev)?;
+        if matches!(state, ThresholdState::Defined)
+            || matches!(deployment.trigger, DeploymentTrigger::AlwaysActive)
+        {
+            return Ok(0);
+        }
+
+        let period = deployment.period;
+        let Some(prev) = prev else {
+            return Ok(0);
+        };
+
+        // The last block of the previous period, it can't be `None` since we aren't defined
+        let Some(mut boundary) = reader.ancestor_below(prev, (prev.height + 1) % period)? else {
+            return Ok(0);
+        };
+
+        while let Some(previous) = reader.ancestor_below(boundary, period)? {
+            if self.state_for(reader, deployment, Some(previous))? != state {
+                break;
+            }
+
+            boundary = previous;
+        }
+
+        Ok(boundary.height + 1)
+    }
+
+    /// Signaling statistics for the period `block` is in, up to `block`
+    fn statistics<S: ChainStore>(
+        reader: &HeaderReader<S>,
+        deployment: &Deployment,
+        block: IndexedHeader,
+    ) -> Result<DeploymentStatistics, BlockchainError> {
+        let elapsed = (block.height + 1) % deployment.period;
+        let elapsed = match elapsed {
+            0 => deployment.period,
+            elapsed => elapsed,
+        };
+
+        let count = reader.count_signals(deployment, block, elapsed)?;
+        let missing = deployment.period - deployment.threshold;
+
+        Ok(DeploymentStatistics {
+            period: deployment.period,
+            threshold: deployment.threshold,
+            elapsed,
+            count,
+            possible: elapsed - count <= missing,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use bitcoin::block::Header as BlockHeader;
+    use bitcoin::block::Version;
+    use bitcoin::hashes::Hash;
+    use bitcoin::BlockHash;
+    use bitcoin::CompactTarget;
+    use bitcoin::TxMerkleNode;
+
+    use super::*;
+    use crate::pruned_utreexo::test_utils::MemChainStore;
+    use crate::DiskBlockHeader;
+
+    const PERIOD: u32 = 10;
+    const SIGNAL: i32 = VERSIONBITS_TOP_BITS | 1 << 1;
+    const NO_SIGNAL: i32 = VERSIONBITS_TOP_BITS;
+
+    fn deployment(trigger: DeploymentTrigger) -> Deployment {
+        Deployment {
+            name: "test",
+            bit: 1,
+            trigger,
+            min_activation_height: 0,
+            threshold: 8,
+            period: PERIOD,
+            script_flags: 1 << 20,
+        }
+    }
+
+    /// Appends one block per version to the chain ending at `tip`, with one minute between
+    /// blocks. If `best` is set, the blocks are also added to the block index.
+    fn extend(
+        store: &mut MemChainStore,
+        tip: Option<BlockHash>,
+        versions: &[i32],
+        best: bool,
+    ) -> Vec<BlockHash> {
+        let (mut prev, mut height) = match tip {
+            Some(hash) => (hash, store.headers[&hash].height().unwrap() + 1),
+            None => (BlockHash::all_zeros(), 0),
+        };
+
+        let mut hashes = Vec::new();
+        for version in versions {
+            let header = BlockHeader {
+                version: Version::from_consensus(*version),
+                prev_blockhash: prev,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_000_000 + height * 60,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: if best { 0 } else { 1 },
+            };
+
+            store
+                .save_header(&DiskBlockHeader::HeadersOnly(header, height))
+                .unwrap();
+            if best {
+                store
+                    .update_block_index(height, header.block_hash())
+                    .unwrap();
+            }
+
+            prev = header.block_hash();
+            hashes.push(prev);
+            height += 1;
+        }
+
+        hashes
+    }
+
+    /// Returns the state for the block at each height in `chain`
+    fn states(
+        store: &MemChainStore,
+        deployment: &Deployment,
+        chain: &[BlockHash],
+    ) -> Vec<ThresholdState> {
+        let mut cache = VersionBitsCache::new();
+
+        (0..chain.len())
+            .map(|height| {
+                let prev = height.checked_sub(1).map(|prev| chain[prev]);
+                cache.get_state(store, deployment, prev).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_signals() {
+        let deployment = deployment(DeploymentTrigger::AlwaysActive);
+
+        assert!(deployment.signals(Version::from_consensus(SIGNAL)));
+        assert!(!deployment.signals(Version::from_consensus(NO_SIGNAL)));
+        // The bit is set, but the top bits aren't
+        assert!(!deployment.signals(Version::from_consensus(1 << 1)));
+        assert!(!deployment.signals(Version::from_consensus(0x6000_0002)));
+    }
+
+    #[test]
+    fn test_bip9_activation() {
+        let deployment = deployment(DeploymentTrigger::MedianTime {
+            start_time: 0,
+            timeout: NO_TIMEOUT,
+        });
+
+        // The first period is always defined, then we need two periods to lock in: one with too
+        // few signals and one with just enough
+        let mut versions = vec![NO_SIGNAL; PERIOD as usize];
+        versions.extend([SIGNAL; 7].iter().chain(&[NO_SIGNAL; 3]));
+        versions.extend([SIGNAL; 8].iter().chain(&[NO_SIGNAL; 2]));
+        versions.extend([NO_SIGNAL; 2 * PERIOD as usize]);
+
+        let mut store = MemChainStore::default();
+        let chain = extend(&mut store, None, &versions, true);
+        let states = states(&store, &deployment, &chain);
+
+        let expected = [
+            ThresholdState::Defined,
+            ThresholdState::Started,
+            ThresholdState::Started,
+            ThresholdState::LockedIn,
+            ThresholdState::Active,
+        ];
+
+        for (height, state) in states.iter().enumerate() {
+            assert_eq!(
+                *state,
+                expected[height / PERIOD as usize],
+                "height {height}"
+            );
+        }
+
+        let mut cache = VersionBitsCache::new();
+        let rules = cache
+            .get_rules(&store, &[deployment.clone()], chain.last().copied())
+            .unwrap();
+        assert_eq!(rules.script_flags, deployment.script_flags);
+        assert!(rules.must_signal.is_empty());
+    }
+
+    #[test]
+    fn test_bip9_timeout() {
+        // The median time past of the last block in the second period is past the timeout
+        let deployment = deployment(DeploymentTrigger::MedianTime {
+            start_time: 0,
+            timeout: 1_000_000 + 10 * 60,
+        });
+
+        let mut versions = vec![NO_SIGNAL; 2 * PERIOD as usize];
+        versions.extend([SIGNAL; 2 * PERIOD as usize]);
+
+        let mut store = MemChainStore::default();
+        let chain = extend(&mut store, None, &versions, true);
+        let states = states(&store, &deployment, &chain);
+
+        assert_eq!(states[PERIOD as usize], ThresholdState::Started);
+        // Failed is final, even if miners signal afterwards
+        assert!(states[2 * PERIOD as usize..]
+            .iter()
+            .all(|state| *state == ThresholdState::Failed));
+    }
+
+    #[test]
+    fn test_min_activation_height() {
+        let deployment = Deployment {
+            min_activation_height: 5 * PERIOD,
+            ..deployment(DeploymentTrigger::MedianTime {
+                start_time: 0,
+                timeout: NO_TIMEOUT,
+            })
+        };
+
+        let mut versions = vec![NO_SIGNAL; PERIOD as usize];
+        versions.extend([SIGNAL; PERIOD as usize]);
+        versions.extend([NO_SIGNAL; 4 * PERIOD as usize]);
+
+        let mut store = MemChainStore::default();
+        let chain = extend(&mut store, None, &versions, true);
+        let states = states(&store, &deployment, &chain);
+
+        // Locked in at the third period, but only active at the sixth
+        assert_eq!(states[2 * PERIOD as usize], ThresholdState::LockedIn);
+        assert_eq!(states[4 * PERIOD as usize], ThresholdState::LockedIn);
+        assert_eq!(states[5 * PERIOD as usize], ThresholdState::Active);
+    }
+
+    #[test]
+    fn test_bip8_must_signal() {
+        let deployment = deployment(DeploymentTrigger::Height {
+            start_height: PERIOD,
+            timeout_height: 3 * PERIOD,
+            lock_in_on_timeout: true,
+        });
+
+        let versions = vec![NO_SIGNAL; 4 * PERIOD as usize];
+        let mut store = MemChainStore::default();
+        let chain = extend(&mut store, None, &versions, true);
+        let states = states(&store, &deployment, &chain);
+
+        assert_eq!(states[0], ThresholdState::Defined);
+        assert_eq!(states[PERIOD as usize], ThresholdState::Started);
+        assert_eq!(states[2 * PERIOD as usize], ThresholdState::MustSignal);
+        assert_eq!(states[3 * PERIOD as usize], ThresholdState::LockedIn);
+
+        // Blocks in the last period must signal
+        let mut cache = VersionBitsCache::new();
+        let prev = Some(chain[2 * PERIOD as usize - 1]);
+        let rules = cache.get_rules(&store, &[deployment], prev).unwrap();
+
+        assert_eq!(rules.must_signal, vec![1]);
+        assert_eq!(
+            rules.check_signaling(Version::from_consensus(NO_SIGNAL)),
+            Err(BlockValidationErrors::MissingDeploymentSignal(1))
+        );
+        assert!(rules
+            .check_signaling(Version::from_consensus(SIGNAL))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_fork_states() {
+        let deployment = deployment(DeploymentTrigger::MedianTime {
+            start_time: 0,
+            timeout: NO_TIMEOUT,
+        });
+
+        // The best chain never signals, but a fork from the end of the first period does
+        let mut store = MemChainStore::default();
+        let chain = extend(&mut store, None, &[NO_SIGNAL; 3 * PERIOD as usize], true);
+        let fork_base = chain[PERIOD as usize - 1];
+        let fork = extend(
+            &mut store,
+            Some(fork_base),
+            &[SIGNAL; PERIOD as usize],
+            false,
+        );
+
+        let mut cache = VersionBitsCache::new();
+        let best_state = cache
+            .get_state(&store, &deployment, Some(chain[2 * PERIOD as usize - 1]))
+            .unwrap();
+        let fork_state = cache
+            .get_state(&store, &deployment, fork.last().copied())
+            .unwrap();
+
+        assert_eq!(best_state, ThresholdState::Started);
+        assert_eq!(fork_state, ThresholdState::LockedIn);
+    }
+
+    #[test]
+    fn test_status() {
+        let deployment = deployment(DeploymentTrigger::MedianTime {
+            start_time: 0,
+            timeout: NO_TIMEOUT,
+        });
+
+        let mut versions = vec![NO_SIGNAL; 2 * PERIOD as usize];
+        versions.extend([SIGNAL, NO_SIGNAL, SIGNAL, NO_SIGNAL]);
+
+        let mut store = MemChainStore::default();
+        let chain = extend(&mut store, None, &versions, true);
+
+        let mut cache = VersionBitsCache::new();
+        let status = cache
+            .get_status(&store, &[deployment], *chain.last().unwrap())
+            .unwrap()
+            .remove(0);
+
+        assert_eq!(status.state, ThresholdState::Started);
+        assert_eq!(status.state_next, ThresholdState::Started);
+        assert_eq!(status.since, PERIOD);
+        assert_eq!(
+            status.statistics,
+            Some(DeploymentStatistics {
+                period: PERIOD,
+                threshold: 8,
+                elapsed: 4,
+                count: 2,
+                possible: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_always_and_never_active() {
+        let mut store = MemChainStore::default();
+        let chain = extend(&mut store, None, &[NO_SIGNAL; 5], true);
+
+        let always = deployment(DeploymentTrigger::AlwaysActive);
+        let never = deployment(DeploymentTrigger::NeverActive);
+
+        assert!(states(&store, &always, &chain)
+            .iter()
+            .all(|state| *state == ThresholdState::Active));
+        assert!(states(&store, &never, &chain)
+            .iter()
+            .all(|state| *state == ThresholdState::Failed));
+    }
+}
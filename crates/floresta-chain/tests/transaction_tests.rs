@@ -1,8 +1,11 @@
 //! Adaptation of Bitcoin Core's `src/test/transaction_tests.cpp`, using the vendored JSON test
 //! vectors in `testdata/bitcoin-core`. We parse them and check the flags that are supported by
-//! Floresta's consensus.
+//! Floresta's consensus, against each script verification backend that is enabled.
 
-#![cfg(all(feature = "bitcoinconsensus", feature = "test-utils"))]
+#![cfg(all(
+    any(feature = "bitcoinconsensus", feature = "script-interpreter"),
+    feature = "test-utils"
+))]
 
 mod util;
 
@@ -11,8 +14,11 @@ use std::collections::HashMap;
 use bitcoin::OutPoint;
 use bitcoin::Transaction;
 use floresta_chain::pruned_utreexo::consensus::Consensus;
+#[cfg(feature = "script-interpreter")]
+use floresta_chain::pruned_utreexo::consensus::ScriptCheck;
 use floresta_chain::pruned_utreexo::consensus::COIN_VALUE;
 use floresta_chain::pruned_utreexo::utxo_data::UtxoData;
+use floresta_chain::verify_flags::*;
 use floresta_chain::BlockchainError;
 use serde_json::Value;
 use util::exclude_individual_flags;
@@ -21,8 +27,15 @@ use util::fmt_shift_flags;
 use util::trim_flags;
 use util::VERIFY_FLAGS_COUNT;
 
-// All the accepted flags in the bitcoinconsensus interface (TODO update with libbitcoinkernel-supported flags)
-const VERIFY_ALL: u32 = bitcoinconsensus::VERIFY_ALL_PRE_TAPROOT;
+// All the consensus flags we enforce. The bitcoinconsensus backend doesn't support taproot, so
+// `Consensus` drops that flag before calling it
+const VERIFY_ALL: u32 = VERIFY_P2SH
+    | VERIFY_DERSIG
+    | VERIFY_NULLDUMMY
+    | VERIFY_CHECKLOCKTIMEVERIFY
+    | VERIFY_CHECKSEQUENCEVERIFY
+    | VERIFY_WITNESS
+    | VERIFY_TAPROOT;
 
 // The dummy height that we use for all the test transactions
 const TX_HEIGHT: u32 = 100_000;
@@ -104,19 +117,48 @@ mod parse {
     }
 }
 
+/// The script verification backends that are enabled for this test run
+#[derive(Debug, Clone, Copy)]
+enum Backend {
+    #[cfg(feature = "bitcoinconsensus")]
+    Bitcoinconsensus,
+    #[cfg(feature = "script-interpreter")]
+    Interpreter,
+}
+
+const BACKENDS: &[Backend] = &[
+    #[cfg(feature = "bitcoinconsensus")]
+    Backend::Bitcoinconsensus,
+    #[cfg(feature = "script-interpreter")]
+    Backend::Interpreter,
+];
+
 fn verify_tx(
     tx: &Transaction,
     coins: &HashMap<OutPoint, UtxoData>,
     flags: u32,
+    backend: Backend,
 ) -> Result<(), BlockchainError> {
     if tx.is_coinbase() {
         return Ok(Consensus::verify_coinbase(tx)?);
     }
     let mut coins = coins.clone();
-    Consensus::verify_transaction(tx, &mut coins, TX_HEIGHT, true, flags).map(|_| ())
+
+    match backend {
+        // With `bitcoinconsensus` enabled, `verify_transaction` uses it for the scripts
+        #[cfg(feature = "bitcoinconsensus")]
+        Backend::Bitcoinconsensus => {
+            Consensus::verify_transaction(tx, &mut coins, TX_HEIGHT, true, flags).map(|_| ())
+        }
+        #[cfg(feature = "script-interpreter")]
+        Backend::Interpreter => {
+            Consensus::verify_transaction(tx, &mut coins, TX_HEIGHT, false, flags)?;
+            Ok(ScriptCheck::new(tx, &mut coins)?.verify_with_interpreter(flags)?)
+        }
+    }
 }
 
-/// Assert that `verify_tx(tx, coins, flags)` succeeds when `expected` and fails when `!expected`.
+/// Assert that `verify_tx(tx, coins, flags, backend)`, for every enabled backend, succeeds when `expected` and fails when `!expected`.
 /// This function helps debugging all the test data when the assertion fails.
 #[track_caller]
 pub fn assert_tx(
@@ -126,17 +168,19 @@ pub fn assert_tx(
     should_pass: bool,
     raw: &Value,
 ) {
-    let res = verify_tx(tx, coins, flags);
-    match (res, should_pass) {
-        (Err(e), true) => panic!(
-            "Tx unexpectedly failed with flags = {}\nerror: {:?}\n\n{:#?}\n\n{:#?}\n\nRaw JSON vector: {}",
-            fmt_shift_flags(flags), e, tx, coins, raw
-        ),
-        (Ok(_), false) => panic!(
-            "Tx unexpectedly passed with flags = {}\n\n{:#?}\n\n{:#?}\n\nRaw JSON vector: {}",
-            fmt_shift_flags(flags), tx, coins, raw
-        ),
-        _ => {} // expected fail or success
+    for &backend in BACKENDS {
+        let res = verify_tx(tx, coins, flags, backend);
+        match (res, should_pass) {
+            (Err(e), true) => panic!(
+                "Tx unexpectedly failed with {:?} and flags = {}\nerror: {:?}\n\n{:#?}\n\n{:#?}\n\nRaw JSON vector: {}",
+                backend, fmt_shift_flags(flags), e, tx, coins, raw
+            ),
+            (Ok(_), false) => panic!(
+                "Tx unexpectedly passed with {:?} and flags = {}\n\n{:#?}\n\n{:#?}\n\nRaw JSON vector: {}",
+                backend, fmt_shift_flags(flags), tx, coins, raw
+            ),
+            _ => {} // expected fail or success
+        }
     }
 }
 
@@ -211,7 +255,7 @@ fn verify_tx_invalid() {
 
         // 3) Check that flags are minimal: removing *any* enabled flag makes it succeed
         for flags_less in exclude_individual_flags(flags) {
-            // Skip unsupported libbitcoinconsensus flags to avoid a non-validation failure
+            // Skip flags unsupported by the backends to avoid a non-validation failure
             if (flags_less & !VERIFY_ALL) != 0 {
                 continue;
             }
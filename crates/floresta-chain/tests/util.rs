@@ -1,14 +1,18 @@
-#![cfg(all(feature = "bitcoinconsensus", feature = "test-utils"))]
+#![cfg(all(
+    any(feature = "bitcoinconsensus", feature = "script-interpreter"),
+    feature = "test-utils"
+))]
 #![allow(clippy::manual_is_multiple_of)]
 
 use std::collections::HashSet;
 
-use bitcoinconsensus::VERIFY_CHECKLOCKTIMEVERIFY;
-use bitcoinconsensus::VERIFY_CHECKSEQUENCEVERIFY;
-use bitcoinconsensus::VERIFY_DERSIG;
-use bitcoinconsensus::VERIFY_NULLDUMMY;
-use bitcoinconsensus::VERIFY_P2SH;
-use bitcoinconsensus::VERIFY_WITNESS;
+use floresta_chain::verify_flags::VERIFY_CHECKLOCKTIMEVERIFY;
+use floresta_chain::verify_flags::VERIFY_CHECKSEQUENCEVERIFY;
+use floresta_chain::verify_flags::VERIFY_DERSIG;
+use floresta_chain::verify_flags::VERIFY_NULLDUMMY;
+use floresta_chain::verify_flags::VERIFY_P2SH;
+use floresta_chain::verify_flags::VERIFY_TAPROOT;
+use floresta_chain::verify_flags::VERIFY_WITNESS;
 pub use script_asm::parse_script;
 pub use script_asm::ParseScriptError;
 
@@ -39,6 +43,7 @@ pub fn parse_flags(s: &str) -> u32 {
             "MINIMALDATA" => 1 << 6,
             "MINIMALIF" => 1 << 13,
             "WITNESS_PUBKEYTYPE" => 1 << 15,
+            "TAPROOT" => VERIFY_TAPROOT,
 
             "BADTX" => 0, // For Core's `checkTransaction` failures
             other => panic!("unknown flag '{other}' in test-vector"),
@@ -0,0 +1,182 @@
+//! A flat file with the BIP157 filter hash and filter header for each block, that lives next
+//! to our [FlatFiltersStore](crate::flat_filters_store::FlatFiltersStore).
+//!
+//! The first four bytes are the height of the last header we have, and the filter hash and
+//! header for a block are at `4 + height * 64`. We may not have them for old blocks, if we've
+//! started downloading filters after them, and we don't know the filter hash for blocks we only
+//! have a checkpoint for. Those are all zeros.
+
+use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::PoisonError;
+
+use bitcoin::bip158::FilterHash;
+use bitcoin::bip158::FilterHeader;
+use bitcoin::hashes::Hash;
+
+use crate::IterableFilterStoreError;
+
+impl From<PoisonError<MutexGuard<'_, File>>> for IterableFilterStoreError {
+    fn from(_: PoisonError<MutexGuard<'_, File>>) -> Self {
+        IterableFilterStoreError::Poisoned
+    }
+}
+
+#[derive(Debug)]
+pub struct FlatFilterHeadersStore(Mutex<File>);
+
+impl FlatFilterHeadersStore {
+    fn offset(height: u32) -> u64 {
+        4 + height as u64 * 64
+    }
+
+    /// Returns the height of the last header we have, or `None` if we have none
+    pub fn get_height(&self) -> Result<Option<u32>, IterableFilterStoreError> {
+        let mut file = self.0.lock()?;
+        if file.metadata()?.len() < 4 {
+            return Ok(None);
+        }
+
+        let mut buf = [0; 4];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut buf)?;
+
+        Ok(Some(u32::from_le_bytes(buf)))
+    }
+
+    /// Persists the height of the last header we have
+    pub fn set_height(&self, height: u32) -> Result<(), IterableFilterStoreError> {
+        let mut file = self.0.lock()?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&height.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    /// Reads the filter hash and filter header for a block, as they are in the file
+    fn read_entry(&self, height: u32) -> Result<[u8; 64], IterableFilterStoreError> {
+        let mut file = self.0.lock()?;
+        let offset = Self::offset(height);
+        if file.metadata()?.len() < offset + 64 {
+            return Ok([0; 64]);
+        }
+
+        let mut buf = [0; 64];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+
+        Ok(buf)
+    }
+
+    /// Fetches the filter header for a block, if we have it
+    pub fn get_header(
+        &self,
+        height: u32,
+    ) -> Result<Option<FilterHeader>, IterableFilterStoreError> {
+        let entry = self.read_entry(height)?;
+        let header: [u8; 32] = entry[32..].try_into().expect("we've read 64 bytes");
+        if header == [0; 32] {
+            return Ok(None);
+        }
+
+        Ok(Some(FilterHeader::from_byte_array(header)))
+    }
+
+    /// Fetches the hash of a block's filter, if we have it
+    pub fn get_filter_hash(
+        &self,
+        height: u32,
+    ) -> Result<Option<FilterHash>, IterableFilterStoreError> {
+        let entry = self.read_entry(height)?;
+        let hash: [u8; 32] = entry[..32].try_into().expect("we've read 64 bytes");
+        if hash == [0; 32] {
+            return Ok(None);
+        }
+
+        Ok(Some(FilterHash::from_byte_array(hash)))
+    }
+
+    /// Writes the filter hashes and filter headers for consecutive blocks, starting at
+    /// `start_height`
+    pub fn put_headers(
+        &self,
+        start_height: u32,
+        headers: &[(FilterHash, FilterHeader)],
+    ) -> Result<(), IterableFilterStoreError> {
+        let mut file = self.0.lock()?;
+        file.seek(SeekFrom::Start(Self::offset(start_height)))?;
+
+        for (hash, header) in headers {
+            file.write_all(hash.as_byte_array())?;
+            file.write_all(header.as_byte_array())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&PathBuf> for FlatFilterHeadersStore {
+    type Error = std::io::Error;
+
+    fn try_from(path: &PathBuf) -> Result<Self, Self::Error> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        Ok(Self(Mutex::new(file)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::fs::remove_file;
+    use std::path::PathBuf;
+
+    use bitcoin::bip158::FilterHash;
+    use bitcoin::bip158::FilterHeader;
+    use bitcoin::hashes::Hash;
+
+    use super::FlatFilterHeadersStore;
+
+    #[test]
+    fn test_filter_headers_store() {
+        let path = PathBuf::from("test_filter_headers_store");
+        let store = FlatFilterHeadersStore::try_from(&path).unwrap();
+
+        assert_eq!(store.get_height().unwrap(), None);
+        assert_eq!(store.get_header(0).unwrap(), None);
+
+        let checkpoint = FilterHeader::from_byte_array([1; 32]);
+        let hash = FilterHash::from_byte_array([2; 32]);
+        let header = hash.filter_header(&checkpoint);
+
+        // We may start from a checkpoint, without its filter hash
+        store
+            .put_headers(1000, &[(FilterHash::all_zeros(), checkpoint)])
+            .unwrap();
+        store.put_headers(1001, &[(hash, header)]).unwrap();
+        store.set_height(1001).unwrap();
+
+        assert_eq!(store.get_height().unwrap(), Some(1001));
+        assert_eq!(store.get_header(999).unwrap(), None);
+        assert_eq!(store.get_header(1000).unwrap(), Some(checkpoint));
+        assert_eq!(store.get_filter_hash(1000).unwrap(), None);
+        assert_eq!(store.get_header(1001).unwrap(), Some(header));
+        assert_eq!(store.get_filter_hash(1001).unwrap(), Some(hash));
+        assert_eq!(store.get_header(1002).unwrap(), None);
+
+        remove_file(path).expect("could not remove file after test");
+    }
+}
@@ -1,6 +1,8 @@
 use std::convert::TryFrom;
+use std::convert::TryInto;
 use std::fs::File;
 use std::io::BufReader;
+use std::io::ErrorKind;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
@@ -154,10 +156,10 @@ impl IterableFilterStore for FlatFiltersStore {
         let start_height = start_height.unwrap_or(0) as u32;
 
         // round down to the nearest 50_000
-        let start_height = start_height - (start_height % 50_000);
+        let index_height = start_height - (start_height % 50_000);
 
         // take the index by dividing by 50_000
-        let index = (start_height / 50_000) * 8;
+        let index = (index_height / 50_000) * 8;
 
         // seek to the index
         inner.index.seek(SeekFrom::Start(index as u64))?;
@@ -169,6 +171,26 @@ impl IterableFilterStore for FlatFiltersStore {
 
         // seek to the position
         reader.seek(SeekFrom::Start(pos))?;
+
+        // skip the filters before `start_height`, without reading them
+        let mut buf = [0; 8];
+        loop {
+            match reader.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+
+            let height = u32::from_le_bytes(buf[..4].try_into().unwrap());
+            let length = u32::from_le_bytes(buf[4..].try_into().unwrap());
+            if height >= start_height {
+                reader.seek_relative(-8)?;
+                break;
+            }
+
+            reader.seek_relative(length as i64)?;
+        }
+
         Ok(FiltersIterator { reader })
     }
 
@@ -227,9 +249,22 @@ mod tests {
             .put_filter(filter.clone(), 1)
             .expect("could not put filter");
 
+        let filter2 = BlockFilter::new(&[14, 15]);
+        store
+            .put_filter(filter2.clone(), 2)
+            .expect("could not put filter");
+
         let mut iter = store.iter(Some(0)).expect("could not get iterator");
         assert_eq!((1, filter), iter.next().unwrap());
+        assert_eq!((2, filter2.clone()), iter.next().unwrap());
+        assert_eq!(iter.next(), None);
+
+        // We start at the height we've asked for
+        let mut iter = store.iter(Some(2)).expect("could not get iterator");
+        assert_eq!((2, filter2), iter.next().unwrap());
+        assert_eq!(iter.next(), None);
 
+        let mut iter = store.iter(Some(3)).expect("could not get iterator");
         assert_eq!(iter.next(), None);
         remove_file(path).expect("could not remove file after test");
         remove_file(format!("{path}-index")).expect("could not remove index after test");
@@ -21,6 +21,7 @@ use std::sync::RwLockWriteGuard;
 use bitcoin::bip158;
 use flat_filters_store::FlatFiltersStore;
 
+pub mod flat_filter_headers;
 pub mod flat_filters_store;
 pub mod kv_filter_database;
 pub mod network_filters;
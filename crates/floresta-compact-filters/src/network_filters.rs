@@ -1,22 +1,26 @@
 use bitcoin::bip158::BlockFilter;
+use bitcoin::bip158::FilterHash;
+use bitcoin::bip158::FilterHeader;
 use bitcoin::BlockHash;
 use floresta_chain::pruned_utreexo::BlockchainInterface;
 
+use crate::flat_filter_headers::FlatFilterHeadersStore;
 use crate::IterableFilterStore;
 use crate::IterableFilterStoreError;
 
 #[derive(Debug)]
 pub struct NetworkFilters<Storage: IterableFilterStore> {
     filters: Storage,
+    headers: FlatFilterHeadersStore,
 }
 
 impl<Storage: IterableFilterStore> NetworkFilters<Storage> {
-    pub fn new(filters: Storage) -> Self {
+    pub fn new(filters: Storage, headers: FlatFilterHeadersStore) -> Self {
         if filters.get_height().is_err() {
             filters.set_height(0).unwrap();
         }
 
-        Self { filters }
+        Self { filters, headers }
     }
 
     pub fn match_any(
@@ -55,6 +59,22 @@ impl<Storage: IterableFilterStore> NetworkFilters<Storage> {
         self.filters.put_filter(filter, height)
     }
 
+    /// Fetches the filters for the blocks from `start_height` to `stop_height`. We may not
+    /// have some of them.
+    pub fn get_filters(
+        &self,
+        start_height: u32,
+        stop_height: u32,
+    ) -> Result<Vec<(u32, BlockFilter)>, IterableFilterStoreError> {
+        let filters = self
+            .filters
+            .iter(Some(start_height as usize))?
+            .take_while(|(height, _)| *height <= stop_height)
+            .collect();
+
+        Ok(filters)
+    }
+
     pub fn get_height(&self) -> Result<u32, IterableFilterStoreError> {
         self.filters.get_height()
     }
@@ -62,4 +82,39 @@ impl<Storage: IterableFilterStore> NetworkFilters<Storage> {
     pub fn save_height(&self, height: u32) -> Result<(), IterableFilterStoreError> {
         self.filters.set_height(height)
     }
+
+    /// Fetches the filter header for a block, if we have it
+    pub fn get_filter_header(
+        &self,
+        height: u32,
+    ) -> Result<Option<FilterHeader>, IterableFilterStoreError> {
+        self.headers.get_header(height)
+    }
+
+    /// Fetches the hash of a block's filter, if we have it
+    pub fn get_filter_hash(
+        &self,
+        height: u32,
+    ) -> Result<Option<FilterHash>, IterableFilterStoreError> {
+        self.headers.get_filter_hash(height)
+    }
+
+    /// Stores the filter hashes and filter headers for consecutive blocks, after we've
+    /// checked them
+    pub fn push_filter_headers(
+        &self,
+        start_height: u32,
+        headers: &[(FilterHash, FilterHeader)],
+    ) -> Result<(), IterableFilterStoreError> {
+        self.headers.put_headers(start_height, headers)
+    }
+
+    /// The height of the last filter header we have
+    pub fn get_headers_height(&self) -> Result<Option<u32>, IterableFilterStoreError> {
+        self.headers.get_height()
+    }
+
+    pub fn save_headers_height(&self, height: u32) -> Result<(), IterableFilterStoreError> {
+        self.headers.set_height(height)
+    }
 }
@@ -11,6 +11,7 @@ use bitcoin::Transaction;
 use bitcoin::TxOut;
 use bitcoin::Txid;
 use floresta_chain::pruned_utreexo::BlockchainInterface;
+use floresta_chain::MIN_RELAY_FEERATE;
 use floresta_common::get_hash_from_u8;
 use floresta_common::get_spk_hash;
 use floresta_common::spsc::Channel;
@@ -263,7 +264,17 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
                     "max": 2016
                 })
             }
-            "blockchain.estimatefee" => json_rpc_res!(request, 0.0001),
+            "blockchain.estimatefee" => {
+                let target = get_arg!(request, usize, 0);
+                let feerate = self
+                    .chain
+                    .estimate_fee(target)
+                    .map_err(|e| super::error::Error::Blockchain(Box::new(e)))?;
+
+                // Electrum expects BTC/kvB, while our estimator works with sat/vB
+                let feerate = sat_per_vbyte_to_btc_per_kvbyte(feerate);
+                json_rpc_res!(request, feerate)
+            }
             "blockchain.headers.subscribe" => {
                 let (height, hash) = self
                     .chain
@@ -279,8 +290,10 @@ impl<Blockchain: BlockchainInterface> ElectrumServer<Blockchain> {
                 });
                 json_rpc_res!(request, result)
             }
-            // TODO: Ask Backend for fees
-            "blockchain.relayfee" => json_rpc_res!(request, 0.00001),
+            "blockchain.relayfee" => {
+                let feerate = sat_per_vbyte_to_btc_per_kvbyte(MIN_RELAY_FEERATE);
+                json_rpc_res!(request, feerate)
+            }
             "blockchain.scripthash.get_balance" => {
                 let script_hash = get_arg!(request, sha256::Hash, 0);
                 let balance = self.address_cache.get_address_balance(&script_hash);
@@ -846,6 +859,11 @@ fn get_status(transactions: Vec<CachedTransaction>) -> sha256::Hash {
     get_hash_from_u8(status_preimage.as_bytes())
 }
 
+/// Converts a feerate in sat/vB to BTC/kvB, the unit used by the Electrum protocol.
+fn sat_per_vbyte_to_btc_per_kvbyte(feerate: f64) -> f64 {
+    feerate * 1_000.0 / 100_000_000.0
+}
+
 #[macro_export]
 /// Builds the response as defined by jsonrpc v2.0. Request should have type [Request] and the
 /// response is always a [json]
@@ -1052,9 +1070,17 @@ mod test {
             max_inflight: 20,
             assume_utreexo: None,
             backfill: false,
+            backfill_ranges: 1,
             filter_start_height: None,
             user_agent: "floresta".to_string(),
             allow_v1_fallback: true,
+            chain_params: None,
+            bridge: false,
+            block_cache_depth: 0,
+            block_cache_size: None,
+            listen: None,
+            max_inbound: 0,
+            whitelist: Vec::new(),
         };
 
         let chain_provider: UtreexoNode<Arc<ChainState<FlatChainStore>>, RunningNode> =
@@ -1152,7 +1178,7 @@ mod test {
             "blockchain.block.headers" => {
                 vec![req_params.pop().unwrap(), req_params.pop().unwrap()]
             }
-            "blockchain.estimatefee" => vec![],
+            "blockchain.estimatefee" => vec![req_params.pop().unwrap()],
             "blockchain.relayfee" => vec![],
             "blockchain.scripthash.subscribe" => vec![req_params.pop().unwrap()],
             "blockchain.scripthash.unsubscribe" => vec![req_params.pop().unwrap()],
@@ -1242,7 +1268,7 @@ mod test {
 
         // blockchain.estimatefee
         let method = Value::String("blockchain.estimatefee".to_string());
-        let estimatefee_req = vec![method];
+        let estimatefee_req = vec![Value::Number(Number::from(6)), method];
 
         // blockchain.relayfee
         let method = Value::String("blockchain.relayfee".to_string());
@@ -1258,7 +1284,8 @@ mod test {
 
         let batch_response = send_request(batch_req, port).await.unwrap();
 
-        assert_eq!(batch_response[0]["result"], 0.0001);
+        // We don't have any data yet, so we should get the minimum relay fee
+        assert_eq!(batch_response[0]["result"], 0.00001);
         assert_eq!(batch_response[1]["result"], 0.00001);
     }
 
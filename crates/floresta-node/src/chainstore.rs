@@ -0,0 +1,304 @@
+//! Runtime selection of the [ChainStore] backend.
+//!
+//! Every backend enabled at compile time can be picked with [Config::chainstore_backend], and
+//! [AnyChainStore] dispatches to the one we've opened. This also lets us move an existing chain
+//! from one backend to another, see [Config::migrate_chainstore_from].
+//!
+//! If [Config::chainstore_compaction] is set, [compaction_task] periodically removes old fork
+//! headers and roots from it.
+//!
+//! [Config::chainstore_backend]: crate::Config::chainstore_backend
+//! [Config::migrate_chainstore_from]: crate::Config::migrate_chainstore_from
+//! [Config::chainstore_compaction]: crate::Config::chainstore_compaction
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bitcoin::BlockHash;
+use floresta_chain::BestChain;
+use floresta_chain::ChainState;
+use floresta_chain::ChainStore;
+use floresta_chain::CompactionPolicy;
+use floresta_chain::CompactionStats;
+use floresta_chain::DatabaseError;
+use floresta_chain::DiskBlockHeader;
+#[cfg(feature = "flat-chainstore")]
+use floresta_chain::FlatChainStore;
+#[cfg(feature = "flat-chainstore")]
+use floresta_chain::FlatChainStoreConfig;
+#[cfg(feature = "flat-chainstore")]
+use floresta_chain::FlatChainstoreError;
+#[cfg(feature = "kv-chainstore")]
+use floresta_chain::KvChainStore;
+#[cfg(feature = "sqlite-chainstore")]
+use floresta_chain::SqliteChainStore;
+#[cfg(feature = "sqlite-chainstore")]
+use floresta_chain::SqliteChainStoreError;
+use tokio::sync::RwLock;
+use tokio::task;
+use tokio::time::sleep;
+use tracing::error;
+use tracing::info;
+
+/// How often we compact our chainstore
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Which database we use to store our chain data
+pub enum ChainStoreBackend {
+    #[cfg(feature = "flat-chainstore")]
+    /// Memory-mapped flat files, stored under `<data_dir>/chaindata`
+    Flat,
+
+    #[cfg(feature = "kv-chainstore")]
+    /// A key-value store, stored under `<data_dir>/chain_data`
+    Kv,
+
+    #[cfg(feature = "sqlite-chainstore")]
+    /// A SQLite database, stored at `<data_dir>/chainstore.sqlite`
+    Sqlite,
+}
+
+#[allow(clippy::derivable_impls, clippy::needless_return, unreachable_code)]
+impl Default for ChainStoreBackend {
+    fn default() -> Self {
+        #[cfg(feature = "flat-chainstore")]
+        return ChainStoreBackend::Flat;
+
+        #[cfg(feature = "kv-chainstore")]
+        return ChainStoreBackend::Kv;
+
+        #[cfg(feature = "sqlite-chainstore")]
+        return ChainStoreBackend::Sqlite;
+    }
+}
+
+impl FromStr for ChainStoreBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            #[cfg(feature = "flat-chainstore")]
+            "flat" => Ok(ChainStoreBackend::Flat),
+            #[cfg(feature = "kv-chainstore")]
+            "kv" => Ok(ChainStoreBackend::Kv),
+            #[cfg(feature = "sqlite-chainstore")]
+            "sqlite" => Ok(ChainStoreBackend::Sqlite),
+            _ => Err(format!(
+                "unknown or disabled chainstore backend {s}, available: {}",
+                ChainStoreBackend::available().join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ChainStoreBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "flat-chainstore")]
+            ChainStoreBackend::Flat => write!(f, "flat"),
+            #[cfg(feature = "kv-chainstore")]
+            ChainStoreBackend::Kv => write!(f, "kv"),
+            #[cfg(feature = "sqlite-chainstore")]
+            ChainStoreBackend::Sqlite => write!(f, "sqlite"),
+        }
+    }
+}
+
+impl ChainStoreBackend {
+    /// The names of all backends enabled in this build
+    pub fn available() -> Vec<&'static str> {
+        vec![
+            #[cfg(feature = "flat-chainstore")]
+            "flat",
+            #[cfg(feature = "kv-chainstore")]
+            "kv",
+            #[cfg(feature = "sqlite-chainstore")]
+            "sqlite",
+        ]
+    }
+}
+
+#[derive(Debug)]
+/// An error returned by the backend behind an [AnyChainStore]
+pub enum AnyChainStoreError {
+    #[cfg(feature = "flat-chainstore")]
+    Flat(FlatChainstoreError),
+
+    #[cfg(feature = "kv-chainstore")]
+    Kv(kv::Error),
+
+    #[cfg(feature = "sqlite-chainstore")]
+    Sqlite(SqliteChainStoreError),
+}
+
+impl DatabaseError for AnyChainStoreError {}
+
+impl fmt::Display for AnyChainStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "flat-chainstore")]
+            AnyChainStoreError::Flat(err) => write!(f, "Flat chainstore error: {err:?}"),
+            #[cfg(feature = "kv-chainstore")]
+            AnyChainStoreError::Kv(err) => write!(f, "Key-value chainstore error: {err}"),
+            #[cfg(feature = "sqlite-chainstore")]
+            AnyChainStoreError::Sqlite(err) => write!(f, "SQLite chainstore error: {err}"),
+        }
+    }
+}
+
+/// A [ChainStore] that may be any of the backends enabled in this build
+pub enum AnyChainStore {
+    #[cfg(feature = "flat-chainstore")]
+    Flat(FlatChainStore),
+
+    #[cfg(feature = "kv-chainstore")]
+    Kv(KvChainStore<'static>),
+
+    #[cfg(feature = "sqlite-chainstore")]
+    Sqlite(SqliteChainStore),
+}
+
+impl AnyChainStore {
+    /// Opens (or creates) the chain store for `backend` inside `data_dir`
+    pub fn open(backend: ChainStoreBackend, data_dir: &str) -> Result<Self, AnyChainStoreError> {
+        match backend {
+            #[cfg(feature = "flat-chainstore")]
+            ChainStoreBackend::Flat => {
+                let config = FlatChainStoreConfig::new(format!("{data_dir}/chaindata"));
+                FlatChainStore::new(config)
+                    .map(AnyChainStore::Flat)
+                    .map_err(AnyChainStoreError::Flat)
+            }
+
+            #[cfg(feature = "kv-chainstore")]
+            ChainStoreBackend::Kv => KvChainStore::new(data_dir.to_string())
+                .map(AnyChainStore::Kv)
+                .map_err(AnyChainStoreError::Kv),
+
+            #[cfg(feature = "sqlite-chainstore")]
+            ChainStoreBackend::Sqlite => {
+                SqliteChainStore::new(&format!("{data_dir}/chainstore.sqlite"))
+                    .map(AnyChainStore::Sqlite)
+                    .map_err(AnyChainStoreError::Sqlite)
+            }
+        }
+    }
+}
+
+/// Calls `$method` on whichever store is inside `$self`, wrapping its error
+macro_rules! dispatch {
+    ($self:ident, $method:ident($($arg:expr),*)) => {
+        match $self {
+            #[cfg(feature = "flat-chainstore")]
+            AnyChainStore::Flat(store) => store.$method($($arg),*).map_err(AnyChainStoreError::Flat),
+            #[cfg(feature = "kv-chainstore")]
+            AnyChainStore::Kv(store) => store.$method($($arg),*).map_err(AnyChainStoreError::Kv),
+            #[cfg(feature = "sqlite-chainstore")]
+            AnyChainStore::Sqlite(store) => {
+                store.$method($($arg),*).map_err(AnyChainStoreError::Sqlite)
+            }
+        }
+    };
+}
+
+impl ChainStore for AnyChainStore {
+    type Error = AnyChainStoreError;
+
+    fn save_roots_for_block(&mut self, roots: Vec<u8>, height: u32) -> Result<(), Self::Error> {
+        dispatch!(self, save_roots_for_block(roots, height))
+    }
+
+    fn load_roots_for_block(&mut self, height: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        dispatch!(self, load_roots_for_block(height))
+    }
+
+    fn load_height(&self) -> Result<Option<BestChain>, Self::Error> {
+        dispatch!(self, load_height())
+    }
+
+    fn save_height(&mut self, height: &BestChain) -> Result<(), Self::Error> {
+        dispatch!(self, save_height(height))
+    }
+
+    fn save_fee_estimates(&mut self, estimates: Vec<u8>) -> Result<(), Self::Error> {
+        dispatch!(self, save_fee_estimates(estimates))
+    }
+
+    fn load_fee_estimates(&mut self) -> Result<Option<Vec<u8>>, Self::Error> {
+        dispatch!(self, load_fee_estimates())
+    }
+
+    fn save_undo_for_block(&mut self, undo: Vec<u8>, height: u32) -> Result<(), Self::Error> {
+        dispatch!(self, save_undo_for_block(undo, height))
+    }
+
+    fn load_undo_for_block(&mut self, height: u32) -> Result<Option<Vec<u8>>, Self::Error> {
+        dispatch!(self, load_undo_for_block(height))
+    }
+
+    fn get_header(&self, block_hash: &BlockHash) -> Result<Option<DiskBlockHeader>, Self::Error> {
+        dispatch!(self, get_header(block_hash))
+    }
+
+    fn get_header_by_height(&self, height: u32) -> Result<Option<DiskBlockHeader>, Self::Error> {
+        dispatch!(self, get_header_by_height(height))
+    }
+
+    fn save_header(&mut self, header: &DiskBlockHeader) -> Result<(), Self::Error> {
+        dispatch!(self, save_header(header))
+    }
+
+    fn get_block_hash(&self, height: u32) -> Result<Option<BlockHash>, Self::Error> {
+        dispatch!(self, get_block_hash(height))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        dispatch!(self, flush())
+    }
+
+    fn update_block_index(&mut self, height: u32, hash: BlockHash) -> Result<(), Self::Error> {
+        dispatch!(self, update_block_index(height, hash))
+    }
+
+    fn check_integrity(&self) -> Result<(), Self::Error> {
+        dispatch!(self, check_integrity())
+    }
+
+    fn compact(
+        &mut self,
+        policy: &CompactionPolicy,
+        best_height: u32,
+        validation_height: u32,
+    ) -> Result<CompactionStats, Self::Error> {
+        dispatch!(self, compact(policy, best_height, validation_height))
+    }
+}
+
+/// Compacts our chainstore every [COMPACTION_INTERVAL], following `policy`. This runs until
+/// `kill_signal` is set.
+pub async fn compaction_task(
+    chain: Arc<ChainState<AnyChainStore>>,
+    policy: CompactionPolicy,
+    kill_signal: Arc<RwLock<bool>>,
+) {
+    while !*kill_signal.read().await {
+        sleep(COMPACTION_INTERVAL).await;
+
+        // This holds our chain's lock while moving data around, so keep it off the runtime
+        let _chain = chain.clone();
+        let result = task::spawn_blocking(move || _chain.compact_chainstore(&policy)).await;
+
+        match result {
+            Ok(Ok(stats)) if stats == CompactionStats::default() => {}
+            Ok(Ok(stats)) => info!(
+                "Compacted our chainstore, removed {} fork headers, the roots for {} blocks and the undo data for {} blocks",
+                stats.fork_headers, stats.roots, stats.undo
+            ),
+            Ok(Err(e)) => error!("Could not compact our chainstore: {e}"),
+            Err(e) => error!("Our chainstore compaction panicked: {e}"),
+        }
+    }
+}
@@ -0,0 +1,815 @@
+//! An optional index with statistics about the UTXO set at every height, like Core's
+//! `coinstatsindex`.
+//!
+//! With Utreexo we don't have the UTXO set, so we can't walk it to answer `gettxoutsetinfo`.
+//! Instead, we follow our chain as a [BlockConsumer] and keep running totals: every block adds
+//! its outputs and removes the UTXOs it spends, which we get from its Utreexo proof. Besides the
+//! UTXO count, total amount and bogosize, we keep a [MuHash3072] commitment to the set, that can
+//! be compared with Core's `gettxoutsetinfo muhash`.
+//!
+//! Since every block changes the totals of the previous one, this index must be built from
+//! genesis, as we validate the chain. If it misses a block (e.g. it was enabled after our node
+//! synced, or we've used assumeutreexo), it stops, and tells so in `getindexinfo`.
+//!
+//! The totals are saved for every height, but the MuHash state is big, so we only keep it for the
+//! last [REORG_DEPTH] blocks. That's also how deep a reorg we can roll back.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use bitcoin::consensus::deserialize;
+use bitcoin::consensus::encode;
+use bitcoin::consensus::serialize;
+use bitcoin::Amount;
+use bitcoin::Block;
+use bitcoin::BlockHash;
+use bitcoin::OutPoint;
+use bitcoin::ScriptBuf;
+use bitcoin::TxOut;
+use floresta_chain::BlockConsumer;
+use floresta_chain::BlockchainInterface;
+use floresta_chain::UtxoData;
+use floresta_common::impl_error_from;
+use kv::Bucket;
+use kv::Config;
+use kv::Integer;
+use kv::Store;
+use tracing::error;
+use tracing::warn;
+
+use crate::muhash::MuHash3072;
+use crate::muhash::MuHashDigest;
+use crate::muhash::MUHASH_SIZE;
+use crate::tx_index::IndexInfo;
+
+/// How many blocks we keep the MuHash state for, and how deep a reorg we can roll back
+pub const REORG_DEPTH: u32 = 100;
+
+/// Where we keep the last height we've indexed
+const BEST_HEIGHT_KEY: &[u8] = b"best_height";
+
+/// Scripts bigger than this can't be spent, so they're not in the UTXO set
+const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// The blocks whose coinbase was overwritten by a later one with the same txid, before BIP30.
+/// Their outputs can't be spent, so Core doesn't count them.
+const BIP30_UNSPENDABLE: [(u32, &str); 2] = [
+    (
+        91722,
+        "00000000000271a2dc26e7667f8419f2e15416dc6955e5a6c6cdf3f2574dd08e",
+    ),
+    (
+        91812,
+        "00000000000af0aed4792b1acee3d966af36cf5def14935db8de83d6f9306f2f",
+    ),
+];
+
+#[derive(Debug)]
+/// Errors returned by the [CoinStatsIndex]
+pub enum CoinStatsError {
+    /// Our database returned an error
+    Db(kv::Error),
+
+    /// Some data in our database is corrupted
+    Decode(encode::Error),
+
+    /// We didn't get the UTXOs spent by a block
+    MissingSpentUtxos(u32),
+
+    /// We need to roll back further than the MuHash states we keep
+    ReorgTooDeep(u32),
+}
+
+impl Display for CoinStatsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CoinStatsError::Db(e) => write!(f, "database error: {e}"),
+            CoinStatsError::Decode(e) => write!(f, "corrupted coinstats data: {e}"),
+            CoinStatsError::MissingSpentUtxos(height) => {
+                write!(f, "missing the spent UTXOs for block {height}")
+            }
+            CoinStatsError::ReorgTooDeep(height) => {
+                write!(f, "can't roll back to block {height}, only the last {REORG_DEPTH} blocks are kept")
+            }
+        }
+    }
+}
+
+impl_error_from!(CoinStatsError, kv::Error, Db);
+impl_error_from!(CoinStatsError, encode::Error, Decode);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The UTXO set statistics after a block
+pub struct CoinStats {
+    /// The block these statistics are for
+    pub block_hash: BlockHash,
+
+    /// How many UTXOs there are
+    pub txouts: u64,
+
+    /// A rough estimate of the UTXO set size, as defined by Core
+    pub bogosize: u64,
+
+    /// The sum of all UTXOs
+    pub total_amount: Amount,
+
+    /// Coins that were created, or could have been, but can never be spent: the genesis reward,
+    /// `OP_RETURN` outputs, BIP30 duplicates, and subsidy or fees not claimed by miners
+    pub total_unspendable_amount: Amount,
+}
+
+impl CoinStats {
+    /// The statistics before any block
+    fn empty(block_hash: BlockHash) -> CoinStats {
+        CoinStats {
+            block_hash,
+            txouts: 0,
+            bogosize: 0,
+            total_amount: Amount::ZERO,
+            total_unspendable_amount: Amount::ZERO,
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        serialize(&(
+            self.block_hash,
+            self.txouts,
+            self.bogosize,
+            self.total_amount.to_sat(),
+            self.total_unspendable_amount.to_sat(),
+        ))
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<CoinStats, encode::Error> {
+        let (block_hash, txouts, bogosize, total_amount, total_unspendable_amount) =
+            deserialize::<(BlockHash, u64, u64, u64, u64)>(bytes)?;
+
+        Ok(CoinStats {
+            block_hash,
+            txouts,
+            bogosize,
+            total_amount: Amount::from_sat(total_amount),
+            total_unspendable_amount: Amount::from_sat(total_unspendable_amount),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Where our index is
+struct IndexState {
+    /// The last height, and its statistics, we've indexed, if any
+    best: Option<(u32, CoinStats)>,
+
+    /// The MuHash of the UTXO set after our best block
+    muhash: MuHash3072,
+
+    /// Whether we've missed a block, and can't go on
+    stalled: bool,
+}
+
+/// Keeps statistics about the UTXO set at every height
+pub struct CoinStatsIndex {
+    /// Our key/value store. We don't use it directly, but the buckets need it alive
+    _store: Store,
+
+    /// The statistics after each block, by height
+    stats: Bucket<'static, Integer, Vec<u8>>,
+
+    /// The MuHash state after each of the last [REORG_DEPTH] blocks, by height
+    muhash: Bucket<'static, Integer, Vec<u8>>,
+
+    /// Which blocks we have indexed
+    meta: Bucket<'static, Vec<u8>, Vec<u8>>,
+
+    /// After how many blocks the subsidy halves, used to find unclaimed rewards
+    subsidy_halving_interval: u32,
+
+    /// Our state. This lock also serializes writes.
+    state: Mutex<IndexState>,
+}
+
+#[cfg_attr(not(feature = "json-rpc"), allow(dead_code))]
+impl CoinStatsIndex {
+    /// Opens the index saved in `path`, or creates a new one
+    pub fn new(
+        path: String,
+        subsidy_halving_interval: u32,
+    ) -> Result<CoinStatsIndex, CoinStatsError> {
+        let store = Store::new(Config::new(path))?;
+        let stats: Bucket<'static, Integer, Vec<u8>> = store.bucket(Some("stats"))?;
+        let muhash: Bucket<'static, Integer, Vec<u8>> = store.bucket(Some("muhash"))?;
+        let meta: Bucket<'static, Vec<u8>, Vec<u8>> = store.bucket(Some("meta"))?;
+
+        let mut state = IndexState {
+            best: None,
+            muhash: MuHash3072::new(),
+            stalled: false,
+        };
+
+        if let Some(height) = meta.get(&BEST_HEIGHT_KEY.to_vec())? {
+            let height: u32 = deserialize(&height)?;
+            state.best = Self::load_stats(&stats, height)?.map(|stats| (height, stats));
+            state.muhash = Self::load_muhash(&muhash, height)?.unwrap_or_default();
+        }
+
+        Ok(CoinStatsIndex {
+            _store: store,
+            stats,
+            muhash,
+            meta,
+            subsidy_halving_interval,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Returns the statistics after the block at `height`, and the MuHash of the UTXO set if
+    /// `with_muhash` is set. The MuHash is only available for the last [REORG_DEPTH] blocks.
+    pub fn get_stats(
+        &self,
+        height: u32,
+        with_muhash: bool,
+    ) -> Result<Option<(CoinStats, Option<MuHashDigest>)>, CoinStatsError> {
+        let Some(stats) = Self::load_stats(&self.stats, height)? else {
+            return Ok(None);
+        };
+
+        let muhash = match with_muhash {
+            true => Self::load_muhash(&self.muhash, height)?.map(|muhash| muhash.finalize()),
+            false => None,
+        };
+
+        Ok(Some((stats, muhash)))
+    }
+
+    /// Returns the last height we've indexed, if any
+    pub fn best_height(&self) -> Option<u32> {
+        self.state.lock().unwrap().best.map(|(height, _)| height)
+    }
+
+    /// Returns how far we've indexed, compared to our chain's `tip`
+    pub fn info(&self, tip: u32) -> IndexInfo {
+        let state = self.state.lock().unwrap();
+        let best_block_height = state.best.map(|(height, _)| height).unwrap_or(0);
+
+        IndexInfo {
+            synced: !state.stalled && state.best.is_some() && best_block_height >= tip,
+            best_block_height,
+        }
+    }
+
+    /// Rolls back the blocks that aren't in `chain` anymore, e.g. if it reorged while we were
+    /// offline. A new index starts with our chain's `genesis`, since our chain never sends it.
+    pub fn check_consistency(&self, chain: &impl BlockchainInterface, genesis: &Block) {
+        let mut state = self.state.lock().unwrap();
+        let Some((best, _)) = state.best else {
+            drop(state);
+
+            if let Err(e) = self.connect_block(genesis, 0, None) {
+                error!("Could not add the genesis block to our coinstatsindex: {e}");
+            }
+            return;
+        };
+
+        let mut height = best;
+        loop {
+            let stats = Self::load_stats(&self.stats, height).ok().flatten();
+            let in_chain = stats
+                .is_some_and(|stats| chain.get_block_hash(height).ok() == Some(stats.block_hash));
+
+            if in_chain || height == 0 {
+                break;
+            }
+            height -= 1;
+        }
+
+        if height == best {
+            return;
+        }
+
+        warn!("Our coinstatsindex is ahead of our chain, rolling back to block {height}");
+        if let Err(e) = self.rollback(&mut state, height + 1) {
+            error!("Could not roll back our coinstatsindex: {e}");
+            state.stalled = true;
+        }
+    }
+
+    /// Updates our statistics with a new block.
+    ///
+    /// Blocks must be connected in order. If `height` is a height we've already indexed, that
+    /// block was reorged out, so we roll back to the block before it first.
+    pub fn connect_block(
+        &self,
+        block: &Block,
+        height: u32,
+        spent_utxos: Option<&HashMap<OutPoint, UtxoData>>,
+    ) -> Result<(), CoinStatsError> {
+        let mut state = self.state.lock().unwrap();
+        let next_height = state.best.map(|(height, _)| height + 1).unwrap_or(0);
+
+        if height > next_height {
+            if !state.stalled {
+                error!(
+                    "Our coinstatsindex is missing block {next_height}, it must be built from genesis"
+                );
+                state.stalled = true;
+            }
+            return Ok(());
+        }
+
+        if height < next_height {
+            self.rollback(&mut state, height)?;
+        }
+
+        let prev_stats = state.best.map(|(_, stats)| stats);
+        let (stats, muhash) =
+            self.apply_block(prev_stats, state.muhash, block, height, spent_utxos)?;
+
+        self.stats.set(&Integer::from(height), &stats.to_bytes())?;
+        self.muhash
+            .set(&Integer::from(height), &muhash.to_bytes().to_vec())?;
+        if let Some(old) = height.checked_sub(REORG_DEPTH) {
+            self.muhash.remove(&Integer::from(old))?;
+        }
+        self.meta
+            .set(&BEST_HEIGHT_KEY.to_vec(), &serialize(&height))?;
+
+        state.best = Some((height, stats));
+        state.muhash = muhash;
+        state.stalled = false;
+
+        Ok(())
+    }
+
+    /// Computes the statistics after `block`, given the ones before it
+    fn apply_block(
+        &self,
+        prev_stats: Option<CoinStats>,
+        mut muhash: MuHash3072,
+        block: &Block,
+        height: u32,
+        spent_utxos: Option<&HashMap<OutPoint, UtxoData>>,
+    ) -> Result<(CoinStats, MuHash3072), CoinStatsError> {
+        let block_hash = block.block_hash();
+        let mut stats = prev_stats.unwrap_or_else(|| CoinStats::empty(block_hash));
+        stats.block_hash = block_hash;
+
+        // The genesis outputs aren't in the UTXO set
+        if height == 0 {
+            stats.total_unspendable_amount += self.subsidy(height);
+            return Ok((stats, muhash));
+        }
+
+        // A block with only its coinbase doesn't spend anything
+        let no_spent_utxos = HashMap::new();
+        let spent_utxos = match spent_utxos {
+            Some(spent_utxos) => spent_utxos,
+            None if block.txdata.len() == 1 => &no_spent_utxos,
+            None => return Err(CoinStatsError::MissingSpentUtxos(height)),
+        };
+
+        // Outputs spent in the same block that created them never make it to the UTXO set
+        let spent_outpoints = block
+            .txdata
+            .iter()
+            .filter(|tx| !tx.is_coinbase())
+            .flat_map(|tx| tx.input.iter().map(|input| input.previous_output))
+            .collect::<HashSet<_>>();
+
+        let is_bip30_unspendable = BIP30_UNSPENDABLE.iter().any(|(bip30_height, hash)| {
+            *bip30_height == height && BlockHash::from_str(hash).ok() == Some(block_hash)
+        });
+
+        let mut claimed = Amount::ZERO;
+        let mut fees = Amount::ZERO;
+        for tx in block.txdata.iter() {
+            let txid = tx.compute_txid();
+            let is_coinbase = tx.is_coinbase();
+            let output_value = tx.output.iter().map(|output| output.value).sum::<Amount>();
+
+            match is_coinbase {
+                true => claimed += output_value,
+                false => {
+                    let mut input_value = Amount::ZERO;
+                    for input in tx.input.iter() {
+                        let utxo = spent_utxos
+                            .get(&input.previous_output)
+                            .ok_or(CoinStatsError::MissingSpentUtxos(height))?;
+                        input_value += utxo.txout.value;
+
+                        // Created in this block, so we've never added it
+                        if utxo.creation_height == height {
+                            continue;
+                        }
+
+                        Self::remove_coin(&mut stats, &mut muhash, input.previous_output, utxo);
+                    }
+
+                    fees += input_value
+                        .checked_sub(output_value)
+                        .unwrap_or(Amount::ZERO);
+                }
+            }
+
+            for (vout, output) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint::new(txid, vout as u32);
+                if (is_coinbase && is_bip30_unspendable)
+                    || Self::is_unspendable(&output.script_pubkey)
+                {
+                    stats.total_unspendable_amount += output.value;
+                    continue;
+                }
+
+                if spent_outpoints.contains(&outpoint) {
+                    continue;
+                }
+
+                Self::add_coin(
+                    &mut stats,
+                    &mut muhash,
+                    outpoint,
+                    output,
+                    height,
+                    is_coinbase,
+                );
+            }
+        }
+
+        // Whatever the miner didn't claim is lost forever
+        let unclaimed = (self.subsidy(height) + fees)
+            .checked_sub(claimed)
+            .unwrap_or(Amount::ZERO);
+        stats.total_unspendable_amount += unclaimed;
+
+        Ok((stats, muhash))
+    }
+
+    /// Goes back to the statistics before the block at `height`
+    fn rollback(&self, state: &mut IndexState, height: u32) -> Result<(), CoinStatsError> {
+        let Some((best, _)) = state.best else {
+            return Ok(());
+        };
+
+        let previous = match height.checked_sub(1) {
+            Some(previous) => {
+                let stats = Self::load_stats(&self.stats, previous)?
+                    .ok_or(CoinStatsError::ReorgTooDeep(previous))?;
+                let muhash = Self::load_muhash(&self.muhash, previous)?
+                    .ok_or(CoinStatsError::ReorgTooDeep(previous))?;
+
+                Some((previous, stats, muhash))
+            }
+            None => None,
+        };
+
+        for reorged in height..=best {
+            self.stats.remove(&Integer::from(reorged))?;
+            self.muhash.remove(&Integer::from(reorged))?;
+        }
+
+        match previous {
+            Some((previous, stats, muhash)) => {
+                self.meta
+                    .set(&BEST_HEIGHT_KEY.to_vec(), &serialize(&previous))?;
+                state.best = Some((previous, stats));
+                state.muhash = muhash;
+            }
+            None => {
+                self.meta.remove(&BEST_HEIGHT_KEY.to_vec())?;
+                state.best = None;
+                state.muhash = MuHash3072::new();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a new UTXO to our statistics
+    fn add_coin(
+        stats: &mut CoinStats,
+        muhash: &mut MuHash3072,
+        outpoint: OutPoint,
+        output: &TxOut,
+        height: u32,
+        is_coinbase: bool,
+    ) {
+        stats.txouts += 1;
+        stats.bogosize += Self::bogosize(&output.script_pubkey);
+        stats.total_amount += output.value;
+        muhash.insert(&Self::serialize_coin(outpoint, output, height, is_coinbase));
+    }
+
+    /// Removes a spent UTXO from our statistics
+    fn remove_coin(
+        stats: &mut CoinStats,
+        muhash: &mut MuHash3072,
+        outpoint: OutPoint,
+        utxo: &UtxoData,
+    ) {
+        stats.txouts = stats.txouts.saturating_sub(1);
+        stats.bogosize = stats
+            .bogosize
+            .saturating_sub(Self::bogosize(&utxo.txout.script_pubkey));
+        stats.total_amount = stats
+            .total_amount
+            .checked_sub(utxo.txout.value)
+            .unwrap_or(Amount::ZERO);
+        muhash.remove(&Self::serialize_coin(
+            outpoint,
+            &utxo.txout,
+            utxo.creation_height,
+            utxo.is_coinbase,
+        ));
+    }
+
+    /// Serializes a UTXO the way Core does before adding it to its MuHash: the outpoint, the
+    /// height and coinbase flag (as `height << 1 | coinbase`), then the output
+    fn serialize_coin(
+        outpoint: OutPoint,
+        output: &TxOut,
+        height: u32,
+        is_coinbase: bool,
+    ) -> Vec<u8> {
+        let mut data = serialize(&outpoint);
+        data.extend(serialize(&((height << 1) | is_coinbase as u32)));
+        data.extend(serialize(output));
+
+        data
+    }
+
+    /// Core's estimate of how many bytes a UTXO takes
+    fn bogosize(script: &ScriptBuf) -> u64 {
+        // txid, vout, height and coinbase flag, amount, script length, then the script
+        (32 + 4 + 4 + 8 + 2 + script.len()) as u64
+    }
+
+    /// Whether an output can never be spent, and isn't part of the UTXO set
+    fn is_unspendable(script: &ScriptBuf) -> bool {
+        script.is_op_return() || script.len() > MAX_SCRIPT_SIZE
+    }
+
+    /// The new coins a block at `height` may create
+    fn subsidy(&self, height: u32) -> Amount {
+        let halvings = height / self.subsidy_halving_interval;
+        match halvings {
+            0..=63 => Amount::from_sat((50 * Amount::ONE_BTC.to_sat()) >> halvings),
+            _ => Amount::ZERO,
+        }
+    }
+
+    fn load_stats(
+        bucket: &Bucket<'static, Integer, Vec<u8>>,
+        height: u32,
+    ) -> Result<Option<CoinStats>, CoinStatsError> {
+        match bucket.get(&Integer::from(height))? {
+            Some(bytes) => Ok(Some(CoinStats::from_bytes(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn load_muhash(
+        bucket: &Bucket<'static, Integer, Vec<u8>>,
+        height: u32,
+    ) -> Result<Option<MuHash3072>, CoinStatsError> {
+        let Some(bytes) = bucket.get(&Integer::from(height))? else {
+            return Ok(None);
+        };
+
+        let bytes: &[u8; MUHASH_SIZE] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| encode::Error::ParseFailed("a MuHash state must have 768 bytes"))?;
+
+        Ok(Some(MuHash3072::from_bytes(bytes)))
+    }
+}
+
+impl BlockConsumer for CoinStatsIndex {
+    fn wants_spent_utxos(&self) -> bool {
+        true
+    }
+
+    fn on_block(
+        &self,
+        block: &Block,
+        height: u32,
+        spent_utxos: Option<&HashMap<OutPoint, UtxoData>>,
+    ) {
+        if let Err(e) = self.connect_block(block, height, spent_utxos) {
+            error!(
+                "Could not add block {} to our coinstatsindex: {e}",
+                block.block_hash()
+            );
+            self.state.lock().unwrap().stalled = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bitcoin::absolute::LockTime;
+    use bitcoin::block::Header;
+    use bitcoin::block::Version;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction;
+    use bitcoin::Amount;
+    use bitcoin::Block;
+    use bitcoin::BlockHash;
+    use bitcoin::CompactTarget;
+    use bitcoin::OutPoint;
+    use bitcoin::ScriptBuf;
+    use bitcoin::Sequence;
+    use bitcoin::Transaction;
+    use bitcoin::TxIn;
+    use bitcoin::TxMerkleNode;
+    use bitcoin::TxOut;
+    use bitcoin::Witness;
+    use floresta_chain::UtxoData;
+
+    use super::CoinStatsIndex;
+    use crate::muhash::MuHash3072;
+
+    fn index(name: &str) -> CoinStatsIndex {
+        let path = format!("./tmp-db/{name}.coinstats");
+        let _ = std::fs::remove_dir_all(&path);
+
+        CoinStatsIndex::new(path, 150).unwrap()
+    }
+
+    fn tx(inputs: Vec<OutPoint>, outputs: Vec<TxOut>, lock_time: u32) -> Transaction {
+        let input = match inputs.is_empty() {
+            // A coinbase
+            true => vec![TxIn::default()],
+            false => inputs
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+        };
+
+        Transaction {
+            version: transaction::Version::ONE,
+            lock_time: LockTime::from_consensus(lock_time),
+            input,
+            output: outputs,
+        }
+    }
+
+    fn output(sats: u64) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+        }
+    }
+
+    fn block(nonce: u32, txdata: Vec<Transaction>) -> Block {
+        Block {
+            header: Header {
+                version: Version::ONE,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce,
+            },
+            txdata,
+        }
+    }
+
+    fn utxo(output: TxOut, creation_height: u32, is_coinbase: bool) -> UtxoData {
+        UtxoData {
+            txout: output,
+            is_coinbase,
+            creation_height,
+            creation_time: 0,
+        }
+    }
+
+    const SUBSIDY: u64 = 50 * 100_000_000;
+
+    #[test]
+    fn test_running_totals() {
+        let index = index("running_totals");
+        index.connect_block(&block(0, vec![]), 0, None).unwrap();
+
+        // Block 1 pays the whole subsidy to a single output
+        let coinbase_1 = tx(vec![], vec![output(SUBSIDY)], 1);
+        index
+            .connect_block(&block(1, vec![coinbase_1.clone()]), 1, None)
+            .unwrap();
+
+        // Block 2 spends it into two outputs, and one of those is spent again in the same block
+        let cb_outpoint = OutPoint::new(coinbase_1.compute_txid(), 0);
+        let spend = tx(
+            vec![cb_outpoint],
+            vec![output(1_000), output(SUBSIDY - 2_000)],
+            0,
+        );
+        let chained = tx(
+            vec![OutPoint::new(spend.compute_txid(), 0)],
+            vec![output(500)],
+            0,
+        );
+        // The miner leaves 1 sat behind
+        let coinbase_2 = tx(vec![], vec![output(SUBSIDY + 1_500 - 1)], 2);
+        let block_2 = block(2, vec![coinbase_2.clone(), spend.clone(), chained.clone()]);
+
+        let mut spent = HashMap::new();
+        spent.insert(cb_outpoint, utxo(output(SUBSIDY), 1, true));
+        spent.insert(
+            OutPoint::new(spend.compute_txid(), 0),
+            utxo(output(1_000), 2, false),
+        );
+        spent.insert(
+            OutPoint::new(spend.compute_txid(), 1),
+            utxo(output(SUBSIDY - 2_000), 2, false),
+        );
+        spent.insert(
+            OutPoint::new(chained.compute_txid(), 0),
+            utxo(output(500), 2, false),
+        );
+        index.connect_block(&block_2, 2, Some(&spent)).unwrap();
+
+        let (stats, muhash) = index.get_stats(2, true).unwrap().unwrap();
+        assert_eq!(stats.txouts, 3);
+        assert_eq!(stats.bogosize, 3 * 51);
+        assert_eq!(
+            stats.total_amount,
+            Amount::from_sat((SUBSIDY - 2_000) + 500 + (SUBSIDY + 1_500 - 1))
+        );
+        assert_eq!(
+            stats.total_unspendable_amount,
+            Amount::from_sat(SUBSIDY + 1)
+        );
+
+        // The MuHash commits to exactly the UTXOs left
+        let mut expected = MuHash3072::new();
+        for (outpoint, output, coinbase) in [
+            (
+                OutPoint::new(spend.compute_txid(), 1),
+                output(SUBSIDY - 2_000),
+                false,
+            ),
+            (OutPoint::new(chained.compute_txid(), 0), output(500), false),
+            (
+                OutPoint::new(coinbase_2.compute_txid(), 0),
+                output(SUBSIDY + 1_500 - 1),
+                true,
+            ),
+        ] {
+            expected.insert(&CoinStatsIndex::serialize_coin(
+                outpoint, &output, 2, coinbase,
+            ));
+        }
+        assert_eq!(muhash, Some(expected.finalize()));
+    }
+
+    #[test]
+    fn test_reorg_and_gaps() {
+        let index = index("reorg_and_gaps");
+        index.connect_block(&block(0, vec![]), 0, None).unwrap();
+        for height in 1..4 {
+            let coinbase = tx(vec![], vec![output(SUBSIDY)], height);
+            index
+                .connect_block(&block(height, vec![coinbase]), height, None)
+                .unwrap();
+        }
+        let (before, _) = index.get_stats(1, false).unwrap().unwrap();
+
+        // A fork from block 1, with an OP_RETURN coinbase
+        let op_return = TxOut {
+            value: Amount::from_sat(SUBSIDY),
+            script_pubkey: ScriptBuf::new_op_return([]),
+        };
+        let fork = block(100, vec![tx(vec![], vec![op_return], 100)]);
+        index.connect_block(&fork, 2, None).unwrap();
+
+        let (stats, _) = index.get_stats(2, false).unwrap().unwrap();
+        assert_eq!(index.best_height(), Some(2));
+        assert_eq!(index.get_stats(3, false).unwrap(), None);
+        assert_eq!(stats.block_hash, fork.block_hash());
+        assert_eq!(stats.txouts, before.txouts);
+        assert_eq!(
+            stats.total_unspendable_amount,
+            before.total_unspendable_amount + Amount::from_sat(SUBSIDY)
+        );
+
+        // A gap stalls the index
+        let coinbase = tx(vec![], vec![output(SUBSIDY)], 10);
+        index
+            .connect_block(&block(10, vec![coinbase]), 10, None)
+            .unwrap();
+        assert_eq!(index.best_height(), Some(2));
+        assert!(!index.info(2).synced);
+    }
+}
@@ -1,4 +1,15 @@
+use std::str::FromStr;
+
+use bitcoin::block::Header as BlockHeader;
+use bitcoin::consensus::encode::deserialize_hex;
+use bitcoin::BlockHash;
+use bitcoin::ScriptBuf;
+use bitcoin::Work;
+use floresta_chain::AssumeUtreexoValue;
+use floresta_chain::ChainParamsConfig;
+use rustreexo::accumulator::node_hash::BitcoinNodeHash;
 use serde::Deserialize;
+use serde::Serialize;
 
 use crate::error::FlorestadError;
 
@@ -9,9 +20,95 @@ pub struct Wallet {
     pub addresses: Option<Vec<String>>,
 }
 
+/// Overrides for the parameters of a custom signet or regtest, see [ChainParamsConfig]
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct Chain {
+    /// The hex-encoded signet block challenge
+    pub signet_challenge: Option<String>,
+    /// The hex-encoded genesis block header
+    pub genesis: Option<String>,
+    pub bip34_height: Option<u32>,
+    pub bip65_height: Option<u32>,
+    pub bip66_height: Option<u32>,
+    pub csv_height: Option<u32>,
+    pub segwit_height: Option<u32>,
+    pub assume_valid: Option<String>,
+    pub assume_utreexo: Option<AssumeUtreexo>,
+    pub dns_seeds: Option<Vec<String>>,
+    /// The hex-encoded minimum chain work, with or without `0x`
+    pub minimum_chain_work: Option<String>,
+    pub checkpoints: Option<Vec<Checkpoint>>,
+}
+
+/// A block that must be in the best chain, as written in a `[[chain.checkpoints]]` section
+#[derive(Clone, Debug, Deserialize)]
+pub struct Checkpoint {
+    pub height: u32,
+    pub hash: String,
+}
+
+/// An [AssumeUtreexoValue], as written in the `[chain.assume_utreexo]` section
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AssumeUtreexo {
+    pub block_hash: String,
+    pub height: u32,
+    pub roots: Vec<String>,
+    pub leaves: u64,
+}
+
+impl From<&AssumeUtreexoValue> for AssumeUtreexo {
+    fn from(value: &AssumeUtreexoValue) -> Self {
+        AssumeUtreexo {
+            block_hash: value.block_hash.to_string(),
+            height: value.height,
+            roots: value.roots.iter().map(|root| root.to_string()).collect(),
+            leaves: value.leaves,
+        }
+    }
+}
+
+impl AssumeUtreexo {
+    /// Parses the hashes in this section
+    pub fn to_value(&self) -> Result<AssumeUtreexoValue, FlorestadError> {
+        let invalid = |field: &str, err: &dyn std::fmt::Display| {
+            FlorestadError::InvalidChainConfig(format!("assume_utreexo.{field}: {err}"))
+        };
+
+        let roots = self
+            .roots
+            .iter()
+            .map(|root| BitcoinNodeHash::from_str(root))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid("roots", &e))?;
+
+        Ok(AssumeUtreexoValue {
+            block_hash: BlockHash::from_str(&self.block_hash)
+                .map_err(|e| invalid("block_hash", &e))?,
+            height: self.height,
+            roots,
+            leaves: self.leaves,
+        })
+    }
+
+    /// Formats this value as a `[chain.assume_utreexo]` section for our config file
+    #[cfg_attr(not(feature = "json-rpc"), allow(dead_code))]
+    pub fn to_toml(&self) -> String {
+        let mut roots = String::new();
+        for root in self.roots.iter() {
+            roots.push_str(&format!("    \"{root}\",\n"));
+        }
+
+        format!(
+            "[chain.assume_utreexo]\nblock_hash = \"{}\"\nheight = {}\nroots = [\n{roots}]\nleaves = {}\n",
+            self.block_hash, self.height, self.leaves
+        )
+    }
+}
+
 #[derive(Default, Debug, Deserialize)]
 pub struct ConfigFile {
     pub wallet: Wallet,
+    pub chain: Option<Chain>,
 }
 
 impl ConfigFile {
@@ -20,3 +117,131 @@ impl ConfigFile {
         Ok(toml::from_str(&file)?)
     }
 }
+
+impl Chain {
+    /// Parses this section into a [ChainParamsConfig]
+    pub fn to_params_config(&self) -> Result<ChainParamsConfig, FlorestadError> {
+        let invalid = |field: &str, err: &dyn std::fmt::Display| {
+            FlorestadError::InvalidChainConfig(format!("{field}: {err}"))
+        };
+
+        let signet_challenge = self
+            .signet_challenge
+            .as_ref()
+            .map(|hex| ScriptBuf::from_hex(hex).map_err(|e| invalid("signet_challenge", &e)))
+            .transpose()?;
+
+        let genesis = self
+            .genesis
+            .as_ref()
+            .map(|hex| deserialize_hex::<BlockHeader>(hex).map_err(|e| invalid("genesis", &e)))
+            .transpose()?;
+
+        let assume_valid = self
+            .assume_valid
+            .as_ref()
+            .map(|hash| BlockHash::from_str(hash).map_err(|e| invalid("assume_valid", &e)))
+            .transpose()?;
+
+        let assume_utreexo = self
+            .assume_utreexo
+            .as_ref()
+            .map(AssumeUtreexo::to_value)
+            .transpose()?;
+
+        let minimum_chain_work = self
+            .minimum_chain_work
+            .as_ref()
+            .map(|hex| {
+                let hex = hex.strip_prefix("0x").unwrap_or(hex);
+                Work::from_unprefixed_hex(hex).map_err(|e| invalid("minimum_chain_work", &e))
+            })
+            .transpose()?;
+
+        let checkpoints = self
+            .checkpoints
+            .as_ref()
+            .map(|checkpoints| {
+                checkpoints
+                    .iter()
+                    .map(|checkpoint| {
+                        let hash = BlockHash::from_str(&checkpoint.hash)
+                            .map_err(|e| invalid("checkpoints", &e))?;
+                        Ok((checkpoint.height, hash))
+                    })
+                    .collect::<Result<Vec<_>, FlorestadError>>()
+            })
+            .transpose()?;
+
+        Ok(ChainParamsConfig {
+            signet_challenge,
+            genesis,
+            bip34_height: self.bip34_height,
+            bip65_height: self.bip65_height,
+            bip66_height: self.bip66_height,
+            csv_activation_height: self.csv_height,
+            segwit_activation_height: self.segwit_height,
+            assume_valid,
+            assume_utreexo,
+            dns_seeds: self.dns_seeds.clone(),
+            minimum_chain_work,
+            checkpoints,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use floresta_chain::ChainParams;
+
+    use super::AssumeUtreexo;
+    use super::ConfigFile;
+
+    #[test]
+    fn test_assume_utreexo_toml() {
+        let value = ChainParams::get_assume_utreexo(bitcoin::Network::Bitcoin);
+        let section = AssumeUtreexo::from(&value);
+
+        // What we print can be pasted into a config file
+        let toml = format!("[wallet]\n{}", section.to_toml());
+        let config_file: ConfigFile = toml::from_str(&toml).unwrap();
+        let parsed = config_file.chain.unwrap().assume_utreexo.unwrap();
+        let parsed = parsed.to_value().unwrap();
+
+        assert_eq!(parsed.block_hash, value.block_hash);
+        assert_eq!(parsed.height, value.height);
+        assert_eq!(parsed.roots, value.roots);
+        assert_eq!(parsed.leaves, value.leaves);
+
+        // An empty accumulator, like the genesis one
+        let genesis = ChainParams::get_assume_utreexo(bitcoin::Network::Regtest);
+        let toml = format!("[wallet]\n{}", AssumeUtreexo::from(&genesis).to_toml());
+        let config_file: ConfigFile = toml::from_str(&toml).unwrap();
+        let parsed = config_file.chain.unwrap().assume_utreexo.unwrap();
+        assert!(parsed.to_value().unwrap().roots.is_empty());
+    }
+
+    #[test]
+    fn test_minimum_work_and_checkpoints() {
+        let toml = r#"
+            [wallet]
+
+            [chain]
+            minimum_chain_work = "0x100"
+
+            [[chain.checkpoints]]
+            height = 10
+            hash = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"
+        "#;
+
+        let config_file: ConfigFile = toml::from_str(toml).unwrap();
+        let params = config_file.chain.unwrap().to_params_config().unwrap();
+        let genesis = ChainParams::from(bitcoin::Network::Regtest).genesis;
+
+        assert_eq!(
+            params.minimum_chain_work,
+            Some(bitcoin::Work::from_unprefixed_hex("100").unwrap())
+        );
+        assert_eq!(params.checkpoints, Some(vec![(10, genesis.block_hash())]));
+    }
+}
@@ -3,15 +3,17 @@ use std::net::AddrParseError;
 use bitcoin::consensus::encode;
 use floresta_chain::BlockValidationErrors;
 use floresta_chain::BlockchainError;
-#[cfg(feature = "flat-chainstore")]
-use floresta_chain::FlatChainstoreError;
+use floresta_chain::ChainParamsError;
 #[cfg(feature = "compact-filters")]
 use floresta_compact_filters::IterableFilterStoreError;
 use floresta_watch_only::kv_database::KvDatabaseError;
 use floresta_watch_only::WatchOnlyError;
 use tokio_rustls::rustls::pki_types;
 
+use crate::chainstore::AnyChainStoreError;
+use crate::coinstats::CoinStatsError;
 use crate::slip132;
+use crate::tx_index::TxIndexError;
 #[derive(Debug)]
 pub enum FlorestadError {
     /// Encoding/decoding error.
@@ -126,17 +128,26 @@ pub enum FlorestadError {
     /// Resolve a hostname error.
     CouldNotResolveHostname(std::io::Error),
 
-    #[cfg(feature = "flat-chainstore")]
-    /// Create a flat chain store error.
-    CouldNotCreateFlatChainStore(FlatChainstoreError),
+    /// Create a chain store error.
+    CouldNotCreateChainStore(AnyChainStoreError),
 
-    #[cfg(feature = "flat-chainstore")]
-    /// Load a flat chain store error.
-    CouldNotLoadFlatChainStore(BlockchainError),
+    /// Load a chain store error.
+    CouldNotLoadChainStore(BlockchainError),
 
-    #[cfg(feature = "kv-chainstore")]
-    /// Load a key-value chain store error.
-    CouldNotLoadKvChainStore(BlockchainError),
+    /// Migrate a chain store between backends error.
+    CouldNotMigrateChainStore(BlockchainError),
+
+    /// Invalid value in the `[chain]` section of the config file, or in `signetchallenge`.
+    InvalidChainConfig(String),
+
+    /// Build the custom chain parameters error.
+    InvalidChainParams(ChainParamsError),
+
+    /// Open or reset the transaction index error.
+    CouldNotLoadTxIndex(TxIndexError),
+
+    /// Open the UTXO set statistics index error.
+    CouldNotLoadCoinStatsIndex(CoinStatsError),
 }
 
 impl std::fmt::Display for FlorestadError {
@@ -237,19 +248,26 @@ impl std::fmt::Display for FlorestadError {
                 write!(f, "Could not resolve hostname: {host}")
             }
 
-            #[cfg(feature = "flat-chainstore")]
-            FlorestadError::CouldNotCreateFlatChainStore(err) => {
-                write!(f, "Failure while creating chainstore: {err:?}")
+            FlorestadError::CouldNotCreateChainStore(err) => {
+                write!(f, "Failure while creating chainstore: {err}")
             }
-
-            #[cfg(feature = "flat-chainstore")]
-            FlorestadError::CouldNotLoadFlatChainStore(err) => {
-                write!(f, "Failure while loading flat chainstore: {err:?}")
+            FlorestadError::CouldNotLoadChainStore(err) => {
+                write!(f, "Failure while loading chainstore: {err:?}")
             }
-
-            #[cfg(feature = "kv-chainstore")]
-            FlorestadError::CouldNotLoadKvChainStore(err) => {
-                write!(f, "Failure while loading key-value chainstore: {err:?}")
+            FlorestadError::CouldNotMigrateChainStore(err) => {
+                write!(f, "Failure while migrating chainstore: {err:?}")
+            }
+            FlorestadError::InvalidChainConfig(err) => {
+                write!(f, "Invalid chain configuration: {err}")
+            }
+            FlorestadError::InvalidChainParams(err) => {
+                write!(f, "Could not build the chain parameters: {err}")
+            }
+            FlorestadError::CouldNotLoadTxIndex(err) => {
+                write!(f, "Could not load the transaction index: {err}")
+            }
+            FlorestadError::CouldNotLoadCoinStatsIndex(err) => {
+                write!(f, "Could not load the coinstatsindex: {err}")
             }
         }
     }
@@ -1,6 +1,7 @@
 use std::fs;
 use std::io;
 use std::io::IsTerminal;
+use std::net::IpAddr;
 #[cfg(feature = "metrics")]
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
@@ -12,18 +13,18 @@ use std::sync::Mutex;
 use std::sync::OnceLock;
 
 pub use bitcoin::Network;
-#[cfg(feature = "zmq-server")]
+use bitcoin::ScriptBuf;
+use floresta_chain::migrate_chain_store;
 use floresta_chain::pruned_utreexo::BlockchainInterface;
 pub use floresta_chain::AssumeUtreexoValue;
 use floresta_chain::AssumeValidArg;
 use floresta_chain::BlockchainError;
+use floresta_chain::ChainParams;
+pub use floresta_chain::ChainParamsConfig;
 use floresta_chain::ChainState;
-#[cfg(feature = "flat-chainstore")]
-use floresta_chain::FlatChainStore as ChainStore;
-#[cfg(feature = "flat-chainstore")]
-use floresta_chain::FlatChainStoreConfig;
-#[cfg(all(feature = "kv-chainstore", not(doc)))]
-use floresta_chain::KvChainStore as ChainStore;
+pub use floresta_chain::CompactionPolicy;
+#[cfg(feature = "compact-filters")]
+use floresta_compact_filters::flat_filter_headers::FlatFilterHeadersStore;
 #[cfg(feature = "compact-filters")]
 use floresta_compact_filters::flat_filters_store::FlatFiltersStore;
 #[cfg(feature = "compact-filters")]
@@ -66,24 +67,31 @@ use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::Layer;
 
+use crate::chainstore;
+use crate::chainstore::AnyChainStore;
+use crate::chainstore::ChainStoreBackend;
+use crate::coinstats::CoinStatsIndex;
 use crate::config_file::ConfigFile;
 use crate::error::FlorestadError;
 #[cfg(feature = "json-rpc")]
 use crate::json_rpc;
+use crate::tx_index;
+use crate::tx_index::IndexedTxSource;
+use crate::tx_index::TxIndex;
 use crate::wallet_input::InitialWalletSetup;
 #[cfg(feature = "zmq-server")]
 use crate::zmq::ZMQServer;
 
-// flat-chainstore and kv-chainstore are mutually exclusive
-#[cfg(all(feature = "flat-chainstore", feature = "kv-chainstore", not(doc)))]
+// at least one chainstore backend must be enabled
+#[cfg(not(any(
+    feature = "flat-chainstore",
+    feature = "kv-chainstore",
+    feature = "sqlite-chainstore"
+)))]
 compile_error!(
-    "You cannot use both flat-chainstore and kv-chainstore at the same time. Please choose one."
+    "You must enable at least one of the flat-chainstore, kv-chainstore or sqlite-chainstore features."
 );
 
-// at least one of flat-chainstore or kv-chainstore must be enabled
-#[cfg(not(any(feature = "flat-chainstore", feature = "kv-chainstore")))]
-compile_error!("You must enable either the flat-chainstore or kv-chainstore feature.");
-
 #[derive(Clone)]
 /// General configuration for the floresta daemon.
 ///
@@ -145,6 +153,18 @@ pub struct Config {
     /// The network we are running in, it may be one of: bitcoin, signet, regtest or testnet.
     pub network: Network,
 
+    /// The hex-encoded block challenge of a custom signet, like Bitcoin Core's `-signetchallenge`
+    ///
+    /// Only valid with the signet network. This changes the network magic, so we'll only talk
+    /// to nodes in that signet.
+    pub signet_challenge: Option<String>,
+
+    /// Custom parameters for signet or regtest, like the genesis block or activation heights
+    ///
+    /// If not set, we use the `[chain]` section of the config file, if any.
+    /// [Config::signet_challenge] has precedence over the challenge set here.
+    pub chain_params: Option<ChainParamsConfig>,
+
     /// Whether we should build and store compact block filters
     ///
     /// Those filters are used for rescanning our wallet for historical transactions. If you don't
@@ -233,6 +253,88 @@ pub struct Config {
     /// and won't affect the node's operation. You may notice that this will take a lot of CPU
     /// and bandwidth to run.
     pub backfill: bool,
+
+    /// Into how many height ranges we split the backfill, each one validated in parallel
+    ///
+    /// If not set, we use the default from [UtreexoNodeConfig].
+    pub backfill_ranges: Option<usize>,
+
+    /// Which database we should use to store our chain data
+    ///
+    /// Only the backends enabled at compile time are available. Defaults to the flat chainstore,
+    /// if enabled.
+    pub chainstore_backend: ChainStoreBackend,
+
+    /// Copy the chain data from this backend into [Config::chainstore_backend] before starting
+    ///
+    /// This lets you switch backends without syncing again. The migration is skipped if the
+    /// destination already has chain data, so it's safe to leave this set after the first run.
+    /// The source database is left untouched.
+    pub migrate_chainstore_from: Option<ChainStoreBackend>,
+
+    /// How many threads we should use to verify the scripts of each block
+    ///
+    /// If not set, we use one thread per available CPU core.
+    pub script_workers: Option<usize>,
+
+    /// Whether we should run as a Utreexo bridge
+    ///
+    /// A bridge keeps the whole accumulator forest, so it can generate proofs for every block
+    /// and serve them to our peers. This requires validating the chain from genesis, so
+    /// assumeutreexo and PoW fraud proofs are disabled.
+    pub bridge: bool,
+
+    /// How many of our most recent blocks we should keep on disk
+    ///
+    /// These blocks, and their Utreexo proofs, are served to our peers and used to answer
+    /// `getblock`. If not set, we don't keep any block.
+    pub block_cache_depth: Option<u32>,
+
+    /// How much space, in MiB, our cached blocks may take
+    ///
+    /// If not set, we only limit the cache by [Config::block_cache_depth].
+    pub block_cache_size: Option<u64>,
+
+    /// Whether we should keep an index with the block of every transaction
+    ///
+    /// This lets `getrawtransaction` return any confirmed transaction, fetching its block from
+    /// our peers, or from our block cache.
+    pub txindex: bool,
+
+    /// If set, only index the transactions in our last `txindex_prune` blocks
+    pub txindex_prune: Option<u32>,
+
+    /// Whether we should throw away our transaction index and build it again
+    pub reindex_txindex: bool,
+
+    /// Whether we should keep statistics about the UTXO set at every height
+    ///
+    /// This lets `gettxoutsetinfo` return the UTXO count, total amount and MuHash, like Core's
+    /// coinstatsindex. It can only be built as we validate the chain from genesis.
+    pub coinstatsindex: bool,
+
+    /// If set, we periodically remove old fork headers and accumulator roots from our chainstore
+    ///
+    /// Only the roots allowed by the policy are kept, so reorgs deeper than the roots we keep
+    /// need our undo data.
+    pub chainstore_compaction: Option<CompactionPolicy>,
+
+    /// The address we should listen on for inbound P2P connections, in the format
+    /// `<address>[:<port>]`
+    ///
+    /// If not set, we only make outbound connections.
+    pub p2p_listen_address: Option<String>,
+
+    /// How many inbound P2P connections we accept
+    ///
+    /// If not set, we use the default from [UtreexoNodeConfig].
+    pub max_inbound: Option<u32>,
+
+    /// Addresses of peers that can always connect to us, like our own light clients
+    ///
+    /// These peers are never banned or evicted, and may connect even if all inbound slots
+    /// are taken.
+    pub whitelist: Vec<IpAddr>,
 }
 
 impl Default for Config {
@@ -246,6 +348,8 @@ impl Default for Config {
             config_file: None,
             proxy: None,
             network: Network::Bitcoin,
+            signet_challenge: None,
+            chain_params: None,
             cfilters: false,
             filters_start_height: None,
             #[cfg(feature = "zmq-server")]
@@ -267,6 +371,21 @@ impl Default for Config {
             tls_cert_path: None,
             allow_v1_fallback: false,
             backfill: false,
+            chainstore_backend: ChainStoreBackend::default(),
+            migrate_chainstore_from: None,
+            backfill_ranges: None,
+            script_workers: None,
+            bridge: false,
+            block_cache_depth: None,
+            block_cache_size: None,
+            txindex: false,
+            txindex_prune: None,
+            reindex_txindex: false,
+            coinstatsindex: false,
+            chainstore_compaction: None,
+            p2p_listen_address: None,
+            max_inbound: None,
+            whitelist: Vec::new(),
         }
     }
 }
@@ -392,6 +511,19 @@ impl Florestad {
             *self.logger_guard.lock().unwrap() = guard?;
         }
 
+        // The config file inside our data directory or inside the specified directory
+        let config_file = match self.config.config_file {
+            Some(ref path) => Self::get_config_file(path),
+            None => {
+                let default_path = format!("{data_dir}/config.toml");
+                Self::get_config_file(&default_path)
+            }
+        };
+
+        let chain_params = self.chain_params(&config_file)?;
+        let subsidy_halving_interval = chain_params.subsidy_halving_interval as u32;
+        let genesis = chain_params.genesis.clone();
+
         info!("Loading watch-only wallet");
         let mut wallet = Self::load_wallet(&data_dir)?;
         wallet
@@ -399,7 +531,7 @@ impl Florestad {
             .map_err(FlorestadError::CouldNotInitializeWallet)?;
 
         // Try to add more wallets to watch if needed
-        self.setup_wallet(&data_dir, &mut wallet)?;
+        self.setup_wallet(config_file, &mut wallet)?;
 
         info!("Loading blockchain database");
         let assume_valid = self
@@ -409,17 +541,33 @@ impl Florestad {
             .map(|value| value.parse().map_err(FlorestadError::InvalidAssumeValid))
             .transpose()?;
 
+        if let Some(from) = self.config.migrate_chainstore_from {
+            Self::migrate_chain_store(from, self.config.chainstore_backend, &data_dir)?;
+        }
+
         let blockchain_state = Arc::new(Self::load_chain_state(
-            data_dir.clone(),
-            self.config.network,
+            self.config.chainstore_backend,
+            &data_dir,
+            chain_params.clone(),
             assume_valid,
         )?);
 
+        let script_workers = self.config.script_workers.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        });
+        info!("Verifying scripts with {script_workers} threads");
+        blockchain_state.set_script_workers(script_workers);
+
         #[cfg(feature = "compact-filters")]
         let cfilters = if self.config.cfilters {
             // Block Filters
             let filter_store = FlatFiltersStore::new((data_dir.clone() + "/cfilters").into());
-            let cfilters = Arc::new(NetworkFilters::new(filter_store));
+            let headers_store =
+                FlatFilterHeadersStore::try_from(&(data_dir.clone() + "/cfilters-headers").into())
+                    .map_err(|e| FlorestadError::CouldNotLoadCompactFiltersStore(e.into()))?;
+            let cfilters = Arc::new(NetworkFilters::new(filter_store, headers_store));
 
             let height = cfilters
                 .get_height()
@@ -434,10 +582,10 @@ impl Florestad {
         #[cfg(not(feature = "compact-filters"))]
         let cfilters = None;
 
-        // For now, we only have compatible bridges on signet
+        // For now, we only have compatible bridges on (the default) signet
         let pow_fraud_proofs = match self.config.network {
             Network::Bitcoin => false,
-            Network::Signet => true,
+            Network::Signet => chain_params.signet_challenge.is_none(),
             Network::Testnet => false,
             Network::Testnet4 => false,
             Network::Regtest => false,
@@ -445,12 +593,23 @@ impl Florestad {
 
         // If this network already allows pow fraud proofs, we should use it instead of assumeutreexo
         let assume_utreexo = match (pow_fraud_proofs, self.config.assume_utreexo) {
-            (false, true) => Some(floresta_chain::ChainParams::get_assume_utreexo(
-                self.config.network,
-            )),
+            (false, true) => Some(chain_params.assume_utreexo.clone()),
             _ => None,
         };
 
+        // A bridge builds its forest from every block, so it can't skip any of them
+        let (pow_fraud_proofs, assume_utreexo, assumeutreexo_value) = match self.config.bridge {
+            true => {
+                info!("Running as a bridge, we'll validate the whole chain from genesis");
+                (false, None, None)
+            }
+            false => (
+                pow_fraud_proofs,
+                assume_utreexo,
+                self.config.assumeutreexo_value.clone(),
+            ),
+        };
+
         let proxy = self
             .config
             .proxy
@@ -458,6 +617,15 @@ impl Florestad {
             .map(|addr| Self::resolve_hostname(addr, 9050))
             .transpose()?;
 
+        let listen = self
+            .config
+            .p2p_listen_address
+            .as_ref()
+            .map(|addr| {
+                Self::resolve_hostname(addr, Self::get_default_p2p_port(self.config.network))
+            })
+            .transpose()?;
+
         let config = UtreexoNodeConfig {
             disable_dns_seeds: self.config.disable_dns_seeds,
             network: self.config.network,
@@ -469,21 +637,40 @@ impl Florestad {
             compact_filters: self.config.cfilters,
             max_outbound: 10,
             max_inflight: 20,
-            assume_utreexo: self.config.assumeutreexo_value.clone().or(assume_utreexo),
+            assume_utreexo: assumeutreexo_value.or(assume_utreexo),
             backfill: self.config.backfill,
+            backfill_ranges: self
+                .config
+                .backfill_ranges
+                .unwrap_or(UtreexoNodeConfig::default().backfill_ranges),
             filter_start_height: self.config.filters_start_height,
             user_agent: self.config.user_agent.clone(),
             allow_v1_fallback: self.config.allow_v1_fallback,
+            chain_params: Some(chain_params),
+            bridge: self.config.bridge,
+            block_cache_depth: self.config.block_cache_depth.unwrap_or(0),
+            block_cache_size: self.config.block_cache_size.map(|size| size * 1024 * 1024),
+            listen,
+            max_inbound: self
+                .config
+                .max_inbound
+                .unwrap_or(UtreexoNodeConfig::default().max_inbound),
+            whitelist: self.config.whitelist.clone(),
         };
 
         let acc = Pollard::new();
         let kill_signal = self.stop_signal.clone();
 
+        // Our mempool feeds the chain's fee estimator, so it learns how long transactions take
+        // to confirm
+        let mut mempool = Mempool::new(acc, 300_000_000);
+        mempool.set_fee_estimator(blockchain_state.fee_estimator());
+
         // Chain Provider (p2p)
         let chain_provider = UtreexoNode::<_, RunningNode>::new(
             config,
             blockchain_state.clone(),
-            Arc::new(tokio::sync::Mutex::new(Mempool::new(acc, 300_000_000))),
+            Arc::new(tokio::sync::Mutex::new(mempool)),
             cfilters.clone(),
             kill_signal.clone(),
             AddressMan::default(),
@@ -507,6 +694,70 @@ impl Florestad {
             };
         }
 
+        // Transaction index
+        let tx_index = match self.config.txindex {
+            true => {
+                info!("Loading transaction index");
+                let tx_index = TxIndex::new(
+                    format!("{data_dir}/txindex"),
+                    self.config.txindex_prune,
+                    genesis.clone(),
+                )
+                .map_err(FlorestadError::CouldNotLoadTxIndex)?;
+
+                if self.config.reindex_txindex {
+                    info!("Rebuilding transaction index");
+                    tx_index
+                        .reset()
+                        .map_err(FlorestadError::CouldNotLoadTxIndex)?;
+                }
+
+                let tx_index = Arc::new(tx_index);
+                blockchain_state.subscribe(tx_index.clone());
+                blockchain_state.set_tx_source(Arc::new(IndexedTxSource::new(
+                    tx_index.clone(),
+                    &blockchain_state,
+                    chain_provider.get_handle(),
+                )));
+                task::spawn(tx_index::sync_task(
+                    tx_index.clone(),
+                    blockchain_state.clone(),
+                    chain_provider.get_handle(),
+                    kill_signal.clone(),
+                ));
+
+                Some(tx_index)
+            }
+            false => None,
+        };
+
+        // UTXO set statistics
+        let coinstats = match self.config.coinstatsindex {
+            true => {
+                info!("Loading coinstatsindex");
+                let coinstats =
+                    CoinStatsIndex::new(format!("{data_dir}/coinstats"), subsidy_halving_interval)
+                        .map_err(FlorestadError::CouldNotLoadCoinStatsIndex)?;
+
+                coinstats.check_consistency(&blockchain_state, &genesis);
+                let coinstats = Arc::new(coinstats);
+                blockchain_state.subscribe(coinstats.clone());
+
+                Some(coinstats)
+            }
+            false => None,
+        };
+
+        // Chainstore compaction
+        if let Some(policy) = self.config.chainstore_compaction {
+            info!("Compacting our chainstore periodically with {policy:?}");
+            task::spawn(chainstore::compaction_task(
+                blockchain_state.clone(),
+                policy,
+                kill_signal.clone(),
+            ));
+        }
+
         info!("Starting server");
         let wallet = Arc::new(wallet);
 
@@ -526,6 +777,8 @@ impl Florestad {
                     .map(|x| Self::resolve_hostname(x, 8332))
                     .transpose()?,
                 format!("{data_dir}/debug.log"),
+                tx_index,
+                coinstats,
             ));
 
             if self.json_rpc.set(server).is_err() {
@@ -533,6 +786,10 @@ impl Florestad {
             }
         }
 
+        // Only our json-rpc reads from the indexes
+        #[cfg(not(feature = "json-rpc"))]
+        let _ = (tx_index, coinstats);
+
         // Electrum Server configuration.
 
         // Instantiate the Electrum Server.
@@ -832,51 +1089,94 @@ impl Florestad {
         None
     }
 
-    #[cfg(feature = "flat-chainstore")]
-    fn load_chain_store(data_dir: String) -> Result<ChainStore, FlorestadError> {
-        let config = FlatChainStoreConfig::new(data_dir + "/chaindata");
-        ChainStore::new(config).map_err(FlorestadError::CouldNotCreateFlatChainStore)
+    fn load_chain_store(
+        backend: ChainStoreBackend,
+        data_dir: &str,
+    ) -> Result<AnyChainStore, FlorestadError> {
+        AnyChainStore::open(backend, data_dir).map_err(FlorestadError::CouldNotCreateChainStore)
     }
 
-    #[cfg(all(feature = "kv-chainstore", not(doc)))]
-    fn load_chain_state<'a>(
-        data_dir: String,
-        network: Network,
-        assume_valid: Option<bitcoin::BlockHash>,
-    ) -> Result<ChainState<ChainStore<'a>>, FlorestadError> {
-        let assume_valid = assume_valid
-            .map(AssumeValidArg::UserInput)
-            .unwrap_or(AssumeValidArg::Hardcoded);
+    /// Copies the chain data from the `from` backend into the `to` backend, unless `to` already
+    /// has some chain data.
+    fn migrate_chain_store(
+        from: ChainStoreBackend,
+        to: ChainStoreBackend,
+        data_dir: &str,
+    ) -> Result<(), FlorestadError> {
+        if from == to {
+            warn!("Asked to migrate the chainstore from {from} into itself, ignoring");
+            return Ok(());
+        }
 
-        let db = ChainStore::new(data_dir.clone())?;
+        let mut source = Self::load_chain_store(from, data_dir)?;
+        let mut dest = Self::load_chain_store(to, data_dir)?;
 
-        ChainState::load_chain_state(db, network, assume_valid).or_else(|err| match err {
-            BlockchainError::ChainNotInitialized => {
-                let db = ChainStore::new(data_dir)?;
-                Ok(ChainState::new(db, network, assume_valid))
+        info!("Migrating chain data from the {from} chainstore into the {to} chainstore");
+        match migrate_chain_store(&mut source, &mut dest) {
+            Ok(headers) => {
+                info!("Migrated {headers} headers into the {to} chainstore");
+                Ok(())
             }
-            anyerr => Err(FlorestadError::CouldNotLoadKvChainStore(anyerr)),
-        })
+            Err(BlockchainError::ChainStoreNotEmpty) => {
+                info!("The {to} chainstore already has chain data, skipping migration");
+                Ok(())
+            }
+            Err(e) => Err(FlorestadError::CouldNotMigrateChainStore(e)),
+        }
+    }
+
+    /// Builds the parameters for our chain, applying the custom ones from [Config::chain_params],
+    /// the config file and [Config::signet_challenge]
+    fn chain_params(&self, config_file: &ConfigFile) -> Result<ChainParams, FlorestadError> {
+        let mut custom = match (&self.config.chain_params, &config_file.chain) {
+            (Some(params), _) => Some(params.clone()),
+            (None, Some(section)) => Some(section.to_params_config()?),
+            (None, None) => None,
+        };
+
+        if let Some(ref challenge) = self.config.signet_challenge {
+            let challenge = ScriptBuf::from_hex(challenge)
+                .map_err(|e| FlorestadError::InvalidChainConfig(format!("signetchallenge: {e}")))?;
+
+            custom.get_or_insert_with(Default::default).signet_challenge = Some(challenge);
+        }
+
+        let Some(custom) = custom else {
+            return Ok(self.config.network.into());
+        };
+
+        let params = ChainParams::from_config(self.config.network, custom)
+            .map_err(FlorestadError::InvalidChainParams)?;
+
+        if let Some(ref challenge) = params.signet_challenge {
+            info!(
+                "Using a custom signet with challenge {} and magic {}",
+                challenge.to_hex_string(),
+                params.magic
+            );
+        }
+
+        Ok(params)
     }
 
-    #[cfg(feature = "flat-chainstore")]
     fn load_chain_state(
-        data_dir: String,
-        network: Network,
+        backend: ChainStoreBackend,
+        data_dir: &str,
+        chain_params: ChainParams,
         assume_valid: Option<bitcoin::BlockHash>,
-    ) -> Result<ChainState<ChainStore>, FlorestadError> {
+    ) -> Result<ChainState<AnyChainStore>, FlorestadError> {
         let assume_v = assume_valid
             .map(AssumeValidArg::UserInput)
             .unwrap_or(AssumeValidArg::Hardcoded);
 
-        let db = Self::load_chain_store(data_dir.clone())?;
+        let db = Self::load_chain_store(backend, data_dir)?;
 
-        ChainState::<ChainStore>::load_chain_state(db, network, assume_v).or_else(|e| match e {
+        ChainState::load_chain_state(db, chain_params.clone(), assume_v).or_else(|e| match e {
             BlockchainError::ChainNotInitialized => {
-                let db = Self::load_chain_store(data_dir)?;
-                Ok(ChainState::new(db, network, assume_v))
+                let db = Self::load_chain_store(backend, data_dir)?;
+                Ok(ChainState::new(db, chain_params, assume_v))
             }
-            anyerr => Err(FlorestadError::CouldNotLoadFlatChainStore(anyerr)),
+            anyerr => Err(FlorestadError::CouldNotLoadChainStore(anyerr)),
         })
     }
 
@@ -888,17 +1188,9 @@ impl Florestad {
 
     fn setup_wallet(
         &self,
-        data_dir: &str,
+        config_file: ConfigFile,
         wallet: &mut AddressCache<KvDatabase>,
     ) -> Result<(), FlorestadError> {
-        // The config file inside our data directory or inside the specified directory
-        let config_file = match self.config.config_file {
-            Some(ref path) => Self::get_config_file(path),
-            None => {
-                let default_path = format!("{data_dir}/config.toml");
-                Self::get_config_file(&default_path)
-            }
-        };
         let setup = self.prepare_wallet_setup(config_file)?;
 
         // Add the configured descriptors and addresses to the wallet
@@ -947,6 +1239,16 @@ impl Florestad {
     /// Testnet4 => 40001 (40003 TLS)
     /// Testnet3 => 30001 (30002 TLS)
     /// Regtest  => 20001 (20002 TLS)
+    fn get_default_p2p_port(network: Network) -> u16 {
+        match network {
+            Network::Bitcoin => 8333,
+            Network::Signet => 38333,
+            Network::Testnet4 => 48333,
+            Network::Testnet => 18333,
+            Network::Regtest => 18444,
+        }
+    }
+
     fn get_default_electrum_port(network: Network, enable_electrum_tls: bool) -> u16 {
         let mut electrum_port = match network {
             Network::Bitcoin => 50001,
@@ -1101,4 +1403,65 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_chain_params() {
+        let config_file: ConfigFile = toml::from_str(
+            r#"
+            [wallet]
+            [chain]
+            signet_challenge = "51"
+            csv_height = 432
+            dns_seeds = ["seed.example.com"]
+            "#,
+        )
+        .unwrap();
+
+        let config = Config {
+            network: Network::Signet,
+            ..Default::default()
+        };
+        let florestad = Florestad::from_config(config);
+
+        let params = florestad.chain_params(&config_file).unwrap();
+        assert_ne!(params.magic, Network::Signet.magic());
+        assert_eq!(params.csv_activation_height, 432);
+        assert_eq!(params.dns_seeds[0].seed, "seed.example.com");
+
+        // The challenge from our config has precedence over the config file
+        let challenge = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+        let config = Config {
+            network: Network::Signet,
+            signet_challenge: Some(challenge.to_string()),
+            ..Default::default()
+        };
+        let florestad = Florestad::from_config(config);
+
+        let params = florestad.chain_params(&config_file).unwrap();
+        assert_eq!(params.magic, Network::Signet.magic());
+
+        // Without any custom parameters, we use the network defaults
+        let config = Config {
+            network: Network::Regtest,
+            ..Default::default()
+        };
+        let florestad = Florestad::from_config(config);
+
+        let params = florestad.chain_params(&ConfigFile::default()).unwrap();
+        assert!(params.signet_challenge.is_none());
+        assert_eq!(params.magic, Network::Regtest.magic());
+
+        // A challenge is only valid for signet
+        let config = Config {
+            network: Network::Regtest,
+            signet_challenge: Some("51".to_string()),
+            ..Default::default()
+        };
+        let florestad = Florestad::from_config(config);
+
+        assert!(matches!(
+            florestad.chain_params(&ConfigFile::default()),
+            Err(FlorestadError::InvalidChainParams(_))
+        ));
+    }
 }
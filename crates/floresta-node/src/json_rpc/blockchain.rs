@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use bitcoin::block::Header;
 use bitcoin::consensus::encode::serialize_hex;
 use bitcoin::consensus::Encodable;
@@ -10,19 +12,35 @@ use bitcoin::OutPoint;
 use bitcoin::Script;
 use bitcoin::ScriptBuf;
 use bitcoin::Txid;
+use floresta_chain::AssumeUtreexoValue;
+use floresta_chain::DeploymentTrigger;
+use floresta_chain::ThresholdState;
+use floresta_chain::NO_TIMEOUT;
+use floresta_watch_only::CachedTransaction;
 use miniscript::descriptor::checksum;
+use rustreexo::accumulator::stump::Stump;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::json;
 use serde_json::Value;
 use tracing::debug;
 
+use super::res::Bip9Info;
+use super::res::Bip9Statistics;
 use super::res::GetBlockResVerbose;
 use super::res::GetBlockchainInfoRes;
+use super::res::GetDeploymentInfoRes;
+use super::res::GetIndexInfoRes;
 use super::res::GetTxOutProof;
+use super::res::GetTxOutSetInfoRes;
 use super::res::JsonRpcError;
+use super::res::SoftForkInfo;
+use super::res::VerifyAssumeUtreexoRes;
 use super::server::RpcChain;
 use super::server::RpcImpl;
+use crate::coinstats::REORG_DEPTH;
+use crate::config_file::AssumeUtreexo;
+use crate::json_rpc::request::arg_parser::HashOrHeight;
 use crate::json_rpc::res::RescanConfidence;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -308,6 +326,7 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
             chain: self.network.to_string(),
             difficulty: latest_header.difficulty(self.chain.get_params()) as u64,
             progress: validated_blocks as f32 / height as f32,
+            softforks: self.get_softforks(hash, height)?,
         })
     }
 
@@ -338,6 +357,104 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
     // getchaintips
     // getchaintxstats
     // getdeploymentinfo
+    pub(super) fn get_deployment_info(
+        &self,
+        hash: Option<BlockHash>,
+    ) -> Result<GetDeploymentInfoRes, JsonRpcError> {
+        let hash = match hash {
+            Some(hash) => hash,
+            None => {
+                self.chain
+                    .get_best_block()
+                    .map_err(|_| JsonRpcError::Chain)?
+                    .1
+            }
+        };
+
+        let height = self
+            .chain
+            .get_block_height(&hash)
+            .map_err(|_| JsonRpcError::Chain)?
+            .ok_or(JsonRpcError::BlockNotFound)?;
+
+        Ok(GetDeploymentInfoRes {
+            hash: hash.to_string(),
+            height,
+            deployments: self.get_softforks(hash, height)?,
+        })
+    }
+
+    /// Returns the status of every soft fork at the block `hash`, with height `height`
+    fn get_softforks(
+        &self,
+        hash: BlockHash,
+        height: u32,
+    ) -> Result<BTreeMap<String, SoftForkInfo>, JsonRpcError> {
+        let info = self
+            .chain
+            .get_deployment_info(hash)
+            .map_err(|_| JsonRpcError::BlockNotFound)?;
+
+        let buried = info.buried.into_iter().map(|deployment| {
+            let softfork = SoftForkInfo {
+                deployment_type: "buried".into(),
+                height: Some(deployment.height),
+                active: height + 1 >= deployment.height,
+                bip9: None,
+            };
+
+            (deployment.name.to_string(), softfork)
+        });
+
+        let versionbits = info.deployments.into_iter().map(|status| {
+            let deployment = status.deployment;
+            let (start_time, timeout, start_height, timeout_height) = match deployment.trigger {
+                DeploymentTrigger::MedianTime {
+                    start_time,
+                    timeout,
+                } => (Some(start_time), Some(timeout), None, None),
+                DeploymentTrigger::Height {
+                    start_height,
+                    timeout_height,
+                    ..
+                } => (None, None, Some(start_height), Some(timeout_height)),
+                // Same values used by Bitcoin Core
+                DeploymentTrigger::AlwaysActive => (Some(-1), Some(NO_TIMEOUT), None, None),
+                DeploymentTrigger::NeverActive => (Some(-2), Some(NO_TIMEOUT), None, None),
+            };
+
+            let statistics = status.statistics.map(|statistics| Bip9Statistics {
+                period: statistics.period,
+                threshold: statistics.threshold,
+                elapsed: statistics.elapsed,
+                count: statistics.count,
+                possible: statistics.possible,
+            });
+
+            let softfork = SoftForkInfo {
+                deployment_type: "bip9".into(),
+                height: (status.state == ThresholdState::Active).then_some(status.since),
+                active: status.state_next == ThresholdState::Active,
+                bip9: Some(Bip9Info {
+                    bit: deployment.bit,
+                    start_time,
+                    timeout,
+                    start_height,
+                    timeout_height,
+                    min_activation_height: deployment.min_activation_height,
+                    status: status.state.to_string(),
+                    since: status.since,
+                    status_next: status.state_next.to_string(),
+                    statistics,
+                }),
+            };
+
+            (deployment.name.to_string(), softfork)
+        });
+
+        Ok(buried.chain(versionbits).collect())
+    }
+
     // getdifficulty
     // getmempoolancestors
     // getmempooldescendants
@@ -545,6 +662,213 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
         Ok(res)
     }
 
+    /// Looks a transaction up in our txindex, fetching its block from the network
+    pub(super) async fn get_indexed_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<CachedTransaction, JsonRpcError> {
+        let tx_index = self.tx_index.as_ref().ok_or(JsonRpcError::TxNotFound)?;
+        let (tx, height, position) = tx_index
+            .get_tx(txid, &self.chain, &self.node)
+            .await
+            .map_err(|e| JsonRpcError::Node(e.to_string()))?
+            .ok_or(JsonRpcError::TxNotFound)?;
+
+        Ok(CachedTransaction {
+            tx,
+            height,
+            merkle_block: None,
+            hash: *txid,
+            position,
+        })
+    }
+
+    /// Returns our accumulator after the block at `height`, as an assumeutreexo value
+    async fn assume_utreexo_value(&self, height: u32) -> Result<AssumeUtreexoValue, JsonRpcError> {
+        let validation_index = self
+            .chain
+            .get_validation_index()
+            .map_err(|_| JsonRpcError::Chain)?;
+
+        // We can't vouch for an accumulator we haven't validated
+        if height > validation_index {
+            return Err(JsonRpcError::AccumulatorNotAvailable(height));
+        }
+
+        // If we started from an assumeutreexo value, every accumulator we have is built on top
+        // of that assumption until backfill validates the blocks behind it
+        let backfill = self.get_backfill_progress().await?;
+
+        if backfill.assumed && !backfill.done {
+            return Err(JsonRpcError::AccumulatorNotValidated(height));
+        }
+
+        let acc = match self.chain.get_roots_for_block(height) {
+            Ok(Some(acc)) => acc,
+            // The genesis outputs can't be spent, so they aren't in the accumulator
+            Ok(None) if height == 0 => Stump::new(),
+            Ok(None) => return Err(JsonRpcError::AccumulatorNotAvailable(height)),
+            Err(_) => return Err(JsonRpcError::Chain),
+        };
+
+        let block_hash = self
+            .chain
+            .get_block_hash(height)
+            .map_err(|_| JsonRpcError::BlockNotFound)?;
+
+        Ok(AssumeUtreexoValue {
+            block_hash,
+            height,
+            roots: acc.roots,
+            leaves: acc.leaves,
+        })
+    }
+
+    /// dumpassumeutreexo: returns our accumulator after the block at `height` (our last
+    /// validated block by default), in a shape we can use as an assumeutreexo value.
+    ///
+    /// `format` is `json` (the default), `toml` for a `[chain.assume_utreexo]` section of our
+    /// config file, or `rust` for the code in `ChainParams::get_assume_utreexo`.
+    pub(super) async fn dump_assume_utreexo(
+        &self,
+        height: Option<u32>,
+        format: Option<String>,
+    ) -> Result<Value, JsonRpcError> {
+        let height = match height {
+            Some(height) => height,
+            None => self
+                .chain
+                .get_validation_index()
+                .map_err(|_| JsonRpcError::Chain)?,
+        };
+
+        let value = self.assume_utreexo_value(height).await?;
+        let section = AssumeUtreexo::from(&value);
+
+        match format.as_deref().unwrap_or("json") {
+            "json" => Ok(serde_json::to_value(section).unwrap()),
+            "toml" => Ok(Value::String(section.to_toml())),
+            "rust" => Ok(Value::String(value.to_rust())),
+            format => Err(JsonRpcError::InvalidDumpFormat(format.to_string())),
+        }
+    }
+
+    /// verifyassumeutreexo: checks an assumeutreexo value against our own accumulator at
+    /// the same height
+    pub(super) async fn verify_assume_utreexo(
+        &self,
+        value: AssumeUtreexo,
+    ) -> Result<VerifyAssumeUtreexoRes, JsonRpcError> {
+        let value = value
+            .to_value()
+            .map_err(|e| JsonRpcError::InvalidParameterType(e.to_string()))?;
+        let ours = self.assume_utreexo_value(value.height).await?;
+
+        let error = if value.block_hash != ours.block_hash {
+            Some("block_hash isn't the block at this height in our chain")
+        } else if value.leaves != ours.leaves {
+            Some("leaves doesn't match our accumulator")
+        } else if value.roots != ours.roots {
+            Some("roots don't match our accumulator")
+        } else {
+            None
+        };
+
+        Ok(VerifyAssumeUtreexoRes {
+            valid: error.is_none(),
+            error: error.map(String::from),
+            our_value: AssumeUtreexo::from(&ours),
+        })
+    }
+
+    /// getindexinfo: returns the status of our indexes, or only of `index_name`
+    pub(super) fn get_index_info(
+        &self,
+        index_name: Option<String>,
+    ) -> Result<GetIndexInfoRes, JsonRpcError> {
+        let tip = self
+            .chain
+            .get_validation_index()
+            .map_err(|_| JsonRpcError::Chain)?;
+
+        let wants = |name: &str| !matches!(index_name.as_deref(), Some(index) if index != name);
+
+        let mut res = GetIndexInfoRes::default();
+        if wants("txindex") {
+            res.txindex = self.tx_index.as_ref().map(|index| index.info(tip));
+        }
+        if wants("coinstatsindex") {
+            res.coinstatsindex = self.coinstats.as_ref().map(|index| index.info(tip));
+        }
+
+        Ok(res)
+    }
+
+    /// gettxoutsetinfo: returns statistics about the UTXO set after a block, our coinstatsindex's
+    /// best block by default.
+    ///
+    /// We can't compute Core's `hash_serialized_3`, so `hash_type` is either `muhash` or
+    /// `none` (the default).
+    pub(super) fn get_txout_set_info(
+        &self,
+        hash_type: Option<String>,
+        hash_or_height: Option<HashOrHeight>,
+    ) -> Result<GetTxOutSetInfoRes, JsonRpcError> {
+        let index = self
+            .coinstats
+            .as_ref()
+            .ok_or(JsonRpcError::NoCoinStatsIndex)?;
+
+        let with_muhash = match hash_type.as_deref().unwrap_or("none") {
+            "muhash" => true,
+            "none" => false,
+            hash_type => return Err(JsonRpcError::InvalidHashType(hash_type.to_string())),
+        };
+
+        let (height, block_hash) = match hash_or_height {
+            Some(HashOrHeight::Height(height)) => (height, None),
+            Some(HashOrHeight::Hash(hash)) => {
+                let height = self
+                    .chain
+                    .get_block_height(&hash)
+                    .map_err(|_| JsonRpcError::Chain)?
+                    .ok_or(JsonRpcError::BlockNotFound)?;
+
+                (height, Some(hash))
+            }
+            None => (
+                index.best_height().ok_or(JsonRpcError::BlockNotFound)?,
+                None,
+            ),
+        };
+
+        let (stats, muhash) = index
+            .get_stats(height, with_muhash)
+            .map_err(|e| JsonRpcError::CoinStats(e.to_string()))?
+            .ok_or(JsonRpcError::BlockNotFound)?;
+
+        // A stale block has the same height as one in our chain
+        if block_hash.is_some_and(|hash| hash != stats.block_hash) {
+            return Err(JsonRpcError::BlockNotFound);
+        }
+
+        if with_muhash && muhash.is_none() {
+            return Err(JsonRpcError::CoinStats(format!(
+                "we only keep the muhash for the last {REORG_DEPTH} blocks"
+            )));
+        }
+
+        Ok(GetTxOutSetInfoRes {
+            height,
+            bestblock: stats.block_hash.to_string(),
+            txouts: stats.txouts,
+            bogosize: stats.bogosize,
+            muhash: muhash.map(|muhash| muhash.to_string()),
+            total_amount: stats.total_amount.to_btc(),
+            total_unspendable_amount: stats.total_unspendable_amount.to_btc(),
+        })
+    }
+
     /// Computes the necessary information for the RPC `gettxoutproof [txids] blockhash (optional)`
     ///
     /// This function has two paths, when blockhash is inserted and when isn't.
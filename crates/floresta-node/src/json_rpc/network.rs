@@ -1,5 +1,7 @@
 //! This module holds all RPC server side methods for interacting with our node's network stack.
 
+use floresta_wire::backfill::BackfillProgress;
+
 use super::res::JsonRpcError;
 use super::server::RpcChain;
 use super::server::RpcImpl;
@@ -11,4 +13,11 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
             .await
             .map_err(|e| JsonRpcError::Node(e.to_string()))
     }
+
+    pub(crate) async fn get_backfill_progress(&self) -> Result<BackfillProgress, JsonRpcError> {
+        self.node
+            .get_backfill_progress()
+            .await
+            .map_err(|e| JsonRpcError::Node(e.to_string()))
+    }
 }
@@ -25,10 +25,18 @@ pub struct RpcRequest {
 pub mod arg_parser {
     use std::str::FromStr;
 
+    use bitcoin::BlockHash;
+    use serde::de::DeserializeOwned;
     use serde_json::Value;
 
     use crate::json_rpc::res::JsonRpcError;
 
+    /// A block, given by its hash or height, like in `gettxoutsetinfo`
+    pub enum HashOrHeight {
+        Hash(BlockHash),
+        Height(u32),
+    }
+
     /// Extracts a u64 parameter from the request parameters at the specified index.
     ///
     /// This function checks if the parameter exists, is of type u64 and can be converted to `T`.
@@ -131,6 +139,21 @@ pub mod arg_parser {
             .collect()
     }
 
+    /// Extracts a JSON object from the request parameters at the specified index, and
+    /// deserializes it into `T`.
+    pub fn get_object<T: DeserializeOwned>(
+        params: &[Value],
+        index: usize,
+        opt_name: &str,
+    ) -> Result<T, JsonRpcError> {
+        let v = params
+            .get(index)
+            .ok_or_else(|| JsonRpcError::MissingParameter(opt_name.to_string()))?;
+
+        serde_json::from_value(v.clone())
+            .map_err(|e| JsonRpcError::InvalidParameterType(format!("{opt_name}: {e}")))
+    }
+
     /// Extracts an optional field from the request parameters at the specified index.
     ///
     /// This function checks if the parameter exists and is of the expected type. If the parameter
@@ -149,4 +172,24 @@ pub mod arg_parser {
         let value = extractor_fn(params, index, opt_name)?;
         Ok(Some(value))
     }
+
+    /// Extracts a block hash or a height from the request parameters at the specified index.
+    ///
+    /// Strings are parsed as hashes, and numbers as heights.
+    pub fn get_hash_or_height(
+        params: &[Value],
+        index: usize,
+        opt_name: &str,
+    ) -> Result<HashOrHeight, JsonRpcError> {
+        match params.get(index) {
+            Some(Value::Number(_)) => {
+                get_numeric(params, index, opt_name).map(HashOrHeight::Height)
+            }
+            Some(Value::String(_)) => get_hash(params, index, opt_name).map(HashOrHeight::Hash),
+            Some(_) => Err(JsonRpcError::InvalidParameterType(format!(
+                "{opt_name} must be a block hash or height"
+            ))),
+            None => Err(JsonRpcError::MissingParameter(opt_name.to_string())),
+        }
+    }
 }
@@ -1,9 +1,13 @@
+use std::collections::BTreeMap;
 use std::fmt::Display;
 
 use axum::response::IntoResponse;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::config_file::AssumeUtreexo;
+use crate::tx_index::IndexInfo;
+
 #[derive(Deserialize, Serialize)]
 pub struct GetBlockchainInfoRes {
     pub best_block: String,
@@ -18,6 +22,149 @@ pub struct GetBlockchainInfoRes {
     pub chain: String,
     pub progress: f32,
     pub difficulty: u64,
+    pub softforks: BTreeMap<String, SoftForkInfo>,
+}
+
+/// The status of a soft fork, see `getdeploymentinfo`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SoftForkInfo {
+    /// `buried` for soft forks with a hardcoded height, `bip9` for versionbits deployments
+    #[serde(rename = "type")]
+    pub deployment_type: String,
+
+    /// The height this soft fork activated at, if it's active
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+
+    /// Whether the next block must follow the rules of this soft fork
+    pub active: bool,
+
+    /// The versionbits status, only for `bip9` soft forks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bip9: Option<Bip9Info>,
+}
+
+/// The versionbits status of a soft fork
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Bip9Info {
+    /// The version bit miners set to signal for this soft fork
+    pub bit: u8,
+
+    /// When signaling starts, as a median time past. -1 means always active, -2 never active
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<i64>,
+
+    /// When the deployment fails if it isn't locked in, as a median time past
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<i64>,
+
+    /// When signaling starts, for deployments with a height trigger (BIP8)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_height: Option<u32>,
+
+    /// When the deployment times out, for deployments with a height trigger (BIP8)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_height: Option<u32>,
+
+    /// A locked in deployment only activates at or after this height
+    pub min_activation_height: u32,
+
+    /// The state for the block we asked for
+    pub status: String,
+
+    /// The height of the first block with this status
+    pub since: u32,
+
+    /// The state for the next block
+    pub status_next: String,
+
+    /// Signaling statistics for the current period, only while signaling is possible
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statistics: Option<Bip9Statistics>,
+}
+
+/// How many blocks signaled for a soft fork in the current period
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Bip9Statistics {
+    /// The length of a signaling period
+    pub period: u32,
+
+    /// How many blocks must signal in a period to lock in
+    pub threshold: u32,
+
+    /// How many blocks of this period we've seen
+    pub elapsed: u32,
+
+    /// How many of those blocks signal
+    pub count: u32,
+
+    /// Whether the threshold can still be reached in this period
+    pub possible: bool,
+}
+
+/// The result of `getdeploymentinfo`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetDeploymentInfoRes {
+    /// The block we computed the deployment states for
+    pub hash: String,
+
+    /// The height of that block
+    pub height: u32,
+
+    /// The status of each soft fork, keyed by name
+    pub deployments: BTreeMap<String, SoftForkInfo>,
+}
+
+/// The result of `getindexinfo`, with the status of each enabled index
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct GetIndexInfoRes {
+    /// Our transaction index, if enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txindex: Option<IndexInfo>,
+
+    /// Our UTXO set statistics index, if enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coinstatsindex: Option<IndexInfo>,
+}
+
+/// The result of `verifyassumeutreexo`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VerifyAssumeUtreexoRes {
+    /// Whether the value matches our own accumulator at that height
+    pub valid: bool,
+
+    /// If it doesn't, the first field that differs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Our own value at that height
+    pub our_value: AssumeUtreexo,
+}
+
+/// The result of `gettxoutsetinfo`, from our coinstatsindex
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetTxOutSetInfoRes {
+    /// The height of the block these statistics are for
+    pub height: u32,
+
+    /// The hash of that block
+    pub bestblock: String,
+
+    /// How many UTXOs there are
+    pub txouts: u64,
+
+    /// A rough estimate of the UTXO set size
+    pub bogosize: u64,
+
+    /// The MuHash of the UTXO set, only with `hash_type=muhash`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub muhash: Option<String>,
+
+    /// The sum of all UTXOs, in BTC
+    pub total_amount: f64,
+
+    /// Coins that can never be spent, in BTC
+    pub total_unspendable_amount: f64,
 }
 
 /// A confidence enum to auxiliate rescan timestamp values.
@@ -96,7 +243,9 @@ pub struct ScriptPubKeyJson {
     pub req_sigs: u32,
     #[serde(rename = "type")]
     pub type_: String,
-    pub address: String,
+    /// Only set if this script has an address, e.g. bare multisig and `OP_RETURN` don't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -310,6 +459,24 @@ pub enum JsonRpcError {
 
     /// Raised if when the rescanblockchain command, with the timestamp flag activated, contains some timestamp thats less than the genesis one and not zero which is the default value for this arg.
     InvalidTimestamp,
+
+    /// This error is returned when the node does not have the coinstatsindex enabled, which is required for `gettxoutsetinfo`
+    NoCoinStatsIndex,
+
+    /// This error is returned when `gettxoutsetinfo` gets a hash type we can't compute
+    InvalidHashType(String),
+
+    /// This error is returned when there is an error with the coinstatsindex, e.g., if it can't read its data, or doesn't have what was asked
+    CoinStats(String),
+
+    /// This error is returned when we don't have the accumulator for a block, either because we haven't validated it yet, or we've deleted it
+    AccumulatorNotAvailable(u32),
+
+    /// This error is returned when we've assumed the accumulator for a block, and didn't backfill it yet
+    AccumulatorNotValidated(u32),
+
+    /// This error is returned when `dumpassumeutreexo` gets a format it doesn't know
+    InvalidDumpFormat(String),
 }
 
 impl Display for JsonRpcError {
@@ -339,6 +506,12 @@ impl Display for JsonRpcError {
             JsonRpcError::Wallet(e) => write!(f, "Wallet error: {e}"),
             JsonRpcError::Filters(e) => write!(f, "Error with filters: {e}"),
             JsonRpcError::InvalidAddnodeCommand => write!(f, "Invalid addnode command"),
+            JsonRpcError::NoCoinStatsIndex => write!(f, "You don't have the coinstatsindex enabled, please start florestad with --coinstatsindex to run this RPC"),
+            JsonRpcError::InvalidHashType(hash_type) => write!(f, "Invalid hash type {hash_type}, should be muhash or none"),
+            JsonRpcError::CoinStats(e) => write!(f, "Error with the coinstatsindex: {e}"),
+            JsonRpcError::AccumulatorNotAvailable(height) => write!(f, "We don't have the accumulator for block {height}"),
+            JsonRpcError::AccumulatorNotValidated(height) => write!(f, "The accumulator for block {height} was assumed, wait until we finish backfilling it"),
+            JsonRpcError::InvalidDumpFormat(format) => write!(f, "Invalid format {format}, should be json, toml or rust"),
         }
     }
 }
@@ -46,14 +46,18 @@ use super::res::ScriptPubKeyJson;
 use super::res::ScriptSigJson;
 use super::res::TxInJson;
 use super::res::TxOutJson;
+use crate::coinstats::CoinStatsIndex;
 use crate::json_rpc::request::arg_parser::get_bool;
 use crate::json_rpc::request::arg_parser::get_hash;
+use crate::json_rpc::request::arg_parser::get_hash_or_height;
 use crate::json_rpc::request::arg_parser::get_hashes_array;
 use crate::json_rpc::request::arg_parser::get_numeric;
+use crate::json_rpc::request::arg_parser::get_object;
 use crate::json_rpc::request::arg_parser::get_optional_field;
 use crate::json_rpc::request::arg_parser::get_string;
 use crate::json_rpc::request::RpcRequest;
 use crate::json_rpc::res::RescanConfidence;
+use crate::tx_index::TxIndex;
 
 pub(super) struct InflightRpc {
     pub method: String,
@@ -78,6 +82,8 @@ pub struct RpcImpl<Blockchain: RpcChain> {
     pub(super) inflight: Arc<RwLock<HashMap<Value, InflightRpc>>>,
     pub(super) log_path: String,
     pub(super) start_time: Instant,
+    pub(super) tx_index: Option<Arc<TxIndex>>,
+    pub(super) coinstats: Option<Arc<CoinStatsIndex>>,
 }
 
 type Result<T> = std::result::Result<T, JsonRpcError>;
@@ -114,19 +120,18 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
         Ok(json!(null))
     }
 
-    fn get_transaction(&self, tx_id: Txid, verbosity: Option<bool>) -> Result<Value> {
+    async fn get_transaction(&self, tx_id: Txid, verbosity: Option<bool>) -> Result<Value> {
+        // Our wallet only has its own transactions, for anything else we need the txindex
+        let tx = match self.wallet.get_transaction(&tx_id) {
+            Some(tx) => tx,
+            None => self.get_indexed_transaction(&tx_id).await?,
+        };
+
         if verbosity == Some(true) {
-            let tx = self
-                .wallet
-                .get_transaction(&tx_id)
-                .ok_or(JsonRpcError::TxNotFound);
-            return tx.map(|tx| serde_json::to_value(self.make_raw_transaction(tx)).unwrap());
+            return Ok(serde_json::to_value(self.make_raw_transaction(tx)).unwrap());
         }
 
-        self.wallet
-            .get_transaction(&tx_id)
-            .and_then(|tx| serde_json::to_value(self.make_raw_transaction(tx)).ok())
-            .ok_or(JsonRpcError::TxNotFound)
+        serde_json::to_value(self.make_raw_transaction(tx)).map_err(|_| JsonRpcError::TxNotFound)
     }
 
     fn load_descriptor(&self, descriptor: String) -> Result<bool> {
@@ -317,6 +322,13 @@ async fn handle_json_rpc_request(
                 .map(|h| serde_json::to_value(h).unwrap())
         }
 
+        "getdeploymentinfo" => {
+            let hash = get_optional_field(&params, 0, "blockhash", get_hash)?;
+            state
+                .get_deployment_info(hash)
+                .map(|v| serde_json::to_value(v).unwrap())
+        }
+
         "gettxout" => {
             let txid = get_hash(&params, 0, "txid")?;
             let vout = get_numeric(&params, 1, "vout")?;
@@ -348,6 +360,41 @@ async fn handle_json_rpc_request(
 
             state
                 .get_transaction(txid, verbosity)
+                .await
+                .map(|v| serde_json::to_value(v).unwrap())
+        }
+
+        "gettxoutsetinfo" => {
+            let hash_type = get_optional_field(&params, 0, "hash_type", get_string)?;
+            let hash_or_height =
+                get_optional_field(&params, 1, "hash_or_height", get_hash_or_height)?;
+
+            state
+                .get_txout_set_info(hash_type, hash_or_height)
+                .map(|v| serde_json::to_value(v).unwrap())
+        }
+
+        "getindexinfo" => {
+            let index_name = get_optional_field(&params, 0, "index_name", get_string)?;
+
+            state
+                .get_index_info(index_name)
+                .map(|v| serde_json::to_value(v).unwrap())
+        }
+
+        "dumpassumeutreexo" => {
+            let height = get_optional_field(&params, 0, "height", get_numeric)?;
+            let format = get_optional_field(&params, 1, "format", get_string)?;
+
+            state.dump_assume_utreexo(height, format).await
+        }
+
+        "verifyassumeutreexo" => {
+            let value = get_object(&params, 0, "value")?;
+
+            state
+                .verify_assume_utreexo(value)
+                .await
                 .map(|v| serde_json::to_value(v).unwrap())
         }
 
@@ -406,6 +453,11 @@ async fn handle_json_rpc_request(
                 .map(|v| serde_json::to_value(v).unwrap())
         }
 
+        "getbackfillprogress" => state
+            .get_backfill_progress()
+            .await
+            .map(|v| serde_json::to_value(v).unwrap()),
+
         "ping" => {
             state.ping().await?;
 
@@ -473,6 +525,9 @@ fn get_http_error_code(err: &JsonRpcError) -> u16 {
         | JsonRpcError::InvalidVerbosityLevel
         | JsonRpcError::Decode(_)
         | JsonRpcError::NoBlockFilters
+        | JsonRpcError::NoCoinStatsIndex
+        | JsonRpcError::InvalidHashType(_)
+        | JsonRpcError::InvalidDumpFormat(_)
         | JsonRpcError::InvalidMemInfoMode
         | JsonRpcError::InvalidAddnodeCommand
         | JsonRpcError::InvalidTimestamp
@@ -483,15 +538,18 @@ fn get_http_error_code(err: &JsonRpcError) -> u16 {
         | JsonRpcError::Wallet(_) => 400,
 
         // idunnolol
-        JsonRpcError::MethodNotFound | JsonRpcError::BlockNotFound | JsonRpcError::TxNotFound => {
-            404
-        }
+        JsonRpcError::MethodNotFound
+        | JsonRpcError::BlockNotFound
+        | JsonRpcError::TxNotFound
+        | JsonRpcError::AccumulatorNotAvailable(_) => 404,
 
         // we messed up, sowwy
         JsonRpcError::InInitialBlockDownload
+        | JsonRpcError::AccumulatorNotValidated(_)
         | JsonRpcError::Node(_)
         | JsonRpcError::Chain
-        | JsonRpcError::Filters(_) => 503,
+        | JsonRpcError::Filters(_)
+        | JsonRpcError::CoinStats(_) => 503,
     }
 }
 
@@ -512,19 +570,25 @@ fn get_json_rpc_error_code(err: &JsonRpcError) -> i32 {
         | JsonRpcError::InvalidVerbosityLevel
         | JsonRpcError::TxNotFound
         | JsonRpcError::BlockNotFound
+        | JsonRpcError::AccumulatorNotAvailable(_)
         | JsonRpcError::InvalidTimestamp
         | JsonRpcError::InvalidMemInfoMode
         | JsonRpcError::InvalidAddnodeCommand
+        | JsonRpcError::InvalidHashType(_)
+        | JsonRpcError::InvalidDumpFormat(_)
         | JsonRpcError::InvalidRescanVal
         | JsonRpcError::NoAddressesToRescan
         | JsonRpcError::Wallet(_) => -32600,
 
         // server error
         JsonRpcError::InInitialBlockDownload
+        | JsonRpcError::AccumulatorNotValidated(_)
         | JsonRpcError::Node(_)
         | JsonRpcError::Chain
         | JsonRpcError::NoBlockFilters
-        | JsonRpcError::Filters(_) => -32603,
+        | JsonRpcError::NoCoinStatsIndex
+        | JsonRpcError::Filters(_)
+        | JsonRpcError::CoinStats(_) => -32603,
     }
 }
 
@@ -664,7 +728,7 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
                 req_sigs: 0, // This field is deprecated
                 address: Address::from_script(&output.script_pubkey, self.network)
                     .map(|a| a.to_string())
-                    .unwrap(),
+                    .ok(),
                 type_: Self::get_script_type(output.script_pubkey)
                     .unwrap_or("nonstandard")
                     .to_string(),
@@ -746,6 +810,8 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
         block_filter_storage: Option<Arc<NetworkFilters<FlatFiltersStore>>>,
         address: Option<SocketAddr>,
         log_path: String,
+        tx_index: Option<Arc<TxIndex>>,
+        coinstats: Option<Arc<CoinStatsIndex>>,
     ) {
         let address = address.unwrap_or_else(|| {
             format!("127.0.0.1:{}", Self::get_port(&network))
@@ -786,6 +852,8 @@ impl<Blockchain: RpcChain> RpcImpl<Blockchain> {
                 inflight: Arc::new(RwLock::new(HashMap::new())),
                 log_path,
                 start_time: Instant::now(),
+                tx_index,
+                coinstats,
             }));
 
         axum::serve(listener, router)
@@ -2,16 +2,23 @@
 
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+mod chainstore;
+mod coinstats;
 mod config_file;
 mod error;
 mod florestad;
 #[cfg(feature = "json-rpc")]
 mod json_rpc;
+mod muhash;
 mod slip132;
+mod tx_index;
 mod wallet_input;
 #[cfg(feature = "zmq-server")]
 mod zmq;
 
+pub use chainstore::ChainStoreBackend;
 pub use florestad::AssumeUtreexoValue;
+pub use florestad::ChainParamsConfig;
+pub use florestad::CompactionPolicy;
 pub use florestad::Config;
 pub use florestad::Florestad;
@@ -0,0 +1,286 @@
+//! MuHash3072, the rolling set hash that Bitcoin Core uses to commit to the UTXO set.
+//!
+//! Each element is hashed with SHA256, expanded into a 3072-bit number with ChaCha20, and
+//! multiplied into (or divided out of) an accumulator modulo the prime `2^3072 - 1103717`. Since
+//! multiplication commutes, the order in which elements are added or removed doesn't matter, and
+//! removing an element undoes adding it. This is the same construction as Core's `MuHash3072`,
+//! so our commitments can be compared with `gettxoutsetinfo muhash`.
+//!
+//! We keep a numerator and a denominator, so removing an element is just a multiplication. The
+//! (slow) modular inversion only happens in [MuHash3072::finalize].
+
+use bitcoin::hashes::hash_newtype;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use chacha20_poly1305::chacha20::ChaCha20;
+use chacha20_poly1305::Key;
+use chacha20_poly1305::Nonce;
+
+/// How many 64-bit limbs we need for a 3072-bit number
+const LIMBS: usize = 48;
+
+/// The modulus is `2^3072 - MAX_PRIME_DIFF`
+const MAX_PRIME_DIFF: u64 = 1103717;
+
+/// The size of a serialized [Num3072]
+pub const NUM3072_SIZE: usize = LIMBS * 8;
+
+/// The size of a serialized [MuHash3072]
+pub const MUHASH_SIZE: usize = 2 * NUM3072_SIZE;
+
+hash_newtype! {
+    /// The commitment to a set, as returned by [MuHash3072::finalize]
+    ///
+    /// Like Core's `uint256`, it's shown in reverse byte order.
+    #[hash_newtype(backward)]
+    pub struct MuHashDigest(sha256::Hash);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A number modulo `2^3072 - 1103717`, as little-endian 64-bit limbs
+struct Num3072([u64; LIMBS]);
+
+impl Num3072 {
+    fn one() -> Num3072 {
+        let mut limbs = [0; LIMBS];
+        limbs[0] = 1;
+        Num3072(limbs)
+    }
+
+    /// Reads a number from its little-endian bytes. It may be bigger than the modulus, it'll be
+    /// reduced by the next multiplication.
+    fn from_bytes(bytes: &[u8; NUM3072_SIZE]) -> Num3072 {
+        let mut limbs = [0; LIMBS];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+            *limb = u64::from_le_bytes(chunk.try_into().expect("chunks have 8 bytes"));
+        }
+
+        Num3072(limbs)
+    }
+
+    fn to_bytes(self) -> [u8; NUM3072_SIZE] {
+        let mut bytes = [0; NUM3072_SIZE];
+        for (chunk, limb) in bytes.chunks_exact_mut(8).zip(self.0) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    /// Whether this number is at least the modulus
+    fn is_overflow(&self) -> bool {
+        self.0[0] > u64::MAX - MAX_PRIME_DIFF && self.0[1..].iter().all(|limb| *limb == u64::MAX)
+    }
+
+    /// Subtracts the modulus, by adding `MAX_PRIME_DIFF` and dropping the 3073rd bit
+    fn full_reduce(&mut self) {
+        let mut carry = MAX_PRIME_DIFF as u128;
+        for limb in self.0.iter_mut() {
+            let sum = *limb as u128 + carry;
+            *limb = sum as u64;
+            carry = sum >> 64;
+        }
+    }
+
+    /// Returns `self * other` modulo `2^3072 - 1103717`
+    fn mul(&self, other: &Num3072) -> Num3072 {
+        let mut product = [0u64; 2 * LIMBS];
+        for (i, a) in self.0.iter().enumerate() {
+            let mut carry = 0u128;
+            for (j, b) in other.0.iter().enumerate() {
+                let sum = product[i + j] as u128 + (*a as u128) * (*b as u128) + carry;
+                product[i + j] = sum as u64;
+                carry = sum >> 64;
+            }
+            product[i + LIMBS] = carry as u64;
+        }
+
+        // Since 2^3072 is MAX_PRIME_DIFF modulo our prime, high * 2^3072 + low is
+        // high * MAX_PRIME_DIFF + low
+        let mut result = [0u64; LIMBS];
+        let mut carry = 0u128;
+        for i in 0..LIMBS {
+            let sum =
+                product[i] as u128 + (product[i + LIMBS] as u128) * MAX_PRIME_DIFF as u128 + carry;
+            result[i] = sum as u64;
+            carry = sum >> 64;
+        }
+
+        // The carry is small, but folding it back may overflow again, a few times at most
+        while carry != 0 {
+            let mut fold = carry * MAX_PRIME_DIFF as u128;
+            for limb in result.iter_mut() {
+                if fold == 0 {
+                    break;
+                }
+                let sum = *limb as u128 + fold;
+                *limb = sum as u64;
+                fold = sum >> 64;
+            }
+            carry = fold;
+        }
+
+        let mut result = Num3072(result);
+        if result.is_overflow() {
+            result.full_reduce();
+        }
+
+        result
+    }
+
+    #[cfg_attr(not(feature = "json-rpc"), allow(dead_code))]
+    /// Returns the inverse of this number, by raising it to `p - 2` (Fermat's little theorem)
+    fn inverse(&self) -> Num3072 {
+        // p - 2 = 2^3072 - MAX_PRIME_DIFF - 2: all bits are set, except a few in the lowest limb
+        let mut exponent = [u64::MAX; LIMBS];
+        exponent[0] = u64::MAX - MAX_PRIME_DIFF - 1;
+
+        // Fixed 4-bit windows, from the most significant one
+        let mut powers = [Num3072::one(); 16];
+        for i in 1..16 {
+            powers[i] = powers[i - 1].mul(self);
+        }
+
+        let mut result = Num3072::one();
+        for limb in exponent.iter().rev() {
+            for shift in (0..16).rev() {
+                for _ in 0..4 {
+                    result = result.mul(&result);
+                }
+
+                let window = (limb >> (shift * 4)) & 0xf;
+                result = result.mul(&powers[window as usize]);
+            }
+        }
+
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A set hash, where elements can be added and removed in any order
+pub struct MuHash3072 {
+    /// The product of every element we've added
+    numerator: Num3072,
+
+    /// The product of every element we've removed
+    denominator: Num3072,
+}
+
+impl Default for MuHash3072 {
+    fn default() -> Self {
+        MuHash3072::new()
+    }
+}
+
+impl MuHash3072 {
+    /// Creates the hash of an empty set
+    pub fn new() -> MuHash3072 {
+        MuHash3072 {
+            numerator: Num3072::one(),
+            denominator: Num3072::one(),
+        }
+    }
+
+    /// Adds an element to the set
+    pub fn insert(&mut self, data: &[u8]) {
+        self.numerator = self.numerator.mul(&Self::to_num3072(data));
+    }
+
+    /// Removes an element from the set
+    pub fn remove(&mut self, data: &[u8]) {
+        self.denominator = self.denominator.mul(&Self::to_num3072(data));
+    }
+
+    #[cfg_attr(not(feature = "json-rpc"), allow(dead_code))]
+    /// Returns the commitment to our set
+    pub fn finalize(&self) -> MuHashDigest {
+        let mut value = self.numerator.mul(&self.denominator.inverse());
+        if value.is_overflow() {
+            value.full_reduce();
+        }
+
+        MuHashDigest::from_raw_hash(sha256::Hash::hash(&value.to_bytes()))
+    }
+
+    /// Serializes our state, so we can resume from it later
+    pub fn to_bytes(self) -> [u8; MUHASH_SIZE] {
+        let mut bytes = [0; MUHASH_SIZE];
+        bytes[..NUM3072_SIZE].copy_from_slice(&self.numerator.to_bytes());
+        bytes[NUM3072_SIZE..].copy_from_slice(&self.denominator.to_bytes());
+
+        bytes
+    }
+
+    /// Reads a state written by [MuHash3072::to_bytes]
+    pub fn from_bytes(bytes: &[u8; MUHASH_SIZE]) -> MuHash3072 {
+        let (numerator, denominator) = bytes.split_at(NUM3072_SIZE);
+
+        MuHash3072 {
+            numerator: Num3072::from_bytes(numerator.try_into().expect("split at NUM3072_SIZE")),
+            denominator: Num3072::from_bytes(
+                denominator.try_into().expect("split at NUM3072_SIZE"),
+            ),
+        }
+    }
+
+    /// Maps an element to a 3072-bit number, using ChaCha20 keyed with its SHA256
+    fn to_num3072(data: &[u8]) -> Num3072 {
+        let key = sha256::Hash::hash(data).to_byte_array();
+        let mut bytes = [0; NUM3072_SIZE];
+        ChaCha20::new(Key::new(key), Nonce::new([0; 12]), 0).apply_keystream(&mut bytes);
+
+        Num3072::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MuHash3072;
+    use super::Num3072;
+
+    /// The vector from Core's `muhash_tests`
+    const EXPECTED: &str = "10d312b100cbd32ada024a6646e40d3482fcff103668d2625f10002a607d5863";
+
+    #[test]
+    fn test_core_vectors() {
+        // H(0) * H(1) / H(2)
+        let mut muhash = MuHash3072::new();
+        let mut data = [0; 32];
+        muhash.insert(&data);
+        data[0] = 1;
+        muhash.insert(&data);
+        data[0] = 2;
+        muhash.remove(&data);
+
+        assert_eq!(muhash.finalize().to_string(), EXPECTED);
+    }
+
+    #[test]
+    fn test_order_and_removal() {
+        let mut a = MuHash3072::new();
+        a.insert(b"foo");
+        a.insert(b"bar");
+
+        let mut b = MuHash3072::new();
+        b.insert(b"bar");
+        b.insert(b"baz");
+        b.insert(b"foo");
+        b.remove(b"baz");
+
+        assert_eq!(a.finalize(), b.finalize());
+        assert_ne!(a.finalize(), MuHash3072::new().finalize());
+
+        let restored = MuHash3072::from_bytes(&b.to_bytes());
+        assert_eq!(restored, b);
+    }
+
+    #[test]
+    fn test_inverse() {
+        let mut bytes = [0xab; super::NUM3072_SIZE];
+        bytes[0] = 7;
+        let x = Num3072::from_bytes(&bytes);
+
+        assert_eq!(x.mul(&x.inverse()), Num3072::one());
+    }
+}
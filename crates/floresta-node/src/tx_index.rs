@@ -0,0 +1,654 @@
+//! An optional transaction index, that maps the txid of every transaction in our chain to the
+//! height of its block, and its position inside that block.
+//!
+//! We don't keep blocks, so to return a transaction we fetch its block from the network, or
+//! from our block cache, with [NodeInterface::get_block].
+//!
+//! The index is fed by [ChainState](floresta_chain::ChainState) as a [BlockConsumer], so it
+//! follows our tip. If it falls behind, e.g. because it was just enabled or rebuilt, the
+//! [sync_task] downloads the missing blocks. If `prune_depth` is set, we only index the
+//! last `prune_depth` blocks.
+//!
+//! Besides the txids, we keep the list of txids in each block, so we can remove them when a
+//! block gets pruned or reorged out.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::time::Duration;
+
+use bitcoin::consensus::deserialize;
+use bitcoin::consensus::encode;
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::Hash;
+use bitcoin::Block;
+use bitcoin::BlockHash;
+use bitcoin::OutPoint;
+use bitcoin::Transaction;
+use bitcoin::Txid;
+use floresta_chain::BlockConsumer;
+use floresta_chain::BlockchainInterface;
+use floresta_chain::TxSource;
+use floresta_chain::UtxoData;
+use floresta_common::impl_error_from;
+use floresta_wire::node_interface::NodeInterface;
+use kv::Bucket;
+use kv::Config;
+use kv::Integer;
+use kv::Store;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tokio::sync::RwLock;
+use tokio::task::block_in_place;
+use tokio::time::sleep;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// Where we keep the lowest height we've indexed
+const START_HEIGHT_KEY: &[u8] = b"start_height";
+
+/// Where we keep the last height we've indexed
+const BEST_HEIGHT_KEY: &[u8] = b"best_height";
+
+/// Where we keep the hash of the last block we've indexed
+const BEST_HASH_KEY: &[u8] = b"best_hash";
+
+/// How long [sync_task] waits before checking again if we are synced
+const SYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+/// Errors returned by the [TxIndex]
+pub enum TxIndexError {
+    /// Our database returned an error
+    Db(kv::Error),
+
+    /// Some data in our database is corrupted
+    Decode(encode::Error),
+
+    /// We couldn't find a block in our chain
+    Blockchain(String),
+
+    /// Our node couldn't fetch a block
+    Node(String),
+
+    /// Nobody had the block with our transaction
+    BlockNotFound(BlockHash),
+}
+
+impl Display for TxIndexError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TxIndexError::Db(e) => write!(f, "database error: {e}"),
+            TxIndexError::Decode(e) => write!(f, "corrupted txindex data: {e}"),
+            TxIndexError::Blockchain(e) => write!(f, "blockchain error: {e}"),
+            TxIndexError::Node(e) => write!(f, "couldn't fetch a block: {e}"),
+            TxIndexError::BlockNotFound(hash) => write!(f, "couldn't find block {hash}"),
+        }
+    }
+}
+
+impl_error_from!(TxIndexError, kv::Error, Db);
+impl_error_from!(TxIndexError, encode::Error, Decode);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// How far our index is, as returned by `getindexinfo`
+pub struct IndexInfo {
+    /// Whether we've indexed every block up to our tip
+    pub synced: bool,
+
+    /// The last block we've indexed
+    pub best_block_height: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Which blocks we have indexed
+struct IndexState {
+    /// The lowest height we have indexed, or will start indexing from
+    start_height: u32,
+
+    /// The last height, and hash, we've indexed, if any
+    best: Option<(u32, BlockHash)>,
+}
+
+impl IndexState {
+    /// The next height we should index
+    fn next_height(&self) -> u32 {
+        self.best
+            .map(|(height, _)| height + 1)
+            .unwrap_or(self.start_height)
+    }
+}
+
+/// Maps txids to where their transactions are in our chain
+pub struct TxIndex {
+    /// Our key/value store. We don't use it directly, but the buckets need it alive
+    _store: Store,
+
+    /// The height and position of each transaction, by txid
+    txs: Bucket<'static, Vec<u8>, Vec<u8>>,
+
+    /// The txids of each indexed block, by height
+    blocks: Bucket<'static, Integer, Vec<u8>>,
+
+    /// Which blocks we have indexed
+    meta: Bucket<'static, Vec<u8>, Vec<u8>>,
+
+    /// If set, we only index the last `prune_depth` blocks
+    prune_depth: Option<u32>,
+
+    /// Our chain's genesis block, that our peers may not send us
+    genesis: Block,
+
+    /// Our state. This lock also serializes writes, as we get blocks from the chain and from
+    /// [sync_task] at the same time.
+    state: Mutex<IndexState>,
+}
+
+// Lookups are only used by our json-rpc
+#[cfg_attr(not(feature = "json-rpc"), allow(dead_code))]
+impl TxIndex {
+    /// Opens the index saved in `path`, or creates a new one
+    pub fn new(
+        path: String,
+        prune_depth: Option<u32>,
+        genesis: Block,
+    ) -> Result<TxIndex, TxIndexError> {
+        let store = Store::new(Config::new(path))?;
+        let txs = store.bucket(Some("txs"))?;
+        let blocks = store.bucket(Some("blocks"))?;
+        let meta: Bucket<'static, Vec<u8>, Vec<u8>> = store.bucket(Some("meta"))?;
+
+        let start_height = match meta.get(&START_HEIGHT_KEY.to_vec())? {
+            Some(height) => deserialize(&height)?,
+            None => 0,
+        };
+
+        let best_height = meta.get(&BEST_HEIGHT_KEY.to_vec())?;
+        let best_hash = meta.get(&BEST_HASH_KEY.to_vec())?;
+        let best = match (best_height, best_hash) {
+            (Some(height), Some(hash)) => Some((deserialize(&height)?, deserialize(&hash)?)),
+            _ => None,
+        };
+
+        Ok(TxIndex {
+            _store: store,
+            txs,
+            blocks,
+            meta,
+            prune_depth,
+            genesis,
+            state: Mutex::new(IndexState { start_height, best }),
+        })
+    }
+
+    /// Fetches a block from `node`
+    async fn fetch_block(
+        &self,
+        block_hash: BlockHash,
+        node: &NodeInterface,
+    ) -> Result<Option<Block>, TxIndexError> {
+        // Our peers may not send us the genesis block, but we already have it
+        if block_hash == self.genesis.block_hash() {
+            return Ok(Some(self.genesis.clone()));
+        }
+
+        node.get_block(block_hash)
+            .await
+            .map_err(|e| TxIndexError::Node(e.to_string()))
+    }
+
+    /// Returns the height, and position inside its block, of a transaction
+    pub fn get_position(&self, txid: &Txid) -> Result<Option<(u32, u32)>, TxIndexError> {
+        let Some(position) = self.txs.get(&txid.as_byte_array().to_vec())? else {
+            return Ok(None);
+        };
+
+        Ok(Some(deserialize(&position)?))
+    }
+
+    /// Returns a transaction, and the height and position where it was mined. We fetch its block
+    /// from `node`.
+    pub async fn get_tx(
+        &self,
+        txid: &Txid,
+        chain: &impl BlockchainInterface,
+        node: &NodeInterface,
+    ) -> Result<Option<(Transaction, u32, u32)>, TxIndexError> {
+        let Some((height, position)) = self.get_position(txid)? else {
+            return Ok(None);
+        };
+
+        let block_hash = chain
+            .get_block_hash(height)
+            .map_err(|e| TxIndexError::Blockchain(format!("{e:?}")))?;
+
+        let block = self
+            .fetch_block(block_hash, node)
+            .await?
+            .ok_or(TxIndexError::BlockNotFound(block_hash))?;
+
+        let tx = block
+            .txdata
+            .into_iter()
+            .nth(position as usize)
+            .filter(|tx| tx.compute_txid() == *txid);
+
+        Ok(tx.map(|tx| (tx, height, position)))
+    }
+
+    /// Returns how far we've indexed, compared to our chain's `tip`
+    pub fn info(&self, tip: u32) -> IndexInfo {
+        let state = self.state.lock().unwrap();
+        let best_block_height = state.best.map(|(height, _)| height).unwrap_or(0);
+
+        IndexInfo {
+            synced: state.best.is_some() && best_block_height >= tip,
+            best_block_height,
+        }
+    }
+
+    /// The next height we need to index, given our chain's `tip`
+    pub fn next_height(&self, tip: u32) -> Result<u32, TxIndexError> {
+        let mut state = self.state.lock().unwrap();
+
+        // An empty, pruned, index doesn't need anything before the pruning window
+        if let (None, Some(depth)) = (state.best, self.prune_depth) {
+            state.start_height = state.start_height.max((tip + 1).saturating_sub(depth));
+            self.save_state(&state)?;
+        }
+
+        Ok(state.next_height())
+    }
+
+    /// Whether the last block we've indexed is still in `chain`
+    pub fn is_consistent(&self, chain: &impl BlockchainInterface) -> bool {
+        let state = self.state.lock().unwrap();
+        match state.best {
+            Some((height, hash)) => chain.get_block_hash(height).ok() == Some(hash),
+            None => true,
+        }
+    }
+
+    /// Indexes the transactions in `block`.
+    ///
+    /// Blocks are indexed in order: if `height` isn't the next height we need, the block is
+    /// ignored, and [sync_task] will fetch it later. If it's a height we've already indexed,
+    /// that block was reorged out, so we remove it, and everything after it, first.
+    pub fn index_block(&self, block: &Block, height: u32) -> Result<(), TxIndexError> {
+        let mut state = self.state.lock().unwrap();
+        if height > state.next_height() || height < state.start_height {
+            return Ok(());
+        }
+
+        if let Some((best_height, _)) = state.best {
+            for reorged in (height..=best_height).rev() {
+                self.remove_block(reorged)?;
+            }
+        }
+
+        let mut txids = Vec::with_capacity(block.txdata.len() * 32);
+        for (position, tx) in block.txdata.iter().enumerate() {
+            let txid = tx.compute_txid();
+            self.txs.set(
+                &txid.as_byte_array().to_vec(),
+                &serialize(&(height, position as u32)),
+            )?;
+            txids.extend(txid.as_byte_array());
+        }
+        self.blocks.set(&Integer::from(height), &txids)?;
+
+        state.best = Some((height, block.block_hash()));
+        if let Some(depth) = self.prune_depth {
+            let start_height = (height + 1).saturating_sub(depth);
+            for pruned in state.start_height..start_height {
+                self.remove_block(pruned)?;
+            }
+            state.start_height = state.start_height.max(start_height);
+        }
+
+        self.save_state(&state)
+    }
+
+    /// Removes everything we've indexed, so we can build the index again
+    pub fn reset(&self) -> Result<(), TxIndexError> {
+        let mut state = self.state.lock().unwrap();
+        self.txs.clear()?;
+        self.blocks.clear()?;
+
+        *state = IndexState::default();
+        self.save_state(&state)?;
+        self.meta.flush()?;
+
+        Ok(())
+    }
+
+    /// Removes the transactions of the block at `height` from our index
+    fn remove_block(&self, height: u32) -> Result<(), TxIndexError> {
+        let Some(txids) = self.blocks.remove(&Integer::from(height))? else {
+            return Ok(());
+        };
+
+        for txid in txids.chunks_exact(32) {
+            self.txs.remove(&txid.to_vec())?;
+        }
+
+        Ok(())
+    }
+
+    /// Saves which blocks we have indexed
+    fn save_state(&self, state: &IndexState) -> Result<(), TxIndexError> {
+        self.meta
+            .set(&START_HEIGHT_KEY.to_vec(), &serialize(&state.start_height))?;
+
+        match state.best {
+            Some((height, hash)) => {
+                self.meta
+                    .set(&BEST_HEIGHT_KEY.to_vec(), &serialize(&height))?;
+                self.meta.set(&BEST_HASH_KEY.to_vec(), &serialize(&hash))?;
+            }
+            None => {
+                self.meta.remove(&BEST_HEIGHT_KEY.to_vec())?;
+                self.meta.remove(&BEST_HASH_KEY.to_vec())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl BlockConsumer for TxIndex {
+    fn wants_spent_utxos(&self) -> bool {
+        false
+    }
+
+    fn on_block(
+        &self,
+        block: &Block,
+        height: u32,
+        _spent_utxos: Option<&HashMap<OutPoint, UtxoData>>,
+    ) {
+        if let Err(e) = self.index_block(block, height) {
+            error!("Could not index block {}: {e}", block.block_hash());
+        }
+    }
+}
+
+/// Lets our chain return transactions from a [TxIndex], see [BlockchainInterface::get_tx].
+///
+/// `get_tx` isn't async, so we block on fetching the block. This needs a multi-threaded tokio
+/// runtime, as the node answers our request in another task.
+pub struct IndexedTxSource<Chain> {
+    /// Where we look for transactions
+    index: Arc<TxIndex>,
+
+    /// Our chain. This is a weak reference, as our chain also keeps this source
+    chain: Weak<Chain>,
+
+    /// Where we fetch blocks from
+    node: NodeInterface,
+
+    /// The runtime our node runs in
+    runtime: Handle,
+}
+
+impl<Chain> IndexedTxSource<Chain> {
+    /// Creates a source for `chain`. This must be called from inside a tokio runtime.
+    pub fn new(index: Arc<TxIndex>, chain: &Arc<Chain>, node: NodeInterface) -> Self {
+        IndexedTxSource {
+            index,
+            chain: Arc::downgrade(chain),
+            node,
+            runtime: Handle::current(),
+        }
+    }
+}
+
+impl<Chain: BlockchainInterface + Send + Sync + 'static> TxSource for IndexedTxSource<Chain> {
+    fn get_tx(&self, txid: &Txid) -> Option<Transaction> {
+        let chain = self.chain.upgrade()?;
+        let tx = block_in_place(|| {
+            self.runtime
+                .block_on(self.index.get_tx(txid, &chain, &self.node))
+        });
+
+        match tx {
+            Ok(tx) => tx.map(|(tx, _, _)| tx),
+            Err(e) => {
+                warn!("Could not get transaction {txid} from our txindex: {e}");
+                None
+            }
+        }
+    }
+}
+
+/// Indexes the blocks our index is missing, until it reaches our chain's tip, fetching them
+/// with `node`. This runs until `kill_signal` is set.
+pub async fn sync_task<Chain: BlockchainInterface>(
+    index: Arc<TxIndex>,
+    chain: Chain,
+    node: NodeInterface,
+    kill_signal: Arc<RwLock<bool>>,
+) {
+    if !index.is_consistent(&chain) {
+        warn!("Our txindex doesn't match our chain, rebuilding it");
+        if let Err(e) = index.reset() {
+            error!("Could not reset our txindex: {e}");
+            return;
+        }
+    }
+
+    let mut synced = false;
+    while !*kill_signal.read().await {
+        let (tip, next_height) = match next_block(&index, &chain) {
+            Ok(next) => next,
+            Err(e) => {
+                error!("Could not sync our txindex: {e}");
+                sleep(SYNC_INTERVAL).await;
+                continue;
+            }
+        };
+
+        if next_height > tip {
+            if !synced {
+                info!("Our txindex is synced at height {tip}");
+                synced = true;
+            }
+
+            sleep(SYNC_INTERVAL).await;
+            continue;
+        }
+
+        synced = false;
+        let block = match chain.get_block_hash(next_height) {
+            Ok(hash) => index.fetch_block(hash, &node).await,
+            Err(_) => {
+                sleep(SYNC_INTERVAL).await;
+                continue;
+            }
+        };
+
+        match block {
+            Ok(Some(block)) => {
+                if let Err(e) = index.index_block(&block, next_height) {
+                    error!("Could not index block {}: {e}", block.block_hash());
+                    sleep(SYNC_INTERVAL).await;
+                }
+            }
+            _ => {
+                warn!("Could not fetch block {next_height} for our txindex, retrying later");
+                sleep(SYNC_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Returns our chain's validation index, and the next block our index needs
+fn next_block(
+    index: &TxIndex,
+    chain: &impl BlockchainInterface,
+) -> Result<(u32, u32), TxIndexError> {
+    let tip = chain
+        .get_validation_index()
+        .map_err(|e| TxIndexError::Blockchain(format!("{e:?}")))?;
+
+    Ok((tip, index.next_height(tip)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::absolute::LockTime;
+    use bitcoin::block::Header;
+    use bitcoin::block::Version;
+    use bitcoin::constants::genesis_block;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction;
+    use bitcoin::Block;
+    use bitcoin::BlockHash;
+    use bitcoin::CompactTarget;
+    use bitcoin::Network;
+    use bitcoin::Transaction;
+    use bitcoin::TxMerkleNode;
+
+    use super::IndexInfo;
+    use super::TxIndex;
+
+    /// A fresh database path for each test
+    fn path(name: &str) -> String {
+        let path = format!("./tmp-db/{name}.txindex");
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    fn index(name: &str, prune_depth: Option<u32>) -> TxIndex {
+        TxIndex::new(path(name), prune_depth, genesis_block(Network::Regtest)).unwrap()
+    }
+
+    /// A block with `n` transactions, that are unique for each `nonce`
+    fn block(nonce: u32, n: u32) -> Block {
+        let txdata = (0..n)
+            .map(|i| Transaction {
+                version: transaction::Version::ONE,
+                lock_time: LockTime::from_consensus(nonce * 1000 + i),
+                input: Vec::new(),
+                output: Vec::new(),
+            })
+            .collect();
+
+        Block {
+            header: Header {
+                version: Version::ONE,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce,
+            },
+            txdata,
+        }
+    }
+
+    #[test]
+    fn test_index_blocks() {
+        let index = index("index_blocks", None);
+        let blocks = (0..3).map(|i| block(i, 2)).collect::<Vec<_>>();
+
+        for (height, block) in blocks.iter().enumerate() {
+            index.index_block(block, height as u32).unwrap();
+        }
+
+        let txid = blocks[2].txdata[1].compute_txid();
+        assert_eq!(index.get_position(&txid).unwrap(), Some((2, 1)));
+        assert_eq!(
+            index.info(2),
+            IndexInfo {
+                synced: true,
+                best_block_height: 2
+            }
+        );
+        assert!(!index.info(3).synced);
+
+        // Blocks after a gap are ignored, we must index them in order
+        index.index_block(&block(10, 1), 10).unwrap();
+        assert_eq!(index.next_height(10).unwrap(), 3);
+        assert_eq!(
+            index
+                .get_position(&block(10, 1).txdata[0].compute_txid())
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_reorg() {
+        let index = index("reorg", None);
+        for height in 0..3 {
+            index.index_block(&block(height, 1), height).unwrap();
+        }
+
+        // A different block at height 1 replaces both blocks 1 and 2
+        let fork = block(100, 1);
+        index.index_block(&fork, 1).unwrap();
+
+        assert_eq!(index.next_height(1).unwrap(), 2);
+        assert_eq!(
+            index.get_position(&fork.txdata[0].compute_txid()).unwrap(),
+            Some((1, 0))
+        );
+        for height in 1..3 {
+            let txid = block(height, 1).txdata[0].compute_txid();
+            assert_eq!(index.get_position(&txid).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_prune() {
+        let index = index("prune", Some(2));
+
+        // An empty index starts from the pruning window
+        assert_eq!(index.next_height(9).unwrap(), 8);
+        for height in 8..12 {
+            index.index_block(&block(height, 1), height).unwrap();
+        }
+
+        for height in 8..10 {
+            let txid = block(height, 1).txdata[0].compute_txid();
+            assert_eq!(index.get_position(&txid).unwrap(), None);
+        }
+        for height in 10..12 {
+            let txid = block(height, 1).txdata[0].compute_txid();
+            assert_eq!(index.get_position(&txid).unwrap(), Some((height, 0)));
+        }
+
+        // Blocks below the pruning window are ignored
+        index.index_block(&block(5, 1), 5).unwrap();
+        assert_eq!(index.next_height(11).unwrap(), 12);
+    }
+
+    #[test]
+    fn test_reset_and_reload() {
+        let path = path("reset_and_reload");
+        let index = TxIndex::new(path.clone(), None, genesis_block(Network::Regtest)).unwrap();
+        for height in 0..2 {
+            index.index_block(&block(height, 1), height).unwrap();
+        }
+        drop(index);
+
+        let index = TxIndex::new(path, None, genesis_block(Network::Regtest)).unwrap();
+        assert_eq!(index.next_height(1).unwrap(), 2);
+
+        index.reset().unwrap();
+        assert_eq!(index.next_height(1).unwrap(), 0);
+        assert_eq!(
+            index
+                .get_position(&block(0, 1).txdata[0].compute_txid())
+                .unwrap(),
+            None
+        );
+    }
+}
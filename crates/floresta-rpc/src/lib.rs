@@ -159,6 +159,25 @@ mod tests {
         assert_eq!(gbi.root_hashes, Vec::<String>::new());
     }
 
+    #[test]
+    fn test_get_deployment_info() {
+        let (_proc, client) = start_florestad();
+
+        let info = client.get_deployment_info(None).expect("rpc not working");
+        assert_eq!(info.height, 0);
+
+        let taproot = &info.deployments["taproot"];
+        assert_eq!(taproot.deployment_type, "bip9");
+        assert!(taproot.active);
+        assert_eq!(taproot.bip9.as_ref().unwrap().status, "active");
+
+        let testdummy = &info.deployments["testdummy"];
+        assert!(!testdummy.active);
+        assert_eq!(testdummy.bip9.as_ref().unwrap().status, "defined");
+
+        assert_eq!(info.deployments["bip34"].deployment_type, "buried");
+    }
+
     #[test]
     fn test_get_roots() {
         let (_proc, client) = start_florestad();
@@ -38,6 +38,12 @@ pub trait FlorestaRPC {
     /// the previous block hash, the merkle root, the timestamp, the difficulty target,
     /// and the nonce.
     fn get_block_header(&self, hash: BlockHash) -> Result<BlockHeader>;
+    /// Returns the status of each soft fork at a given block
+    ///
+    /// This method returns which soft forks are active, both the ones buried at a fixed height
+    /// and the ones deployed with versionbits (BIP9), with their signaling statistics. If no
+    /// block hash is given, our best block is used.
+    fn get_deployment_info(&self, blockhash: Option<BlockHash>) -> Result<GetDeploymentInfoRes>;
     /// Gets a transaction from the blockchain
     ///
     /// This method returns a transaction that's cached in our wallet. If the verbosity flag is
@@ -81,6 +87,14 @@ pub trait FlorestaRPC {
     /// a set of roots, that let's us prove that a UTXO exists in the chain. This method returns
     /// a vector of hexadecimal strings, each of which is a root in the accumulator.
     fn get_roots(&self) -> Result<Vec<String>>;
+    /// Returns our accumulator after the block at `height`, as an assumeutreexo value
+    ///
+    /// If `height` isn't set, we use our last validated block. `format` may be `json` (the
+    /// default), `toml` or `rust`, the last two return a string that can be pasted into
+    /// florestad's config file or into `ChainParams::get_assume_utreexo`.
+    fn dump_assume_utreexo(&self, height: Option<u32>, format: Option<String>) -> Result<Value>;
+    /// Checks an assumeutreexo value against our own accumulator at the same height
+    fn verify_assume_utreexo(&self, value: AssumeUtreexoValue) -> Result<VerifyAssumeUtreexoRes>;
     /// Gets information about the peers we're connected with
     ///
     /// This method returns information about the peers we're connected with. This includes
@@ -132,6 +146,26 @@ pub trait FlorestaRPC {
     fn list_descriptors(&self) -> Result<Vec<String>>;
     /// Sends a ping to all peers, checking if they are still alive
     fn ping(&self) -> Result<()>;
+    /// Returns how far we are in the backfill of assumed blocks
+    ///
+    /// If we've assumed some blocks with assumeutreexo or PoW fraud proofs, and the backfill
+    /// option is enabled, we download and validate them in the background. This returns the
+    /// progress of each backfill range, and roughly how many seconds until we finish.
+    fn get_backfill_progress(&self) -> Result<GetBackfillProgressRes>;
+    /// Returns the status of our indexes
+    ///
+    /// If `index_name` is set, only that index is returned. Indexes that aren't enabled are
+    /// left out.
+    fn get_index_info(&self, index_name: Option<String>) -> Result<GetIndexInfoRes>;
+    /// Returns statistics about the UTXO set, from our coinstatsindex
+    ///
+    /// `hash_type` may be `muhash`, to also get the MuHash of the UTXO set, or `none`. If
+    /// `height` isn't set, we use the last block the index has seen.
+    fn get_txout_set_info(
+        &self,
+        hash_type: Option<String>,
+        height: Option<u32>,
+    ) -> Result<GetTxOutSetInfoRes>;
 }
 
 /// Since the workflow for jsonrpc is the same for all methods, we can implement a trait
@@ -218,6 +252,28 @@ impl<T: JsonRPCClient> FlorestaRPC for T {
         self.call("getroots", &[])
     }
 
+    fn dump_assume_utreexo(&self, height: Option<u32>, format: Option<String>) -> Result<Value> {
+        let params = match (height, format) {
+            (None, None) => Vec::new(),
+            (Some(height), None) => vec![Value::Number(height.into())],
+            // The height comes first, so we use our last validated block
+            (height, Some(format)) => {
+                let height = match height {
+                    Some(height) => height,
+                    None => self.get_blockchain_info()?.validated,
+                };
+                vec![Value::Number(height.into()), Value::String(format)]
+            }
+        };
+
+        self.call("dumpassumeutreexo", &params)
+    }
+
+    fn verify_assume_utreexo(&self, value: AssumeUtreexoValue) -> Result<VerifyAssumeUtreexoRes> {
+        let value = serde_json::to_value(value).expect("AssumeUtreexoValue serializes to json");
+        self.call("verifyassumeutreexo", &[value])
+    }
+
     fn get_block(&self, hash: BlockHash, verbosity: Option<u32>) -> Result<GetBlockRes> {
         let verbosity = verbosity.unwrap_or(0);
 
@@ -310,6 +366,15 @@ impl<T: JsonRPCClient> FlorestaRPC for T {
         self.call("getblockchaininfo", &[])
     }
 
+    fn get_deployment_info(&self, blockhash: Option<BlockHash>) -> Result<GetDeploymentInfoRes> {
+        let params: Vec<Value> = blockhash
+            .map(|hash| Value::String(hash.to_string()))
+            .into_iter()
+            .collect();
+
+        self.call("getdeploymentinfo", &params)
+    }
+
     fn send_raw_transaction(&self, tx: String) -> Result<Txid> {
         self.call("sendrawtransaction", &[Value::String(tx)])
     }
@@ -321,4 +386,28 @@ impl<T: JsonRPCClient> FlorestaRPC for T {
     fn ping(&self) -> Result<()> {
         self.call("ping", &[])
     }
+
+    fn get_backfill_progress(&self) -> Result<GetBackfillProgressRes> {
+        self.call("getbackfillprogress", &[])
+    }
+
+    fn get_index_info(&self, index_name: Option<String>) -> Result<GetIndexInfoRes> {
+        let params: Vec<Value> = index_name.map(Value::String).into_iter().collect();
+
+        self.call("getindexinfo", &params)
+    }
+
+    fn get_txout_set_info(
+        &self,
+        hash_type: Option<String>,
+        height: Option<u32>,
+    ) -> Result<GetTxOutSetInfoRes> {
+        let hash_type = hash_type.unwrap_or_else(|| "none".to_string());
+        let mut params = vec![Value::String(hash_type)];
+        if let Some(height) = height {
+            params.push(Value::Number(height.into()));
+        }
+
+        self.call("gettxoutsetinfo", &params)
+    }
 }
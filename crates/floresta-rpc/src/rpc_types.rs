@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fmt::Display;
 
 use serde::Deserialize;
@@ -50,6 +51,180 @@ pub struct GetBlockchainInfoRes {
     /// On average, miners needs to make `difficulty` hashes before finding one that
     /// solves a block's PoW
     pub difficulty: u64,
+    /// The status of each soft fork at our best block, keyed by name
+    #[serde(default)]
+    pub softforks: BTreeMap<String, SoftForkInfo>,
+}
+
+/// The status of a soft fork, as returned by `getdeploymentinfo`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SoftForkInfo {
+    /// `buried` for soft forks with a hardcoded height, `bip9` for versionbits deployments
+    #[serde(rename = "type")]
+    pub deployment_type: String,
+    /// The height this soft fork activated at, if it's active
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Whether the next block must follow the rules of this soft fork
+    pub active: bool,
+    /// The versionbits status, only for `bip9` soft forks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bip9: Option<Bip9Info>,
+}
+
+/// The versionbits status of a soft fork
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Bip9Info {
+    /// The version bit miners set to signal for this soft fork
+    pub bit: u8,
+    /// When signaling starts, as a median time past. -1 means always active, -2 never active
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<i64>,
+    /// When the deployment fails if it isn't locked in, as a median time past
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<i64>,
+    /// When signaling starts, for deployments with a height trigger (BIP8)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_height: Option<u32>,
+    /// When the deployment times out, for deployments with a height trigger (BIP8)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_height: Option<u32>,
+    /// A locked in deployment only activates at or after this height
+    pub min_activation_height: u32,
+    /// The state for the block we asked for
+    ///
+    /// One of `defined`, `started`, `must_signal`, `locked_in`, `active` or `failed`
+    pub status: String,
+    /// The height of the first block with this status
+    pub since: u32,
+    /// The state for the next block
+    pub status_next: String,
+    /// Signaling statistics for the current period, only while signaling is possible
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statistics: Option<Bip9Statistics>,
+}
+
+/// How many blocks signaled for a soft fork in the current period
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Bip9Statistics {
+    /// The length of a signaling period
+    pub period: u32,
+    /// How many blocks must signal in a period to lock in
+    pub threshold: u32,
+    /// How many blocks of this period we've seen
+    pub elapsed: u32,
+    /// How many of those blocks signal
+    pub count: u32,
+    /// Whether the threshold can still be reached in this period
+    pub possible: bool,
+}
+
+/// The return type for `getdeploymentinfo`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetDeploymentInfoRes {
+    /// The block we computed the deployment states for
+    pub hash: String,
+    /// The height of that block
+    pub height: u32,
+    /// The status of each soft fork, keyed by name
+    pub deployments: BTreeMap<String, SoftForkInfo>,
+}
+
+/// The status of one index, see `getindexinfo`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IndexInfo {
+    /// Whether we've indexed every block up to our tip
+    pub synced: bool,
+    /// The last block we've indexed
+    pub best_block_height: u32,
+}
+
+/// The return type for `getindexinfo`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetIndexInfoRes {
+    /// Our transaction index, if enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txindex: Option<IndexInfo>,
+    /// Our UTXO set statistics index, if enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coinstatsindex: Option<IndexInfo>,
+}
+
+/// An accumulator we may assume, see `dumpassumeutreexo`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AssumeUtreexoValue {
+    /// The block this accumulator is for
+    pub block_hash: String,
+    /// The height of that block
+    pub height: u32,
+    /// The roots of the accumulator after that block
+    pub roots: Vec<String>,
+    /// How many leaves were ever added to the accumulator
+    pub leaves: u64,
+}
+
+/// The return type for `verifyassumeutreexo`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VerifyAssumeUtreexoRes {
+    /// Whether the value matches the node's accumulator at that height
+    pub valid: bool,
+    /// If it doesn't, the first field that differs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The node's own value at that height
+    pub our_value: AssumeUtreexoValue,
+}
+
+/// The return type for `gettxoutsetinfo`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetTxOutSetInfoRes {
+    /// The height of the block these statistics are for
+    pub height: u32,
+    /// The hash of that block
+    pub bestblock: String,
+    /// How many UTXOs there are
+    pub txouts: u64,
+    /// A rough estimate of the UTXO set size
+    pub bogosize: u64,
+    /// The MuHash of the UTXO set, only with `hash_type=muhash`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub muhash: Option<String>,
+    /// The sum of all UTXOs, in BTC
+    pub total_amount: f64,
+    /// Coins that can never be spent, in BTC
+    pub total_unspendable_amount: f64,
+}
+
+/// The progress of one backfill range
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BackfillRangeProgress {
+    /// The block right before this range
+    pub start: u32,
+    /// The last block in this range
+    pub end: u32,
+    /// The last block we've validated in this range
+    pub height: u32,
+}
+
+/// The return type for `getbackfillprogress`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetBackfillProgressRes {
+    /// Whether we've assumed blocks that we didn't backfill yet
+    pub assumed: bool,
+    /// Whether we are downloading and validating blocks right now
+    pub running: bool,
+    /// Whether we've validated all blocks that we've assumed
+    pub done: bool,
+    /// The progress of each range
+    pub ranges: Vec<BackfillRangeProgress>,
+    /// How many blocks we've validated, in all ranges
+    pub validated_blocks: u64,
+    /// How many blocks we need to validate, in all ranges
+    pub total_blocks: u64,
+    /// `validated_blocks / total_blocks`, from 0 to 1
+    pub progress: f64,
+    /// Roughly how many seconds until we finish, if we know it
+    pub eta: Option<u64>,
 }
 
 /// The information returned by a get_raw_tx
@@ -122,7 +297,8 @@ pub struct ScriptPubKey {
     /// The type of this spk. E.g: PKH, SH, WSH, WPKH, TR, non-standard...
     pub type_: String,
     /// Encode this script using one of the standard address types, if possible
-    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
 }
 
 /// A transaction input returned by some rpcs, like gettransaction and getblock
@@ -171,7 +347,7 @@ pub struct PeerInfo {
     ///
     /// We can connect with peers for different reasons. E.g. we can connect to a peer to
     /// see if it has a block we're missing, or just to check if that address is still alive.
-    /// Possible values are: Feeler, Regular and Extra
+    /// Possible values are: Feeler, Regular, Extra and Inbound
     pub kind: String,
     /// The state of this peer
     ///
@@ -21,10 +21,20 @@ mod p2p_wire;
 #[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::address_man;
 #[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::backfill;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::block_cache;
+#[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::block_proof;
 #[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::bridge;
+#[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::chain_selector;
 #[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::compact_block;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::filter_headers;
+#[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::mempool;
 #[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::node;
@@ -36,6 +46,8 @@ pub use p2p_wire::node_interface;
 pub use p2p_wire::running_node;
 #[cfg(not(target_arch = "wasm32"))]
 pub use p2p_wire::sync_node;
+#[cfg(not(target_arch = "wasm32"))]
+pub use p2p_wire::tx_relay;
 pub use p2p_wire::UtreexoNodeConfig;
 /// NodeHooks is a trait that defines the hooks that a node can use to interact with the network
 /// and the blockchain. Every time an event happens, the node will call the corresponding hook.
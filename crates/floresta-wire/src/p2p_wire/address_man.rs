@@ -377,7 +377,7 @@ impl AddressMan {
 
         // ask for any peer (if filtering isn't available)
         if seed.filters == ServiceFlags::NONE {
-            let _addresses = Self::do_lookup(seed.seed, default_port, socks5);
+            let _addresses = Self::do_lookup(&seed.seed, default_port, socks5);
             let _addresses = _addresses.into_iter().map(|mut x| {
                 x.services = ServiceFlags::NETWORK | ServiceFlags::WITNESS;
                 x
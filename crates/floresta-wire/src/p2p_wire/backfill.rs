@@ -0,0 +1,801 @@
+//! If we assume a chain with assumeutreexo or PoW fraud proofs, we can still download and
+//! validate the skipped blocks in the background. This is called backfilling.
+//!
+//! To make it faster, we split the skipped blocks into height ranges, and validate each one with
+//! its own [PartialChainState](floresta_chain::pruned_utreexo::partial_chain::PartialChainState).
+//! A range starts from an accumulator we've assumed (either the one from genesis, an
+//! assumeutreexo value, or one that our peers agreed on), and must end with the accumulator the
+//! next range starts from. Once all ranges finish, their final accumulators are checked against
+//! each other, so the whole chain is confirmed.
+//!
+//! Each range saves its progress to its own checkpoint file, inside the `backfill` directory in
+//! our datadir, so we can resume after a restart. See [BackfillRange::serialize] for the format.
+
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::io::Cursor;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use bitcoin::hashes::sha256d;
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::Magic;
+use bitcoin::BlockHash;
+use floresta_common::impl_error_from;
+use rustreexo::accumulator::stump::Stump;
+use serde::Serialize;
+
+/// The name of the directory, inside our datadir, where we keep the backfill state
+const BACKFILL_DIR: &str = "backfill";
+
+/// A file inside [BACKFILL_DIR] meaning we've finished backfilling
+const DONE_FILE: &str = "done";
+
+/// The extension of checkpoints that are still being written
+const TMP_EXTENSION: &str = "tmp";
+
+/// The file where we used to keep the state of our single backfill range
+const LEGACY_STATE_FILE: &str = ".sync_node_state";
+
+/// The first bytes of every checkpoint file
+const CHECKPOINT_MAGIC: [u8; 4] = *b"FBFC";
+
+/// The current version of our checkpoint format
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// The size of the checkpoint header: magic, version, network magic and checksum
+const CHECKPOINT_HEADER_LEN: usize = 16;
+
+#[derive(Debug)]
+/// Errors we may find while loading the backfill checkpoints
+pub enum BackfillError {
+    /// We couldn't read or write a checkpoint
+    Io(io::Error),
+
+    /// This file isn't a checkpoint
+    InvalidMagic,
+
+    /// This checkpoint was written by an unknown version
+    UnsupportedVersion(u32),
+
+    /// This checkpoint is from another network
+    WrongNetwork(Magic),
+
+    /// The checkpoint got corrupted
+    InvalidChecksum,
+
+    /// The checkpoint is shorter than it should be, probably from an interrupted write
+    Truncated,
+
+    /// The checkpoint is well-formed, but makes no sense
+    InvalidRange(String),
+
+    /// The last block we've validated isn't in our chain anymore
+    UnknownTip(u32, BlockHash),
+}
+
+impl Display for BackfillError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BackfillError::Io(e) => write!(f, "io error: {e}"),
+            BackfillError::InvalidMagic => write!(f, "not a backfill checkpoint"),
+            BackfillError::UnsupportedVersion(version) => {
+                write!(f, "unsupported checkpoint version {version}")
+            }
+            BackfillError::WrongNetwork(magic) => {
+                write!(f, "checkpoint is from the network with magic {magic}")
+            }
+            BackfillError::InvalidChecksum => write!(f, "invalid checkpoint checksum"),
+            BackfillError::Truncated => write!(f, "checkpoint is truncated"),
+            BackfillError::InvalidRange(e) => write!(f, "invalid backfill range: {e}"),
+            BackfillError::UnknownTip(height, hash) => {
+                write!(f, "block {hash} at height {height} isn't in our chain")
+            }
+        }
+    }
+}
+
+impl_error_from!(BackfillError, io::Error, Io);
+
+#[derive(Debug, Clone, PartialEq)]
+/// A range of blocks being validated by one backfill worker
+pub struct BackfillRange {
+    /// The block right before this range, whose accumulator we start from
+    pub start: u32,
+
+    /// The last block in this range
+    pub end: u32,
+
+    /// The accumulator after block `start`, that we've assumed
+    pub start_acc: Stump,
+
+    /// The accumulator we must have after block `end`
+    ///
+    /// This is the `start_acc` of the next range, or the accumulator we've assumed for our
+    /// validation index if this is the last one.
+    pub end_acc: Stump,
+
+    /// The last block we've validated in this range
+    pub height: u32,
+
+    /// The accumulator after block `height`
+    pub acc: Stump,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// What we know about the backfill from a previous run
+pub enum BackfillState {
+    /// We've never started backfilling
+    NotStarted,
+
+    /// We've started backfilling with these ranges, some of them may be done already
+    InProgress(Vec<BackfillRange>),
+
+    /// We've validated all blocks already
+    Done,
+}
+
+impl BackfillRange {
+    /// Creates a range that haven't validated any block yet
+    pub fn new(start: u32, end: u32, start_acc: Stump, end_acc: Stump) -> Self {
+        BackfillRange {
+            start,
+            end,
+            acc: start_acc.clone(),
+            height: start,
+            start_acc,
+            end_acc,
+        }
+    }
+
+    /// Whether we've validated all blocks in this range
+    pub fn is_done(&self) -> bool {
+        self.height == self.end
+    }
+
+    /// Whether we've validated all blocks in this range, and got the accumulator we expected
+    pub fn is_valid(&self) -> bool {
+        self.is_done() && self.acc == self.end_acc
+    }
+
+    /// Serializes a checkpoint for this range, where `tip` is the hash of block `height`.
+    ///
+    /// The checkpoint has a 16-byte header, followed by the payload:
+    ///
+    /// | Field           | Size | Description                                      |
+    /// |-----------------|------|--------------------------------------------------|
+    /// | magic           | 4    | Always `FBFC`                                    |
+    /// | version         | 4    | Little-endian, currently 1                       |
+    /// | network         | 4    | The p2p magic of our network                     |
+    /// | checksum        | 4    | First 4 bytes of the double-SHA256 of the payload|
+    /// | start, end      | 8    | Little-endian u32s                               |
+    /// | height          | 4    | Little-endian u32                                |
+    /// | tip             | 32   | The hash of block `height`                       |
+    /// | start_acc       | var  | A serialized [Stump]                             |
+    /// | end_acc         | var  | A serialized [Stump]                             |
+    /// | acc             | var  | A serialized [Stump]                             |
+    pub fn serialize(&self, network: Magic, tip: BlockHash) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&self.start.to_le_bytes());
+        payload.extend_from_slice(&self.end.to_le_bytes());
+        payload.extend_from_slice(&self.height.to_le_bytes());
+        payload.extend_from_slice(tip.as_byte_array());
+
+        for acc in [&self.start_acc, &self.end_acc, &self.acc] {
+            acc.serialize(&mut payload)
+                .expect("writing to a vec shouldn't fail");
+        }
+
+        let checksum = sha256d::Hash::hash(&payload);
+
+        let mut data = Vec::with_capacity(CHECKPOINT_HEADER_LEN + payload.len());
+        data.extend_from_slice(&CHECKPOINT_MAGIC);
+        data.extend_from_slice(&CHECKPOINT_VERSION.to_le_bytes());
+        data.extend_from_slice(&network.to_bytes());
+        data.extend_from_slice(&checksum[..4]);
+        data.extend_from_slice(&payload);
+
+        data
+    }
+
+    /// Deserializes a checkpoint written by [BackfillRange::serialize], returning the range and
+    /// the hash of block `height`
+    pub fn deserialize(data: &[u8], network: Magic) -> Result<(Self, BlockHash), BackfillError> {
+        if data.len() < CHECKPOINT_HEADER_LEN {
+            return Err(BackfillError::Truncated);
+        }
+
+        let (header, payload) = data.split_at(CHECKPOINT_HEADER_LEN);
+        if header[0..4] != CHECKPOINT_MAGIC {
+            return Err(BackfillError::InvalidMagic);
+        }
+
+        let version = u32::from_le_bytes(header[4..8].try_into().expect("we took 4 bytes"));
+        if version != CHECKPOINT_VERSION {
+            return Err(BackfillError::UnsupportedVersion(version));
+        }
+
+        let magic = Magic::from_bytes(header[8..12].try_into().expect("we took 4 bytes"));
+        if magic != network {
+            return Err(BackfillError::WrongNetwork(magic));
+        }
+
+        if header[12..16] != sha256d::Hash::hash(payload)[..4] {
+            return Err(BackfillError::InvalidChecksum);
+        }
+
+        let height = |offset: usize| {
+            payload
+                .get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().expect("we took 4 bytes")))
+                .ok_or(BackfillError::Truncated)
+        };
+
+        let (start, end, height) = (height(0)?, height(4)?, height(8)?);
+        let tip = payload.get(12..44).ok_or(BackfillError::Truncated)?;
+        let tip = BlockHash::from_slice(tip).expect("we took 32 bytes");
+
+        let mut reader = Cursor::new(&payload[44..]);
+        let mut read_acc = || Stump::deserialize(&mut reader).map_err(BackfillError::InvalidRange);
+        let (start_acc, end_acc, acc) = (read_acc()?, read_acc()?, read_acc()?);
+
+        if start > height || height > end {
+            return Err(BackfillError::InvalidRange(format!(
+                "height {height} is outside of {start}..={end}"
+            )));
+        }
+
+        let range = BackfillRange {
+            start,
+            end,
+            start_acc,
+            end_acc,
+            height,
+            acc,
+        };
+
+        Ok((range, tip))
+    }
+
+    /// The name of the file where we save this range
+    fn file_name(&self) -> String {
+        format!("{}-{}", self.start, self.end)
+    }
+}
+
+/// Returns the heights where the backfill ranges should start, if we want to split the blocks
+/// up to `end` into `ranges` ranges of (roughly) the same size. Genesis isn't included, as we
+/// always know its accumulator.
+pub fn checkpoint_heights(end: u32, ranges: usize) -> Vec<u32> {
+    let ranges = ranges.max(1) as u64;
+    let mut heights: Vec<u32> = (1..ranges)
+        .map(|i| (end as u64 * i / ranges) as u32)
+        .filter(|height| *height > 0 && *height < end)
+        .collect();
+
+    heights.dedup();
+    heights
+}
+
+/// Splits the blocks up to `end` into ranges, one starting at genesis and one at each checkpoint.
+///
+/// `end_acc` is the accumulator we've assumed after `end`, and `checkpoints` are the other
+/// accumulators we've assumed, as `(height, acc)` pairs. Checkpoints outside of `1..end` are
+/// ignored.
+pub fn plan(end: u32, end_acc: Stump, checkpoints: &[(u32, Stump)]) -> Vec<BackfillRange> {
+    if end == 0 {
+        return Vec::new();
+    }
+
+    let mut checkpoints: Vec<_> = checkpoints
+        .iter()
+        .filter(|(height, _)| *height > 0 && *height < end)
+        .cloned()
+        .collect();
+
+    checkpoints.sort_by_key(|(height, _)| *height);
+    checkpoints.dedup_by_key(|(height, _)| *height);
+
+    let mut boundaries = vec![(0, Stump::default())];
+    boundaries.extend(checkpoints);
+    boundaries.push((end, end_acc));
+
+    boundaries
+        .windows(2)
+        .map(|window| {
+            let (start, start_acc) = window[0].clone();
+            let (end, end_acc) = window[1].clone();
+            BackfillRange::new(start, end, start_acc, end_acc)
+        })
+        .collect()
+}
+
+/// Checks whether all ranges are done, and connect to each other: each one must end where the
+/// next one starts, with the accumulator that the next one assumed.
+///
+/// Returns the first range that doesn't, if any.
+pub fn find_invalid_range(ranges: &[BackfillRange]) -> Option<&BackfillRange> {
+    if let Some(range) = ranges.iter().find(|range| !range.is_valid()) {
+        return Some(range);
+    }
+
+    ranges
+        .windows(2)
+        .find(|window| window[0].end != window[1].start || window[0].acc != window[1].start_acc)
+        .map(|window| &window[1])
+}
+
+fn backfill_dir(datadir: &str) -> PathBuf {
+    PathBuf::from(datadir).join(BACKFILL_DIR)
+}
+
+/// Writes `data` to `path`, without ever leaving a partially written file there
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension(TMP_EXTENSION);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    fs::rename(tmp_path, path)
+}
+
+/// Loads the backfill state saved in `datadir`, for the network with the given p2p magic
+///
+/// `block_hash` should return the hash of a block in our chain, given its height. We use it to
+/// check that the last block validated by each range is still in our chain.
+///
+/// If we find the state file from an older version, we start from scratch, unless it says
+/// we've finished backfilling.
+pub fn load(
+    datadir: &str,
+    network: Magic,
+    block_hash: impl Fn(u32) -> Option<BlockHash>,
+) -> Result<BackfillState, BackfillError> {
+    let dir = backfill_dir(datadir);
+    if dir.join(DONE_FILE).exists() {
+        return Ok(BackfillState::Done);
+    }
+
+    let legacy_file = PathBuf::from(datadir).join(LEGACY_STATE_FILE);
+    if let Ok(legacy_state) = fs::read(&legacy_file) {
+        // an empty file meant we've finished backfilling
+        if legacy_state.is_empty() {
+            mark_done(datadir)?;
+            fs::remove_file(&legacy_file)?;
+            return Ok(BackfillState::Done);
+        }
+
+        fs::remove_file(&legacy_file)?;
+    }
+
+    if !dir.exists() {
+        return Ok(BackfillState::NotStarted);
+    }
+
+    let mut ranges = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        // a write that didn't finish, the previous checkpoint is still there
+        if path.extension().is_some_and(|ext| ext == TMP_EXTENSION) {
+            continue;
+        }
+
+        let (range, tip) = BackfillRange::deserialize(&fs::read(path)?, network)?;
+        if block_hash(range.height) != Some(tip) {
+            return Err(BackfillError::UnknownTip(range.height, tip));
+        }
+
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() {
+        return Ok(BackfillState::NotStarted);
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    Ok(BackfillState::InProgress(ranges))
+}
+
+/// Saves a checkpoint for one range, where `tip` is the hash of block `range.height`
+pub fn save_range(
+    datadir: &str,
+    network: Magic,
+    range: &BackfillRange,
+    tip: BlockHash,
+) -> io::Result<()> {
+    let dir = backfill_dir(datadir);
+    fs::create_dir_all(&dir)?;
+    write_atomically(&dir.join(range.file_name()), &range.serialize(network, tip))
+}
+
+/// Removes every checkpoint, so we can start backfilling from scratch
+pub fn reset(datadir: &str) -> io::Result<()> {
+    let dir = backfill_dir(datadir);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+
+    Ok(())
+}
+
+/// Records that we've assumed some blocks, instead of validating them
+pub fn mark_assumed(datadir: &str) -> io::Result<()> {
+    fs::create_dir_all(backfill_dir(datadir))
+}
+
+/// Whether we've assumed blocks that we didn't backfill yet. We may have validated the blocks
+/// after them, but only on top of the accumulator we've assumed.
+pub fn has_assumed_blocks(datadir: &str) -> bool {
+    let dir = backfill_dir(datadir);
+    dir.exists() && !dir.join(DONE_FILE).exists()
+}
+
+/// Removes every checkpoint, and records that we've finished backfilling
+pub fn mark_done(datadir: &str) -> io::Result<()> {
+    reset(datadir)?;
+
+    let dir = backfill_dir(datadir);
+    fs::create_dir_all(&dir)?;
+    write_atomically(&dir.join(DONE_FILE), &[])
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+/// How far a backfill range is
+pub struct BackfillRangeProgress {
+    /// The block right before this range
+    pub start: u32,
+
+    /// The last block in this range
+    pub end: u32,
+
+    /// The last block we've validated in this range
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+/// How far we are in the backfill, returned by [BackfillTracker::progress]
+pub struct BackfillProgress {
+    /// Whether we've assumed blocks that we didn't backfill yet, see [has_assumed_blocks]
+    pub assumed: bool,
+
+    /// Whether we are downloading and validating blocks right now
+    pub running: bool,
+
+    /// Whether we've validated all blocks that we've assumed
+    pub done: bool,
+
+    /// The progress of each range
+    pub ranges: Vec<BackfillRangeProgress>,
+
+    /// How many blocks we've validated, in all ranges
+    pub validated_blocks: u64,
+
+    /// How many blocks we need to validate, in all ranges
+    pub total_blocks: u64,
+
+    /// `validated_blocks / total_blocks`, from 0 to 1
+    pub progress: f64,
+
+    /// Roughly how many seconds until we finish, based on how fast we've been validating blocks
+    /// since we started. We don't know it until we validate some blocks.
+    pub eta: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+/// Keeps track of the backfill ranges while they run, so we can tell our users how far we are
+pub struct BackfillTracker {
+    /// The latest known state of each range
+    ranges: Vec<BackfillRange>,
+
+    /// Whether we've validated all blocks that we've assumed
+    done: bool,
+
+    /// When we started backfilling, and how many blocks were validated by then
+    started: Option<(Instant, u64)>,
+}
+
+impl BackfillTracker {
+    /// Starts tracking these ranges
+    pub fn start(&mut self, ranges: Vec<BackfillRange>) {
+        self.started = Some((Instant::now(), Self::validated_blocks(&ranges)));
+        self.ranges = ranges;
+    }
+
+    /// Updates the progress of the i-th range
+    pub fn update(&mut self, i: usize, height: u32, acc: Stump) -> &BackfillRange {
+        let range = &mut self.ranges[i];
+        range.height = height;
+        range.acc = acc;
+        range
+    }
+
+    /// Records that we've validated all blocks
+    pub fn finish(&mut self) {
+        self.done = true;
+        self.started = None;
+    }
+
+    /// The latest known state of each range
+    pub fn ranges(&self) -> &[BackfillRange] {
+        &self.ranges
+    }
+
+    fn validated_blocks(ranges: &[BackfillRange]) -> u64 {
+        ranges
+            .iter()
+            .map(|range| (range.height - range.start) as u64)
+            .sum()
+    }
+
+    /// Returns how far we are. `assumed` is what [has_assumed_blocks] returns for our datadir.
+    pub fn progress(&self, assumed: bool) -> BackfillProgress {
+        let validated_blocks = Self::validated_blocks(&self.ranges);
+        let total_blocks: u64 = self
+            .ranges
+            .iter()
+            .map(|range| (range.end - range.start) as u64)
+            .sum();
+
+        let progress = match (self.done, total_blocks) {
+            (true, _) => 1.0,
+            (false, 0) => 0.0,
+            (false, total) => validated_blocks as f64 / total as f64,
+        };
+
+        let eta = self.started.and_then(|(started, initial_blocks)| {
+            let elapsed = started.elapsed().as_secs_f64();
+            let blocks = validated_blocks.saturating_sub(initial_blocks);
+            if blocks == 0 || elapsed == 0.0 {
+                return None;
+            }
+
+            let remaining = total_blocks.saturating_sub(validated_blocks);
+            Some((remaining as f64 * elapsed / blocks as f64) as u64)
+        });
+
+        BackfillProgress {
+            assumed,
+            running: self.started.is_some(),
+            done: self.done,
+            ranges: self
+                .ranges
+                .iter()
+                .map(|range| BackfillRangeProgress {
+                    start: range.start,
+                    end: range.end,
+                    height: range.height,
+                })
+                .collect(),
+            validated_blocks,
+            total_blocks,
+            progress,
+            eta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::Network;
+    use rustreexo::accumulator::node_hash::BitcoinNodeHash;
+
+    use super::*;
+
+    fn acc(leaves: u64) -> Stump {
+        Stump {
+            leaves,
+            roots: vec![BitcoinNodeHash::from([leaves as u8; 32])],
+        }
+    }
+
+    /// A fake chain, where the hash of each block is its height
+    fn block_hash(height: u32) -> Option<BlockHash> {
+        let mut hash = [0; 32];
+        hash[..4].copy_from_slice(&height.to_le_bytes());
+        Some(BlockHash::from_byte_array(hash))
+    }
+
+    fn datadir() -> String {
+        format!("./tmp-db/{}/", rand::random::<u64>())
+    }
+
+    fn save(datadir: &str, range: &BackfillRange) {
+        let tip = block_hash(range.height).unwrap();
+        save_range(datadir, Network::Regtest.magic(), range, tip).unwrap();
+    }
+
+    fn load(datadir: &str) -> Result<BackfillState, BackfillError> {
+        super::load(datadir, Network::Regtest.magic(), block_hash)
+    }
+
+    #[test]
+    fn test_checkpoint_heights() {
+        assert_eq!(checkpoint_heights(1000, 4), vec![250, 500, 750]);
+        assert_eq!(checkpoint_heights(1000, 1), Vec::<u32>::new());
+        assert_eq!(checkpoint_heights(1000, 0), Vec::<u32>::new());
+        // too few blocks for this many ranges
+        assert_eq!(checkpoint_heights(2, 8), vec![1]);
+        assert_eq!(checkpoint_heights(u32::MAX, 2), vec![u32::MAX / 2]);
+    }
+
+    #[test]
+    fn test_plan() {
+        let checkpoints = [(500, acc(5)), (0, acc(0)), (250, acc(2)), (1000, acc(10))];
+        let ranges = plan(1000, acc(10), &checkpoints);
+
+        let expected = vec![
+            BackfillRange::new(0, 250, Stump::default(), acc(2)),
+            BackfillRange::new(250, 500, acc(2), acc(5)),
+            BackfillRange::new(500, 1000, acc(5), acc(10)),
+        ];
+        assert_eq!(ranges, expected);
+
+        assert_eq!(
+            plan(1000, acc(10), &[]),
+            vec![BackfillRange::new(0, 1000, Stump::default(), acc(10))]
+        );
+        assert!(plan(0, acc(10), &checkpoints).is_empty());
+    }
+
+    #[test]
+    fn test_find_invalid_range() {
+        let mut ranges = plan(1000, acc(10), &[(250, acc(2)), (500, acc(5))]);
+        assert!(find_invalid_range(&ranges).is_some());
+
+        for range in ranges.iter_mut() {
+            range.height = range.end;
+            range.acc = range.end_acc.clone();
+        }
+        assert_eq!(find_invalid_range(&ranges), None);
+
+        // the second range didn't end with the accumulator the third one assumed
+        ranges[1].acc = acc(4);
+        assert_eq!(find_invalid_range(&ranges), Some(&ranges[1]));
+
+        // the ranges don't connect to each other
+        ranges[1].acc = acc(5);
+        ranges[2].start_acc = acc(4);
+        assert_eq!(find_invalid_range(&ranges), Some(&ranges[2]));
+    }
+
+    #[test]
+    fn test_checkpoint_format() {
+        let mut range = BackfillRange::new(500, 1000, acc(5), acc(10));
+        range.height = 700;
+        range.acc = acc(7);
+
+        let magic = Network::Regtest.magic();
+        let tip = block_hash(700).unwrap();
+        let data = range.serialize(magic, tip);
+        assert_eq!(
+            BackfillRange::deserialize(&data, magic).unwrap(),
+            (range.clone(), tip)
+        );
+
+        // every truncated checkpoint is an error, not a panic
+        for len in 0..data.len() {
+            assert!(BackfillRange::deserialize(&data[..len], magic).is_err());
+        }
+
+        let corrupt = |offset: usize| {
+            let mut data = data.clone();
+            data[offset] ^= 1;
+            BackfillRange::deserialize(&data, magic).unwrap_err()
+        };
+        assert!(matches!(corrupt(0), BackfillError::InvalidMagic));
+        assert!(matches!(corrupt(4), BackfillError::UnsupportedVersion(0)));
+        assert!(matches!(corrupt(8), BackfillError::WrongNetwork(_)));
+        assert!(matches!(corrupt(12), BackfillError::InvalidChecksum));
+        assert!(matches!(corrupt(20), BackfillError::InvalidChecksum));
+
+        let signet = Network::Signet.magic();
+        assert!(matches!(
+            BackfillRange::deserialize(&data, signet),
+            Err(BackfillError::WrongNetwork(magic)) if magic == Network::Regtest.magic()
+        ));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let datadir = datadir();
+        assert_eq!(load(&datadir).unwrap(), BackfillState::NotStarted);
+
+        let mut ranges = plan(1000, acc(10), &[(500, acc(5))]);
+        ranges[1].height = 700;
+        ranges[1].acc = acc(7);
+        for range in ranges.iter() {
+            save(&datadir, range);
+        }
+        assert_eq!(
+            load(&datadir).unwrap(),
+            BackfillState::InProgress(ranges.clone())
+        );
+
+        // an interrupted write leaves the previous checkpoint
+        let dir = backfill_dir(&datadir);
+        fs::write(dir.join("0-500.tmp"), [1, 2, 3]).unwrap();
+        assert_eq!(
+            load(&datadir).unwrap(),
+            BackfillState::InProgress(ranges.clone())
+        );
+
+        // the tip of a range isn't in our chain anymore
+        let other_chain = |height| block_hash(height + 1);
+        assert!(matches!(
+            super::load(&datadir, Network::Regtest.magic(), other_chain),
+            Err(BackfillError::UnknownTip(0, _))
+        ));
+
+        // a truncated checkpoint is an error, and we can start from scratch
+        let path = dir.join(ranges[0].file_name());
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert!(load(&datadir).is_err());
+
+        reset(&datadir).unwrap();
+        assert_eq!(load(&datadir).unwrap(), BackfillState::NotStarted);
+
+        mark_done(&datadir).unwrap();
+        assert_eq!(load(&datadir).unwrap(), BackfillState::Done);
+    }
+
+    #[test]
+    fn test_legacy_state() {
+        let datadir = datadir();
+        fs::create_dir_all(&datadir).unwrap();
+
+        // an unfinished legacy state is dropped
+        let legacy_file = PathBuf::from(&datadir).join(LEGACY_STATE_FILE);
+        fs::write(&legacy_file, [1, 2, 3]).unwrap();
+        assert_eq!(load(&datadir).unwrap(), BackfillState::NotStarted);
+        assert!(!legacy_file.exists());
+
+        // an empty legacy state means we're done
+        fs::write(&legacy_file, []).unwrap();
+        assert_eq!(load(&datadir).unwrap(), BackfillState::Done);
+        assert_eq!(load(&datadir).unwrap(), BackfillState::Done);
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut tracker = BackfillTracker::default();
+        let progress = tracker.progress(true);
+        assert!(!progress.running && !progress.done);
+        assert_eq!(progress.eta, None);
+
+        let mut ranges = plan(1000, acc(10), &[(500, acc(5))]);
+        ranges[0].height = 100;
+        tracker.start(ranges);
+
+        let progress = tracker.progress(true);
+        assert!(progress.running);
+        assert_eq!(progress.validated_blocks, 100);
+        assert_eq!(progress.total_blocks, 1000);
+        assert_eq!(progress.progress, 0.1);
+        // we haven't validated anything since we started
+        assert_eq!(progress.eta, None);
+
+        tracker.update(1, 800, acc(8));
+        let progress = tracker.progress(true);
+        assert_eq!(progress.validated_blocks, 400);
+        assert_eq!(
+            progress.ranges[1],
+            BackfillRangeProgress {
+                start: 500,
+                end: 1000,
+                height: 800,
+            }
+        );
+
+        tracker.finish();
+        let progress = tracker.progress(true);
+        assert!(progress.done && !progress.running);
+        assert_eq!(progress.progress, 1.0);
+    }
+}
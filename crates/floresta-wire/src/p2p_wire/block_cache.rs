@@ -0,0 +1,402 @@
+//! A bounded, on-disk cache with our most recent blocks and their Utreexo proofs.
+//!
+//! Our chainstate doesn't keep any blocks, so without this we can't serve blocks to our peers,
+//! nor answer `getblock` without asking the network. Every block we validate is saved here,
+//! together with the proof and leaf data we've used to validate it, and we only keep the last
+//! `max_depth` blocks (and optionally, up to `max_size` bytes).
+//!
+//! Each block lives in its own file, inside the `blocks` directory in our datadir, named after
+//! its hash. A file has the block height, the [UtreexoProof] and the block itself, all
+//! consensus-encoded.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bitcoin::consensus::encode;
+use bitcoin::consensus::serialize;
+use bitcoin::consensus::Decodable;
+use bitcoin::Block;
+use bitcoin::BlockHash;
+use floresta_common::impl_error_from;
+use tracing::warn;
+
+use super::block_proof::UtreexoProof;
+
+/// The name of the directory, inside our datadir, where we keep the cached blocks
+const BLOCKS_DIR: &str = "blocks";
+
+/// How many of our most recent blocks a `NODE_NETWORK_LIMITED` node must serve (BIP159)
+pub const NETWORK_LIMITED_DEPTH: u32 = 288;
+
+#[derive(Debug)]
+/// Errors returned by the [BlockCache]
+pub enum BlockCacheError {
+    /// We couldn't read or write a block
+    Io(io::Error),
+
+    /// A cached block is corrupted
+    Decode(encode::Error),
+}
+
+impl Display for BlockCacheError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockCacheError::Io(e) => write!(f, "io error: {e}"),
+            BlockCacheError::Decode(e) => write!(f, "corrupted cached block: {e}"),
+        }
+    }
+}
+
+impl_error_from!(BlockCacheError, io::Error, Io);
+impl_error_from!(BlockCacheError, encode::Error, Decode);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where a cached block is, and how much space it takes
+struct CachedBlock {
+    /// The height of this block
+    height: u32,
+
+    /// The size of its file, in bytes
+    size: u64,
+}
+
+#[derive(Debug)]
+/// Keeps our most recent blocks on disk, with the Utreexo proof for each one
+pub struct BlockCache {
+    /// The directory where we keep the blocks
+    dir: PathBuf,
+
+    /// How many blocks, counting from our tip, we keep
+    max_depth: u32,
+
+    /// How many bytes we may use, if limited
+    max_size: Option<u64>,
+
+    /// All the blocks in our cache
+    blocks: HashMap<BlockHash, CachedBlock>,
+
+    /// The sum of the sizes of all cached blocks
+    size: u64,
+}
+
+impl BlockCache {
+    /// Opens the block cache inside `datadir`, indexing the blocks that are already there
+    pub fn new(
+        datadir: &str,
+        max_depth: u32,
+        max_size: Option<u64>,
+    ) -> Result<BlockCache, BlockCacheError> {
+        let dir = PathBuf::from(datadir).join(BLOCKS_DIR);
+        fs::create_dir_all(&dir)?;
+
+        let mut blocks = HashMap::new();
+        let mut size = 0;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let Some(block_hash) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| BlockHash::from_str(name).ok())
+            else {
+                // Leftovers from an interrupted write, or something that isn't ours
+                continue;
+            };
+
+            match Self::read_height(&path) {
+                Ok(height) => {
+                    let file_size = fs::metadata(&path)?.len();
+                    blocks.insert(
+                        block_hash,
+                        CachedBlock {
+                            height,
+                            size: file_size,
+                        },
+                    );
+                    size += file_size;
+                }
+                Err(e) => {
+                    warn!("Removing corrupted cached block {block_hash}: {e}");
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+
+        Ok(BlockCache {
+            dir,
+            max_depth,
+            max_size,
+            blocks,
+            size,
+        })
+    }
+
+    /// Saves a block we've just validated, and evicts the ones that are now too old or don't fit
+    pub fn save(
+        &mut self,
+        height: u32,
+        block: &Block,
+        proof: &UtreexoProof,
+    ) -> Result<(), BlockCacheError> {
+        let block_hash = block.block_hash();
+
+        let mut data = serialize(&height);
+        data.extend(serialize(proof));
+        data.extend(serialize(block));
+
+        let path = self.path(&block_hash);
+        let tmp_path = path.with_extension("tmp");
+
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, path)?;
+
+        let cached = CachedBlock {
+            height,
+            size: data.len() as u64,
+        };
+
+        if let Some(old) = self.blocks.insert(block_hash, cached) {
+            self.size -= old.size;
+        }
+        self.size += cached.size;
+
+        self.evict(height)
+    }
+
+    /// Returns a cached block, if we have it
+    pub fn get_block(&self, block_hash: &BlockHash) -> Result<Option<Block>, BlockCacheError> {
+        Ok(self.get(block_hash)?.map(|(block, _)| block))
+    }
+
+    /// Returns the Utreexo proof for a cached block, if we have it
+    pub fn get_proof(
+        &self,
+        block_hash: &BlockHash,
+    ) -> Result<Option<UtreexoProof>, BlockCacheError> {
+        Ok(self.get(block_hash)?.map(|(_, proof)| proof))
+    }
+
+    /// Returns a cached block and its proof, if we have it
+    pub fn get(
+        &self,
+        block_hash: &BlockHash,
+    ) -> Result<Option<(Block, UtreexoProof)>, BlockCacheError> {
+        if !self.blocks.contains_key(block_hash) {
+            return Ok(None);
+        }
+
+        let data = fs::read(self.path(block_hash))?;
+        let mut reader = Cursor::new(data);
+
+        let _height = u32::consensus_decode(&mut reader)?;
+        let proof = UtreexoProof::consensus_decode(&mut reader)?;
+        let block = Block::consensus_decode(&mut reader)?;
+
+        Ok(Some((block, proof)))
+    }
+
+    /// How many blocks we have cached
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether our cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// How many bytes our cached blocks take
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Whether we always keep the last [NETWORK_LIMITED_DEPTH] blocks, as promised by
+    /// `NODE_NETWORK_LIMITED`. A size limit may evict them, so we can't have one.
+    pub fn is_network_limited(&self) -> bool {
+        self.max_depth >= NETWORK_LIMITED_DEPTH && self.max_size.is_none()
+    }
+
+    /// Removes the blocks that are deeper than `max_depth` from `tip`, then the oldest ones until
+    /// we fit inside `max_size`
+    fn evict(&mut self, tip: u32) -> Result<(), BlockCacheError> {
+        let min_height = (tip + 1).saturating_sub(self.max_depth);
+
+        let mut by_height = self
+            .blocks
+            .iter()
+            .map(|(hash, cached)| (cached.height, *hash))
+            .collect::<Vec<_>>();
+        by_height.sort_unstable();
+
+        let max_size = self.max_size.unwrap_or(u64::MAX);
+        for (height, block_hash) in by_height {
+            if height >= min_height && self.size <= max_size {
+                break;
+            }
+
+            self.remove(&block_hash)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes a block from our cache
+    fn remove(&mut self, block_hash: &BlockHash) -> Result<(), BlockCacheError> {
+        let Some(cached) = self.blocks.remove(block_hash) else {
+            return Ok(());
+        };
+
+        self.size -= cached.size;
+        match fs::remove_file(self.path(block_hash)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads only the height of a cached block
+    fn read_height(path: &PathBuf) -> Result<u32, BlockCacheError> {
+        let mut height = [0; 4];
+        fs::File::open(path)?.read_exact(&mut height)?;
+
+        Ok(u32::from_le_bytes(height))
+    }
+
+    /// The file where we keep a block
+    fn path(&self, block_hash: &BlockHash) -> PathBuf {
+        self.dir.join(block_hash.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::block::Header;
+    use bitcoin::block::Version;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Block;
+    use bitcoin::BlockHash;
+    use bitcoin::CompactTarget;
+    use bitcoin::TxMerkleNode;
+    use rustreexo::accumulator::node_hash::BitcoinNodeHash;
+
+    use super::BlockCache;
+    use super::NETWORK_LIMITED_DEPTH;
+    use crate::block_proof::UtreexoProof;
+
+    fn datadir() -> String {
+        format!("./tmp-db/{}.block_cache", rand::random::<u32>())
+    }
+
+    fn block(nonce: u32) -> Block {
+        Block {
+            header: Header {
+                version: Version::ONE,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce,
+            },
+            txdata: Vec::new(),
+        }
+    }
+
+    fn proof(block: &Block) -> UtreexoProof {
+        UtreexoProof {
+            block_hash: block.block_hash(),
+            proof_hashes: vec![BitcoinNodeHash::from([1; 32])],
+            targets: vec![42],
+            leaf_data: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_save_and_get() {
+        let datadir = datadir();
+        let mut cache = BlockCache::new(&datadir, 10, None).unwrap();
+
+        let block = block(1);
+        cache.save(1, &block, &proof(&block)).unwrap();
+
+        assert_eq!(
+            cache.get_block(&block.block_hash()).unwrap(),
+            Some(block.clone())
+        );
+        assert_eq!(
+            cache.get_proof(&block.block_hash()).unwrap(),
+            Some(proof(&block))
+        );
+        assert_eq!(cache.get_block(&BlockHash::all_zeros()).unwrap(), None);
+
+        // Re-open the cache, it should find the block we've saved
+        let size = cache.size();
+        let cache = BlockCache::new(&datadir, 10, None).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.size(), size);
+        assert_eq!(cache.get_block(&block.block_hash()).unwrap(), Some(block));
+    }
+
+    #[test]
+    fn test_evict_by_depth() {
+        let mut cache = BlockCache::new(&datadir(), 3, None).unwrap();
+
+        let blocks = (0..5).map(block).collect::<Vec<_>>();
+        for (height, block) in blocks.iter().enumerate() {
+            cache.save(height as u32, block, &proof(block)).unwrap();
+        }
+
+        // Only the last 3 blocks are kept
+        assert_eq!(cache.len(), 3);
+        for block in &blocks[..2] {
+            assert_eq!(cache.get_block(&block.block_hash()).unwrap(), None);
+        }
+        for block in &blocks[2..] {
+            assert!(cache.get_block(&block.block_hash()).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn test_evict_by_size() {
+        let datadir = datadir();
+        let mut cache = BlockCache::new(&datadir, 100, None).unwrap();
+
+        let first = block(0);
+        cache.save(0, &first, &proof(&first)).unwrap();
+        let block_size = cache.size();
+
+        // Room for two blocks, all our blocks have the same size
+        let mut cache = BlockCache::new(&datadir, 100, Some(2 * block_size)).unwrap();
+        for height in 1..4 {
+            let block = block(height);
+            cache.save(height, &block, &proof(&block)).unwrap();
+        }
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.size(), 2 * block_size);
+        assert_eq!(cache.get_block(&first.block_hash()).unwrap(), None);
+        assert!(cache.get_block(&block(3).block_hash()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_is_network_limited() {
+        let datadir = datadir();
+        let limited = |depth, size| {
+            BlockCache::new(&datadir, depth, size)
+                .unwrap()
+                .is_network_limited()
+        };
+
+        assert!(limited(NETWORK_LIMITED_DEPTH, None));
+        assert!(!limited(NETWORK_LIMITED_DEPTH - 1, None));
+        assert!(!limited(NETWORK_LIMITED_DEPTH, Some(u64::MAX)));
+    }
+}
@@ -23,6 +23,7 @@
 use bitcoin::consensus::Decodable;
 use bitcoin::consensus::Encodable;
 use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
 use bitcoin::BlockHash;
 use bitcoin::VarInt;
 use floresta_chain::CompactLeafData;
@@ -93,6 +94,13 @@ impl Bitmap {
     pub fn is_empty(&self) -> bool {
         self.n_inputs == 0
     }
+
+    /// Returns whether the input at `index` was requested.
+    pub fn is_requested(&self, index: usize) -> bool {
+        self.bytes
+            .get(index / u8::BITS as usize)
+            .is_some_and(|byte| byte & (1 << (index % u8::BITS as usize)) != 0)
+    }
 }
 
 impl Encodable for Bitmap {
@@ -106,6 +114,23 @@ impl Encodable for Bitmap {
     }
 }
 
+impl Decodable for Bitmap {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        let len = read_bounded_len(reader, MAX_INPUTS_PER_BLOCK.div_ceil(u8::BITS as usize))?;
+        let mut bytes = vec![0; len];
+        reader.read_exact(&mut bytes)?;
+
+        // The number of inputs isn't serialized, so we assume the last byte is full
+        Ok(Bitmap {
+            n_inputs: len as u32 * u8::BITS,
+            bytes,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 /// Represents a Utreexo proof request, for a specific block.
 pub struct GetUtreexoProof {
     /// The block hash for which the proof is requested.
@@ -136,7 +161,20 @@ impl Encodable for GetUtreexoProof {
     }
 }
 
-#[derive(Debug, Clone)]
+impl Decodable for GetUtreexoProof {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        Ok(GetUtreexoProof {
+            block_hash: BlockHash::consensus_decode(reader)?,
+            include_leaves: bool::consensus_decode(reader)?,
+            proof_hashes_bitmap: Bitmap::consensus_decode(reader)?,
+            leaf_index_bitmap: Bitmap::consensus_decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 /// Represents a Utreexo proof for a specific block.
 ///
 /// This message will be sent in response to a [GetUtreexoProof] request.
@@ -158,6 +196,34 @@ pub struct UtreexoProof {
     pub leaf_data: Vec<CompactLeafData>,
 }
 
+impl Encodable for UtreexoProof {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        let mut len = self.block_hash.consensus_encode(writer)?;
+
+        len += VarInt::from(self.proof_hashes.len()).consensus_encode(writer)?;
+        for hash in self.proof_hashes.iter() {
+            len += sha256::Hash::from_byte_array(**hash).consensus_encode(writer)?;
+        }
+
+        len += VarInt::from(self.targets.len()).consensus_encode(writer)?;
+        for target in self.targets.iter() {
+            len += VarInt(*target).consensus_encode(writer)?;
+        }
+
+        len += VarInt::from(self.leaf_data.len()).consensus_encode(writer)?;
+        for leaf in self.leaf_data.iter() {
+            len += leaf.header_code.consensus_encode(writer)?;
+            len += leaf.amount.consensus_encode(writer)?;
+            len += leaf.spk_ty.consensus_encode(writer)?;
+        }
+
+        Ok(len)
+    }
+}
+
 impl Decodable for UtreexoProof {
     fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
         reader: &mut R,
@@ -217,6 +283,7 @@ mod utreexo_proof_tests {
     use floresta_chain::AssumeValidArg;
     use floresta_chain::BlockchainError;
     use floresta_chain::ChainState;
+    use floresta_chain::DeploymentRules;
     use floresta_chain::FlatChainStore;
     use floresta_chain::FlatChainStoreConfig;
     use floresta_common::acchashes;
@@ -301,6 +368,8 @@ mod utreexo_proof_tests {
             )),
             _ => Err(BlockchainError::BlockNotPresent),
         };
+        // We don't have the headers to compute the MTPs, and this block has no relative lock times
+        let get_mtp = |_| Ok(0);
 
         let state = setup_test_chain(Network::Bitcoin, AssumeValidArg::Disabled);
         let acc = Stump {
@@ -320,8 +389,14 @@ mod utreexo_proof_tests {
         };
 
         // STEP 1: Verify the accumulator and the block
-        let (del_hashes, utxos) =
-            process_proof(&proof.leaf_data, &block.txdata, height, get_block_hash).unwrap();
+        let (del_hashes, utxos) = process_proof(
+            &proof.leaf_data,
+            &block.txdata,
+            height,
+            get_block_hash,
+            get_mtp,
+        )
+        .unwrap();
         let r_proof = Proof {
             targets: proof.targets,
             hashes: proof.proof_hashes,
@@ -332,7 +407,13 @@ mod utreexo_proof_tests {
         }
 
         state
-            .validate_block_no_acc(&block, height, utxos)
+            .validate_block_no_acc(
+                &block,
+                height,
+                block.header.time,
+                &DeploymentRules::default(),
+                utxos,
+            )
             .expect("Block validation must pass for the given UTXOs map");
 
         // STEP 2: Add a tx that tries to spend an UTXO created later in the block; utreexo fails
@@ -345,8 +426,14 @@ mod utreexo_proof_tests {
         let mut invalid_txdata = block.txdata.clone();
         invalid_txdata.insert(1, spending_tx);
 
-        let (del_hashes, _utxos) =
-            process_proof(&proof.leaf_data, &invalid_txdata, height, get_block_hash).unwrap();
+        let (del_hashes, _utxos) = process_proof(
+            &proof.leaf_data,
+            &invalid_txdata,
+            height,
+            get_block_hash,
+            get_mtp,
+        )
+        .unwrap();
 
         if acc.verify(&r_proof, &to_acc_hashes(del_hashes)).unwrap() {
             panic!("Proof must be invalid")
@@ -0,0 +1,1172 @@
+//! A Utreexo bridge keeps the whole forest, instead of only its roots. This lets it generate
+//! proofs for any UTXO, so it can validate blocks without asking peers for proofs, and serve
+//! those proofs to other utreexo nodes.
+//!
+//! Besides the forest, the bridge needs the data committed in each leaf, so it keeps the whole
+//! UTXO set in memory. This is only practical for small chains, like a regtest or a custom signet.
+//!
+//! Everything is saved inside the `bridge` directory in our datadir:
+//!   - `state`: a snapshot of our tip, the forest and the leaf data of every UTXO. Writing it
+//!     is expensive, so we only move it forward every [STATE_INTERVAL] blocks.
+//!   - `blocks`: the UTXOs spent and created by each block after `state`, as an append-only
+//!     list of `<u32 length><serialized changes>` records. When loading, we replay them on top
+//!     of `state`.
+//!   - `proofs`: the proof we've generated for each block, so we can serve them later. It's
+//!     an append-only list of `<u32 length><serialized UtreexoProof>` records. Our peers may ask
+//!     for the proof of any block, so it's never pruned, and grows with the chain (as does the
+//!     index of it we keep in memory). Proofs for blocks that were reorged out are kept too.
+//!
+//! Since the forest isn't `Send`, the [Bridge] lives in its own thread, and the node talks to it
+//! using a [BridgeHandle].
+//!
+//! A bridge can only connect blocks extending its tip. On a reorg, [Bridge::rollback] rebuilds
+//! the forest from `state` and the blocks we're keeping, so we can roll back as long as the fork
+//! point isn't before `state`. We always keep at least [STATE_INTERVAL] blocks after it.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+use bitcoin::consensus::deserialize;
+use bitcoin::consensus::encode;
+use bitcoin::consensus::serialize;
+use bitcoin::consensus::Decodable;
+use bitcoin::Block;
+use bitcoin::BlockHash;
+use bitcoin::OutPoint;
+use floresta_chain::proof_util;
+use floresta_chain::CompactLeafData;
+use floresta_chain::LeafData;
+use floresta_common::impl_error_from;
+use rustreexo::accumulator::mem_forest::MemForest;
+use rustreexo::accumulator::node_hash::BitcoinNodeHash;
+use rustreexo::accumulator::proof::Proof;
+
+use super::block_proof::GetUtreexoProof;
+use super::block_proof::UtreexoProof;
+
+/// The name of the directory, inside our datadir, where we keep the bridge data
+const BRIDGE_DIR: &str = "bridge";
+
+/// The file with our tip, forest and leaf data
+const STATE_FILE: &str = "state";
+
+/// The file with the changes made by each block after our state
+const BLOCKS_FILE: &str = "blocks";
+
+/// The file with the proofs for each block
+const PROOFS_FILE: &str = "proofs";
+
+/// How many blocks we connect before moving our state forward. Since we keep between one and two
+/// intervals of blocks after it, we can always roll back at least this many blocks.
+pub const STATE_INTERVAL: u32 = 100;
+
+#[derive(Debug)]
+/// Errors returned by the [Bridge]
+pub enum BridgeError {
+    /// We couldn't read or write our data
+    Io(io::Error),
+
+    /// Our saved data is corrupted
+    Decode(encode::Error),
+
+    /// Our forest returned an error
+    Accumulator(String),
+
+    /// A block spends an output that isn't in our UTXO set
+    UtxoNotFound(OutPoint),
+
+    /// We can only connect blocks that extend our tip
+    BlockDoesntExtendTip(BlockHash),
+
+    /// The bridge thread stopped
+    Stopped,
+
+    /// Our forest isn't at the same height as our chain, as `(forest, chain)` heights
+    OutOfSync(u32, u32),
+
+    /// We can't roll back to this height, since it's before our saved state
+    ReorgTooDeep(u32),
+}
+
+impl Display for BridgeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BridgeError::Io(e) => write!(f, "io error: {e}"),
+            BridgeError::Decode(e) => write!(f, "corrupted bridge data: {e}"),
+            BridgeError::Accumulator(e) => write!(f, "accumulator error: {e}"),
+            BridgeError::UtxoNotFound(outpoint) => write!(f, "utxo {outpoint} not found"),
+            BridgeError::BlockDoesntExtendTip(block) => {
+                write!(f, "block {block} doesn't extend the bridge tip")
+            }
+            BridgeError::Stopped => write!(f, "the bridge thread stopped"),
+            BridgeError::OutOfSync(bridge, chain) => write!(
+                f,
+                "the bridge is at height {bridge}, but our chain was validated up to {chain}"
+            ),
+            BridgeError::ReorgTooDeep(height) => write!(
+                f,
+                "can't roll the bridge back to height {height}, it's before our saved state"
+            ),
+        }
+    }
+}
+
+impl_error_from!(BridgeError, io::Error, Io);
+impl_error_from!(BridgeError, encode::Error, Decode);
+impl_error_from!(BridgeError, String, Accumulator);
+
+#[derive(Debug, Clone, PartialEq)]
+/// The UTXOs spent and created by a block we've connected, so we can connect it again when
+/// rebuilding our forest
+struct BlockChanges {
+    /// The height of this block
+    height: u32,
+
+    /// The hash of this block
+    block_hash: BlockHash,
+
+    /// The UTXOs spent by this block, in the order they are deleted from the forest
+    spent: Vec<OutPoint>,
+
+    /// The UTXOs created by this block, in the order they are added to the forest
+    created: Vec<LeafData>,
+}
+
+impl BlockChanges {
+    fn serialize(&self) -> Vec<u8> {
+        let mut data = serialize(&self.height);
+        data.extend(serialize(&self.block_hash));
+        data.extend(serialize(&(self.spent.len() as u64)));
+        for prevout in self.spent.iter() {
+            data.extend(serialize(prevout));
+        }
+
+        data.extend(serialize(&(self.created.len() as u64)));
+        for leaf in self.created.iter() {
+            serialize_leaf(leaf, &mut data);
+        }
+
+        data
+    }
+
+    fn deserialize(data: &[u8]) -> Result<BlockChanges, BridgeError> {
+        let mut reader = Cursor::new(data);
+        let height = u32::consensus_decode(&mut reader)?;
+        let block_hash = BlockHash::consensus_decode(&mut reader)?;
+
+        let n_spent = u64::consensus_decode(&mut reader)?;
+        let spent = (0..n_spent)
+            .map(|_| OutPoint::consensus_decode(&mut reader))
+            .collect::<Result<_, _>>()?;
+
+        let n_created = u64::consensus_decode(&mut reader)?;
+        let created = (0..n_created)
+            .map(|_| LeafData::consensus_decode(&mut reader))
+            .collect::<Result<_, _>>()?;
+
+        Ok(BlockChanges {
+            height,
+            block_hash,
+            spent,
+            created,
+        })
+    }
+}
+
+/// Appends the fields of `leaf` to `data`, in the order [LeafData] is decoded
+fn serialize_leaf(leaf: &LeafData, data: &mut Vec<u8>) {
+    data.extend(serialize(&leaf.block_hash));
+    data.extend(serialize(&leaf.prevout));
+    data.extend(serialize(&leaf.header_code));
+    data.extend(serialize(&leaf.utxo));
+}
+
+/// Applies the changes made by a block to `forest` and `leaves`
+fn apply_changes(
+    forest: &mut MemForest<BitcoinNodeHash>,
+    leaves: &mut HashMap<OutPoint, LeafData>,
+    changes: &BlockChanges,
+) -> Result<(), BridgeError> {
+    let del_hashes: Vec<BitcoinNodeHash> = changes
+        .spent
+        .iter()
+        .map(|prevout| {
+            leaves
+                .get(prevout)
+                .map(|leaf| leaf._get_leaf_hashes().into())
+                .ok_or(BridgeError::UtxoNotFound(*prevout))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let add_hashes: Vec<BitcoinNodeHash> = changes
+        .created
+        .iter()
+        .map(|leaf| leaf._get_leaf_hashes().into())
+        .collect();
+
+    forest.modify(&add_hashes, &del_hashes)?;
+    for prevout in changes.spent.iter() {
+        leaves.remove(prevout);
+    }
+
+    leaves.extend(
+        changes
+            .created
+            .iter()
+            .map(|leaf| (leaf.prevout, leaf.clone())),
+    );
+
+    Ok(())
+}
+
+/// Keeps the whole Utreexo forest, and the data of every leaf in it
+pub struct Bridge {
+    /// The forest, with all UTXOs up to our tip
+    forest: MemForest<BitcoinNodeHash>,
+
+    /// The data committed in each leaf of our forest
+    leaves: HashMap<OutPoint, LeafData>,
+
+    /// The height of the last block we've connected
+    height: u32,
+
+    /// The hash of the last block we've connected
+    tip: BlockHash,
+
+    /// The height and hash of the tip in our state file
+    saved_tip: (u32, BlockHash),
+
+    /// The changes made by each block after our state file, in order
+    changes: Vec<BlockChanges>,
+
+    /// The directory where we save our data
+    dir: PathBuf,
+
+    /// The file where we append the changes made by each block
+    blocks: File,
+
+    /// The file where we append the proof of each block
+    proofs: File,
+
+    /// Where each proof is inside `proofs`, as `(offset, length)`. Like the file, this is never
+    /// pruned
+    proof_index: HashMap<BlockHash, (u64, u32)>,
+}
+
+impl Bridge {
+    /// Loads the bridge saved in `datadir`, or creates an empty one at `genesis` if there's none
+    pub fn load(datadir: &str, genesis: BlockHash) -> Result<Bridge, BridgeError> {
+        let dir = PathBuf::from(datadir).join(BRIDGE_DIR);
+        fs::create_dir_all(&dir)?;
+
+        let (height, tip, forest, leaves) = Self::read_state(&dir, (0, genesis))?;
+
+        let mut blocks = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(BLOCKS_FILE))?;
+
+        let changes = Self::read_changes(&mut blocks)?;
+
+        let mut proofs = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(PROOFS_FILE))?;
+
+        let proof_index = Self::index_proofs(&mut proofs)?;
+
+        let mut bridge = Bridge {
+            forest,
+            leaves,
+            height,
+            tip,
+            saved_tip: (height, tip),
+            changes: Vec::new(),
+            dir,
+            blocks,
+            proofs,
+            proof_index,
+        };
+
+        // If we've crashed after moving our state forward, but before removing the blocks it
+        // already has from our blocks file, we just skip them here
+        let n_changes = changes.len();
+        for changes in changes
+            .into_iter()
+            .filter(|changes| changes.height > height)
+        {
+            if changes.height != bridge.height + 1 {
+                return Err(encode::Error::ParseFailed("missing blocks in the bridge").into());
+            }
+
+            bridge.apply(&changes)?;
+            bridge.changes.push(changes);
+        }
+
+        if bridge.changes.len() != n_changes {
+            bridge.rewrite_changes()?;
+        }
+
+        Ok(bridge)
+    }
+
+    /// Returns the height and hash of the last block we've connected
+    pub fn tip(&self) -> (u32, BlockHash) {
+        (self.height, self.tip)
+    }
+
+    /// Returns the hash of the block we've connected at `height`, if we can still roll back to it
+    pub fn block_hash(&self, height: u32) -> Option<BlockHash> {
+        if height == self.saved_tip.0 {
+            return Some(self.saved_tip.1);
+        }
+
+        let index = height.checked_sub(self.saved_tip.0 + 1)?;
+        self.changes
+            .get(index as usize)
+            .map(|changes| changes.block_hash)
+    }
+
+    /// Rolls our forest back to the block we've connected at `height`, usually the fork point of
+    /// a reorg. We rebuild it from our state file and the blocks after it, so `height` can't be
+    /// before our saved state.
+    pub fn rollback(&mut self, height: u32) -> Result<(), BridgeError> {
+        if height >= self.height {
+            return Ok(());
+        }
+
+        if height < self.saved_tip.0 {
+            return Err(BridgeError::ReorgTooDeep(height));
+        }
+
+        let (saved_height, saved_tip, forest, leaves) =
+            Self::read_state(&self.dir, self.saved_tip)?;
+        self.forest = forest;
+        self.leaves = leaves;
+        self.height = saved_height;
+        self.tip = saved_tip;
+
+        let mut changes = std::mem::take(&mut self.changes);
+        changes.truncate((height - saved_height) as usize);
+        for changes in changes.iter() {
+            self.apply(changes)?;
+        }
+
+        self.changes = changes;
+        self.rewrite_changes()
+    }
+
+    /// Returns the leaf data of the UTXOs spent by `block`, in the order they are spent.
+    ///
+    /// Just like in [proof_util::process_proof], outputs created in this block by an earlier
+    /// transaction don't need a proof.
+    fn spent_leaves(&self, block: &Block) -> Result<Vec<&LeafData>, BridgeError> {
+        let mut created = HashSet::new();
+        let mut spent = Vec::new();
+
+        for tx in block.txdata.iter().skip(1) {
+            let txid = tx.compute_txid();
+            created.extend((0..tx.output.len()).map(|vout| OutPoint::new(txid, vout as u32)));
+
+            for input in tx.input.iter() {
+                if created.contains(&input.previous_output) {
+                    continue;
+                }
+
+                let leaf = self
+                    .leaves
+                    .get(&input.previous_output)
+                    .ok_or(BridgeError::UtxoNotFound(input.previous_output))?;
+
+                spent.push(leaf);
+            }
+        }
+
+        Ok(spent)
+    }
+
+    /// Generates the proof and leaf data for the UTXOs spent by `block`, which must extend our
+    /// tip. This doesn't change our state, see [Bridge::connect_block] for that.
+    pub fn prove_block(&self, block: &Block) -> Result<(Proof, Vec<CompactLeafData>), BridgeError> {
+        if block.header.prev_blockhash != self.tip {
+            return Err(BridgeError::BlockDoesntExtendTip(block.block_hash()));
+        }
+
+        let spent = self.spent_leaves(block)?;
+        let del_hashes: Vec<BitcoinNodeHash> = spent
+            .iter()
+            .map(|leaf| leaf._get_leaf_hashes().into())
+            .collect();
+
+        let proof = self.forest.prove(&del_hashes)?;
+        let leaf_data = spent
+            .iter()
+            .map(|leaf| CompactLeafData {
+                header_code: leaf.header_code,
+                amount: leaf.utxo.value.to_sat(),
+                spk_ty: proof_util::get_script_type(&leaf.utxo.script_pubkey),
+            })
+            .collect();
+
+        Ok((proof, leaf_data))
+    }
+
+    /// Returns the leaves created by `block`, in the same order as
+    /// [proof_util::get_block_adds]: every spendable output, unless it's spent in this block.
+    fn created_leaves(block: &Block, height: u32) -> Vec<LeafData> {
+        let spent: HashSet<_> = block
+            .txdata
+            .iter()
+            .flat_map(|tx| tx.input.iter().map(|input| input.previous_output))
+            .collect();
+
+        let block_hash = block.block_hash();
+        let mut leaves = Vec::new();
+        for tx in block.txdata.iter() {
+            let txid = tx.compute_txid();
+            let header_code = (height << 1) | tx.is_coinbase() as u32;
+
+            for (vout, output) in tx.output.iter().enumerate() {
+                let prevout = OutPoint::new(txid, vout as u32);
+                if proof_util::is_unspendable(&output.script_pubkey) || spent.contains(&prevout) {
+                    continue;
+                }
+
+                leaves.push(LeafData {
+                    block_hash,
+                    prevout,
+                    header_code,
+                    utxo: output.clone(),
+                });
+            }
+        }
+
+        leaves
+    }
+
+    /// Connects a block that extends our tip, saving its proof so we can serve it later. This
+    /// should only be called after the block is validated.
+    pub fn connect_block(&mut self, block: &Block) -> Result<(), BridgeError> {
+        let (proof, leaf_data) = self.prove_block(block)?;
+        let block_hash = block.block_hash();
+        let height = self.height + 1;
+
+        let changes = BlockChanges {
+            height,
+            block_hash,
+            spent: self
+                .spent_leaves(block)?
+                .iter()
+                .map(|leaf| leaf.prevout)
+                .collect(),
+            created: Self::created_leaves(block, height),
+        };
+
+        self.apply(&changes)?;
+
+        let proof = UtreexoProof {
+            block_hash,
+            proof_hashes: proof.hashes,
+            targets: proof.targets,
+            leaf_data,
+        };
+
+        self.save_proof(&proof)?;
+        self.save_changes(changes)
+    }
+
+    /// Applies the changes made by the block after our tip
+    fn apply(&mut self, changes: &BlockChanges) -> Result<(), BridgeError> {
+        apply_changes(&mut self.forest, &mut self.leaves, changes)?;
+        self.height = changes.height;
+        self.tip = changes.block_hash;
+
+        Ok(())
+    }
+
+    /// Appends the changes made by a block to our blocks file, and moves our state forward once
+    /// we have two intervals of blocks after it
+    fn save_changes(&mut self, changes: BlockChanges) -> Result<(), BridgeError> {
+        let data = changes.serialize();
+        self.blocks.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.blocks.write_all(&data)?;
+        self.blocks.sync_data()?;
+
+        self.changes.push(changes);
+        if self.changes.len() < 2 * STATE_INTERVAL as usize {
+            return Ok(());
+        }
+
+        // We rebuild the forest at the end of the first interval, and save it as our new state.
+        // The blocks in the second interval are kept, so we can still roll them back.
+        let (_, _, mut forest, mut leaves) = Self::read_state(&self.dir, self.saved_tip)?;
+        let (saved, kept) = self.changes.split_at(STATE_INTERVAL as usize);
+        for changes in saved.iter() {
+            apply_changes(&mut forest, &mut leaves, changes)?;
+        }
+
+        let last = saved.last().expect("STATE_INTERVAL isn't zero");
+        let saved_tip = (last.height, last.block_hash);
+        Self::save_state(&self.dir, saved_tip, &forest, &leaves)?;
+
+        self.saved_tip = saved_tip;
+        self.changes = kept.to_vec();
+        self.rewrite_changes()
+    }
+
+    /// Reads every record in the blocks file. If the last record is incomplete, probably because
+    /// we've crashed while writing it, it's removed.
+    fn read_changes(blocks: &mut File) -> Result<Vec<BlockChanges>, BridgeError> {
+        let mut data = Vec::new();
+        blocks.seek(SeekFrom::Start(0))?;
+        blocks.read_to_end(&mut data)?;
+
+        let mut changes = Vec::new();
+        let mut offset = 0;
+        while let Some(len) = data.get(offset..offset + 4) {
+            let len = u32::from_le_bytes(len.try_into().expect("we took 4 bytes"));
+            let start = offset + 4;
+            let Some(record) = data.get(start..start + len as usize) else {
+                break;
+            };
+
+            changes.push(BlockChanges::deserialize(record)?);
+            offset = start + len as usize;
+        }
+
+        blocks.set_len(offset as u64)?;
+        Ok(changes)
+    }
+
+    /// Replaces our blocks file with the changes we're keeping in memory, without ever leaving a
+    /// partially written file there
+    fn rewrite_changes(&mut self) -> Result<(), BridgeError> {
+        let mut data = Vec::new();
+        for changes in self.changes.iter() {
+            let record = changes.serialize();
+            data.extend((record.len() as u32).to_le_bytes());
+            data.extend(record);
+        }
+
+        let path = self.dir.join(BLOCKS_FILE);
+        let tmp_path = path.with_extension("tmp");
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, &path)?;
+
+        self.blocks = OpenOptions::new().read(true).append(true).open(path)?;
+        Ok(())
+    }
+
+    /// Returns the proof we've generated for a block, if we've connected it
+    pub fn get_proof(
+        &mut self,
+        block_hash: BlockHash,
+    ) -> Result<Option<UtreexoProof>, BridgeError> {
+        let Some((offset, len)) = self.proof_index.get(&block_hash).copied() else {
+            return Ok(None);
+        };
+
+        let mut data = vec![0; len as usize];
+        self.proofs.seek(SeekFrom::Start(offset))?;
+        self.proofs.read_exact(&mut data)?;
+
+        Ok(Some(deserialize(&data)?))
+    }
+
+    /// Appends a proof to our proofs file
+    fn save_proof(&mut self, proof: &UtreexoProof) -> Result<(), BridgeError> {
+        let data = serialize(proof);
+        let offset = self.proofs.seek(SeekFrom::End(0))? + 4;
+
+        self.proofs.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.proofs.write_all(&data)?;
+        self.proofs.sync_data()?;
+
+        self.proof_index
+            .insert(proof.block_hash, (offset, data.len() as u32));
+
+        Ok(())
+    }
+
+    /// Reads every record in the proofs file, and returns where each one is. If the last record
+    /// is incomplete, probably because we've crashed while writing it, it's removed.
+    fn index_proofs(proofs: &mut File) -> Result<HashMap<BlockHash, (u64, u32)>, BridgeError> {
+        let mut data = Vec::new();
+        proofs.seek(SeekFrom::Start(0))?;
+        proofs.read_to_end(&mut data)?;
+
+        let mut index = HashMap::new();
+        let mut offset = 0;
+        while let Some(len) = data.get(offset..offset + 4) {
+            let len = u32::from_le_bytes(len.try_into().expect("we took 4 bytes"));
+            let start = offset + 4;
+            let Some(record) = data.get(start..start + len as usize) else {
+                break;
+            };
+
+            let block_hash = BlockHash::consensus_decode(&mut &record[..])?;
+            index.insert(block_hash, (start as u64, len));
+            offset = start + len as usize;
+        }
+
+        proofs.set_len(offset as u64)?;
+        Ok(index)
+    }
+
+    /// Writes a tip, forest and leaves to the state file in `dir`, without ever leaving a
+    /// partially written file there
+    fn save_state(
+        dir: &Path,
+        (height, tip): (u32, BlockHash),
+        forest: &MemForest<BitcoinNodeHash>,
+        leaves: &HashMap<OutPoint, LeafData>,
+    ) -> Result<(), BridgeError> {
+        let mut data = serialize(&height);
+        data.extend(serialize(&tip));
+        forest.serialize(&mut data)?;
+
+        data.extend(serialize(&(leaves.len() as u64)));
+        for leaf in leaves.values() {
+            serialize_leaf(leaf, &mut data);
+        }
+
+        let path = dir.join(STATE_FILE);
+        let tmp_path = path.with_extension("tmp");
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+
+        Ok(fs::rename(tmp_path, path)?)
+    }
+
+    /// Reads the state file in `dir`. If we haven't written one yet, returns an empty forest at
+    /// `genesis`.
+    #[allow(clippy::type_complexity)]
+    fn read_state(
+        dir: &Path,
+        genesis: (u32, BlockHash),
+    ) -> Result<
+        (
+            u32,
+            BlockHash,
+            MemForest<BitcoinNodeHash>,
+            HashMap<OutPoint, LeafData>,
+        ),
+        BridgeError,
+    > {
+        match fs::read(dir.join(STATE_FILE)) {
+            Ok(state) => Self::decode_state(&state),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Ok((genesis.0, genesis.1, MemForest::new(), HashMap::new()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Decodes the data written by [Bridge::save_state]
+    #[allow(clippy::type_complexity)]
+    fn decode_state(
+        data: &[u8],
+    ) -> Result<
+        (
+            u32,
+            BlockHash,
+            MemForest<BitcoinNodeHash>,
+            HashMap<OutPoint, LeafData>,
+        ),
+        BridgeError,
+    > {
+        let mut reader = Cursor::new(data);
+        let height = u32::consensus_decode(&mut reader)?;
+        let tip = BlockHash::consensus_decode(&mut reader)?;
+        let forest = MemForest::deserialize(&mut reader)?;
+
+        let n_leaves = u64::consensus_decode(&mut reader)?;
+        let mut leaves = HashMap::new();
+        for _ in 0..n_leaves {
+            let leaf = LeafData::consensus_decode(&mut reader)?;
+            leaves.insert(leaf.prevout, leaf);
+        }
+
+        Ok((height, tip, forest, leaves))
+    }
+}
+
+/// Builds our reply to a [GetUtreexoProof] request, given the full proof for that block.
+///
+/// An empty bitmap means our peer wants everything, otherwise we only send the proof hashes,
+/// targets and leaf data they've asked for.
+pub fn select_proof(proof: UtreexoProof, request: &GetUtreexoProof) -> UtreexoProof {
+    fn select<T>(items: Vec<T>, bitmap: &super::block_proof::Bitmap) -> Vec<T> {
+        if bitmap.is_empty() {
+            return items;
+        }
+
+        items
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| bitmap.is_requested(*i))
+            .map(|(_, item)| item)
+            .collect()
+    }
+
+    let leaf_data = match request.include_leaves {
+        true => select(proof.leaf_data, &request.leaf_index_bitmap),
+        false => Vec::new(),
+    };
+
+    UtreexoProof {
+        block_hash: proof.block_hash,
+        proof_hashes: select(proof.proof_hashes, &request.proof_hashes_bitmap),
+        targets: select(proof.targets, &request.leaf_index_bitmap),
+        leaf_data,
+    }
+}
+
+/// A job that runs in the bridge thread
+type BridgeJob = Box<dyn FnOnce(&mut Bridge) + Send>;
+
+#[derive(Debug, Clone)]
+/// A handle to a [Bridge] running in its own thread
+pub struct BridgeHandle {
+    jobs: mpsc::Sender<BridgeJob>,
+}
+
+impl BridgeHandle {
+    /// Loads the bridge saved in `datadir` (see [Bridge::load]), and starts its thread
+    pub fn start(datadir: &str, genesis: BlockHash) -> Result<BridgeHandle, BridgeError> {
+        let (jobs, jobs_rx) = mpsc::channel::<BridgeJob>();
+        let (loaded, loaded_rx) = mpsc::channel();
+        let datadir = datadir.to_string();
+
+        thread::Builder::new()
+            .name("utreexo-bridge".into())
+            .spawn(move || {
+                let mut bridge = match Bridge::load(&datadir, genesis) {
+                    Ok(bridge) => bridge,
+                    Err(e) => {
+                        let _ = loaded.send(Err(e));
+                        return;
+                    }
+                };
+
+                let _ = loaded.send(Ok(()));
+                for job in jobs_rx {
+                    job(&mut bridge);
+                }
+            })?;
+
+        loaded_rx.recv().map_err(|_| BridgeError::Stopped)??;
+        Ok(BridgeHandle { jobs })
+    }
+
+    /// Runs `job` in the bridge thread, and waits for its result
+    fn call<R: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut Bridge) -> R + Send + 'static,
+    ) -> Result<R, BridgeError> {
+        let (result, result_rx) = mpsc::channel();
+        self.jobs
+            .send(Box::new(move |bridge| {
+                let _ = result.send(job(bridge));
+            }))
+            .map_err(|_| BridgeError::Stopped)?;
+
+        result_rx.recv().map_err(|_| BridgeError::Stopped)
+    }
+
+    /// See [Bridge::tip]
+    pub fn tip(&self) -> Result<(u32, BlockHash), BridgeError> {
+        self.call(|bridge| bridge.tip())
+    }
+
+    /// See [Bridge::prove_block]
+    pub fn prove_block(&self, block: Block) -> Result<(Proof, Vec<CompactLeafData>), BridgeError> {
+        self.call(move |bridge| bridge.prove_block(&block))?
+    }
+
+    /// See [Bridge::connect_block]
+    pub fn connect_block(&self, block: Block) -> Result<(), BridgeError> {
+        self.call(move |bridge| bridge.connect_block(&block))?
+    }
+
+    /// See [Bridge::get_proof]
+    pub fn get_proof(&self, block_hash: BlockHash) -> Result<Option<UtreexoProof>, BridgeError> {
+        self.call(move |bridge| bridge.get_proof(block_hash))?
+    }
+
+    /// See [Bridge::block_hash]
+    pub fn block_hash(&self, height: u32) -> Result<Option<BlockHash>, BridgeError> {
+        self.call(move |bridge| bridge.block_hash(height))
+    }
+
+    /// See [Bridge::rollback]
+    pub fn rollback(&self, height: u32) -> Result<(), BridgeError> {
+        self.call(move |bridge| bridge.rollback(height))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::absolute::LockTime;
+    use bitcoin::block::Header;
+    use bitcoin::block::Version;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction;
+    use bitcoin::Amount;
+    use bitcoin::CompactTarget;
+    use bitcoin::ScriptBuf;
+    use bitcoin::Sequence;
+    use bitcoin::Transaction;
+    use bitcoin::TxIn;
+    use bitcoin::TxMerkleNode;
+    use bitcoin::TxOut;
+    use bitcoin::Witness;
+    use floresta_chain::BlockchainError;
+    use rustreexo::accumulator::stump::Stump;
+
+    use super::*;
+    use crate::p2p_wire::block_proof::Bitmap;
+
+    fn datadir() -> String {
+        format!("./tmp-db/{}/", rand::random::<u64>())
+    }
+
+    fn tx(inputs: &[OutPoint], outputs: usize) -> Transaction {
+        let input = match inputs.is_empty() {
+            // a coinbase
+            true => vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::from_bytes(vec![rand::random(); 4]),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            false => inputs
+                .iter()
+                .map(|prevout| TxIn {
+                    previous_output: *prevout,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+        };
+
+        // anyone can spend these outputs, so we don't need to reconstruct any script
+        let output = (0..outputs)
+            .map(|_| TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+            })
+            .collect();
+
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input,
+            output,
+        }
+    }
+
+    fn block(prev_blockhash: BlockHash, txdata: Vec<Transaction>) -> Block {
+        Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        }
+    }
+
+    /// Builds a few blocks, spending outputs from previous blocks and from the same block
+    fn blocks() -> Vec<Block> {
+        let genesis = BlockHash::all_zeros();
+
+        let coinbase = tx(&[], 2);
+        let block1 = block(genesis, vec![coinbase.clone()]);
+
+        let spend = tx(&[OutPoint::new(coinbase.compute_txid(), 0)], 3);
+        let spend_same_block = tx(&[OutPoint::new(spend.compute_txid(), 0)], 1);
+        let block2 = block(
+            block1.block_hash(),
+            vec![tx(&[], 1), spend.clone(), spend_same_block],
+        );
+
+        let spend_both = tx(
+            &[
+                OutPoint::new(spend.compute_txid(), 2),
+                OutPoint::new(coinbase.compute_txid(), 1),
+            ],
+            1,
+        );
+        let block3 = block(block2.block_hash(), vec![tx(&[], 1), spend_both]);
+
+        vec![block1, block2, block3]
+    }
+
+    #[test]
+    fn test_bridge_proofs() {
+        let datadir = datadir();
+        let blocks = blocks();
+        let mut bridge = Bridge::load(&datadir, BlockHash::all_zeros()).unwrap();
+
+        // We validate the proofs just like a utreexo node would, keeping only the roots
+        let mut acc = Stump::new();
+        let block_hashes: Vec<_> = blocks.iter().map(Block::block_hash).collect();
+
+        for (i, block) in blocks.iter().enumerate() {
+            let height = i as u32 + 1;
+            let (proof, leaf_data) = bridge.prove_block(block).unwrap();
+
+            let (del_hashes, _) = proof_util::process_proof(
+                &leaf_data,
+                &block.txdata,
+                height,
+                |h| Ok::<_, BlockchainError>(block_hashes[h as usize - 1]),
+                |_| Ok(0),
+            )
+            .unwrap();
+
+            let del_hashes: Vec<BitcoinNodeHash> = del_hashes.into_iter().map(Into::into).collect();
+
+            assert!(acc.verify(&proof, &del_hashes).unwrap());
+
+            let adds = proof_util::get_block_adds(block, height, block.block_hash());
+            acc = acc.modify(&adds, &del_hashes, &proof).unwrap().0;
+
+            bridge.connect_block(block).unwrap();
+            assert_eq!(bridge.tip(), (height, block.block_hash()));
+
+            let roots: Vec<_> = bridge
+                .forest
+                .get_roots()
+                .iter()
+                .map(|r| r.get_data())
+                .collect();
+            assert_eq!(roots, acc.roots);
+        }
+
+        // We can't connect a block that doesn't extend our tip
+        assert!(matches!(
+            bridge.connect_block(&blocks[0]),
+            Err(BridgeError::BlockDoesntExtendTip(_))
+        ));
+
+        // Our state survives a restart
+        let proof = bridge.get_proof(block_hashes[2]).unwrap().unwrap();
+        drop(bridge);
+
+        let mut bridge = Bridge::load(&datadir, BlockHash::all_zeros()).unwrap();
+        assert_eq!(bridge.tip(), (3, block_hashes[2]));
+        assert_eq!(bridge.leaves.len(), 5);
+        assert_eq!(bridge.get_proof(block_hashes[2]).unwrap(), Some(proof));
+        assert_eq!(bridge.get_proof(BlockHash::all_zeros()).unwrap(), None);
+    }
+
+    fn roots(bridge: &Bridge) -> Vec<BitcoinNodeHash> {
+        bridge
+            .forest
+            .get_roots()
+            .iter()
+            .map(|r| r.get_data())
+            .collect()
+    }
+
+    #[test]
+    fn test_bridge_reorg() {
+        let (datadir, fork_datadir) = (datadir(), datadir());
+        let blocks = blocks();
+        let mut bridge = Bridge::load(&datadir, BlockHash::all_zeros()).unwrap();
+        for block in blocks.iter() {
+            bridge.connect_block(block).unwrap();
+        }
+
+        // A fork starting after the first block, spending one of its coinbase outputs
+        let coinbase = blocks[0].txdata[0].compute_txid();
+        let block2 = block(
+            blocks[0].block_hash(),
+            vec![tx(&[], 2), tx(&[OutPoint::new(coinbase, 1)], 2)],
+        );
+        let block3 = block(block2.block_hash(), vec![tx(&[], 1)]);
+        let fork = [blocks[0].clone(), block2, block3];
+
+        bridge.rollback(1).unwrap();
+        assert_eq!(bridge.tip(), (1, blocks[0].block_hash()));
+        assert_eq!(bridge.block_hash(2), None);
+
+        for block in fork.iter().skip(1) {
+            bridge.connect_block(block).unwrap();
+        }
+
+        // We end up with the same forest as a bridge that only saw the fork
+        let mut expected = Bridge::load(&fork_datadir, BlockHash::all_zeros()).unwrap();
+        for block in fork.iter() {
+            expected.connect_block(block).unwrap();
+        }
+
+        assert_eq!(bridge.tip(), expected.tip());
+        assert_eq!(roots(&bridge), roots(&expected));
+        assert_eq!(bridge.leaves, expected.leaves);
+        assert_eq!(bridge.block_hash(2), Some(fork[1].block_hash()));
+
+        // And the rolled back blocks are gone after a restart
+        drop(bridge);
+        let bridge = Bridge::load(&datadir, BlockHash::all_zeros()).unwrap();
+        assert_eq!(bridge.tip(), expected.tip());
+        assert_eq!(roots(&bridge), roots(&expected));
+    }
+
+    #[test]
+    fn test_bridge_state_interval() {
+        let datadir = datadir();
+        let mut bridge = Bridge::load(&datadir, BlockHash::all_zeros()).unwrap();
+
+        let mut block_hashes = vec![BlockHash::all_zeros()];
+        let mut rollback_roots = Vec::new();
+        for height in 1..=2 * STATE_INTERVAL + 10 {
+            let block = block(*block_hashes.last().unwrap(), vec![tx(&[], 1)]);
+            bridge.connect_block(&block).unwrap();
+            block_hashes.push(block.block_hash());
+
+            if height == STATE_INTERVAL + 5 {
+                rollback_roots = roots(&bridge);
+            }
+        }
+
+        // We've moved our state forward once, keeping the blocks after it
+        assert_eq!(
+            bridge.saved_tip,
+            (STATE_INTERVAL, block_hashes[STATE_INTERVAL as usize])
+        );
+        assert_eq!(bridge.changes.len(), STATE_INTERVAL as usize + 10);
+
+        // Restarting replays the blocks after our state
+        let tip = bridge.tip();
+        let tip_roots = roots(&bridge);
+        drop(bridge);
+
+        let mut bridge = Bridge::load(&datadir, BlockHash::all_zeros()).unwrap();
+        assert_eq!(bridge.tip(), tip);
+        assert_eq!(roots(&bridge), tip_roots);
+
+        // We can't roll back before our state
+        assert!(matches!(
+            bridge.rollback(STATE_INTERVAL - 1),
+            Err(BridgeError::ReorgTooDeep(_))
+        ));
+        assert_eq!(bridge.block_hash(STATE_INTERVAL - 1), None);
+
+        bridge.rollback(STATE_INTERVAL + 5).unwrap();
+        let height = STATE_INTERVAL + 5;
+        assert_eq!(bridge.tip(), (height, block_hashes[height as usize]));
+        assert_eq!(roots(&bridge), rollback_roots);
+    }
+
+    #[test]
+    fn test_truncated_proofs() {
+        let datadir = datadir();
+        let blocks = blocks();
+
+        let mut bridge = Bridge::load(&datadir, BlockHash::all_zeros()).unwrap();
+        for block in blocks.iter() {
+            bridge.connect_block(block).unwrap();
+        }
+        drop(bridge);
+
+        // We've crashed while writing the last proof
+        let path = PathBuf::from(&datadir).join(BRIDGE_DIR).join(PROOFS_FILE);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let mut bridge = Bridge::load(&datadir, BlockHash::all_zeros()).unwrap();
+        assert!(bridge.get_proof(blocks[1].block_hash()).unwrap().is_some());
+        assert!(bridge.get_proof(blocks[2].block_hash()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_select_proof() {
+        let proof = UtreexoProof {
+            block_hash: BlockHash::all_zeros(),
+            proof_hashes: (0..4u8).map(|i| BitcoinNodeHash::from([i; 32])).collect(),
+            targets: vec![10, 20, 30],
+            leaf_data: (0..3)
+                .map(|i| CompactLeafData {
+                    header_code: i,
+                    amount: 0,
+                    spk_ty: floresta_chain::ScriptPubKeyKind::PubKeyHash,
+                })
+                .collect(),
+        };
+
+        let mut request = GetUtreexoProof {
+            block_hash: BlockHash::all_zeros(),
+            include_leaves: true,
+            proof_hashes_bitmap: Bitmap::new(),
+            leaf_index_bitmap: Bitmap::new(),
+        };
+        assert_eq!(select_proof(proof.clone(), &request), proof);
+
+        request.include_leaves = false;
+        request.proof_hashes_bitmap = Bitmap::new();
+        for requested in [true, false, false, true] {
+            request.proof_hashes_bitmap.push_input(requested);
+        }
+        for requested in [false, true, false] {
+            request.leaf_index_bitmap.push_input(requested);
+        }
+
+        let selected = select_proof(proof.clone(), &request);
+        assert_eq!(
+            selected.proof_hashes,
+            vec![proof.proof_hashes[0], proof.proof_hashes[3]]
+        );
+        assert_eq!(selected.targets, vec![20]);
+        assert!(selected.leaf_data.is_empty());
+    }
+
+    #[test]
+    fn test_bridge_handle() {
+        let datadir = datadir();
+        let blocks = blocks();
+
+        let bridge = BridgeHandle::start(&datadir, BlockHash::all_zeros()).unwrap();
+        for block in blocks.iter() {
+            bridge.prove_block(block.clone()).unwrap();
+            bridge.connect_block(block.clone()).unwrap();
+        }
+
+        assert_eq!(bridge.tip().unwrap(), (3, blocks[2].block_hash()));
+        assert!(bridge.get_proof(blocks[1].block_hash()).unwrap().is_some());
+    }
+}
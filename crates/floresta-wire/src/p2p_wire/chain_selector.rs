@@ -70,14 +70,13 @@ use tracing::error;
 use tracing::info;
 use tracing::warn;
 
+use super::backfill;
 use super::error::WireError;
 use super::node_interface::UserRequest;
 use super::peer::PeerMessages;
-use crate::address_man::AddressState;
-use crate::block_proof::Bitmap;
 use crate::node::periodic_job;
 use crate::node::try_and_log;
-use crate::node::InflightBlock;
+use crate::node::HeadersPresync;
 use crate::node::InflightRequests;
 use crate::node::NodeNotification;
 use crate::node::NodeRequest;
@@ -159,19 +158,31 @@ where
             headers[0].block_hash()
         );
 
-        for header in headers.iter() {
-            if let Err(e) = self.chain.accept_header(*header) {
-                error!("Error while downloading headers from peer={peer} err={e}");
+        let headers = match self.presync_headers(peer, headers)? {
+            HeadersPresync::Accept(headers) => headers,
+            HeadersPresync::Continue(headers, locator) => {
+                self.accept_headers_from(peer, &headers).await?;
 
-                self.send_to_peer(peer, NodeRequest::Shutdown).await?;
+                self.send_to_peer(peer, NodeRequest::GetHeaders(locator))
+                    .await?;
+                self.inflight
+                    .insert(InflightRequests::Headers, (peer, Instant::now()));
 
-                let peer = self.peers.get(&peer).unwrap();
-                self.common.address_man.update_set_state(
-                    peer.address_id as usize,
-                    AddressState::Banned(ChainSelector::BAN_TIME),
-                );
+                return Ok(());
             }
-        }
+            // This peer has nothing useful for us, like an empty headers message
+            HeadersPresync::LowWork => {
+                self.empty_headers_message(peer).await?;
+                return Ok(());
+            }
+            HeadersPresync::Invalid => {
+                self.increase_banscore(peer, 5).await?;
+                self.empty_headers_message(peer).await?;
+                return Ok(());
+            }
+        };
+
+        self.accept_headers_from(peer, &headers).await?;
 
         let last = headers.last().unwrap().block_hash();
         self.context
@@ -183,6 +194,31 @@ where
         self.request_headers(last, peer).await
     }
 
+    /// Accepts headers from a peer, banning it if they are invalid, or disconnecting it if it
+    /// gave us too many fork headers
+    async fn accept_headers_from(
+        &mut self,
+        peer: PeerId,
+        headers: &[Header],
+    ) -> Result<(), WireError> {
+        for header in headers.iter() {
+            match self.accept_peer_header(peer, *header) {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("Peer {peer} gave us too many fork headers, disconnecting");
+                    self.send_to_peer(peer, NodeRequest::Shutdown).await?;
+                    return Ok(());
+                }
+                Err(e) => {
+                    error!("Error while downloading headers from peer={peer} err={e}");
+                    self.ban_peer(peer, ChainSelector::BAN_TIME)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Takes a serialized accumulator and parses it into a Stump
     fn parse_acc(mut acc: Vec<u8>) -> Result<Stump, WireError> {
         if acc.is_empty() {
@@ -396,93 +432,6 @@ where
         Ok(Some(peer2))
     }
 
-    /// Requests a block and its proof from a peer
-    ///
-    /// If you need to see a peer's version of a given block, you can use this method
-    /// to request a block from a specific peer.
-    async fn get_block_and_proof(
-        &mut self,
-        peer: PeerId,
-        block_hash: BlockHash,
-    ) -> Result<InflightBlock, WireError> {
-        self.send_to_peer(peer, NodeRequest::GetBlock(vec![block_hash]))
-            .await?;
-
-        let timeout = Instant::now() + Duration::from_secs(60);
-        let mut block = None;
-        loop {
-            if Instant::now() > timeout {
-                return Err(WireError::PeerTimeout);
-            }
-
-            let Some(NodeNotification::FromPeer(id, message)) = self.node_rx.recv().await else {
-                // Keep waiting until peer message is read or timeout
-                continue;
-            };
-
-            if id != peer {
-                continue;
-            }
-
-            match message {
-                // STEP 1: Receive the block and ask for the proof
-                PeerMessages::Block(recv_block) => {
-                    if recv_block.block_hash() != block_hash {
-                        error!("peer {peer} sent us a block we didn't request");
-                        self.increase_banscore(peer, self.max_banscore).await?;
-                        return Err(WireError::PeerMisbehaving);
-                    }
-
-                    // Check if the blocks was maliciously mutated by our peer
-                    let is_mutated =
-                        !(recv_block.check_merkle_root() && recv_block.check_witness_commitment());
-
-                    if is_mutated {
-                        error!(
-                            "Peer {peer} sent us a mutated block {}",
-                            recv_block.block_hash()
-                        );
-                        self.increase_banscore(peer, self.config.max_banscore)
-                            .await?;
-                        return Err(WireError::PeerMisbehaving);
-                    }
-
-                    block = Some(recv_block);
-                    // ask for the proof. Sending two empty bitmaps means we want the full
-                    // proof and all leaf data
-                    self.send_to_peer(
-                        peer,
-                        NodeRequest::GetBlockProof((block_hash, Bitmap::new(), Bitmap::new())),
-                    )
-                    .await?;
-                }
-
-                // STEP 2: Receive the proof and return the `InflightBlock`
-                PeerMessages::UtreexoProof(uproof) => {
-                    let Some(block) = block else {
-                        error!("peer {peer} sent us a proof without sending the block first");
-                        self.increase_banscore(peer, self.config.max_banscore)
-                            .await?;
-                        return Err(WireError::PeerMisbehaving);
-                    };
-
-                    let proof = Proof {
-                        hashes: uproof.proof_hashes,
-                        targets: uproof.targets,
-                    };
-
-                    return Ok(InflightBlock {
-                        block,
-                        proof: Some(proof),
-                        leaf_data: Some(uproof.leaf_data),
-                        peer,
-                    });
-                }
-                _ => {}
-            }
-        }
-    }
-
     /// Updates a Stump, with the data from a block and its proof
     fn update_acc(
         &self,
@@ -492,9 +441,13 @@ where
         leaf_data: &[CompactLeafData],
         height: u32,
     ) -> Result<Stump, WireError> {
-        let (del_hashes, _) = proof_util::process_proof(leaf_data, &block.txdata, height, |h| {
-            self.chain.get_block_hash(h)
-        })?;
+        let (del_hashes, _) = proof_util::process_proof(
+            leaf_data,
+            &block.txdata,
+            height,
+            |h| self.chain.get_block_hash(h),
+            |h| self.chain.get_median_time_past(h),
+        )?;
 
         Ok(self
             .chain
@@ -599,6 +552,7 @@ where
                     };
                     self.chain
                         .mark_chain_as_assumed(acc, assume_utreexo.block_hash)?;
+                    backfill::mark_assumed(&self.config.datadir)?;
                     return Ok(());
                 }
 
@@ -634,10 +588,13 @@ where
         let block = self.get_block_and_proof(rand_peer, fork).await?;
         let leaf_data = block.leaf_data.expect("Leaf data should be present");
         let proof = block.proof.expect("Block proof should be present");
-        let (del_hashes, inputs) =
-            proof_util::process_proof(&leaf_data, &block.block.txdata, fork_height, |h| {
-                self.chain.get_block_hash(h)
-            })?;
+        let (del_hashes, inputs) = proof_util::process_proof(
+            &leaf_data,
+            &block.block.txdata,
+            fork_height,
+            |h| self.chain.get_block_hash(h),
+            |h| self.chain.get_median_time_past(h),
+        )?;
 
         let acc = self.find_accumulator_for_block(fork_height, fork).await?;
         let is_valid = self
@@ -659,13 +616,10 @@ where
     }
 
     async fn ban_peers_on_tip(&mut self, tip: BlockHash) -> Result<(), WireError> {
-        for peer in self.common.peers.clone() {
-            if self.context.tip_cache.get(&peer.0).copied().eq(&Some(tip)) {
-                self.address_man.update_set_state(
-                    peer.1.address_id as usize,
-                    AddressState::Banned(ChainSelector::BAN_TIME),
-                );
-                self.send_to_peer(peer.0, NodeRequest::Shutdown).await?;
+        let peers: Vec<PeerId> = self.common.peers.keys().copied().collect();
+        for peer in peers {
+            if self.context.tip_cache.get(&peer).copied().eq(&Some(tip)) {
+                self.ban_peer(peer, ChainSelector::BAN_TIME)?;
             }
         }
 
@@ -689,7 +643,12 @@ where
 
                 self.context.state = ChainSelectorState::Done;
                 self.chain.mark_chain_as_assumed(acc, tips[0]).unwrap();
+                backfill::mark_assumed(&self.config.datadir)?;
                 self.chain.toggle_ibd(false);
+
+                if self.config.backfill {
+                    self.find_backfill_checkpoints(height).await?;
+                }
             }
             // if we have more than one tip, we need to check if our best chain has an invalid block
             tips.remove(0); // no need to check our best one
@@ -705,6 +664,23 @@ where
         Ok(())
     }
 
+    /// Finds the accumulators where each backfill range should start, if we want to backfill the
+    /// chain up to `end`. See [backfill::checkpoint_heights].
+    ///
+    /// If our peers can't give us one of them, the range starting there is merged with the
+    /// previous one.
+    async fn find_backfill_checkpoints(&mut self, end: u32) -> Result<(), WireError> {
+        for height in backfill::checkpoint_heights(end, self.config.backfill_ranges) {
+            let hash = self.chain.get_block_hash(height)?;
+            match self.find_accumulator_for_block(height, hash).await {
+                Ok(acc) => self.common.backfill_checkpoints.push((height, acc)),
+                Err(e) => warn!("Could not find the accumulator for block {height}: {e:?}"),
+            }
+        }
+
+        Ok(())
+    }
+
     /// Ask for headers, given a tip
     ///
     /// This function will send a `getheaders` request to our peers, assuming this
@@ -761,6 +737,10 @@ where
                         self.address_man.push_addresses(&addresses);
                     }
 
+                    Some(NodeNotification::InboundConnection(stream, address)) => {
+                        try_and_log!(self.handle_inbound_connection(stream, address).await);
+                    }
+
                     None => {
                         break;
                     }
@@ -865,6 +845,10 @@ where
                         self.address_man.push_addresses(&addresses);
                     }
 
+                    NodeNotification::InboundConnection(stream, address) => {
+                        try_and_log!(self.handle_inbound_connection(stream, address).await);
+                    }
+
                     NodeNotification::FromPeer(peer, message) => {
                         if let PeerMessages::UtreexoState(state) = message {
                             self.inflight.remove(&InflightRequests::UtreexoState(peer));
@@ -1003,6 +987,22 @@ where
                 self.increase_banscore(peer, 5).await?;
             }
 
+            PeerMessages::GetUtreexoProof(request) => {
+                self.handle_get_utreexo_proof(peer, request).await?;
+            }
+
+            PeerMessages::GetData(inv) => {
+                self.handle_get_data(peer, inv).await?;
+            }
+
+            PeerMessages::SendCmpct => {
+                self.handle_send_cmpct(peer);
+            }
+
+            PeerMessages::GetBlockTxn(request) => {
+                self.handle_get_block_txn(peer, request).await?;
+            }
+
             _ => {}
         }
         Ok(())
@@ -0,0 +1,284 @@
+//! Rebuilds [BIP152](https://github.com/bitcoin/bips/blob/master/bip-0152.mediawiki) compact
+//! blocks using the transactions in our mempool.
+//!
+//! A compact block has the block header, a few prefilled transactions (at least the coinbase)
+//! and a 6-byte short ID for each of the other transactions. We match those short IDs against
+//! our mempool, and only ask the peer for the transactions we don't have, with `getblocktxn`.
+//! Once we have all of them, the block is handled like any full block, so we request its
+//! Utreexo proof afterwards.
+//!
+//! Short IDs may collide, so a rebuilt block is only used if it matches the merkle root and
+//! witness commitment in its header. If it doesn't, we download the full block instead.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use bitcoin::bip152::HeaderAndShortIds;
+use bitcoin::bip152::ShortId;
+use bitcoin::block::Header;
+use bitcoin::Block;
+use bitcoin::BlockHash;
+use bitcoin::Transaction;
+
+use crate::node_context::PeerId;
+
+/// The version of compact blocks we use. Version 2 uses wtxids for short IDs and includes
+/// witness data in transactions, version 1 is pre-segwit and useless for us.
+pub const COMPACT_BLOCKS_VERSION: u64 = 2;
+
+/// How many peers may send us new blocks as compact blocks, without announcing them first
+pub const MAX_HIGH_BANDWIDTH_PEERS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Errors returned while rebuilding a compact block
+pub enum CompactBlockError {
+    /// A prefilled transaction is past the end of the block
+    InvalidPrefill,
+
+    /// Two transactions in this block have the same short ID
+    DuplicateShortId,
+
+    /// A `blocktxn` doesn't have the transactions we've asked for
+    WrongTransactionCount,
+
+    /// The rebuilt block doesn't match its merkle root or witness commitment. This may be a
+    /// short ID collision with one of our mempool transactions
+    InvalidBlock,
+}
+
+impl Display for CompactBlockError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CompactBlockError::InvalidPrefill => write!(f, "invalid prefilled transaction index"),
+            CompactBlockError::DuplicateShortId => write!(f, "duplicated short id"),
+            CompactBlockError::WrongTransactionCount => {
+                write!(f, "wrong number of transactions in blocktxn")
+            }
+            CompactBlockError::InvalidBlock => {
+                write!(f, "rebuilt block doesn't match its commitments")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A compact block that we are rebuilding
+pub struct PartialBlock {
+    /// The peer that sent us this compact block
+    pub peer: PeerId,
+
+    /// The block header
+    header: Header,
+
+    /// Every transaction in this block, or `None` if we still don't have it
+    transactions: Vec<Option<Transaction>>,
+
+    /// How many transactions we've got from our mempool
+    mempool_hits: usize,
+
+    /// How many transactions weren't prefilled by our peer
+    short_ids: usize,
+}
+
+impl PartialBlock {
+    /// Fills a compact block with its prefilled transactions and the matching transactions
+    /// from our mempool
+    pub fn new<'a>(
+        compact: &HeaderAndShortIds,
+        peer: PeerId,
+        mempool: impl Iterator<Item = &'a Transaction>,
+    ) -> Result<PartialBlock, CompactBlockError> {
+        let total = compact.short_ids.len() + compact.prefilled_txs.len();
+        let mut transactions = vec![None; total];
+
+        // Prefilled indexes are differentially encoded, each one is relative to the previous
+        let mut next_index = 0;
+        for prefilled in compact.prefilled_txs.iter() {
+            let index = next_index + prefilled.idx as usize;
+            let slot = transactions
+                .get_mut(index)
+                .ok_or(CompactBlockError::InvalidPrefill)?;
+
+            *slot = Some(prefilled.tx.clone());
+            next_index = index + 1;
+        }
+
+        // Short IDs fill the remaining positions, in order
+        let mut positions = HashMap::with_capacity(compact.short_ids.len());
+        let mut empty_slots = transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(index, _)| index);
+
+        for short_id in compact.short_ids.iter() {
+            let index = empty_slots
+                .next()
+                .ok_or(CompactBlockError::InvalidPrefill)?;
+
+            if positions.insert(*short_id, index).is_some() {
+                return Err(CompactBlockError::DuplicateShortId);
+            }
+        }
+
+        let siphash_keys = ShortId::calculate_siphash_keys(&compact.header, compact.nonce);
+        let mut mempool_hits = 0;
+        for tx in mempool {
+            let short_id =
+                ShortId::with_siphash_keys(&tx.compute_wtxid().to_raw_hash(), siphash_keys);
+            let Some(index) = positions.get(&short_id) else {
+                continue;
+            };
+
+            if transactions[*index].is_none() {
+                transactions[*index] = Some(tx.clone());
+                mempool_hits += 1;
+            }
+        }
+
+        Ok(PartialBlock {
+            peer,
+            header: compact.header,
+            transactions,
+            mempool_hits,
+            short_ids: compact.short_ids.len(),
+        })
+    }
+
+    /// The hash of this block
+    pub fn block_hash(&self) -> BlockHash {
+        self.header.block_hash()
+    }
+
+    /// The indexes of the transactions we still need, that should go in a `getblocktxn`
+    pub fn missing(&self) -> Vec<u64> {
+        self.transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(index, _)| index as u64)
+            .collect()
+    }
+
+    /// Which fraction of the non-prefilled transactions we've found in our mempool
+    pub fn hit_rate(&self) -> f64 {
+        if self.short_ids == 0 {
+            return 1.0;
+        }
+
+        self.mempool_hits as f64 / self.short_ids as f64
+    }
+
+    /// Adds the transactions from a `blocktxn`, in the same order as [PartialBlock::missing]
+    pub fn fill(&mut self, transactions: Vec<Transaction>) -> Result<(), CompactBlockError> {
+        let missing = self.missing();
+        if missing.len() != transactions.len() {
+            return Err(CompactBlockError::WrongTransactionCount);
+        }
+
+        for (index, tx) in missing.into_iter().zip(transactions) {
+            self.transactions[index as usize] = Some(tx);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the full block, if we have all its transactions and they match the header
+    pub fn into_block(self) -> Result<Block, CompactBlockError> {
+        let txdata = self
+            .transactions
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .ok_or(CompactBlockError::WrongTransactionCount)?;
+
+        let block = Block {
+            header: self.header,
+            txdata,
+        };
+
+        if !block.check_merkle_root() || !block.check_witness_commitment() {
+            return Err(CompactBlockError::InvalidBlock);
+        }
+
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitcoin::bip152::HeaderAndShortIds;
+    use bitcoin::consensus::deserialize;
+    use bitcoin::Block;
+
+    use super::CompactBlockError;
+    use super::PartialBlock;
+
+    fn get_block() -> Block {
+        let file = include_bytes!("../../../floresta-chain/testdata/block_866342/raw.zst");
+        let bytes = zstd::decode_all(Cursor::new(file)).unwrap();
+        deserialize(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_rebuild_from_mempool() {
+        let block = get_block();
+        let compact = HeaderAndShortIds::from_block(&block, 42, 2, &[3]).unwrap();
+
+        // Our mempool has every other transaction in this block
+        let mempool: Vec<_> = block.txdata.iter().skip(1).step_by(2).collect();
+        let mut partial = PartialBlock::new(&compact, 0, mempool.iter().copied()).unwrap();
+
+        let missing = partial.missing();
+        assert!(!missing.is_empty());
+        assert!(partial.hit_rate() > 0.4 && partial.hit_rate() < 0.6);
+        assert_eq!(partial.block_hash(), block.block_hash());
+
+        // The wrong number of transactions is refused
+        assert_eq!(
+            partial.fill(Vec::new()),
+            Err(CompactBlockError::WrongTransactionCount)
+        );
+
+        let transactions = missing
+            .iter()
+            .map(|index| block.txdata[*index as usize].clone())
+            .collect();
+
+        partial.fill(transactions).unwrap();
+        assert!(partial.missing().is_empty());
+        assert_eq!(partial.into_block().unwrap(), block);
+    }
+
+    #[test]
+    fn test_invalid_compact_blocks() {
+        let block = get_block();
+        let mut compact = HeaderAndShortIds::from_block(&block, 42, 2, &[]).unwrap();
+
+        // If the transactions we put in the block don't match the header, we can't use it
+        let mut partial = PartialBlock::new(&compact, 0, block.txdata.iter().skip(2)).unwrap();
+        assert_eq!(partial.missing(), vec![1]);
+
+        partial.fill(vec![block.txdata[2].clone()]).unwrap();
+        assert_eq!(partial.into_block(), Err(CompactBlockError::InvalidBlock));
+
+        // Duplicated short IDs
+        let short_id = compact.short_ids[0];
+        compact.short_ids[1] = short_id;
+        assert_eq!(
+            PartialBlock::new(&compact, 0, std::iter::empty()).unwrap_err(),
+            CompactBlockError::DuplicateShortId
+        );
+
+        // Prefilled transactions past the end of the block
+        let mut compact = HeaderAndShortIds::from_block(&block, 42, 2, &[]).unwrap();
+        compact.prefilled_txs[0].idx = u16::MAX;
+        assert_eq!(
+            PartialBlock::new(&compact, 0, std::iter::empty()).unwrap_err(),
+            CompactBlockError::InvalidPrefill
+        );
+    }
+}
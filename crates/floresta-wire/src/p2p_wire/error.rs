@@ -9,6 +9,8 @@ use floresta_common::impl_error_from;
 use floresta_compact_filters::IterableFilterStoreError;
 use tokio::sync::mpsc::error::SendError;
 
+use super::block_cache::BlockCacheError;
+use super::bridge::BridgeError;
 use super::peer::PeerError;
 use super::transport::TransportError;
 use crate::node::NodeRequest;
@@ -88,6 +90,12 @@ pub enum WireError {
 
     /// Couldn't find the leaf data for a block
     LeafDataNotFound,
+
+    /// Our Utreexo bridge returned an error
+    Bridge(BridgeError),
+
+    /// Our block cache returned an error
+    BlockCache(BlockCacheError),
 }
 
 impl std::fmt::Display for WireError {
@@ -132,6 +140,8 @@ impl std::fmt::Display for WireError {
                 "We tried to work on a block that we don't have a proof for yet"
             ),
             WireError::LeafDataNotFound => write!(f, "Couldn't find the leaf data for a block"),
+            WireError::Bridge(err) => write!(f, "Utreexo bridge error: {err}"),
+            WireError::BlockCache(err) => write!(f, "Block cache error: {err}"),
         }
     }
 }
@@ -147,6 +157,8 @@ impl_error_from!(WireError, AddrParseError, InvalidAddress);
 impl_error_from!(WireError, SendError<NodeRequest>, ChannelSend);
 impl_error_from!(WireError, serde_json::Error, Serde);
 impl_error_from!(WireError, io::Error, Io);
+impl_error_from!(WireError, BridgeError, Bridge);
+impl_error_from!(WireError, BlockCacheError, BlockCache);
 
 impl From<tokio::sync::oneshot::error::RecvError> for WireError {
     fn from(_: tokio::sync::oneshot::error::RecvError) -> Self {
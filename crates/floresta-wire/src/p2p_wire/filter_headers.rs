@@ -0,0 +1,536 @@
+//! Checks the [BIP157](https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki) filter
+//! headers we use to verify the compact filters downloaded from our peers.
+//!
+//! Each filter header commits to a block filter and to the previous filter header, so once we
+//! have the header for a block, no peer can give us a different filter for it. We first ask a
+//! few peers for their checkpoints (one filter header every 1000 blocks) with `getcfcheckpt`.
+//! Then we download the headers between checkpoints with `getcfheaders`, from any peer, and
+//! they must chain up to the next checkpoint. Headers after the last checkpoint are downloaded
+//! from two peers, that must agree with each other.
+//!
+//! If two peers disagree, one of them is lying. We find the first block where their filter
+//! hashes differ, build the filter for that block ourselves and ban whoever got it wrong. To
+//! build it, we download the block and its Utreexo proof, as the proof has the scripts of every
+//! output the block spends. We only trust those scripts if the proof is valid for our
+//! accumulator. That's a [FilterDispute].
+
+use std::collections::BTreeMap;
+
+use bitcoin::bip158;
+use bitcoin::bip158::BlockFilter;
+use bitcoin::bip158::FilterHash;
+use bitcoin::bip158::FilterHeader;
+use bitcoin::Block;
+use bitcoin::BlockHash;
+use floresta_chain::proof_util;
+use floresta_chain::proof_util::UtreexoLeafError;
+use rustreexo::accumulator::node_hash::BitcoinNodeHash;
+use rustreexo::accumulator::proof::Proof;
+use rustreexo::accumulator::stump::Stump;
+
+use crate::block_proof::UtreexoProof;
+use crate::node_context::PeerId;
+
+/// We get one checkpoint every this many blocks
+pub const CHECKPOINT_INTERVAL: u32 = 1000;
+
+/// How many filter headers a peer may send us in one `cfheaders`
+pub const MAX_FILTER_HEADERS: u32 = 2000;
+
+/// How many filters a peer may ask us in one `getcfilters`
+pub const MAX_GETCFILTERS_SIZE: u32 = 1000;
+
+/// How many peers we ask for checkpoints
+pub const CHECKPOINT_PEERS: usize = 3;
+
+/// How many peers we ask for the filter headers after the last checkpoint
+pub const TIP_HEADERS_PEERS: usize = 2;
+
+/// Computes the filter headers for consecutive blocks, given the header of the block before
+/// the first one
+pub fn compute_filter_headers(previous: FilterHeader, hashes: &[FilterHash]) -> Vec<FilterHeader> {
+    let mut previous = previous;
+    hashes
+        .iter()
+        .map(|hash| {
+            previous = hash.filter_header(&previous);
+            previous
+        })
+        .collect()
+}
+
+/// The height of the block for the i-th checkpoint in a `cfcheckpt`
+pub fn checkpoint_height(index: usize) -> u32 {
+    (index as u32 + 1) * CHECKPOINT_INTERVAL
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What we've learned from comparing the checkpoints sent by several peers
+pub enum CheckpointsAgreement {
+    /// Every peer agrees on these checkpoints, by height
+    Agree(BTreeMap<u32, FilterHeader>),
+
+    /// Those peers disagree on the checkpoint with this index. They agree on every checkpoint
+    /// before it.
+    Disagree {
+        index: usize,
+        peers: (PeerId, PeerId),
+    },
+}
+
+/// Compares the checkpoints our peers sent us
+///
+/// Peers with less checkpoints may just be behind, so we only compare the checkpoints they
+/// have. If every peer agrees, we use all the checkpoints we've got.
+pub fn compare_checkpoints(responses: &[(PeerId, Vec<FilterHeader>)]) -> CheckpointsAgreement {
+    let mut agreed = BTreeMap::new();
+    let longest = responses
+        .iter()
+        .map(|(_, checkpoints)| checkpoints.len())
+        .max()
+        .unwrap_or(0);
+
+    for index in 0..longest {
+        let mut checkpoints = responses
+            .iter()
+            .filter_map(|(peer, checkpoints)| Some((*peer, *checkpoints.get(index)?)));
+
+        let (first_peer, first) = checkpoints.next().expect("someone has this checkpoint");
+        if let Some((peer, _)) = checkpoints.find(|(_, checkpoint)| *checkpoint != first) {
+            return CheckpointsAgreement::Disagree {
+                index,
+                peers: (first_peer, peer),
+            };
+        }
+
+        agreed.insert(checkpoint_height(index), first);
+    }
+
+    CheckpointsAgreement::Agree(agreed)
+}
+
+/// The last block for a `getcfheaders` that starts at `start`
+///
+/// Up to the last checkpoint, we stop at a checkpoint, so we can check the headers against it.
+/// After it, we stop at our tip.
+pub fn filter_headers_stop(start: u32, last_checkpoint: u32, tip: u32) -> u32 {
+    if start > last_checkpoint {
+        return tip.min(start + MAX_FILTER_HEADERS - 1);
+    }
+
+    let stop = (start + MAX_FILTER_HEADERS - 1) / CHECKPOINT_INTERVAL * CHECKPOINT_INTERVAL;
+    stop.min(last_checkpoint)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The filter headers for a range of blocks, that we've asked to one or more peers
+pub struct FilterHeadersBatch {
+    /// The first block in this batch
+    pub start: u32,
+
+    /// The last block in this batch
+    pub stop: u32,
+
+    /// The filter header of the block before `start`
+    pub previous: FilterHeader,
+
+    /// The peers we've asked for those headers
+    pub peers: Vec<PeerId>,
+
+    /// The filter hashes each peer sent us
+    pub responses: Vec<(PeerId, Vec<FilterHash>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What we've learned from the filter headers in a [FilterHeadersBatch]
+pub enum BatchCheck {
+    /// Every peer sent the same headers, and they match our checkpoints
+    Valid(Vec<FilterHeader>),
+
+    /// This peer sent us headers that don't match our checkpoints, or the wrong number of them
+    Invalid(PeerId),
+
+    /// Those peers sent different filter hashes for this block, but the same ones before it
+    Disagree {
+        height: u32,
+        peers: (PeerId, PeerId),
+        hashes: (FilterHash, FilterHash),
+    },
+
+    /// No peer answered
+    Empty,
+}
+
+impl FilterHeadersBatch {
+    pub fn new(start: u32, stop: u32, previous: FilterHeader, peers: Vec<PeerId>) -> Self {
+        FilterHeadersBatch {
+            start,
+            stop,
+            previous,
+            peers,
+            responses: Vec::new(),
+        }
+    }
+
+    /// Whether we have the answer from every peer we've asked
+    pub fn is_complete(&self) -> bool {
+        self.responses.len() == self.peers.len()
+    }
+
+    /// Checks the filter hashes our peers sent against each other and against the
+    /// checkpoints we've got
+    pub fn check(&self, checkpoints: &BTreeMap<u32, FilterHeader>) -> BatchCheck {
+        let expected_len = (self.stop - self.start + 1) as usize;
+        let mut valid = Vec::new();
+
+        for (peer, hashes) in self.responses.iter() {
+            if hashes.len() != expected_len {
+                return BatchCheck::Invalid(*peer);
+            }
+
+            let headers = compute_filter_headers(self.previous, hashes);
+            let matches_checkpoints = checkpoints
+                .range(self.start..=self.stop)
+                .all(|(height, checkpoint)| headers[(height - self.start) as usize] == *checkpoint);
+
+            if !matches_checkpoints {
+                return BatchCheck::Invalid(*peer);
+            }
+
+            valid.push((*peer, hashes, headers));
+        }
+
+        let Some((first_peer, first_hashes, first_headers)) = valid.first() else {
+            return BatchCheck::Empty;
+        };
+
+        for (peer, hashes, _) in valid.iter().skip(1) {
+            let Some(index) = first_difference(first_hashes, hashes) else {
+                continue;
+            };
+
+            return BatchCheck::Disagree {
+                height: self.start + index as u32,
+                peers: (*first_peer, *peer),
+                hashes: (first_hashes[index], hashes[index]),
+            };
+        }
+
+        BatchCheck::Valid(first_headers.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Two peers that disagree on the filter for a block, while we download that block and its
+/// Utreexo proof to build the filter ourselves
+pub struct FilterDispute {
+    /// The height of the block
+    pub height: u32,
+
+    /// The hash of the block
+    pub block_hash: BlockHash,
+
+    /// The peers that disagree, and the filter hash each of them sent
+    pub claims: [(PeerId, FilterHash); 2],
+
+    /// The block, once we get it
+    pub block: Option<Block>,
+
+    /// The block's Utreexo proof, once we get it
+    pub proof: Option<UtreexoProof>,
+}
+
+impl FilterDispute {
+    pub fn new(
+        height: u32,
+        block_hash: BlockHash,
+        peers: (PeerId, PeerId),
+        hashes: (FilterHash, FilterHash),
+    ) -> Self {
+        FilterDispute {
+            height,
+            block_hash,
+            claims: [(peers.0, hashes.0), (peers.1, hashes.1)],
+            block: None,
+            proof: None,
+        }
+    }
+
+    /// Builds the filter for this block, using the scripts in its Utreexo proof.
+    ///
+    /// A peer could make up those scripts to get an honest peer banned, so the proof must be
+    /// valid for `acc`, our accumulator before this block. Returns `None` if we don't have the
+    /// block and proof yet, or the proof is invalid.
+    pub fn build_filter<E: From<UtreexoLeafError>>(
+        &self,
+        acc: &Stump,
+        get_block_hash: impl Fn(u32) -> Result<BlockHash, E>,
+        get_median_time_past: impl Fn(u32) -> Result<u32, E>,
+    ) -> Option<BlockFilter> {
+        let (block, uproof) = (self.block.as_ref()?, self.proof.as_ref()?);
+        let (del_hashes, utxos) = proof_util::process_proof(
+            &uproof.leaf_data,
+            &block.txdata,
+            self.height,
+            get_block_hash,
+            get_median_time_past,
+        )
+        .ok()?;
+
+        let proof = Proof {
+            hashes: uproof.proof_hashes.clone(),
+            targets: uproof.targets.clone(),
+        };
+        let del_hashes: Vec<BitcoinNodeHash> = del_hashes.into_iter().map(Into::into).collect();
+        if !acc.verify(&proof, &del_hashes).unwrap_or(false) {
+            return None;
+        }
+
+        BlockFilter::new_script_filter(block, |outpoint| {
+            utxos
+                .get(outpoint)
+                .map(|utxo| utxo.txout.script_pubkey.clone())
+                .ok_or(bip158::Error::UtxoMissing(*outpoint))
+        })
+        .ok()
+    }
+
+    /// Whoever lied, given the filter hash we've built ourselves. Returns `None` if both did.
+    pub fn liar(&self, filter_hash: FilterHash) -> Option<PeerId> {
+        let [(peer1, hash1), (peer2, hash2)] = self.claims;
+        match (hash1 == filter_hash, hash2 == filter_hash) {
+            (true, false) => Some(peer2),
+            (false, true) => Some(peer1),
+            _ => None,
+        }
+    }
+}
+
+/// The index of the first filter hash that isn't the same in both lists
+pub fn first_difference(a: &[FilterHash], b: &[FilterHash]) -> Option<usize> {
+    a.iter().zip(b.iter()).position(|(a, b)| a != b)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bitcoin::bip158::FilterHash;
+    use bitcoin::bip158::FilterHeader;
+    use bitcoin::hashes::Hash;
+
+    use bitcoin::absolute::LockTime;
+    use bitcoin::block::Header;
+    use bitcoin::block::Version;
+    use bitcoin::transaction;
+    use bitcoin::Amount;
+    use bitcoin::CompactTarget;
+    use bitcoin::OutPoint;
+    use bitcoin::ScriptBuf;
+    use bitcoin::Transaction;
+    use bitcoin::TxIn;
+    use bitcoin::TxMerkleNode;
+    use bitcoin::TxOut;
+    use bitcoin::Txid;
+    use floresta_chain::CompactLeafData;
+    use floresta_chain::LeafData;
+    use floresta_chain::ScriptPubKeyKind;
+    use rustreexo::accumulator::mem_forest::MemForest;
+
+    use super::*;
+
+    fn hashes(seed: u8, count: usize) -> Vec<FilterHash> {
+        (0..count)
+            .map(|i| FilterHash::hash(&[seed, i as u8, (i >> 8) as u8]))
+            .collect()
+    }
+
+    #[test]
+    fn test_compare_checkpoints() {
+        let a = FilterHeader::from_byte_array([1; 32]);
+        let b = FilterHeader::from_byte_array([2; 32]);
+        let c = FilterHeader::from_byte_array([3; 32]);
+
+        // A peer that is behind still agrees with us
+        let responses = vec![(0, vec![a, b]), (1, vec![a]), (2, vec![a, b])];
+        let agreed = BTreeMap::from([(1000, a), (2000, b)]);
+        assert_eq!(
+            compare_checkpoints(&responses),
+            CheckpointsAgreement::Agree(agreed)
+        );
+
+        let responses = vec![(0, vec![a, b]), (1, vec![a, b]), (2, vec![a, c])];
+        assert_eq!(
+            compare_checkpoints(&responses),
+            CheckpointsAgreement::Disagree {
+                index: 1,
+                peers: (0, 2)
+            }
+        );
+
+        assert_eq!(
+            compare_checkpoints(&[]),
+            CheckpointsAgreement::Agree(BTreeMap::new())
+        );
+    }
+
+    #[test]
+    fn test_filter_headers_stop() {
+        assert_eq!(filter_headers_stop(0, 5000, 5500), 1000);
+        assert_eq!(filter_headers_stop(1001, 5000, 5500), 3000);
+        assert_eq!(filter_headers_stop(4001, 5000, 5500), 5000);
+        assert_eq!(filter_headers_stop(5001, 5000, 5500), 5500);
+        assert_eq!(filter_headers_stop(5001, 5000, 9000), 7000);
+        assert_eq!(filter_headers_stop(1, 0, 500), 500);
+    }
+
+    #[test]
+    fn test_check_batch() {
+        let previous = FilterHeader::from_byte_array([7; 32]);
+        let honest = hashes(0, 1000);
+        let headers = compute_filter_headers(previous, &honest);
+        let checkpoints = BTreeMap::from([(2000, headers[999])]);
+
+        let mut batch = FilterHeadersBatch::new(1001, 2000, previous, vec![0]);
+        assert_eq!(batch.check(&checkpoints), BatchCheck::Empty);
+
+        batch.responses.push((0, honest.clone()));
+        assert!(batch.is_complete());
+        assert_eq!(
+            batch.check(&checkpoints),
+            BatchCheck::Valid(headers.clone())
+        );
+
+        // Headers that don't chain up to our checkpoint
+        let mut liar = honest.clone();
+        liar[500] = FilterHash::all_zeros();
+        batch.responses = vec![(0, liar.clone())];
+        assert_eq!(batch.check(&checkpoints), BatchCheck::Invalid(0));
+
+        // The wrong number of headers
+        batch.responses = vec![(0, honest[1..].to_vec())];
+        assert_eq!(batch.check(&checkpoints), BatchCheck::Invalid(0));
+
+        // Without checkpoints, we find where two peers disagree
+        let mut batch = FilterHeadersBatch::new(1001, 2000, previous, vec![0, 1]);
+        batch.responses = vec![(0, honest.clone()), (1, liar)];
+        assert_eq!(
+            batch.check(&BTreeMap::new()),
+            BatchCheck::Disagree {
+                height: 1501,
+                peers: (0, 1),
+                hashes: (honest[500], FilterHash::all_zeros()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_filter_dispute_liar() {
+        let [a, b, c] = [0, 1, 2].map(|seed| hashes(seed, 1)[0]);
+        let dispute = FilterDispute::new(1501, BlockHash::all_zeros(), (0, 1), (a, b));
+
+        assert_eq!(dispute.liar(a), Some(1));
+        assert_eq!(dispute.liar(b), Some(0));
+        assert_eq!(dispute.liar(c), None);
+    }
+
+    #[test]
+    fn test_filter_dispute_forged_proof() {
+        // A block spending an output created at height 1, with an anyone-can-spend script
+        let script = ScriptBuf::from_bytes(vec![0x51]);
+        let prevout = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+        let coinbase = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::from_bytes(vec![1, 2]),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: script.clone(),
+            }],
+        };
+        let spend = Transaction {
+            input: vec![TxIn {
+                previous_output: prevout,
+                ..Default::default()
+            }],
+            ..coinbase.clone()
+        };
+        let block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata: vec![coinbase, spend],
+        };
+
+        let leaf = LeafData {
+            block_hash: BlockHash::all_zeros(),
+            prevout,
+            header_code: 1 << 1,
+            utxo: TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: script.clone(),
+            },
+        };
+        let leaf_hash: BitcoinNodeHash = leaf._get_leaf_hashes().into();
+
+        let mut forest = MemForest::new();
+        forest.modify(&[leaf_hash], &[]).unwrap();
+        let acc = Stump::new()
+            .modify(&[leaf_hash], &[], &Proof::default())
+            .unwrap()
+            .0;
+        let proof = forest.prove(&[leaf_hash]).unwrap();
+
+        let uproof = |spk: &ScriptBuf| UtreexoProof {
+            block_hash: block.block_hash(),
+            proof_hashes: proof.hashes.clone(),
+            targets: proof.targets.clone(),
+            leaf_data: vec![CompactLeafData {
+                header_code: leaf.header_code,
+                amount: 1_000,
+                spk_ty: ScriptPubKeyKind::Other(spk.to_bytes().into_boxed_slice()),
+            }],
+        };
+        let filter_hash = |spk: &ScriptBuf| {
+            let filter = BlockFilter::new_script_filter(&block, |_| Ok(spk.clone())).unwrap();
+            FilterHash::hash(&filter.content)
+        };
+
+        // Peer 1 lies about the filter, claiming the block spends another script
+        let forged_script = ScriptBuf::from_bytes(vec![0x52]);
+        let mut dispute = FilterDispute::new(
+            2,
+            block.block_hash(),
+            (0, 1),
+            (filter_hash(&script), filter_hash(&forged_script)),
+        );
+        dispute.block = Some(block.clone());
+
+        let build = |dispute: &FilterDispute| {
+            dispute.build_filter(
+                &acc,
+                |_| Ok::<_, UtreexoLeafError>(BlockHash::all_zeros()),
+                |_| Ok(0),
+            )
+        };
+
+        // A utreexo peer backs that lie with a forged proof, but it isn't valid for our
+        // accumulator, so we don't ban the honest peer
+        dispute.proof = Some(uproof(&forged_script));
+        assert!(build(&dispute).is_none());
+
+        // With the real proof, we find the liar
+        dispute.proof = Some(uproof(&script));
+        let filter = build(&dispute).unwrap();
+        assert_eq!(dispute.liar(FilterHash::hash(&filter.content)), Some(1));
+    }
+}
@@ -4,6 +4,8 @@
 //! Once our transaction is included in a block, we remove it from the mempool.
 use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -19,9 +21,11 @@ use bitcoin::Transaction;
 use bitcoin::TxMerkleNode;
 use bitcoin::TxOut;
 use bitcoin::Txid;
+use bitcoin::Wtxid;
 use floresta_chain::proof_util;
 use floresta_chain::pruned_utreexo::BlockchainInterface;
 use floresta_chain::CompactLeafData;
+use floresta_chain::FeeEstimator;
 use floresta_chain::LeafData;
 use rustreexo::accumulator::node_hash::BitcoinNodeHash;
 use rustreexo::accumulator::pollard::Pollard;
@@ -35,6 +39,24 @@ use rustreexo::accumulator::proof::Proof;
 /// that only we know. This way, peers can't cause collisions and make our mempool slow.
 type ShortTxid = u64;
 
+/// The minimum feerate, in sats per 1000 virtual bytes, for us to accept and relay a transaction
+pub const MIN_RELAY_FEERATE: u64 = 1_000;
+
+/// The maximum weight of a standard transaction
+const MAX_STANDARD_TX_WEIGHT: u64 = 400_000;
+
+/// Transactions smaller than this (without witness) are non-standard, see CVE-2017-12842
+const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+
+/// The maximum size of a standard scriptSig, enough for a 15-of-15 multisig
+const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1_650;
+
+/// The maximum size of a standard `OP_RETURN` output script
+const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// A transaction can't replace more than this many mempool transactions, counting descendants
+const MAX_REPLACEMENT_CANDIDATES: usize = 100;
+
 #[derive(Debug)]
 /// A transaction in the mempool.
 ///
@@ -84,8 +106,16 @@ pub struct Mempool {
     prevouts: HashMap<OutPoint, CompactLeafData>,
     /// A queue of transaction we know about, but don't have a proof for
     queue: Vec<Txid>,
+    /// Maps the short id of a transaction's wtxid to its short txid, so we can find transactions
+    /// announced by wtxid
+    wtxids: HashMap<ShortTxid, ShortTxid>,
+    /// Which mempool transaction spends each outpoint, used to find conflicts
+    spent_outpoints: HashMap<OutPoint, ShortTxid>,
     /// A hasher that we use to compute the short transaction ids.
     hasher: ahash::RandomState,
+    /// If set, we'll tell this estimator about every transaction we accept, so it can learn how
+    /// long transactions take to confirm.
+    fee_estimator: Option<Arc<FeeEstimator>>,
 }
 
 unsafe impl Send for Mempool {}
@@ -111,6 +141,17 @@ pub enum AcceptToMempoolError {
     /// The transaction has duplicate inputs.
     DuplicateInput,
     BlockNotFound,
+    /// We already have this transaction in our mempool.
+    AlreadyInMempool,
+    /// The transaction is valid, but we don't relay it.
+    NonStandard(String),
+    /// The transaction doesn't pay our minimum relay feerate.
+    FeeTooLow,
+    /// The transaction conflicts with mempool transactions, and doesn't pay enough to replace
+    /// them.
+    InsufficientReplacementFee,
+    /// The transaction would replace too many mempool transactions.
+    TooManyReplacements,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -141,13 +182,65 @@ impl Mempool {
             transactions: HashMap::new(),
             prevouts: HashMap::new(),
             queue: Vec::new(),
+            wtxids: HashMap::new(),
+            spent_outpoints: HashMap::new(),
             mempool_size: 0,
             max_mempool_size,
             acc,
             hasher,
+            fee_estimator: None,
         }
     }
 
+    /// Sets the fee estimator that should learn from the transactions in this mempool.
+    pub fn set_fee_estimator(&mut self, fee_estimator: Arc<FeeEstimator>) {
+        self.fee_estimator = Some(fee_estimator);
+    }
+
+    /// Tells our fee estimator (if any) about a transaction that just entered the mempool.
+    ///
+    /// We need the value of every prevout to compute the feerate, they either come from the
+    /// prevouts we know about, or from the outputs of an unconfirmed parent.
+    fn track_feerate(&self, tx: &Transaction) {
+        let Some(fee_estimator) = &self.fee_estimator else {
+            return;
+        };
+
+        let feerate = FeeEstimator::compute_feerate(tx, |prevout| self.get_prevout_value(prevout));
+
+        if let Some(feerate) = feerate {
+            fee_estimator.process_transaction(tx.compute_txid(), feerate);
+        }
+    }
+
+    /// The value of an output spent by a mempool transaction. It's either a prevout we know
+    /// about, or an output of an unconfirmed parent.
+    fn get_prevout_value(&self, prevout: &OutPoint) -> Option<u64> {
+        if let Some(leaf_data) = self.prevouts.get(prevout) {
+            return Some(leaf_data.amount);
+        }
+
+        let short_txid = self.hasher.hash_one(prevout.txid);
+        let parent = self.transactions.get(&short_txid)?;
+        parent
+            .transaction
+            .output
+            .get(prevout.vout as usize)
+            .map(|output| output.value.to_sat())
+    }
+
+    /// Returns the fee paid by a transaction, if we know the value of all its prevouts
+    pub fn get_fee(&self, tx: &Transaction) -> Option<u64> {
+        let input_value = tx
+            .input
+            .iter()
+            .map(|input| self.get_prevout_value(&input.previous_output))
+            .sum::<Option<u64>>()?;
+
+        let output_value: u64 = tx.output.iter().map(|output| output.value.to_sat()).sum();
+        input_value.checked_sub(output_value)
+    }
+
     /// List transactions we are pending to process.
     ///
     /// Usually, we don't have a proof for these transactions, so we can't add them to the mempool,
@@ -167,6 +260,11 @@ impl Mempool {
             .collect()
     }
 
+    /// Iterates over all transactions we've accepted to the mempool.
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.transactions.values().map(|tx| &tx.transaction)
+    }
+
     /// Returns the data of the prevouts that are being spent by a transaction.
     ///
     /// This data isn't part of the actual transaction, usually we would fetch it from the UTXO
@@ -497,6 +595,9 @@ impl Mempool {
             tx.children.push(short_txid);
         }
 
+        self.track_feerate(&transaction);
+        self.index_transaction(short_txid, &transaction);
+
         self.transactions.insert(
             short_txid,
             MempoolTransaction {
@@ -562,6 +663,171 @@ impl Mempool {
             });
         }
 
+        self.track_feerate(&transaction);
+        self.index_transaction(short_txid, &transaction);
+
+        self.transactions.insert(
+            short_txid,
+            MempoolTransaction {
+                time: Instant::now(),
+                depends,
+                transaction,
+                children: Vec::new(),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Adds a transaction we are about to insert to our wtxid and spent outpoint indexes
+    fn index_transaction(&mut self, short_txid: ShortTxid, transaction: &Transaction) {
+        let short_wtxid = self.hasher.hash_one(transaction.compute_wtxid());
+        self.wtxids.insert(short_wtxid, short_txid);
+
+        for input in transaction.input.iter() {
+            self.spent_outpoints
+                .insert(input.previous_output, short_txid);
+        }
+    }
+
+    /// Removes a transaction from the mempool and from all our indexes, unlinking it from its
+    /// parents and children
+    fn remove_transaction(&mut self, short_txid: ShortTxid) -> Option<Transaction> {
+        let removed = self.transactions.remove(&short_txid)?;
+        let transaction = removed.transaction;
+
+        let short_wtxid = self.hasher.hash_one(transaction.compute_wtxid());
+        self.wtxids.remove(&short_wtxid);
+        self.mempool_size = self.mempool_size.saturating_sub(transaction.total_size());
+
+        for input in transaction.input.iter() {
+            if self.spent_outpoints.get(&input.previous_output) == Some(&short_txid) {
+                self.spent_outpoints.remove(&input.previous_output);
+                self.prevouts.remove(&input.previous_output);
+            }
+        }
+
+        for parent in removed.depends.iter() {
+            if let Some(parent) = self.transactions.get_mut(parent) {
+                parent.children.retain(|child| *child != short_txid);
+            }
+        }
+
+        for child in removed.children.iter() {
+            if let Some(child) = self.transactions.get_mut(child) {
+                child.depends.retain(|parent| *parent != short_txid);
+            }
+        }
+
+        Some(transaction)
+    }
+
+    /// Returns a mempool transaction and all its descendants
+    fn with_descendants(&self, short_txid: ShortTxid) -> HashSet<ShortTxid> {
+        let mut descendants = HashSet::new();
+        let mut to_visit = vec![short_txid];
+
+        while let Some(short_txid) = to_visit.pop() {
+            if !descendants.insert(short_txid) {
+                continue;
+            }
+
+            if let Some(tx) = self.transactions.get(&short_txid) {
+                to_visit.extend(tx.children.iter().copied());
+            }
+        }
+
+        descendants
+    }
+
+    /// Adds a transaction relayed by one of our peers to the mempool, returning its fee.
+    ///
+    /// The caller must validate this transaction first, and give us the data for every prevout
+    /// that isn't created by a mempool transaction. Here we only check our relay policy: the
+    /// transaction must be standard, and pay at least [MIN_RELAY_FEERATE].
+    ///
+    /// We do full replace-by-fee, like Bitcoin Core's default: a transaction may replace any
+    /// mempool transaction it conflicts with, whether they signal replaceability or not, as long
+    /// as it passes the fee rules checked by `check_replacement`.
+    pub fn accept_relayed_transaction(
+        &mut self,
+        transaction: Transaction,
+        prevouts: &[(OutPoint, CompactLeafData)],
+    ) -> Result<u64, AcceptToMempoolError> {
+        let txid = transaction.compute_txid();
+        let short_txid = self.hasher.hash_one(txid);
+        if self.transactions.contains_key(&short_txid) {
+            return Err(AcceptToMempoolError::AlreadyInMempool);
+        }
+
+        check_standard(&transaction)?;
+
+        let input_value = transaction
+            .input
+            .iter()
+            .map(|input| {
+                prevouts
+                    .iter()
+                    .find(|(prevout, _)| *prevout == input.previous_output)
+                    .map(|(_, leaf_data)| leaf_data.amount)
+                    .or_else(|| self.get_prevout_value(&input.previous_output))
+            })
+            .sum::<Option<u64>>()
+            .ok_or(AcceptToMempoolError::PrevoutNotFound)?;
+
+        let output_value: u64 = transaction
+            .output
+            .iter()
+            .map(|output| output.value.to_sat())
+            .sum();
+
+        let fee = input_value
+            .checked_sub(output_value)
+            .ok_or(AcceptToMempoolError::InvalidPrevout)?;
+
+        let vsize = transaction.vsize() as u64;
+        let min_fee = MIN_RELAY_FEERATE * vsize / 1000;
+        if fee < min_fee {
+            return Err(AcceptToMempoolError::FeeTooLow);
+        }
+
+        let tx_size = transaction.total_size();
+        if self.mempool_size + tx_size > self.max_mempool_size {
+            return Err(AcceptToMempoolError::MemoryUsageTooHigh);
+        }
+
+        let conflicts = transaction
+            .input
+            .iter()
+            .filter_map(|input| self.spent_outpoints.get(&input.previous_output).copied())
+            .collect::<HashSet<_>>();
+
+        let replaced = conflicts
+            .iter()
+            .flat_map(|conflict| self.with_descendants(*conflict))
+            .collect::<HashSet<_>>();
+
+        if !replaced.is_empty() {
+            self.check_replacement(&transaction, fee, &conflicts, &replaced)?;
+        }
+
+        for short_txid in replaced {
+            self.remove_transaction(short_txid);
+        }
+
+        self.prevouts.extend(prevouts.iter().cloned());
+
+        let depends = self.find_mempool_depends(&transaction);
+        for depend in depends.iter() {
+            if let Some(parent) = self.transactions.get_mut(depend) {
+                parent.children.push(short_txid);
+            }
+        }
+
+        self.mempool_size += tx_size;
+        self.track_feerate(&transaction);
+        self.index_transaction(short_txid, &transaction);
+
         self.transactions.insert(
             short_txid,
             MempoolTransaction {
@@ -572,9 +838,117 @@ impl Mempool {
             },
         );
 
+        Ok(fee)
+    }
+
+    /// Checks whether a transaction may replace the mempool transactions it conflicts with. The
+    /// replacement must:
+    ///
+    /// - Evict at most [MAX_REPLACEMENT_CANDIDATES] transactions, counting descendants
+    /// - Not spend any of the transactions it evicts
+    /// - Have a higher feerate than every transaction it directly conflicts with
+    /// - Pay the fees of every evicted transaction, plus its own relay at [MIN_RELAY_FEERATE]
+    fn check_replacement(
+        &self,
+        transaction: &Transaction,
+        fee: u64,
+        conflicts: &HashSet<ShortTxid>,
+        replaced: &HashSet<ShortTxid>,
+    ) -> Result<(), AcceptToMempoolError> {
+        if replaced.len() > MAX_REPLACEMENT_CANDIDATES {
+            return Err(AcceptToMempoolError::TooManyReplacements);
+        }
+
+        // A replacement can't spend the transactions it replaces
+        let spends_replaced = transaction
+            .input
+            .iter()
+            .any(|input| replaced.contains(&self.hasher.hash_one(input.previous_output.txid)));
+
+        if spends_replaced {
+            return Err(AcceptToMempoolError::ConflictingTransaction);
+        }
+
+        let vsize = transaction.vsize() as u64;
+        let mut replaced_fees = 0;
+        for short_txid in replaced {
+            let replaced_tx = &self.transactions[short_txid].transaction;
+            let replaced_fee = self
+                .get_fee(replaced_tx)
+                .ok_or(AcceptToMempoolError::ConflictingTransaction)?;
+
+            // The replacement must have a higher feerate than every transaction it directly
+            // conflicts with
+            let replaced_vsize = replaced_tx.vsize() as u64;
+            if conflicts.contains(short_txid) && fee * replaced_vsize <= replaced_fee * vsize {
+                return Err(AcceptToMempoolError::InsufficientReplacementFee);
+            }
+
+            replaced_fees += replaced_fee;
+        }
+
+        // And pay for the bandwidth used to relay it, on top of the fees it replaces
+        if fee < replaced_fees + MIN_RELAY_FEERATE * vsize / 1000 {
+            return Err(AcceptToMempoolError::InsufficientReplacementFee);
+        }
+
         Ok(())
     }
 
+    /// Removes the transactions confirmed in a block, and the ones that conflict with them.
+    ///
+    /// Transactions spending outputs from this block stay in the mempool, and we remember those
+    /// outputs as prevouts, since their parents aren't in the mempool anymore.
+    pub fn remove_confirmed(&mut self, block: &Block, height: u32) {
+        if self.transactions.is_empty() && self.prevouts.is_empty() {
+            return;
+        }
+
+        for tx in block.txdata.iter() {
+            let txid = tx.compute_txid();
+            let is_coinbase = tx.is_coinbase();
+
+            for (vout, output) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint::new(txid, vout as u32);
+                if !self.spent_outpoints.contains_key(&outpoint) {
+                    continue;
+                }
+
+                let leaf_data = CompactLeafData {
+                    amount: output.value.to_sat(),
+                    spk_ty: proof_util::get_script_type(&output.script_pubkey),
+                    header_code: (height << 1) | is_coinbase as u32,
+                };
+                self.prevouts.insert(outpoint, leaf_data);
+            }
+
+            self.remove_transaction(self.hasher.hash_one(txid));
+
+            for input in tx.input.iter() {
+                if let Some(conflict) = self.spent_outpoints.get(&input.previous_output).copied() {
+                    for short_txid in self.with_descendants(conflict) {
+                        self.remove_transaction(short_txid);
+                    }
+                }
+
+                self.prevouts.remove(&input.previous_output);
+            }
+        }
+    }
+
+    /// Returns the data of a prevout spent by a mempool transaction, if it isn't created by
+    /// another mempool transaction
+    pub fn get_prevout_data(&self, outpoint: &OutPoint) -> Option<&CompactLeafData> {
+        self.prevouts.get(outpoint)
+    }
+
+    /// Get a transaction from the mempool by its wtxid.
+    pub fn get_by_wtxid(&self, wtxid: &Wtxid) -> Option<&Transaction> {
+        let short_wtxid = self.hasher.hash_one(wtxid);
+        let short_txid = self.wtxids.get(&short_wtxid)?;
+        self.transactions.get(short_txid).map(|tx| &tx.transaction)
+    }
+
     /// Get a transaction from the mempool.
     pub fn get_from_mempool<'a>(&'a self, id: &Txid) -> Option<&'a Transaction> {
         let id = self.hasher.hash_one(id);
@@ -596,6 +970,66 @@ impl Mempool {
     }
 }
 
+/// Checks whether we should relay a transaction, following Bitcoin Core's standardness rules
+pub fn check_standard(transaction: &Transaction) -> Result<(), AcceptToMempoolError> {
+    let non_standard = |reason: &str| Err(AcceptToMempoolError::NonStandard(reason.into()));
+
+    if !matches!(transaction.version.0, 1 | 2) {
+        return non_standard("version");
+    }
+
+    if transaction.weight().to_wu() > MAX_STANDARD_TX_WEIGHT {
+        return non_standard("tx-size");
+    }
+
+    if transaction.base_size() < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        return non_standard("tx-size-small");
+    }
+
+    for input in transaction.input.iter() {
+        if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return non_standard("scriptsig-size");
+        }
+
+        if !input.script_sig.is_push_only() {
+            return non_standard("scriptsig-not-pushonly");
+        }
+    }
+
+    let mut op_returns = 0;
+    for output in transaction.output.iter() {
+        let script = &output.script_pubkey;
+        if script.is_op_return() {
+            if script.len() > MAX_OP_RETURN_RELAY {
+                return non_standard("scriptpubkey");
+            }
+
+            op_returns += 1;
+            continue;
+        }
+
+        let is_standard = script.is_p2pkh()
+            || script.is_p2sh()
+            || script.is_witness_program()
+            || script.is_p2pk()
+            || script.is_multisig();
+
+        if !is_standard {
+            return non_standard("scriptpubkey");
+        }
+
+        if output.value < script.minimal_non_dust() {
+            return non_standard("dust");
+        }
+    }
+
+    if op_returns > 1 {
+        return non_standard("multi-op-return");
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -615,10 +1049,12 @@ mod tests {
     use bitcoin::Sequence;
     use bitcoin::Target;
     use bitcoin::Transaction;
+    use bitcoin::WPubkeyHash;
     use bitcoin::Witness;
     use floresta_chain::proof_util;
     use floresta_chain::CompactLeafData;
     use floresta_chain::LeafData;
+    use floresta_chain::ScriptPubKeyKind;
     use floresta_common::acchashes;
     use floresta_common::assert_ok;
     use floresta_common::bhash;
@@ -629,6 +1065,8 @@ mod tests {
     use rustreexo::accumulator::pollard::PollardAddition;
     use rustreexo::accumulator::proof::Proof;
 
+    use super::check_standard;
+    use super::AcceptToMempoolError;
     use super::BlockHashOracle;
     use super::Mempool;
     use crate::mempool::MempoolProof;
@@ -1021,4 +1459,160 @@ mod tests {
 
         check_block_transactions(block);
     }
+
+    /// A standard transaction spending one output and creating a P2WPKH output
+    fn relay_transaction(previous_output: OutPoint, value: u64) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([1; 20])),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_check_standard() {
+        let outpoint = OutPoint::new(bitcoin::Txid::all_zeros(), 0);
+        let tx = relay_transaction(outpoint, 10_000);
+        assert_ok!(check_standard(&tx));
+
+        let mut non_standard = tx.clone();
+        non_standard.version = Version(3);
+        assert!(check_standard(&non_standard).is_err());
+
+        let mut dust = tx.clone();
+        dust.output[0].value = bitcoin::Amount::from_sat(1);
+        assert_eq!(
+            check_standard(&dust),
+            Err(AcceptToMempoolError::NonStandard("dust".into()))
+        );
+
+        // OP_RETURN OP_PUSHDATA1 <80 bytes> is the biggest standard OP_RETURN
+        let op_return = |len: u8| {
+            let mut script = vec![0x6a, 0x4c, len];
+            script.extend(vec![0; len as usize]);
+            ScriptBuf::from_bytes(script)
+        };
+
+        let mut big_op_return = tx.clone();
+        big_op_return.output.push(bitcoin::TxOut {
+            value: bitcoin::Amount::ZERO,
+            script_pubkey: op_return(80),
+        });
+        assert_ok!(check_standard(&big_op_return));
+        big_op_return.output[1].script_pubkey = op_return(81);
+        assert!(check_standard(&big_op_return).is_err());
+
+        let mut unknown_script = tx.clone();
+        unknown_script.output[0].script_pubkey = ScriptBuf::from_bytes(vec![0x51, 0x51]);
+        assert!(check_standard(&unknown_script).is_err());
+
+        let mut not_push_only = tx;
+        not_push_only.input[0].script_sig = ScriptBuf::from_bytes(vec![0x51, 0x93]);
+        assert!(check_standard(&not_push_only).is_err());
+    }
+
+    #[test]
+    fn test_accept_relayed_transaction() {
+        let mut mempool = Mempool::new(Pollard::default(), 10_000_000);
+
+        let outpoint = OutPoint::new(bitcoin::Txid::all_zeros(), 0);
+        let leaf_data = CompactLeafData {
+            amount: 100_000,
+            spk_ty: ScriptPubKeyKind::WitnessV0PubKeyHash,
+            header_code: 10 << 1,
+        };
+        let prevouts = [(outpoint, leaf_data)];
+
+        // 82 vbytes, paying less than 1 sat/vbyte
+        let cheap = relay_transaction(outpoint, 99_950);
+        assert_eq!(
+            mempool.accept_relayed_transaction(cheap, &prevouts),
+            Err(AcceptToMempoolError::FeeTooLow)
+        );
+
+        let original = relay_transaction(outpoint, 99_000);
+        assert_eq!(
+            mempool.accept_relayed_transaction(original.clone(), &prevouts),
+            Ok(1_000)
+        );
+        assert_eq!(
+            mempool.get_by_wtxid(&original.compute_wtxid()),
+            Some(&original)
+        );
+        assert_eq!(
+            mempool.accept_relayed_transaction(original.clone(), &prevouts),
+            Err(AcceptToMempoolError::AlreadyInMempool)
+        );
+
+        // A replacement must pay the original fees, plus its own relay fee
+        let replacement = relay_transaction(outpoint, 98_950);
+        assert_eq!(
+            mempool.accept_relayed_transaction(replacement, &[]),
+            Err(AcceptToMempoolError::InsufficientReplacementFee)
+        );
+
+        let replacement = relay_transaction(outpoint, 98_000);
+        assert_eq!(
+            mempool.accept_relayed_transaction(replacement.clone(), &[]),
+            Ok(2_000)
+        );
+        assert!(mempool.get_from_mempool(&original.compute_txid()).is_none());
+
+        // A child doesn't need prevouts, they come from its parent
+        let child_outpoint = OutPoint::new(replacement.compute_txid(), 0);
+        let child = relay_transaction(child_outpoint, 97_000);
+        assert_eq!(
+            mempool.accept_relayed_transaction(child.clone(), &[]),
+            Ok(1_000)
+        );
+
+        // Once the parent confirms, we remember the output spent by the child
+        let block = Block {
+            header: genesis_header(),
+            txdata: vec![replacement.clone()],
+        };
+        mempool.remove_confirmed(&block, 11);
+
+        assert!(mempool
+            .get_from_mempool(&replacement.compute_txid())
+            .is_none());
+        assert_eq!(
+            mempool.get_from_mempool(&child.compute_txid()),
+            Some(&child)
+        );
+        assert_eq!(
+            mempool
+                .get_prevout_data(&child_outpoint)
+                .unwrap()
+                .header_code,
+            11 << 1
+        );
+        assert!(mempool.get_prevout_data(&outpoint).is_none());
+
+        // And a block spending the same output evicts the child
+        let conflict = relay_transaction(child_outpoint, 90_000);
+        let block = Block {
+            header: genesis_header(),
+            txdata: vec![conflict],
+        };
+        mempool.remove_confirmed(&block, 12);
+
+        assert!(mempool.transactions.is_empty());
+        assert!(mempool.spent_outpoints.is_empty());
+        assert!(mempool.wtxids.is_empty());
+        assert_eq!(mempool.mempool_size, 0);
+    }
+
+    fn genesis_header() -> block::Header {
+        bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header
+    }
 }
@@ -1,10 +1,12 @@
 //! Main module for the p2p chain. This is a blockchain provider, just like cli-chain, but it's
 //! backed by p2p Bitcoin's p2p network.
 
+use std::net::IpAddr;
 use std::net::SocketAddr;
 
 use bitcoin::Network;
 use floresta_chain::AssumeUtreexoValue;
+use floresta_chain::ChainParams;
 
 #[derive(Debug, Clone)]
 /// Configuration for the Utreexo node.
@@ -53,6 +55,12 @@ pub struct UtreexoNodeConfig {
     /// is that we are vulnerable to a fraud proof attack for a few hours, but we can spot it
     /// and react in a couple of hours at most, so the attack window is very small.
     pub backfill: bool,
+    /// Into how many height ranges we split the backfill, each one validated in parallel.
+    /// Defaults to 4.
+    ///
+    /// Each range needs an accumulator to start from, that we get from our peers with PoW fraud
+    /// proofs. If we can't find it, that range is merged with the previous one.
+    pub backfill_ranges: usize,
     /// If we are using network-provided block filters, we may not need to download the whole
     /// chain of filters, as our wallets may not have been created at the beginning of the chain.
     /// With this option, we can make a rough estimate of the block height we need to start
@@ -69,6 +77,42 @@ pub struct UtreexoNodeConfig {
     pub allow_v1_fallback: bool,
     /// Whether to disable DNS seeds. Defaults to false.
     pub disable_dns_seeds: bool,
+    /// Custom parameters for the chain we are in, like a custom signet. Defaults to None,
+    /// meaning the default parameters for `network`.
+    ///
+    /// We take the network magic and DNS seeds from here.
+    pub chain_params: Option<ChainParams>,
+    /// Whether to run as a Utreexo bridge. Defaults to false.
+    ///
+    /// A bridge keeps the full accumulator forest on disk, so it can prove every block by
+    /// itself and answer `getuproof` requests from our peers. Since the forest is built block
+    /// by block, this requires validating the whole chain from genesis, and can't be used
+    /// with `assume_utreexo` or `pow_fraud_proofs`.
+    pub bridge: bool,
+    /// How many of our most recent blocks we keep on disk, with their Utreexo proofs, to serve
+    /// them to our peers and answer requests for recent blocks. Defaults to 0, meaning we don't
+    /// keep any block.
+    ///
+    /// We only advertise `NODE_NETWORK_LIMITED` if this is at least 288 blocks, as required by
+    /// BIP159, and `block_cache_size` isn't set, since it could evict some of them.
+    pub block_cache_depth: u32,
+    /// How many bytes our cached blocks may take. If we need more than this, the oldest ones are
+    /// removed, even if they are within `block_cache_depth`. Defaults to None, no limit.
+    pub block_cache_size: Option<u64>,
+    /// The address we listen on for inbound connections. Defaults to None, meaning we only
+    /// make outbound connections.
+    pub listen: Option<SocketAddr>,
+    /// Maximum number of inbound connections. Defaults to 32.
+    ///
+    /// If all inbound slots are taken, we evict one of our inbound peers to make room for the
+    /// new one, if we can find a peer worth evicting.
+    pub max_inbound: u32,
+    /// Addresses of peers that may always connect to us, like our own light clients. Defaults
+    /// to empty.
+    ///
+    /// Inbound peers from these addresses are never evicted or banned, and may connect even if
+    /// all inbound slots are taken.
+    pub whitelist: Vec<IpAddr>,
 }
 
 impl Default for UtreexoNodeConfig {
@@ -85,18 +129,31 @@ impl Default for UtreexoNodeConfig {
             datadir: ".floresta-node".to_string(),
             proxy: None,
             backfill: false,
+            backfill_ranges: 4,
             assume_utreexo: None,
             filter_start_height: None,
             user_agent: format!("floresta:{}", env!("CARGO_PKG_VERSION")),
             allow_v1_fallback: true,
+            chain_params: None,
+            bridge: false,
+            block_cache_depth: 0,
+            block_cache_size: None,
+            listen: None,
+            max_inbound: 32,
+            whitelist: Vec::new(),
         }
     }
 }
 
 pub mod address_man;
+pub mod backfill;
+pub mod block_cache;
 pub mod block_proof;
+pub mod bridge;
 pub mod chain_selector;
+pub mod compact_block;
 pub mod error;
+pub mod filter_headers;
 pub mod mempool;
 pub mod node;
 pub mod node_context;
@@ -109,3 +166,4 @@ pub mod sync_node;
 #[doc(hidden)]
 pub mod tests;
 pub mod transport;
+pub mod tx_relay;
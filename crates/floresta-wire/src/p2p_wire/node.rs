@@ -15,29 +15,54 @@ use std::time::Instant;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
+use bitcoin::bip152::BlockTransactions;
+use bitcoin::bip152::BlockTransactionsRequest;
+use bitcoin::bip152::HeaderAndShortIds;
+use bitcoin::bip158;
+use bitcoin::bip158::BlockFilter;
+use bitcoin::bip158::FilterHeader;
+use bitcoin::block::Header;
+use bitcoin::constants::genesis_block;
+use bitcoin::hashes::Hash;
 use bitcoin::p2p::address::AddrV2;
 use bitcoin::p2p::address::AddrV2Message;
+use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::p2p::message_filter::CFCheckpt;
+use bitcoin::p2p::message_filter::CFHeaders;
+use bitcoin::p2p::message_filter::CFilter;
+use bitcoin::p2p::message_filter::GetCFCheckpt;
+use bitcoin::p2p::message_filter::GetCFHeaders;
+use bitcoin::p2p::message_filter::GetCFilters;
+use bitcoin::p2p::Magic;
 use bitcoin::p2p::ServiceFlags;
 use bitcoin::Block;
 use bitcoin::BlockHash;
 use bitcoin::Network;
+use bitcoin::ScriptBuf;
 use bitcoin::Txid;
 use floresta_chain::proof_util;
 use floresta_chain::proof_util::UtreexoLeafError;
 use floresta_chain::BlockValidationErrors;
 use floresta_chain::BlockchainError;
 use floresta_chain::ChainBackend;
+use floresta_chain::ChainParams;
 use floresta_chain::CompactLeafData;
+use floresta_chain::HeadersSyncState;
+use floresta_chain::MAX_HEADERS_RESULTS;
 use floresta_common::service_flags;
 use floresta_common::service_flags::UTREEXO;
 use floresta_common::FractionAvg;
 use floresta_compact_filters::flat_filters_store::FlatFiltersStore;
 use floresta_compact_filters::network_filters::NetworkFilters;
+use floresta_compact_filters::IterableFilterStoreError;
 use rand::seq::SliceRandom;
 use rustreexo::accumulator::proof::Proof;
+use rustreexo::accumulator::stump::Stump;
 use serde::Deserialize;
 use serde::Serialize;
 use tokio::net::tcp::WriteHalf;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
 use tokio::spawn;
 use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::mpsc::UnboundedReceiver;
@@ -53,9 +78,20 @@ use tracing::warn;
 use super::address_man::AddressMan;
 use super::address_man::AddressState;
 use super::address_man::LocalAddress;
+use super::backfill;
+use super::backfill::BackfillTracker;
+use super::block_cache::BlockCache;
 use super::block_proof::Bitmap;
+use super::block_proof::GetUtreexoProof;
+use super::bridge;
+use super::bridge::BridgeError;
+use super::bridge::BridgeHandle;
+use super::compact_block::COMPACT_BLOCKS_VERSION;
 use super::error::AddrParseError;
 use super::error::WireError;
+use super::filter_headers::CHECKPOINT_INTERVAL;
+use super::filter_headers::MAX_FILTER_HEADERS;
+use super::filter_headers::MAX_GETCFILTERS_SIZE;
 use super::mempool::Mempool;
 use super::mempool::MempoolProof;
 use super::node_context::NodeContext;
@@ -71,6 +107,7 @@ use super::running_node::RunningNode;
 use super::socks::Socks5StreamBuilder;
 use super::transport;
 use super::transport::TransportProtocol;
+use super::tx_relay::TxAnnouncement;
 use super::UtreexoNodeConfig;
 use crate::block_proof::UtreexoProof;
 use crate::node_context::PeerId;
@@ -82,14 +119,24 @@ const HARDCODED_ADDRESSES_GRACE_PERIOD: Duration = Duration::from_secs(60);
 /// How long before we try to get addresses from DNS seeds again (5 minutes)
 const DNS_SEED_RETRY_PERIOD: Duration = Duration::from_secs(5 * 60);
 
+/// How many headers a peer may give us that aren't in our best chain, before we disconnect it.
+/// Fork headers are saved forever, so we don't want a single peer to fill our disk with them.
+pub(crate) const MAX_FORK_HEADERS_PER_PEER: u32 = 2016;
+
+/// How many of our oldest inbound peers are never evicted to make room for new ones. Someone
+/// opening lots of connections to us can't take over these slots.
+pub(crate) const PROTECTED_INBOUND_PEERS: usize = 4;
+
 #[derive(Debug)]
 pub enum NodeNotification {
     DnsSeedAddresses(Vec<LocalAddress>),
     FromPeer(u32, PeerMessages),
     FromUser(UserRequest, oneshot::Sender<NodeResponse>),
+    /// A peer connected to our listener
+    InboundConnection(TcpStream, SocketAddr),
 }
 
-#[derive(Debug, Clone, PartialEq, Hash)]
+#[derive(Debug, Clone, PartialEq)]
 /// Sent from node to peers, usually to request something
 pub enum NodeRequest {
     /// Request the full block data for one or more blocks
@@ -120,6 +167,13 @@ pub enum NodeRequest {
     /// starting at a given block hash and height.
     GetFilter((BlockHash, u32)),
 
+    /// Requests the peer to send us the compact filter hashes for blocks starting at a given
+    /// height, up to a given block hash, so we can build their filter headers
+    GetFilterHeaders((BlockHash, u32)),
+
+    /// Requests the peer to send us a filter header every 1000 blocks, up to a given block hash
+    GetFilterCheckpoint(BlockHash),
+
     /// Sends a ping to the peer to check if it's alive
     Ping,
 
@@ -131,6 +185,46 @@ pub enum NodeRequest {
     /// Proof hashes are the hashes needed to reconstruct the proof, while
     /// leaf data are the actual data of the leaves (i.e., the txouts).
     GetBlockProof((BlockHash, Bitmap, Bitmap)),
+
+    /// Sends a Utreexo proof to a peer that asked for it
+    SendUtreexoProof(UtreexoProof),
+
+    /// Sends a block to a peer that asked for it
+    SendBlock(Block),
+
+    /// Sends some transactions from a block to a peer that asked for them
+    SendBlockTxn(BlockTransactions),
+
+    /// Tells a peer that we don't have the data it asked for
+    SendNotFound(Inventory),
+
+    /// Requests a block as a compact block, see [compact_block](super::compact_block)
+    GetCompactBlock(BlockHash),
+
+    /// Requests the transactions we are missing to rebuild a compact block
+    GetBlockTxn(BlockTransactionsRequest),
+
+    /// Asks the peer to send us compact blocks, either right away (high-bandwidth) or after
+    /// announcing them (low-bandwidth)
+    SendCmpct(bool),
+
+    /// Sends a compact block to a peer that asked for it
+    SendCompactBlock(Box<HeaderAndShortIds>),
+
+    /// Announces a transaction we've accepted to our mempool, see [tx_relay](super::tx_relay)
+    AnnounceTransaction(TxAnnouncement),
+
+    /// Asks a utreexo peer for an announced transaction, with the proof for its inputs
+    GetUtreexoTransaction(Inventory),
+
+    /// Sends a compact block filter to a peer that asked for it
+    SendFilter(CFilter),
+
+    /// Sends the filter hashes for some blocks to a peer that asked for them
+    SendFilterHeaders(CFHeaders),
+
+    /// Sends our filter header checkpoints to a peer that asked for them
+    SendFilterCheckpoint(CFCheckpt),
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
@@ -150,8 +244,18 @@ pub(crate) enum InflightRequests {
     /// Requests the peer to send us the compact filters for blocks
     GetFilters,
 
+    /// Requests the peer to send us filter headers, see [filter_headers](super::filter_headers)
+    GetFilterHeaders(PeerId),
+
+    /// Requests the peer to send us its filter header checkpoints
+    GetFilterCheckpoint(PeerId),
+
     /// Requests the peer to send us the utreexo proof for a given block
     UtreexoProof(BlockHash),
+
+    /// Requests the peer to send us a block and its utreexo proof, to find out who lied about
+    /// its filter, see [FilterDispute](super::filter_headers::FilterDispute)
+    FilterDispute(BlockHash),
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -159,6 +263,8 @@ pub enum ConnectionKind {
     Feeler,
     Regular(ServiceFlags),
     Extra,
+    /// A connection opened by our peer, see [UtreexoNodeConfig::listen]
+    Inbound,
 }
 
 impl Serialize for ConnectionKind {
@@ -170,6 +276,7 @@ impl Serialize for ConnectionKind {
             ConnectionKind::Feeler => serializer.serialize_str("feeler"),
             ConnectionKind::Regular(_) => serializer.serialize_str("regular"),
             ConnectionKind::Extra => serializer.serialize_str("extra"),
+            ConnectionKind::Inbound => serializer.serialize_str("inbound"),
         }
     }
 }
@@ -188,6 +295,27 @@ pub struct LocalPeerView {
     pub(crate) height: u32,
     pub(crate) banscore: u32,
     pub(crate) transport_protocol: TransportProtocol,
+    /// How many headers this peer gave us that aren't in our best chain, see
+    /// [MAX_FORK_HEADERS_PER_PEER]
+    pub(crate) fork_headers: u32,
+    /// When we've opened (or accepted) this connection
+    pub(crate) connected_at: Instant,
+    /// Whether this peer can send us compact blocks, see [compact_block](super::compact_block)
+    pub(crate) compact_blocks: bool,
+}
+
+#[derive(Debug)]
+/// What to do with a peer's headers, after [UtreexoNode::presync_headers]
+pub(crate) enum HeadersPresync {
+    /// These headers can be accepted, this peer's chain has the minimum chain work
+    Accept(Vec<Header>),
+    /// We are still presyncing, these headers can be accepted (if any), and we should ask this
+    /// peer for more headers with this locator
+    Continue(Vec<Header>, Vec<BlockHash>),
+    /// This peer's chain doesn't have the minimum chain work
+    LowWork,
+    /// This peer sent headers that don't match its presync
+    Invalid,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -203,6 +331,13 @@ impl Default for RunningNode {
             last_address_rearrange: Instant::now(),
             last_invs: HashMap::default(),
             inflight_filters: BTreeMap::new(),
+            filter_checkpoints: None,
+            checkpoint_responses: Vec::new(),
+            filter_headers_batch: None,
+            filter_dispute: None,
+            partial_blocks: HashMap::new(),
+            high_bandwidth_peers: Vec::new(),
+            tx_requests: HashMap::new(),
         }
     }
 }
@@ -236,6 +371,18 @@ pub struct NodeCommon<Chain: ChainBackend> {
     pub(crate) mempool: Arc<tokio::sync::Mutex<Mempool>>,
     pub(crate) block_filters: Option<Arc<NetworkFilters<FlatFiltersStore>>>,
     pub(crate) last_filter: BlockHash,
+    /// The accumulators our peers agreed on, that we use as starting points for the backfill
+    /// ranges, see [backfill](super::backfill)
+    pub(crate) backfill_checkpoints: Vec<(u32, Stump)>,
+    /// The progress of the backfill tasks, if we've started them
+    pub(crate) backfill: Arc<std::sync::Mutex<BackfillTracker>>,
+    /// Our Utreexo bridge, if we are running as one. See [bridge](super::bridge)
+    pub(crate) bridge: Option<BridgeHandle>,
+    /// Our most recent blocks, if we keep them. See [block_cache](super::block_cache)
+    pub(crate) block_cache: Option<BlockCache>,
+    /// The headers presync of each peer whose chain doesn't have the minimum chain work yet, see
+    /// [HeadersSyncState]
+    pub(crate) headers_sync: HashMap<PeerId, HeadersSyncState>,
 
     // 2. Peer Management
     pub(crate) peer_id_count: u32,
@@ -245,6 +392,11 @@ pub struct NodeCommon<Chain: ChainBackend> {
     pub(crate) max_banscore: u32,
     pub(crate) address_man: AddressMan,
     pub(crate) added_peers: Vec<AddedPeerInfo>,
+    /// The socket we accept inbound connections on, until we start listening on it
+    pub(crate) listener: Option<std::net::TcpListener>,
+    /// When we've banned the inbound peers from each address. Those aren't in our address
+    /// manager, so we keep their bans here
+    pub(crate) inbound_bans: HashMap<IpAddr, Instant>,
 
     // 3. Internal Communication
     pub(crate) node_rx: UnboundedReceiver<NodeNotification>,
@@ -274,6 +426,7 @@ pub struct NodeCommon<Chain: ChainBackend> {
     pub(crate) config: UtreexoNodeConfig,
     pub(crate) datadir: String,
     pub(crate) network: Network,
+    pub(crate) chain_params: ChainParams,
     pub(crate) kill_signal: Arc<tokio::sync::RwLock<bool>>,
 }
 
@@ -349,6 +502,27 @@ where
             .map(|address| Self::resolve_connect_host(address, Self::get_port(config.network)))
             .transpose()?;
 
+        let chain_params = config
+            .chain_params
+            .clone()
+            .unwrap_or_else(|| config.network.into());
+
+        let bridge = match config.bridge {
+            true => Some(Self::start_bridge(&config.datadir, &chain)?),
+            false => None,
+        };
+
+        let listener = config.listen.map(std::net::TcpListener::bind).transpose()?;
+
+        let block_cache = match config.block_cache_depth {
+            0 => None,
+            depth => Some(BlockCache::new(
+                &config.datadir,
+                depth,
+                config.block_cache_size,
+            )?),
+        };
+
         Ok(UtreexoNode {
             common: NodeCommon {
                 last_dns_seed_call: Instant::now(),
@@ -356,6 +530,11 @@ where
                 block_sync_avg: FractionAvg::new(0, 0),
                 last_filter: chain.get_block_hash(0).unwrap(),
                 block_filters,
+                backfill_checkpoints: Vec::new(),
+                backfill: Arc::new(std::sync::Mutex::new(BackfillTracker::default())),
+                bridge,
+                block_cache,
+                headers_sync: HashMap::new(),
                 inflight: HashMap::new(),
                 inflight_user_requests: HashMap::new(),
                 peer_id_count: 0,
@@ -366,6 +545,7 @@ where
                 peer_by_service: HashMap::new(),
                 mempool,
                 network: config.network,
+                chain_params,
                 node_rx,
                 node_tx,
                 address_man,
@@ -384,11 +564,65 @@ where
                 config,
                 kill_signal,
                 added_peers: Vec::new(),
+                listener,
+                inbound_bans: HashMap::new(),
             },
             context: T::default(),
         })
     }
 
+    /// Starts our Utreexo bridge, making sure its forest is at the same block as our chain.
+    ///
+    /// The forest is built by connecting every block since genesis, so if it's behind (e.g. we
+    /// used assumeutreexo before), we can't use it. If it's ahead or in a stale fork, we roll it
+    /// back.
+    fn start_bridge(datadir: &str, chain: &Chain) -> Result<BridgeHandle, WireError> {
+        let bridge = BridgeHandle::start(datadir, chain.get_block_hash(0)?)?;
+
+        let validation_index = chain.get_validation_index()?;
+        Self::roll_back_bridge(&bridge, chain, validation_index)?;
+
+        let (height, tip) = bridge.tip()?;
+        if height != validation_index || chain.get_block_hash(height)? != tip {
+            return Err(BridgeError::OutOfSync(height, validation_index).into());
+        }
+
+        info!("Utreexo bridge loaded at height {height}");
+        Ok(bridge)
+    }
+
+    /// Rolls our bridge back to the last block it has in common with our chain, up to `height`.
+    /// Its forest is left behind the chain after a reorg, so we call this before proving a block.
+    fn roll_back_bridge(
+        bridge: &BridgeHandle,
+        chain: &Chain,
+        height: u32,
+    ) -> Result<(), WireError> {
+        let (bridge_height, bridge_tip) = bridge.tip()?;
+        if bridge_height <= height && chain.get_block_hash(bridge_height).ok() == Some(bridge_tip) {
+            return Ok(());
+        }
+
+        // Walk back until we find a block in both our chain and the bridge
+        let mut fork_height = bridge_height.min(height);
+        loop {
+            let Some(block_hash) = bridge.block_hash(fork_height)? else {
+                return Err(BridgeError::ReorgTooDeep(fork_height).into());
+            };
+
+            if chain.get_block_hash(fork_height).ok() == Some(block_hash) {
+                break;
+            }
+
+            fork_height = fork_height
+                .checked_sub(1)
+                .ok_or(BridgeError::ReorgTooDeep(0))?;
+        }
+
+        warn!("Rolling our utreexo bridge back from height {bridge_height} to {fork_height}");
+        Ok(bridge.rollback(fork_height)?)
+    }
+
     /// Checks whether some of our inflight requests have timed out.
     ///
     /// This function will check if any of our inflight requests have timed out, and if so,
@@ -604,8 +838,25 @@ where
 
                 return;
             }
-            UserRequest::Block(block_hash) => NodeRequest::GetBlock(vec![block_hash]),
+            UserRequest::Block(block_hash) => {
+                if let Some(block) = self.get_cached_block(&block_hash) {
+                    try_and_log!(responder.send(NodeResponse::Block(Some(block))));
+                    return;
+                }
+
+                NodeRequest::GetBlock(vec![block_hash])
+            }
             UserRequest::UtreexoProof(block_hash) => {
+                if let Some(proof) = self.get_cached_proof(&block_hash) {
+                    let proof = Proof {
+                        hashes: proof.proof_hashes,
+                        targets: proof.targets,
+                    };
+
+                    try_and_log!(responder.send(NodeResponse::UtreexoProof(Some(proof))));
+                    return;
+                }
+
                 NodeRequest::GetBlockProof((block_hash, Bitmap::default(), Bitmap::default()))
             }
             UserRequest::MempoolTransaction(txid) => NodeRequest::MempoolTransaction(txid),
@@ -613,6 +864,12 @@ where
                 self.handle_get_peer_info(responder);
                 return;
             }
+            UserRequest::GetBackfillProgress => {
+                let assumed = backfill::has_assumed_blocks(&self.config.datadir);
+                let progress = self.backfill.lock().unwrap().progress(assumed);
+                try_and_log!(responder.send(NodeResponse::GetBackfillProgress(progress)));
+                return;
+            }
             UserRequest::Add((addr, port, v2transport)) => {
                 let node_response =
                     match self.handle_addnode_add_peer(addr, port, v2transport).await {
@@ -766,6 +1023,12 @@ where
             block_hash, inflight_block.peer
         );
 
+        // Our bridge will prove this block once we process it
+        if self.bridge.is_some() {
+            self.blocks.insert(block_hash, inflight_block);
+            return Ok(());
+        }
+
         self.send_to_random_peer(
             NodeRequest::GetBlockProof((block_hash, Bitmap::new(), Bitmap::new())),
             UTREEXO.into(),
@@ -803,7 +1066,7 @@ where
                 return Ok(());
             };
 
-            if block.proof.is_none() {
+            if block.proof.is_none() && self.bridge.is_none() {
                 // If the block doesn't have a proof, we can't process it
                 return Ok(());
             }
@@ -844,18 +1107,52 @@ where
             .remove(&block_hash)
             .ok_or(WireError::BlockNotFound)?;
 
-        let leaf_data = inflight_block
-            .leaf_data
-            .ok_or(WireError::LeafDataNotFound)?;
-
-        let proof = inflight_block.proof.ok_or(WireError::BlockProofNotFound)?;
         let block = inflight_block.block;
         let peer = inflight_block.peer;
 
-        let (del_hashes, inputs) =
-            proof_util::process_proof(&leaf_data, &block.txdata, block_height, |h| {
-                self.chain.get_block_hash(h)
-            })?;
+        // After a reorg, our forest may still be in the old chain
+        if let Some(bridge) = &self.bridge {
+            Self::roll_back_bridge(bridge, &self.chain, block_height - 1)?;
+        }
+
+        let (proof, leaf_data) = match (inflight_block.proof, inflight_block.leaf_data) {
+            (Some(proof), Some(leaf_data)) => (proof, leaf_data),
+            (proof, leaf_data) => match &self.bridge {
+                Some(bridge) => match bridge.prove_block(block.clone()) {
+                    Ok(proof) => proof,
+                    Err(BridgeError::UtxoNotFound(outpoint)) => {
+                        error!(
+                            "Invalid block {block_hash} received by peer {peer} reason: {outpoint} doesn't exist"
+                        );
+
+                        try_and_log!(self.chain.invalidate_block(block_hash));
+                        return self.ban_invalid_block_peer(peer, block_hash).await;
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+                None => {
+                    let leaf_data = leaf_data.ok_or(WireError::LeafDataNotFound)?;
+                    let proof = proof.ok_or(WireError::BlockProofNotFound)?;
+                    (proof, leaf_data)
+                }
+            },
+        };
+
+        // The chain takes our proof, so we keep a copy to save in our cache
+        let cached_proof = self.block_cache.is_some().then(|| UtreexoProof {
+            block_hash,
+            proof_hashes: proof.hashes.clone(),
+            targets: proof.targets.clone(),
+            leaf_data: leaf_data.clone(),
+        });
+
+        let (del_hashes, inputs) = proof_util::process_proof(
+            &leaf_data,
+            &block.txdata,
+            block_height,
+            |h| self.chain.get_block_hash(h),
+            |h| self.chain.get_median_time_past(h),
+        )?;
 
         if let Err(e) = self.chain.connect_block(&block, proof, inputs, del_hashes) {
             error!(
@@ -887,12 +1184,23 @@ where
                     | BlockValidationErrors::BadBip34
                     | BlockValidationErrors::BIP94TimeWarp
                     | BlockValidationErrors::UnspendableUTXO
+                    | BlockValidationErrors::TooManySigops
+                    | BlockValidationErrors::DuplicateInput
+                    | BlockValidationErrors::WitnessItemTooBig
+                    | BlockValidationErrors::WitnessScriptTooBig
+                    | BlockValidationErrors::TooManyWitnessItems
+                    | BlockValidationErrors::NonFinalTransaction
+                    | BlockValidationErrors::SequenceLockNotSatisfied
+                    | BlockValidationErrors::MissingDeploymentSignal(_)
+                    | BlockValidationErrors::CheckpointMismatch(_)
+                    | BlockValidationErrors::ForkBeforeCheckpoint(_)
                     | BlockValidationErrors::CoinbaseNotMatured => {
                         try_and_log!(self.chain.invalidate_block(block.block_hash()));
                     }
                     BlockValidationErrors::InvalidProof => {}
                     BlockValidationErrors::BlockExtendsAnOrphanChain
-                    | BlockValidationErrors::BlockDoesntExtendTip => {
+                    | BlockValidationErrors::BlockDoesntExtendTip
+                    | BlockValidationErrors::LowChainWork => {
                         // for some reason, we've tried to connect a block that doesn't extend the
                         // tip
                         self.last_block_request = self.chain.get_validation_index().unwrap_or(0);
@@ -902,25 +1210,346 @@ where
                 }
             }
 
-            warn!(
-                "Block {} from peer {peer} is invalid, banning peer",
-                block.block_hash()
+            return self.ban_invalid_block_peer(peer, block_hash).await;
+        }
+
+        if let (Some(cache), Some(proof)) = (&mut self.block_cache, cached_proof) {
+            try_and_log!(cache.save(block_height, &block, &proof));
+        }
+
+        // Transactions in this block, or conflicting with it, can't stay in our mempool
+        self.mempool
+            .lock()
+            .await
+            .remove_confirmed(&block, block_height);
+
+        // The chain accepted this block, so we can add it to our forest
+        if let Some(bridge) = &self.bridge {
+            bridge.connect_block(block)?;
+        }
+
+        self.last_tip_update = Instant::now();
+        Ok(())
+    }
+
+    /// Disconnects and bans a peer that sent us an invalid block
+    async fn ban_invalid_block_peer(
+        &mut self,
+        peer: PeerId,
+        block_hash: BlockHash,
+    ) -> Result<(), WireError> {
+        warn!("Block {block_hash} from peer {peer} is invalid, banning peer");
+
+        self.ban_peer(peer, T::BAN_TIME)?;
+        Err(WireError::PeerMisbehaving)
+    }
+
+    /// Answers a `getuproof` request from one of our peers.
+    ///
+    /// A bridge has the proofs for every block, otherwise we can only answer for the blocks in
+    /// our block cache. We ignore requests for proofs we don't have.
+    pub(crate) async fn handle_get_utreexo_proof(
+        &mut self,
+        peer: PeerId,
+        request: GetUtreexoProof,
+    ) -> Result<(), WireError> {
+        let proof = match &self.bridge {
+            Some(bridge) => bridge.get_proof(request.block_hash)?,
+            None => self.get_cached_proof(&request.block_hash),
+        };
+
+        let Some(proof) = proof else {
+            debug!(
+                "Peer {peer} asked for the utreexo proof of {}, but we don't have it",
+                request.block_hash
             );
+            return Ok(());
+        };
 
-            // Disconnect the peer and ban it.
-            if let Some(peer) = self.peers.get(&peer).cloned() {
-                self.address_man
-                    .update_set_state(peer.address_id as usize, AddressState::Banned(T::BAN_TIME));
+        let proof = bridge::select_proof(proof, &request);
+        self.send_to_peer(peer, NodeRequest::SendUtreexoProof(proof))
+            .await
+    }
+
+    /// Remembers that a peer can send us compact blocks
+    pub(crate) fn handle_send_cmpct(&mut self, peer: PeerId) {
+        if let Some(peer) = self.peers.get_mut(&peer) {
+            peer.compact_blocks = true;
+        }
+    }
+
+    /// Answers a `getdata` for a block, if it's in our block cache
+    pub(crate) async fn handle_get_data(
+        &mut self,
+        peer: PeerId,
+        inv: Inventory,
+    ) -> Result<(), WireError> {
+        if let Inventory::CompactBlock(block_hash) = inv {
+            let compact_block = self.get_cached_block(&block_hash).and_then(|block| {
+                let version = COMPACT_BLOCKS_VERSION as u32;
+                HeaderAndShortIds::from_block(&block, rand::random(), version, &[]).ok()
+            });
+
+            let request = match compact_block {
+                Some(compact_block) => NodeRequest::SendCompactBlock(Box::new(compact_block)),
+                None => NodeRequest::SendNotFound(inv),
+            };
+
+            return self.send_to_peer(peer, request).await;
+        }
+
+        let block = match inv {
+            Inventory::WitnessBlock(block_hash) => self.get_cached_block(&block_hash),
+            Inventory::Block(block_hash) => {
+                self.get_cached_block(&block_hash).map(|mut block| {
+                    // This peer doesn't want witness data
+                    block
+                        .txdata
+                        .iter_mut()
+                        .flat_map(|tx| tx.input.iter_mut())
+                        .for_each(|input| input.witness.clear());
+
+                    block
+                })
             }
+            _ => None,
+        };
 
-            self.send_to_peer(peer, NodeRequest::Shutdown).await?;
-            return Err(WireError::PeerMisbehaving);
+        let request = match block {
+            Some(block) => NodeRequest::SendBlock(block),
+            None => NodeRequest::SendNotFound(inv),
+        };
+
+        self.send_to_peer(peer, request).await
+    }
+
+    /// Answers a `getblocktxn`, if the block is in our block cache
+    pub(crate) async fn handle_get_block_txn(
+        &mut self,
+        peer: PeerId,
+        request: BlockTransactionsRequest,
+    ) -> Result<(), WireError> {
+        let Some(block) = self.get_cached_block(&request.block_hash) else {
+            let inv = Inventory::WitnessBlock(request.block_hash);
+            return self
+                .send_to_peer(peer, NodeRequest::SendNotFound(inv))
+                .await;
+        };
+
+        match BlockTransactions::from_request(&request, &block) {
+            Ok(block_txn) => {
+                self.send_to_peer(peer, NodeRequest::SendBlockTxn(block_txn))
+                    .await
+            }
+            Err(_) => {
+                warn!(
+                    "Peer {peer} asked for transactions that aren't in block {}",
+                    request.block_hash
+                );
+                self.increase_banscore(peer, 10).await
+            }
+        }
+    }
+
+    /// The height of the last block in a range of filters a peer asked for. This block must be
+    /// in our main chain.
+    fn get_filters_stop_height(&self, stop_hash: BlockHash) -> Result<Option<u32>, WireError> {
+        let Some(height) = self.chain.get_block_height(&stop_hash)? else {
+            return Ok(None);
+        };
+
+        if self.chain.get_block_hash(height)? != stop_hash {
+            return Ok(None);
+        }
+
+        Ok(Some(height))
+    }
+
+    /// Builds the filter for the genesis block, that we never download
+    fn get_genesis_filter(&self) -> Result<BlockFilter, WireError> {
+        let genesis = genesis_block(self.network);
+
+        // The genesis block only has a coinbase, so it doesn't spend anything
+        BlockFilter::new_script_filter(&genesis, |outpoint| {
+            Err::<ScriptBuf, _>(bip158::Error::UtxoMissing(*outpoint))
+        })
+        .map_err(|_| WireError::CompactBlockFiltersError(IterableFilterStoreError::Eof))
+    }
+
+    /// Whether we have every compact filter and filter header, from the genesis block up to
+    /// our tip. Only then we tell our peers we can serve them.
+    pub(crate) fn has_all_compact_filters(&self) -> bool {
+        let Some(ref filters) = self.block_filters else {
+            return false;
+        };
+
+        let Ok(best_height) = self.chain.get_height() else {
+            return false;
+        };
+
+        let has_headers = filters
+            .get_filter_header(0)
+            .is_ok_and(|header| header.is_some())
+            && filters
+                .get_headers_height()
+                .is_ok_and(|height| height >= Some(best_height));
+
+        // We download filters from the first block after the genesis
+        let has_filters = filters
+            .get_filters(1, 1)
+            .is_ok_and(|filters| !filters.is_empty())
+            && filters
+                .get_height()
+                .is_ok_and(|height| height >= best_height);
+
+        has_headers && has_filters
+    }
+
+    /// Answers a `getcfilters`, sending one `cfilter` for each block, if we have them all
+    pub(crate) async fn handle_get_cfilters(
+        &mut self,
+        peer: PeerId,
+        request: GetCFilters,
+    ) -> Result<(), WireError> {
+        let Some(filters) = self.block_filters.clone() else {
+            return Ok(());
+        };
+
+        let Some(stop_height) = self.get_filters_stop_height(request.stop_hash)? else {
+            debug!("Peer {peer} asked for filters up to an unknown block");
+            return Ok(());
+        };
+
+        let start_height = request.start_height;
+        if start_height > stop_height || stop_height - start_height >= MAX_GETCFILTERS_SIZE {
+            warn!("Peer {peer} asked for an invalid range of filters");
+            return self.increase_banscore(peer, 10).await;
+        }
+
+        let mut block_filters = filters.get_filters(start_height, stop_height)?;
+        if start_height == 0 {
+            block_filters.insert(0, (0, self.get_genesis_filter()?));
+        }
+
+        if block_filters.len() != (stop_height - start_height + 1) as usize {
+            debug!("Peer {peer} asked for filters up to {stop_height}, but we don't have them");
+            return Ok(());
+        }
+
+        for (height, filter) in block_filters {
+            let filter = CFilter {
+                filter_type: 0,
+                block_hash: self.chain.get_block_hash(height)?,
+                filter: filter.content,
+            };
+
+            self.send_to_peer(peer, NodeRequest::SendFilter(filter))
+                .await?;
         }
 
-        self.last_tip_update = Instant::now();
         Ok(())
     }
 
+    /// Answers a `getcfheaders` with the filter hashes we have in our filter headers store
+    pub(crate) async fn handle_get_cfheaders(
+        &mut self,
+        peer: PeerId,
+        request: GetCFHeaders,
+    ) -> Result<(), WireError> {
+        let Some(filters) = self.block_filters.clone() else {
+            return Ok(());
+        };
+
+        let Some(stop_height) = self.get_filters_stop_height(request.stop_hash)? else {
+            debug!("Peer {peer} asked for filter headers up to an unknown block");
+            return Ok(());
+        };
+
+        let start_height = request.start_height;
+        if start_height > stop_height || stop_height - start_height >= MAX_FILTER_HEADERS {
+            warn!("Peer {peer} asked for an invalid range of filter headers");
+            return self.increase_banscore(peer, 10).await;
+        }
+
+        let previous_filter_header = match start_height.checked_sub(1) {
+            Some(height) => filters.get_filter_header(height)?,
+            None => Some(FilterHeader::all_zeros()),
+        };
+
+        let filter_hashes = (start_height..=stop_height)
+            .map(|height| filters.get_filter_hash(height))
+            .collect::<Result<Option<Vec<_>>, _>>()?;
+
+        let (Some(previous_filter_header), Some(filter_hashes)) =
+            (previous_filter_header, filter_hashes)
+        else {
+            debug!("Peer {peer} asked for filter headers we don't have");
+            return Ok(());
+        };
+
+        let headers = CFHeaders {
+            filter_type: 0,
+            stop_hash: request.stop_hash,
+            previous_filter_header,
+            filter_hashes,
+        };
+
+        self.send_to_peer(peer, NodeRequest::SendFilterHeaders(headers))
+            .await
+    }
+
+    /// Answers a `getcfcheckpt` with a filter header every 1000 blocks
+    pub(crate) async fn handle_get_cfcheckpt(
+        &mut self,
+        peer: PeerId,
+        request: GetCFCheckpt,
+    ) -> Result<(), WireError> {
+        let Some(filters) = self.block_filters.clone() else {
+            return Ok(());
+        };
+
+        let Some(stop_height) = self.get_filters_stop_height(request.stop_hash)? else {
+            debug!("Peer {peer} asked for filter checkpoints up to an unknown block");
+            return Ok(());
+        };
+
+        let filter_headers = (1..=stop_height / CHECKPOINT_INTERVAL)
+            .map(|index| filters.get_filter_header(index * CHECKPOINT_INTERVAL))
+            .collect::<Result<Option<Vec<_>>, _>>()?;
+
+        let Some(filter_headers) = filter_headers else {
+            debug!("Peer {peer} asked for filter checkpoints we don't have");
+            return Ok(());
+        };
+
+        let checkpoint = CFCheckpt {
+            filter_type: 0,
+            stop_hash: request.stop_hash,
+            filter_headers,
+        };
+
+        self.send_to_peer(peer, NodeRequest::SendFilterCheckpoint(checkpoint))
+            .await
+    }
+
+    /// Returns a block from our block cache, if we have it
+    fn get_cached_block(&self, block_hash: &BlockHash) -> Option<Block> {
+        let cache = self.block_cache.as_ref()?;
+        cache
+            .get_block(block_hash)
+            .map_err(|e| warn!("Couldn't read block {block_hash} from our cache: {e}"))
+            .ok()?
+    }
+
+    /// Returns the Utreexo proof of a block from our block cache, if we have it
+    fn get_cached_proof(&self, block_hash: &BlockHash) -> Option<UtreexoProof> {
+        let cache = self.block_cache.as_ref()?;
+        cache
+            .get_proof(block_hash)
+            .map_err(|e| warn!("Couldn't read the proof of {block_hash} from our cache: {e}"))
+            .ok()?
+    }
+
     // TODO(@luisschwab): get rid of this once
     // https://github.com/rust-bitcoin/rust-bitcoin/pull/4639 makes it into a release.
     fn get_port(network: Network) -> u16 {
@@ -967,6 +1596,20 @@ where
                 inflight.1
             }
 
+            PeerMessages::FilterHeaders(_) => {
+                let inflight = self
+                    .inflight
+                    .get(&InflightRequests::GetFilterHeaders(peer))?;
+                inflight.1
+            }
+
+            PeerMessages::FilterCheckpoint(_) => {
+                let inflight = self
+                    .inflight
+                    .get(&InflightRequests::GetFilterCheckpoint(peer))?;
+                inflight.1
+            }
+
             PeerMessages::UtreexoState(_) => {
                 let inflight = self.inflight.get(&InflightRequests::UtreexoState(peer))?;
                 inflight.1
@@ -1117,6 +1760,120 @@ where
         metrics.peer_count.set(self.peer_ids.len() as f64);
     }
 
+    #[cfg(feature = "metrics")]
+    pub(crate) fn update_headers_metrics(&self) {
+        use metrics::get_metrics;
+
+        let fork_headers: u32 = self.peers.values().map(|peer| peer.fork_headers).sum();
+        let presync_headers: usize = self
+            .headers_sync
+            .values()
+            .map(|sync| sync.buffered_headers())
+            .sum();
+
+        let metrics = get_metrics();
+        metrics.fork_headers.set(fork_headers.into());
+        metrics.presync_headers.set(presync_headers as i64);
+    }
+
+    /// Runs the headers a peer sent us through its presync. If this peer's chain doesn't have the
+    /// minimum chain work yet, we start a new presync for it. See [HeadersSyncState].
+    pub(crate) fn presync_headers(
+        &mut self,
+        peer: PeerId,
+        headers: Vec<Header>,
+    ) -> Result<HeadersPresync, WireError> {
+        // An empty message ends any presync with this peer
+        if headers.is_empty() {
+            self.headers_sync.remove(&peer);
+            return Ok(HeadersPresync::Accept(headers));
+        }
+
+        let sync = match self.headers_sync.remove(&peer) {
+            Some(sync) => Some(sync),
+            None => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as u32;
+
+                self.chain
+                    .start_headers_presync(&headers, now, rand::random())?
+            }
+        };
+
+        let Some(mut sync) = sync else {
+            return Ok(HeadersPresync::Accept(headers));
+        };
+
+        let full_message = headers.len() == MAX_HEADERS_RESULTS;
+        let result = sync.process_next_headers(&headers, full_message);
+
+        let presync = match (result.success, result.request_more) {
+            (false, _) => {
+                info!("Peer {peer} sent headers that don't match its presync");
+                HeadersPresync::Invalid
+            }
+            (true, true) => {
+                let locator = sync.next_headers_request_locator();
+                debug!(
+                    "Presyncing headers from peer {peer}, phase={:?} height={}",
+                    sync.phase(),
+                    sync.current_height()
+                );
+
+                self.headers_sync.insert(peer, sync);
+                HeadersPresync::Continue(result.headers, locator)
+            }
+            (true, false) if result.headers.is_empty() => {
+                info!("Peer {peer}'s chain doesn't have the minimum chain work");
+                HeadersPresync::LowWork
+            }
+            (true, false) => HeadersPresync::Accept(result.headers),
+        };
+
+        #[cfg(feature = "metrics")]
+        self.update_headers_metrics();
+
+        Ok(presync)
+    }
+
+    /// Accepts a header from a peer, counting it if it doesn't end up in our best chain.
+    /// This header must have gone through [UtreexoNode::presync_headers] first.
+    ///
+    /// Returns `false` if this peer went over [MAX_FORK_HEADERS_PER_PEER], and should be
+    /// disconnected.
+    pub(crate) fn accept_peer_header(
+        &mut self,
+        peer: PeerId,
+        header: Header,
+    ) -> Result<bool, WireError> {
+        let block_hash = header.block_hash();
+        let is_new = self.chain.get_block_header(&block_hash).is_err();
+        self.chain.accept_presynced_header(header)?;
+
+        if !is_new {
+            return Ok(true);
+        }
+
+        let best_block = self.chain.get_best_block()?.1;
+        let Some(peer_view) = self.peers.get_mut(&peer) else {
+            return Ok(true);
+        };
+
+        // Once this peer's chain becomes our best one, its headers aren't in a fork anymore
+        match best_block == block_hash {
+            true => peer_view.fork_headers = 0,
+            false => peer_view.fork_headers += 1,
+        }
+
+        #[cfg(feature = "metrics")]
+        self.update_headers_metrics();
+
+        let fork_headers = self.peers.get(&peer).map(|peer| peer.fork_headers);
+        Ok(fork_headers.unwrap_or(0) <= MAX_FORK_HEADERS_PER_PEER)
+    }
+
     pub(crate) async fn handle_disconnection(
         &mut self,
         peer: u32,
@@ -1134,6 +1891,11 @@ where
                 .as_secs();
 
             match p.state {
+                // Inbound peers aren't in our address manager
+                PeerStatus::Banned if p.kind == ConnectionKind::Inbound => {
+                    self.inbound_bans.insert(p.address, Instant::now());
+                }
+                _ if p.kind == ConnectionKind::Inbound => {}
                 PeerStatus::Ready => {
                     self.address_man
                         .update_set_state(idx, AddressState::Tried(now));
@@ -1150,6 +1912,7 @@ where
         }
 
         self.peer_ids.retain(|&id| id != peer);
+        self.headers_sync.remove(&peer);
         for (_, v) in self.peer_by_service.iter_mut() {
             v.retain(|&id| id != peer);
         }
@@ -1167,7 +1930,10 @@ where
         }
 
         #[cfg(feature = "metrics")]
-        self.update_peer_metrics();
+        {
+            self.update_peer_metrics();
+            self.update_headers_metrics();
+        }
 
         Ok(())
     }
@@ -1176,8 +1942,8 @@ where
     /// for yet, and don't have any GetProofs inflight. This may be caused by a peer disconnecting
     /// while we didn't have more utreexo peers to redo the request.
     pub(crate) async fn ask_for_missed_proofs(&mut self) -> Result<(), WireError> {
-        // If we have no peers, we can't ask for proofs
-        if !self.has_utreexo_peers() {
+        // If we have no peers, we can't ask for proofs. A bridge makes its own proofs
+        if !self.has_utreexo_peers() || self.bridge.is_some() {
             return Ok(());
         }
 
@@ -1292,6 +2058,13 @@ where
             InflightRequests::Connect(_) => {
                 // WE DON'T NEED TO DO ANYTHING HERE
             }
+            InflightRequests::GetFilterHeaders(_) | InflightRequests::GetFilterCheckpoint(_) => {
+                // We'll go on with the answers from the other peers, or ask again if
+                // there's none
+            }
+            InflightRequests::FilterDispute(_) => {
+                // We give up on this dispute, and ask for the filter headers again
+            }
         }
 
         Ok(())
@@ -1409,9 +2182,11 @@ where
                     .push(peer);
             }
 
-            self.address_man
-                .update_set_state(version.address_id, AddressState::Connected)
-                .update_set_service_flag(version.address_id, version.services);
+            if version.kind != ConnectionKind::Inbound {
+                self.address_man
+                    .update_set_state(version.address_id, AddressState::Connected)
+                    .update_set_service_flag(version.address_id, version.services);
+            }
 
             self.peer_ids.push(peer);
         }
@@ -1452,6 +2227,13 @@ where
 
         peer.banscore += factor;
 
+        // We never ban our whitelisted peers
+        let is_inbound = peer.kind == ConnectionKind::Inbound;
+        if is_inbound && self.common.config.whitelist.contains(&peer.address) {
+            debug!("increasing banscore for whitelisted peer {peer_id}");
+            return Ok(());
+        }
+
         // This peer is misbehaving too often, ban it
         let is_missbehaving = peer.banscore >= self.common.max_banscore;
         // extra peers should be banned immediately
@@ -1459,9 +2241,7 @@ where
 
         if is_missbehaving || is_extra {
             warn!("banning peer {peer_id} for misbehaving");
-            peer.channel.send(NodeRequest::Shutdown)?;
-            peer.state = PeerStatus::Banned;
-            return Ok(());
+            return self.ban_peer(peer_id, T::BAN_TIME);
         }
 
         debug!("increasing banscore for peer {peer_id}");
@@ -1469,6 +2249,41 @@ where
         Ok(())
     }
 
+    /// Disconnects and bans a peer for `ban_time` seconds.
+    ///
+    /// Inbound peers aren't in our address manager, so we ban their IP instead, and refuse their
+    /// connections until [`NodeContext::BAN_TIME`] passes.
+    pub(crate) fn ban_peer(&mut self, peer_id: PeerId, ban_time: u64) -> Result<(), WireError> {
+        let Some(peer) = self.common.peers.get_mut(&peer_id) else {
+            return Ok(());
+        };
+
+        peer.state = PeerStatus::Banned;
+        match peer.kind {
+            ConnectionKind::Inbound => {
+                self.common
+                    .inbound_bans
+                    .insert(peer.address, Instant::now());
+            }
+            _ => {
+                self.common
+                    .address_man
+                    .update_set_state(peer.address_id as usize, AddressState::Banned(ban_time));
+            }
+        }
+
+        peer.channel.send(NodeRequest::Shutdown)?;
+        Ok(())
+    }
+
+    /// How many connections we've opened, or are opening, with our peers
+    pub(crate) fn outbound_peer_count(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|peer| peer.kind != ConnectionKind::Inbound)
+            .count()
+    }
+
     pub(crate) fn has_utreexo_peers(&self) -> bool {
         !self
             .peer_by_service
@@ -1477,6 +2292,34 @@ where
             .is_empty()
     }
 
+    /// Whether we can get proofs for the blocks we download, either from our bridge or a peer
+    pub(crate) fn can_get_proofs(&self) -> bool {
+        self.bridge.is_some() || self.has_utreexo_peers()
+    }
+
+    /// The services we advertise to our peers. We only serve proofs if we are a bridge, and
+    /// compact filters if we have all of them. If we keep a block cache, we can only serve our
+    /// most recent blocks, so we advertise `NODE_NETWORK_LIMITED` (BIP159) instead of
+    /// `NODE_NETWORK`, and only if the cache always keeps as many blocks as BIP159 requires
+    pub(crate) fn our_services(&self) -> ServiceFlags {
+        let network = match &self.block_cache {
+            Some(cache) if cache.is_network_limited() => ServiceFlags::NETWORK_LIMITED,
+            Some(_) => ServiceFlags::NONE,
+            None => ServiceFlags::NETWORK,
+        };
+
+        let mut services = network | ServiceFlags::WITNESS;
+        if self.bridge.is_some() {
+            services |= UTREEXO.into();
+        }
+
+        if self.has_all_compact_filters() {
+            services |= ServiceFlags::COMPACT_FILTERS;
+        }
+
+        services
+    }
+
     pub(crate) fn has_compact_filters_peer(&self) -> bool {
         self.peer_by_service
             .get(&ServiceFlags::COMPACT_FILTERS)
@@ -1525,6 +2368,7 @@ where
     pub(crate) fn get_peers_from_dns(&self) -> Result<(), WireError> {
         let node_sender = self.node_tx.clone();
         let network = self.network;
+        let dns_seeds = self.chain_params.dns_seeds.clone();
 
         let proxy_addr = self.socks5.as_ref().map(|proxy| {
             let addr = proxy.address;
@@ -1533,7 +2377,6 @@ where
         });
 
         tokio::task::spawn_blocking(move || {
-            let dns_seeds = floresta_chain::get_chain_dns_seeds(network);
             let mut addresses = Vec::new();
 
             let default_port = Self::get_port(network);
@@ -1734,6 +2577,16 @@ where
         }
 
         info!("No peers found, using hardcoded addresses");
+        self.add_fixed_addresses();
+    }
+
+    /// Loads our hardcoded addresses for this network, unless we are in a custom chain (those
+    /// addresses are for the default one).
+    fn add_fixed_addresses(&mut self) {
+        if self.chain_params.magic != self.network.magic() {
+            return;
+        }
+
         let net = self.network;
         self.address_man.add_fixed_addresses(net);
     }
@@ -1782,7 +2635,7 @@ where
     ) -> Result<(), WireError> {
         // If the user passes in a `--connect` cli argument, we only connect with
         // that particular peer.
-        if self.fixed_peer.is_some() && !self.peers.is_empty() {
+        if self.fixed_peer.is_some() && self.outbound_peer_count() > 0 {
             return Ok(());
         }
 
@@ -1796,7 +2649,7 @@ where
         self.maybe_open_connection_with_added_peers().await?;
 
         let connection_kind = ConnectionKind::Regular(required_service);
-        if self.peers.len() < T::MAX_OUTGOING_PEERS {
+        if self.outbound_peer_count() < T::MAX_OUTGOING_PEERS {
             self.create_connection(connection_kind).await?;
         }
 
@@ -1812,6 +2665,93 @@ where
         Ok(())
     }
 
+    /// Requests a block and its proof from a peer
+    ///
+    /// If you need to see a peer's version of a given block, you can use this method
+    /// to request a block from a specific peer.
+    pub(crate) async fn get_block_and_proof(
+        &mut self,
+        peer: PeerId,
+        block_hash: BlockHash,
+    ) -> Result<InflightBlock, WireError> {
+        self.send_to_peer(peer, NodeRequest::GetBlock(vec![block_hash]))
+            .await?;
+
+        let timeout = Instant::now() + Duration::from_secs(60);
+        let mut block = None;
+        loop {
+            if Instant::now() > timeout {
+                return Err(WireError::PeerTimeout);
+            }
+
+            let Some(NodeNotification::FromPeer(id, message)) = self.node_rx.recv().await else {
+                // Keep waiting until peer message is read or timeout
+                continue;
+            };
+
+            if id != peer {
+                continue;
+            }
+
+            match message {
+                // STEP 1: Receive the block and ask for the proof
+                PeerMessages::Block(recv_block) => {
+                    if recv_block.block_hash() != block_hash {
+                        error!("peer {peer} sent us a block we didn't request");
+                        self.increase_banscore(peer, self.max_banscore).await?;
+                        return Err(WireError::PeerMisbehaving);
+                    }
+
+                    // Check if the blocks was maliciously mutated by our peer
+                    let is_mutated =
+                        !(recv_block.check_merkle_root() && recv_block.check_witness_commitment());
+
+                    if is_mutated {
+                        error!(
+                            "Peer {peer} sent us a mutated block {}",
+                            recv_block.block_hash()
+                        );
+                        self.increase_banscore(peer, self.config.max_banscore)
+                            .await?;
+                        return Err(WireError::PeerMisbehaving);
+                    }
+
+                    block = Some(recv_block);
+                    // ask for the proof. Sending two empty bitmaps means we want the full
+                    // proof and all leaf data
+                    self.send_to_peer(
+                        peer,
+                        NodeRequest::GetBlockProof((block_hash, Bitmap::new(), Bitmap::new())),
+                    )
+                    .await?;
+                }
+
+                // STEP 2: Receive the proof and return the `InflightBlock`
+                PeerMessages::UtreexoProof(uproof) => {
+                    let Some(block) = block else {
+                        error!("peer {peer} sent us a proof without sending the block first");
+                        self.increase_banscore(peer, self.config.max_banscore)
+                            .await?;
+                        return Err(WireError::PeerMisbehaving);
+                    };
+
+                    let proof = Proof {
+                        hashes: uproof.proof_hashes,
+                        targets: uproof.targets,
+                    };
+
+                    return Ok(InflightBlock {
+                        block,
+                        proof: Some(proof),
+                        leaf_data: Some(uproof.leaf_data),
+                        peer,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
     pub(crate) async fn request_blocks(&mut self, blocks: Vec<BlockHash>) -> Result<(), WireError> {
         let should_request = |block: &BlockHash| {
             let is_inflight = self
@@ -1861,8 +2801,7 @@ where
 
         let Some((peer_id, address)) = address else {
             // No peers with the desired services are known, load hardcoded addresses
-            let net = self.network;
-            self.address_man.add_fixed_addresses(net);
+            self.add_fixed_addresses();
 
             return Err(WireError::NoAddressesAvailable);
         };
@@ -1913,14 +2852,16 @@ where
         peer_id_count: u32,
         mempool: Arc<Mutex<Mempool>>,
         network: Network,
+        magic: Magic,
         node_tx: UnboundedSender<NodeNotification>,
         user_agent: String,
+        services: ServiceFlags,
         allow_v1_fallback: bool,
     ) -> Result<(), WireError> {
         let address = (address.get_net_address(), address.get_port());
 
         let (transport_reader, transport_writer, transport_protocol) =
-            transport::connect(address, network, allow_v1_fallback).await?;
+            transport::connect(address, network, magic, allow_v1_fallback).await?;
 
         let (cancellation_sender, cancellation_receiver) = oneshot::channel();
         let (actor_receiver, actor) = create_actors(transport_reader);
@@ -1942,6 +2883,7 @@ where
             actor_receiver,
             transport_writer,
             user_agent,
+            services,
             cancellation_sender,
             transport_protocol,
         )
@@ -1957,16 +2899,18 @@ where
         kind: ConnectionKind,
         mempool: Arc<Mutex<Mempool>>,
         network: Network,
+        magic: Magic,
         node_tx: UnboundedSender<NodeNotification>,
         peer_id: usize,
         address: LocalAddress,
         requests_rx: UnboundedReceiver<NodeRequest>,
         peer_id_count: u32,
         user_agent: String,
+        services: ServiceFlags,
         allow_v1_fallback: bool,
     ) -> Result<(), WireError> {
         let (transport_reader, transport_writer, transport_protocol) =
-            transport::connect_proxy(proxy, address, network, allow_v1_fallback).await?;
+            transport::connect_proxy(proxy, address, network, magic, allow_v1_fallback).await?;
 
         let (cancellation_sender, cancellation_receiver) = oneshot::channel();
         let (actor_receiver, actor) = create_actors(transport_reader);
@@ -1987,6 +2931,7 @@ where
             actor_receiver,
             transport_writer,
             user_agent,
+            services,
             cancellation_sender,
             transport_protocol,
         )
@@ -2021,12 +2966,14 @@ where
                     kind,
                     self.mempool.clone(),
                     self.network,
+                    self.chain_params.magic,
                     self.node_tx.clone(),
                     peer_id,
                     address.clone(),
                     requests_rx,
                     self.peer_id_count,
                     self.config.user_agent.clone(),
+                    self.our_services(),
                     allow_v1_fallback,
                 ),
             ));
@@ -2041,8 +2988,10 @@ where
                     self.peer_id_count,
                     self.mempool.clone(),
                     self.network,
+                    self.chain_params.magic,
                     self.node_tx.clone(),
                     self.config.user_agent.clone(),
+                    self.our_services(),
                     allow_v1_fallback,
                 ),
             ));
@@ -2071,6 +3020,9 @@ where
                 banscore: 0,
                 // Will be downgraded to V1 if the V2 handshake fails, and we allow fallback
                 transport_protocol: TransportProtocol::V2,
+                fork_headers: 0,
+                connected_at: Instant::now(),
+                compact_blocks: false,
             },
         );
 
@@ -2087,6 +3039,203 @@ where
 
         Ok(())
     }
+
+    /// Starts accepting inbound connections on the socket we've bound at startup, if any.
+    ///
+    /// Each new connection is sent to our node as a [`NodeNotification::InboundConnection`]. The
+    /// accept loop stops once our node goes away.
+    pub(crate) fn start_listener(&mut self) -> Result<(), WireError> {
+        let Some(listener) = self.listener.take() else {
+            return Ok(());
+        };
+
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        info!(
+            "Accepting inbound connections on {}",
+            listener.local_addr()?
+        );
+
+        let node_tx = self.node_tx.clone();
+        spawn(async move {
+            loop {
+                let (stream, address) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        warn!("Failed to accept an inbound connection: {e}");
+                        continue;
+                    }
+                };
+
+                if node_tx
+                    .send(NodeNotification::InboundConnection(stream, address))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Handles a peer that connected to our listener.
+    ///
+    /// We refuse peers we've banned, and if all our inbound slots are taken, we try to evict
+    /// one of our inbound peers to make room (see [`Self::select_inbound_to_evict`]). Whitelisted
+    /// peers skip those checks. Like [`Self::open_connection`], the handshake happens in the
+    /// [`Peer`] actor, that sends a [`PeerMessages::Ready`] once it's done.
+    pub(crate) async fn handle_inbound_connection(
+        &mut self,
+        stream: TcpStream,
+        address: SocketAddr,
+    ) -> Result<(), WireError> {
+        let ip = address.ip();
+        if !self.config.whitelist.contains(&ip) {
+            if let Some(banned_at) = self.inbound_bans.get(&ip) {
+                if banned_at.elapsed() < Duration::from_secs(T::BAN_TIME) {
+                    debug!("Refusing inbound connection from banned address {address}");
+                    return Ok(());
+                }
+
+                self.inbound_bans.remove(&ip);
+            }
+
+            let inbound = self.peers.len() - self.outbound_peer_count();
+            if inbound >= self.config.max_inbound as usize {
+                let Some(peer) = Self::select_inbound_to_evict(&self.peers, &self.config.whitelist)
+                else {
+                    debug!("Refusing inbound connection from {address}, no inbound slots left");
+                    return Ok(());
+                };
+
+                info!("Evicting inbound peer {peer} to make room for {address}");
+                self.send_to_peer(peer, NodeRequest::Shutdown).await?;
+                // forget this peer right away, so it doesn't take a slot until it disconnects
+                self.handle_disconnection(peer, 0).await?;
+            }
+        }
+
+        let (requests_tx, requests_rx) = unbounded_channel();
+        spawn(timeout(
+            Duration::from_secs(10),
+            Self::open_inbound_connection(
+                stream,
+                requests_rx,
+                self.peer_id_count,
+                self.mempool.clone(),
+                self.network,
+                self.chain_params.magic,
+                self.node_tx.clone(),
+                self.config.user_agent.clone(),
+                self.our_services(),
+            ),
+        ));
+
+        let peer_count = self.peer_id_count;
+        self.inflight.insert(
+            InflightRequests::Connect(peer_count),
+            (peer_count, Instant::now()),
+        );
+
+        self.peers.insert(
+            peer_count,
+            LocalPeerView {
+                address: ip,
+                port: address.port(),
+                user_agent: "".to_string(),
+                state: PeerStatus::Awaiting,
+                channel: requests_tx,
+                services: ServiceFlags::NONE,
+                _last_message: Instant::now(),
+                kind: ConnectionKind::Inbound,
+                // inbound peers aren't in our address manager
+                address_id: 0,
+                height: 0,
+                banscore: 0,
+                // Will be updated once we know which protocol our peer speaks
+                transport_protocol: TransportProtocol::V2,
+                fork_headers: 0,
+                connected_at: Instant::now(),
+                compact_blocks: false,
+            },
+        );
+
+        debug!("Accepted inbound connection from {address} as peer {peer_count}");
+        self.peer_id_count += 1;
+
+        Ok(())
+    }
+
+    /// Negotiates the transport with a peer that connected to us, and creates its [`Peer`] actor
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn open_inbound_connection(
+        stream: TcpStream,
+        requests_rx: UnboundedReceiver<NodeRequest>,
+        peer_id_count: u32,
+        mempool: Arc<Mutex<Mempool>>,
+        network: Network,
+        magic: Magic,
+        node_tx: UnboundedSender<NodeNotification>,
+        user_agent: String,
+        services: ServiceFlags,
+    ) -> Result<(), WireError> {
+        let (transport_reader, transport_writer, transport_protocol) =
+            transport::accept(stream, network, magic).await?;
+
+        let (cancellation_sender, cancellation_receiver) = oneshot::channel();
+        let (actor_receiver, actor) = create_actors(transport_reader);
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = cancellation_receiver => {}
+                _ = actor.run() => {}
+            }
+        });
+
+        Peer::<WriteHalf>::create_peer(
+            peer_id_count,
+            mempool,
+            node_tx,
+            requests_rx,
+            0,
+            ConnectionKind::Inbound,
+            actor_receiver,
+            transport_writer,
+            user_agent,
+            services,
+            cancellation_sender,
+            transport_protocol,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    /// Picks one of our inbound peers to evict, so a new one can connect to us.
+    ///
+    /// Whitelisted peers, peers still in the handshake, and our [`PROTECTED_INBOUND_PEERS`]
+    /// oldest inbound peers are never evicted. Among the others, we pick the one with the
+    /// highest banscore, or the newest one if there's a tie. Returns `None` if there's no
+    /// peer we can evict.
+    pub(crate) fn select_inbound_to_evict(
+        peers: &HashMap<PeerId, LocalPeerView>,
+        whitelist: &[IpAddr],
+    ) -> Option<PeerId> {
+        let mut candidates: Vec<_> = peers
+            .iter()
+            .filter(|(_, peer)| peer.kind == ConnectionKind::Inbound)
+            .filter(|(_, peer)| peer.state == PeerStatus::Ready)
+            .filter(|(_, peer)| !whitelist.contains(&peer.address))
+            .collect();
+
+        // oldest first
+        candidates.sort_by_key(|(_, peer)| peer.connected_at);
+        candidates
+            .into_iter()
+            .skip(PROTECTED_INBOUND_PEERS)
+            .max_by_key(|(_, peer)| (peer.banscore, peer.connected_at))
+            .map(|(id, _)| *id)
+    }
 }
 
 /// Run a task and log any errors that might occur.
@@ -2130,11 +3279,140 @@ pub(crate) use try_and_warn;
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::p2p::ServiceFlags;
+    use bitcoin::BlockHash;
+    use bitcoin::Network;
     use floresta_chain::pruned_utreexo::partial_chain::PartialChainState;
-
+    use floresta_chain::AssumeValidArg;
+    use floresta_chain::ChainParams;
+    use floresta_chain::ChainState;
+    use floresta_chain::FlatChainStore;
+    use floresta_chain::FlatChainStoreConfig;
+    use rustreexo::accumulator::pollard::Pollard;
+    use tokio::net::TcpListener;
+    use tokio::net::TcpStream;
+    use tokio::sync::mpsc::unbounded_channel;
+    use tokio::sync::Mutex;
+    use tokio::sync::RwLock;
+
+    use crate::address_man::AddressMan;
+    use crate::mempool::Mempool;
+    use crate::node::ConnectionKind;
+    use crate::node::LocalPeerView;
+    use crate::node::PeerStatus;
     use crate::node::UtreexoNode;
+    use crate::node::PROTECTED_INBOUND_PEERS;
+    use crate::p2p_wire::tests::utils::get_node_config;
+    use crate::p2p_wire::transport::TransportProtocol;
     use crate::running_node::RunningNode;
 
+    fn make_peer(kind: ConnectionKind, address: IpAddr, age: u64, banscore: u32) -> LocalPeerView {
+        LocalPeerView {
+            state: PeerStatus::Ready,
+            address_id: 0,
+            channel: unbounded_channel().0,
+            services: ServiceFlags::NONE,
+            user_agent: String::new(),
+            address,
+            port: 8333,
+            _last_message: Instant::now(),
+            kind,
+            height: 0,
+            banscore,
+            transport_protocol: TransportProtocol::V2,
+            fork_headers: 0,
+            connected_at: Instant::now() - Duration::from_secs(age),
+            compact_blocks: false,
+        }
+    }
+
+    #[test]
+    fn test_select_inbound_to_evict() {
+        let select = UtreexoNode::<PartialChainState, RunningNode>::select_inbound_to_evict;
+        let address: IpAddr = "10.0.0.1".parse().unwrap();
+        let whitelisted: IpAddr = "10.0.0.2".parse().unwrap();
+
+        // our oldest inbound peers are protected
+        let mut peers: HashMap<_, _> = (0..PROTECTED_INBOUND_PEERS as u32)
+            .map(|id| (id, make_peer(ConnectionKind::Inbound, address, 1000, 10)))
+            .collect();
+        assert_eq!(select(&peers, &[]), None);
+
+        // and so are outbound, whitelisted and handshaking peers
+        peers.insert(10, make_peer(ConnectionKind::Extra, address, 10, 50));
+        peers.insert(11, make_peer(ConnectionKind::Inbound, whitelisted, 10, 50));
+        let mut handshaking = make_peer(ConnectionKind::Inbound, address, 10, 50);
+        handshaking.state = PeerStatus::Awaiting;
+        peers.insert(12, handshaking);
+        assert_eq!(select(&peers, &[whitelisted]), None);
+
+        // we evict the one with the highest banscore, or the newest one
+        peers.insert(20, make_peer(ConnectionKind::Inbound, address, 30, 0));
+        peers.insert(21, make_peer(ConnectionKind::Inbound, address, 20, 0));
+        assert_eq!(select(&peers, &[whitelisted]), Some(21));
+
+        peers.insert(22, make_peer(ConnectionKind::Inbound, address, 40, 5));
+        assert_eq!(select(&peers, &[whitelisted]), Some(22));
+
+        // without the whitelist, that peer can be evicted too
+        assert_eq!(select(&peers, &[]), Some(11));
+    }
+
+    #[tokio::test]
+    async fn test_ban_inbound_peer() {
+        let datadir = format!("./tmp-db/{}.inbound_ban", rand::random::<u32>());
+        let chainstore = FlatChainStore::new(FlatChainStoreConfig::new(datadir.clone())).unwrap();
+        let chain = ChainState::new(
+            chainstore,
+            ChainParams::from(Network::Regtest),
+            AssumeValidArg::Disabled,
+        );
+
+        let mut config = get_node_config(datadir, Network::Regtest, false);
+        config.max_inbound = 8;
+
+        let mut node = UtreexoNode::<Arc<ChainState<FlatChainStore>>, RunningNode>::new(
+            config,
+            Arc::new(chain),
+            Arc::new(Mutex::new(Mempool::new(Pollard::default(), 1000))),
+            None,
+            Arc::new(RwLock::new(false)),
+            AddressMan::default(),
+        )
+        .unwrap();
+
+        // An inbound peer sends us an invalid block
+        let address: IpAddr = "127.0.0.1".parse().unwrap();
+        node.peers
+            .insert(0, make_peer(ConnectionKind::Inbound, address, 10, 0));
+        assert!(node
+            .ban_invalid_block_peer(0, BlockHash::all_zeros())
+            .await
+            .is_err());
+
+        assert_eq!(node.peers[&0].state, PeerStatus::Banned);
+        assert!(node.inbound_bans.contains_key(&address));
+
+        // So we refuse it when it connects again
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, peer_address) = listener.accept().await.unwrap();
+
+        node.handle_inbound_connection(stream, peer_address)
+            .await
+            .unwrap();
+        assert_eq!(node.peers.len(), 1);
+    }
+
     fn check_address_resolving(address: &str, port: u16, should_succeed: bool, description: &str) {
         let result =
             UtreexoNode::<PartialChainState, RunningNode>::resolve_connect_host(address, port);
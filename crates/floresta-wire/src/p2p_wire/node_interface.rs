@@ -13,6 +13,7 @@ use serde::Serialize;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot;
 
+use super::backfill::BackfillProgress;
 use super::node::ConnectionKind;
 use super::node::NodeNotification;
 use super::node::PeerStatus;
@@ -78,6 +79,9 @@ pub enum UserRequest {
 
     /// Ping all connected peers to check if they are alive.
     Ping,
+
+    /// Return how far we are in the backfill of assumed blocks.
+    GetBackfillProgress,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -125,6 +129,9 @@ pub enum NodeResponse {
 
     /// A response indicating whether the ping was successful.
     Ping(bool),
+
+    /// A response containing the progress of the backfill.
+    GetBackfillProgress(BackfillProgress),
 }
 
 #[derive(Debug, Clone)]
@@ -213,7 +220,8 @@ impl NodeInterface {
     /// Gets a block by its hash.
     ///
     /// This function will try to get a block from the network and return it. Note that we don't
-    /// keep a local copy of the blockchain, so this function will always make a network request.
+    /// keep a local copy of the blockchain, so unless the block is in our block cache, this
+    /// function will make a network request.
     pub async fn get_block(
         &self,
         block: BlockHash,
@@ -255,6 +263,16 @@ impl NodeInterface {
 
         extract_variant!(Ping, val)
     }
+
+    /// Gets how far we are in the backfill of blocks we've assumed, and an estimate of how long
+    /// until we finish.
+    pub async fn get_backfill_progress(
+        &self,
+    ) -> Result<BackfillProgress, oneshot::error::RecvError> {
+        let val = self.send_request(UserRequest::GetBackfillProgress).await?;
+
+        extract_variant!(GetBackfillProgress, val)
+    }
 }
 
 macro_rules! extract_variant {
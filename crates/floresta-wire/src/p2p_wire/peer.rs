@@ -1,9 +1,13 @@
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
 use bip324::serde::CommandString;
+use bitcoin::bip152::BlockTransactions;
+use bitcoin::bip152::BlockTransactionsRequest;
+use bitcoin::bip152::HeaderAndShortIds;
 use bitcoin::bip158::BlockFilter;
 use bitcoin::block::Header as BlockHeader;
 use bitcoin::consensus::deserialize;
@@ -13,6 +17,15 @@ use bitcoin::hashes::Hash;
 use bitcoin::p2p::address::AddrV2Message;
 use bitcoin::p2p::message::NetworkMessage;
 use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::p2p::message_compact_blocks::BlockTxn;
+use bitcoin::p2p::message_compact_blocks::CmpctBlock;
+use bitcoin::p2p::message_compact_blocks::GetBlockTxn;
+use bitcoin::p2p::message_compact_blocks::SendCmpct;
+use bitcoin::p2p::message_filter::CFCheckpt;
+use bitcoin::p2p::message_filter::CFHeaders;
+use bitcoin::p2p::message_filter::GetCFCheckpt;
+use bitcoin::p2p::message_filter::GetCFHeaders;
+use bitcoin::p2p::message_filter::GetCFilters;
 use bitcoin::p2p::message_network::VersionMessage;
 use bitcoin::p2p::ServiceFlags;
 use bitcoin::Block;
@@ -32,12 +45,22 @@ use tracing::error;
 use tracing::warn;
 
 use self::peer_utils::make_pong;
+use super::compact_block::COMPACT_BLOCKS_VERSION;
 use super::mempool::Mempool;
+use super::mempool::MIN_RELAY_FEERATE;
 use super::node::NodeNotification;
 use super::node::NodeRequest;
 use super::transport::TransportError;
 use super::transport::TransportProtocol;
 use super::transport::WriteTransport;
+use super::tx_relay::next_inventory_broadcast;
+use super::tx_relay::utreexo_tx_inventory;
+use super::tx_relay::TxAnnouncement;
+use super::tx_relay::UtreexoTransaction;
+use super::tx_relay::INBOUND_INVENTORY_INTERVAL;
+use super::tx_relay::MAX_INVENTORY_BROADCAST;
+use super::tx_relay::MAX_KNOWN_INVENTORY;
+use super::tx_relay::OUTBOUND_INVENTORY_INTERVAL;
 use crate::node::ConnectionKind;
 use crate::p2p_wire::block_proof::GetUtreexoProof;
 use crate::p2p_wire::block_proof::UtreexoProof;
@@ -56,6 +79,9 @@ const UTREEXO_PROOF_CMD_STRING: &str = "uproof";
 /// The command string for the "get utreexo proof" message
 const GET_UTREEXO_PROOF_CMD: &str = "getuproof";
 
+/// The command string for the "utreexo transaction" message
+const UTREEXO_TX_CMD: &str = "utreexotx";
+
 #[derive(Debug, PartialEq)]
 enum State {
     None,
@@ -118,8 +144,20 @@ pub struct Peer<T: AsyncWrite + Unpin + Send + Sync> {
     actor_receiver: UnboundedReceiver<ReaderMessage>, // Add the receiver for messages from TcpStreamActor
     writer: WriteTransport<T>,
     our_user_agent: String,
+    our_services: ServiceFlags,
     cancellation_sender: tokio::sync::oneshot::Sender<()>,
     transport_protocol: TransportProtocol,
+    /// Our peer only wants transactions paying at least this feerate, in sats per 1000 vbytes
+    fee_filter: u64,
+    /// Our peer wants transactions announced by wtxid (BIP339)
+    wtxid_relay: bool,
+    /// The txids and wtxids of the transactions this peer knows about, so we don't announce
+    /// them back
+    known_inventory: HashSet<[u8; 32]>,
+    /// The transactions we'll announce in our next `inv`, see [tx_relay](super::tx_relay)
+    inventory_queue: Vec<TxAnnouncement>,
+    /// When we should send our next `inv`
+    next_inventory_broadcast: Instant,
 }
 
 #[derive(Debug)]
@@ -243,10 +281,11 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
     }
 
     async fn peer_loop_inner(&mut self) -> Result<()> {
-        // send a version
-        let version = peer_utils::build_version_message(self.our_user_agent.clone());
-        self.write(version).await?;
-        self.state = State::SentVersion(Instant::now());
+        // send a version, inbound peers must send theirs first
+        if self.kind != ConnectionKind::Inbound {
+            self.send_version().await?;
+        }
+
         loop {
             tokio::select! {
                 request = tokio::time::timeout(Duration::from_secs(2), self.node_requests.recv()) => {
@@ -320,7 +359,54 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                     return Err(PeerError::UnexpectedMessage);
                 }
             }
+
+            self.broadcast_inventory().await?;
+        }
+    }
+
+    /// Sends the transactions in our inventory queue, if it's time to do so
+    async fn broadcast_inventory(&mut self) -> Result<()> {
+        if self.inventory_queue.is_empty() || Instant::now() < self.next_inventory_broadcast {
+            return Ok(());
         }
+
+        let average = match self.kind {
+            ConnectionKind::Inbound => INBOUND_INVENTORY_INTERVAL,
+            _ => OUTBOUND_INVENTORY_INTERVAL,
+        };
+        self.next_inventory_broadcast = Instant::now() + next_inventory_broadcast(average);
+
+        let fee_filter = self.fee_filter;
+        let wtxid_relay = self.wtxid_relay;
+        let count = self.inventory_queue.len().min(MAX_INVENTORY_BROADCAST);
+        let inv = self
+            .inventory_queue
+            .drain(..count)
+            .filter(|announcement| announcement.feerate >= fee_filter)
+            .map(|announcement| announcement.inventory(wtxid_relay))
+            .collect::<Vec<_>>();
+
+        if inv.is_empty() {
+            return Ok(());
+        }
+
+        self.write(NetworkMessage::Inv(inv)).await
+    }
+
+    /// Remembers that our peer knows about a transaction, because it announced it, sent it to
+    /// us or we've announced it
+    fn add_known_inventory(&mut self, hash: [u8; 32]) {
+        if self.known_inventory.len() >= MAX_KNOWN_INVENTORY {
+            self.known_inventory.clear();
+        }
+
+        self.known_inventory.insert(hash);
+    }
+
+    /// Remembers that our peer knows about a transaction, by txid and wtxid
+    fn add_known_transaction(&mut self, tx: &Transaction) {
+        self.add_known_inventory(tx.compute_txid().to_byte_array());
+        self.add_known_inventory(tx.compute_wtxid().to_byte_array());
     }
 
     pub async fn handle_node_request(&mut self, request: NodeRequest) -> Result<()> {
@@ -370,6 +456,21 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 self.write(NetworkMessage::GetData(vec![Inventory::Transaction(txid)]))
                     .await?;
             }
+            NodeRequest::AnnounceTransaction(announcement) => {
+                let txid = announcement.txid.to_byte_array();
+                if self.blocks_only || self.known_inventory.contains(&txid) {
+                    return Ok(());
+                }
+
+                self.add_known_inventory(txid);
+                self.add_known_inventory(announcement.wtxid.to_byte_array());
+                self.inventory_queue.push(announcement);
+            }
+            NodeRequest::GetUtreexoTransaction(inv) => {
+                if let Some(inv) = utreexo_tx_inventory(inv) {
+                    self.write(NetworkMessage::GetData(vec![inv])).await?;
+                }
+            }
             NodeRequest::SendAddresses(addresses) => {
                 self.write(NetworkMessage::AddrV2(addresses)).await?;
             }
@@ -382,6 +483,25 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
 
                 self.write(NetworkMessage::GetCFilters(get_filter)).await?;
             }
+            NodeRequest::GetFilterHeaders((stop_hash, start_height)) => {
+                let get_headers = bitcoin::p2p::message_filter::GetCFHeaders {
+                    filter_type: 0,
+                    start_height,
+                    stop_hash,
+                };
+
+                self.write(NetworkMessage::GetCFHeaders(get_headers))
+                    .await?;
+            }
+            NodeRequest::GetFilterCheckpoint(stop_hash) => {
+                let get_checkpoint = bitcoin::p2p::message_filter::GetCFCheckpt {
+                    filter_type: 0,
+                    stop_hash,
+                };
+
+                self.write(NetworkMessage::GetCFCheckpt(get_checkpoint))
+                    .await?;
+            }
             NodeRequest::Ping => {
                 let nonce = rand::random();
                 self.last_ping = Some(Instant::now());
@@ -402,6 +522,56 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 })
                 .await?;
             }
+            NodeRequest::SendBlock(block) => {
+                self.write(NetworkMessage::Block(block)).await?;
+            }
+            NodeRequest::SendBlockTxn(block_txn) => {
+                let block_txn = BlockTxn {
+                    transactions: block_txn,
+                };
+                self.write(NetworkMessage::BlockTxn(block_txn)).await?;
+            }
+            NodeRequest::GetCompactBlock(block_hash) => {
+                self.write(NetworkMessage::GetData(vec![Inventory::CompactBlock(
+                    block_hash,
+                )]))
+                .await?;
+            }
+            NodeRequest::GetBlockTxn(request) => {
+                let get_block_txn = GetBlockTxn {
+                    txs_request: request,
+                };
+                self.write(NetworkMessage::GetBlockTxn(get_block_txn))
+                    .await?;
+            }
+            NodeRequest::SendCmpct(high_bandwidth) => {
+                self.send_cmpct(high_bandwidth).await?;
+            }
+            NodeRequest::SendCompactBlock(compact_block) => {
+                let compact_block = *compact_block;
+                self.write(NetworkMessage::CmpctBlock(CmpctBlock { compact_block }))
+                    .await?;
+            }
+            NodeRequest::SendNotFound(inv) => {
+                self.write(NetworkMessage::NotFound(vec![inv])).await?;
+            }
+            NodeRequest::SendFilter(filter) => {
+                self.write(NetworkMessage::CFilter(filter)).await?;
+            }
+            NodeRequest::SendFilterHeaders(headers) => {
+                self.write(NetworkMessage::CFHeaders(headers)).await?;
+            }
+            NodeRequest::SendFilterCheckpoint(checkpoint) => {
+                self.write(NetworkMessage::CFCheckpt(checkpoint)).await?;
+            }
+            NodeRequest::SendUtreexoProof(proof) => {
+                self.write(NetworkMessage::Unknown {
+                    command: CommandString::try_from_static(UTREEXO_PROOF_CMD_STRING)
+                        .expect("Invalid command string"),
+                    payload: serialize(&proof),
+                })
+                .await?;
+            }
         }
         Ok(())
     }
@@ -415,7 +585,16 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                     for inv_entry in inv {
                         match inv_entry {
                             Inventory::Error => {}
-                            Inventory::Transaction(_) => {}
+                            Inventory::Transaction(_)
+                            | Inventory::WitnessTransaction(_)
+                            | Inventory::WTx(_) => {
+                                if let Some(hash) = inv_entry.network_hash() {
+                                    self.add_known_inventory(hash);
+                                }
+
+                                self.send_to_node(PeerMessages::TransactionInv(inv_entry))
+                                    .await;
+                            }
                             Inventory::Block(block_hash)
                             | Inventory::WitnessBlock(block_hash)
                             | Inventory::CompactBlock(block_hash) => {
@@ -438,8 +617,8 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 NetworkMessage::Ping(nonce) => {
                     self.handle_ping(nonce).await?;
                 }
-                NetworkMessage::FeeFilter(_) => {
-                    self.write(NetworkMessage::FeeFilter(1000)).await?;
+                NetworkMessage::FeeFilter(fee_filter) => {
+                    self.fee_filter = fee_filter.max(0) as u64;
                 }
                 NetworkMessage::AddrV2(addresses) => {
                     self.send_to_node(PeerMessages::Addr(addresses)).await;
@@ -456,6 +635,7 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                     }
                 }
                 NetworkMessage::Tx(tx) => {
+                    self.add_known_transaction(&tx);
                     self.send_to_node(PeerMessages::Transaction(tx)).await;
                 }
                 NetworkMessage::NotFound(inv) => {
@@ -470,25 +650,45 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 NetworkMessage::Pong(_) => {
                     self.last_ping = None;
                 }
-                NetworkMessage::Unknown { command, payload } => {
-                    let utreexo_proof_cmd =
-                        CommandString::try_from_static(UTREEXO_PROOF_CMD_STRING)
-                            .expect("Invalid command string");
-
-                    if command != utreexo_proof_cmd {
+                NetworkMessage::Unknown { command, payload } => match command.as_ref() {
+                    UTREEXO_PROOF_CMD_STRING => {
+                        let utreexo_proof: UtreexoProof = deserialize(&payload)?;
+                        self.send_to_node(PeerMessages::UtreexoProof(utreexo_proof))
+                            .await;
+                    }
+                    GET_UTREEXO_PROOF_CMD => {
+                        let request: GetUtreexoProof = deserialize(&payload)?;
+                        self.send_to_node(PeerMessages::GetUtreexoProof(request))
+                            .await;
+                    }
+                    UTREEXO_TX_CMD => {
+                        let utreexo_tx: UtreexoTransaction = deserialize(&payload)?;
+                        self.add_known_transaction(&utreexo_tx.transaction);
+                        self.send_to_node(PeerMessages::UtreexoTransaction(utreexo_tx))
+                            .await;
+                    }
+                    _ => {
                         warn!("Unknown command string: {command}");
-                        return Ok(());
                     }
-
-                    let utreexo_proof: UtreexoProof = deserialize(&payload)?;
-                    self.send_to_node(PeerMessages::UtreexoProof(utreexo_proof))
-                        .await;
-
-                    return Ok(());
-                }
+                },
                 NetworkMessage::Block(block) => {
                     self.send_to_node(PeerMessages::Block(block)).await;
                 }
+                NetworkMessage::GetBlockTxn(request) => {
+                    self.send_to_node(PeerMessages::GetBlockTxn(request.txs_request))
+                        .await;
+                }
+                NetworkMessage::SendCmpct(send_cmpct) => {
+                    self.handle_send_cmpct(send_cmpct).await;
+                }
+                NetworkMessage::CmpctBlock(cmpct_block) => {
+                    self.send_to_node(PeerMessages::CompactBlock(cmpct_block.compact_block))
+                        .await;
+                }
+                NetworkMessage::BlockTxn(block_txn) => {
+                    self.send_to_node(PeerMessages::BlockTxn(block_txn.transactions))
+                        .await;
+                }
                 NetworkMessage::CFilter(filter_msg) => match filter_msg.filter_type {
                     0 => {
                         let filter = BlockFilter::new(&filter_msg.filter);
@@ -505,6 +705,35 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                     }
                     _ => {}
                 },
+                NetworkMessage::CFHeaders(headers) => {
+                    if headers.filter_type == 0 {
+                        self.send_to_node(PeerMessages::FilterHeaders(headers))
+                            .await;
+                    }
+                }
+                NetworkMessage::GetCFilters(request) => {
+                    if request.filter_type == 0 {
+                        self.send_to_node(PeerMessages::GetFilters(request)).await;
+                    }
+                }
+                NetworkMessage::GetCFHeaders(request) => {
+                    if request.filter_type == 0 {
+                        self.send_to_node(PeerMessages::GetFilterHeaders(request))
+                            .await;
+                    }
+                }
+                NetworkMessage::GetCFCheckpt(request) => {
+                    if request.filter_type == 0 {
+                        self.send_to_node(PeerMessages::GetFilterCheckpoint(request))
+                            .await;
+                    }
+                }
+                NetworkMessage::CFCheckpt(checkpoint) => {
+                    if checkpoint.filter_type == 0 {
+                        self.send_to_node(PeerMessages::FilterCheckpoint(checkpoint))
+                            .await;
+                    }
+                }
                 // Explicitly ignore these messages, if something changes in the future
                 // this would cause a compile error.
                 NetworkMessage::Verack
@@ -512,21 +741,12 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 | NetworkMessage::WtxidRelay
                 | NetworkMessage::Reject(_)
                 | NetworkMessage::Alert(_)
-                | NetworkMessage::BlockTxn(_)
-                | NetworkMessage::CFCheckpt(_)
-                | NetworkMessage::CFHeaders(_)
-                | NetworkMessage::CmpctBlock(_)
                 | NetworkMessage::FilterAdd(_)
                 | NetworkMessage::FilterClear
                 | NetworkMessage::FilterLoad(_)
-                | NetworkMessage::GetBlockTxn(_)
-                | NetworkMessage::GetCFCheckpt(_)
-                | NetworkMessage::GetCFHeaders(_)
                 | NetworkMessage::Addr(_)
-                | NetworkMessage::GetCFilters(_)
                 | NetworkMessage::MemPool
-                | NetworkMessage::MerkleBlock(_)
-                | NetworkMessage::SendCmpct(_) => {}
+                | NetworkMessage::MerkleBlock(_) => {}
             },
             State::None | State::SentVersion(_) => match message {
                 bitcoin::p2p::message::NetworkMessage::Version(version) => {
@@ -540,6 +760,17 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
             State::SentVerack => match message {
                 bitcoin::p2p::message::NetworkMessage::Verack => {
                     self.state = State::Connected;
+
+                    // Tell our peer the minimum feerate of the transactions we want
+                    self.write(NetworkMessage::FeeFilter(MIN_RELAY_FEERATE as i64))
+                        .await?;
+
+                    // Tell segwit peers we can receive compact blocks, once they announce
+                    // a new block. See [compact_block](super::compact_block)
+                    if self.services.has(ServiceFlags::WITNESS) {
+                        self.send_cmpct(false).await?;
+                    }
+
                     self.send_to_node(PeerMessages::Ready(Version {
                         user_agent: self.user_agent.clone(),
                         protocol_version: 0,
@@ -558,7 +789,12 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                 bitcoin::p2p::message::NetworkMessage::SendHeaders => {
                     self.send_headers = true;
                 }
-                bitcoin::p2p::message::NetworkMessage::WtxidRelay => {}
+                bitcoin::p2p::message::NetworkMessage::WtxidRelay => {
+                    self.wtxid_relay = true;
+                }
+                bitcoin::p2p::message::NetworkMessage::SendCmpct(send_cmpct) => {
+                    self.handle_send_cmpct(send_cmpct).await;
+                }
                 _ => {
                     warn!("unexpected message: {:?} from peer {}", message, self.id);
                     return Err(PeerError::UnexpectedMessage);
@@ -590,6 +826,16 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
                     self.write(NetworkMessage::Tx(tx)).await?;
                 }
             }
+            Inventory::WTx(wtxid) => {
+                let tx = self.mempool.lock().await.get_by_wtxid(&wtxid).cloned();
+                if let Some(tx) = tx {
+                    self.write(NetworkMessage::Tx(tx)).await?;
+                }
+            }
+            // We don't keep blocks here, our node may have them in its block cache
+            Inventory::Block(_) | Inventory::WitnessBlock(_) | Inventory::CompactBlock(_) => {
+                self.send_to_node(PeerMessages::GetData(inv)).await;
+            }
             _ => {}
         }
         Ok(())
@@ -606,6 +852,7 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
         actor_receiver: UnboundedReceiver<ReaderMessage>,
         writer: WriteTransport<W>,
         our_user_agent: String,
+        our_services: ServiceFlags,
         cancellation_sender: tokio::sync::oneshot::Sender<()>,
         transport_protocol: TransportProtocol,
     ) {
@@ -631,8 +878,14 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
             actor_receiver, // Add the receiver for messages from TcpStreamActor
             writer,
             our_user_agent,
+            our_services,
             cancellation_sender,
             transport_protocol,
+            fee_filter: 0,
+            wtxid_relay: false,
+            known_inventory: HashSet::new(),
+            inventory_queue: Vec::new(),
+            next_inventory_broadcast: Instant::now(),
         };
 
         spawn(peer.read_loop());
@@ -642,12 +895,45 @@ impl<T: AsyncWrite + Unpin + Send + Sync> Peer<T> {
         let pong = make_pong(nonce);
         self.write(pong).await
     }
+    async fn send_version(&mut self) -> Result<()> {
+        let version =
+            peer_utils::build_version_message(self.our_user_agent.clone(), self.our_services);
+        self.write(version).await?;
+        self.state = State::SentVersion(Instant::now());
+        Ok(())
+    }
+
+    /// Asks our peer to send us compact blocks. In high-bandwidth mode, it should send them right
+    /// away, instead of announcing new blocks first
+    async fn send_cmpct(&mut self, high_bandwidth: bool) -> Result<()> {
+        let send_cmpct = SendCmpct {
+            send_compact: high_bandwidth,
+            version: COMPACT_BLOCKS_VERSION,
+        };
+
+        self.write(NetworkMessage::SendCmpct(send_cmpct)).await
+    }
+
+    /// Tells our node this peer can send us compact blocks, if it uses a version we understand
+    async fn handle_send_cmpct(&mut self, send_cmpct: SendCmpct) {
+        if send_cmpct.version == COMPACT_BLOCKS_VERSION {
+            self.send_to_node(PeerMessages::SendCmpct).await;
+        }
+    }
+
     async fn handle_version(&mut self, version: VersionMessage) -> Result<()> {
+        // We only answer with our version after an inbound peer sends theirs
+        if self.state == State::None {
+            self.send_version().await?;
+        }
+
         self.user_agent = version.user_agent;
         self.blocks_only = !version.relay;
         self.current_best_block = version.start_height;
         self.services = version.services;
         if version.version >= 70016 {
+            // We announce transactions by wtxid, if our peer also wants so (BIP339)
+            self.write(NetworkMessage::WtxidRelay).await?;
             self.write(NetworkMessage::SendAddrV2).await?;
         }
         self.state = State::SentVerack;
@@ -671,6 +957,7 @@ pub(super) mod peer_utils {
     use bitcoin::p2p::message::NetworkMessage;
     use bitcoin::p2p::message::{self};
     use bitcoin::p2p::message_network;
+    use bitcoin::p2p::ServiceFlags;
 
     /// Protocol version we speak
     pub const PROTOCOL_VERSION: u32 = 70016;
@@ -679,15 +966,13 @@ pub(super) mod peer_utils {
         NetworkMessage::Pong(nonce)
     }
 
-    pub(crate) fn build_version_message(user_agent: String) -> message::NetworkMessage {
-        use bitcoin::p2p::ServiceFlags;
-
+    pub(crate) fn build_version_message(
+        user_agent: String,
+        services: ServiceFlags,
+    ) -> message::NetworkMessage {
         // Building version message, see https://en.bitcoin.it/wiki/Protocol_documentation#version
         let my_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 38332);
 
-        // "bitfield of features to be enabled for this connection"
-        let services = ServiceFlags::NETWORK | ServiceFlags::from(1 << 24) | ServiceFlags::WITNESS;
-
         // "standard UNIX timestamp in seconds"
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -715,7 +1000,7 @@ pub(super) mod peer_utils {
             nonce,
             user_agent,
             start_height,
-            relay: false,
+            relay: true,
             version: PROTOCOL_VERSION,
         })
     }
@@ -760,12 +1045,51 @@ pub enum PeerMessages {
     /// Remote peer sent us a transaction
     Transaction(Transaction),
 
+    /// Remote peer announced a transaction, see [tx_relay](super::tx_relay)
+    TransactionInv(Inventory),
+
+    /// Remote peer sent us a transaction with a Utreexo proof for its inputs
+    UtreexoTransaction(UtreexoTransaction),
+
     /// Remote peer sent us a Utreexo state
     UtreexoState(Vec<u8>),
 
     /// Remote peer sent us a compact block filter
     BlockFilter((BlockHash, BlockFilter)),
 
+    /// Remote peer sent us the filter hashes we've asked for, to build their filter headers
+    FilterHeaders(CFHeaders),
+
+    /// Remote peer sent us its filter header checkpoints
+    FilterCheckpoint(CFCheckpt),
+
+    /// Remote peer wants the compact filters for some blocks
+    GetFilters(GetCFilters),
+
+    /// Remote peer wants the filter hashes for some blocks, to build their filter headers
+    GetFilterHeaders(GetCFHeaders),
+
+    /// Remote peer wants our filter header checkpoints
+    GetFilterCheckpoint(GetCFCheckpt),
+
     /// Remote peer sent us a Utreexo proof,
     UtreexoProof(UtreexoProof),
+
+    /// Remote peer wants the Utreexo proof for a block
+    GetUtreexoProof(GetUtreexoProof),
+
+    /// Remote peer wants some data we don't keep in the peer, like a block
+    GetData(Inventory),
+
+    /// Remote peer wants some transactions from a block
+    GetBlockTxn(BlockTransactionsRequest),
+
+    /// Remote peer can send us compact blocks, see [compact_block](super::compact_block)
+    SendCmpct,
+
+    /// Remote peer sent us a compact block
+    CompactBlock(HeaderAndShortIds),
+
+    /// Remote peer sent us the transactions we've asked for, to rebuild a compact block
+    BlockTxn(BlockTransactions),
 }
@@ -6,19 +6,34 @@ use std::collections::HashMap;
 use std::time::Duration;
 use std::time::Instant;
 
+use bitcoin::bip152::BlockTransactions;
+use bitcoin::bip152::BlockTransactionsRequest;
+use bitcoin::bip152::HeaderAndShortIds;
 use bitcoin::bip158::BlockFilter;
+use bitcoin::bip158::FilterHash;
+use bitcoin::bip158::FilterHeader;
+use bitcoin::hashes::Hash;
 use bitcoin::p2p::address::AddrV2Message;
 use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::p2p::message_filter::CFCheckpt;
+use bitcoin::p2p::message_filter::CFHeaders;
 use bitcoin::p2p::ServiceFlags;
 use bitcoin::BlockHash;
+use bitcoin::Txid;
 use floresta_chain::proof_util;
+use floresta_chain::pruned_utreexo::consensus::Consensus;
 use floresta_chain::pruned_utreexo::partial_chain::PartialChainState;
 use floresta_chain::pruned_utreexo::BlockchainInterface;
 use floresta_chain::pruned_utreexo::UpdatableChainstate;
 use floresta_chain::ThreadSafeChain;
+use floresta_chain::UtxoData;
 use floresta_common::service_flags;
 use floresta_common::service_flags::UTREEXO;
 use rand::random;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use rustreexo::accumulator::node_hash::BitcoinNodeHash;
+use rustreexo::accumulator::proof::Proof;
 use rustreexo::accumulator::stump::Stump;
 use tokio::time::timeout;
 use tracing::debug;
@@ -26,15 +41,40 @@ use tracing::error;
 use tracing::info;
 use tracing::warn;
 
+use super::backfill;
+use super::backfill::BackfillRange;
+use super::backfill::BackfillState;
+use super::block_proof::Bitmap;
+use super::compact_block::CompactBlockError;
+use super::compact_block::PartialBlock;
+use super::compact_block::MAX_HIGH_BANDWIDTH_PEERS;
 use super::error::WireError;
+use super::filter_headers::checkpoint_height;
+use super::filter_headers::compare_checkpoints;
+use super::filter_headers::filter_headers_stop;
+use super::filter_headers::BatchCheck;
+use super::filter_headers::CheckpointsAgreement;
+use super::filter_headers::FilterDispute;
+use super::filter_headers::FilterHeadersBatch;
+use super::filter_headers::CHECKPOINT_INTERVAL;
+use super::filter_headers::CHECKPOINT_PEERS;
+use super::filter_headers::TIP_HEADERS_PEERS;
 use super::peer::PeerMessages;
+use super::tx_relay::TxAnnouncement;
+use super::tx_relay::UtreexoTransaction;
+use super::tx_relay::MAX_TX_REQUESTS;
+use super::tx_relay::MEMPOOL_SCRIPT_FLAGS;
+use super::tx_relay::TX_REQUEST_TIMEOUT;
+use super::UtreexoNodeConfig;
 use crate::node::periodic_job;
 use crate::node::try_and_log;
 use crate::node::try_and_warn;
 use crate::node::ConnectionKind;
+use crate::node::HeadersPresync;
 use crate::node::InflightRequests;
 use crate::node::NodeNotification;
 use crate::node::NodeRequest;
+use crate::node::PeerStatus;
 use crate::node::UtreexoNode;
 use crate::node_context::NodeContext;
 use crate::node_context::PeerId;
@@ -53,8 +93,28 @@ pub struct RunningNode {
     /// We also keep the moment we received the first inv message
     pub(crate) last_invs: HashMap<BlockHash, (Instant, Vec<PeerId>)>,
     pub(crate) inflight_filters: BTreeMap<u32, BlockFilter>,
+    /// The filter header checkpoints our peers agreed on, by height. See
+    /// [filter_headers](super::filter_headers)
+    pub(crate) filter_checkpoints: Option<BTreeMap<u32, FilterHeader>>,
+    /// The filter header checkpoints our peers sent us, before we compare them
+    pub(crate) checkpoint_responses: Vec<(PeerId, Vec<FilterHeader>)>,
+    /// The filter headers we are downloading
+    pub(crate) filter_headers_batch: Option<FilterHeadersBatch>,
+    /// The block whose filter two peers disagree on, while we build the filter ourselves
+    pub(crate) filter_dispute: Option<FilterDispute>,
+    /// The compact blocks we are rebuilding, see [compact_block](super::compact_block)
+    pub(crate) partial_blocks: HashMap<BlockHash, PartialBlock>,
+    /// The peers we've asked to send us compact blocks right away, oldest first
+    pub(crate) high_bandwidth_peers: Vec<PeerId>,
+    /// The announced transactions we've asked for, by txid or wtxid, and when we did it. See
+    /// [tx_relay](super::tx_relay)
+    pub(crate) tx_requests: HashMap<[u8; 32], Instant>,
 }
 
+/// We only ask for compact blocks up to this many blocks after our validation index, the
+/// transactions in older blocks aren't in our mempool anymore
+const MAX_COMPACT_BLOCK_DEPTH: u32 = 2;
+
 impl NodeContext for RunningNode {
     const REQUEST_TIMEOUT: u64 = 2 * 60;
     fn get_required_services(&self) -> ServiceFlags {
@@ -124,12 +184,22 @@ where
         self.maybe_open_connection_with_added_peers().await?;
 
         // if we have 10 connections, but not a single utreexo or CBF one, disconnect one random
-        // peer and create a utreexo and CBS connection
+        // peer and create a utreexo and CBS connection. Inbound peers don't count here
+        let outbound_peers: Vec<_> = self
+            .peer_ids
+            .iter()
+            .copied()
+            .filter(|peer| {
+                self.peers
+                    .get(peer)
+                    .is_some_and(|peer| peer.kind != ConnectionKind::Inbound)
+            })
+            .collect();
+
         if !self.has_utreexo_peers() {
-            if self.peer_ids.len() == 10 {
-                let peer = random::<usize>() % self.peer_ids.len();
-                let peer = self
-                    .peer_ids
+            if outbound_peers.len() == 10 {
+                let peer = random::<usize>() % outbound_peers.len();
+                let peer = outbound_peers
                     .get(peer)
                     .expect("we've modulo before, we should have it");
                 self.send_to_peer(*peer, NodeRequest::Shutdown).await?;
@@ -142,10 +212,9 @@ where
             if self.block_filters.is_none() {
                 return Ok(());
             }
-            if self.peer_ids.len() == 10 {
-                let peer = random::<usize>() % self.peer_ids.len();
-                let peer = self
-                    .peer_ids
+            if outbound_peers.len() == 10 {
+                let peer = random::<usize>() % outbound_peers.len();
+                let peer = outbound_peers
                     .get(peer)
                     .expect("we've modulo before, we should have it");
                 self.send_to_peer(*peer, NodeRequest::Shutdown).await?;
@@ -164,105 +233,182 @@ where
     /// historical blocks. This allow us to start the node faster, making it usable in a few
     /// minutes. If you still want to validate all blocks, you can enable the backfill option.
     ///
-    /// This function will spawn a background task that will download and validate all blocks
-    /// that got assumed. After completion, the task will shutdown and the node will continue
-    /// running normally. If we ever assume an invalid chain, the node will [halt and catch fire].
+    /// This function will spawn background tasks that will download and validate all blocks
+    /// that got assumed, one for each range in our [backfill] plan. After all of them complete,
+    /// we check that their final accumulators match, and the tasks shutdown. The node will
+    /// continue running normally. If we ever assume an invalid chain, the node will
+    /// [halt and catch fire].
+    ///
+    /// Each task sends a message to `done_flag` when it stops. Returns how many tasks we've
+    /// spawned.
     ///
     /// [halt and catch fire]: https://en.wikipedia.org/wiki/Halt_and_Catch_Fire_(computing)
-    pub fn backfill(&self, done_flag: std::sync::mpsc::Sender<()>) -> Result<bool, WireError> {
-        // try finding the last state of the sync node
-        let state = std::fs::read(self.config.datadir.clone() + "/.sync_node_state");
-        // try to recover from the disk state, if it exists. Otherwise, start from genesis
-        let (chain, end) = match state {
-            Ok(state) => {
-                // if this file is empty, this means we've finished backfilling
-                if state.is_empty() {
-                    return Ok(false);
+    pub fn backfill(&self, done_flag: std::sync::mpsc::Sender<()>) -> Result<usize, WireError> {
+        let datadir = self.config.datadir.clone();
+        let network = self.chain_params.magic;
+
+        // try to recover the ranges from the disk state. If there's none, or it can't be used,
+        // start a new plan
+        let state = backfill::load(&datadir, network, |height| {
+            self.chain.get_block_hash(height).ok()
+        });
+
+        let state = state.unwrap_or_else(|e| {
+            warn!("Can't resume the backfill ({e}), starting it from scratch");
+            backfill::reset(&datadir).expect("Failed to remove backfill state");
+            BackfillState::NotStarted
+        });
+
+        let ranges = match state {
+            BackfillState::Done => {
+                self.backfill.lock().unwrap().finish();
+                return Ok(0);
+            }
+            BackfillState::InProgress(ranges) => {
+                info!("Recovering {} backfill ranges", ranges.len());
+                ranges
+            }
+            BackfillState::NotStarted => {
+                let end = self.chain.get_validation_index()?;
+
+                let mut checkpoints = self.backfill_checkpoints.clone();
+                if let Some(assume_utreexo) = self.config.assume_utreexo.as_ref() {
+                    let acc = Stump {
+                        leaves: assume_utreexo.leaves,
+                        roots: assume_utreexo.roots.clone(),
+                    };
+                    checkpoints.push((assume_utreexo.height, acc));
                 }
 
-                let acc = Stump::deserialize(&state[..(state.len() - 8)]).unwrap();
-                let tip = u32::from_le_bytes(
-                    state[(state.len() - 8)..(state.len() - 4)]
-                        .try_into()
-                        .unwrap(),
-                );
+                let ranges = backfill::plan(end, self.chain.acc(), &checkpoints);
+                for range in ranges.iter() {
+                    let tip = self.chain.get_block_hash(range.height)?;
+                    backfill::save_range(&datadir, network, range, tip)?;
+                }
 
-                let end = u32::from_le_bytes(state[(state.len() - 4)..].try_into().unwrap());
-                info!("Recovering backfill node from state tip={tip}, end={tip}");
-
-                (
-                    self.chain
-                        .get_partial_chain(tip, end, acc)
-                        .expect("Failed to get partial chain"),
-                    end,
-                )
-            }
-            Err(_) => {
-                // if the file doesn't exist or got corrupted, start from genesis
-                let end = self
-                    .chain
-                    .get_validation_index()
-                    .expect("can get the validation index");
-                (
-                    self.chain
-                        .get_partial_chain(0, end, Stump::default())
-                        .unwrap(),
-                    end,
-                )
+                ranges
             }
         };
 
-        let backfill = UtreexoNode::<PartialChainState, SyncNode>::new(
-            self.config.clone(),
-            chain,
-            self.mempool.clone(),
-            None,
-            self.kill_signal.clone(),
-            self.address_man.clone(),
-        )
-        .unwrap();
+        let pending: Vec<_> = (0..ranges.len())
+            .filter(|i| !ranges[*i].is_done())
+            .collect();
 
-        let datadir = self.config.datadir.clone();
-        let outer_chain = self.chain.clone();
+        // every range finished, but we didn't get to check them
+        if pending.is_empty() {
+            Self::finish_backfill(&ranges, &self.chain, &datadir);
+            self.backfill.lock().unwrap().finish();
+            return Ok(0);
+        }
 
-        let fut = UtreexoNode::<PartialChainState, SyncNode>::run(
-            backfill,
-            move |chain: &PartialChainState| {
-                if chain.has_invalid_blocks() {
-                    panic!("We assumed a chain with invalid blocks, something went really wrong");
-                }
+        self.backfill.lock().unwrap().start(ranges.clone());
+        for i in pending.iter().copied() {
+            let range = ranges[i].clone();
+            info!(
+                "Starting backfill of blocks {}..={} from height={}",
+                range.start + 1,
+                range.end,
+                range.height
+            );
 
-                done_flag.send(()).unwrap();
-
-                // we haven't finished the backfill yet, save the current state for the next run
-                if chain.is_in_ibd() {
-                    let acc = chain.get_acc();
-                    let tip = chain.get_height().unwrap();
-                    let mut ser_acc = Vec::new();
-                    acc.serialize(&mut ser_acc).unwrap();
-                    ser_acc.extend_from_slice(&tip.to_le_bytes());
-                    ser_acc.extend_from_slice(&end.to_le_bytes());
-                    std::fs::write(datadir + "/.sync_node_state", ser_acc)
-                        .expect("Failed to write sync node state");
-                    return;
+            let chain = self
+                .chain
+                .get_partial_chain(range.height, range.end, range.acc)?;
+
+            // The bridge and block cache only follow our tip, backfill tasks don't touch them.
+            // Only our main node accepts inbound connections
+            let config = UtreexoNodeConfig {
+                bridge: false,
+                block_cache_depth: 0,
+                listen: None,
+                ..self.config.clone()
+            };
+
+            let backfill = UtreexoNode::<PartialChainState, SyncNode>::new(
+                config,
+                chain,
+                self.mempool.clone(),
+                None,
+                self.kill_signal.clone(),
+                self.address_man.clone(),
+            )?;
+
+            let tracker = self.backfill.clone();
+            let outer_chain = self.chain.clone();
+            let save_progress = {
+                let datadir = datadir.clone();
+                move |chain: &PartialChainState| {
+                    let height = chain.get_height().unwrap();
+                    let tip = outer_chain
+                        .get_block_hash(height)
+                        .expect("Backfilled blocks are in our chain");
+
+                    let mut tracker = tracker.lock().unwrap();
+                    let range = tracker.update(i, height, chain.get_acc());
+                    try_and_warn!(backfill::save_range(&datadir, network, range, tip));
                 }
+            };
+
+            let tracker = self.backfill.clone();
+            let outer_chain = self.chain.clone();
+            let datadir = datadir.clone();
+            let done_flag = done_flag.clone();
+            let on_progress = save_progress.clone();
+
+            let fut = UtreexoNode::<PartialChainState, SyncNode>::run_with_progress(
+                backfill,
+                save_progress,
+                move |chain: &PartialChainState| {
+                    if chain.has_invalid_blocks() {
+                        panic!(
+                            "We assumed a chain with invalid blocks, something went really wrong"
+                        );
+                    }
 
-                // empty the file if we're done
-                std::fs::write(datadir + "/.sync_node_state", Vec::new())
-                    .expect("Failed to write sync node state");
+                    // save the current state, so we can resume this range on the next run
+                    on_progress(chain);
 
-                for block in chain.list_valid_blocks() {
-                    outer_chain
-                        .mark_block_as_valid(block.block_hash())
-                        .expect("Failed to mark block as valid");
-                }
+                    let mut tracker = tracker.lock().unwrap();
+                    if tracker.ranges().iter().all(BackfillRange::is_done) {
+                        Self::finish_backfill(tracker.ranges(), &outer_chain, &datadir);
+                        tracker.finish();
+                    }
 
-                info!("Backfilling task shutting down...");
-            },
-        );
+                    let _ = done_flag.send(());
+                },
+            );
+
+            tokio::task::spawn(fut);
+        }
 
-        tokio::task::spawn(fut);
-        Ok(true)
+        Ok(pending.len())
+    }
+
+    /// Checks that all backfill ranges got the accumulators we've assumed, and marks the blocks
+    /// as valid.
+    fn finish_backfill(ranges: &[BackfillRange], chain: &Chain, datadir: &str) {
+        if let Some(range) = backfill::find_invalid_range(ranges) {
+            panic!(
+                "We assumed a chain with invalid blocks, the backfill of blocks {}..={} got an \
+                unexpected accumulator",
+                range.start + 1,
+                range.end
+            );
+        }
+
+        let end = ranges.last().map_or(0, |range| range.end);
+        for height in 0..=end {
+            let block = chain
+                .get_block_hash(height)
+                .expect("Backfilled blocks are in our chain");
+
+            chain
+                .mark_block_as_valid(block)
+                .expect("Failed to mark block as valid");
+        }
+
+        backfill::mark_done(datadir).expect("Failed to write backfill state");
+        info!("Backfilled all blocks up to height={end}, backfilling tasks shutting down...");
     }
 
     pub async fn run(mut self, stop_signal: tokio::sync::oneshot::Sender<()>) {
@@ -290,13 +436,13 @@ where
         // download blocks from the network before our validation index, probably because we've
         // assumed it somehow.
         let (sender, recv) = std::sync::mpsc::channel();
-        let is_backfilling = match self.config.backfill {
+        let backfill_tasks = match self.config.backfill {
             true => {
-                info!("Starting backfill task...");
+                info!("Starting backfill tasks...");
                 self.backfill(sender)
-                    .expect("Failed to spawn backfill thread")
+                    .expect("Failed to spawn backfill tasks")
             }
-            false => false,
+            false => 0,
         };
 
         // Catch up with the network, downloading blocks from our last validation index to the tip
@@ -331,6 +477,8 @@ where
                 .unwrap();
         }
 
+        try_and_log!(self.start_listener());
+
         info!("starting running node...");
         loop {
             if *self.kill_signal.read().await {
@@ -373,6 +521,13 @@ where
 
             // Check if some of our peers have timed out a request
             try_and_log!(self.check_for_timeout().await);
+            self.drop_stale_partial_blocks();
+            self.drop_stale_filter_dispute();
+
+            // Forget the transactions our peers didn't send in time, so we can ask someone else
+            self.context
+                .tx_requests
+                .retain(|_, when| when.elapsed() < TX_REQUEST_TIMEOUT);
 
             // Open new feeler connection periodically
             periodic_job!(
@@ -419,7 +574,7 @@ where
             try_and_log!(self.download_filters().await);
 
             // requests that need a utreexo peer
-            if !self.has_utreexo_peers() {
+            if !self.can_get_proofs() {
                 continue;
             }
 
@@ -429,9 +584,9 @@ where
             }
         }
 
-        // ignore the error here because if the backfill task already
+        // ignore the error here because if the backfill tasks already
         // finished, this channel will be closed
-        if is_backfilling {
+        for _ in 0..backfill_tasks {
             let _ = recv.recv();
         }
 
@@ -472,12 +627,14 @@ where
             return Ok(());
         }
 
+        // We only download the filters we can check against their filter headers
+        let headers_height = filters.get_headers_height()?.unwrap_or(0);
+        if headers_height <= height || filters.get_filter_header(height)?.is_none() {
+            return self.download_filter_headers(height).await;
+        }
+
         info!("Downloading filters from height {}", filters.get_height()?);
-        let stop = if height + 500 > best_height {
-            best_height
-        } else {
-            height + 500
-        };
+        let stop = (height + 500).min(best_height).min(headers_height);
 
         let stop_hash = self.chain.get_block_hash(stop)?;
         self.last_filter = stop_hash;
@@ -495,6 +652,460 @@ where
         Ok(())
     }
 
+    /// Downloads the filter headers we need to check the filters after `filter_height`, see
+    /// [filter_headers](super::filter_headers)
+    async fn download_filter_headers(&mut self, filter_height: u32) -> Result<(), WireError> {
+        let waiting = self.inflight.keys().any(|request| {
+            matches!(
+                request,
+                InflightRequests::GetFilterHeaders(_)
+                    | InflightRequests::GetFilterCheckpoint(_)
+                    | InflightRequests::FilterDispute(_)
+            )
+        });
+
+        if waiting {
+            return Ok(());
+        }
+
+        if let Some(batch) = self.context.filter_headers_batch.take() {
+            if batch.is_complete() {
+                return self.check_filter_headers(batch).await;
+            }
+
+            // Some peer didn't answer, we'll ask again. If we were looking for who lied about
+            // a checkpoint, we start over with new checkpoints.
+            if self.context.filter_checkpoints.is_none() {
+                self.context.checkpoint_responses.clear();
+            }
+
+            return Ok(());
+        }
+
+        let Some(checkpoints) = self.context.filter_checkpoints.as_ref() else {
+            if self.context.checkpoint_responses.is_empty() {
+                return self.request_filter_checkpoints().await;
+            }
+
+            return self.check_filter_checkpoints().await;
+        };
+
+        let Some(ref filters) = self.block_filters else {
+            return Ok(());
+        };
+
+        let best_height = self.chain.get_height()?;
+        let last_checkpoint = checkpoints.keys().last().copied().unwrap_or(0);
+
+        let headers_height = filters.get_headers_height()?;
+        let (start, previous) = match headers_height {
+            Some(height)
+                if height >= filter_height
+                    && filters.get_filter_header(filter_height)?.is_some() =>
+            {
+                let previous = filters
+                    .get_filter_header(height)?
+                    .expect("we have every header after our filters");
+
+                (height + 1, previous)
+            }
+            _ => {
+                // We don't need the headers before our filters, so we start from the
+                // checkpoint right before them
+                let height = (filter_height / CHECKPOINT_INTERVAL * CHECKPOINT_INTERVAL)
+                    .min(last_checkpoint);
+
+                match checkpoints.get(&height) {
+                    Some(checkpoint) => {
+                        // We don't know the filter hash for a checkpoint
+                        let entry = (FilterHash::all_zeros(), *checkpoint);
+                        filters.push_filter_headers(height, &[entry])?;
+                        filters.save_headers_height(height)?;
+                        (height + 1, *checkpoint)
+                    }
+                    None => (0, FilterHeader::all_zeros()),
+                }
+            }
+        };
+
+        if start > best_height {
+            return Ok(());
+        }
+
+        // Headers after our last checkpoint must be the same for more than one peer
+        let stop = filter_headers_stop(start, last_checkpoint, best_height);
+        let peers = match stop > last_checkpoint {
+            true => self.compact_filters_peers(TIP_HEADERS_PEERS),
+            false => self.compact_filters_peers(1),
+        };
+
+        if peers.is_empty() {
+            return Ok(());
+        }
+
+        debug!("Downloading filter headers from {start} to {stop}");
+        let stop_hash = self.chain.get_block_hash(stop)?;
+        for peer in peers.iter() {
+            self.send_to_peer(*peer, NodeRequest::GetFilterHeaders((stop_hash, start)))
+                .await?;
+
+            self.inflight.insert(
+                InflightRequests::GetFilterHeaders(*peer),
+                (*peer, Instant::now()),
+            );
+        }
+
+        self.context.filter_headers_batch =
+            Some(FilterHeadersBatch::new(start, stop, previous, peers));
+
+        Ok(())
+    }
+
+    /// Up to `count` random peers that can send us compact filters
+    fn compact_filters_peers(&self, count: usize) -> Vec<PeerId> {
+        let peers: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.state == PeerStatus::Ready)
+            .filter(|(_, peer)| peer.services.has(ServiceFlags::COMPACT_FILTERS))
+            .map(|(id, _)| *id)
+            .collect();
+
+        peers
+            .choose_multiple(&mut thread_rng(), count)
+            .copied()
+            .collect()
+    }
+
+    async fn request_filter_checkpoints(&mut self) -> Result<(), WireError> {
+        let (_, stop_hash) = self.chain.get_best_block()?;
+        for peer in self.compact_filters_peers(CHECKPOINT_PEERS) {
+            self.send_to_peer(peer, NodeRequest::GetFilterCheckpoint(stop_hash))
+                .await?;
+
+            self.inflight.insert(
+                InflightRequests::GetFilterCheckpoint(peer),
+                (peer, Instant::now()),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn handle_filter_checkpoint(
+        &mut self,
+        peer: PeerId,
+        checkpoint: CFCheckpt,
+    ) -> Result<(), WireError> {
+        let request = InflightRequests::GetFilterCheckpoint(peer);
+        if self.inflight.remove(&request).is_none() {
+            debug!("Peer {peer} sent us filter checkpoints, but we didn't ask");
+            return Ok(());
+        }
+
+        // We can't use checkpoints for blocks we don't have
+        let mut headers = checkpoint.filter_headers;
+        let best_height = self.chain.get_height()?;
+        headers.truncate((best_height / CHECKPOINT_INTERVAL) as usize);
+
+        self.context.checkpoint_responses.push((peer, headers));
+        Ok(())
+    }
+
+    /// Compares the checkpoints our peers sent us. If two of them disagree, we download the
+    /// filter headers up to that checkpoint from both, to find out who is lying.
+    async fn check_filter_checkpoints(&mut self) -> Result<(), WireError> {
+        let (index, peer1, peer2) = match compare_checkpoints(&self.context.checkpoint_responses) {
+            CheckpointsAgreement::Agree(checkpoints) => {
+                info!(
+                    "Our peers agree on {} filter checkpoints",
+                    checkpoints.len()
+                );
+
+                self.context.filter_checkpoints = Some(checkpoints);
+                self.context.checkpoint_responses.clear();
+                return Ok(());
+            }
+            CheckpointsAgreement::Disagree {
+                index,
+                peers: (peer1, peer2),
+            } => (index, peer1, peer2),
+        };
+
+        warn!("Peers {peer1} and {peer2} disagree on the filter checkpoint {index}");
+
+        let (start, previous) = match index {
+            0 => (0, FilterHeader::all_zeros()),
+            _ => {
+                let (_, checkpoints) = self
+                    .context
+                    .checkpoint_responses
+                    .iter()
+                    .find(|(peer, _)| *peer == peer1)
+                    .expect("this peer sent us checkpoints");
+
+                (checkpoint_height(index - 1) + 1, checkpoints[index - 1])
+            }
+        };
+
+        let stop = checkpoint_height(index);
+        let stop_hash = self.chain.get_block_hash(stop)?;
+        for peer in [peer1, peer2] {
+            self.send_to_peer(peer, NodeRequest::GetFilterHeaders((stop_hash, start)))
+                .await?;
+
+            self.inflight.insert(
+                InflightRequests::GetFilterHeaders(peer),
+                (peer, Instant::now()),
+            );
+        }
+
+        self.context.filter_headers_batch = Some(FilterHeadersBatch::new(
+            start,
+            stop,
+            previous,
+            vec![peer1, peer2],
+        ));
+
+        Ok(())
+    }
+
+    async fn handle_filter_headers(
+        &mut self,
+        peer: PeerId,
+        headers: CFHeaders,
+    ) -> Result<(), WireError> {
+        let request = InflightRequests::GetFilterHeaders(peer);
+        if self.inflight.remove(&request).is_none() {
+            debug!("Peer {peer} sent us filter headers, but we didn't ask");
+            return Ok(());
+        }
+
+        let Some((stop, previous)) = self
+            .context
+            .filter_headers_batch
+            .as_ref()
+            .map(|batch| (batch.stop, batch.previous))
+        else {
+            return Ok(());
+        };
+
+        let stop_hash = self.chain.get_block_hash(stop)?;
+        if headers.stop_hash != stop_hash || headers.previous_filter_header != previous {
+            warn!("Peer {peer} sent us filter headers that don't match our request");
+            return self.increase_banscore(peer, 10).await;
+        }
+
+        let batch = self
+            .context
+            .filter_headers_batch
+            .as_mut()
+            .expect("we've just checked it");
+
+        batch.responses.push((peer, headers.filter_hashes));
+        if batch.is_complete() {
+            self.download_filters().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores the headers in a batch, if they are valid and every peer we've asked agrees
+    async fn check_filter_headers(&mut self, batch: FilterHeadersBatch) -> Result<(), WireError> {
+        let check = match self.context.filter_checkpoints.as_ref() {
+            Some(checkpoints) => batch.check(checkpoints),
+            None => batch.check(&BTreeMap::new()),
+        };
+
+        // If we don't have checkpoints, this batch goes up to the one our peers disagree on
+        let disputed_checkpoint = self.context.filter_checkpoints.is_none();
+        match check {
+            BatchCheck::Valid(headers) if disputed_checkpoint => {
+                // Both peers sent the same headers, so whoever sent a different checkpoint lied
+                let index = (batch.stop / CHECKPOINT_INTERVAL - 1) as usize;
+                let last = headers.last().copied();
+
+                let liars: Vec<_> = self
+                    .context
+                    .checkpoint_responses
+                    .iter()
+                    .filter(|(_, checkpoints)| {
+                        checkpoints
+                            .get(index)
+                            .is_some_and(|checkpoint| Some(*checkpoint) != last)
+                    })
+                    .map(|(peer, _)| *peer)
+                    .collect();
+
+                for liar in liars {
+                    warn!("Peer {liar} sent us an invalid filter checkpoint, banning it");
+                    self.increase_banscore(liar, self.max_banscore).await?;
+                }
+            }
+            BatchCheck::Valid(headers) => {
+                let Some(ref filters) = self.block_filters else {
+                    return Ok(());
+                };
+
+                let (_, hashes) = &batch.responses[0];
+                let entries: Vec<_> = hashes.iter().copied().zip(headers).collect();
+
+                filters.push_filter_headers(batch.start, &entries)?;
+                filters.save_headers_height(batch.stop)?;
+                return Ok(());
+            }
+            BatchCheck::Invalid(peer) => {
+                warn!("Peer {peer} sent us invalid filter headers, banning it");
+                self.increase_banscore(peer, self.max_banscore).await?;
+            }
+            BatchCheck::Disagree {
+                height,
+                peers,
+                hashes,
+            } => {
+                warn!(
+                    "Peers {} and {} disagree on the filter for block {height}",
+                    peers.0, peers.1
+                );
+
+                let dispute =
+                    FilterDispute::new(height, self.chain.get_block_hash(height)?, peers, hashes);
+
+                if let Err(e) = self.start_filter_dispute(dispute).await {
+                    warn!("Couldn't find who lied about the filter for block {height}: {e}");
+                }
+            }
+            BatchCheck::Empty => {}
+        }
+
+        // We'll start over with the peers that are left
+        if disputed_checkpoint {
+            self.context.checkpoint_responses.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Starts finding which of two peers is lying about the filter for a block
+    ///
+    /// Like [ChainSelector] does for accumulators, we download the block and its Utreexo proof,
+    /// and build the filter ourselves, as the proof has the scripts for every output spent in
+    /// this block. We decide who lied once both arrive, see [Self::handle_filter_dispute_data].
+    async fn start_filter_dispute(&mut self, dispute: FilterDispute) -> Result<(), WireError> {
+        let utreexo_peer = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.state == PeerStatus::Ready)
+            .find(|(_, peer)| peer.services.has(UTREEXO.into()))
+            .map(|(id, _)| *id)
+            .ok_or(WireError::NoPeersAvailable)?;
+
+        let block_hash = dispute.block_hash;
+        self.send_to_peer(utreexo_peer, NodeRequest::GetBlock(vec![block_hash]))
+            .await?;
+
+        // Sending two empty bitmaps means we want the full proof and all leaf data
+        self.send_to_peer(
+            utreexo_peer,
+            NodeRequest::GetBlockProof((block_hash, Bitmap::new(), Bitmap::new())),
+        )
+        .await?;
+
+        self.inflight.insert(
+            InflightRequests::FilterDispute(block_hash),
+            (utreexo_peer, Instant::now()),
+        );
+        self.context.filter_dispute = Some(dispute);
+
+        Ok(())
+    }
+
+    /// Whether we've asked `peer` for this block, or its proof, to settle a [FilterDispute]
+    fn is_filter_dispute_data(&self, peer: PeerId, block_hash: BlockHash) -> bool {
+        let request = InflightRequests::FilterDispute(block_hash);
+        self.inflight
+            .get(&request)
+            .is_some_and(|(dispute_peer, _)| *dispute_peer == peer)
+    }
+
+    /// Builds the filter for the disputed block, once we have both the block and its proof, and
+    /// bans whoever lied about it
+    async fn handle_filter_dispute_data(&mut self, peer: PeerId) -> Result<(), WireError> {
+        let ready = self
+            .context
+            .filter_dispute
+            .as_ref()
+            .is_some_and(|dispute| dispute.block.is_some() && dispute.proof.is_some());
+
+        if !ready {
+            return Ok(());
+        }
+
+        let dispute = self
+            .context
+            .filter_dispute
+            .take()
+            .expect("we've just checked it");
+        self.inflight
+            .remove(&InflightRequests::FilterDispute(dispute.block_hash));
+
+        let height = dispute.height;
+        let block = dispute.block.as_ref().expect("we've just checked it");
+
+        // Check if the block was maliciously mutated by our peer
+        if !(block.check_merkle_root() && block.check_witness_commitment()) {
+            error!("Peer {peer} sent us a mutated block {}", dispute.block_hash);
+            return self.increase_banscore(peer, self.max_banscore).await;
+        }
+
+        // We can only trust the scripts in the proof if it's valid for our accumulator
+        let Some(acc) = self.chain.get_roots_for_block(height.saturating_sub(1))? else {
+            warn!("Can't check the proof for block {height}, we don't have our accumulator");
+            return Ok(());
+        };
+
+        let filter = dispute.build_filter(
+            &acc,
+            |h| self.chain.get_block_hash(h),
+            |h| self.chain.get_median_time_past(h),
+        );
+
+        let Some(filter) = filter else {
+            warn!(
+                "Peer {peer} sent us an invalid proof for block {}, banning it",
+                dispute.block_hash
+            );
+            return self.increase_banscore(peer, self.max_banscore).await;
+        };
+
+        match dispute.liar(FilterHash::hash(&filter.content)) {
+            Some(liar) => {
+                warn!("Peer {liar} lied about the filter for block {height}, banning it");
+                self.increase_banscore(liar, self.max_banscore).await?;
+            }
+            None => warn!("Couldn't find who lied about the filter for block {height}"),
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the [FilterDispute] whose peer didn't send the block and proof in time, or
+    /// disconnected. We'll ask for the filter headers again.
+    fn drop_stale_filter_dispute(&mut self) {
+        let Some(dispute) = self.context.filter_dispute.as_ref() else {
+            return;
+        };
+
+        let request = InflightRequests::FilterDispute(dispute.block_hash);
+        if !self.inflight.contains_key(&request) {
+            warn!(
+                "Couldn't find who lied about the filter for block {}",
+                dispute.height
+            );
+            self.context.filter_dispute = None;
+        }
+    }
+
     async fn ask_missed_block(&mut self) -> Result<(), WireError> {
         let tip = self.chain.get_height().unwrap();
         let next = self.chain.get_validation_index().unwrap();
@@ -586,6 +1197,456 @@ where
         Ok(())
     }
 
+    /// Asks a peer for a block it has announced. If this block is close to our tip, and this
+    /// peer supports it, we ask for a compact block, as our mempool may have most of its
+    /// transactions.
+    async fn request_announced_block(
+        &mut self,
+        peer: PeerId,
+        block_hash: BlockHash,
+    ) -> Result<(), WireError> {
+        let compact_blocks = self
+            .peers
+            .get(&peer)
+            .is_some_and(|peer| peer.compact_blocks);
+        if !compact_blocks || !self.is_close_to_tip(&block_hash)? {
+            return self.request_full_block(peer, block_hash).await;
+        }
+
+        self.send_to_peer(peer, NodeRequest::GetCompactBlock(block_hash))
+            .await?;
+
+        self.inflight
+            .insert(InflightRequests::Blocks(block_hash), (peer, Instant::now()));
+
+        Ok(())
+    }
+
+    async fn request_full_block(
+        &mut self,
+        peer: PeerId,
+        block_hash: BlockHash,
+    ) -> Result<(), WireError> {
+        self.send_to_peer(peer, NodeRequest::GetBlock(vec![block_hash]))
+            .await?;
+
+        self.inflight
+            .insert(InflightRequests::Blocks(block_hash), (peer, Instant::now()));
+
+        Ok(())
+    }
+
+    /// Whether this block is at most [MAX_COMPACT_BLOCK_DEPTH] blocks after our validation index
+    fn is_close_to_tip(&self, block_hash: &BlockHash) -> Result<bool, WireError> {
+        let Some(height) = self.chain.get_block_height(block_hash)? else {
+            return Ok(false);
+        };
+
+        let validation_index = self.chain.get_validation_index()?;
+        Ok(height <= validation_index + MAX_COMPACT_BLOCK_DEPTH)
+    }
+
+    /// Starts rebuilding a compact block with our mempool transactions, and asks our peer for
+    /// the ones we don't have
+    async fn handle_compact_block(
+        &mut self,
+        peer: PeerId,
+        compact_block: HeaderAndShortIds,
+    ) -> Result<(), WireError> {
+        let block_hash = compact_block.header.block_hash();
+        if self.blocks.contains_key(&block_hash)
+            || self.context.partial_blocks.contains_key(&block_hash)
+        {
+            return Ok(());
+        }
+
+        // High-bandwidth peers send us new blocks before we have their headers
+        if self.chain.get_block_header(&block_hash).is_err() {
+            let prev_block = compact_block.header.prev_blockhash;
+            if self.chain.get_block_header(&prev_block).is_err() {
+                return self.handle_new_block(block_hash, peer).await;
+            }
+
+            if !self.accept_peer_header(peer, compact_block.header)? {
+                info!("Peer {peer} gave us too many fork headers, disconnecting");
+                return self.send_to_peer(peer, NodeRequest::Shutdown).await;
+            }
+        }
+
+        let validation_index = self.chain.get_validation_index()?;
+        if let Some(height) = self.chain.get_block_height(&block_hash)? {
+            if height <= validation_index {
+                return Ok(());
+            }
+        }
+
+        let partial = {
+            let mempool = self.mempool.lock().await;
+            PartialBlock::new(&compact_block, peer, mempool.transactions())
+        };
+
+        let partial = match partial {
+            Ok(partial) => partial,
+            // Short IDs may collide, that's not our peer's fault
+            Err(CompactBlockError::DuplicateShortId) => {
+                return self.request_full_block(peer, block_hash).await;
+            }
+            Err(e) => {
+                warn!("Peer {peer} sent us an invalid compact block {block_hash}: {e}");
+                return self.increase_banscore(peer, 10).await;
+            }
+        };
+
+        let missing = partial.missing();
+        if missing.is_empty() {
+            return self.finish_compact_block(partial).await;
+        }
+
+        debug!(
+            "Asking peer {peer} for {} transactions of compact block {block_hash}",
+            missing.len()
+        );
+
+        let request = BlockTransactionsRequest {
+            block_hash,
+            indexes: missing,
+        };
+
+        self.send_to_peer(peer, NodeRequest::GetBlockTxn(request))
+            .await?;
+
+        self.inflight
+            .insert(InflightRequests::Blocks(block_hash), (peer, Instant::now()));
+
+        self.context.partial_blocks.insert(block_hash, partial);
+        Ok(())
+    }
+
+    /// Adds the transactions we've asked for to a compact block we are rebuilding
+    async fn handle_block_txn(
+        &mut self,
+        peer: PeerId,
+        block_txn: BlockTransactions,
+    ) -> Result<(), WireError> {
+        let block_hash = block_txn.block_hash;
+        let Some(partial) = self.context.partial_blocks.get_mut(&block_hash) else {
+            debug!("Peer {peer} sent us transactions for {block_hash}, but we didn't ask");
+            return Ok(());
+        };
+
+        if partial.peer != peer {
+            debug!("Peer {peer} sent us transactions for {block_hash}, but we didn't ask");
+            return Ok(());
+        }
+
+        if let Err(e) = partial.fill(block_txn.transactions) {
+            warn!("Peer {peer} sent us invalid transactions for {block_hash}: {e}");
+            self.context.partial_blocks.remove(&block_hash);
+            self.inflight.remove(&InflightRequests::Blocks(block_hash));
+
+            self.increase_banscore(peer, 10).await?;
+            return self.request_blocks(vec![block_hash]).await;
+        }
+
+        let partial = self
+            .context
+            .partial_blocks
+            .remove(&block_hash)
+            .expect("we've just filled this block");
+
+        self.finish_compact_block(partial).await
+    }
+
+    /// Forgets the compact blocks whose peer didn't send the missing transactions in time, or
+    /// disconnected. Their requests were already redone, so we'll get the full blocks instead.
+    fn drop_stale_partial_blocks(&mut self) {
+        let inflight = &self.common.inflight;
+        self.context.partial_blocks.retain(|block_hash, partial| {
+            match inflight.get(&InflightRequests::Blocks(*block_hash)) {
+                Some((peer, _)) => *peer == partial.peer,
+                None => false,
+            }
+        });
+    }
+
+    /// Handles a compact block that has all its transactions, like we do for full blocks. If it
+    /// doesn't match its header, we download the full block instead.
+    async fn finish_compact_block(&mut self, partial: PartialBlock) -> Result<(), WireError> {
+        let block_hash = partial.block_hash();
+        let peer = partial.peer;
+
+        #[cfg(feature = "metrics")]
+        {
+            use metrics::get_metrics;
+
+            get_metrics()
+                .compact_block_hit_rate
+                .observe(partial.hit_rate());
+        }
+
+        match partial.into_block() {
+            Ok(block) => {
+                self.select_high_bandwidth_peer(peer).await?;
+                self.request_block_proof(block, peer).await
+            }
+            Err(e) => {
+                debug!("Couldn't rebuild compact block {block_hash}: {e}, downloading it instead");
+
+                #[cfg(feature = "metrics")]
+                {
+                    use metrics::get_metrics;
+
+                    get_metrics().compact_block_failures.inc();
+                }
+
+                self.request_full_block(peer, block_hash).await
+            }
+        }
+    }
+
+    /// Asks the peer that just gave us a block to send us new blocks right away. Like BIP152
+    /// suggests, we keep the [MAX_HIGH_BANDWIDTH_PEERS] that did it most recently.
+    async fn select_high_bandwidth_peer(&mut self, peer: PeerId) -> Result<(), WireError> {
+        let peers = &self.common.peers;
+        let high_bandwidth_peers = &mut self.context.high_bandwidth_peers;
+        high_bandwidth_peers.retain(|peer| peers.contains_key(peer));
+
+        if high_bandwidth_peers.contains(&peer) {
+            return Ok(());
+        }
+
+        high_bandwidth_peers.push(peer);
+        let oldest = (high_bandwidth_peers.len() > MAX_HIGH_BANDWIDTH_PEERS)
+            .then(|| high_bandwidth_peers.remove(0));
+
+        self.send_to_peer(peer, NodeRequest::SendCmpct(true))
+            .await?;
+
+        if let Some(oldest) = oldest {
+            self.send_to_peer(oldest, NodeRequest::SendCmpct(false))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Asks for a transaction announced by one of our peers, if we don't have it yet.
+    ///
+    /// Only utreexo peers can send us the proofs we need to validate a transaction, so we ignore
+    /// announcements from other peers.
+    async fn handle_transaction_inv(
+        &mut self,
+        peer: PeerId,
+        inv: Inventory,
+    ) -> Result<(), WireError> {
+        let Some(hash) = inv.network_hash() else {
+            return Ok(());
+        };
+
+        let is_utreexo = self
+            .peers
+            .get(&peer)
+            .is_some_and(|info| info.services.has(UTREEXO.into()));
+
+        if !is_utreexo
+            || self.context.tx_requests.contains_key(&hash)
+            || self.context.tx_requests.len() >= MAX_TX_REQUESTS
+        {
+            return Ok(());
+        }
+
+        let in_mempool = {
+            let mempool = self.mempool.lock().await;
+            match inv {
+                Inventory::WTx(wtxid) => mempool.get_by_wtxid(&wtxid).is_some(),
+                _ => mempool
+                    .get_from_mempool(&Txid::from_byte_array(hash))
+                    .is_some(),
+            }
+        };
+
+        if in_mempool {
+            return Ok(());
+        }
+
+        self.send_to_peer(peer, NodeRequest::GetUtreexoTransaction(inv))
+            .await?;
+        self.context.tx_requests.insert(hash, Instant::now());
+
+        Ok(())
+    }
+
+    /// Validates a transaction relayed by one of our peers, adds it to our mempool and
+    /// announces it to our other peers.
+    ///
+    /// Inputs spending mempool transactions, or prevouts we already know about, don't need a
+    /// proof. For every other input, this transaction must carry its leaf data, in order, and a
+    /// proof for our current accumulator. Invalid transactions are just dropped: the proof may be
+    /// for another tip, and our peer may have a different policy.
+    async fn accept_relayed_transaction(
+        &mut self,
+        peer: PeerId,
+        utreexo_tx: UtreexoTransaction,
+    ) -> Result<(), WireError> {
+        let UtreexoTransaction {
+            transaction,
+            proof_hashes,
+            targets,
+            leaf_data,
+        } = utreexo_tx;
+
+        let txid = transaction.compute_txid();
+        let wtxid = transaction.compute_wtxid();
+        self.context.tx_requests.remove(&txid.to_byte_array());
+        self.context.tx_requests.remove(&wtxid.to_byte_array());
+
+        let height = self.chain.get_validation_index()?;
+        let median_time_past = self.chain.get_median_time_past(height)?;
+
+        let mut mempool = self.mempool.lock().await;
+        if mempool.get_from_mempool(&txid).is_some() {
+            return Ok(());
+        }
+
+        let mut utxos = HashMap::new();
+        let mut prevouts = Vec::new();
+        let mut del_hashes = Vec::new();
+        let mut leaves = leaf_data.into_iter();
+
+        for input in transaction.input.iter() {
+            let outpoint = input.previous_output;
+            if let Some(parent) = mempool.get_from_mempool(&outpoint.txid) {
+                let Some(txout) = parent.output.get(outpoint.vout as usize) else {
+                    debug!("Transaction {txid} from peer {peer} spends a missing output");
+                    return Ok(());
+                };
+
+                let utxo = UtxoData {
+                    txout: txout.clone(),
+                    is_coinbase: false,
+                    creation_height: height + 1,
+                    creation_time: median_time_past,
+                };
+                utxos.insert(outpoint, utxo);
+                continue;
+            }
+
+            let (leaf, needs_proof) = match mempool.get_prevout_data(&outpoint) {
+                Some(leaf) => (leaf.clone(), false),
+                None => match leaves.next() {
+                    Some(leaf) => (leaf, true),
+                    None => {
+                        debug!("Transaction {txid} from peer {peer} has no proof for {outpoint}");
+                        return Ok(());
+                    }
+                },
+            };
+
+            let creation_height = leaf.header_code >> 1;
+            let (Ok(block_hash), Ok(creation_time)) = (
+                self.chain.get_block_hash(creation_height),
+                self.chain
+                    .get_median_time_past(creation_height.saturating_sub(1)),
+            ) else {
+                debug!("Transaction {txid} from peer {peer} spends an unknown block");
+                return Ok(());
+            };
+
+            let Ok(leaf_data) = proof_util::reconstruct_leaf_data(&leaf, input, block_hash) else {
+                debug!("Transaction {txid} from peer {peer} has invalid leaf data");
+                return Ok(());
+            };
+
+            if needs_proof {
+                let hash = leaf_data._get_leaf_hashes().to_byte_array();
+                del_hashes.push(BitcoinNodeHash::Some(hash));
+            }
+
+            let utxo = UtxoData {
+                txout: leaf_data.utxo,
+                is_coinbase: leaf.header_code & 1 == 1,
+                creation_height,
+                creation_time,
+            };
+            utxos.insert(outpoint, utxo);
+            prevouts.push((outpoint, leaf));
+        }
+
+        if leaves.next().is_some() {
+            debug!("Transaction {txid} from peer {peer} has extra leaf data");
+            return Ok(());
+        }
+
+        // We can only accept transactions that could be mined in the next block, so both their
+        // absolute and relative lock times must be satisfied
+        if let Err(e) = Consensus::validate_locktime(
+            &transaction,
+            &utxos,
+            height + 1,
+            median_time_past,
+            MEMPOOL_SCRIPT_FLAGS,
+        ) {
+            debug!("Transaction {txid} from peer {peer} isn't final: {e:?}");
+            return Ok(());
+        }
+
+        if !del_hashes.is_empty() {
+            let proof = Proof::new(targets, proof_hashes);
+            if !self
+                .chain
+                .acc()
+                .verify(&proof, &del_hashes)
+                .unwrap_or(false)
+            {
+                debug!("Transaction {txid} from peer {peer} has an invalid proof");
+                return Ok(());
+            }
+        }
+
+        if let Err(e) = Consensus::verify_transaction(
+            &transaction,
+            &mut utxos,
+            height + 1,
+            true,
+            MEMPOOL_SCRIPT_FLAGS,
+        ) {
+            debug!("Transaction {txid} from peer {peer} is invalid: {e:?}");
+            return Ok(());
+        }
+
+        let vsize = transaction.vsize() as u64;
+        let fee = match mempool.accept_relayed_transaction(transaction, &prevouts) {
+            Ok(fee) => fee,
+            Err(e) => {
+                debug!("Transaction {txid} from peer {peer} wasn't accepted: {e:?}");
+                return Ok(());
+            }
+        };
+
+        drop(mempool);
+        debug!("Accepted transaction {txid} from peer {peer} to our mempool");
+
+        let announcement = TxAnnouncement {
+            txid,
+            wtxid,
+            feerate: fee * 1000 / vsize,
+        };
+
+        let peers = self
+            .peers
+            .iter()
+            .filter(|(id, info)| **id != peer && info.state == PeerStatus::Ready)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for peer in peers {
+            self.send_to_peer(peer, NodeRequest::AnnounceTransaction(announcement))
+                .await?;
+        }
+
+        Ok(())
+    }
+
     async fn handle_notification(
         &mut self,
         notification: NodeNotification,
@@ -599,12 +1660,31 @@ where
                 self.address_man.push_addresses(&addresses);
             }
 
+            NodeNotification::InboundConnection(stream, address) => {
+                self.handle_inbound_connection(stream, address).await?;
+            }
+
             NodeNotification::FromPeer(peer, message) => {
                 #[cfg(feature = "metrics")]
                 self.register_message_time(&message, peer);
 
                 match message {
                     PeerMessages::UtreexoProof(uproof) => {
+                        let block_hash = uproof.block_hash;
+                        if self.is_filter_dispute_data(peer, block_hash) {
+                            if let Some(dispute) = self.context.filter_dispute.as_mut() {
+                                dispute.proof = Some(uproof.clone());
+                            }
+
+                            self.handle_filter_dispute_data(peer).await?;
+
+                            // Unless we also need this proof to validate the block
+                            let request = InflightRequests::UtreexoProof(block_hash);
+                            if !self.inflight.contains_key(&request) {
+                                return Ok(());
+                            }
+                        }
+
                         self.attach_proof(uproof, peer).await?;
                         self.process_pending_blocks().await?;
                     }
@@ -649,9 +1729,43 @@ where
                     }
 
                     PeerMessages::Block(block) => {
+                        let block_hash = block.block_hash();
+                        if self.is_filter_dispute_data(peer, block_hash) {
+                            if let Some(dispute) = self.context.filter_dispute.as_mut() {
+                                dispute.block = Some(block.clone());
+                            }
+
+                            self.handle_filter_dispute_data(peer).await?;
+
+                            // Unless we, or our user, also asked for this block
+                            let wanted = self
+                                .inflight
+                                .contains_key(&InflightRequests::Blocks(block_hash))
+                                || self
+                                    .inflight_user_requests
+                                    .contains_key(&UserRequest::Block(block_hash));
+
+                            if !wanted {
+                                return Ok(());
+                            }
+                        }
+
+                        self.context.partial_blocks.remove(&block_hash);
                         self.request_block_proof(block, peer).await?;
                     }
 
+                    PeerMessages::SendCmpct => {
+                        self.handle_send_cmpct(peer);
+                    }
+
+                    PeerMessages::CompactBlock(compact_block) => {
+                        self.handle_compact_block(peer, compact_block).await?;
+                    }
+
+                    PeerMessages::BlockTxn(block_txn) => {
+                        self.handle_block_txn(peer, block_txn).await?;
+                    }
+
                     PeerMessages::Headers(headers) => {
                         debug!(
                             "Got headers from peer {peer} with {} headers",
@@ -689,24 +1803,39 @@ where
                             });
                         }
 
+                        let (headers, locator) = match self.presync_headers(peer, headers)? {
+                            HeadersPresync::Accept(headers) => (headers, None),
+                            HeadersPresync::Continue(headers, locator) => (headers, Some(locator)),
+                            HeadersPresync::LowWork => return Ok(()),
+                            HeadersPresync::Invalid => {
+                                self.increase_banscore(peer, 5).await?;
+                                return Ok(());
+                            }
+                        };
+
                         for header in headers.iter() {
-                            self.chain.accept_header(*header)?;
-
-                            self.send_to_peer(
-                                peer,
-                                NodeRequest::GetBlock(vec![header.block_hash()]),
-                            )
-                            .await?;
-
-                            self.inflight.insert(
-                                InflightRequests::Blocks(header.block_hash()),
-                                (peer, Instant::now()),
-                            );
+                            if !self.accept_peer_header(peer, *header)? {
+                                info!("Peer {peer} gave us too many fork headers, disconnecting");
+                                self.send_to_peer(peer, NodeRequest::Shutdown).await?;
+                                return Ok(());
+                            }
+
+                            self.request_announced_block(peer, header.block_hash())
+                                .await?;
+                        }
+
+                        if let Some(locator) = locator {
+                            self.send_to_peer(peer, NodeRequest::GetHeaders(locator))
+                                .await?;
+                            self.inflight
+                                .insert(InflightRequests::Headers, (peer, Instant::now()));
                         }
 
                         // update the peer info
                         self.peers.entry(peer).and_modify(|info| {
-                            info.kind = ConnectionKind::Regular(peer_info.services);
+                            if info.kind != ConnectionKind::Inbound {
+                                info.kind = ConnectionKind::Regular(peer_info.services);
+                            }
                         });
                     }
 
@@ -720,6 +1849,8 @@ where
 
                     PeerMessages::Disconnected(idx) => {
                         self.handle_disconnection(peer, idx).await?;
+                        self.drop_stale_partial_blocks();
+                        self.drop_stale_filter_dispute();
                     }
 
                     PeerMessages::Addr(addresses) => {
@@ -740,6 +1871,26 @@ where
                                 return Ok(());
                             };
 
+                            // Every filter must match the filter headers we've checked
+                            let previous = match this_height.checked_sub(1) {
+                                Some(height) => filters.get_filter_header(height)?,
+                                None => Some(FilterHeader::all_zeros()),
+                            };
+
+                            let Some((previous, expected)) =
+                                previous.zip(filters.get_filter_header(this_height)?)
+                            else {
+                                debug!("Filter for block {hash} received, but we didn't ask");
+                                return Ok(());
+                            };
+
+                            if filter.filter_header(&previous) != expected {
+                                warn!("Peer {peer} sent us an invalid filter for block {hash}");
+                                self.context.inflight_filters.clear();
+                                self.inflight.remove(&InflightRequests::GetFilters);
+                                return self.increase_banscore(peer, self.max_banscore).await;
+                            }
+
                             if current_height + 1 != this_height {
                                 self.context.inflight_filters.insert(this_height, filter);
                                 return Ok(());
@@ -766,6 +1917,26 @@ where
                         }
                     }
 
+                    PeerMessages::FilterHeaders(headers) => {
+                        self.handle_filter_headers(peer, headers).await?;
+                    }
+
+                    PeerMessages::FilterCheckpoint(checkpoint) => {
+                        self.handle_filter_checkpoint(peer, checkpoint)?;
+                    }
+
+                    PeerMessages::GetFilters(request) => {
+                        self.handle_get_cfilters(peer, request).await?;
+                    }
+
+                    PeerMessages::GetFilterHeaders(request) => {
+                        self.handle_get_cfheaders(peer, request).await?;
+                    }
+
+                    PeerMessages::GetFilterCheckpoint(request) => {
+                        self.handle_get_cfcheckpt(peer, request).await?;
+                    }
+
                     PeerMessages::NotFound(inv) => match inv {
                         Inventory::Error => {}
                         Inventory::Block(block)
@@ -801,15 +1972,45 @@ where
                         {
                             request
                                 .2
-                                .send(NodeResponse::MempoolTransaction(Some(tx)))
+                                .send(NodeResponse::MempoolTransaction(Some(tx.clone())))
                                 .unwrap();
                         }
+
+                        // Without a proof, we can only accept transactions spending outputs
+                        // we already know about
+                        let utreexo_tx = UtreexoTransaction {
+                            transaction: tx,
+                            proof_hashes: Vec::new(),
+                            targets: Vec::new(),
+                            leaf_data: Vec::new(),
+                        };
+                        self.accept_relayed_transaction(peer, utreexo_tx).await?;
+                    }
+
+                    PeerMessages::TransactionInv(inv) => {
+                        self.handle_transaction_inv(peer, inv).await?;
+                    }
+
+                    PeerMessages::UtreexoTransaction(utreexo_tx) => {
+                        self.accept_relayed_transaction(peer, utreexo_tx).await?;
                     }
 
                     PeerMessages::UtreexoState(_) => {
                         warn!("Utreexo state received from peer {peer}, but we didn't ask",);
                         self.increase_banscore(peer, 5).await?;
                     }
+
+                    PeerMessages::GetUtreexoProof(request) => {
+                        self.handle_get_utreexo_proof(peer, request).await?;
+                    }
+
+                    PeerMessages::GetData(inv) => {
+                        self.handle_get_data(peer, inv).await?;
+                    }
+
+                    PeerMessages::GetBlockTxn(request) => {
+                        self.handle_get_block_txn(peer, request).await?;
+                    }
                 }
             }
         }
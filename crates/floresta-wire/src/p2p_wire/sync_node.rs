@@ -38,6 +38,11 @@ use crate::node_interface::NodeResponse;
 #[derive(Clone, Debug, Default)]
 pub struct SyncNode {}
 
+impl SyncNode {
+    /// How often we report our progress, in seconds
+    pub const PROGRESS_INTERVAL: u64 = 60;
+}
+
 impl NodeContext for SyncNode {
     fn get_required_services(&self) -> bitcoin::p2p::ServiceFlags {
         ServiceFlags::WITNESS | service_flags::UTREEXO.into() | ServiceFlags::NETWORK
@@ -179,9 +184,21 @@ where
     ///     - Checks if our tip is obsolete and requests a new one, creating a new connection.
     ///     - Handles timeouts for inflight requests.
     ///     - If were low on inflights, requests new blocks to validate.
-    pub async fn run(mut self, done_cb: impl FnOnce(&Chain)) -> Self {
+    pub async fn run(self, done_cb: impl FnOnce(&Chain)) -> Self {
+        self.run_with_progress(|_| {}, done_cb).await
+    }
+
+    /// Same as [`UtreexoNode::run`], but also calls `on_progress` every
+    /// [`SyncNode::PROGRESS_INTERVAL`] seconds while we are syncing, so the caller can save or
+    /// report how far we are.
+    pub async fn run_with_progress(
+        mut self,
+        mut on_progress: impl FnMut(&Chain),
+        done_cb: impl FnOnce(&Chain),
+    ) -> Self {
         info!("Starting sync node...");
         self.last_block_request = self.chain.get_validation_index().unwrap();
+        let mut last_progress = Instant::now();
 
         loop {
             while let Ok(Some(msg)) = timeout(Duration::from_secs(1), self.node_rx.recv()).await {
@@ -226,6 +243,11 @@ where
 
             try_and_log!(self.check_for_timeout().await);
 
+            if last_progress.elapsed().as_secs() > SyncNode::PROGRESS_INTERVAL {
+                on_progress(&self.chain);
+                last_progress = Instant::now();
+            }
+
             let assume_stale = Instant::now()
                 .duration_since(self.common.last_tip_update)
                 .as_secs()
@@ -238,7 +260,7 @@ where
             }
 
             try_and_log!(self.process_pending_blocks().await);
-            if !self.has_utreexo_peers() {
+            if !self.can_get_proofs() {
                 continue;
             }
 
@@ -264,6 +286,10 @@ where
                 self.address_man.push_addresses(&addresses);
             }
 
+            NodeNotification::InboundConnection(stream, address) => {
+                self.handle_inbound_connection(stream, address).await?;
+            }
+
             NodeNotification::FromPeer(peer, notification) => {
                 #[cfg(feature = "metrics")]
                 self.register_message_time(&notification, peer);
@@ -356,6 +382,22 @@ where
                         self.get_blocks_to_download().await;
                     }
 
+                    PeerMessages::GetUtreexoProof(request) => {
+                        self.handle_get_utreexo_proof(peer, request).await?;
+                    }
+
+                    PeerMessages::GetData(inv) => {
+                        self.handle_get_data(peer, inv).await?;
+                    }
+
+                    PeerMessages::SendCmpct => {
+                        self.handle_send_cmpct(peer);
+                    }
+
+                    PeerMessages::GetBlockTxn(request) => {
+                        self.handle_get_block_txn(peer, request).await?;
+                    }
+
                     _ => {}
                 }
             }
@@ -1,2 +1,2 @@
 mod sync_node;
-mod utils;
+pub(crate) mod utils;
@@ -4,8 +4,10 @@ mod tests_utils {
     use std::time::Duration;
 
     use bitcoin::Network;
+    use bitcoin::Work;
     use floresta_chain::pruned_utreexo::UpdatableChainstate;
     use floresta_chain::AssumeValidArg;
+    use floresta_chain::ChainParams;
     use floresta_chain::ChainState;
     use floresta_chain::FlatChainStore;
     use floresta_chain::FlatChainStoreConfig;
@@ -24,6 +26,7 @@ mod tests_utils {
     use crate::p2p_wire::tests::utils::BlockDataMap;
     use crate::p2p_wire::tests::utils::BlockHashMap;
     use crate::p2p_wire::tests::utils::HeaderList;
+    use crate::UtreexoNodeConfig;
 
     type PeerData = (HeaderList, BlockHashMap, BlockDataMap);
 
@@ -31,13 +34,17 @@ mod tests_utils {
         peers: Vec<PeerData>,
         pow_fraud_proofs: bool,
         network: Network,
+        configure: impl FnOnce(&mut UtreexoNodeConfig),
     ) -> Arc<ChainState<FlatChainStore>> {
         let datadir = format!("./tmp-db/{}.sync_node", rand::random::<u32>());
         let config = FlatChainStoreConfig::new(datadir.clone());
 
         let chainstore = FlatChainStore::new(config).unwrap();
         let mempool = Arc::new(Mutex::new(Mempool::new(Pollard::default(), 1000)));
-        let chain = ChainState::new(chainstore, network, AssumeValidArg::Disabled);
+        // Our test headers don't have the minimum chain work
+        let mut params = ChainParams::from(network);
+        params.minimum_chain_work = Work::from_be_bytes([0; 32]);
+        let chain = ChainState::new(chainstore, params, AssumeValidArg::Disabled);
         let chain = Arc::new(chain);
 
         // Adding 9 signet headers in the chain-state prior validation
@@ -48,7 +55,8 @@ mod tests_utils {
             chain.accept_header(header).unwrap();
         }
 
-        let config = get_node_config(datadir, network, pow_fraud_proofs);
+        let mut config = get_node_config(datadir, network, pow_fraud_proofs);
+        configure(&mut config);
 
         let kill_signal = Arc::new(RwLock::new(false));
         let mut node = UtreexoNode::<Arc<ChainState<FlatChainStore>>, SyncNode>::new(
@@ -92,6 +100,7 @@ mod tests {
     use bitcoin::Network;
     use floresta_chain::pruned_utreexo::BlockchainInterface;
 
+    use crate::block_cache::BlockCache;
     use crate::p2p_wire::tests::sync_node::tests_utils::setup_node;
     use crate::p2p_wire::tests::utils::get_essentials;
 
@@ -102,6 +111,27 @@ mod tests {
             vec![(Vec::new(), essentials.blocks.clone(), HashMap::new())],
             false,
             Network::Signet,
+            |_| {},
+        )
+        .await;
+
+        assert_eq!(chain.get_validation_index().unwrap(), 9);
+        assert_eq!(
+            chain.get_best_block().unwrap().1,
+            essentials.headers[9].block_hash()
+        );
+        assert!(!chain.is_in_ibd());
+    }
+
+    #[tokio::test]
+    async fn test_sync_with_bridge() {
+        // A bridge proves the blocks by itself, so it never asks our peer for proofs
+        let essentials = get_essentials();
+        let chain = setup_node(
+            vec![(Vec::new(), essentials.blocks.clone(), HashMap::new())],
+            false,
+            Network::Signet,
+            |config| config.bridge = true,
         )
         .await;
 
@@ -128,7 +158,7 @@ mod tests {
             .insert(essentials.headers[7].block_hash(), essentials.invalid_block);
 
         let peer = vec![(Vec::new(), essentials.blocks.clone(), HashMap::new())];
-        let chain = setup_node(peer, false, Network::Signet).await;
+        let chain = setup_node(peer, false, Network::Signet, |_| {}).await;
 
         assert_eq!(chain.get_validation_index().unwrap(), 6);
         assert_eq!(
@@ -137,4 +167,36 @@ mod tests {
         );
         assert!(!chain.is_in_ibd());
     }
+
+    #[tokio::test]
+    async fn test_sync_with_block_cache() {
+        let essentials = get_essentials();
+        let datadir = format!("./tmp-db/{}.block_cache", rand::random::<u32>());
+
+        let chain = setup_node(
+            vec![(Vec::new(), essentials.blocks.clone(), HashMap::new())],
+            false,
+            Network::Signet,
+            |config| {
+                config.datadir = datadir.clone();
+                config.block_cache_depth = 4;
+            },
+        )
+        .await;
+
+        assert_eq!(chain.get_validation_index().unwrap(), 9);
+
+        // We only keep the last 4 blocks, with their proofs
+        let cache = BlockCache::new(&datadir, 4, None).unwrap();
+        assert_eq!(cache.len(), 4);
+
+        for (height, header) in essentials.headers.iter().enumerate().take(10) {
+            let block = cache.get_block(&header.block_hash()).unwrap();
+            assert_eq!(block.is_some(), height >= 6);
+        }
+
+        let tip = essentials.headers[9].block_hash();
+        let proof = cache.get_proof(&tip).unwrap().unwrap();
+        assert_eq!(proof.block_hash, tip);
+    }
 }
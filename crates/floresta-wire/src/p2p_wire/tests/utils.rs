@@ -186,6 +186,9 @@ pub fn create_peer(
         address_id: 0,
         _last_message: Instant::now(),
         transport_protocol: TransportProtocol::V2,
+        fork_headers: 0,
+        connected_at: Instant::now(),
+        compact_blocks: false,
     }
 }
 
@@ -207,9 +210,17 @@ pub fn get_node_config(
         proxy: None,
         assume_utreexo: None,
         backfill: false,
+        backfill_ranges: 1,
         filter_start_height: None,
         user_agent: "node_test".to_string(),
         allow_v1_fallback: true,
+        chain_params: None,
+        bridge: false,
+        block_cache_depth: 0,
+        block_cache_size: None,
+        listen: None,
+        max_inbound: 0,
+        whitelist: Vec::new(),
     }
 }
 
@@ -91,7 +91,7 @@ pub enum ReadTransport<R: AsyncRead + Unpin + Send> {
 
 pub enum WriteTransport<W: AsyncWrite + Unpin + Send + Sync> {
     V2(W, AsyncProtocolWriter),
-    V1(W, Network),
+    V1(W, Magic),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -138,6 +138,8 @@ impl Decodable for V1MessageHeader {
 ///
 /// * `address` - The address of a target node
 /// * `network` - The bitcoin network
+/// * `magic` - The magic bytes of our chain. If it isn't the default for `network` (e.g. on a
+///   custom signet), we can only use V1, as BIP-324 derives its magic from `network`
 /// * `allow_v1_fallback` - Whether to allow fallback to V1 protocol if V2 negotiation fails
 ///
 /// # Returns
@@ -150,14 +152,16 @@ impl Decodable for V1MessageHeader {
 pub async fn connect<A: ToSocketAddrs>(
     address: A,
     network: Network,
+    magic: Magic,
     allow_v1_fallback: bool,
 ) -> TransportResult {
-    match try_connection(&address, network, false).await {
+    let force_v1 = magic != network.magic();
+    match try_connection(&address, network, magic, force_v1).await {
         Ok(transport) => Ok(transport),
         Err(TransportError::Protocol(ProtocolError::Io(_, ProtocolFailureSuggestion::RetryV1)))
             if allow_v1_fallback =>
         {
-            try_connection(&address, network, true).await
+            try_connection(&address, network, magic, true).await
         }
         Err(e) => Err(e),
     }
@@ -166,6 +170,7 @@ pub async fn connect<A: ToSocketAddrs>(
 async fn try_connection<A: ToSocketAddrs>(
     address: &A,
     network: Network,
+    magic: Magic,
     force_v1: bool,
 ) -> TransportResult {
     let tcp_stream = TcpStream::connect(address).await?;
@@ -186,7 +191,7 @@ async fn try_connection<A: ToSocketAddrs>(
             debug!("Using V1 protocol for connection to {peer_addr}");
             Ok((
                 ReadTransport::V1(reader),
-                WriteTransport::V1(writer, network),
+                WriteTransport::V1(writer, magic),
                 TransportProtocol::V1,
             ))
         }
@@ -217,6 +222,101 @@ async fn try_connection<A: ToSocketAddrs>(
     }
 }
 
+/// Negotiates the bitcoin protocol with a peer that connected to us.
+///
+/// V1 peers start by sending a `version` message, while V2 peers start with their BIP-324
+/// public key. Like Bitcoin Core, we peek at the first bytes of the stream, and if they are
+/// the header of a V1 `version` message, we use V1. Otherwise, we answer the V2 handshake.
+///
+/// # Arguments
+///
+/// * `tcp_stream` - The stream we got from our listener
+/// * `network` - The bitcoin network
+/// * `magic` - The magic bytes of our chain. If it isn't the default for `network`, we only
+///   accept V1 connections, see [`connect`]
+///
+/// # Errors
+///
+/// Returns a `TransportError` if the peer disconnects before we can tell which protocol it
+/// speaks, or the V2 handshake fails.
+pub async fn accept(tcp_stream: TcpStream, network: Network, magic: Magic) -> TransportResult {
+    tcp_stream.set_nodelay(false)?;
+
+    let peer_addr = match tcp_stream.peer_addr() {
+        Ok(addr) => addr.to_string(),
+        Err(_) => String::from("unknown peer"),
+    };
+
+    let force_v1 = magic != network.magic();
+    let is_v1 = force_v1 || is_v1_handshake(&tcp_stream, magic).await?;
+
+    let (reader, mut writer) = tokio::io::split(tcp_stream);
+    let mut reader = BufReader::new(reader);
+
+    if is_v1 {
+        debug!("Using V1 protocol for inbound connection from {peer_addr}");
+        return Ok((
+            ReadTransport::V1(reader),
+            WriteTransport::V1(writer, magic),
+            TransportProtocol::V1,
+        ));
+    }
+
+    match AsyncProtocol::new(
+        network,
+        Role::Responder,
+        None,
+        None,
+        &mut reader,
+        &mut writer,
+    )
+    .await
+    {
+        Ok(protocol) => {
+            debug!("Successfully established V2 protocol connection from {peer_addr}");
+            let (reader_protocol, writer_protocol) = protocol.into_split();
+            Ok((
+                ReadTransport::V2(reader, reader_protocol),
+                WriteTransport::V2(writer, writer_protocol),
+                TransportProtocol::V2,
+            ))
+        }
+        Err(e) => {
+            debug!("Failed to establish V2 protocol connection from {peer_addr}: {e:?}");
+            Err(TransportError::Protocol(e))
+        }
+    }
+}
+
+/// Whether the first bytes sent by this peer are the header of a V1 `version` message.
+///
+/// We only peek at the stream, so those bytes are still there for the actual handshake.
+async fn is_v1_handshake(tcp_stream: &TcpStream, magic: Magic) -> Result<bool, TransportError> {
+    // magic + "version" padded with zeros to 12 bytes
+    let mut v1_prefix = [0u8; 16];
+    v1_prefix[0..4].copy_from_slice(&magic.to_bytes());
+    v1_prefix[4..11].copy_from_slice(b"version");
+
+    let mut buf = [0u8; 16];
+    loop {
+        let read = tcp_stream.peek(&mut buf).await?;
+        if read == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        if buf[..read] != v1_prefix[..read] {
+            return Ok(false);
+        }
+
+        if read == v1_prefix.len() {
+            return Ok(true);
+        }
+
+        // peek returns right away if there's anything to read, wait for the rest of the prefix
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+}
+
 /// Establishes a connection through a SOCKS5 proxy and negotiates the bitcoin protocol.
 ///
 /// This function connects to a SOCKS5 proxy, establishes a connection to the target address
@@ -229,6 +329,7 @@ async fn try_connection<A: ToSocketAddrs>(
 /// * `address` - The target address to connect to through the proxy
 /// * `port` - The port to connect to on the target
 /// * `network` - The bitcoin network
+/// * `magic` - The magic bytes of our chain, see [`connect`]
 /// * `allow_v1_fallback` - Whether to allow fallback to V1 protocol if V2 negotiation fails
 ///
 /// # Returns
@@ -243,6 +344,7 @@ pub async fn connect_proxy<A: ToSocketAddrs>(
     proxy_addr: A,
     address: LocalAddress,
     network: Network,
+    magic: Magic,
     allow_v1_fallback: bool,
 ) -> TransportResult {
     let addr = match address.get_address() {
@@ -257,12 +359,14 @@ pub async fn connect_proxy<A: ToSocketAddrs>(
         }
     };
 
-    match try_proxy_connection(&proxy_addr, &addr, address.get_port(), network, false).await {
+    let port = address.get_port();
+    let force_v1 = magic != network.magic();
+    match try_proxy_connection(&proxy_addr, &addr, port, network, magic, force_v1).await {
         Ok(transport) => Ok(transport),
         Err(TransportError::Protocol(ProtocolError::Io(_, ProtocolFailureSuggestion::RetryV1)))
             if allow_v1_fallback =>
         {
-            try_proxy_connection(&proxy_addr, &addr, address.get_port(), network, true).await
+            try_proxy_connection(&proxy_addr, &addr, port, network, magic, true).await
         }
         Err(e) => Err(e),
     }
@@ -273,6 +377,7 @@ async fn try_proxy_connection<A: ToSocketAddrs>(
     target_addr: &Socks5Addr,
     port: u16,
     network: Network,
+    magic: Magic,
     force_v1: bool,
 ) -> TransportResult {
     let proxy = TcpStream::connect(proxy_addr).await?;
@@ -284,7 +389,7 @@ async fn try_proxy_connection<A: ToSocketAddrs>(
             info!("Using V1 protocol for proxy connection to {target_addr:?}",);
             Ok((
                 ReadTransport::V1(reader),
-                WriteTransport::V1(writer, network),
+                WriteTransport::V1(writer, magic),
                 TransportProtocol::V1,
             ))
         }
@@ -368,7 +473,7 @@ where
                 let data = serialize_v2(message)?;
                 protocol.encrypt_and_write(&data, writer).await?;
             }
-            WriteTransport::V1(writer, network) => {
+            WriteTransport::V1(writer, magic) => {
                 if let NetworkMessage::Unknown { payload, command } = message {
                     let expected_cmd = CommandString::try_from_static("getuproof").unwrap();
                     assert_eq!(
@@ -382,7 +487,7 @@ where
                     let checksum = &sha256d_payload(&payload)[0..4];
 
                     let mut message_header = [0u8; 24];
-                    message_header[0..4].copy_from_slice(&network.magic().to_bytes());
+                    message_header[0..4].copy_from_slice(&magic.to_bytes());
                     message_header[4..13].copy_from_slice("getuproof".as_bytes());
                     message_header[16..20].copy_from_slice(&(payload.len() as u32).to_le_bytes());
                     message_header[20..24].copy_from_slice(checksum);
@@ -393,7 +498,7 @@ where
                     return Ok(());
                 }
 
-                let data = &mut RawNetworkMessage::new(network.magic(), message);
+                let data = &mut RawNetworkMessage::new(*magic, message);
                 let data = serialize(&data);
                 writer.write_all(&data).await?;
                 writer.flush().await?;
@@ -0,0 +1,290 @@
+//! Messages and helpers for relaying mempool transactions.
+//!
+//! Peers announce new transactions with an `inv`, by txid or, if both sides sent `wtxidrelay`
+//! during the handshake (BIP339), by wtxid. We don't have a UTXO set, so we can't validate a
+//! transaction by itself: we need a Utreexo proof for every input that doesn't spend one of our
+//! mempool transactions. That's why we ask utreexo peers for announced transactions with the
+//! utreexo flag set in the inventory type, and they answer with a [UtreexoTransaction], that
+//! carries this proof.
+//!
+//! Once we accept a transaction, we announce it to our other peers. We don't send an `inv`
+//! right away, every peer has a queue that is flushed at random intervals (trickling), so
+//! it's harder to find who created a transaction by timing its announcements.
+
+use std::ffi::c_uint;
+use std::time::Duration;
+
+use bitcoin::consensus::Decodable;
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::sha256;
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::message_blockdata::Inventory;
+use bitcoin::Transaction;
+use bitcoin::Txid;
+use bitcoin::VarInt;
+use bitcoin::Wtxid;
+use floresta_chain::verify_flags;
+use floresta_chain::CompactLeafData;
+use floresta_chain::ScriptPubKeyKind;
+use floresta_common::read_bounded_len;
+use floresta_common::service_flags::UTREEXO;
+use rustreexo::accumulator::node_hash::BitcoinNodeHash;
+
+/// The inventory type for a transaction, requested by txid, with its witness
+const MSG_WITNESS_TX: u32 = 0x40000001;
+
+/// The inventory type for a transaction, requested by wtxid (BIP339)
+const MSG_WTX: u32 = 5;
+
+/// How many inputs a standard transaction can have, given its maximum weight
+const MAX_TX_INPUTS: usize = 2_500;
+
+/// How high the Utreexo forest can be.
+const MAX_TREE_DEPTH: usize = 64;
+
+/// The maximum number of proof hashes in the proof for one transaction
+const MAX_PROOF_HASHES: usize = MAX_TX_INPUTS * MAX_TREE_DEPTH;
+
+/// The script rules every new block follows, so mempool transactions must follow them too
+pub const MEMPOOL_SCRIPT_FLAGS: c_uint = verify_flags::VERIFY_P2SH
+    | verify_flags::VERIFY_DERSIG
+    | verify_flags::VERIFY_NULLDUMMY
+    | verify_flags::VERIFY_CHECKLOCKTIMEVERIFY
+    | verify_flags::VERIFY_CHECKSEQUENCEVERIFY
+    | verify_flags::VERIFY_WITNESS
+    | verify_flags::VERIFY_TAPROOT;
+
+/// On average, how often we announce transactions to an inbound peer
+pub const INBOUND_INVENTORY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// On average, how often we announce transactions to an outbound peer
+pub const OUTBOUND_INVENTORY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How many transactions we announce at once
+pub const MAX_INVENTORY_BROADCAST: usize = 1_000;
+
+/// How many transactions we remember a peer knows about, before we forget all of them
+pub const MAX_KNOWN_INVENTORY: usize = 50_000;
+
+/// If a peer doesn't send a transaction we've asked for in this time, we may ask someone else
+pub const TX_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many transactions we may be waiting for at once
+pub const MAX_TX_REQUESTS: usize = 5_000;
+
+/// The `getdata` entry asking a utreexo peer for an announced transaction and its proof
+///
+/// Returns `None` if this inventory doesn't announce a transaction.
+pub fn utreexo_tx_inventory(announced: Inventory) -> Option<Inventory> {
+    let (inv_type, hash) = match announced {
+        Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+            (MSG_WITNESS_TX, txid.to_byte_array())
+        }
+        Inventory::WTx(wtxid) => (MSG_WTX, wtxid.to_byte_array()),
+        _ => return None,
+    };
+
+    Some(Inventory::Unknown {
+        inv_type: inv_type | UTREEXO as u32,
+        hash,
+    })
+}
+
+/// Picks when we'll announce our queued transactions to a peer again.
+///
+/// Those intervals follow an exponential distribution with the given average, like the time
+/// between events in a Poisson process.
+pub fn next_inventory_broadcast(average: Duration) -> Duration {
+    let uniform: f64 = rand::random();
+    average.mul_f64(-(1.0 - uniform).ln())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A transaction we've accepted to our mempool, that we should announce to a peer
+pub struct TxAnnouncement {
+    pub txid: Txid,
+    pub wtxid: Wtxid,
+
+    /// The feerate of this transaction, in sats per 1000 virtual bytes. Peers may ask us to
+    /// only announce transactions above some feerate, with a `feefilter`.
+    pub feerate: u64,
+}
+
+impl TxAnnouncement {
+    /// How we announce this transaction, depending on whether our peer wants wtxids
+    pub fn inventory(&self, wtxid_relay: bool) -> Inventory {
+        match wtxid_relay {
+            true => Inventory::WTx(self.wtxid),
+            false => Inventory::Transaction(self.txid),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A transaction with the Utreexo proof for the inputs that aren't spending other mempool
+/// transactions.
+///
+/// This message will be sent in response to a `getdata` for [utreexo_tx_inventory].
+pub struct UtreexoTransaction {
+    /// The transaction itself
+    pub transaction: Transaction,
+
+    /// The proof hashes for the proven inputs
+    pub proof_hashes: Vec<BitcoinNodeHash>,
+
+    /// The positions of the UTXOs being spent inside the forest
+    pub targets: Vec<u64>,
+
+    /// The leaf data for the proven inputs, in the same order they appear in the transaction
+    pub leaf_data: Vec<CompactLeafData>,
+}
+
+impl Encodable for UtreexoTransaction {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        let mut len = self.transaction.consensus_encode(writer)?;
+
+        len += VarInt::from(self.proof_hashes.len()).consensus_encode(writer)?;
+        for hash in self.proof_hashes.iter() {
+            len += sha256::Hash::from_byte_array(**hash).consensus_encode(writer)?;
+        }
+
+        len += VarInt::from(self.targets.len()).consensus_encode(writer)?;
+        for target in self.targets.iter() {
+            len += VarInt(*target).consensus_encode(writer)?;
+        }
+
+        len += VarInt::from(self.leaf_data.len()).consensus_encode(writer)?;
+        for leaf in self.leaf_data.iter() {
+            len += leaf.header_code.consensus_encode(writer)?;
+            len += leaf.amount.consensus_encode(writer)?;
+            len += leaf.spk_ty.consensus_encode(writer)?;
+        }
+
+        Ok(len)
+    }
+}
+
+impl Decodable for UtreexoTransaction {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        let transaction = Transaction::consensus_decode(reader)?;
+
+        let n_hashes = read_bounded_len(reader, MAX_PROOF_HASHES)?;
+        let mut proof_hashes = Vec::with_capacity(n_hashes);
+        for _ in 0..n_hashes {
+            let hash = sha256::Hash::consensus_decode(reader)?;
+            proof_hashes.push(hash.into());
+        }
+
+        let n_targets = read_bounded_len(reader, MAX_TX_INPUTS)?;
+        let mut targets = Vec::with_capacity(n_targets);
+        for _ in 0..n_targets {
+            targets.push(VarInt::consensus_decode(reader)?.0);
+        }
+
+        let n_leaf_data = read_bounded_len(reader, MAX_TX_INPUTS)?;
+        let mut leaf_data = Vec::with_capacity(n_leaf_data);
+        for _ in 0..n_leaf_data {
+            leaf_data.push(CompactLeafData {
+                header_code: u32::consensus_decode(reader)?,
+                amount: u64::consensus_decode(reader)?,
+                spk_ty: ScriptPubKeyKind::consensus_decode(reader)?,
+            });
+        }
+
+        Ok(UtreexoTransaction {
+            transaction,
+            proof_hashes,
+            targets,
+            leaf_data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use bitcoin::consensus::deserialize;
+    use bitcoin::consensus::encode::deserialize_hex;
+    use bitcoin::consensus::serialize;
+    use bitcoin::hashes::Hash;
+    use bitcoin::p2p::message_blockdata::Inventory;
+    use bitcoin::Transaction;
+    use bitcoin::Txid;
+    use bitcoin::Wtxid;
+    use floresta_chain::CompactLeafData;
+    use floresta_chain::ScriptPubKeyKind;
+    use floresta_common::acchashes;
+    use rustreexo::accumulator::node_hash::BitcoinNodeHash;
+
+    use super::*;
+
+    #[test]
+    fn test_utreexo_tx_inventory() {
+        let txid = Txid::from_byte_array([1; 32]);
+        let wtxid = Wtxid::from_byte_array([2; 32]);
+
+        assert_eq!(
+            utreexo_tx_inventory(Inventory::Transaction(txid)),
+            Some(Inventory::Unknown {
+                inv_type: 0x41000001,
+                hash: [1; 32]
+            })
+        );
+        assert_eq!(
+            utreexo_tx_inventory(Inventory::WTx(wtxid)),
+            Some(Inventory::Unknown {
+                inv_type: 0x01000005,
+                hash: [2; 32]
+            })
+        );
+        assert_eq!(utreexo_tx_inventory(Inventory::Error), None);
+
+        let announcement = TxAnnouncement {
+            txid,
+            wtxid,
+            feerate: 1000,
+        };
+        assert_eq!(announcement.inventory(true), Inventory::WTx(wtxid));
+        assert_eq!(announcement.inventory(false), Inventory::Transaction(txid));
+    }
+
+    #[test]
+    fn test_next_inventory_broadcast() {
+        let average = Duration::from_secs(5);
+        let total: Duration = (0..1000).map(|_| next_inventory_broadcast(average)).sum();
+
+        // The mean of 1000 samples should be close to the average
+        assert!(total > Duration::from_secs(4000));
+        assert!(total < Duration::from_secs(6000));
+    }
+
+    #[test]
+    fn test_utreexo_transaction_roundtrip() {
+        // The first bitcoin transaction, from block 170
+        let transaction: Transaction = deserialize_hex("0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000").unwrap();
+
+        let utreexo_tx = UtreexoTransaction {
+            transaction,
+            proof_hashes: acchashes![
+                "4c3d7bb5b2a5bd4b1d2d6e0b36e2ae5a1b8e1f2c2b8e6f3c4f1b6f4e2e1d2c3b",
+            ]
+            .to_vec(),
+            targets: vec![42],
+            leaf_data: vec![CompactLeafData {
+                header_code: 9 << 1 | 1,
+                amount: 5_000_000_000,
+                spk_ty: ScriptPubKeyKind::Other(Box::new([0x51])),
+            }],
+        };
+
+        let decoded: UtreexoTransaction = deserialize(&serialize(&utreexo_tx)).unwrap();
+        assert_eq!(decoded, utreexo_tx);
+    }
+}
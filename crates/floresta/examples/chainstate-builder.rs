@@ -18,7 +18,6 @@ const DATA_DIR: &str = "./tmp-db";
 
 #[tokio::main]
 async fn main() {
-    let network = Network::Bitcoin;
     let params = ChainParams::from(Network::Bitcoin);
     let genesis = genesis_block(&params);
     // Create a new chain state, which will store the accumulator and the headers chain.
@@ -40,13 +39,14 @@ async fn main() {
     //
     // We also set the chain params, which are the parameters of the network that we
     // are connecting to. We use the Bitcoin network here, but you can also use
-    // Testnet, Signet or Regtest.
+    // Testnet, Signet or Regtest. For a custom signet or regtest, build them with
+    // `ChainParams::from_config`.
     //
     // Finally, we set the utreexo accumulator. This is the accumulator that we use
     // to validate the blockchain. If you set the chain height, you should update
     // the accumulator to the state of the blockchain at that height too.
     let _chain = ChainStateBuilder::new()
-        .with_assume_valid(AssumeValidArg::Disabled, network)
+        .with_assume_valid(AssumeValidArg::Disabled)
         .with_chain_params(params)
         .with_tip((genesis.block_hash(), 0), genesis.header)
         .assume_utreexo(Stump::new())
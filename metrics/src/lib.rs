@@ -5,6 +5,7 @@ use std::sync::OnceLock;
 use axum::routing::get;
 use axum::Router;
 use prometheus_client::encoding::text::encode;
+use prometheus_client::metrics::counter::Counter;
 use prometheus_client::metrics::gauge::Gauge;
 use prometheus_client::metrics::histogram::Histogram;
 use prometheus_client::registry::Registry;
@@ -17,6 +18,10 @@ pub struct AppMetrics {
     pub peer_count: Gauge<f64, AtomicU64>,
     pub avg_block_processing_time: Gauge<f64, AtomicU64>,
     pub message_times: Histogram,
+    pub fork_headers: Gauge,
+    pub presync_headers: Gauge,
+    pub compact_block_hit_rate: Histogram,
+    pub compact_block_failures: Counter,
 }
 
 impl AppMetrics {
@@ -27,6 +32,10 @@ impl AppMetrics {
         let peer_count = Gauge::<f64, AtomicU64>::default();
         let avg_block_processing_time = Gauge::<f64, AtomicU64>::default();
         let message_times = Histogram::new([0.1, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0].into_iter());
+        let fork_headers = Gauge::default();
+        let presync_headers = Gauge::default();
+        let compact_block_hit_rate = Histogram::new([0.5, 0.75, 0.9, 0.95, 0.99, 1.0].into_iter());
+        let compact_block_failures = Counter::default();
 
         registry.register("block_height", "Current block height", block_height.clone());
         registry.register(
@@ -53,6 +62,30 @@ impl AppMetrics {
             message_times.clone(),
         );
 
+        registry.register(
+            "fork_headers",
+            "Number of headers our connected peers gave us that aren't in our best chain",
+            fork_headers.clone(),
+        );
+
+        registry.register(
+            "presync_headers",
+            "Number of headers held in memory while presyncing low-work chains",
+            presync_headers.clone(),
+        );
+
+        registry.register(
+            "compact_block_hit_rate",
+            "Which fraction of the transactions in each compact block we've found in our mempool",
+            compact_block_hit_rate.clone(),
+        );
+
+        registry.register(
+            "compact_block_failures",
+            "Number of compact blocks we couldn't rebuild, and downloaded in full instead",
+            compact_block_failures.clone(),
+        );
+
         Self {
             registry,
             block_height,
@@ -60,6 +93,10 @@ impl AppMetrics {
             peer_count,
             avg_block_processing_time,
             message_times,
+            fork_headers,
+            presync_headers,
+            compact_block_hit_rate,
+            compact_block_failures,
         }
     }
 
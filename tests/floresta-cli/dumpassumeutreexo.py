@@ -0,0 +1,97 @@
+"""
+dumpassumeutreexo.py
+
+This functional test cli utility to interact with a Floresta node with `dumpassumeutreexo`
+and `verifyassumeutreexo`
+"""
+
+import time
+
+from test_framework import FlorestaTestFramework
+
+DATA_DIR = FlorestaTestFramework.get_integration_test_dir()
+
+
+class DumpAssumeUtreexoTest(FlorestaTestFramework):
+    """
+    Mine some blocks with utreexod, sync floresta with it, and check that the value we
+    dump at the tip matches our roots, and that `verifyassumeutreexo` accepts it. A value
+    with different roots must be rejected.
+    """
+
+    def set_test_params(self):
+        """
+        Setup a florestad and a utreexod node
+        """
+        name = self.__class__.__name__.lower()
+        self.data_dirs = DumpAssumeUtreexoTest.create_data_dirs(DATA_DIR, name, 2)
+
+        self.florestad = self.add_node(
+            variant="florestad", extra_args=[f"--data-dir={self.data_dirs[0]}"]
+        )
+
+        self.utreexod = self.add_node(
+            variant="utreexod",
+            extra_args=[
+                f"--datadir={self.data_dirs[1]}",
+                "--miningaddr=bcrt1q4gfcga7jfjmm02zpvrh4ttc5k7lmnq2re52z2y",
+                "--prune=0",
+            ],
+        )
+
+    def run_test(self):
+        """
+        Dump and verify the accumulator at genesis and at our tip
+        """
+        self.run_node(self.florestad)
+        self.run_node(self.utreexod)
+
+        # The accumulator before any block is empty
+        genesis = self.florestad.rpc.dump_assume_utreexo(0)
+        self.assertEqual(genesis["height"], 0)
+        self.assertEqual(genesis["leaves"], 0)
+        self.assertEqual(genesis["roots"], [])
+
+        self.log("=== Mining blocks with utreexod")
+        self.utreexod.rpc.generate(10)
+
+        host = self.utreexod.get_host()
+        port = self.utreexod.get_port("p2p")
+        self.florestad.rpc.addnode(
+            f"{host}:{port}", command="onetry", v2transport=False
+        )
+
+        self.log("=== Waiting for floresta to sync...")
+        for _ in range(30):
+            if self.florestad.rpc.get_blockchain_info()["validated"] == 10:
+                break
+            time.sleep(1)
+
+        value = self.florestad.rpc.dump_assume_utreexo()
+        self.log(value)
+        self.assertEqual(value["height"], 10)
+        self.assertEqual(value["block_hash"], self.utreexod.rpc.get_blockhash(10))
+        self.assertEqual(value["roots"], self.florestad.rpc.get_roots())
+        self.assertTrue(value["leaves"] > 0)
+
+        # The other formats are strings we can paste into our config, or our code
+        toml = self.florestad.rpc.dump_assume_utreexo(10, "toml")
+        self.assertTrue(toml.startswith("[chain.assume_utreexo]"))
+        rust = self.florestad.rpc.dump_assume_utreexo(10, "rust")
+        self.assertTrue(rust.startswith("AssumeUtreexoValue {"))
+
+        # Our own value is valid
+        res = self.florestad.rpc.verify_assume_utreexo(value)
+        self.assertTrue(res["valid"])
+
+        # But not with different roots
+        value["roots"] = value["roots"][1:]
+        res = self.florestad.rpc.verify_assume_utreexo(value)
+        self.assertFalse(res["valid"])
+        self.assertEqual(res["error"], "roots don't match our accumulator")
+
+        self.stop()
+
+
+if __name__ == "__main__":
+    DumpAssumeUtreexoTest().main()
@@ -0,0 +1,32 @@
+"""
+A test that starts a florestad node and checks the result of `getbackfillprogress`.
+Since we start from genesis, we don't assume any block, so there's nothing to backfill.
+"""
+
+from test_framework import FlorestaTestFramework
+
+
+class GetBackfillProgressTest(FlorestaTestFramework):
+    expected_chain = "regtest"
+
+    def set_test_params(self):
+        self.florestad = self.add_node(variant="florestad")
+
+    def run_test(self):
+        self.run_node(self.florestad)
+
+        progress = self.florestad.rpc.get_backfill_progress()
+        self.log(progress)
+
+        self.assertFalse(progress["assumed"])
+        self.assertFalse(progress["running"])
+        self.assertEqual(progress["ranges"], [])
+        self.assertEqual(progress["validated_blocks"], 0)
+        self.assertEqual(progress["total_blocks"], 0)
+        self.assertIsNone(progress["eta"])
+
+        self.stop()
+
+
+if __name__ == "__main__":
+    GetBackfillProgressTest().main()
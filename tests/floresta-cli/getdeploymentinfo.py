@@ -0,0 +1,63 @@
+"""
+floresta_cli_getdeploymentinfo.py
+
+This functional test cli utility to interact with a Floresta node with `getdeploymentinfo`
+"""
+
+from test_framework import FlorestaTestFramework
+
+
+class GetDeploymentInfoTest(FlorestaTestFramework):
+    """
+    Test `getdeploymentinfo` with a fresh node. On regtest, taproot is always active,
+    `testdummy` is still defined and the buried soft forks are reported with their heights.
+    """
+
+    nodes = [-1]
+    genesis = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"
+    buried = ["bip34", "bip65", "bip66", "csv", "segwit"]
+
+    def set_test_params(self):
+        """
+        Setup a single node
+        """
+        self.florestad = self.add_node(variant="florestad")
+
+    def run_test(self):
+        """
+        Run JSONRPC and get the deployments at the genesis block
+        """
+        self.run_node(self.florestad)
+
+        response = self.florestad.rpc.get_deploymentinfo()
+        self.assertEqual(response["hash"], GetDeploymentInfoTest.genesis)
+        self.assertEqual(response["height"], 0)
+
+        deployments = response["deployments"]
+        for name in GetDeploymentInfoTest.buried:
+            self.assertEqual(deployments[name]["type"], "buried")
+
+        taproot = deployments["taproot"]
+        self.assertEqual(taproot["type"], "bip9")
+        self.assertTrue(taproot["active"])
+        self.assertEqual(taproot["bip9"]["status"], "active")
+        self.assertEqual(taproot["bip9"]["start_time"], -1)
+
+        testdummy = deployments["testdummy"]
+        self.assertFalse(testdummy["active"])
+        self.assertEqual(testdummy["bip9"]["bit"], 28)
+        self.assertEqual(testdummy["bip9"]["status"], "defined")
+
+        # Asking for the genesis block explicitly gives the same result
+        response = self.florestad.rpc.get_deploymentinfo(GetDeploymentInfoTest.genesis)
+        self.assertEqual(response["deployments"], deployments)
+
+        # getblockchaininfo reports the same soft forks
+        response = self.florestad.rpc.get_blockchain_info()
+        self.assertEqual(response["softforks"], deployments)
+
+        self.stop()
+
+
+if __name__ == "__main__":
+    GetDeploymentInfoTest().main()
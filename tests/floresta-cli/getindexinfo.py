@@ -0,0 +1,48 @@
+"""
+A test that starts a florestad node with `--txindex` and checks the result of `getindexinfo`.
+Since we start from genesis, the index should be synced at height 0.
+"""
+
+import time
+
+from test_framework import FlorestaTestFramework
+
+GENESIS_COINBASE = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+
+
+class GetIndexInfoTest(FlorestaTestFramework):
+    expected_chain = "regtest"
+
+    def set_test_params(self):
+        self.florestad = self.add_node(extra_args=["--txindex"], variant="florestad")
+
+    def run_test(self):
+        self.run_node(self.florestad)
+
+        # The index syncs in the background, give it some time
+        for _ in range(30):
+            info = self.florestad.rpc.get_index_info()
+            if info["txindex"]["synced"]:
+                break
+            time.sleep(1)
+
+        self.log(info)
+        self.assertTrue(info["txindex"]["synced"])
+        self.assertEqual(info["txindex"]["best_block_height"], 0)
+
+        # Asking only for the txindex returns the same thing, unknown indexes are left out
+        self.assertEqual(self.florestad.rpc.get_index_info("txindex"), info)
+        self.assertEqual(self.florestad.rpc.get_index_info("coinstatsindex"), {})
+
+        # The genesis coinbase is indexed, even though it isn't in our wallet
+        tx = self.florestad.rpc.perform_request(
+            "getrawtransaction", params=[GENESIS_COINBASE, True]
+        )
+        self.assertEqual(len(tx["vin"]), 1)
+        self.assertEqual(len(tx["vout"]), 1)
+
+        self.stop()
+
+
+if __name__ == "__main__":
+    GetIndexInfoTest().main()
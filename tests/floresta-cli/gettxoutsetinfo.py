@@ -0,0 +1,55 @@
+"""
+A test that starts a florestad node with `--coinstatsindex` and checks the result of
+`gettxoutsetinfo`. Since we start from genesis, the UTXO set is empty, and the genesis
+reward can't be spent. The MuHash of an empty set is the same one Core returns.
+"""
+
+import time
+
+from test_framework import FlorestaTestFramework
+
+EMPTY_MUHASH = "dd5ad2a105c2d29495f577245c357409002329b9f4d6182c0af3dc2f462555c8"
+REGTEST_GENESIS = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"
+
+
+class GetTxOutSetInfoTest(FlorestaTestFramework):
+    expected_chain = "regtest"
+
+    def set_test_params(self):
+        self.florestad = self.add_node(
+            extra_args=["--coinstatsindex"], variant="florestad"
+        )
+
+    def run_test(self):
+        self.run_node(self.florestad)
+
+        # The index may take a moment to load
+        for _ in range(30):
+            info = self.florestad.rpc.get_index_info("coinstatsindex")
+            if info.get("coinstatsindex", {}).get("synced"):
+                break
+            time.sleep(1)
+
+        self.log(info)
+        self.assertTrue(info["coinstatsindex"]["synced"])
+        self.assertEqual(info["coinstatsindex"]["best_block_height"], 0)
+
+        stats = self.florestad.rpc.get_txout_set_info("muhash")
+        self.log(stats)
+        self.assertEqual(stats["height"], 0)
+        self.assertEqual(stats["bestblock"], REGTEST_GENESIS)
+        self.assertEqual(stats["txouts"], 0)
+        self.assertEqual(stats["bogosize"], 0)
+        self.assertEqual(stats["total_amount"], 0)
+        self.assertEqual(stats["total_unspendable_amount"], 50)
+        self.assertEqual(stats["muhash"], EMPTY_MUHASH)
+
+        # Without a hash type, there's no muhash
+        stats = self.florestad.rpc.get_txout_set_info()
+        self.assertFalse("muhash" in stats)
+
+        self.stop()
+
+
+if __name__ == "__main__":
+    GetTxOutSetInfoTest().main()
@@ -5,6 +5,8 @@ A test framework for testing JsonRPC calls to a floresta node.
 """
 
 import re
+from typing import Optional
+
 from test_framework.rpc.base import BaseRPC
 
 REGTEST_RPC_SERVER = {
@@ -55,6 +57,19 @@ class FlorestaRPC(BaseRPC):
 
         return self.perform_request("getblockheader", params=[blockhash])
 
+    def get_deploymentinfo(self, blockhash: Optional[str] = None) -> dict:
+        """
+        Get the status of each soft fork at a block (our best block by default)
+        performing `perform_request('getdeploymentinfo', params=[<str>])`
+        """
+        if blockhash is None:
+            return self.perform_request("getdeploymentinfo")
+
+        if not bool(re.fullmatch(r"^[a-f0-9]{64}$", blockhash)):
+            raise ValueError(f"Invalid blockhash '{blockhash}'.")
+
+        return self.perform_request("getdeploymentinfo", params=[blockhash])
+
     def get_block(self, blockhash: str, verbosity: int = 1):
         """
         Get a full block, given its hash performing
@@ -133,6 +148,49 @@ class FlorestaRPC(BaseRPC):
         """
         return self.perform_request("ping")
 
+    def get_backfill_progress(self) -> dict:
+        """
+        Get how far we are in the backfill of assumed blocks
+        performing `perform_request('getbackfillprogress')`
+        """
+        return self.perform_request("getbackfillprogress")
+
+    def get_index_info(self, index_name: Optional[str] = None) -> dict:
+        """
+        Get the status of our indexes performing
+        `perform_request('getindexinfo', params=[<index_name>])`
+        """
+        params = [index_name] if index_name is not None else []
+        return self.perform_request("getindexinfo", params=params)
+
+    def dump_assume_utreexo(
+        self, height: Optional[int] = None, dump_format: Optional[str] = None
+    ):
+        """
+        Get our accumulator after a block performing
+        `perform_request('dumpassumeutreexo', params=[<height>, <format>])`
+        """
+        params = [height] if height is not None else []
+        if dump_format is not None:
+            params.append(dump_format)
+
+        return self.perform_request("dumpassumeutreexo", params=params)
+
+    def verify_assume_utreexo(self, value: dict) -> dict:
+        """
+        Check an assumeutreexo value against our accumulator performing
+        `perform_request('verifyassumeutreexo', params=[<value>])`
+        """
+        return self.perform_request("verifyassumeutreexo", params=[value])
+
+    def get_txout_set_info(self, hash_type: Optional[str] = None) -> dict:
+        """
+        Get statistics about the UTXO set performing
+        `perform_request('gettxoutsetinfo', params=[<hash_type>])`
+        """
+        params = [hash_type] if hash_type is not None else []
+        return self.perform_request("gettxoutsetinfo", params=params)
+
     def get_roots(self):
         """
         Returns the roots of our current floresta state performing
@@ -68,6 +68,11 @@ BASE_TEST_SUITE = [
     ("floresta-cli", "getpeerinfo"),
     ("floresta-cli", "getblockchaininfo"),
     ("floresta-cli", "getblockheader"),
+    ("floresta-cli", "getdeploymentinfo"),
+    ("floresta-cli", "getbackfillprogress"),
+    ("floresta-cli", "getindexinfo"),
+    ("floresta-cli", "gettxoutsetinfo"),
+    ("floresta-cli", "dumpassumeutreexo"),
     ("example", "bitcoin"),
     ("example", "utreexod"),
 ]